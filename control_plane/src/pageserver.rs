@@ -8,7 +8,7 @@ use std::collections::HashMap;
 
 use std::io;
 use std::io::Write;
-use std::num::NonZeroU64;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
@@ -383,6 +383,13 @@ impl PageServerNode {
                 .map(|x| x.parse::<AuxFilePolicy>())
                 .transpose()
                 .context("Failed to parse 'switch_aux_file_policy'")?,
+            remote_storage_concurrency_limit: settings
+                .remove("remote_storage_concurrency_limit")
+                .map(|x| x.parse::<NonZeroUsize>())
+                .transpose()
+                .context(
+                    "Failed to parse 'remote_storage_concurrency_limit' as non zero integer",
+                )?,
         };
         if !settings.is_empty() {
             bail!("Unrecognized tenant settings: {settings:?}")
@@ -506,6 +513,13 @@ impl PageServerNode {
                     .map(|x| x.parse::<AuxFilePolicy>())
                     .transpose()
                     .context("Failed to parse 'switch_aux_file_policy'")?,
+                remote_storage_concurrency_limit: settings
+                    .remove("remote_storage_concurrency_limit")
+                    .map(|x| x.parse::<NonZeroUsize>())
+                    .transpose()
+                    .context(
+                        "Failed to parse 'remote_storage_concurrency_limit' as non zero integer",
+                    )?,
             }
         };
 
@@ -0,0 +1,241 @@
+//! Throughput of uploads, downloads and listings of the remote storage backends.
+//!
+//! Runs against [`remote_storage::LocalFs`] in a temporary directory, and additionally against a real S3 bucket
+//! if `ENABLE_REAL_S3_REMOTE_STORAGE` is set, configured with the same environment variables as
+//! the `test_real_s3` tests: `REMOTE_STORAGE_S3_BUCKET` and `REMOTE_STORAGE_S3_REGION`. The
+//! objects are written below a random prefix, which is deleted again afterwards.
+//!
+//! ```text
+//! cargo bench -p remote_storage --bench benchmarks
+//! ENABLE_REAL_S3_REMOTE_STORAGE=1 REMOTE_STORAGE_S3_BUCKET=... REMOTE_STORAGE_S3_REGION=... \
+//!     cargo bench -p remote_storage --bench benchmarks -- s3
+//! ```
+
+use std::num::NonZeroUsize;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Context;
+use bytes::Bytes;
+use camino_tempfile::Utf8TempDir;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::StreamExt;
+use rand::Rng;
+use remote_storage::{
+    GenericRemoteStorage, ListOptions, ListingMode, RemotePath, RemoteStorageConfig,
+    RemoteStorageKind, S3Config, UploadOptions,
+};
+use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
+
+const ENABLE_REAL_S3_REMOTE_STORAGE_ENV_VAR_NAME: &str = "ENABLE_REAL_S3_REMOTE_STORAGE";
+
+const OBJECT_SIZES: [usize; 3] = [4 * 1024, 1024 * 1024, 16 * 1024 * 1024];
+const LIST_CARDINALITIES: [usize; 3] = [10, 100, 1000];
+
+/// A backend to benchmark, with everything needed to clean up after it.
+struct Backend {
+    name: &'static str,
+    storage: GenericRemoteStorage,
+    /// Everything is written below this prefix
+    prefix: RemotePath,
+    /// Removed on drop, for [`remote_storage::LocalFs`]
+    _local_dir: Option<Utf8TempDir>,
+}
+
+impl Backend {
+    fn path(&self, name: &str) -> RemotePath {
+        self.prefix.join(name)
+    }
+}
+
+fn backends() -> anyhow::Result<Vec<Backend>> {
+    let local_dir = camino_tempfile::tempdir()?;
+    let local = Backend {
+        name: "local_fs",
+        storage: GenericRemoteStorage::from_config(&RemoteStorageConfig {
+            storage: RemoteStorageKind::LocalFs {
+                local_path: local_dir.path().to_owned(),
+                sync_on_upload: false,
+            },
+            timeouts: RemoteStorageConfig::DEFAULT_TIMEOUT.into(),
+        })?,
+        prefix: RemotePath::from_string("bench")?,
+        _local_dir: Some(local_dir),
+    };
+
+    let mut backends = vec![local];
+    if std::env::var(ENABLE_REAL_S3_REMOTE_STORAGE_ENV_VAR_NAME).is_ok() {
+        backends.push(s3_backend()?);
+    }
+    Ok(backends)
+}
+
+fn s3_backend() -> anyhow::Result<Backend> {
+    let bucket_name = std::env::var("REMOTE_STORAGE_S3_BUCKET").context(
+        "`REMOTE_STORAGE_S3_BUCKET` env var is not set, but real S3 benches are enabled",
+    )?;
+    let bucket_region = std::env::var("REMOTE_STORAGE_S3_REGION").context(
+        "`REMOTE_STORAGE_S3_REGION` env var is not set, but real S3 benches are enabled",
+    )?;
+
+    let millis = std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_millis();
+    let random = rand::thread_rng().gen::<u32>();
+
+    let storage = GenericRemoteStorage::from_config(&RemoteStorageConfig {
+        storage: RemoteStorageKind::AwsS3(S3Config {
+            bucket_name,
+            bucket_region,
+            prefix_in_bucket: None,
+            endpoint: None,
+            concurrency_limit: NonZeroUsize::new(100).unwrap(),
+            max_keys_per_list_response: None,
+            upload_storage_class: None,
+            max_idle_connections: None,
+            connection_idle_timeout: None,
+            connect_timeout: None,
+            operation_attempt_timeout: None,
+            sdk_max_attempts: None,
+            profile_name: None,
+            rps_limits: Default::default(),
+            expected_bucket_owner: None,
+            requester_pays: false,
+            slow_request_threshold: None,
+            user_agent_suffix: None,
+            app_name: None,
+        }),
+        timeouts: RemoteStorageConfig::DEFAULT_TIMEOUT.into(),
+    })?;
+
+    Ok(Backend {
+        name: "s3",
+        storage,
+        prefix: RemotePath::from_string(&format!("bench_{millis}_{random:08x}"))?,
+        _local_dir: None,
+    })
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn random_bytes(size: usize) -> Bytes {
+    let mut data = vec![0; size];
+    rand::thread_rng().fill(&mut data[..]);
+    Bytes::from(data)
+}
+
+async fn upload(backend: &Backend, path: &RemotePath, data: Bytes, cancel: &CancellationToken) {
+    let len = data.len();
+    let from = futures::stream::once(futures::future::ready(Ok(data)));
+    backend
+        .storage
+        .upload(from, len, path, UploadOptions::default(), cancel)
+        .await
+        .unwrap();
+}
+
+async fn download(backend: &Backend, path: &RemotePath, cancel: &CancellationToken) -> usize {
+    let download = backend.storage.download(path, cancel).await.unwrap();
+    let mut stream = std::pin::pin!(download.download_stream);
+    let mut len = 0;
+    while let Some(chunk) = stream.next().await {
+        len += chunk.unwrap().len();
+    }
+    len
+}
+
+fn cleanup(rt: &Runtime, backend: &Backend, cancel: &CancellationToken) {
+    rt.block_on(backend.storage.delete_prefix(&backend.prefix, cancel))
+        .unwrap();
+}
+
+fn bench_upload(c: &mut Criterion) {
+    let rt = runtime();
+    let cancel = CancellationToken::new();
+    let mut group = c.benchmark_group("upload");
+    for backend in backends().unwrap() {
+        for size in OBJECT_SIZES {
+            let data = random_bytes(size);
+            let path = backend.path(&format!("upload_{size}"));
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::new(backend.name, size), &data, |b, data| {
+                b.iter(|| rt.block_on(upload(&backend, &path, data.clone(), &cancel)))
+            });
+        }
+        cleanup(&rt, &backend, &cancel);
+    }
+    group.finish();
+}
+
+fn bench_download(c: &mut Criterion) {
+    let rt = runtime();
+    let cancel = CancellationToken::new();
+    let mut group = c.benchmark_group("download");
+    for backend in backends().unwrap() {
+        for size in OBJECT_SIZES {
+            let path = backend.path(&format!("download_{size}"));
+            rt.block_on(upload(&backend, &path, random_bytes(size), &cancel));
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::new(backend.name, size), &path, |b, path| {
+                b.iter(|| assert_eq!(rt.block_on(download(&backend, path, &cancel)), size))
+            });
+        }
+        cleanup(&rt, &backend, &cancel);
+    }
+    group.finish();
+}
+
+fn bench_list(c: &mut Criterion) {
+    let rt = runtime();
+    let cancel = CancellationToken::new();
+    let mut group = c.benchmark_group("list");
+    // Listings of the larger prefixes take many requests on S3
+    group.measurement_time(Duration::from_secs(10));
+    for backend in backends().unwrap() {
+        for count in LIST_CARDINALITIES {
+            // Zero padded, so that no prefix is a string prefix of another one
+            let prefix = backend.path(&format!("list_{count:06}"));
+            let (backend_ref, prefix_ref, cancel_ref) = (&backend, &prefix, &cancel);
+            rt.block_on(async {
+                let uploads = (0..count).map(move |i| async move {
+                    let path = prefix_ref.join(format!("object_{i:06}"));
+                    upload(backend_ref, &path, Bytes::from_static(b"x"), cancel_ref).await
+                });
+                futures::stream::iter(uploads)
+                    .buffer_unordered(32)
+                    .collect::<Vec<()>>()
+                    .await
+            });
+
+            group.throughput(Throughput::Elements(count as u64));
+            group.bench_with_input(
+                BenchmarkId::new(backend.name, count),
+                &prefix,
+                |b, prefix| {
+                    b.iter(|| {
+                        let listing = rt
+                            .block_on(backend.storage.list(
+                                Some(prefix),
+                                ListingMode::NoDelimiter,
+                                None,
+                                ListOptions::default(),
+                                &cancel,
+                            ))
+                            .unwrap();
+                        assert_eq!(listing.objects.len(), count);
+                    })
+                },
+            );
+        }
+        cleanup(&rt, &backend, &cancel);
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_upload, bench_download, bench_list);
+criterion_main!(benches);
@@ -5,7 +5,7 @@ use std::collections::HashMap;
 use std::env;
 use std::fmt::Display;
 use std::io;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -13,13 +13,16 @@ use std::time::Duration;
 use std::time::SystemTime;
 
 use super::REMOTE_STORAGE_PREFIX_SEPARATOR;
-use anyhow::Result;
-use azure_core::request_options::{MaxResults, Metadata, Range};
-use azure_core::RetryOptions;
-use azure_identity::DefaultAzureCredential;
+use anyhow::{Context, Result};
+use azure_core::request_options::{IfMatchCondition, MaxResults, Metadata, NextMarker, Range};
+use azure_core::{ClientOptions, RetryOptions, TelemetryOptions};
+use azure_identity::{
+    DefaultAzureCredential, ImdsId, TokenCredentialOptions, VirtualMachineManagedIdentityCredential,
+};
 use azure_storage::StorageCredentials;
 use azure_storage_blobs::blob::CopyStatus;
-use azure_storage_blobs::prelude::ClientBuilder;
+use azure_storage_blobs::blob::{BlobBlockType, BlockList};
+use azure_storage_blobs::prelude::{BlobClient, BlobContentEncoding, BlockId, ClientBuilder};
 use azure_storage_blobs::{blob::operations::GetBlobBuilder, prelude::ContainerClient};
 use bytes::Bytes;
 use futures::future::Either;
@@ -34,39 +37,84 @@ use utils::backoff;
 
 use crate::metrics::{start_measuring_requests, AttemptOutcome, RequestKind};
 use crate::{
-    error::Cancelled, AzureConfig, ConcurrencyLimiter, Download, DownloadError, Listing,
-    ListingMode, RemotePath, RemoteStorage, StorageMetadata, TimeTravelError, TimeoutOrCancel,
+    error::Cancelled,
+    support,
+    traffic::{CountingDownload, TrafficCounters},
+    AzureAuthMethod, AzureConfig, Compression, ConcurrencyLimiter, ContinuationToken, CopyMetadata,
+    Download, DownloadError, Etag, ListOptions, Listing, ListingMode, ListingObject,
+    PerKindTimeouts, PreconditionFailed, RateLimiter, RemotePath, RemoteStorage,
+    RemoteStorageConfig, RemoteStorageKind, RpsLimits, StorageDescription, StorageMetadata,
+    Throttled, TimeTravelError, TimeTravelSummary, TimeoutOrCancel, TrafficStats, UploadOptions,
 };
 
 pub struct AzureBlobStorage {
     client: ContainerClient,
+    container_name: String,
+    container_region: String,
     prefix_in_container: Option<String>,
     max_keys_per_list_response: Option<NonZeroU32>,
     concurrency_limiter: ConcurrencyLimiter,
-    // Per-request timeout. Accessible for tests.
-    pub timeout: Duration,
+    // Shared with the handles created by `with_concurrency_limit`.
+    rate_limiter: Arc<RateLimiter>,
+    // Per-request timeouts. Accessible for tests.
+    pub timeouts: PerKindTimeouts,
+    max_block_size: usize,
+    max_concurrency_per_upload: usize,
+    traffic: Arc<TrafficCounters>,
+    // The config this storage was created from, for `redacted_config`.
+    config: Arc<AzureConfig>,
 }
 
 impl AzureBlobStorage {
-    pub fn new(azure_config: &AzureConfig, timeout: Duration) -> Result<Self> {
+    pub fn new(azure_config: &AzureConfig, timeouts: impl Into<PerKindTimeouts>) -> Result<Self> {
         debug!(
             "Creating azure remote storage for azure container {}",
             azure_config.container_name
         );
 
-        let account = env::var("AZURE_STORAGE_ACCOUNT").expect("missing AZURE_STORAGE_ACCOUNT");
+        let account = match &azure_config.storage_account {
+            Some(account) => account.clone(),
+            None => env::var("AZURE_STORAGE_ACCOUNT").expect("missing AZURE_STORAGE_ACCOUNT"),
+        };
 
-        // If the `AZURE_STORAGE_ACCESS_KEY` env var has an access key, use that,
-        // otherwise try the token based credentials.
-        let credentials = if let Ok(access_key) = env::var("AZURE_STORAGE_ACCESS_KEY") {
-            StorageCredentials::access_key(account.clone(), access_key)
-        } else {
-            let token_credential = DefaultAzureCredential::default();
-            StorageCredentials::token_credential(Arc::new(token_credential))
+        let credentials = match &azure_config.auth_method {
+            AzureAuthMethod::DefaultChain => {
+                // If the `AZURE_STORAGE_ACCESS_KEY` env var has an access key, use that,
+                // otherwise try the token based credentials.
+                if let Ok(access_key) = env::var("AZURE_STORAGE_ACCESS_KEY") {
+                    StorageCredentials::access_key(account.clone(), access_key)
+                } else {
+                    let token_credential = DefaultAzureCredential::default();
+                    StorageCredentials::token_credential(Arc::new(token_credential))
+                }
+            }
+            AzureAuthMethod::ManagedIdentity { client_id } => {
+                let imds_id = match client_id {
+                    Some(client_id) => ImdsId::ClientId(client_id.clone()),
+                    None => ImdsId::SystemAssigned,
+                };
+                debug!("Using managed identity {imds_id:?} for azure authentication");
+                let token_credential =
+                    VirtualMachineManagedIdentityCredential::new(TokenCredentialOptions::default())
+                        .with_identity(imds_id);
+                StorageCredentials::token_credential(Arc::new(token_credential))
+            }
+            AzureAuthMethod::StorageKey(access_key) => {
+                StorageCredentials::access_key(account.clone(), access_key.clone())
+            }
+            AzureAuthMethod::Sas(token) => StorageCredentials::sas_token(token)
+                .map_err(|e| anyhow::anyhow!("invalid azure SAS token: {e}"))?,
         };
 
-        // we have an outer retry
-        let builder = ClientBuilder::new(account, credentials).retry(RetryOptions::none());
+        let telemetry = TelemetryOptions::default().application_id(crate::user_agent(
+            azure_config.app_name.as_deref(),
+            azure_config.user_agent_suffix.as_deref(),
+        ));
+        // Replaces all options, so it goes first
+        let builder = ClientBuilder::new(account, credentials)
+            .client_options(ClientOptions::default().telemetry(telemetry))
+            // we have an outer retry
+            .retry(RetryOptions::none());
 
         let client = builder.container_client(azure_config.container_name.to_owned());
 
@@ -82,62 +130,276 @@ impl AzureBlobStorage {
 
         Ok(AzureBlobStorage {
             client,
-            prefix_in_container: azure_config.prefix_in_container.to_owned(),
+            container_name: azure_config.container_name.clone(),
+            container_region: azure_config.container_region.clone(),
+            prefix_in_container: normalize_prefix_in_container(
+                azure_config.prefix_in_container.as_deref(),
+            ),
             max_keys_per_list_response,
             concurrency_limiter: ConcurrencyLimiter::new(azure_config.concurrency_limit.get()),
-            timeout,
+            rate_limiter: Arc::new(RateLimiter::new(&azure_config.rps_limits)),
+            timeouts: timeouts.into(),
+            max_block_size: azure_config.max_block_size.get(),
+            max_concurrency_per_upload: azure_config.max_concurrency_per_upload.get(),
+            traffic: Arc::default(),
+            config: Arc::new(azure_config.clone()),
         })
     }
 
+    /// A handle on the same container which shares the HTTP connection pool and the rate limits
+    /// with this one, but has its own concurrency limit and traffic counters.
+    pub(crate) fn with_concurrency_limit(&self, limit: NonZeroUsize) -> Self {
+        Self {
+            client: self.client.clone(),
+            container_name: self.container_name.clone(),
+            container_region: self.container_region.clone(),
+            prefix_in_container: self.prefix_in_container.clone(),
+            max_keys_per_list_response: self.max_keys_per_list_response,
+            concurrency_limiter: ConcurrencyLimiter::new(limit.get()),
+            rate_limiter: Arc::clone(&self.rate_limiter),
+            timeouts: self.timeouts,
+            max_block_size: self.max_block_size,
+            max_concurrency_per_upload: self.max_concurrency_per_upload,
+            traffic: Arc::default(),
+            config: Arc::new(AzureConfig {
+                concurrency_limit: limit,
+                ..AzureConfig::clone(&self.config)
+            }),
+        }
+    }
+
+    /// A handle on the same container for [`crate::GenericRemoteStorage::healthcheck`], with a
+    /// single permit and a client and rate limiter of its own.
+    pub(crate) fn healthcheck_handle(&self) -> Result<Self> {
+        let config = AzureConfig {
+            concurrency_limit: NonZeroUsize::MIN,
+            rps_limits: RpsLimits::default(),
+            ..AzureConfig::clone(&self.config)
+        };
+        Self::new(&config, self.timeouts)
+    }
+
     pub fn relative_path_to_name(&self, path: &RemotePath) -> String {
         assert_eq!(std::path::MAIN_SEPARATOR, REMOTE_STORAGE_PREFIX_SEPARATOR);
-        let path_string = path
-            .get_path()
-            .as_str()
-            .trim_end_matches(REMOTE_STORAGE_PREFIX_SEPARATOR);
-        match &self.prefix_in_container {
-            Some(prefix) => {
-                if prefix.ends_with(REMOTE_STORAGE_PREFIX_SEPARATOR) {
-                    prefix.clone() + path_string
-                } else {
-                    format!("{prefix}{REMOTE_STORAGE_PREFIX_SEPARATOR}{path_string}")
+        support::join_storage_prefix(self.prefix_in_container.as_deref(), path)
+    }
+
+    pub(crate) fn name_to_relative_path(&self, key: &str) -> RemotePath {
+        match support::strip_storage_prefix(self.prefix_in_container.as_deref(), key) {
+            Some(path) => path,
+            // we rely on Azure to return properly prefixed paths
+            // for requests with a certain prefix
+            None => panic!(
+                "Key {key} does not start with container prefix {:?}",
+                self.prefix_in_container
+            ),
+        }
+    }
+
+    async fn upload0(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        check_upload_options(to, &options)?;
+        let UploadOptions {
+            metadata,
+            content_encoding,
+            content_md5,
+            ..
+        } = options;
+        let from = support::Md5Verifying::new(from, Some(data_size_bytes), content_md5);
+        let kind = RequestKind::Put;
+        let _permit = self.permit(kind, cancel).await?;
+
+        let started_at = start_measuring_requests(kind);
+
+        let op = async {
+            let blob_client = self.client.blob_client(self.relative_path_to_name(to));
+
+            if data_size_bytes <= self.max_block_size {
+                let from: Pin<
+                    Box<dyn Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static>,
+                > = Box::pin(from);
+
+                let from = NonSeekableStream::new(from, data_size_bytes);
+
+                let body = azure_core::Body::SeekableStream(Box::new(from));
+
+                let mut builder = blob_client.put_block_blob(body);
+
+                if let Some(metadata) = metadata {
+                    builder = builder.metadata(to_azure_metadata(metadata));
+                }
+                if let Some(content_encoding) = content_encoding {
+                    builder = builder.content_encoding(BlobContentEncoding::from(
+                        content_encoding.as_content_encoding(),
+                    ));
                 }
+
+                self.with_timeout(builder.into_future()).await
+            } else {
+                let block_count = data_size_bytes.div_ceil(self.max_block_size);
+                anyhow::ensure!(
+                    block_count <= MAX_BLOCKS_PER_BLOB,
+                    "upload of {data_size_bytes} bytes needs {block_count} blocks of {} bytes, more than the {MAX_BLOCKS_PER_BLOB} a blob can have",
+                    self.max_block_size
+                );
+                let blocks = numbered_blocks(from, self.max_block_size, Some(data_size_bytes));
+                self.put_blocks(&blob_client, blocks, metadata, content_encoding)
+                    .await
             }
-            None => path_string.to_string(),
+        };
+
+        let res = tokio::select! {
+            res = op => res,
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        };
+
+        let outcome = match res {
+            Ok(_) => AttemptOutcome::Ok,
+            Err(_) => AttemptOutcome::Err,
+        };
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, outcome, started_at);
+
+        if res.is_ok() {
+            self.traffic.record_upload(data_size_bytes);
         }
+        res
     }
 
-    fn name_to_relative_path(&self, key: &str) -> RemotePath {
-        let relative_path =
-            match key.strip_prefix(self.prefix_in_container.as_deref().unwrap_or_default()) {
-                Some(stripped) => stripped,
-                // we rely on Azure to return properly prefixed paths
-                // for requests with a certain prefix
-                None => panic!(
-                    "Key {key} does not start with container prefix {:?}",
-                    self.prefix_in_container
-                ),
-            };
-        RemotePath(
-            relative_path
-                .split(REMOTE_STORAGE_PREFIX_SEPARATOR)
+    /// Uploads a stream of unknown size as blocks of up to `max_block_size` bytes, of which only
+    /// the ones in flight are held in memory.
+    async fn upload_unsized0(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        check_upload_options(to, &options)?;
+        let UploadOptions {
+            metadata,
+            content_encoding,
+            content_md5,
+            ..
+        } = options;
+        let from = support::Md5Verifying::new(from, None, content_md5);
+        let kind = RequestKind::Put;
+        let _permit = self.permit(kind, cancel).await?;
+
+        let started_at = start_measuring_requests(kind);
+
+        let mut uploaded = 0;
+        let op = async {
+            let blob_client = self.client.blob_client(self.relative_path_to_name(to));
+            let blocks = numbered_blocks(from, self.max_block_size, None)
+                .inspect_ok(|(_, data)| uploaded += data.len());
+            self.put_blocks(&blob_client, blocks, metadata, content_encoding)
+                .await
+        };
+
+        let res = tokio::select! {
+            res = op => res,
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        };
+
+        let outcome = match res {
+            Ok(_) => AttemptOutcome::Ok,
+            Err(_) => AttemptOutcome::Err,
+        };
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, outcome, started_at);
+
+        if res.is_ok() {
+            self.traffic.record_upload(uploaded);
+        }
+        res
+    }
+
+    /// Applies the per-request timeout of uploads to an Azure upload request.
+    async fn with_timeout<T>(
+        &self,
+        fut: impl std::future::Future<Output = azure_core::Result<T>>,
+    ) -> anyhow::Result<()> {
+        let timeout = self.timeouts.for_kind(RequestKind::Put);
+        match tokio::time::timeout(timeout, fut).await {
+            Ok(Ok(_response)) => Ok(()),
+            Ok(Err(azure)) => Err(to_anyhow_error(azure)),
+            Err(_timeout) => Err(TimeoutOrCancel::Timeout.into()),
+        }
+    }
+
+    /// Uploads a blob that is larger than a single block: stages the numbered `blocks`, up to
+    /// [`blocks_in_flight`] at a time, and then commits the block list. Each request gets the full
+    /// per-request timeout.
+    async fn put_blocks(
+        &self,
+        blob_client: &BlobClient,
+        blocks: impl Stream<Item = anyhow::Result<(usize, Bytes)>>,
+        metadata: Option<StorageMetadata>,
+        content_encoding: Option<Compression>,
+    ) -> anyhow::Result<()> {
+        // Uncommitted blocks of concurrent uploads to the same blob share one namespace
+        let nonce: u64 = rand::random();
+        let block_ids = blocks
+            .map_ok(|(i, data)| async move {
+                let block_id = BlockId::new(block_id(nonce, i));
+                self.with_timeout(blob_client.put_block(block_id.clone(), data).into_future())
+                    .await?;
+                anyhow::Ok(block_id)
+            })
+            .try_buffered(blocks_in_flight(
+                self.max_block_size,
+                self.max_concurrency_per_upload,
+            ))
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        let block_list = BlockList {
+            blocks: block_ids
+                .into_iter()
+                .map(BlobBlockType::new_uncommitted)
                 .collect(),
-        )
+        };
+        let mut builder = blob_client.put_block_list(block_list);
+        if let Some(metadata) = metadata {
+            builder = builder.metadata(to_azure_metadata(metadata));
+        }
+        if let Some(content_encoding) = content_encoding {
+            builder = builder.content_encoding(BlobContentEncoding::from(
+                content_encoding.as_content_encoding(),
+            ));
+        }
+        self.with_timeout(builder.into_future()).await
     }
 
+    /// Downloads the blob, failing with [`DownloadError::Timeout`] at the caller's `deadline` if
+    /// that comes before the request timeout.
     async fn download_for_builder(
         &self,
         builder: GetBlobBuilder,
+        deadline: Option<tokio::time::Instant>,
         cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
         let kind = RequestKind::Get;
 
         let _permit = self.permit(kind, cancel).await?;
-        let cancel_or_timeout = crate::support::cancel_or_timeout(self.timeout, cancel.clone());
-        let cancel_or_timeout_ = crate::support::cancel_or_timeout(self.timeout, cancel.clone());
+        let timeout = crate::support::timeout_until(self.timeouts.for_kind(kind), deadline);
+        let cancel_or_timeout = crate::support::cancel_or_timeout(timeout, cancel.clone());
+        let cancel_or_timeout_ = crate::support::cancel_or_timeout(timeout, cancel.clone());
 
         let mut etag = None;
         let mut last_modified = None;
+        let mut content_encoding = None;
         let mut metadata = HashMap::new();
 
         let started_at = start_measuring_requests(kind);
@@ -151,12 +413,12 @@ impl AzureBlobStorage {
                 .map_err(to_download_error);
 
             // apply per request timeout
-            let response = tokio_stream::StreamExt::timeout(response, self.timeout);
+            let response = tokio_stream::StreamExt::timeout(response, timeout);
 
             // flatten
             let response = response.map(|res| match res {
                 Ok(res) => res,
-                Err(_elapsed) => Err(DownloadError::Timeout),
+                Err(_elapsed) => Err(DownloadError::Timeout(None)),
             });
 
             let mut response = Box::pin(response);
@@ -168,15 +430,31 @@ impl AzureBlobStorage {
             };
             let part = part?;
             if etag.is_none() {
-                etag = Some(part.blob.properties.etag);
+                etag = Some(Etag::from(part.blob.properties.etag.to_string()));
             }
             if last_modified.is_none() {
                 last_modified = Some(part.blob.properties.last_modified.into());
             }
+            if content_encoding.is_none() {
+                content_encoding = part
+                    .blob
+                    .properties
+                    .content_encoding
+                    .as_deref()
+                    .and_then(Compression::from_content_encoding);
+            }
             if let Some(blob_meta) = part.blob.metadata {
                 metadata.extend(blob_meta.iter().map(|(k, v)| (k.to_owned(), v.to_owned())));
             }
 
+            // The size of the whole blob, also for ranged requests, see `download_byte_range`. The
+            // SDK fetches blobs in chunks, also without a range in the request, so each response is
+            // a range, and the size of the blob is the total of its `Content-Range`.
+            let blob_size = match &part.content_range {
+                Some(content_range) => content_range.total_length(),
+                None => part.blob.properties.content_length,
+            };
+
             // unwrap safety: if these were None, bufs would be empty and we would have returned an error already
             let etag = etag.unwrap();
             let last_modified = last_modified.unwrap();
@@ -196,20 +474,26 @@ impl AzureBlobStorage {
             //.chain(SyncStream::from_pin(Box::pin(tail_stream)));
 
             let download_stream = crate::support::DownloadStream::new(cancel_or_timeout_, stream);
+            let download_stream = CountingDownload::new(self.traffic.clone(), download_stream);
 
             Ok(Download {
                 download_stream: Box::pin(download_stream),
                 etag,
+                content_length: blob_size,
+                object_size: blob_size,
                 last_modified,
-                metadata: Some(StorageMetadata(metadata)),
+                // Azure preserves the case of keys, so blobs from before they were normalized
+                // may still carry uppercase ones
+                metadata: Some(StorageMetadata(metadata).normalized()),
+                content_encoding,
             })
         };
 
         let download = tokio::select! {
             bufs = download => bufs,
             cancel_or_timeout = cancel_or_timeout => match cancel_or_timeout {
-                TimeoutOrCancel::Timeout => return Err(DownloadError::Timeout),
-                TimeoutOrCancel::Cancel => return Err(DownloadError::Cancelled),
+                TimeoutOrCancel::Timeout => return Err(DownloadError::Timeout(None)),
+                TimeoutOrCancel::Cancel => return Err(DownloadError::Cancelled(None)),
             },
         };
         let started_at = ScopeGuard::into_inner(started_at);
@@ -228,28 +512,146 @@ impl AzureBlobStorage {
         kind: RequestKind,
         cancel: &CancellationToken,
     ) -> Result<tokio::sync::SemaphorePermit<'_>, Cancelled> {
-        let acquire = self.concurrency_limiter.acquire(kind);
+        // Take the rate limit token first, so that waiting for it doesn't occupy a concurrency slot
+        let acquire = async {
+            self.rate_limiter.acquire(kind).await;
+            self.concurrency_limiter.acquire(kind).await
+        };
+
+        let permit = tokio::select! {
+            permit = acquire => permit.expect("never closed"),
+            _ = cancel.cancelled() => return Err(Cancelled),
+        };
+        // Every request is sent with a permit
+        self.traffic.record_request();
+        Ok(permit)
+    }
+}
 
-        tokio::select! {
-            permit = acquire => Ok(permit.expect("never closed")),
-            _ = cancel.cancelled() => Err(Cancelled),
+/// Normalizes `prefix_in_container` like `prefix_in_bucket`, except that a deprecated leading
+/// separator is kept: older versions kept it in the blob names, which must still be found.
+fn normalize_prefix_in_container(prefix: Option<&str>) -> Option<String> {
+    let normalized = support::normalize_storage_prefix(prefix)?;
+    match prefix {
+        Some(prefix)
+            if prefix.starts_with(REMOTE_STORAGE_PREFIX_SEPARATOR) && !normalized.is_empty() =>
+        {
+            Some(format!("{REMOTE_STORAGE_PREFIX_SEPARATOR}{normalized}"))
         }
+        _ => Some(normalized),
     }
 }
 
 fn to_azure_metadata(metadata: StorageMetadata) -> Metadata {
     let mut res = Metadata::new();
-    for (k, v) in metadata.0.into_iter() {
+    for (k, v) in metadata.normalized().0.into_iter() {
         res.insert(k, v);
     }
     res
 }
 
+/// Returns true if Azure rejected the request because we are sending too many of them: this is
+/// a 503 `ServerBusy` for storage accounts, or a 429 from intermediaries.
+/// Fails uploads with options that Azure Blob Storage only has per container.
+fn check_upload_options(to: &RemotePath, options: &UploadOptions) -> anyhow::Result<()> {
+    if let Some(acl) = options.acl {
+        anyhow::bail!(
+            "cannot upload {to} with ACL {acl:?}: Azure Blob Storage has no per-blob ACLs, public access is configured on the container"
+        )
+    }
+    if let Some(object_lock) = options.object_lock {
+        anyhow::bail!(
+            "cannot upload {to} with {object_lock:?}: Azure Blob Storage configures immutability policies on the container"
+        )
+    }
+    Ok(())
+}
+
+/// The most blocks a block blob can be committed with.
+/// <https://learn.microsoft.com/en-us/rest/api/storageservices/put-block-list#remarks>
+const MAX_BLOCKS_PER_BLOB: usize = 50_000;
+
+/// How many bytes of blocks a single upload buffers at most while staging them, unless a single
+/// block is larger than that.
+const MAX_BLOCK_BYTES_IN_FLIGHT: usize = 256 * 1024 * 1024;
+
+/// How many blocks of `block_size` bytes an upload stages at once: `max_concurrency`, but no more
+/// than fit into [`MAX_BLOCK_BYTES_IN_FLIGHT`], and at least one.
+fn blocks_in_flight(block_size: usize, max_concurrency: usize) -> usize {
+    (MAX_BLOCK_BYTES_IN_FLIGHT / block_size).clamp(1, max_concurrency.max(1))
+}
+
+/// The id of the `i`-th block of the upload with the given `nonce`.
+///
+/// Block ids must all have the same length within a blob, and the nonce keeps the staged blocks of
+/// concurrent uploads to the same blob apart.
+fn block_id(nonce: u64, i: usize) -> String {
+    format!("{nonce:016x}-{i:016}")
+}
+
+/// Splits `from` into numbered blocks of `block_size` bytes for [`AzureBlobStorage::put_blocks`],
+/// failing once there are more than [`MAX_BLOCKS_PER_BLOB`] of them. With `data_size_bytes`, also
+/// fails if `from` is shorter or longer than that.
+fn numbered_blocks(
+    from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+    block_size: usize,
+    data_size_bytes: Option<usize>,
+) -> impl Stream<Item = anyhow::Result<(usize, Bytes)>> + Send {
+    let chunks = Box::pin(support::chunks(from, block_size));
+    futures::stream::try_unfold((chunks, 0, 0), move |(mut chunks, i, read)| async move {
+        let Some(data) = chunks.next().await else {
+            if let Some(data_size_bytes) = data_size_bytes {
+                anyhow::ensure!(
+                    read == data_size_bytes,
+                    "upload stream ended after {read} of {data_size_bytes} bytes"
+                );
+            }
+            return Ok(None);
+        };
+        let data = data.with_context(|| format!("read block {i} of the upload stream"))?;
+        anyhow::ensure!(
+            i < MAX_BLOCKS_PER_BLOB,
+            "upload stream needs more than the {MAX_BLOCKS_PER_BLOB} blocks of {block_size} bytes a blob can have"
+        );
+        let read = read + data.len();
+        if let Some(data_size_bytes) = data_size_bytes {
+            anyhow::ensure!(
+                read <= data_size_bytes,
+                "upload stream is longer than {data_size_bytes} bytes"
+            );
+        }
+        Ok(Some(((i, data), (chunks, i + 1, read))))
+    })
+}
+
+fn is_throttled(error: &azure_core::Error) -> bool {
+    let throttled = error.as_http_error().is_some_and(|http_err| {
+        matches!(
+            http_err.status(),
+            StatusCode::ServiceUnavailable | StatusCode::TooManyRequests
+        )
+    });
+    if throttled {
+        crate::metrics::BUCKET_METRICS
+            .throttled_total
+            .with_label_values(&["azure"])
+            .inc();
+    }
+    throttled
+}
+
 fn to_download_error(error: azure_core::Error) -> DownloadError {
+    if is_throttled(&error) {
+        return DownloadError::Throttled(anyhow::Error::new(error));
+    }
     if let Some(http_err) = error.as_http_error() {
         match http_err.status() {
-            StatusCode::NotFound => DownloadError::NotFound,
+            StatusCode::NotFound => DownloadError::NotFound(None),
+            StatusCode::Forbidden => DownloadError::Forbidden(anyhow::Error::new(error)),
             StatusCode::BadRequest => DownloadError::BadInput(anyhow::Error::new(error)),
+            StatusCode::RequestedRangeNotSatisfiable => {
+                DownloadError::InvalidRange(anyhow::Error::new(error))
+            }
             _ => DownloadError::Other(anyhow::Error::new(error)),
         }
     } else {
@@ -257,31 +659,53 @@ fn to_download_error(error: azure_core::Error) -> DownloadError {
     }
 }
 
+/// Converts errors of `anyhow::Error` returning operations, marking throttling with [`Throttled`].
+/// Returns true if Azure refused to copy the blob server-side, while downloading and uploading it
+/// again would work, e.g. because the source can't be read through its URL by the copy.
+fn is_copy_ineligible(error: &anyhow::Error) -> bool {
+    let Some(http_err) = error
+        .downcast_ref::<azure_core::Error>()
+        .and_then(|e| e.as_http_error())
+    else {
+        return false;
+    };
+    http_err.status() == StatusCode::NotImplemented
+        || http_err.error_code() == Some("CannotVerifyCopySource")
+}
+
+fn to_anyhow_error(error: azure_core::Error) -> anyhow::Error {
+    if is_throttled(&error) {
+        anyhow::Error::new(error).context(Throttled)
+    } else {
+        error.into()
+    }
+}
+
 impl RemoteStorage for AzureBlobStorage {
     async fn list(
         &self,
         prefix: Option<&RemotePath>,
         mode: ListingMode,
         max_keys: Option<NonZeroU32>,
+        options: ListOptions,
         cancel: &CancellationToken,
     ) -> anyhow::Result<Listing, DownloadError> {
         let _permit = self.permit(RequestKind::List, cancel).await?;
 
         let op = async {
-            // get the passed prefix or if it is not set use prefix_in_bucket value
-            let list_prefix = prefix
-                .map(|p| self.relative_path_to_name(p))
-                .or_else(|| self.prefix_in_container.clone())
-                .map(|mut p| {
-                    // required to end with a separator
-                    // otherwise request will return only the entry of a prefix
-                    if matches!(mode, ListingMode::WithDelimiter)
-                        && !p.ends_with(REMOTE_STORAGE_PREFIX_SEPARATOR)
-                    {
-                        p.push(REMOTE_STORAGE_PREFIX_SEPARATOR);
-                    }
-                    p
-                });
+            let list_prefix =
+                support::storage_list_prefix(self.prefix_in_container.as_deref(), prefix).map(
+                    |mut p| {
+                        // required to end with a separator
+                        // otherwise request will return only the entry of a prefix
+                        if matches!(mode, ListingMode::WithDelimiter)
+                            && !p.ends_with(REMOTE_STORAGE_PREFIX_SEPARATOR)
+                        {
+                            p.push(REMOTE_STORAGE_PREFIX_SEPARATOR);
+                        }
+                        p
+                    },
+                );
 
             let mut builder = self.client.list_blobs();
 
@@ -297,12 +721,24 @@ impl RemoteStorage for AzureBlobStorage {
                 builder = builder.max_results(MaxResults::new(limit));
             }
 
+            // Unlike S3, Azure can return the metadata in the listing itself
+            if options.with_metadata {
+                builder = builder.include_metadata(true);
+            }
+
+            if options.include_version_ids {
+                builder = builder.include_versions(true);
+            }
+
             let response = builder.into_stream();
             let response = response.into_stream().map_err(to_download_error);
-            let response = tokio_stream::StreamExt::timeout(response, self.timeout);
+            let response = tokio_stream::StreamExt::timeout(
+                response,
+                self.timeouts.for_kind(RequestKind::List),
+            );
             let response = response.map(|res| match res {
                 Ok(res) => res,
-                Err(_elapsed) => Err(DownloadError::Timeout),
+                Err(_elapsed) => Err(DownloadError::Timeout(None)),
             });
 
             let mut response = std::pin::pin!(response);
@@ -318,13 +754,23 @@ impl RemoteStorage for AzureBlobStorage {
                     .map(|prefix| self.name_to_relative_path(&prefix.name));
                 res.prefixes.extend(prefix_iter);
 
-                let blob_iter = entry
-                    .blobs
-                    .blobs()
-                    .map(|k| self.name_to_relative_path(&k.name));
+                let blob_iter = entry.blobs.blobs().map(|k| ListingObject {
+                    key: self.name_to_relative_path(&k.name),
+                    last_modified: k.properties.last_modified.into(),
+                    size: k.properties.content_length,
+                    metadata: k
+                        .metadata
+                        .clone()
+                        .map(|metadata| StorageMetadata(metadata).normalized()),
+                    version_id: k.version_id.clone(),
+                });
 
-                for key in blob_iter {
-                    res.keys.push(key);
+                for object in blob_iter {
+                    if matches!(options.modified_since, Some(since) if object.last_modified < since)
+                    {
+                        continue;
+                    }
+                    res.objects.push(object);
 
                     if let Some(mut mk) = max_keys {
                         assert!(mk > 0);
@@ -340,99 +786,225 @@ impl RemoteStorage for AzureBlobStorage {
             Ok(res)
         };
 
-        tokio::select! {
-            res = op => res,
-            _ = cancel.cancelled() => Err(DownloadError::Cancelled),
-        }
+        let mut listing = tokio::select! {
+            res = op => res?,
+            _ = cancel.cancelled() => return Err(DownloadError::Cancelled(None)),
+        };
+        // Azure lists in this order already, but callers merging listings rely on it
+        listing.sort();
+        Ok(listing)
     }
 
-    async fn upload(
+    async fn list_page(
         &self,
-        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
-        data_size_bytes: usize,
-        to: &RemotePath,
-        metadata: Option<StorageMetadata>,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
         cancel: &CancellationToken,
-    ) -> anyhow::Result<()> {
-        let kind = RequestKind::Put;
-        let _permit = self.permit(kind, cancel).await?;
-
-        let started_at = start_measuring_requests(kind);
+    ) -> Result<(Listing, Option<ContinuationToken>), DownloadError> {
+        let _permit = self.permit(RequestKind::List, cancel).await?;
 
         let op = async {
-            let blob_client = self.client.blob_client(self.relative_path_to_name(to));
+            let list_prefix =
+                support::storage_list_prefix(self.prefix_in_container.as_deref(), prefix).map(
+                    |mut p| {
+                        // required to end with a separator, like in `list`
+                        if matches!(mode, ListingMode::WithDelimiter)
+                            && !p.ends_with(REMOTE_STORAGE_PREFIX_SEPARATOR)
+                        {
+                            p.push(REMOTE_STORAGE_PREFIX_SEPARATOR);
+                        }
+                        p
+                    },
+                );
 
-            let from: Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static>> =
-                Box::pin(from);
+            let mut builder = self.client.list_blobs();
 
-            let from = NonSeekableStream::new(from, data_size_bytes);
+            if let ListingMode::WithDelimiter = mode {
+                builder = builder.delimiter(REMOTE_STORAGE_PREFIX_SEPARATOR.to_string());
+            }
 
-            let body = azure_core::Body::SeekableStream(Box::new(from));
+            if let Some(prefix) = list_prefix {
+                builder = builder.prefix(Cow::from(prefix.to_owned()));
+            }
 
-            let mut builder = blob_client.put_block_blob(body);
+            let limit = self
+                .max_keys_per_list_response
+                .into_iter()
+                .chain(max_keys)
+                .min();
+            if let Some(limit) = limit {
+                builder = builder.max_results(MaxResults::new(limit));
+            }
 
-            if let Some(metadata) = metadata {
-                builder = builder.metadata(to_azure_metadata(metadata));
+            if let Some(resume_from) = resume_from {
+                builder = builder.marker(NextMarker::new(resume_from.into_inner()));
             }
 
-            let fut = builder.into_future();
-            let fut = tokio::time::timeout(self.timeout, fut);
+            // Only the first page of the pageable: it is the one `resume_from` points to
+            let mut response = builder.into_stream();
+            let page =
+                tokio::time::timeout(self.timeouts.for_kind(RequestKind::List), response.next())
+                    .await
+                    .map_err(|_elapsed| DownloadError::Timeout(None))?;
+            let Some(page) = page else {
+                return Ok((Listing::default(), None));
+            };
+            let page = page.map_err(to_download_error)?;
 
-            match fut.await {
-                Ok(Ok(_response)) => Ok(()),
-                Ok(Err(azure)) => Err(azure.into()),
-                Err(_timeout) => Err(TimeoutOrCancel::Timeout.into()),
-            }
+            let mut listing = Listing::default();
+            listing.prefixes.extend(
+                page.blobs
+                    .prefixes()
+                    .map(|prefix| self.name_to_relative_path(&prefix.name)),
+            );
+            listing
+                .objects
+                .extend(page.blobs.blobs().map(|k| ListingObject {
+                    key: self.name_to_relative_path(&k.name),
+                    last_modified: k.properties.last_modified.into(),
+                    size: k.properties.content_length,
+                    metadata: None,
+                    version_id: None,
+                }));
+            let next = page
+                .next_marker
+                .map(|marker| ContinuationToken(marker.as_str().to_owned()));
+            Ok((listing, next))
         };
 
-        let res = tokio::select! {
-            res = op => res,
-            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        let (mut listing, next) = tokio::select! {
+            res = op => res?,
+            _ = cancel.cancelled() => return Err(DownloadError::Cancelled(None)),
         };
+        listing.sort();
+        Ok((listing, next))
+    }
 
-        let outcome = match res {
-            Ok(_) => AttemptOutcome::Ok,
-            Err(_) => AttemptOutcome::Err,
-        };
-        let started_at = ScopeGuard::into_inner(started_at);
-        crate::metrics::BUCKET_METRICS
-            .req_seconds
-            .observe_elapsed(kind, outcome, started_at);
+    async fn upload(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.upload0(from, data_size_bytes, to, options, cancel)
+            .await
+    }
 
-        res
+    async fn upload_unsized(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.upload_unsized0(from, to, options, cancel).await
     }
 
     async fn download(
         &self,
         from: &RemotePath,
         cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_with_deadline(from, None, cancel).await
+    }
+
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_byte_range_with_deadline(from, start_inclusive, end_exclusive, None, cancel)
+            .await
+    }
+
+    async fn download_with_deadline(
+        &self,
+        from: &RemotePath,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
         let blob_client = self.client.blob_client(self.relative_path_to_name(from));
 
         let builder = blob_client.get();
 
-        self.download_for_builder(builder, cancel).await
+        self.download_for_builder(builder, deadline, cancel).await
     }
 
-    async fn download_byte_range(
+    async fn download_byte_range_with_deadline(
         &self,
         from: &RemotePath,
         start_inclusive: u64,
         end_exclusive: Option<u64>,
+        deadline: Option<tokio::time::Instant>,
         cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
+        support::check_byte_range(start_inclusive, end_exclusive)?;
+
         let blob_client = self.client.blob_client(self.relative_path_to_name(from));
 
         let mut builder = blob_client.get();
 
-        let range: Range = if let Some(end_exclusive) = end_exclusive {
-            (start_inclusive..end_exclusive).into()
-        } else {
-            (start_inclusive..).into()
+        let range: Option<Range> = match end_exclusive {
+            Some(end_exclusive) => Some((start_inclusive..end_exclusive).into()),
+            // An open ended range from the start is the whole blob: send it as a plain GET, so
+            // that the response is identical to `download`.
+            None if start_inclusive == 0 => None,
+            None => Some((start_inclusive..).into()),
+        };
+        if let Some(range) = range {
+            builder = builder.range(range);
+        }
+
+        let mut download = self.download_for_builder(builder, deadline, cancel).await?;
+        let blob_size = download.object_size;
+        download.content_length = end_exclusive
+            .unwrap_or(blob_size)
+            .min(blob_size)
+            .saturating_sub(start_inclusive);
+        Ok(download)
+    }
+
+    async fn head_object(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError> {
+        let kind = RequestKind::Get;
+        let _permit = self.permit(kind, cancel).await?;
+        let started_at = start_measuring_requests(kind);
+
+        let blob_client = self.client.blob_client(self.relative_path_to_name(key));
+        let op = blob_client.get_properties().into_future();
+
+        let res = tokio::select! {
+            res = tokio::time::timeout(self.timeouts.for_kind(kind), op) => match res {
+                Ok(res) => res.map_err(to_download_error),
+                Err(_elapsed) => Err(DownloadError::Timeout(None)),
+            },
+            _ = cancel.cancelled() => return Err(DownloadError::Cancelled(None)),
         };
-        builder = builder.range(range);
 
-        self.download_for_builder(builder, cancel).await
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &res, started_at);
+
+        let blob = res?.blob;
+        Ok(ListingObject {
+            key: key.clone(),
+            last_modified: blob.properties.last_modified.into(),
+            size: blob.properties.content_length,
+            metadata: blob
+                .metadata
+                .map(|metadata| StorageMetadata(metadata).normalized()),
+            version_id: blob.version_id,
+        })
     }
 
     async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
@@ -440,6 +1012,52 @@ impl RemoteStorage for AzureBlobStorage {
             .await
     }
 
+    async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let kind = RequestKind::Delete;
+        let _permit = self.permit(kind, cancel).await?;
+        let started_at = start_measuring_requests(kind);
+
+        let op = async {
+            let res = self
+                .client
+                .blob_client(self.relative_path_to_name(path))
+                .delete()
+                .if_match(IfMatchCondition::Match(etag.to_string()))
+                .into_future()
+                .await;
+            match res {
+                Ok(_) => Ok(()),
+                Err(e) => match e.as_http_error().map(|http_err| http_err.status()) {
+                    Some(StatusCode::PreconditionFailed) => Err(anyhow::Error::new(e)
+                        .context(PreconditionFailed)
+                        .context(format!("delete {path} if it matches {etag}"))),
+                    // Deleted already, e.g. by an earlier attempt of this deletion
+                    Some(StatusCode::NotFound) => Ok(()),
+                    _ => Err(to_anyhow_error(e)).with_context(|| format!("delete {path}")),
+                },
+            }
+        };
+
+        let res = tokio::select! {
+            res = tokio::time::timeout(self.timeouts.for_kind(kind), op) => match res {
+                Ok(res) => res,
+                Err(_elapsed) => Err(TimeoutOrCancel::Timeout.into()),
+            },
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        };
+
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &res, started_at);
+        res
+    }
+
     async fn delete_objects<'a>(
         &self,
         paths: &'a [RemotePath],
@@ -472,7 +1090,7 @@ impl RemoteStorage for AzureBlobStorage {
 
                         let request = blob_client.delete().into_future();
 
-                        let res = tokio::time::timeout(self.timeout, request).await;
+                        let res = tokio::time::timeout(self.timeouts.for_kind(kind), request).await;
 
                         match res {
                             Ok(Ok(_v)) => Ok(()),
@@ -500,7 +1118,7 @@ impl RemoteStorage for AzureBlobStorage {
                 .ok_or_else(|| AzureOrTimeout::Cancel)
                 .and_then(|x| x)
                 .map_err(|e| match e {
-                    AzureOrTimeout::AzureError(err) => anyhow::Error::from(err),
+                    AzureOrTimeout::AzureError(err) => to_anyhow_error(err),
                     AzureOrTimeout::Timeout => TimeoutOrCancel::Timeout.into(),
                     AzureOrTimeout::Cancel => TimeoutOrCancel::Cancel.into(),
                 })?;
@@ -524,15 +1142,17 @@ impl RemoteStorage for AzureBlobStorage {
         &self,
         from: &RemotePath,
         to: &RemotePath,
+        metadata: CopyMetadata,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
         let kind = RequestKind::Copy;
-        let _permit = self.permit(kind, cancel).await?;
+        let permit = self.permit(kind, cancel).await?;
         let started_at = start_measuring_requests(kind);
 
-        let timeout = tokio::time::sleep(self.timeout);
+        let timeout = tokio::time::sleep(self.timeouts.for_kind(kind));
 
         let mut copy_status = None;
+        let fallback_metadata = metadata.clone();
 
         let op = async {
             let blob_client = self.client.blob_client(self.relative_path_to_name(to));
@@ -543,10 +1163,17 @@ impl RemoteStorage for AzureBlobStorage {
                 self.relative_path_to_name(from)
             );
 
-            let builder = blob_client.copy(Url::from_str(&source_url)?);
+            let mut builder = blob_client.copy(Url::from_str(&source_url)?);
+            // Without metadata in the request, Azure copies the source blob's metadata, so empty
+            // metadata can only be set once the copy is done
+            let mut clear_metadata = false;
+            if let CopyMetadata::Replace(metadata) = metadata {
+                clear_metadata = metadata.0.is_empty();
+                builder = builder.metadata(to_azure_metadata(metadata));
+            }
             let copy = builder.into_future();
 
-            let result = copy.await?;
+            let result = copy.await.map_err(to_anyhow_error)?;
 
             copy_status = Some(result.copy_status);
             loop {
@@ -557,7 +1184,7 @@ impl RemoteStorage for AzureBlobStorage {
                     CopyStatus::Failed => {
                         anyhow::bail!("Received failure response for copy from {from} to {to}.");
                     }
-                    CopyStatus::Success => return Ok(()),
+                    CopyStatus::Success => break,
                     CopyStatus::Pending => (),
                 }
                 // The copy is taking longer. Waiting a second and then re-trying.
@@ -566,10 +1193,20 @@ impl RemoteStorage for AzureBlobStorage {
                 let properties = blob_client.get_properties().into_future().await?;
                 let Some(status) = properties.blob.properties.copy_status else {
                     tracing::warn!("copy_status for copy is None!, from={from}, to={to}");
-                    return Ok(());
+                    break;
                 };
                 copy_status = Some(status);
             }
+
+            if clear_metadata {
+                // Setting metadata without any entries removes all of them
+                blob_client
+                    .set_metadata()
+                    .into_future()
+                    .await
+                    .map_err(to_anyhow_error)?;
+            }
+            Ok(())
         };
 
         let res = tokio::select! {
@@ -582,6 +1219,61 @@ impl RemoteStorage for AzureBlobStorage {
             },
         };
 
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &res, started_at);
+
+        match res {
+            Err(e) if is_copy_ineligible(&e) => {
+                // The download and upload take permits of their own
+                drop(permit);
+                tracing::info!(
+                    "{from} can't be copied server-side ({e:#}), copying it to {to} by downloading and re-uploading it"
+                );
+                crate::support::copy_by_streaming(self, from, to, fallback_metadata, cancel).await
+            }
+            res => res,
+        }
+    }
+
+    async fn touch(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let kind = RequestKind::Copy;
+        let _permit = self.permit(kind, cancel).await?;
+        let started_at = start_measuring_requests(kind);
+
+        let op = async {
+            let blob_client = self.client.blob_client(self.relative_path_to_name(path));
+
+            // Properties missing from the request are cleared, so all of them are set to their
+            // current values. The ETag condition keeps a blob overwritten in the meantime from
+            // getting the properties of the one it replaced.
+            let properties = blob_client
+                .get_properties()
+                .into_future()
+                .await
+                .map_err(to_anyhow_error)?
+                .blob
+                .properties;
+            let etag = properties.etag.to_string();
+            blob_client
+                .set_properties()
+                .set_from_blob_properties(properties)
+                .if_match(IfMatchCondition::Match(etag))
+                .into_future()
+                .await
+                .map_err(to_anyhow_error)?;
+            Ok(())
+        };
+
+        let res = tokio::select! {
+            res = tokio::time::timeout(self.timeouts.for_kind(kind), op) => match res {
+                Ok(res) => res,
+                Err(_elapsed) => Err(TimeoutOrCancel::Timeout.into()),
+            },
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        };
+
         let started_at = ScopeGuard::into_inner(started_at);
         crate::metrics::BUCKET_METRICS
             .req_seconds
@@ -595,11 +1287,43 @@ impl RemoteStorage for AzureBlobStorage {
         _timestamp: SystemTime,
         _done_if_after: SystemTime,
         _cancel: &CancellationToken,
-    ) -> Result<(), TimeTravelError> {
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
         // TODO use Azure point in time recovery feature for this
         // https://learn.microsoft.com/en-us/azure/storage/blobs/point-in-time-restore-overview
         Err(TimeTravelError::Unimplemented)
     }
+
+    async fn abort_incomplete_uploads(
+        &self,
+        _prefix: Option<&RemotePath>,
+        _older_than: Duration,
+        _cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        // Uncommitted blocks are garbage collected by Azure after a week
+        Ok(0)
+    }
+
+    fn traffic_stats(&self) -> TrafficStats {
+        self.traffic.stats()
+    }
+
+    fn describe(&self) -> StorageDescription {
+        StorageDescription {
+            backend: "azure",
+            bucket_or_container: self.container_name.clone(),
+            prefix: self.prefix_in_container.clone(),
+            endpoint: self.client.url().ok().map(|url| url.to_string()),
+            region: Some(self.container_region.clone()),
+        }
+    }
+
+    fn redacted_config(&self) -> serde_json::Value {
+        RemoteStorageConfig {
+            storage: RemoteStorageKind::AzureContainer(AzureConfig::clone(&self.config)),
+            timeouts: self.timeouts,
+        }
+        .redacted_config()
+    }
 }
 
 pin_project_lite::pin_project! {
@@ -751,3 +1475,99 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream_of(data: &[u8], piece: usize) -> impl Stream<Item = std::io::Result<Bytes>> {
+        let pieces = data
+            .chunks(piece)
+            .map(|piece| Ok(Bytes::copy_from_slice(piece)))
+            .collect::<Vec<_>>();
+        futures::stream::iter(pieces)
+    }
+
+    #[test]
+    fn block_ids_have_one_length_and_differ_between_uploads() {
+        let ids = [
+            block_id(0, 0),
+            block_id(u64::MAX, 0),
+            block_id(1, MAX_BLOCKS_PER_BLOB - 1),
+        ];
+        assert!(ids.iter().all(|id| id.len() == ids[0].len()), "{ids:?}");
+        assert_ne!(block_id(1, 7), block_id(2, 7));
+        assert_ne!(block_id(1, 7), block_id(1, 8));
+    }
+
+    #[test]
+    fn blocks_in_flight_is_bounded_by_memory() {
+        assert_eq!(blocks_in_flight(DEFAULT_AZURE_MAX_BLOCK_SIZE, 16), 1);
+        assert_eq!(blocks_in_flight(1024 * 1024, 16), 16);
+        assert_eq!(
+            blocks_in_flight(1024 * 1024, 1024),
+            MAX_BLOCK_BYTES_IN_FLIGHT / (1024 * 1024)
+        );
+        assert_eq!(blocks_in_flight(1, 0), 1);
+    }
+
+    #[tokio::test]
+    async fn numbered_blocks_split_the_stream() {
+        let data = (0..100u8).collect::<Vec<_>>();
+        let blocks = numbered_blocks(stream_of(&data, 7), 30, Some(data.len()))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let numbers = blocks.iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        assert_eq!(numbers, [0, 1, 2, 3]);
+        let sizes = blocks
+            .iter()
+            .map(|(_, data)| data.len())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [30, 30, 30, 10]);
+        let joined = blocks
+            .iter()
+            .flat_map(|(_, data)| data.iter().copied())
+            .collect::<Vec<_>>();
+        assert_eq!(joined, data);
+    }
+
+    #[tokio::test]
+    async fn numbered_blocks_check_the_size() {
+        let data = vec![1; 100];
+        let short = numbered_blocks(stream_of(&data, 7), 30, Some(101))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(
+            short.to_string().contains("ended after 100 of 101"),
+            "{short}"
+        );
+
+        let long = numbered_blocks(stream_of(&data, 7), 30, Some(99))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(long.to_string().contains("longer than 99"), "{long}");
+    }
+
+    #[tokio::test]
+    async fn numbered_blocks_are_limited() {
+        let data = vec![1; MAX_BLOCKS_PER_BLOB + 1];
+        let blocks = numbered_blocks(stream_of(&data, 4096), 1, None)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(
+            blocks.to_string().contains("more than the 50000"),
+            "{blocks}"
+        );
+
+        let data = vec![1; MAX_BLOCKS_PER_BLOB];
+        let blocks = numbered_blocks(stream_of(&data, 4096), 1, None)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(blocks.len(), MAX_BLOCKS_PER_BLOB);
+    }
+}
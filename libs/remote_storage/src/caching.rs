@@ -0,0 +1,521 @@
+//! A wrapper around a [`RemoteStorage`] which remembers the results of [`RemoteStorage::head_object`]
+//! and [`RemoteStorage::list`] for a while, for polling loops which check the same keys over and
+//! over again, like secondary tenant downloads and the scrubber.
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use futures::stream::Stream;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    ContinuationToken, CopyMetadata, Download, DownloadError, Etag, ListOptions, Listing,
+    ListingMode, ListingObject, RemotePath, RemoteStorage, StorageDescription, TimeTravelError,
+    TimeTravelSummary, TrafficStats, UploadOptions,
+};
+
+/// Caches the outcome of [`RemoteStorage::head_object`], including [`DownloadError::NotFound`], and
+/// of [`RemoteStorage::list`] for `ttl`.
+///
+/// **Results may be stale**: only modifications made through this wrapper invalidate the cached
+/// results for the affected keys and the listings which could contain them. Anything written to
+/// the storage by other processes, or through other handles of the same process, is only seen
+/// once the cached results expire. So this is opt-in, for callers which can tolerate missing
+/// changes for up to `ttl`. Downloads are never cached.
+pub struct CachingStorage<S> {
+    inner: S,
+    ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// Incremented by every invalidation, so that requests which were already in flight while
+    /// the storage was modified don't cache what they saw.
+    generation: u64,
+    /// `None` for objects which don't exist.
+    heads: HashMap<RemotePath, Cached<Option<ListingObject>>>,
+    listings: HashMap<ListingRequest, Cached<Listing>>,
+    /// When to drop the expired entries next, which are otherwise only dropped when looked up.
+    next_prune: Option<Instant>,
+}
+
+struct Cached<T> {
+    value: T,
+    cached_at: Instant,
+}
+
+/// All the arguments of [`RemoteStorage::list`] which affect the result.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ListingRequest {
+    prefix: Option<RemotePath>,
+    mode: ListingMode,
+    max_keys: Option<NonZeroU32>,
+    options: ListOptions,
+}
+
+impl<S: RemoteStorage> CachingStorage<S> {
+    pub fn new(inner: S, ttl: Duration) -> Self {
+        CachingStorage {
+            inner,
+            ttl,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Whether there is an object at `key`, see [`RemoteStorage::head_object`].
+    pub async fn exists(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<bool, DownloadError> {
+        match self.head_object(key, cancel).await {
+            Ok(_) => Ok(true),
+            Err(DownloadError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn is_fresh<T>(&self, cached: &Cached<T>) -> bool {
+        cached.cached_at.elapsed() < self.ttl
+    }
+
+    fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    fn cached_head(&self, key: &RemotePath) -> Option<Result<ListingObject, DownloadError>> {
+        let mut state = self.state.lock().unwrap();
+        match state.heads.get(key) {
+            Some(cached) if self.is_fresh(cached) => {
+                Some(cached.value.clone().ok_or(DownloadError::NotFound(None)))
+            }
+            Some(_) => {
+                state.heads.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn cached_listing(&self, request: &ListingRequest) -> Option<Listing> {
+        let mut state = self.state.lock().unwrap();
+        match state.listings.get(request) {
+            Some(cached) if self.is_fresh(cached) => Some(cached.value.clone()),
+            Some(_) => {
+                state.listings.remove(request);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Caches a result unless the storage was modified since `generation`.
+    fn insert(&self, generation: u64, insert: impl FnOnce(&mut CacheState, Instant)) {
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return;
+        }
+        let now = Instant::now();
+        if !state.next_prune.is_some_and(|next_prune| now < next_prune) {
+            state.heads.retain(|_, cached| self.is_fresh(cached));
+            state.listings.retain(|_, cached| self.is_fresh(cached));
+            state.next_prune = Some(now + self.ttl);
+        }
+        insert(&mut state, now);
+    }
+
+    /// Forgets everything cached about the objects below `prefix`, or about all of them for
+    /// `None`. A single key is also a prefix of itself.
+    fn invalidate(&self, prefix: Option<&RemotePath>) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        let Some(prefix) = prefix else {
+            state.heads.clear();
+            state.listings.clear();
+            return;
+        };
+
+        // Listing prefixes are plain string prefixes, and may or may not end with a slash.
+        let prefix = prefix.get_path().as_str();
+        state
+            .heads
+            .retain(|key, _| !key.get_path().as_str().starts_with(prefix));
+        state.listings.retain(|request, _| match &request.prefix {
+            Some(listed) => {
+                let listed = listed.get_path().as_str();
+                !listed.starts_with(prefix) && !prefix.starts_with(listed)
+            }
+            None => false,
+        });
+    }
+}
+
+impl<S: RemoteStorage> RemoteStorage for CachingStorage<S> {
+    async fn list(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        options: ListOptions,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        let request = ListingRequest {
+            prefix: prefix.cloned(),
+            mode,
+            max_keys,
+            options,
+        };
+        if let Some(listing) = self.cached_listing(&request) {
+            return Ok(listing);
+        }
+
+        let generation = self.generation();
+        let listing = self
+            .inner
+            .list(prefix, mode, max_keys, options, cancel)
+            .await?;
+        self.insert(generation, |state, cached_at| {
+            let value = listing.clone();
+            state.listings.insert(request, Cached { value, cached_at });
+        });
+        Ok(listing)
+    }
+
+    fn list_prefixes_recursive<'a>(
+        &'a self,
+        prefix: Option<&'a RemotePath>,
+        cancel: &'a CancellationToken,
+    ) -> impl Stream<Item = Result<RemotePath, DownloadError>> + 'a {
+        // Not cached: this is for walking a whole hierarchy once, not for polling
+        self.inner.list_prefixes_recursive(prefix, cancel)
+    }
+
+    async fn list_page(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
+        cancel: &CancellationToken,
+    ) -> Result<(Listing, Option<ContinuationToken>), DownloadError> {
+        // Not cached: pages are for walking a whole listing once, not for polling
+        self.inner
+            .list_page(prefix, mode, max_keys, resume_from, cancel)
+            .await
+    }
+
+    async fn upload(
+        &self,
+        data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let res = self
+            .inner
+            .upload(data, data_size_bytes, to, options, cancel)
+            .await;
+        // Even a failed upload may have gone through, e.g. on timeouts
+        self.invalidate(Some(to));
+        res
+    }
+
+    async fn upload_bytes(
+        &self,
+        data: Bytes,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let res = self.inner.upload_bytes(data, to, options, cancel).await;
+        self.invalidate(Some(to));
+        res
+    }
+
+    async fn upload_unsized(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let res = self.inner.upload_unsized(from, to, options, cancel).await;
+        self.invalidate(Some(to));
+        res
+    }
+
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.inner.download(from, cancel).await
+    }
+
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.inner
+            .download_byte_range(from, start_inclusive, end_exclusive, cancel)
+            .await
+    }
+
+    async fn download_with_deadline(
+        &self,
+        from: &RemotePath,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.inner
+            .download_with_deadline(from, deadline, cancel)
+            .await
+    }
+
+    async fn download_byte_range_with_deadline(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.inner
+            .download_byte_range_with_deadline(
+                from,
+                start_inclusive,
+                end_exclusive,
+                deadline,
+                cancel,
+            )
+            .await
+    }
+
+    async fn head_object(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError> {
+        if let Some(res) = self.cached_head(key) {
+            return res;
+        }
+
+        let generation = self.generation();
+        let res = self.inner.head_object(key, cancel).await;
+        let value = match &res {
+            Ok(object) => Some(object.clone()),
+            Err(DownloadError::NotFound(_)) => None,
+            // Errors other than the object missing are not remembered
+            Err(_) => return res,
+        };
+        self.insert(generation, |state, cached_at| {
+            state.heads.insert(key.clone(), Cached { value, cached_at });
+        });
+        res
+    }
+
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let res = self.inner.delete(path, cancel).await;
+        self.invalidate(Some(path));
+        res
+    }
+
+    async fn delete_if_exists(
+        &self,
+        path: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<bool> {
+        // Not through the cached `head_object`, which might not have seen the object yet
+        let res = self.inner.delete_if_exists(path, cancel).await;
+        self.invalidate(Some(path));
+        res
+    }
+
+    async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let res = self.inner.delete_if_match(path, etag, cancel).await;
+        self.invalidate(Some(path));
+        res
+    }
+
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let res = self.inner.delete_objects(paths, cancel).await;
+        for path in paths {
+            self.invalidate(Some(path));
+        }
+        res
+    }
+
+    async fn delete_prefix(
+        &self,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        // Not the default implementation, which would list through the cache
+        let res = self.inner.delete_prefix(prefix, cancel).await;
+        self.invalidate(Some(prefix));
+        res
+    }
+
+    async fn delete_by_tag(
+        &self,
+        prefix: &RemotePath,
+        tag_key: &str,
+        tag_value: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        let res = self
+            .inner
+            .delete_by_tag(prefix, tag_key, tag_value, cancel)
+            .await;
+        self.invalidate(Some(prefix));
+        res
+    }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        metadata: CopyMetadata,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let res = self.inner.copy(from, to, metadata, cancel).await;
+        self.invalidate(Some(to));
+        res
+    }
+
+    async fn touch(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let res = self.inner.touch(path, cancel).await;
+        self.invalidate(Some(path));
+        res
+    }
+
+    async fn time_travel_recover(
+        &self,
+        prefix: Option<&RemotePath>,
+        timestamp: SystemTime,
+        done_if_after: SystemTime,
+        cancel: &CancellationToken,
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
+        let res = self
+            .inner
+            .time_travel_recover(prefix, timestamp, done_if_after, cancel)
+            .await;
+        self.invalidate(prefix);
+        res
+    }
+
+    async fn abort_incomplete_uploads(
+        &self,
+        prefix: Option<&RemotePath>,
+        older_than: Duration,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        // Incomplete uploads are neither listed nor visible to HEAD requests
+        self.inner
+            .abort_incomplete_uploads(prefix, older_than, cancel)
+            .await
+    }
+
+    fn traffic_stats(&self) -> TrafficStats {
+        self.inner.traffic_stats()
+    }
+
+    fn describe(&self) -> StorageDescription {
+        self.inner.describe()
+    }
+
+    fn redacted_config(&self) -> serde_json::Value {
+        self.inner.redacted_config()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino_tempfile::tempdir;
+
+    use super::*;
+    use crate::LocalFs;
+
+    fn path(p: &str) -> RemotePath {
+        RemotePath::from_string(p).unwrap()
+    }
+
+    async fn upload(
+        storage: &impl RemoteStorage,
+        to: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let body = Bytes::from_static(b"cached contents");
+        let len = body.len();
+        let from = futures::stream::once(futures::future::ready(Ok(body)));
+        storage
+            .upload(from, len, to, UploadOptions::default(), cancel)
+            .await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caches_until_modified_or_expired() -> anyhow::Result<()> {
+        let root = tempdir()?;
+        let ttl = Duration::from_secs(10);
+        let storage = CachingStorage::new(
+            LocalFs::new(root.path().to_path_buf(), Duration::from_secs(120), false)?,
+            ttl,
+        );
+        let cancel = CancellationToken::new();
+        let key = path("timelines/t1/index_part.json");
+        let prefix = path("timelines/");
+        let requests = || storage.traffic_stats().requests;
+        let list = || {
+            storage.list(
+                Some(&prefix),
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
+        };
+
+        // A missing object is remembered as missing
+        assert!(!storage.exists(&key, &cancel).await?);
+        assert!(list().await?.objects.is_empty());
+        let before = requests();
+        assert!(!storage.exists(&key, &cancel).await?);
+        assert!(list().await?.objects.is_empty());
+        assert_eq!(requests(), before);
+
+        // Uploads through the wrapper invalidate the key and the listings containing it
+        upload(&storage, &key, &cancel).await?;
+        assert_eq!(storage.head_object(&key, &cancel).await?.key, key);
+        assert_eq!(list().await?.objects.len(), 1);
+
+        // Changes made around the wrapper are only seen once the results expire
+        let other = path("timelines/t2/index_part.json");
+        upload(&storage.inner, &other, &cancel).await?;
+        assert_eq!(list().await?.objects.len(), 1);
+        tokio::time::advance(ttl).await;
+        assert_eq!(list().await?.objects.len(), 2);
+
+        storage.delete(&key, &cancel).await?;
+        assert!(!storage.exists(&key, &cancel).await?);
+        assert_eq!(list().await?.objects.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Fast-fails requests to a storage which keeps failing, instead of letting every request run
+//! into the timeout while the provider is down.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Counts consecutive failed requests, and after `failure_threshold` of them opens for
+/// `cooldown`, during which [`CircuitBreaker::check`] rejects all requests.
+///
+/// Once the cooldown is over, requests are let through again. The first success closes the
+/// breaker, while a failure opens it again right away, without waiting for another
+/// `failure_threshold` failures.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Returns how long the breaker stays open if requests are currently rejected.
+    pub(crate) fn check(&self) -> Result<(), Duration> {
+        let state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(open_until) => match open_until.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => Err(remaining),
+                _ => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = 0;
+        if state.open_until.take().is_some() {
+            tracing::info!("remote storage requests succeed again, closing the circuit breaker");
+            crate::metrics::BUCKET_METRICS.circuit_breakers_open.dec();
+        }
+    }
+
+    pub(crate) fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if state.consecutive_failures < self.failure_threshold {
+            return;
+        }
+
+        let now = Instant::now();
+        // Requests which were already in flight when the breaker opened keep failing, which
+        // must not extend the cooldown.
+        if state.open_until.is_some_and(|open_until| open_until > now) {
+            return;
+        }
+        if state.open_until.is_none() {
+            crate::metrics::BUCKET_METRICS.circuit_breakers_open.inc();
+        }
+        crate::metrics::BUCKET_METRICS
+            .circuit_breaker_trips_total
+            .inc();
+        tracing::warn!(
+            consecutive_failures = state.consecutive_failures,
+            "remote storage keeps failing, rejecting requests for {:?}",
+            self.cooldown
+        );
+        state.open_until = Some(now + self.cooldown);
+    }
+}
+
+impl Drop for CircuitBreaker {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        if state.open_until.is_some() {
+            crate::metrics::BUCKET_METRICS.circuit_breakers_open.dec();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_threshold_and_closes_on_success() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(3600));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        // A success in between starts the count over
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+
+        breaker.record_failure();
+        let remaining = breaker.check().unwrap_err();
+        assert!(remaining <= Duration::from_secs(3600));
+
+        breaker.record_success();
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn reopens_on_first_failure_after_cooldown() {
+        let mut breaker = CircuitBreaker::new(2, Duration::ZERO);
+        breaker.record_failure();
+        breaker.record_failure();
+        // With a zero cooldown, the breaker is half open right away
+        assert!(breaker.check().is_ok());
+
+        breaker.cooldown = Duration::from_secs(3600);
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+    }
+}
@@ -1,18 +1,37 @@
 /// Reasons for downloads or listings to fail.
+///
+/// The variants which are only about what happened, like [`DownloadError::NotFound`], carry no
+/// error of their own, but get one with the context of [`DownloadError::add_context`]. Match them
+/// with `NotFound(_)`, and create them with `NotFound(None)`.
 #[derive(Debug)]
 pub enum DownloadError {
     /// Validation or other error happened due to user input.
     BadInput(anyhow::Error),
     /// The file was not found in the remote storage.
-    NotFound,
+    NotFound(Option<anyhow::Error>),
+    /// The credentials lack permission for the request, e.g. S3 `AccessDenied` or an Azure 403.
+    ///
+    /// Without `s3:ListBucket`, S3 answers requests for missing objects with this rather than
+    /// [`DownloadError::NotFound`], so a misconfigured policy can also show up here.
+    Forbidden(anyhow::Error),
     /// A cancellation token aborted the download, typically during
     /// tenant detach or process shutdown.
-    Cancelled,
+    Cancelled(Option<anyhow::Error>),
     /// A timeout happened while executing the request. Possible reasons:
     /// - stuck tcp connection
     ///
     /// Concurrency control is not timed within timeout.
-    Timeout,
+    Timeout(Option<anyhow::Error>),
+    /// The byte range of a download is empty or inverted, or starts past the end of the object.
+    ///
+    /// Empty and inverted ranges are rejected before any request is made, ranges starting past
+    /// the end are what the storage answers with HTTP 416. A range ending past the end of the
+    /// object is not an error, it is clamped to the object.
+    InvalidRange(anyhow::Error),
+    /// The remote storage asked us to slow down, e.g. S3 `SlowDown` or Azure `ServerBusy`.
+    ///
+    /// Retrying is fine, but callers should back off for longer than for other errors.
+    Throttled(anyhow::Error),
     /// The file was found in the remote storage, but the download failed.
     Other(anyhow::Error),
 }
@@ -23,9 +42,20 @@ impl std::fmt::Display for DownloadError {
             DownloadError::BadInput(e) => {
                 write!(f, "Failed to download a remote file due to user input: {e}")
             }
-            DownloadError::NotFound => write!(f, "No file found for the remote object id given"),
-            DownloadError::Cancelled => write!(f, "Cancelled, shutting down"),
-            DownloadError::Timeout => write!(f, "timeout"),
+            DownloadError::NotFound(None) => {
+                write!(f, "No file found for the remote object id given")
+            }
+            DownloadError::NotFound(Some(e)) => write!(f, "No file found: {e:#}"),
+            DownloadError::Forbidden(e) => write!(
+                f,
+                "Access denied, the credentials lack read permissions on this object or prefix: {e:?}"
+            ),
+            DownloadError::Cancelled(None) => write!(f, "Cancelled, shutting down"),
+            DownloadError::Cancelled(Some(e)) => write!(f, "Cancelled, shutting down: {e:#}"),
+            DownloadError::Timeout(None) => write!(f, "timeout"),
+            DownloadError::Timeout(Some(e)) => write!(f, "timeout: {e:#}"),
+            DownloadError::InvalidRange(e) => write!(f, "Invalid byte range: {e:?}"),
+            DownloadError::Throttled(e) => write!(f, "Throttled by remote storage: {e:?}"),
             DownloadError::Other(e) => write!(f, "Failed to download a remote file: {e:?}"),
         }
     }
@@ -34,12 +64,42 @@ impl std::fmt::Display for DownloadError {
 impl std::error::Error for DownloadError {}
 
 impl DownloadError {
+    /// Adds `context` to the error, so that messages say which object or range failed. The
+    /// variant stays the same, so callers can still match on it.
+    pub fn add_context<C>(self, context: C) -> Self
+    where
+        C: std::fmt::Display + Send + Sync + 'static,
+    {
+        use DownloadError::*;
+
+        fn add<C>(e: Option<anyhow::Error>, context: C) -> Option<anyhow::Error>
+        where
+            C: std::fmt::Display + Send + Sync + 'static,
+        {
+            Some(match e {
+                Some(e) => e.context(context),
+                None => anyhow::anyhow!("{context}"),
+            })
+        }
+
+        match self {
+            BadInput(e) => BadInput(e.context(context)),
+            Forbidden(e) => Forbidden(e.context(context)),
+            InvalidRange(e) => InvalidRange(e.context(context)),
+            Throttled(e) => Throttled(e.context(context)),
+            Other(e) => Other(e.context(context)),
+            NotFound(e) => NotFound(add(e, context)),
+            Cancelled(e) => Cancelled(add(e, context)),
+            Timeout(e) => Timeout(add(e, context)),
+        }
+    }
+
     /// Returns true if the error should not be retried with backoff
     pub fn is_permanent(&self) -> bool {
         use DownloadError::*;
         match self {
-            BadInput(_) | NotFound | Cancelled => true,
-            Timeout | Other(_) => false,
+            BadInput(_) | NotFound(_) | Forbidden(_) | InvalidRange(_) | Cancelled(_) => true,
+            Timeout(_) | Throttled(_) | Other(_) => false,
         }
     }
 }
@@ -64,6 +124,28 @@ impl From<std::io::Error> for DownloadError {
     }
 }
 
+/// This type is used as the context for throttling errors with `anyhow::Error` returning
+/// RemoteStorage methods, the write-path counterpart of [`DownloadError::Throttled`].
+///
+/// Use [`Throttled::caused_by_throttling`] to query for it.
+#[derive(Debug)]
+pub struct Throttled;
+
+impl std::fmt::Display for Throttled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "throttled by remote storage")
+    }
+}
+
+impl std::error::Error for Throttled {}
+
+impl Throttled {
+    /// Returns true if the error was marked as [`Throttled`] by the storage backend.
+    pub fn caused_by_throttling(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
 #[derive(Debug)]
 pub enum TimeTravelError {
     /// Validation or other error happened due to user input.
@@ -72,6 +154,16 @@ pub enum TimeTravelError {
     Unimplemented,
     /// The number of versions/deletion markers is above our limit.
     TooManyVersions,
+    /// The storage listed a version of `key` without a version id, so there are no versions to
+    /// recover from: versioning is disabled or suspended on the bucket, or the object was written
+    /// before it was enabled.
+    VersioningDisabled { key: String },
+    /// Restoring `key` failed, while copying `version_id` over it, or while deleting it if `None`.
+    Restore {
+        key: String,
+        version_id: Option<String>,
+        source: anyhow::Error,
+    },
     /// A cancellation token aborted the process, typically during
     /// request closure or process shutdown.
     Cancelled,
@@ -79,6 +171,21 @@ pub enum TimeTravelError {
     Other(anyhow::Error),
 }
 
+impl TimeTravelError {
+    /// Turns [`Self::Other`] into [`Self::Restore`] of `key`, so that the message says which
+    /// object and version tripped the recovery. Other variants are returned unchanged.
+    pub(crate) fn restoring(self, key: &str, version_id: Option<&str>) -> Self {
+        match self {
+            TimeTravelError::Other(source) => TimeTravelError::Restore {
+                key: key.to_owned(),
+                version_id: version_id.map(str::to_owned),
+                source,
+            },
+            e => e,
+        }
+    }
+}
+
 impl std::fmt::Display for TimeTravelError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -96,6 +203,23 @@ impl std::fmt::Display for TimeTravelError {
             TimeTravelError::TooManyVersions => {
                 write!(f, "Number of versions/delete markers above limit")
             }
+            TimeTravelError::VersioningDisabled { key } => write!(
+                f,
+                "Key {key} has no version id, bucket versioning is disabled or was enabled after it was written"
+            ),
+            TimeTravelError::Restore {
+                key,
+                version_id: Some(version_id),
+                source,
+            } => write!(
+                f,
+                "Failed to restore key {key} to version {version_id}: {source:?}"
+            ),
+            TimeTravelError::Restore {
+                key,
+                version_id: None,
+                source,
+            } => write!(f, "Failed to restore key {key} by deleting it: {source:?}"),
             TimeTravelError::Other(e) => write!(f, "Failed to time travel recover a prefix: {e:?}"),
         }
     }
@@ -103,6 +227,28 @@ impl std::fmt::Display for TimeTravelError {
 
 impl std::error::Error for TimeTravelError {}
 
+/// This type is used as the context for errors of conditional requests whose condition didn't
+/// hold, e.g. [`crate::RemoteStorage::delete_if_match`] of an object which was overwritten.
+///
+/// Use [`PreconditionFailed::caused_by_precondition_failure`] to query for it.
+#[derive(Debug)]
+pub struct PreconditionFailed;
+
+impl std::fmt::Display for PreconditionFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "precondition failed, the object has changed")
+    }
+}
+
+impl std::error::Error for PreconditionFailed {}
+
+impl PreconditionFailed {
+    /// Returns true if the error was marked as [`PreconditionFailed`] by the storage backend.
+    pub fn caused_by_precondition_failure(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
 /// Plain cancelled error.
 ///
 /// By design this type does not not implement `std::error::Error` so it cannot be put as the root
@@ -134,7 +280,57 @@ impl From<Cancelled> for TimeoutOrCancel {
 
 impl From<Cancelled> for DownloadError {
     fn from(_: Cancelled) -> Self {
-        DownloadError::Cancelled
+        DownloadError::Cancelled(None)
+    }
+}
+
+/// Why the S3 backend did not hand out a permit to send a request.
+#[derive(Debug)]
+pub(crate) enum PermitError {
+    Cancelled,
+    /// The circuit breaker is open after consecutive failed requests, and rejects requests for
+    /// the given time. Surfaced as throttling, so that callers back off.
+    Unavailable(std::time::Duration),
+}
+
+impl PermitError {
+    fn unavailable(remaining: std::time::Duration) -> anyhow::Error {
+        anyhow::anyhow!(
+            "remote storage is unavailable after consecutive failed requests, rejecting requests for another {remaining:?}"
+        )
+    }
+}
+
+impl From<PermitError> for anyhow::Error {
+    fn from(value: PermitError) -> Self {
+        match value {
+            PermitError::Cancelled => Cancelled.into(),
+            PermitError::Unavailable(remaining) => {
+                PermitError::unavailable(remaining).context(Throttled)
+            }
+        }
+    }
+}
+
+impl From<PermitError> for TimeTravelError {
+    fn from(value: PermitError) -> Self {
+        match value {
+            PermitError::Cancelled => TimeTravelError::Cancelled,
+            PermitError::Unavailable(remaining) => {
+                TimeTravelError::Other(PermitError::unavailable(remaining))
+            }
+        }
+    }
+}
+
+impl From<PermitError> for DownloadError {
+    fn from(value: PermitError) -> Self {
+        match value {
+            PermitError::Cancelled => DownloadError::Cancelled(None),
+            PermitError::Unavailable(remaining) => {
+                DownloadError::Throttled(PermitError::unavailable(remaining))
+            }
+        }
     }
 }
 
@@ -142,7 +338,8 @@ impl From<Cancelled> for DownloadError {
 /// RemoteStorage methods.
 ///
 /// For use with `utils::backoff::retry` and `anyhow::Error` returning operations there is
-/// `TimeoutOrCancel::caused_by_cancel` method to query "proper form" errors.
+/// `TimeoutOrCancel::caused_by_cancel` method to query "proper form" errors, and
+/// `TimeoutOrCancel::from_error` to tell timeouts and cancellations apart.
 #[derive(Debug)]
 pub enum TimeoutOrCancel {
     Timeout,
@@ -162,18 +359,29 @@ impl std::fmt::Display for TimeoutOrCancel {
 impl std::error::Error for TimeoutOrCancel {}
 
 impl TimeoutOrCancel {
+    /// Returns the root cause of `error` if it is a timeout or a cancellation, so that callers
+    /// can tell the two apart, e.g. to log an expected shutdown cancellation at a lower level
+    /// than a timeout.
+    pub fn from_error(error: &anyhow::Error) -> Option<&Self> {
+        error.root_cause().downcast_ref::<Self>()
+    }
+
     /// Returns true if the error was caused by [`TimeoutOrCancel::Cancel`].
     pub fn caused_by_cancel(error: &anyhow::Error) -> bool {
-        error
-            .root_cause()
-            .downcast_ref::<Self>()
-            .is_some_and(Self::is_cancel)
+        Self::from_error(error).is_some_and(Self::is_cancel)
+    }
+
+    /// Returns true if the error was caused by [`TimeoutOrCancel::Timeout`].
+    pub fn caused_by_timeout(error: &anyhow::Error) -> bool {
+        Self::from_error(error).is_some_and(Self::is_timeout)
     }
 
+    /// The operation was cancelled through its `CancellationToken`, usually during shutdown.
     pub fn is_cancel(&self) -> bool {
         matches!(self, TimeoutOrCancel::Cancel)
     }
 
+    /// The operation ran into the remote storage's configured timeout.
     pub fn is_timeout(&self) -> bool {
         matches!(self, TimeoutOrCancel::Timeout)
     }
@@ -193,8 +401,8 @@ impl From<TimeoutOrCancel> for DownloadError {
         use TimeoutOrCancel::*;
 
         match value {
-            Timeout => DownloadError::Timeout,
-            Cancel => DownloadError::Cancelled,
+            Timeout => DownloadError::Timeout(None),
+            Cancel => DownloadError::Cancelled(None),
         }
     }
 }
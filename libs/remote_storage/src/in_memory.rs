@@ -0,0 +1,402 @@
+//! A storage keeping its objects in memory, for tests which need a [`RemoteStorage`] but not the
+//! objects to outlive the process.
+//!
+//! It follows the S3 semantics which [`crate::LocalFs`] mirrors: keys are listed in the byte
+//! order of their paths, prefixes are plain string prefixes, and deleting a missing object
+//! succeeds.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, ensure, Context};
+use bytes::{Bytes, BytesMut};
+use futures::stream::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    traffic::{CountingDownload, TrafficCounters},
+    Compression, CopyMetadata, Download, DownloadError, Etag, ListOptions, Listing, ListingMode,
+    ListingObject, PreconditionFailed, RemotePath, RemoteStorage, StorageDescription,
+    StorageMetadata, TimeTravelError, TimeTravelSummary, TimeoutOrCancel, TrafficStats,
+    UploadOptions, REMOTE_STORAGE_PREFIX_SEPARATOR,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemory {
+    // Keyed by the path string, so that the map iterates in the listing order. Shared by the
+    // clones, like the objects of a bucket.
+    objects: Arc<Mutex<BTreeMap<String, StoredObject>>>,
+    traffic: Arc<TrafficCounters>,
+}
+
+#[derive(Debug, Clone)]
+struct StoredObject {
+    data: Bytes,
+    last_modified: SystemTime,
+    etag: Etag,
+    metadata: Option<StorageMetadata>,
+    content_encoding: Option<Compression>,
+}
+
+impl StoredObject {
+    fn listing_object(&self, key: &str, with_metadata: bool) -> ListingObject {
+        ListingObject {
+            key: RemotePath::from_string(key).expect("stored keys are valid paths"),
+            last_modified: self.last_modified,
+            size: self.data.len() as u64,
+            metadata: if with_metadata {
+                self.metadata.clone()
+            } else {
+                None
+            },
+            version_id: None,
+        }
+    }
+}
+
+impl InMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn objects(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, StoredObject>> {
+        self.objects.lock().unwrap()
+    }
+
+    fn get(&self, key: &RemotePath) -> Result<StoredObject, DownloadError> {
+        self.objects()
+            .get(key.get_path().as_str())
+            .cloned()
+            .ok_or(DownloadError::NotFound(None))
+    }
+
+    async fn upload_with_cancel(
+        &self,
+        data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync,
+        data_size_bytes: Option<usize>,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let UploadOptions {
+            metadata,
+            content_encoding,
+            content_md5,
+            // There is no access control in memory
+            acl: _,
+            object_lock,
+        } = options;
+        if let Some(object_lock) = object_lock {
+            bail!("cannot upload {to} with {object_lock:?}: the in-memory storage can't enforce retention");
+        }
+        let data = crate::support::Md5Verifying::new(data, data_size_bytes, content_md5);
+
+        self.traffic.record_request();
+        // Nothing is stored before the whole body arrived, so a cancelled upload leaves nothing
+        let data = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+            data = read_upload(data) => data?,
+        };
+        if let Some(data_size_bytes) = data_size_bytes {
+            ensure!(
+                data.len() == data_size_bytes,
+                "Provided stream has {} bytes, expected {data_size_bytes}",
+                data.len()
+            );
+        }
+
+        self.traffic.record_upload(data.len());
+        let object = StoredObject {
+            etag: format!("{:x}", md5::compute(&data)).into(),
+            data,
+            last_modified: SystemTime::now(),
+            metadata: metadata.map(StorageMetadata::normalized),
+            content_encoding,
+        };
+        self.objects()
+            .insert(to.get_path().as_str().to_owned(), object);
+        Ok(())
+    }
+
+    fn download_object(
+        &self,
+        object: StoredObject,
+        range: std::ops::Range<usize>,
+        cancel: &CancellationToken,
+    ) -> Download {
+        let data = object.data.slice(range);
+        let content_length = data.len() as u64;
+        let source = futures::stream::once(futures::future::ready(Ok(data)));
+
+        let cancel = cancel.clone();
+        let cancelled = async move {
+            cancel.cancelled().await;
+            TimeoutOrCancel::Cancel
+        };
+        let source = crate::support::DownloadStream::new(cancelled, source);
+        let source = CountingDownload::new(self.traffic.clone(), source);
+
+        Download {
+            download_stream: Box::pin(source),
+            last_modified: object.last_modified,
+            etag: object.etag,
+            content_length,
+            object_size: object.data.len() as u64,
+            metadata: object.metadata,
+            content_encoding: object.content_encoding,
+        }
+    }
+}
+
+async fn read_upload(data: impl Stream<Item = std::io::Result<Bytes>>) -> anyhow::Result<Bytes> {
+    let mut data = std::pin::pin!(data);
+    let mut buffer = BytesMut::new();
+    while let Some(chunk) = data.next().await {
+        buffer.extend_from_slice(&chunk.context("read the upload stream")?);
+    }
+    Ok(buffer.freeze())
+}
+
+impl RemoteStorage for InMemory {
+    async fn list(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        options: ListOptions,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        self.traffic.record_request();
+        if cancel.is_cancelled() {
+            return Err(DownloadError::Cancelled(None));
+        }
+
+        let list_prefix = prefix.map(|p| p.get_path().as_str()).unwrap_or_default();
+        let is_modified_since = |o: &StoredObject| match options.modified_since {
+            Some(since) => o.last_modified >= since,
+            None => true,
+        };
+
+        let mut result = Listing::default();
+        let mut prefixes = BTreeSet::new();
+        for (key, object) in self.objects().range(list_prefix.to_owned()..) {
+            let Some(relative_key) = key.strip_prefix(list_prefix) else {
+                break;
+            };
+            if let ListingMode::WithDelimiter = mode {
+                // Like on S3, prefixes are full paths up to the first delimiter after the listed
+                // prefix
+                if let Some(delimiter) = relative_key.find(REMOTE_STORAGE_PREFIX_SEPARATOR) {
+                    prefixes.insert(key[..list_prefix.len() + delimiter].to_owned());
+                    continue;
+                }
+            }
+            if is_modified_since(object) {
+                result
+                    .objects
+                    .push(object.listing_object(key, options.with_metadata));
+            }
+        }
+        result.prefixes = prefixes
+            .into_iter()
+            .map(|s| RemotePath::from_string(&s).unwrap())
+            .collect();
+
+        if let Some(max_keys) = max_keys {
+            result.objects.truncate(max_keys.get() as usize);
+        }
+        Ok(result)
+    }
+
+    async fn upload(
+        &self,
+        data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.upload_with_cancel(data, Some(data_size_bytes), to, options, cancel)
+            .await
+    }
+
+    async fn upload_unsized(
+        &self,
+        data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.upload_with_cancel(data, None, to, options, cancel)
+            .await
+    }
+
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.traffic.record_request();
+        let object = self.get(from)?;
+        let len = object.data.len();
+        Ok(self.download_object(object, 0..len, cancel))
+    }
+
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.traffic.record_request();
+        crate::support::check_byte_range(start_inclusive, end_exclusive)?;
+
+        let object = self.get(from)?;
+        let len = object.data.len() as u64;
+        // Like S3, which answers 416 for these, while an open range from 0 is the whole object
+        if start_inclusive > 0 && start_inclusive >= len {
+            return Err(DownloadError::InvalidRange(anyhow::anyhow!(
+                "range starts at {start_inclusive}, past the end of the {len} bytes object"
+            )));
+        }
+        // An end past the end of the object is clamped to it, like S3 does
+        let end_exclusive = end_exclusive.map_or(len, |end| end.min(len));
+        Ok(self.download_object(
+            object,
+            start_inclusive as usize..end_exclusive as usize,
+            cancel,
+        ))
+    }
+
+    async fn head_object(
+        &self,
+        key: &RemotePath,
+        _cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError> {
+        self.traffic.record_request();
+        let object = self.get(key)?;
+        Ok(object.listing_object(key.get_path().as_str(), true))
+    }
+
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.delete_if_exists(path, cancel).await.map(|_| ())
+    }
+
+    async fn delete_if_exists(
+        &self,
+        path: &RemotePath,
+        _cancel: &CancellationToken,
+    ) -> anyhow::Result<bool> {
+        self.traffic.record_request();
+        // Like on S3, deleting a missing object succeeds
+        Ok(self.objects().remove(path.get_path().as_str()).is_some())
+    }
+
+    async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        _cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.traffic.record_request();
+        let mut objects = self.objects();
+        // Already deleted, which is fine, like for `delete`
+        let Some(object) = objects.get(path.get_path().as_str()) else {
+            return Ok(());
+        };
+        if !object.etag.eq_ignoring_weak(etag) {
+            return Err(
+                anyhow::anyhow!("{path} has ETag {}, expected {etag}", object.etag)
+                    .context(PreconditionFailed),
+            );
+        }
+        objects.remove(path.get_path().as_str());
+        Ok(())
+    }
+
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        for path in paths {
+            self.delete(path, cancel).await?
+        }
+        Ok(())
+    }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        metadata: CopyMetadata,
+        _cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.traffic.record_request();
+        let mut objects = self.objects();
+        let mut object = objects
+            .get(from.get_path().as_str())
+            .cloned()
+            .with_context(|| format!("Failed to copy {from} to {to}: {from} does not exist"))?;
+        if let CopyMetadata::Replace(metadata) = metadata {
+            object.metadata = Some(metadata.normalized());
+        }
+        object.last_modified = SystemTime::now();
+        objects.insert(to.get_path().as_str().to_owned(), object);
+        Ok(())
+    }
+
+    async fn touch(&self, path: &RemotePath, _cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.traffic.record_request();
+        let mut objects = self.objects();
+        let object = objects
+            .get_mut(path.get_path().as_str())
+            .with_context(|| format!("Failed to touch {path}: it does not exist"))?;
+        object.last_modified = SystemTime::now();
+        Ok(())
+    }
+
+    async fn time_travel_recover(
+        &self,
+        _prefix: Option<&RemotePath>,
+        _timestamp: SystemTime,
+        _done_if_after: SystemTime,
+        _cancel: &CancellationToken,
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
+        Err(TimeTravelError::Unimplemented)
+    }
+
+    async fn abort_incomplete_uploads(
+        &self,
+        _prefix: Option<&RemotePath>,
+        _older_than: Duration,
+        _cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        // Uploads are buffered until complete, there are no parts to clean up
+        Ok(0)
+    }
+
+    fn traffic_stats(&self) -> TrafficStats {
+        self.traffic.stats()
+    }
+
+    fn describe(&self) -> StorageDescription {
+        StorageDescription {
+            backend: "memory",
+            bucket_or_container: String::new(),
+            prefix: None,
+            endpoint: None,
+            region: None,
+        }
+    }
+
+    fn redacted_config(&self) -> serde_json::Value {
+        serde_json::json!({ "memory": {} })
+    }
+}
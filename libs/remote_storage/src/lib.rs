@@ -5,20 +5,28 @@
 //!   * [`local_fs`] allows to use local file system as an external storage
 //!   * [`s3_bucket`] uses AWS S3 bucket as an external storage
 //!   * [`azure_blob`] allows to use Azure Blob storage as an external storage
+//!   * [`in_memory`] keeps the objects in memory, for tests
 //!
 #![deny(unsafe_code)]
 #![deny(clippy::undocumented_unsafe_blocks)]
 
 mod azure_blob;
+mod caching;
+mod circuit_breaker;
 mod error;
+mod in_memory;
 mod local_fs;
 mod metrics;
+mod mirror;
+mod op_label;
 mod s3_bucket;
+mod scoped;
 mod simulate_failures;
 mod support;
+mod traffic;
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     fmt::Debug,
     num::{NonZeroU32, NonZeroUsize},
     pin::Pin,
@@ -40,15 +48,22 @@ use toml_edit::Item;
 use tracing::info;
 
 pub use self::{
-    azure_blob::AzureBlobStorage, local_fs::LocalFs, s3_bucket::S3Bucket,
+    azure_blob::AzureBlobStorage,
+    caching::CachingStorage,
+    in_memory::InMemory,
+    local_fs::LocalFs,
+    mirror::{MirrorMode, MirrorStorage},
+    s3_bucket::S3Bucket,
+    scoped::ScopedRemoteStorage,
     simulate_failures::UnreliableWrapper,
 };
 use s3_bucket::RequestKind;
 
-/// Azure SDK's ETag type is a simple String wrapper: we use this internally instead of repeating it here.
-pub use azure_core::Etag;
+pub use support::ProgressReporting;
 
-pub use error::{DownloadError, TimeTravelError, TimeoutOrCancel};
+pub use error::{DownloadError, PreconditionFailed, Throttled, TimeTravelError, TimeoutOrCancel};
+pub use op_label::{with_op_label, UNLABELED_OP};
+pub use traffic::TrafficStats;
 
 /// Currently, sync happens with AWS S3, that has two limits on requests per second:
 /// ~200 RPS for IAM services
@@ -61,15 +76,58 @@ pub const DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT: usize = 100;
 /// Here, a limit of max 20k concurrent connections was noted.
 /// <https://learn.microsoft.com/en-us/answers/questions/1301863/is-there-any-limitation-to-concurrent-connections>
 pub const DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT: usize = 100;
+/// The largest block Azure accepts, so by default every upload is a single `Put Blob` request.
+/// <https://learn.microsoft.com/en-us/rest/api/storageservices/understanding-block-blobs--append-blobs--and-page-blobs#about-block-blobs>
+pub const DEFAULT_AZURE_MAX_BLOCK_SIZE: usize = 4000 * 1024 * 1024;
+/// Blocks of one upload are staged one after another by default.
+pub const DEFAULT_AZURE_MAX_CONCURRENCY_PER_UPLOAD: usize = 1;
+/// How long an idle connection to S3 is kept in the connection pool before being closed.
+///
+/// The number of idle connections kept is derived from the concurrency limit instead, see
+/// [`S3Config::max_idle_connections`].
+pub const DEFAULT_REMOTE_STORAGE_S3_CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// How long the AWS SDK waits for a connection to S3 to be established, same as the SDK's own
+/// default. See [`S3Config::connect_timeout`].
+pub const DEFAULT_REMOTE_STORAGE_S3_CONNECT_TIMEOUT: Duration = Duration::from_millis(3100);
+/// S3 requests taking longer than this are logged, see [`S3Config::slow_request_threshold`].
+pub const DEFAULT_REMOTE_STORAGE_S3_SLOW_REQUEST_THRESHOLD: Duration = Duration::from_secs(10);
+/// How long [`GenericRemoteStorage::healthcheck`] waits for the storage to answer.
+pub const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(10);
 /// No limits on the client side, which currenltly means 1000 for AWS S3.
 /// <https://docs.aws.amazon.com/AmazonS3/latest/API/API_ListObjectsV2.html#API_ListObjectsV2_RequestSyntax>
 pub const DEFAULT_MAX_KEYS_PER_LIST_RESPONSE: Option<i32> = None;
 
+/// Maximum length of the range requests [`GenericRemoteStorage::download_buffered`] issues.
+pub const DOWNLOAD_BUFFERED_RANGE_SIZE: u64 = 8 * 1024 * 1024;
+
 /// As defined in S3 docs
 pub const MAX_KEYS_PER_DELETE: usize = 1000;
 
 const REMOTE_STORAGE_PREFIX_SEPARATOR: char = '/';
 
+/// The application name sent along in the `User-Agent` header of storage requests, so that the
+/// storage provider can tell our traffic apart: the `app_name` the binary set, or else the crate
+/// name, followed by the configured `user_agent_suffix`, if any.
+pub(crate) fn user_agent(app_name: Option<&str>, suffix: Option<&str>) -> String {
+    let mut user_agent = match app_name {
+        Some(app_name) => app_name
+            .chars()
+            .map(|c| if is_user_agent_char(c) { c } else { '-' })
+            .collect(),
+        None => env!("CARGO_PKG_NAME").to_owned(),
+    };
+    if let Some(suffix) = suffix {
+        user_agent.push('-');
+        user_agent.push_str(suffix);
+    }
+    user_agent
+}
+
+/// The HTTP token characters, which are all the AWS SDK accepts in an application name.
+fn is_user_agent_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
 /// Path on the remote storage, relative to some inner prefix.
 /// The prefix is an implementation detail, that allows representing local paths
 /// as the remote ones, stripping the local storage prefix away.
@@ -147,17 +205,192 @@ impl RemotePath {
 /// We don't need callers to be able to pass arbitrary delimiters: just control
 /// whether listings will use a '/' separator or not.
 ///
-/// The WithDelimiter mode will populate `prefixes` and `keys` in the result.  The
-/// NoDelimiter mode will only populate `keys`.
+/// The WithDelimiter mode will populate `prefixes` and `objects` in the result.  The
+/// NoDelimiter mode will only populate `objects`, but [`Listing::compute_prefixes_at_depth`]
+/// can derive `prefixes` from them afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ListingMode {
     WithDelimiter,
     NoDelimiter,
 }
 
-#[derive(Default)]
+/// The result of [`RemoteStorage::list`].
+///
+/// `prefixes` and `objects` are each sorted lexicographically by the bytes of their UTF-8 paths,
+/// the order in which S3 lists keys, and every backend returns them in that order. Note that
+/// this is not the order of [`RemotePath`]'s `Ord`, which compares path components, e.g. `a-b`
+/// sorts before `a/b` here. Versions of the same key, see [`ListOptions::include_version_ids`], are listed next
+/// to each other, in the order the storage returned them.
+#[derive(Default, Clone)]
 pub struct Listing {
     pub prefixes: Vec<RemotePath>,
-    pub keys: Vec<RemotePath>,
+    pub objects: Vec<ListingObject>,
+    /// Listed keys and prefixes which were left out of `objects` and `prefixes`, because they can't
+    /// be represented as a [`RemotePath`], with the reason. One malformed key doesn't fail the
+    /// listing of a whole prefix, so callers scanning everything can report them and go on.
+    pub skipped: Vec<(String, String)>,
+}
+
+/// What is stored below a prefix, see [`RemoteStorage::prefix_size`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefixSize {
+    /// Sum of the sizes of all objects, in bytes.
+    pub total_bytes: u64,
+    pub object_count: u64,
+}
+
+/// Which operations the credentials of a storage are allowed to perform, see
+/// [`GenericRemoteStorage::verify_permissions`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PermissionReport {
+    pub can_get: bool,
+    pub can_put: bool,
+    pub can_list: bool,
+    pub can_delete: bool,
+    pub can_copy: bool,
+    /// The error of each failed probe, by operation.
+    pub errors: Vec<(&'static str, String)>,
+}
+
+impl PermissionReport {
+    pub fn all_granted(&self) -> bool {
+        self.can_get && self.can_put && self.can_list && self.can_delete && self.can_copy
+    }
+
+    fn record<T>(&mut self, operation: &'static str, result: anyhow::Result<T>) -> bool {
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                self.errors.push((operation, format!("{e:#}")));
+                false
+            }
+        }
+    }
+}
+
+/// Where a storage keeps its objects, see [`RemoteStorage::describe`]. Meant for status and debug
+/// endpoints, so it holds no credentials.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StorageDescription {
+    /// `s3`, `azure`, `localfs` or `memory`.
+    pub backend: &'static str,
+    /// The bucket or container name, or the storage root directory for [`LocalFs`].
+    pub bucket_or_container: String,
+    /// The prefix all keys of this storage are below, if any.
+    pub prefix: Option<String>,
+    /// A custom endpoint for S3, or the container URL for Azure.
+    pub endpoint: Option<String>,
+    pub region: Option<String>,
+}
+
+impl Listing {
+    /// Whether `prefixes` and `objects` are in the order documented on [`Listing`].
+    pub fn is_sorted(&self) -> bool {
+        let prefixes = self.prefixes.iter().map(listing_order_key);
+        let keys = self
+            .objects
+            .iter()
+            .map(|object| listing_order_key(&object.key));
+        is_ascending(prefixes) && is_ascending(keys)
+    }
+
+    /// Brings `prefixes` and `objects` into the order documented on [`Listing`]. The sort is stable,
+    /// so that versions of the same key stay in the order they were listed in.
+    pub(crate) fn sort(&mut self) {
+        self.prefixes
+            .sort_by(|a, b| listing_order_key(a).cmp(listing_order_key(b)));
+        self.objects
+            .sort_by(|a, b| listing_order_key(&a.key).cmp(listing_order_key(&b.key)));
+    }
+
+    /// Fill `prefixes` with the distinct "directories" containing `objects`, truncated to `depth`
+    /// `/`-separated segments, e.g. `a/b` for the key `a/b/c/d` at depth 2.  Keys with at most
+    /// `depth` segments do not have a prefix at that depth.
+    ///
+    /// This gives directory-style aggregation of a [`ListingMode::NoDelimiter`] listing, without the
+    /// extra requests that listing each level [`ListingMode::WithDelimiter`] would need.  With
+    /// `depth: None`, `prefixes` is left as it is.
+    pub fn compute_prefixes_at_depth(&mut self, depth: Option<usize>) {
+        let Some(depth) = depth else {
+            return;
+        };
+        let prefixes: BTreeSet<String> = self
+            .objects
+            .iter()
+            .filter(|object| object.key.get_path().components().count() > depth)
+            .map(|object| {
+                let prefix: Utf8PathBuf = object.key.get_path().components().take(depth).collect();
+                prefix.into_string()
+            })
+            .collect();
+        self.prefixes = prefixes.into_iter().map(|p| RemotePath(p.into())).collect();
+    }
+}
+
+/// Where a paged listing stopped, see [`RemoteStorage::list_streaming`]. Passing it back resumes
+/// the listing with the next page, also from another process, as it serializes to a plain string.
+///
+/// The token is opaque and only valid for the storage and listing it came from: the S3
+/// continuation token, the Azure marker, or for other storages the last key or prefix listed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ContinuationToken(String);
+
+impl ContinuationToken {
+    pub(crate) fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+/// The key [`Listing`]s are sorted by.
+fn listing_order_key(path: &RemotePath) -> &str {
+    path.get_path().as_str()
+}
+
+fn is_ascending<'a>(mut keys: impl Iterator<Item = &'a str>) -> bool {
+    let Some(mut previous) = keys.next() else {
+        return true;
+    };
+    keys.all(|key| {
+        let ascending = previous <= key;
+        previous = key;
+        ascending
+    })
+}
+
+/// An object returned in a [`Listing`], along with the information that the
+/// listing responses of the storage backends give us for free, and optionally its metadata.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListingObject {
+    pub key: RemotePath,
+    pub last_modified: SystemTime,
+    /// Size of the object in bytes
+    pub size: u64,
+    /// The [`StorageMetadata`] of the object, only filled in when listing
+    /// [`ListOptions::with_metadata`].
+    pub metadata: Option<StorageMetadata>,
+    /// The version of the object, only filled in when listing
+    /// [`ListOptions::include_version_ids`] from a storage which keeps versions.
+    pub version_id: Option<String>,
+}
+
+/// The optional parts of a [`RemoteStorage::list`], which are all off by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ListOptions {
+    /// Skips any keys that were last modified before the given time, which lets callers do
+    /// incremental scans. Prefixes are not affected by it, and skipped keys do not count towards
+    /// `max_keys`.
+    pub modified_since: Option<SystemTime>,
+    /// Fills in [`ListingObject::metadata`] for every listed key. S3 list responses don't carry
+    /// metadata, so this costs one `HeadObject` request per key there, issued a bounded number at
+    /// a time after the listing itself. Azure includes the metadata in the listing responses, and
+    /// [`LocalFs`] reads one sidecar file per key.
+    pub with_metadata: bool,
+    /// Lists every version of the objects instead of only the current ones, with
+    /// [`ListingObject::version_id`] filled in, so a key can be listed more than once. S3 lists
+    /// with `ListObjectVersions` then, without the delete markers, and Azure includes the blob
+    /// versions. [`LocalFs`] keeps no versions, and lists as usual.
+    pub include_version_ids: bool,
 }
 
 /// Storage (potentially remote) API to manage its state.
@@ -172,26 +405,103 @@ pub trait RemoteStorage: Send + Sync + 'static {
     /// from the absolute root of the bucket.
     ///
     /// `mode` configures whether to use a delimiter.  Without a delimiter all keys
-    /// within the prefix are listed in the `keys` of the result.  With a delimiter, any "directories" at the top level of
+    /// within the prefix are listed in the `objects` of the result.  With a delimiter, any "directories" at the top level of
     /// the prefix are returned in the `prefixes` of the result, and keys in the top level of the prefix are
-    /// returned in `keys` ().
+    /// returned in `objects` ().
     ///
     /// `max_keys` controls the maximum number of keys that will be returned.  If this is None, this function
     /// will iteratively call listobjects until it runs out of keys.  Note that this is not safe to use on
     /// unlimted size buckets, as the full list of objects is allocated into a monolithic data structure.
     ///
+    /// `options` turns on incremental listing, metadata and versions, see [`ListOptions`].
+    ///
     async fn list(
         &self,
         prefix: Option<&RemotePath>,
         _mode: ListingMode,
         max_keys: Option<NonZeroU32>,
+        options: ListOptions,
         cancel: &CancellationToken,
     ) -> Result<Listing, DownloadError>;
 
+    /// Like a [`ListingMode::WithDelimiter`] [`Self::list`] of `prefix`, but also lists the
+    /// returned prefixes, and theirs in turn, down to `depth` levels below `prefix`. The result
+    /// holds the prefixes and keys of all those levels together, e.g. with `depth` 2 on a tenant
+    /// root both the timeline prefixes and the objects directly inside them, like the index. A
+    /// `depth` of 1 is the same as a single delimited listing.
+    ///
+    /// The levels are listed one after another, a bounded number of prefixes at a time.
+    async fn list_with_depth(
+        &self,
+        prefix: Option<&RemotePath>,
+        depth: NonZeroU32,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        support::list_with_depth(self, prefix, depth, cancel).await
+    }
+
+    /// Lists all "directories" (common prefixes) below `prefix`, at any depth, without listing
+    /// the objects themselves to the caller. `None` lists from the root.
+    ///
+    /// Object stores walk the delimiter hierarchy breadth first, one listing per prefix, while
+    /// [`LocalFs`] walks its directories. Prefixes are yielded without a trailing slash, like in
+    /// [`Listing::prefixes`], and the stream ends after the first error.
+    fn list_prefixes_recursive<'a>(
+        &'a self,
+        prefix: Option<&'a RemotePath>,
+        cancel: &'a CancellationToken,
+    ) -> impl Stream<Item = Result<RemotePath, DownloadError>> + 'a {
+        support::list_prefixes_breadth_first(self, prefix, cancel)
+    }
+
+    /// Lists a single page of [`Self::list`] with the default [`ListOptions`],
+    /// starting after `resume_from`, or from the beginning with `None`. Returns the token to
+    /// list the next page with, `None` after the last page.
+    ///
+    /// A page holds at most `max_keys` keys and prefixes, and no more than a single listing
+    /// request of the storage returns. S3 and Azure resume with their continuation token and
+    /// marker, other storages list in full and skip what was listed up to the token.
+    async fn list_page(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
+        cancel: &CancellationToken,
+    ) -> Result<(Listing, Option<ContinuationToken>), DownloadError> {
+        support::list_page_after(self, prefix, mode, max_keys, resume_from, cancel).await
+    }
+
+    /// Streams the pages of a listing of `prefix`, see [`Self::list_page`], each along with the
+    /// token to resume after it. Persisting the token of the last processed page lets a long scan
+    /// pick up where it stopped after a restart, by passing it as `resume_from`.
+    ///
+    /// The stream ends after the last page, or after the first error.
+    fn list_streaming<'a>(
+        &'a self,
+        prefix: Option<&'a RemotePath>,
+        mode: ListingMode,
+        max_keys_per_page: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
+        cancel: &'a CancellationToken,
+    ) -> impl Stream<Item = Result<(Listing, Option<ContinuationToken>), DownloadError>> + 'a {
+        support::list_streaming(self, prefix, mode, max_keys_per_page, resume_from, cancel)
+    }
+
     /// Streams the local file contents into remote into the remote storage entry.
     ///
     /// If the operation fails because of timeout or cancellation, the root cause of the error will be
     /// set to `TimeoutOrCancel`.
+    ///
+    /// A cancelled or timed out upload leaves no object behind. S3 uploads are a single
+    /// `PutObject` request, so dropping the request discards the partial body, except for those
+    /// above the 5 GiB limit of `PutObject`. These, like all S3 uploads through
+    /// [`Self::upload_unsized`], are multipart uploads, which are aborted when they fail, and in
+    /// the background when their future is dropped, rather than leaving parts to clean up with
+    /// [`Self::abort_incomplete_uploads`]. Azure discards the uncommitted blocks of large uploads
+    /// on its own after a week.
+    ///
+    /// See [`UploadOptions`] for what else can be stored with the object.
     async fn upload(
         &self,
         from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
@@ -199,10 +509,40 @@ pub trait RemoteStorage: Send + Sync + 'static {
         // otherwise it starts to fail with the concurrent connection count increasing.
         data_size_bytes: usize,
         to: &RemotePath,
-        metadata: Option<StorageMetadata>,
+        options: UploadOptions,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()>;
 
+    /// [`Self::upload`] for payloads which are already in memory, like index parts.
+    async fn upload_bytes(
+        &self,
+        data: Bytes,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let data_size_bytes = data.len();
+        let from = futures::stream::once(futures::future::ready(Ok(data)));
+        self.upload(from, data_size_bytes, to, options, cancel)
+            .await
+    }
+
+    /// Like [`Self::upload`], but for a stream whose size isn't known up front, e.g. the output of
+    /// a compressor, so that it doesn't need to be staged in a file first.
+    ///
+    /// S3 uploads the stream in parts with a multipart upload, and Azure as a list of blocks,
+    /// holding one part or block in memory at a time. [`LocalFs`] writes it to a file directly.
+    /// Other storages buffer the whole stream in memory.
+    async fn upload_unsized(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        support::upload_buffered(self, from, to, options, cancel).await
+    }
+
     /// Streams the remote storage entry contents.
     ///
     /// The returned download stream will obey initial timeout and cancellation signal by erroring
@@ -218,6 +558,14 @@ pub trait RemoteStorage: Send + Sync + 'static {
 
     /// Streams a given byte range of the remote storage entry contents.
     ///
+    /// With `end_exclusive` of `None`, the stream covers everything from `start_inclusive` to the
+    /// end of the object, like an open ended `Range: bytes=start-` HTTP header. All backends
+    /// behave the same way here.
+    ///
+    /// An end past the end of the object is clamped to it. Empty or inverted ranges, and ranges
+    /// starting past the end of the object, fail with [`DownloadError::InvalidRange`]; an open
+    /// ended range from 0 is the whole object, even if that is empty.
+    ///
     /// The returned download stream will obey initial timeout and cancellation signal by erroring
     /// on whichever happens first. Only one of the reasons will fail the stream, which is usually
     /// enough for `tokio::io::copy_buf` usage. If needed the error can be filtered out.
@@ -231,12 +579,103 @@ pub trait RemoteStorage: Send + Sync + 'static {
         cancel: &CancellationToken,
     ) -> Result<Download, DownloadError>;
 
+    /// Like [`Self::download`], but fails with [`DownloadError::Timeout`] at `deadline`, if that
+    /// comes before the storage's own timeout. Reading the returned stream fails at the deadline
+    /// as well. `None` behaves like [`Self::download`].
+    ///
+    /// Wrapping a download in `tokio::time::timeout` only bounds the request until the response
+    /// headers arrive, while the body keeps its connection busy for as long as the stream is
+    /// held. The backends instead apply the deadline as their own request timeout, which drops the
+    /// in-flight request, and its connection, once it passes.
+    async fn download_with_deadline(
+        &self,
+        from: &RemotePath,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        support::download_with_deadline(deadline, self.download(from, cancel)).await
+    }
+
+    /// [`Self::download_byte_range`] with a `deadline`, see [`Self::download_with_deadline`].
+    async fn download_byte_range_with_deadline(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        support::download_with_deadline(
+            deadline,
+            self.download_byte_range(from, start_inclusive, end_exclusive, cancel),
+        )
+        .await
+    }
+
+    /// Downloads the object at `from` into the local file `local`, replacing it if it exists.
+    ///
+    /// The object is streamed into a temporary file next to `local`, which is fsynced and then
+    /// renamed over `local`, so that `local` never holds a partial download, also when this
+    /// fails or is cancelled midway. The temporary file is removed on errors.
+    ///
+    /// For objects uploaded with a [`Compression`], the file gets the decoded contents.
+    async fn download_to_file(
+        &self,
+        from: &RemotePath,
+        local: &Utf8Path,
+        cancel: &CancellationToken,
+    ) -> Result<ObjectMetadata, DownloadError> {
+        support::download_to_file(self, from, local, cancel).await
+    }
+
+    /// Returns the size, last modified time and metadata of the object at `key`, without
+    /// downloading it. Fails with [`DownloadError::NotFound`] if there is no such object.
+    async fn head_object(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError>;
+
     /// Delete a single path from remote storage.
     ///
     /// If the operation fails because of timeout or cancellation, the root cause of the error will be
     /// set to `TimeoutOrCancel`. In such situation it is unknown if the deletion went through.
     async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()>;
 
+    /// Like [`Self::delete`], which succeeds whether or not there was an object at `path`, but
+    /// returns whether there was one, for callers which need to know.
+    ///
+    /// S3 doesn't tell whether a deleted key existed, so by default this checks with
+    /// [`Self::head_object`] first. The answer is then racy if the object is concurrently
+    /// created or deleted; [`LocalFs`] knows it exactly.
+    async fn delete_if_exists(
+        &self,
+        path: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<bool> {
+        let existed = match self.head_object(path, cancel).await {
+            Ok(_) => true,
+            Err(DownloadError::NotFound(_)) => false,
+            Err(e) => return Err(e.into()),
+        };
+        self.delete(path, cancel).await?;
+        Ok(existed)
+    }
+
+    /// Like [`Self::delete`], but only deletes the object if its ETag is still `etag`, so that an
+    /// object which was overwritten after `etag` was read survives. Fails with an error caused by
+    /// [`PreconditionFailed`] if the ETag differs. Like for [`Self::delete`], a missing object is
+    /// not an error, so that retries of a deletion which went through succeed.
+    ///
+    /// S3 and Azure check the ETag with an `If-Match` condition on the request. [`LocalFs`]
+    /// compares its mtime based ETag, which is racy with concurrent writers.
+    async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()>;
+
     /// Delete a multiple paths from remote storage.
     ///
     /// If the operation fails because of timeout or cancellation, the root cause of the error will be
@@ -248,22 +687,143 @@ pub trait RemoteStorage: Send + Sync + 'static {
         cancel: &CancellationToken,
     ) -> anyhow::Result<()>;
 
+    /// Deletes every object below `prefix`, in batches of up to [`MAX_KEYS_PER_DELETE`] keys.
+    /// `prefix` is treated as a directory: deleting `a/b` leaves `a/bc` alone.
+    ///
+    /// Errors are those of [`Self::list`] and [`Self::delete_objects`]. Each batch is deleted
+    /// before the next one is listed, so on error some of the objects may already be gone, and
+    /// the deletion can simply be retried.
+    async fn delete_prefix(
+        &self,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let deleted = support::delete_prefix(self, prefix, cancel).await?;
+        info!("Deleted {deleted} objects below {prefix}");
+        Ok(())
+    }
+
+    /// Deletes the objects below `prefix` which are tagged with `tag_key` = `tag_value`, e.g.
+    /// everything tagged `orphan=true`, and returns how many were deleted. Like for
+    /// [`Self::delete_prefix`], `prefix` is treated as a directory.
+    ///
+    /// Listings can't filter by tag, so this lists `prefix` page by page, fetches the tags of each
+    /// listed object, a bounded number at a time, and deletes the tagged ones of a page before
+    /// listing the next one.
+    ///
+    /// Only S3 has object tags. Azure blob index tags aren't supported, and [`LocalFs`] has no
+    /// tags, so both fail instead.
+    async fn delete_by_tag(
+        &self,
+        prefix: &RemotePath,
+        tag_key: &str,
+        tag_value: &str,
+        _cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        anyhow::bail!(
+            "cannot delete objects tagged {tag_key}={tag_value} below {prefix}: the storage has no object tags"
+        )
+    }
+
+    /// Sums up the listed sizes of the objects below `prefix`, i.e. what is actually stored there,
+    /// without downloading or requesting anything per object. `prefix` is treated as a directory:
+    /// the size of `a/b` doesn't include `a/bc`.
+    async fn prefix_size(
+        &self,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<PrefixSize, DownloadError> {
+        support::prefix_size(self, prefix, cancel).await
+    }
+
+    /// Does a [`ListingMode::WithDelimiter`] [`Self::list`] of `prefix`, and returns the
+    /// [`Self::prefix_size`] of each of the common prefixes in it, sorted by prefix. E.g. on the
+    /// `tenants/` root, this is the stored size of every tenant. Objects directly at `prefix`
+    /// don't belong to any common prefix and aren't counted.
+    ///
+    /// The listings are consumed page by page and the prefixes are sized a bounded number at a
+    /// time as they are listed. Only their sizes are returned, not the listed objects.
+    async fn list_with_common_prefix_counts(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(RemotePath, PrefixSize)>, DownloadError> {
+        support::list_with_common_prefix_counts(self, prefix, cancel).await
+    }
+
     /// Copy a remote object inside a bucket from one path to another.
+    ///
+    /// `metadata` controls whether the copy keeps the [`StorageMetadata`] of the source object,
+    /// or gets new metadata instead.
+    ///
+    /// S3 and Azure copy server-side. If the storage refuses to, e.g. S3 for objects larger than
+    /// 5 GiB, the object is downloaded and uploaded again instead, which is slower but works for
+    /// any object.
     async fn copy(
         &self,
         from: &RemotePath,
         to: &RemotePath,
+        metadata: CopyMetadata,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()>;
 
-    /// Resets the content of everything with the given prefix to the given state
+    /// Sets the last modified time of the object at `path` to now, keeping its contents and
+    /// metadata. Lifecycle policies moving objects to colder storage tiers after some time without
+    /// modification then start over, which is cheaper than uploading hot objects again.
+    ///
+    /// S3 copies the object onto itself, Azure sets the blob properties to their current values,
+    /// and [`LocalFs`] sets the file's mtime.
+    ///
+    /// On S3, the copy keeps the storage class, tags, user metadata and the `Cache-Control`,
+    /// `Content-Disposition`, `Content-Encoding`, `Content-Language`, `Content-Type` and `Expires`
+    /// headers. ACLs and server-side encryption fall back to the bucket defaults. On a versioned
+    /// bucket every touch creates a new version of the object, which is stored in full until the
+    /// noncurrent versions expire. Objects above 5 GiB can't be copied in one request, so touching
+    /// them fails.
+    async fn touch(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()>;
+
+    /// Resets the content of everything with the given prefix to the given state, and returns
+    /// how many objects that touched.
+    ///
+    /// A recovery which succeeds without restoring or deleting anything usually means that
+    /// `timestamp` or `prefix` were wrong, so callers should report the summary.
+    ///
+    /// S3 lists all versions below the prefix once before restoring anything, and fails without
+    /// changes if there are too many of them, or if versioning was disabled for any object. A
+    /// recovery that fails midway, e.g. on objects written since, leaves the prefix partially
+    /// restored, and can be retried: restoring is idempotent.
     async fn time_travel_recover(
         &self,
         prefix: Option<&RemotePath>,
         timestamp: SystemTime,
         done_if_after: SystemTime,
         cancel: &CancellationToken,
-    ) -> Result<(), TimeTravelError>;
+    ) -> Result<TimeTravelSummary, TimeTravelError>;
+
+    /// Aborts multipart uploads below `prefix` which were started more than `older_than` ago and
+    /// never completed, so that their parts stop accruing storage cost. Returns how many uploads
+    /// were aborted.
+    ///
+    /// Only S3 keeps such uploads around: Azure garbage collects uncommitted blocks by itself,
+    /// and [`LocalFs`] has no multipart uploads, so this is a no-op for them.
+    async fn abort_incomplete_uploads(
+        &self,
+        prefix: Option<&RemotePath>,
+        older_than: Duration,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize>;
+
+    /// Bytes transferred and requests issued by this storage since it was created. Unlike the
+    /// prometheus metrics, these are per storage instance, so they can be read in-process to
+    /// attribute the traffic of e.g. a single tenant.
+    fn traffic_stats(&self) -> TrafficStats;
+
+    /// The effective location of this storage, as resolved from its config.
+    fn describe(&self) -> StorageDescription;
+
+    /// The config this storage was created from, with credentials redacted, see
+    /// [`RemoteStorageConfig::redacted_config`].
+    fn redacted_config(&self) -> serde_json::Value;
 }
 
 /// DownloadStream is sensitive to the timeout and cancellation used with the original
@@ -279,14 +839,236 @@ pub struct Download {
     pub last_modified: SystemTime,
     /// A way to identify this specific version of the resource (`etag` HTTP header)
     pub etag: Etag,
+    /// The number of bytes in `download_stream`: the size of the object, or the length of the
+    /// range for [`RemoteStorage::download_byte_range`] (`content-length` HTTP header).
+    ///
+    /// For objects with a [`Download::content_encoding`], this is the encoded length, also if
+    /// [`GenericRemoteStorage::download`] has decoded the stream.
+    pub content_length: u64,
+    /// The size of the whole object, also for range downloads (`content-range` HTTP header).
+    pub object_size: u64,
     /// Extra key-value data, associated with the current remote file.
     pub metadata: Option<StorageMetadata>,
+    /// The encoding of the bytes in `download_stream`, if the object was uploaded with one.
+    ///
+    /// [`GenericRemoteStorage::download`] decodes the stream, and always returns `None` here.
+    pub content_encoding: Option<Compression>,
+}
+
+/// What [`RemoteStorage::download_to_file`] learned about the downloaded object.
+#[derive(Debug, Clone)]
+pub struct ObjectMetadata {
+    /// The last time the object was modified (`last-modified` HTTP header)
+    pub last_modified: SystemTime,
+    /// The version of the object which was downloaded (`etag` HTTP header)
+    pub etag: Etag,
+    /// The number of bytes written to the local file
+    pub size: u64,
+    /// Extra key-value data, associated with the object.
+    pub metadata: Option<StorageMetadata>,
+}
+
+impl Download {
+    /// Wraps the stream to undo its [`Download::content_encoding`].
+    fn decoded(self) -> Self {
+        let Some(content_encoding) = self.content_encoding else {
+            return self;
+        };
+        let reader = tokio_util::io::StreamReader::new(self.download_stream);
+        let download_stream: DownloadStream = match content_encoding {
+            Compression::Gzip => Box::pin(tokio_util::io::ReaderStream::new(
+                async_compression::tokio::bufread::GzipDecoder::new(reader),
+            )),
+        };
+        Download {
+            download_stream,
+            content_encoding: None,
+            ..self
+        }
+    }
+}
+
+/// What [`RemoteStorage::time_travel_recover`] did, counted in keys.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TimeTravelSummary {
+    /// Keys whose old version was copied back into place
+    pub restored: usize,
+    /// Keys deleted because they didn't exist at the recovery timestamp
+    pub deleted: usize,
+    /// Keys left as they were: unchanged since the timestamp, already deleted, or modified after
+    /// `done_if_after`
+    pub skipped: usize,
+}
+
+impl std::ops::AddAssign for TimeTravelSummary {
+    fn add_assign(&mut self, other: Self) {
+        self.restored += other.restored;
+        self.deleted += other.deleted;
+        self.skipped += other.skipped;
+    }
+}
+
+/// The optional parts of an upload with [`RemoteStorage::upload`] and its siblings. The default is
+/// a plain upload of the bytes, without metadata.
+#[derive(Debug, Clone, Default)]
+pub struct UploadOptions {
+    /// Stored with the object and returned in [`Download::metadata`].
+    pub metadata: Option<StorageMetadata>,
+    /// The encoding the uploaded bytes already have. It is stored along with the object, e.g. as
+    /// its `Content-Encoding` header, and reported back in [`Download::content_encoding`].
+    pub content_encoding: Option<Compression>,
+    /// Fails the upload instead of storing the object if the MD5 digest of the uploaded bytes
+    /// isn't this one, to catch data corrupted on the way to the storage.
+    ///
+    /// S3 sends the digest as the `Content-MD5` header of the `PutObject` request and lets the
+    /// server reject a mismatching body. Objects larger than a single `PutObject` allows (5 GiB)
+    /// are uploaded in parts instead, each with the `Content-MD5` of the part, and the digest of
+    /// the whole object is checked while streaming it, like other backends do: the upload fails
+    /// before the last chunk is passed on.
+    pub content_md5: Option<[u8; 16]>,
+    /// A canned ACL to apply to the object, e.g. to make it publicly readable. Without one, the
+    /// object gets the bucket's default.
+    ///
+    /// Only S3 has per-object ACLs. Azure configures public access per container, and fails the
+    /// upload instead, while [`LocalFs`] has no access control and ignores the ACL.
+    pub acl: Option<ObjectAcl>,
+    /// Places the object under Object Lock retention, so that it can't be deleted or overwritten
+    /// until [`ObjectLockConfig::retain_until`], e.g. for WORM compliance of WAL backups. The
+    /// bucket must have Object Lock enabled, and S3 requires an integrity checksum with such
+    /// uploads, so a CRC32 one is sent along.
+    ///
+    /// Only S3 supports this. Azure configures immutability policies on the container, and
+    /// [`LocalFs`] can't enforce retention, so both fail the upload.
+    pub object_lock: Option<ObjectLockConfig>,
+}
+
+/// Compression that can be applied to objects with [`GenericRemoteStorage::upload_compressed`].
+///
+/// It is recorded as the content encoding of the object, and downloads remove it again
+/// transparently, so readers don't need to know whether an object was compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+}
+
+impl Compression {
+    /// The value of the `Content-Encoding` HTTP header for this compression.
+    pub fn as_content_encoding(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+        }
+    }
+
+    /// Parses a `Content-Encoding` header. Encodings we can't decode are treated as none, i.e.
+    /// the bytes are passed on as they are stored.
+    pub fn from_content_encoding(content_encoding: &str) -> Option<Self> {
+        match content_encoding.trim() {
+            "gzip" => Some(Compression::Gzip),
+            _ => None,
+        }
+    }
+}
+
+/// Canned access control list for objects, see [`UploadOptions::acl`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectAcl {
+    /// Only the owner of the object has access.
+    Private,
+    /// Everyone can read the object, also without credentials.
+    PublicRead,
+    /// The owners of both the object and the bucket have full control.
+    BucketOwnerFullControl,
+}
+
+/// S3 Object Lock retention for objects, see [`UploadOptions::object_lock`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectLockConfig {
+    pub mode: ObjectLockMode,
+    /// The object version can't be deleted or overwritten before this time.
+    pub retain_until: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectLockMode {
+    /// Users with the `s3:BypassGovernanceRetention` permission can still shorten or remove the
+    /// retention, or delete the object.
+    Governance,
+    /// Nobody can shorten the retention or delete the object, not even the root account.
+    Compliance,
+}
+
+/// A way to identify a specific version of a remote object (`etag` HTTP header).
+///
+/// Backends disagree on whether ETags are quoted: S3 returns them quoted, while Azure and the
+/// local FS mock do not.  To make comparisons between ETags from any source meaningful, the value
+/// is normalized on construction to the quoted form from RFC 7232, i.e. `"<tag>"`, or `W/"<tag>"`
+/// for weak ETags.  All ETags returned in [`Download`] are normalized this way.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Etag(String);
+
+impl Etag {
+    const WEAK_PREFIX: &'static str = "W/";
+
+    /// The opaque tag, without quotes and without the weakness indicator.
+    pub fn strip_quotes(&self) -> &str {
+        let tag = self.0.strip_prefix(Self::WEAK_PREFIX).unwrap_or(&self.0);
+        &tag[1..tag.len() - 1]
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.0.starts_with(Self::WEAK_PREFIX)
+    }
+
+    /// Returns true if this is an ETag of an S3 multipart upload, which has the form
+    /// `<md5 of part md5s>-<part count>`, and thus is not an MD5 of the object contents.
+    pub fn is_multipart(&self) -> bool {
+        match self.strip_quotes().rsplit_once('-') {
+            Some((_, parts)) => !parts.is_empty() && parts.bytes().all(|b| b.is_ascii_digit()),
+            None => false,
+        }
+    }
+
+    /// Compares two ETags using the weak comparison function from RFC 7232: the opaque tags
+    /// must match, but either or both may be weak.
+    pub fn eq_ignoring_weak(&self, other: &Etag) -> bool {
+        self.strip_quotes() == other.strip_quotes()
+    }
+}
+
+impl From<String> for Etag {
+    fn from(value: String) -> Self {
+        let value = value.trim();
+        let (prefix, tag) = match value.strip_prefix(Self::WEAK_PREFIX) {
+            Some(tag) => (Self::WEAK_PREFIX, tag),
+            None => ("", value),
+        };
+        let tag = tag
+            .strip_prefix('"')
+            .and_then(|t| t.strip_suffix('"'))
+            .unwrap_or(tag);
+        Etag(format!("{prefix}\"{tag}\""))
+    }
+}
+
+impl From<&str> for Etag {
+    fn from(value: &str) -> Self {
+        Etag::from(value.to_owned())
+    }
+}
+
+impl std::fmt::Display for Etag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
 impl Debug for Download {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Download")
+            .field("content_length", &self.content_length)
+            .field("object_size", &self.object_size)
             .field("metadata", &self.metadata)
+            .field("content_encoding", &self.content_encoding)
             .finish()
     }
 }
@@ -300,6 +1082,40 @@ pub enum GenericRemoteStorage<Other: Clone = Arc<UnreliableWrapper>> {
     AwsS3(Arc<S3Bucket>),
     AzureBlob(Arc<AzureBlobStorage>),
     Unreliable(Other),
+    Mirror(Arc<MirrorStorage<GenericRemoteStorage, GenericRemoteStorage>>),
+}
+
+impl<Other: Clone> GenericRemoteStorage<Other> {
+    /// Short, stable name of the backend, for use in log lines and as a metric label.
+    pub fn backend_name(&self) -> &'static str {
+        match self {
+            Self::LocalFs(_) => "localfs",
+            Self::AwsS3(_) => "s3",
+            Self::AzureBlob(_) => "azure",
+            Self::Unreliable(_) => "unreliable",
+            Self::Mirror(_) => "mirror",
+        }
+    }
+
+    /// The underlying [`LocalFs`], if this is a local filesystem storage. Meant for tests which
+    /// need to manipulate the stored files directly, e.g. to corrupt them.
+    pub fn as_local_fs(&self) -> Option<&LocalFs> {
+        match self {
+            Self::LocalFs(local_fs) => Some(local_fs),
+            _ => None,
+        }
+    }
+
+    fn count_request(&self, kind: metrics::RequestKind) {
+        metrics::BUCKET_METRICS
+            .requests_by_backend
+            .with_label_values(&[
+                self.backend_name(),
+                kind.as_str(),
+                op_label::current_op_label(),
+            ])
+            .inc();
+    }
 }
 
 impl<Other: RemoteStorage> GenericRemoteStorage<Arc<Other>> {
@@ -308,46 +1124,220 @@ impl<Other: RemoteStorage> GenericRemoteStorage<Arc<Other>> {
         prefix: Option<&RemotePath>,
         mode: ListingMode,
         max_keys: Option<NonZeroU32>,
+        options: ListOptions,
         cancel: &CancellationToken,
     ) -> anyhow::Result<Listing, DownloadError> {
-        match self {
-            Self::LocalFs(s) => s.list(prefix, mode, max_keys, cancel).await,
-            Self::AwsS3(s) => s.list(prefix, mode, max_keys, cancel).await,
-            Self::AzureBlob(s) => s.list(prefix, mode, max_keys, cancel).await,
-            Self::Unreliable(s) => s.list(prefix, mode, max_keys, cancel).await,
-        }
+        self.count_request(metrics::RequestKind::List);
+        let res = match self {
+            Self::LocalFs(s) => s.list(prefix, mode, max_keys, options, cancel).await,
+            Self::AwsS3(s) => s.list(prefix, mode, max_keys, options, cancel).await,
+            Self::AzureBlob(s) => s.list(prefix, mode, max_keys, options, cancel).await,
+            Self::Unreliable(s) => s.list(prefix, mode, max_keys, options, cancel).await,
+            Self::Mirror(s) => Box::pin(s.list(prefix, mode, max_keys, options, cancel)).await,
+        };
+        res.map_err(|e| match prefix {
+            Some(prefix) => e.add_context(format!("list {prefix}")),
+            None => e.add_context("list from the root"),
+        })
     }
 
-    /// See [`RemoteStorage::upload`]
-    pub async fn upload(
+    /// See [`RemoteStorage::list_with_depth`]
+    pub async fn list_with_depth(
         &self,
-        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
-        data_size_bytes: usize,
-        to: &RemotePath,
-        metadata: Option<StorageMetadata>,
+        prefix: Option<&RemotePath>,
+        depth: NonZeroU32,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        self.count_request(metrics::RequestKind::List);
+        let res = match self {
+            Self::LocalFs(s) => s.list_with_depth(prefix, depth, cancel).await,
+            Self::AwsS3(s) => s.list_with_depth(prefix, depth, cancel).await,
+            Self::AzureBlob(s) => s.list_with_depth(prefix, depth, cancel).await,
+            Self::Unreliable(s) => s.list_with_depth(prefix, depth, cancel).await,
+            Self::Mirror(s) => Box::pin(s.list_with_depth(prefix, depth, cancel)).await,
+        };
+        res.map_err(|e| match prefix {
+            Some(prefix) => e.add_context(format!("list {prefix} to depth {depth}")),
+            None => e.add_context(format!("list from the root to depth {depth}")),
+        })
+    }
+
+    /// See [`RemoteStorage::list_page`]
+    pub async fn list_page(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
+        cancel: &CancellationToken,
+    ) -> Result<(Listing, Option<ContinuationToken>), DownloadError> {
+        self.count_request(metrics::RequestKind::List);
+        let res = match self {
+            Self::LocalFs(s) => {
+                s.list_page(prefix, mode, max_keys, resume_from, cancel)
+                    .await
+            }
+            Self::AwsS3(s) => {
+                s.list_page(prefix, mode, max_keys, resume_from, cancel)
+                    .await
+            }
+            Self::AzureBlob(s) => {
+                s.list_page(prefix, mode, max_keys, resume_from, cancel)
+                    .await
+            }
+            Self::Unreliable(s) => {
+                s.list_page(prefix, mode, max_keys, resume_from, cancel)
+                    .await
+            }
+            Self::Mirror(s) => {
+                Box::pin(s.list_page(prefix, mode, max_keys, resume_from, cancel)).await
+            }
+        };
+        res.map_err(|e| match prefix {
+            Some(prefix) => e.add_context(format!("list a page of {prefix}")),
+            None => e.add_context("list a page from the root"),
+        })
+    }
+
+    /// See [`RemoteStorage::list_streaming`]. Every page is counted as a listing.
+    pub fn list_streaming<'a>(
+        &'a self,
+        prefix: Option<&'a RemotePath>,
+        mode: ListingMode,
+        max_keys_per_page: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
+        cancel: &'a CancellationToken,
+    ) -> impl Stream<Item = Result<(Listing, Option<ContinuationToken>), DownloadError>> + 'a {
+        support::list_streaming(self, prefix, mode, max_keys_per_page, resume_from, cancel)
+    }
+
+    /// See [`RemoteStorage::list_prefixes_recursive`]
+    pub fn list_prefixes_recursive<'a>(
+        &'a self,
+        prefix: Option<&'a RemotePath>,
+        cancel: &'a CancellationToken,
+    ) -> impl Stream<Item = Result<RemotePath, DownloadError>> + 'a {
+        use futures::future::Either;
+
+        self.count_request(metrics::RequestKind::List);
+        match self {
+            Self::LocalFs(s) => {
+                Either::Left(Either::Left(s.list_prefixes_recursive(prefix, cancel)))
+            }
+            Self::AwsS3(s) => {
+                Either::Left(Either::Right(s.list_prefixes_recursive(prefix, cancel)))
+            }
+            Self::AzureBlob(s) => Either::Right(Either::Left(Either::Left(
+                s.list_prefixes_recursive(prefix, cancel),
+            ))),
+            Self::Unreliable(s) => Either::Right(Either::Left(Either::Right(
+                s.list_prefixes_recursive(prefix, cancel),
+            ))),
+            // Boxed, as the mirrored storages are generic storages themselves
+            Self::Mirror(s) => Either::Right(Either::Right(Box::pin(
+                s.list_prefixes_recursive(prefix, cancel),
+            )
+                as Pin<Box<dyn Stream<Item = Result<RemotePath, DownloadError>> + Send + 'a>>)),
+        }
+    }
+
+    /// See [`RemoteStorage::upload`]
+    pub async fn upload(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.count_request(metrics::RequestKind::Put);
+        match self {
+            Self::LocalFs(s) => s.upload(from, data_size_bytes, to, options, cancel).await,
+            Self::AwsS3(s) => s.upload(from, data_size_bytes, to, options, cancel).await,
+            Self::AzureBlob(s) => s.upload(from, data_size_bytes, to, options, cancel).await,
+            Self::Unreliable(s) => s.upload(from, data_size_bytes, to, options, cancel).await,
+            Self::Mirror(s) => Box::pin(s.upload(from, data_size_bytes, to, options, cancel)).await,
+        }
+    }
+
+    /// See [`RemoteStorage::upload_unsized`]
+    pub async fn upload_unsized(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.count_request(metrics::RequestKind::Put);
+        match self {
+            Self::LocalFs(s) => s.upload_unsized(from, to, options, cancel).await,
+            Self::AwsS3(s) => s.upload_unsized(from, to, options, cancel).await,
+            Self::AzureBlob(s) => s.upload_unsized(from, to, options, cancel).await,
+            Self::Unreliable(s) => s.upload_unsized(from, to, options, cancel).await,
+            Self::Mirror(s) => Box::pin(s.upload_unsized(from, to, options, cancel)).await,
+        }
+    }
+
+    /// See [`RemoteStorage::upload_bytes`]
+    pub async fn upload_bytes(
+        &self,
+        data: Bytes,
+        to: &RemotePath,
+        options: UploadOptions,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
+        self.count_request(metrics::RequestKind::Put);
         match self {
-            Self::LocalFs(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
-            Self::AwsS3(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
-            Self::AzureBlob(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
-            Self::Unreliable(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
+            Self::LocalFs(s) => s.upload_bytes(data, to, options, cancel).await,
+            Self::AwsS3(s) => s.upload_bytes(data, to, options, cancel).await,
+            Self::AzureBlob(s) => s.upload_bytes(data, to, options, cancel).await,
+            Self::Unreliable(s) => s.upload_bytes(data, to, options, cancel).await,
+            Self::Mirror(s) => Box::pin(s.upload_bytes(data, to, options, cancel)).await,
         }
     }
 
+    /// See [`RemoteStorage::download`]. If the object was uploaded with a content encoding, the
+    /// stream is transparently decoded.
     pub async fn download(
         &self,
         from: &RemotePath,
         cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
-        match self {
-            Self::LocalFs(s) => s.download(from, cancel).await,
-            Self::AwsS3(s) => s.download(from, cancel).await,
-            Self::AzureBlob(s) => s.download(from, cancel).await,
-            Self::Unreliable(s) => s.download(from, cancel).await,
-        }
+        self.download_with_deadline(from, None, cancel).await
+    }
+
+    /// See [`RemoteStorage::download_with_deadline`]. Decodes like [`Self::download`].
+    pub async fn download_with_deadline(
+        &self,
+        from: &RemotePath,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_raw(from, deadline, cancel)
+            .await
+            .map(Download::decoded)
+    }
+
+    /// [`Self::download_with_deadline`] without decoding the content encoding.
+    pub(crate) async fn download_raw(
+        &self,
+        from: &RemotePath,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.count_request(metrics::RequestKind::Get);
+        let res = match self {
+            Self::LocalFs(s) => s.download_with_deadline(from, deadline, cancel).await,
+            Self::AwsS3(s) => s.download_with_deadline(from, deadline, cancel).await,
+            Self::AzureBlob(s) => s.download_with_deadline(from, deadline, cancel).await,
+            Self::Unreliable(s) => s.download_with_deadline(from, deadline, cancel).await,
+            Self::Mirror(s) => Box::pin(s.download_with_deadline(from, deadline, cancel)).await,
+        };
+        res.map_err(|e| e.add_context(format!("download {from}")))
     }
 
+    /// See [`RemoteStorage::download_byte_range`]. The range is of the stored bytes, so for an
+    /// object with a content encoding, the returned stream is not decoded.
     pub async fn download_byte_range(
         &self,
         from: &RemotePath,
@@ -355,24 +1345,97 @@ impl<Other: RemoteStorage> GenericRemoteStorage<Arc<Other>> {
         end_exclusive: Option<u64>,
         cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
-        match self {
+        self.download_byte_range_with_deadline(from, start_inclusive, end_exclusive, None, cancel)
+            .await
+    }
+
+    /// See [`RemoteStorage::download_byte_range_with_deadline`]. Like
+    /// [`Self::download_byte_range`], the stream is not decoded.
+    pub async fn download_byte_range_with_deadline(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        let add_context = |e: DownloadError| {
+            let end = end_exclusive.map(|end| end.to_string()).unwrap_or_default();
+            e.add_context(format!("download {from} bytes {start_inclusive}..{end}"))
+        };
+        // Before counting or wrapping anything, so that a caller bug doesn't look like a request
+        support::check_byte_range(start_inclusive, end_exclusive).map_err(add_context)?;
+        self.count_request(metrics::RequestKind::Get);
+        let res = match self {
             Self::LocalFs(s) => {
-                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
-                    .await
+                s.download_byte_range_with_deadline(
+                    from,
+                    start_inclusive,
+                    end_exclusive,
+                    deadline,
+                    cancel,
+                )
+                .await
             }
             Self::AwsS3(s) => {
-                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
-                    .await
+                s.download_byte_range_with_deadline(
+                    from,
+                    start_inclusive,
+                    end_exclusive,
+                    deadline,
+                    cancel,
+                )
+                .await
             }
             Self::AzureBlob(s) => {
-                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
-                    .await
+                s.download_byte_range_with_deadline(
+                    from,
+                    start_inclusive,
+                    end_exclusive,
+                    deadline,
+                    cancel,
+                )
+                .await
             }
             Self::Unreliable(s) => {
-                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
-                    .await
+                s.download_byte_range_with_deadline(
+                    from,
+                    start_inclusive,
+                    end_exclusive,
+                    deadline,
+                    cancel,
+                )
+                .await
             }
-        }
+            Self::Mirror(s) => {
+                Box::pin(s.download_byte_range_with_deadline(
+                    from,
+                    start_inclusive,
+                    end_exclusive,
+                    deadline,
+                    cancel,
+                ))
+                .await
+            }
+        };
+        res.map_err(add_context)
+    }
+
+    /// See [`RemoteStorage::head_object`]
+    pub async fn head_object(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError> {
+        self.count_request(metrics::RequestKind::Get);
+        let res = match self {
+            Self::LocalFs(s) => s.head_object(key, cancel).await,
+            Self::AwsS3(s) => s.head_object(key, cancel).await,
+            Self::AzureBlob(s) => s.head_object(key, cancel).await,
+            Self::Unreliable(s) => s.head_object(key, cancel).await,
+            Self::Mirror(s) => Box::pin(s.head_object(key, cancel)).await,
+        };
+        res.map_err(|e| e.add_context(format!("head {key}")))
     }
 
     /// See [`RemoteStorage::delete`]
@@ -381,11 +1444,46 @@ impl<Other: RemoteStorage> GenericRemoteStorage<Arc<Other>> {
         path: &RemotePath,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
+        self.count_request(metrics::RequestKind::Delete);
         match self {
             Self::LocalFs(s) => s.delete(path, cancel).await,
             Self::AwsS3(s) => s.delete(path, cancel).await,
             Self::AzureBlob(s) => s.delete(path, cancel).await,
             Self::Unreliable(s) => s.delete(path, cancel).await,
+            Self::Mirror(s) => Box::pin(s.delete(path, cancel)).await,
+        }
+    }
+
+    /// See [`RemoteStorage::delete_if_match`]
+    pub async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.count_request(metrics::RequestKind::Delete);
+        match self {
+            Self::LocalFs(s) => s.delete_if_match(path, etag, cancel).await,
+            Self::AwsS3(s) => s.delete_if_match(path, etag, cancel).await,
+            Self::AzureBlob(s) => s.delete_if_match(path, etag, cancel).await,
+            Self::Unreliable(s) => s.delete_if_match(path, etag, cancel).await,
+            Self::Mirror(s) => Box::pin(s.delete_if_match(path, etag, cancel)).await,
+        }
+    }
+
+    /// See [`RemoteStorage::delete_if_exists`]
+    pub async fn delete_if_exists(
+        &self,
+        path: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<bool> {
+        self.count_request(metrics::RequestKind::Delete);
+        match self {
+            Self::LocalFs(s) => s.delete_if_exists(path, cancel).await,
+            Self::AwsS3(s) => s.delete_if_exists(path, cancel).await,
+            Self::AzureBlob(s) => s.delete_if_exists(path, cancel).await,
+            Self::Unreliable(s) => s.delete_if_exists(path, cancel).await,
+            Self::Mirror(s) => Box::pin(s.delete_if_exists(path, cancel)).await,
         }
     }
 
@@ -395,26 +1493,106 @@ impl<Other: RemoteStorage> GenericRemoteStorage<Arc<Other>> {
         paths: &[RemotePath],
         cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
+        self.count_request(metrics::RequestKind::Delete);
         match self {
             Self::LocalFs(s) => s.delete_objects(paths, cancel).await,
             Self::AwsS3(s) => s.delete_objects(paths, cancel).await,
             Self::AzureBlob(s) => s.delete_objects(paths, cancel).await,
             Self::Unreliable(s) => s.delete_objects(paths, cancel).await,
+            Self::Mirror(s) => Box::pin(s.delete_objects(paths, cancel)).await,
         }
     }
 
+    /// See [`RemoteStorage::download_to_file`]
+    pub async fn download_to_file(
+        &self,
+        from: &RemotePath,
+        local: &Utf8Path,
+        cancel: &CancellationToken,
+    ) -> Result<ObjectMetadata, DownloadError> {
+        // The download is counted as it is issued, and decoded like [`Self::download`]
+        support::download_to_file(self, from, local, cancel).await
+    }
+
+    /// See [`RemoteStorage::delete_prefix`]
+    pub async fn delete_prefix(
+        &self,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        // Listings and deletions are counted as they are issued
+        let deleted = support::delete_prefix(self, prefix, cancel).await?;
+        info!("Deleted {deleted} objects below {prefix}");
+        Ok(())
+    }
+
+    /// See [`RemoteStorage::delete_by_tag`]
+    pub async fn delete_by_tag(
+        &self,
+        prefix: &RemotePath,
+        tag_key: &str,
+        tag_value: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        self.count_request(metrics::RequestKind::Delete);
+        let deleted = match self {
+            Self::LocalFs(s) => s.delete_by_tag(prefix, tag_key, tag_value, cancel).await,
+            Self::AwsS3(s) => s.delete_by_tag(prefix, tag_key, tag_value, cancel).await,
+            Self::AzureBlob(s) => s.delete_by_tag(prefix, tag_key, tag_value, cancel).await,
+            Self::Unreliable(s) => s.delete_by_tag(prefix, tag_key, tag_value, cancel).await,
+            Self::Mirror(s) => Box::pin(s.delete_by_tag(prefix, tag_key, tag_value, cancel)).await,
+        }?;
+        info!("Deleted {deleted} objects tagged {tag_key}={tag_value} below {prefix}");
+        Ok(deleted)
+    }
+
+    /// See [`RemoteStorage::prefix_size`]
+    pub async fn prefix_size(
+        &self,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<PrefixSize, DownloadError> {
+        // Listings are counted as they are issued
+        support::prefix_size(self, prefix, cancel).await
+    }
+
+    /// See [`RemoteStorage::list_with_common_prefix_counts`]
+    pub async fn list_with_common_prefix_counts(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(RemotePath, PrefixSize)>, DownloadError> {
+        // Listings are counted as they are issued
+        support::list_with_common_prefix_counts(self, prefix, cancel).await
+    }
+
     /// See [`RemoteStorage::copy`]
     pub async fn copy_object(
         &self,
         from: &RemotePath,
         to: &RemotePath,
+        metadata: CopyMetadata,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
+        self.count_request(metrics::RequestKind::Copy);
+        match self {
+            Self::LocalFs(s) => s.copy(from, to, metadata, cancel).await,
+            Self::AwsS3(s) => s.copy(from, to, metadata, cancel).await,
+            Self::AzureBlob(s) => s.copy(from, to, metadata, cancel).await,
+            Self::Unreliable(s) => s.copy(from, to, metadata, cancel).await,
+            Self::Mirror(s) => Box::pin(s.copy(from, to, metadata, cancel)).await,
+        }
+    }
+
+    /// See [`RemoteStorage::touch`]
+    pub async fn touch(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.count_request(metrics::RequestKind::Copy);
         match self {
-            Self::LocalFs(s) => s.copy(from, to, cancel).await,
-            Self::AwsS3(s) => s.copy(from, to, cancel).await,
-            Self::AzureBlob(s) => s.copy(from, to, cancel).await,
-            Self::Unreliable(s) => s.copy(from, to, cancel).await,
+            Self::LocalFs(s) => s.touch(path, cancel).await,
+            Self::AwsS3(s) => s.touch(path, cancel).await,
+            Self::AzureBlob(s) => s.touch(path, cancel).await,
+            Self::Unreliable(s) => s.touch(path, cancel).await,
+            Self::Mirror(s) => Box::pin(s.touch(path, cancel)).await,
         }
     }
 
@@ -425,7 +1603,8 @@ impl<Other: RemoteStorage> GenericRemoteStorage<Arc<Other>> {
         timestamp: SystemTime,
         done_if_after: SystemTime,
         cancel: &CancellationToken,
-    ) -> Result<(), TimeTravelError> {
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
+        self.count_request(metrics::RequestKind::TimeTravel);
         match self {
             Self::LocalFs(s) => {
                 s.time_travel_recover(prefix, timestamp, done_if_after, cancel)
@@ -443,404 +1622,3218 @@ impl<Other: RemoteStorage> GenericRemoteStorage<Arc<Other>> {
                 s.time_travel_recover(prefix, timestamp, done_if_after, cancel)
                     .await
             }
+            Self::Mirror(s) => {
+                Box::pin(s.time_travel_recover(prefix, timestamp, done_if_after, cancel)).await
+            }
         }
     }
-}
 
-impl GenericRemoteStorage {
-    pub fn from_config(storage_config: &RemoteStorageConfig) -> anyhow::Result<Self> {
-        let timeout = storage_config.timeout;
-        Ok(match &storage_config.storage {
-            RemoteStorageKind::LocalFs(path) => {
-                info!("Using fs root '{path}' as a remote storage");
-                Self::LocalFs(LocalFs::new(path.clone(), timeout)?)
-            }
-            RemoteStorageKind::AwsS3(s3_config) => {
-                // The profile and access key id are only printed here for debugging purposes,
-                // their values don't indicate the eventually taken choice for auth.
-                let profile = std::env::var("AWS_PROFILE").unwrap_or_else(|_| "<none>".into());
-                let access_key_id =
-                    std::env::var("AWS_ACCESS_KEY_ID").unwrap_or_else(|_| "<none>".into());
-                info!("Using s3 bucket '{}' in region '{}' as a remote storage, prefix in bucket: '{:?}', bucket endpoint: '{:?}', profile: {profile}, access_key_id: {access_key_id}",
-                      s3_config.bucket_name, s3_config.bucket_region, s3_config.prefix_in_bucket, s3_config.endpoint);
-                Self::AwsS3(Arc::new(S3Bucket::new(s3_config, timeout)?))
-            }
-            RemoteStorageKind::AzureContainer(azure_config) => {
-                info!("Using azure container '{}' in region '{}' as a remote storage, prefix in container: '{:?}'",
-                      azure_config.container_name, azure_config.container_region, azure_config.prefix_in_container);
-                Self::AzureBlob(Arc::new(AzureBlobStorage::new(azure_config, timeout)?))
+    /// See [`RemoteStorage::abort_incomplete_uploads`].
+    pub async fn abort_incomplete_uploads(
+        &self,
+        prefix: Option<&RemotePath>,
+        older_than: Duration,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        self.count_request(metrics::RequestKind::Delete);
+        match self {
+            Self::LocalFs(s) => s.abort_incomplete_uploads(prefix, older_than, cancel).await,
+            Self::AwsS3(s) => s.abort_incomplete_uploads(prefix, older_than, cancel).await,
+            Self::AzureBlob(s) => s.abort_incomplete_uploads(prefix, older_than, cancel).await,
+            Self::Unreliable(s) => s.abort_incomplete_uploads(prefix, older_than, cancel).await,
+            Self::Mirror(s) => {
+                Box::pin(s.abort_incomplete_uploads(prefix, older_than, cancel)).await
             }
-        })
+        }
     }
 
-    pub fn unreliable_wrapper(s: Self, fail_first: u64) -> Self {
-        Self::Unreliable(Arc::new(UnreliableWrapper::new(s, fail_first)))
+    /// See [`RemoteStorage::traffic_stats`].
+    pub fn traffic_stats(&self) -> TrafficStats {
+        match self {
+            Self::LocalFs(s) => s.traffic_stats(),
+            Self::AwsS3(s) => s.traffic_stats(),
+            Self::AzureBlob(s) => s.traffic_stats(),
+            Self::Unreliable(s) => s.traffic_stats(),
+            Self::Mirror(s) => s.traffic_stats(),
+        }
     }
 
-    /// See [`RemoteStorage::upload`], which this method calls with `None` as metadata.
-    pub async fn upload_storage_object(
+    /// See [`RemoteStorage::describe`]. For the [`UnreliableWrapper`], this describes the wrapped
+    /// storage.
+    pub fn describe(&self) -> StorageDescription {
+        match self {
+            Self::LocalFs(s) => s.describe(),
+            Self::AwsS3(s) => s.describe(),
+            Self::AzureBlob(s) => s.describe(),
+            Self::Unreliable(s) => s.describe(),
+            Self::Mirror(s) => s.describe(),
+        }
+    }
+
+    /// See [`RemoteStorage::redacted_config`]. For the [`UnreliableWrapper`], this is the config
+    /// of the wrapped storage.
+    pub fn redacted_config(&self) -> serde_json::Value {
+        match self {
+            Self::LocalFs(s) => s.redacted_config(),
+            Self::AwsS3(s) => s.redacted_config(),
+            Self::AzureBlob(s) => s.redacted_config(),
+            Self::Unreliable(s) => s.redacted_config(),
+            Self::Mirror(s) => s.redacted_config(),
+        }
+    }
+}
+
+/// Lets code written against [`RemoteStorage`] take the type-erased storage as well as the concrete
+/// backends. Inherent methods take precedence over trait methods, so each of these calls the
+/// inherent method of the same name, and requests are counted as usual.
+impl<Other: RemoteStorage> RemoteStorage for GenericRemoteStorage<Arc<Other>> {
+    async fn list(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        options: ListOptions,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        self.list(prefix, mode, max_keys, options, cancel).await
+    }
+
+    async fn list_with_depth(
+        &self,
+        prefix: Option<&RemotePath>,
+        depth: NonZeroU32,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        self.list_with_depth(prefix, depth, cancel).await
+    }
+
+    fn list_prefixes_recursive<'a>(
+        &'a self,
+        prefix: Option<&'a RemotePath>,
+        cancel: &'a CancellationToken,
+    ) -> impl Stream<Item = Result<RemotePath, DownloadError>> + 'a {
+        self.list_prefixes_recursive(prefix, cancel)
+    }
+
+    async fn list_page(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
+        cancel: &CancellationToken,
+    ) -> Result<(Listing, Option<ContinuationToken>), DownloadError> {
+        self.list_page(prefix, mode, max_keys, resume_from, cancel)
+            .await
+    }
+
+    fn list_streaming<'a>(
+        &'a self,
+        prefix: Option<&'a RemotePath>,
+        mode: ListingMode,
+        max_keys_per_page: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
+        cancel: &'a CancellationToken,
+    ) -> impl Stream<Item = Result<(Listing, Option<ContinuationToken>), DownloadError>> + 'a {
+        self.list_streaming(prefix, mode, max_keys_per_page, resume_from, cancel)
+    }
+
+    async fn upload(
         &self,
         from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
-        from_size_bytes: usize,
+        data_size_bytes: usize,
         to: &RemotePath,
+        options: UploadOptions,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
-        self.upload(from, from_size_bytes, to, None, cancel)
+        self.upload(from, data_size_bytes, to, options, cancel)
             .await
-            .with_context(|| {
-                format!("Failed to upload data of length {from_size_bytes} to storage path {to:?}")
-            })
     }
 
-    /// Downloads the storage object into the `to_path` provided.
-    /// `byte_range` could be specified to dowload only a part of the file, if needed.
-    pub async fn download_storage_object(
+    async fn upload_bytes(
+        &self,
+        data: Bytes,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.upload_bytes(data, to, options, cancel).await
+    }
+
+    async fn upload_unsized(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.upload_unsized(from, to, options, cancel).await
+    }
+
+    async fn download(
         &self,
-        byte_range: Option<(u64, Option<u64>)>,
         from: &RemotePath,
         cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
-        match byte_range {
-            Some((start, end)) => self.download_byte_range(from, start, end, cancel).await,
-            None => self.download(from, cancel).await,
-        }
+        self.download(from, cancel).await
     }
-}
-
-/// Extra set of key-value pairs that contain arbitrary metadata about the storage entry.
-/// Immutable, cannot be changed once the file is created.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct StorageMetadata(HashMap<String, String>);
 
-impl<const N: usize> From<[(&str, &str); N]> for StorageMetadata {
-    fn from(arr: [(&str, &str); N]) -> Self {
-        let map: HashMap<String, String> = arr
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect();
-        Self(map)
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_byte_range(from, start_inclusive, end_exclusive, cancel)
+            .await
     }
-}
+
+    async fn download_with_deadline(
+        &self,
+        from: &RemotePath,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_with_deadline(from, deadline, cancel).await
+    }
+
+    async fn download_byte_range_with_deadline(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_byte_range_with_deadline(
+            from,
+            start_inclusive,
+            end_exclusive,
+            deadline,
+            cancel,
+        )
+        .await
+    }
+
+    async fn download_to_file(
+        &self,
+        from: &RemotePath,
+        local: &Utf8Path,
+        cancel: &CancellationToken,
+    ) -> Result<ObjectMetadata, DownloadError> {
+        self.download_to_file(from, local, cancel).await
+    }
+
+    async fn head_object(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError> {
+        self.head_object(key, cancel).await
+    }
+
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.delete(path, cancel).await
+    }
+
+    async fn delete_if_exists(
+        &self,
+        path: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<bool> {
+        self.delete_if_exists(path, cancel).await
+    }
+
+    async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.delete_if_match(path, etag, cancel).await
+    }
+
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.delete_objects(paths, cancel).await
+    }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        metadata: CopyMetadata,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.copy_object(from, to, metadata, cancel).await
+    }
+
+    async fn touch(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.touch(path, cancel).await
+    }
+
+    async fn time_travel_recover(
+        &self,
+        prefix: Option<&RemotePath>,
+        timestamp: SystemTime,
+        done_if_after: SystemTime,
+        cancel: &CancellationToken,
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
+        self.time_travel_recover(prefix, timestamp, done_if_after, cancel)
+            .await
+    }
+
+    async fn abort_incomplete_uploads(
+        &self,
+        prefix: Option<&RemotePath>,
+        older_than: Duration,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        self.abort_incomplete_uploads(prefix, older_than, cancel)
+            .await
+    }
+
+    async fn delete_prefix(
+        &self,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.delete_prefix(prefix, cancel).await
+    }
+
+    async fn delete_by_tag(
+        &self,
+        prefix: &RemotePath,
+        tag_key: &str,
+        tag_value: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        self.delete_by_tag(prefix, tag_key, tag_value, cancel).await
+    }
+
+    async fn prefix_size(
+        &self,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<PrefixSize, DownloadError> {
+        self.prefix_size(prefix, cancel).await
+    }
+
+    async fn list_with_common_prefix_counts(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(RemotePath, PrefixSize)>, DownloadError> {
+        self.list_with_common_prefix_counts(prefix, cancel).await
+    }
+
+    fn traffic_stats(&self) -> TrafficStats {
+        self.traffic_stats()
+    }
+
+    fn describe(&self) -> StorageDescription {
+        self.describe()
+    }
+
+    fn redacted_config(&self) -> serde_json::Value {
+        self.redacted_config()
+    }
+}
+
+impl GenericRemoteStorage {
+    pub fn from_config(storage_config: &RemoteStorageConfig) -> anyhow::Result<Self> {
+        let timeouts = storage_config.timeouts;
+        let storage = match &storage_config.storage {
+            RemoteStorageKind::LocalFs {
+                local_path,
+                sync_on_upload,
+            } => {
+                info!("Using fs root '{local_path}' as a remote storage, sync on upload: {sync_on_upload}");
+                Self::LocalFs(LocalFs::new(local_path.clone(), timeouts, *sync_on_upload)?)
+            }
+            RemoteStorageKind::AwsS3(s3_config) => {
+                // The profile and access key id are only printed here for debugging purposes,
+                // their values don't indicate the eventually taken choice for auth.
+                let profile = match &s3_config.profile_name {
+                    Some(profile_name) => profile_name.clone(),
+                    None => std::env::var("AWS_PROFILE").unwrap_or_else(|_| "<none>".into()),
+                };
+                let access_key_id =
+                    std::env::var("AWS_ACCESS_KEY_ID").unwrap_or_else(|_| "<none>".into());
+                info!("Using s3 bucket '{}' in region '{}' as a remote storage, prefix in bucket: '{:?}', bucket endpoint: '{:?}', profile: {profile}, access_key_id: {access_key_id}",
+                      s3_config.bucket_name, s3_config.bucket_region, s3_config.prefix_in_bucket, s3_config.endpoint);
+                Self::AwsS3(Arc::new(S3Bucket::new(s3_config, timeouts)?))
+            }
+            RemoteStorageKind::AzureContainer(azure_config) => {
+                info!("Using azure container '{}' in region '{}' as a remote storage, prefix in container: '{:?}'",
+                      azure_config.container_name, azure_config.container_region, azure_config.prefix_in_container);
+                Self::AzureBlob(Arc::new(AzureBlobStorage::new(azure_config, timeouts)?))
+            }
+            RemoteStorageKind::Mirror(mirror_config) => {
+                let MirrorConfig {
+                    primary,
+                    secondary,
+                    mode,
+                } = mirror_config;
+                info!("Mirroring writes of the primary remote storage to the secondary one, mode: {mode:?}");
+                let mirrored = |storage: &RemoteStorageKind| {
+                    Self::from_config(&RemoteStorageConfig {
+                        storage: storage.clone(),
+                        timeouts,
+                    })
+                };
+                let primary = mirrored(primary).context("primary of the mirror")?;
+                let secondary = mirrored(secondary).context("secondary of the mirror")?;
+                Self::Mirror(Arc::new(MirrorStorage::new(primary, secondary, *mode)))
+            }
+        };
+        info!(
+            "Initialized '{}' remote storage with timeouts {timeouts:?}",
+            storage.backend_name()
+        );
+        Ok(storage)
+    }
+
+    pub fn unreliable_wrapper(s: Self, fail_first: u64) -> Self {
+        Self::Unreliable(Arc::new(UnreliableWrapper::new(s, fail_first)))
+    }
+
+    /// Returns a handle on the same storage with its own limit of `limit` concurrent requests of
+    /// each kind, e.g. one per tenant, so that a busy tenant can't starve the others of permits.
+    ///
+    /// The handle shares the HTTP connection pool and the [`RpsLimits`] with `self`, which still
+    /// protect the bucket as a whole, but counts its own [`TrafficStats`]. [`LocalFs`] has no
+    /// concurrency limit, so it and the [`UnreliableWrapper`] are returned as they are. Both
+    /// storages of a [`MirrorStorage`] get a limit of their own.
+    pub fn with_concurrency_limit(&self, limit: NonZeroUsize) -> Self {
+        match self {
+            Self::AwsS3(s) => Self::AwsS3(Arc::new(s.with_concurrency_limit(limit))),
+            Self::AzureBlob(s) => Self::AzureBlob(Arc::new(s.with_concurrency_limit(limit))),
+            Self::Mirror(s) => Self::Mirror(Arc::new(MirrorStorage::new(
+                s.primary().with_concurrency_limit(limit),
+                s.secondary().with_concurrency_limit(limit),
+                s.mode(),
+            ))),
+            Self::LocalFs(_) | Self::Unreliable(_) => self.clone(),
+        }
+    }
+
+    /// Returns a handle which only sees the objects under `prefix`, see [`ScopedRemoteStorage`].
+    pub fn scoped(&self, prefix: RemotePath) -> ScopedRemoteStorage {
+        ScopedRemoteStorage::new(self.clone(), prefix)
+    }
+
+    /// Probes which operations the configured credentials may perform, by uploading, reading,
+    /// listing, copying and deleting a throwaway object below `.permission_probe/`.
+    ///
+    /// Meant for onboarding new environments, where policies are often incomplete in ways which
+    /// only show up on the first compaction or deletion. Failed probes don't stop the later
+    /// ones, so the report is complete, but get and copy can't be probed meaningfully without
+    /// the upload.
+    pub async fn verify_permissions(&self, cancel: &CancellationToken) -> PermissionReport {
+        support::verify_permissions(self, cancel).await
+    }
+
+    /// Returns a handle on the same storage for [`Self::healthcheck`], to be created once and
+    /// kept around. It has a single permit, and a client, rate limiter and circuit breaker of its
+    /// own, so that a busy data path neither delays the probes nor is slowed down by them, and an
+    /// open circuit breaker of the data path doesn't fail them without asking the storage.
+    ///
+    /// [`LocalFs`] has nothing to separate, and the [`UnreliableWrapper`] is probed without its
+    /// injected failures.
+    pub fn healthcheck_handle(&self) -> anyhow::Result<Self> {
+        Ok(match self {
+            Self::AwsS3(s) => Self::AwsS3(Arc::new(s.healthcheck_handle()?)),
+            Self::AzureBlob(s) => Self::AzureBlob(Arc::new(s.healthcheck_handle()?)),
+            Self::Mirror(s) => Self::Mirror(Arc::new(MirrorStorage::new(
+                s.primary().healthcheck_handle()?,
+                s.secondary().healthcheck_handle()?,
+                s.mode(),
+            ))),
+            Self::Unreliable(s) => s.inner().healthcheck_handle()?,
+            Self::LocalFs(_) => self.clone(),
+        })
+    }
+
+    /// Checks that the storage is reachable and the credentials are accepted, by listing at
+    /// most one key at the root, within [`HEALTHCHECK_TIMEOUT`].
+    ///
+    /// Meant for status endpoints, on a handle from [`Self::healthcheck_handle`].
+    pub async fn healthcheck(&self, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let list = self.list(
+            None,
+            ListingMode::WithDelimiter,
+            Some(NonZeroU32::MIN),
+            ListOptions::default(),
+            cancel,
+        );
+        match tokio::time::timeout(HEALTHCHECK_TIMEOUT, list).await {
+            Ok(res) => res
+                .map(|_| ())
+                .context("list the root of the remote storage"),
+            Err(_) => Err(anyhow::Error::new(TimeoutOrCancel::Timeout)
+                .context(format!("no response within {HEALTHCHECK_TIMEOUT:?}"))),
+        }
+    }
+
+    /// See [`RemoteStorage::upload`], which this method calls without any [`UploadOptions`].
+    pub async fn upload_storage_object(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        from_size_bytes: usize,
+        to: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.upload(from, from_size_bytes, to, UploadOptions::default(), cancel)
+            .await
+            .with_context(|| {
+                format!("Failed to upload data of length {from_size_bytes} to storage path {to:?}")
+            })
+    }
+
+    /// Compresses `data` in memory and uploads it with the matching content encoding, which
+    /// [`Self::download`] transparently decodes again. Meant for small objects that compress well,
+    /// like index parts.
+    pub async fn upload_compressed(
+        &self,
+        data: Bytes,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+        compression: Compression,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        use tokio::io::AsyncReadExt;
+
+        let mut compressed = Vec::new();
+        match compression {
+            Compression::Gzip => {
+                async_compression::tokio::bufread::GzipEncoder::new(&data[..])
+                    .read_to_end(&mut compressed)
+                    .await
+                    .context("compress upload")?;
+            }
+        }
+
+        let len = compressed.len();
+        let from = futures::stream::once(futures::future::ready(Ok(Bytes::from(compressed))));
+        let options = UploadOptions {
+            metadata,
+            content_encoding: Some(compression),
+            ..Default::default()
+        };
+        self.upload(from, len, to, options, cancel).await
+    }
+
+    /// Uploads many small objects, with up to `concurrency` uploads in flight at a time, and
+    /// yields the result of each upload as it completes, i.e. not necessarily in input order.
+    ///
+    /// The uploads still go through the backend's concurrency limiter, so this can't starve other
+    /// writers beyond what individual uploads would.
+    pub fn upload_many<'a>(
+        &'a self,
+        items: impl Stream<Item = (RemotePath, Bytes, Option<StorageMetadata>)> + 'a,
+        concurrency: usize,
+        cancel: &'a CancellationToken,
+    ) -> impl Stream<Item = (RemotePath, anyhow::Result<()>)> + 'a {
+        use futures::StreamExt;
+
+        items
+            .map(move |(path, data, metadata)| async move {
+                let len = data.len();
+                let from = futures::stream::once(futures::future::ready(Ok(data)));
+                let options = UploadOptions {
+                    metadata,
+                    ..Default::default()
+                };
+                let res = self.upload(from, len, &path, options, cancel).await;
+                (path, res)
+            })
+            .buffer_unordered(concurrency.max(1))
+    }
+
+    /// Downloads the storage object into the `to_path` provided.
+    /// `byte_range` could be specified to dowload only a part of the file, if needed, see
+    /// [`Self::download_byte_range`] for the ranges which fail with [`DownloadError::InvalidRange`].
+    pub async fn download_storage_object(
+        &self,
+        byte_range: Option<(u64, Option<u64>)>,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        match byte_range {
+            Some((start, end)) => self.download_byte_range(from, start, end, cancel).await,
+            None => self.download(from, cancel).await,
+        }
+    }
+
+    /// Like [`Self::download`], but if the download stream fails partway through with an I/O error,
+    /// the download is transparently resumed with [`Self::download_byte_range`] from the last
+    /// successfully yielded offset.  At most `max_resumptions` resumptions are attempted before the
+    /// error is passed on to the caller.
+    ///
+    /// Cancellation and timeout errors are never resumed, so `cancel` and the per-request timeout
+    /// are obeyed just like with [`Self::download`].  If the object changes between resumptions
+    /// (detected via its ETag), the stream fails instead of yielding mixed contents.
+    pub async fn download_resumable(
+        &self,
+        from: &RemotePath,
+        max_resumptions: u32,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        // Resumption offsets are of the stored bytes, so only decode once everything is stitched
+        // back together.
+        let download = self.download_raw(from, None, cancel).await?;
+
+        let storage = self.clone();
+        let (from, cancel) = (from.clone(), cancel.clone());
+        let download_stream = resumable_download_stream(
+            from.clone(),
+            download.etag.clone(),
+            download.download_stream,
+            max_resumptions,
+            move |offset| {
+                let (storage, from, cancel) = (storage.clone(), from.clone(), cancel.clone());
+                async move {
+                    storage
+                        .download_byte_range(&from, offset, None, &cancel)
+                        .await
+                }
+            },
+        );
+
+        // The unfold future holds on to backend request futures, which are not necessarily Sync
+        let download_stream = sync_wrapper::SyncStream::new(download_stream);
+
+        Ok(Download {
+            download_stream: Box::pin(download_stream),
+            ..download
+        }
+        .decoded())
+    }
+
+    /// Like [`Self::download`], but downloads the object as consecutive range requests, with
+    /// enough of them in flight to keep up to `readahead_bytes` buffered ahead of the consumer.
+    /// The ranges are yielded in order, as a single stream. On high latency links, this reads
+    /// large objects sequentially faster than a single request does.
+    ///
+    /// Ranges are [`DOWNLOAD_BUFFERED_RANGE_SIZE`] long, whatever the readahead, and only the
+    /// number of requests in flight follows from `readahead_bytes`. Readahead below a single
+    /// range, including 0, gets one request in flight. If the object changes while it is
+    /// downloaded (detected via its ETag), the stream fails instead of yielding mixed contents.
+    pub async fn download_buffered(
+        &self,
+        from: &RemotePath,
+        readahead_bytes: usize,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_in_ranges(from, DOWNLOAD_BUFFERED_RANGE_SIZE, readahead_bytes, cancel)
+            .await
+    }
+
+    /// [`Self::download_buffered`] with ranges of `range_size` bytes, which tests make small.
+    async fn download_in_ranges(
+        &self,
+        from: &RemotePath,
+        range_size: u64,
+        readahead_bytes: usize,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        use futures::{future::Either, StreamExt, TryStreamExt};
+
+        let max_in_flight = (readahead_bytes as u64 / range_size).max(1) as usize;
+
+        // The first range tells the size of the object
+        let first = match self
+            .download_byte_range(from, 0, Some(range_size), cancel)
+            .await
+        {
+            Ok(first) => first,
+            // S3 and Azure reject any range of an empty object as unsatisfiable, and only an
+            // empty object has no byte at the start. Other errors are returned as they are,
+            // the plain download wouldn't fare any better.
+            Err(DownloadError::InvalidRange(e)) => {
+                tracing::debug!("Falling back to a plain download of {from} after error: {e:#}");
+                return self.download(from, cancel).await;
+            }
+            Err(e) => return Err(e),
+        };
+        let Download {
+            download_stream: first_stream,
+            last_modified,
+            etag,
+            content_length: _,
+            object_size,
+            metadata,
+            content_encoding,
+        } = first;
+
+        let storage = self.clone();
+        let from = from.clone();
+        let cancel = cancel.clone();
+        let expected_etag = etag.clone();
+        let ranges = (1..)
+            .map(move |i| i * range_size)
+            .take_while(move |start| *start < object_size)
+            .map(move |start| {
+                // Read the last range up to EOF, so that it can't come out empty
+                let end = Some(start + range_size).filter(|end| *end < object_size);
+                let (storage, from, cancel, expected_etag) = (
+                    storage.clone(),
+                    from.clone(),
+                    cancel.clone(),
+                    expected_etag.clone(),
+                );
+                Either::Right(async move {
+                    let download = storage
+                        .download_byte_range(&from, start, end, &cancel)
+                        .await
+                        .map_err(std::io::Error::other)?;
+                    if download.etag != expected_etag {
+                        return Err(std::io::Error::other(DownloadError::Other(
+                            anyhow::anyhow!(
+                                "{from} changed while downloading: etag {} != {expected_etag}",
+                                download.etag,
+                            ),
+                        )));
+                    }
+                    download.download_stream.try_collect::<Vec<_>>().await
+                })
+            });
+
+        // Every range is buffered in full, which lets the requests for the following ones
+        // proceed while the consumer reads it.
+        let download_stream = futures::stream::once(futures::future::ready(Either::Left(
+            first_stream.try_collect::<Vec<_>>(),
+        )))
+        .chain(futures::stream::iter(ranges))
+        .buffered(max_in_flight)
+        .map_ok(|chunks| futures::stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>)))
+        .try_flatten();
+
+        // The buffered futures hold on to backend request futures, which are not necessarily Sync
+        let download_stream = sync_wrapper::SyncStream::new(download_stream);
+
+        Ok(Download {
+            download_stream: Box::pin(download_stream),
+            last_modified,
+            etag,
+            content_length: object_size,
+            object_size,
+            metadata,
+            content_encoding,
+        }
+        .decoded())
+    }
+}
+
+/// The stream of [`GenericRemoteStorage::download_resumable`]: yields `stream`, and when it fails
+/// with an I/O error, calls `resume` with the offset of the first byte not yet yielded to continue
+/// from a range download, at most `max_resumptions` times.
+fn resumable_download_stream<R, F>(
+    from: RemotePath,
+    etag: Etag,
+    stream: DownloadStream,
+    max_resumptions: u32,
+    resume: R,
+) -> impl Stream<Item = std::io::Result<Bytes>>
+where
+    R: FnMut(u64) -> F,
+    F: std::future::Future<Output = Result<Download, DownloadError>>,
+{
+    struct ResumeState<R> {
+        from: RemotePath,
+        etag: Etag,
+        stream: DownloadStream,
+        offset: u64,
+        resumptions_left: u32,
+        resume: R,
+    }
+
+    let state = ResumeState {
+        from,
+        etag,
+        stream,
+        offset: 0,
+        resumptions_left: max_resumptions,
+        resume,
+    };
+
+    futures::stream::unfold(Some(state), |state| async move {
+        use futures::StreamExt;
+
+        let mut state = state?;
+        loop {
+            let e = match state.stream.next().await {
+                Some(Ok(bytes)) => {
+                    state.offset += bytes.len() as u64;
+                    return Some((Ok(bytes), Some(state)));
+                }
+                Some(Err(e)) => e,
+                None => return None,
+            };
+
+            let timeout_or_cancel = e
+                .get_ref()
+                .and_then(|x| x.downcast_ref::<DownloadError>())
+                .is_some_and(|x| {
+                    matches!(x, DownloadError::Cancelled(_) | DownloadError::Timeout(_))
+                });
+            if timeout_or_cancel || state.resumptions_left == 0 {
+                return Some((Err(e), None));
+            }
+            state.resumptions_left -= 1;
+
+            tracing::info!(
+                "Resuming download of {} at offset {} after error: {e}",
+                state.from,
+                state.offset
+            );
+            match (state.resume)(state.offset).await {
+                Ok(resumed) if resumed.etag == state.etag => {
+                    state.stream = resumed.download_stream;
+                }
+                Ok(resumed) => {
+                    let e = DownloadError::Other(anyhow::anyhow!(
+                        "{} changed while downloading: etag {} != {}",
+                        state.from,
+                        resumed.etag,
+                        state.etag
+                    ));
+                    return Some((Err(std::io::Error::other(e)), None));
+                }
+                Err(e) => return Some((Err(std::io::Error::other(e)), None)),
+            }
+        }
+    })
+}
+
+/// Extra set of key-value pairs that contain arbitrary metadata about the storage entry.
+/// Immutable, cannot be changed once the file is created.
+///
+/// Object stores treat metadata keys as case-insensitive, and S3 returns them lowercased no
+/// matter how they were sent. So that every backend round-trips the same map, keys are
+/// lowercased when stored: uploading `{"Foo": "Bar"}` downloads as `{"foo": "Bar"}`. Keys which
+/// differ only by case collide, and only one of their values is kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageMetadata(HashMap<String, String>);
+
+impl StorageMetadata {
+    /// The metadata with all keys lowercased, the way backends store and return it.
+    pub(crate) fn normalized(self) -> Self {
+        Self(
+            self.0
+                .into_iter()
+                .map(|(k, v)| (k.to_lowercase(), v))
+                .collect(),
+        )
+    }
+}
+
+/// What [`RemoteStorage::copy`] does with the [`StorageMetadata`] of the source object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum CopyMetadata {
+    /// The copy gets the metadata of the source object, if it has any.
+    #[default]
+    Preserve,
+    /// The copy gets the given metadata, and the source object's metadata is dropped.
+    ///
+    /// On S3, replacing the metadata also drops the source object's content encoding, so don't
+    /// replace the metadata of objects uploaded with [`GenericRemoteStorage::upload_compressed`].
+    Replace(StorageMetadata),
+}
+
+impl<const N: usize> From<[(&str, &str); N]> for StorageMetadata {
+    fn from(arr: [(&str, &str); N]) -> Self {
+        let map: HashMap<String, String> = arr
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Self(map)
+    }
+}
 
 /// External backup storage configuration, enough for creating a client for that storage.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RemoteStorageConfig {
     /// The storage connection configuration.
     pub storage: RemoteStorageKind,
-    /// A common timeout enforced for all requests after concurrency limiter permit has been
+    /// Timeouts enforced for the requests of each kind after concurrency limiter permit has been
     /// acquired.
-    pub timeout: Duration,
+    pub timeouts: PerKindTimeouts,
 }
 
-/// A kind of a remote storage to connect to, with its connection configuration.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum RemoteStorageKind {
-    /// Storage based on local file system.
-    /// Specify a root folder to place all stored files into.
-    LocalFs(Utf8PathBuf),
-    /// AWS S3 based storage, storing all files in the S3 bucket
-    /// specified by the config
-    AwsS3(S3Config),
-    /// Azure Blob based storage, storing all files in the container
-    /// specified by the config
-    AzureContainer(AzureConfig),
-}
+/// Per-request timeouts for each kind of request. Large listings or uploads legitimately take
+/// longer than small downloads, and a timeout long enough for the slowest kind would let stuck
+/// requests of the other kinds hang for just as long.
+///
+/// Configured with `timeout`, the `default` for all kinds, and a `timeouts` table overriding
+/// single kinds, e.g. `timeouts = { list = '10m' }`. Time travel recovery uses the `default`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerKindTimeouts {
+    pub default: Duration,
+    pub get: Duration,
+    pub put: Duration,
+    pub list: Duration,
+    pub delete: Duration,
+    pub copy: Duration,
+}
+
+impl PerKindTimeouts {
+    pub(crate) fn for_kind(&self, kind: RequestKind) -> Duration {
+        match kind {
+            RequestKind::Get => self.get,
+            RequestKind::Put => self.put,
+            RequestKind::List => self.list,
+            RequestKind::Delete => self.delete,
+            RequestKind::Copy => self.copy,
+            RequestKind::TimeTravel => self.default,
+        }
+    }
+
+    /// The longest of the timeouts.
+    pub fn max(&self) -> Duration {
+        [
+            self.default,
+            self.get,
+            self.put,
+            self.list,
+            self.delete,
+            self.copy,
+        ]
+        .into_iter()
+        .max()
+        .expect("not empty")
+    }
+}
+
+/// The same timeout for all kinds of requests.
+impl From<Duration> for PerKindTimeouts {
+    fn from(timeout: Duration) -> Self {
+        Self {
+            default: timeout,
+            get: timeout,
+            put: timeout,
+            list: timeout,
+            delete: timeout,
+            copy: timeout,
+        }
+    }
+}
+
+/// A kind of a remote storage to connect to, with its connection configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteStorageKind {
+    /// Storage based on local file system.
+    LocalFs {
+        /// Root folder to place all stored files into.
+        local_path: Utf8PathBuf,
+        /// Fsync uploads before acknowledging them, see [`LocalFs::new`]. Production single node
+        /// deployments should enable this.
+        sync_on_upload: bool,
+    },
+    /// AWS S3 based storage, storing all files in the S3 bucket
+    /// specified by the config
+    AwsS3(S3Config),
+    /// Azure Blob based storage, storing all files in the container
+    /// specified by the config
+    AzureContainer(AzureConfig),
+    /// Two storages written at once, see [`MirrorStorage`].
+    Mirror(MirrorConfig),
+}
+
+impl RemoteStorageKind {
+    fn set_app_name(&mut self, app_name: &str) {
+        match self {
+            RemoteStorageKind::LocalFs { .. } => {}
+            RemoteStorageKind::AwsS3(s3_config) => s3_config.app_name = Some(app_name.to_owned()),
+            RemoteStorageKind::AzureContainer(azure_config) => {
+                azure_config.app_name = Some(app_name.to_owned())
+            }
+            RemoteStorageKind::Mirror(mirror) => {
+                mirror.primary.set_app_name(app_name);
+                mirror.secondary.set_app_name(app_name);
+            }
+        }
+    }
+}
+
+/// The storages of a [`MirrorStorage`], which can't be mirrors themselves, and how to treat
+/// failures of the secondary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorConfig {
+    pub primary: Box<RemoteStorageKind>,
+    pub secondary: Box<RemoteStorageKind>,
+    pub mode: MirrorMode,
+}
+
+/// AWS S3 bucket coordinates and access credentials to manage the bucket contents (read and write).
+#[derive(Clone, PartialEq, Eq)]
+pub struct S3Config {
+    /// Name of the bucket to connect to.
+    pub bucket_name: String,
+    /// The region where the bucket is located at.
+    pub bucket_region: String,
+    /// A "subfolder" in the bucket, to use the same bucket separately by multiple remote storage users at once.
+    pub prefix_in_bucket: Option<String>,
+    /// A base URL to send S3 requests to.
+    /// By default, the endpoint is derived from a region name, assuming it's
+    /// an AWS S3 region name, erroring on wrong region name.
+    /// Endpoint provides a way to support other S3 flavors and their regions.
+    ///
+    /// Example: `http://127.0.0.1:5000`
+    pub endpoint: Option<String>,
+    /// AWS S3 has various limits on its API calls, we need not to exceed those.
+    /// See [`DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT`] for more details.
+    pub concurrency_limit: NonZeroUsize,
+    pub max_keys_per_list_response: Option<i32>,
+    pub upload_storage_class: Option<StorageClass>,
+    /// Maximum number of idle connections kept in the HTTP connection pool.
+    /// Defaults to twice the `concurrency_limit`, as reads and writes are limited separately,
+    /// so that any burst of requests allowed by the concurrency limiter can reuse connections.
+    pub max_idle_connections: Option<NonZeroUsize>,
+    /// How long idle connections are kept in the pool.
+    /// Defaults to [`DEFAULT_REMOTE_STORAGE_S3_CONNECTION_IDLE_TIMEOUT`].
+    pub connection_idle_timeout: Option<Duration>,
+    /// How long the AWS SDK waits for a connection to be established.
+    /// Defaults to [`DEFAULT_REMOTE_STORAGE_S3_CONNECT_TIMEOUT`].
+    pub connect_timeout: Option<Duration>,
+    /// How long a single attempt of an SDK operation may take. Defaults to the remote storage
+    /// `timeout`, which bounds all attempts of a request together, so a larger value has no
+    /// effect.
+    pub operation_attempt_timeout: Option<Duration>,
+    /// How many attempts the AWS SDK makes for each request before returning the error to our
+    /// own retry loop. Defaults to 1, i.e. retries are only done by us, and the SDK retry config
+    /// only serves to enable its adaptive rate limiting on throttling responses.
+    pub sdk_max_attempts: Option<NonZeroU32>,
+    /// Name of the AWS profile to take the credentials from, from the shared config and
+    /// credentials files. If unset, the usual chain of credential sources is tried, which only
+    /// picks up a profile from the `AWS_PROFILE` environment variable, shared by all storages of
+    /// the process.
+    pub profile_name: Option<String>,
+    /// Requests per second allowed for each kind of request, on top of the `concurrency_limit`.
+    pub rps_limits: RpsLimits,
+    /// Account ID expected to own the bucket, sent with every request as
+    /// `x-amz-expected-bucket-owner`. Requests then fail with `403 Forbidden` instead of going
+    /// to a bucket of the same name in another account, e.g. after a typo in the bucket name or
+    /// after the bucket was deleted and the name taken over.
+    pub expected_bucket_owner: Option<String>,
+    /// Send `x-amz-request-payer: requester` with every request, to access requester-pays
+    /// buckets, which reject requests without it with `403 Forbidden`. The requests are then
+    /// billed to our account instead of the bucket owner's.
+    pub requester_pays: bool,
+    /// Requests taking longer than this are logged with a warning naming the request and its
+    /// S3 request ID, to tell which objects were slow to serve. For downloads, this is the time
+    /// until the response headers arrived, not until the body was read.
+    /// Defaults to [`DEFAULT_REMOTE_STORAGE_S3_SLOW_REQUEST_THRESHOLD`].
+    pub slow_request_threshold: Option<Duration>,
+    /// Appended to the application name in the `User-Agent` header of every request. Providers
+    /// use it to identify clients, e.g. to tell the traffic of a deployment apart during
+    /// incidents.
+    pub user_agent_suffix: Option<String>,
+    /// The program using the storage and its version, which starts the application name in the
+    /// `User-Agent` header instead of just this crate's name. Not part of the TOML config, but
+    /// set by the binaries with [`RemoteStorageConfig::set_app_name`].
+    pub app_name: Option<String>,
+}
+
+impl Debug for S3Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Config")
+            .field("bucket_name", &self.bucket_name)
+            .field("bucket_region", &self.bucket_region)
+            .field("prefix_in_bucket", &self.prefix_in_bucket)
+            .field("concurrency_limit", &self.concurrency_limit)
+            .field(
+                "max_keys_per_list_response",
+                &self.max_keys_per_list_response,
+            )
+            .field("max_idle_connections", &self.max_idle_connections)
+            .field("connection_idle_timeout", &self.connection_idle_timeout)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("operation_attempt_timeout", &self.operation_attempt_timeout)
+            .field("sdk_max_attempts", &self.sdk_max_attempts)
+            .field("profile_name", &self.profile_name)
+            .field("rps_limits", &self.rps_limits)
+            .field("expected_bucket_owner", &self.expected_bucket_owner)
+            .field("requester_pays", &self.requester_pays)
+            .field("slow_request_threshold", &self.slow_request_threshold)
+            .field("user_agent_suffix", &self.user_agent_suffix)
+            .field("app_name", &self.app_name)
+            .finish()
+    }
+}
+
+/// Azure  bucket coordinates and access credentials to manage the bucket contents (read and write).
+#[derive(Clone, PartialEq, Eq)]
+pub struct AzureConfig {
+    /// Name of the container to connect to.
+    pub container_name: String,
+    /// The region where the bucket is located at.
+    pub container_region: String,
+    /// A "subfolder" in the container, to use the same container separately by multiple remote storage users at once.
+    pub prefix_in_container: Option<String>,
+    /// Azure has various limits on its API calls, we need not to exceed those.
+    /// See [`DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT`] for more details.
+    pub concurrency_limit: NonZeroUsize,
+    pub max_keys_per_list_response: Option<i32>,
+    /// How to authenticate against the storage account.
+    pub auth_method: AzureAuthMethod,
+    /// Uploads larger than this are split into blocks of up to this many bytes, which are
+    /// staged separately and then committed. Smaller uploads are a single request. A blob can
+    /// have at most 50 000 blocks, which bounds the size of uploads.
+    pub max_block_size: NonZeroUsize,
+    /// How many blocks of a single upload are staged concurrently. Each one is buffered in
+    /// memory while it is staged, so fewer are if they would take more than 256 MiB together.
+    pub max_concurrency_per_upload: NonZeroUsize,
+    /// Requests per second allowed for each kind of request, on top of the `concurrency_limit`.
+    pub rps_limits: RpsLimits,
+    /// Name of the storage account the container belongs to.
+    /// Defaults to the `AZURE_STORAGE_ACCOUNT` environment variable.
+    pub storage_account: Option<String>,
+    /// Appended to the application id in the `User-Agent` header of every request, see
+    /// [`S3Config::user_agent_suffix`].
+    pub user_agent_suffix: Option<String>,
+    /// See [`S3Config::app_name`].
+    pub app_name: Option<String>,
+}
+
+/// Upper bounds on the requests per second sent to the storage, per kind of request.
+/// Unset kinds are not rate limited, which is the default.
+///
+/// The concurrency limit alone does not prevent exceeding the request rate limits of the
+/// storage, e.g. AWS S3 throttles above 3500 PUT/COPY/POST/DELETE and 5500 GET/HEAD requests per
+/// second per prefix, when many small requests complete quickly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RpsLimits {
+    pub get: Option<NonZeroU32>,
+    pub put: Option<NonZeroU32>,
+    pub delete: Option<NonZeroU32>,
+    pub list: Option<NonZeroU32>,
+    pub copy: Option<NonZeroU32>,
+    pub time_travel: Option<NonZeroU32>,
+}
+
+/// Credentials used to access an Azure storage account.
+///
+/// Everything but [`AzureAuthMethod::DefaultChain`] uses exactly the configured method and
+/// fails instead of falling back to other sources of credentials.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub enum AzureAuthMethod {
+    /// Use `AZURE_STORAGE_ACCESS_KEY` if set, otherwise the default token credential chain.
+    #[default]
+    DefaultChain,
+    /// Use a managed identity assigned to the VM, e.g. an AKS node pool's kubelet identity, with
+    /// tokens from the instance metadata service. Without a `client_id`, the system-assigned
+    /// identity is used.
+    ///
+    /// AKS workload identity federates a Kubernetes service account instead, without going
+    /// through the instance metadata service: use [`AzureAuthMethod::DefaultChain`] for it.
+    ManagedIdentity { client_id: Option<String> },
+    /// Shared key of the storage account.
+    StorageKey(String),
+    /// Shared access signature token.
+    Sas(String),
+}
+
+impl Debug for AzureAuthMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DefaultChain => f.write_str("DefaultChain"),
+            Self::ManagedIdentity { client_id } => f
+                .debug_struct("ManagedIdentity")
+                .field("client_id", client_id)
+                .finish(),
+            Self::StorageKey(_) => f.write_str("StorageKey(<redacted>)"),
+            Self::Sas(_) => f.write_str("Sas(<redacted>)"),
+        }
+    }
+}
+
+impl Debug for AzureConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AzureConfig")
+            .field("bucket_name", &self.container_name)
+            .field("bucket_region", &self.container_region)
+            .field("prefix_in_bucket", &self.prefix_in_container)
+            .field("concurrency_limit", &self.concurrency_limit)
+            .field(
+                "max_keys_per_list_response",
+                &self.max_keys_per_list_response,
+            )
+            .field("auth_method", &self.auth_method)
+            .field("max_block_size", &self.max_block_size)
+            .field(
+                "max_concurrency_per_upload",
+                &self.max_concurrency_per_upload,
+            )
+            .field("rps_limits", &self.rps_limits)
+            .field("storage_account", &self.storage_account)
+            .field("user_agent_suffix", &self.user_agent_suffix)
+            .field("app_name", &self.app_name)
+            .finish()
+    }
+}
+
+impl RemoteStorageConfig {
+    pub const DEFAULT_TIMEOUT: Duration = std::time::Duration::from_secs(120);
+
+    /// Names the program using the storage in the `User-Agent` header of its requests, see
+    /// [`S3Config::app_name`]: `name`, followed by the abbreviated commit of `git_version`, the
+    /// `GIT_VERSION` of the binary.
+    pub fn set_app_name(&mut self, name: &str, git_version: &str) {
+        let commit = git_version.rsplit(':').next().unwrap_or(git_version);
+        let commit: String = commit.chars().take(12).collect();
+        self.storage.set_app_name(&format!("{name}-{commit}"));
+    }
+
+    pub fn from_toml(toml: &toml_edit::Item) -> anyhow::Result<Option<RemoteStorageConfig>> {
+        let local_path = toml.get("local_path");
+        let bucket_name = toml.get("bucket_name");
+        let bucket_region = toml.get("bucket_region");
+        let container_name = toml.get("container_name");
+        let container_region = toml.get("container_region");
+        let mirror = toml.get("mirror");
+
+        if mirror.is_some()
+            && (local_path.is_some() || bucket_name.is_some() || container_name.is_some())
+        {
+            bail!("'mirror' is mutually exclusive with 'local_path', 'bucket_name' and 'container_name'");
+        }
+
+        let use_azure = container_name.is_some() && container_region.is_some();
+
+        let default_concurrency_limit = if use_azure {
+            DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT
+        } else {
+            DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT
+        };
+        let concurrency_limit = NonZeroUsize::new(
+            parse_optional_integer("concurrency_limit", toml)?.unwrap_or(default_concurrency_limit),
+        )
+        .context("Failed to parse 'concurrency_limit' as a positive integer")?;
+
+        let max_keys_per_list_response =
+            parse_optional_integer::<i32, _>("max_keys_per_list_response", toml)
+                .context("Failed to parse 'max_keys_per_list_response' as a positive integer")?
+                .or(DEFAULT_MAX_KEYS_PER_LIST_RESPONSE);
+
+        let endpoint = toml
+            .get("endpoint")
+            .map(|endpoint| parse_toml_string("endpoint", endpoint))
+            .transpose()?;
+
+        let timeout = toml
+            .get("timeout")
+            .map(|timeout| {
+                timeout
+                    .as_str()
+                    .ok_or_else(|| anyhow::Error::msg("timeout was not a string"))
+            })
+            .transpose()
+            .and_then(|timeout| {
+                timeout
+                    .map(humantime::parse_duration)
+                    .transpose()
+                    .map_err(anyhow::Error::new)
+            })
+            .context("parse timeout")?
+            .unwrap_or(Self::DEFAULT_TIMEOUT);
+
+        if timeout < Duration::from_secs(1) {
+            bail!("timeout was specified as {timeout:?} which is too low");
+        }
+        let timeouts = parse_per_kind_timeouts(toml, timeout)?;
+
+        let rps_limits = parse_rps_limits(toml)?;
+
+        let storage = match (
+            local_path,
+            bucket_name,
+            bucket_region,
+            container_name,
+            container_region,
+        ) {
+            // no 'local_path' nor 'bucket_name' options are provided, consider this remote storage disabled
+            (None, None, None, None, None) => match mirror {
+                Some(mirror) => RemoteStorageKind::Mirror(parse_mirror_config(mirror)?),
+                None => return Ok(None),
+            },
+            (_, Some(_), None, ..) => {
+                bail!("'bucket_region' option is mandatory if 'bucket_name' is given ")
+            }
+            (_, None, Some(_), ..) => {
+                bail!("'bucket_name' option is mandatory if 'bucket_region' is given ")
+            }
+            (None, Some(bucket_name), Some(bucket_region), ..) => {
+                RemoteStorageKind::AwsS3(S3Config {
+                    bucket_name: parse_toml_string("bucket_name", bucket_name)?,
+                    bucket_region: parse_toml_string("bucket_region", bucket_region)?,
+                    prefix_in_bucket: toml
+                        .get("prefix_in_bucket")
+                        .map(|prefix_in_bucket| {
+                            parse_toml_string("prefix_in_bucket", prefix_in_bucket)
+                        })
+                        .transpose()?,
+                    endpoint,
+                    concurrency_limit,
+                    max_keys_per_list_response,
+                    upload_storage_class: toml
+                        .get("upload_storage_class")
+                        .map(|prefix_in_bucket| -> anyhow::Result<_> {
+                            let s = parse_toml_string("upload_storage_class", prefix_in_bucket)?;
+                            let storage_class = StorageClass::from_str(&s).expect("infallible");
+                            #[allow(deprecated)]
+                            if matches!(storage_class, StorageClass::Unknown(_)) {
+                                bail!("Specified storage class unknown to SDK: '{s}'. Allowed values: {:?}", StorageClass::values());
+                            }
+                            Ok(storage_class)
+                        })
+                        .transpose()?,
+                    max_idle_connections: parse_optional_integer("max_idle_connections", toml)?
+                        .map(NonZeroUsize::new)
+                        .map(|n| n.context("'max_idle_connections' must be a positive integer"))
+                        .transpose()?,
+                    connection_idle_timeout: parse_optional_duration(
+                        "connection_idle_timeout",
+                        toml,
+                    )?,
+                    connect_timeout: parse_optional_duration("connect_timeout", toml)?,
+                    operation_attempt_timeout: parse_optional_duration(
+                        "operation_attempt_timeout",
+                        toml,
+                    )?,
+                    sdk_max_attempts: parse_optional_integer("sdk_max_attempts", toml)?
+                        .map(NonZeroU32::new)
+                        .map(|n| n.context("'sdk_max_attempts' must be a positive integer"))
+                        .transpose()?,
+                    profile_name: toml
+                        .get("profile_name")
+                        .map(|profile_name| parse_toml_string("profile_name", profile_name))
+                        .transpose()?,
+                    rps_limits,
+                    expected_bucket_owner: toml
+                        .get("expected_bucket_owner")
+                        .map(|owner| parse_toml_string("expected_bucket_owner", owner))
+                        .transpose()?,
+                    requester_pays: toml
+                        .get("requester_pays")
+                        .map(|requester_pays| {
+                            requester_pays
+                                .as_bool()
+                                .context("Failed to parse 'requester_pays' as a boolean")
+                        })
+                        .transpose()?
+                        .unwrap_or(false),
+                    slow_request_threshold: parse_optional_duration(
+                        "slow_request_threshold",
+                        toml,
+                    )?,
+                    user_agent_suffix: parse_user_agent_suffix(toml)?,
+                    app_name: None,
+                })
+            }
+            (_, _, _, Some(_), None) => {
+                bail!("'container_name' option is mandatory if 'container_region' is given ")
+            }
+            (_, _, _, None, Some(_)) => {
+                bail!("'container_name' option is mandatory if 'container_region' is given ")
+            }
+            (None, None, None, Some(container_name), Some(container_region)) => {
+                RemoteStorageKind::AzureContainer(AzureConfig {
+                    container_name: parse_toml_string("container_name", container_name)?,
+                    container_region: parse_toml_string("container_region", container_region)?,
+                    prefix_in_container: parse_prefix_in_container(toml)?,
+                    concurrency_limit,
+                    max_keys_per_list_response,
+                    auth_method: parse_azure_auth_method(toml)?,
+                    max_block_size: NonZeroUsize::new(
+                        parse_optional_integer("max_block_size", toml)?
+                            .unwrap_or(DEFAULT_AZURE_MAX_BLOCK_SIZE),
+                    )
+                    .context("'max_block_size' must be a positive integer")?,
+                    max_concurrency_per_upload: NonZeroUsize::new(
+                        parse_optional_integer("max_concurrency_per_upload", toml)?
+                            .unwrap_or(DEFAULT_AZURE_MAX_CONCURRENCY_PER_UPLOAD),
+                    )
+                    .context("'max_concurrency_per_upload' must be a positive integer")?,
+                    rps_limits,
+                    storage_account: toml
+                        .get("storage_account")
+                        .map(|storage_account| {
+                            parse_toml_string("storage_account", storage_account)
+                        })
+                        .transpose()?,
+                    user_agent_suffix: parse_user_agent_suffix(toml)?,
+                    app_name: None,
+                })
+            }
+            (Some(local_path), None, None, None, None) => RemoteStorageKind::LocalFs {
+                local_path: Utf8PathBuf::from(parse_toml_string("local_path", local_path)?),
+                sync_on_upload: toml
+                    .get("sync_on_upload")
+                    .map(|sync| {
+                        sync.as_bool()
+                            .context("Failed to parse 'sync_on_upload' as a boolean")
+                    })
+                    .transpose()?
+                    .unwrap_or(false),
+            },
+            (Some(_), Some(_), ..) => {
+                bail!("'local_path' and 'bucket_name' are mutually exclusive")
+            }
+            (Some(_), _, _, Some(_), Some(_)) => {
+                bail!("local_path and 'container_name' are mutually exclusive")
+            }
+        };
+
+        Ok(Some(RemoteStorageConfig { storage, timeouts }))
+    }
+
+    /// Builds a config from a URL, for command line tools and tests, which would otherwise need
+    /// a TOML document or several arguments per storage:
+    ///
+    /// * `s3://bucket/prefix?region=us-east-1`, also accepting `endpoint` and `profile`
+    /// * `azure://account/container/prefix`, also accepting `region`
+    /// * `file:///path`
+    ///
+    /// All schemes accept a `timeout`, e.g. `?timeout=30s`. Options which are not part of the URL
+    /// have the same defaults as in [`RemoteStorageConfig::from_toml`].
+    pub fn from_url(url: &str) -> anyhow::Result<RemoteStorageConfig> {
+        let url = url::Url::parse(url).with_context(|| format!("Failed to parse URL '{url}'"))?;
+
+        let mut params = HashMap::new();
+        for (name, value) in url.query_pairs() {
+            if params.insert(name.to_string(), value.to_string()).is_some() {
+                bail!("query parameter '{name}' is given more than once");
+            }
+        }
+        let mut param = |name: &str| params.remove(name);
+
+        let timeout = param("timeout")
+            .map(|timeout| humantime::parse_duration(&timeout))
+            .transpose()
+            .context("parse timeout")?
+            .unwrap_or(Self::DEFAULT_TIMEOUT);
+        if timeout < Duration::from_secs(1) {
+            bail!("timeout was specified as {timeout:?} which is too low");
+        }
+
+        let host = || {
+            url.host_str()
+                .filter(|host| !host.is_empty())
+                .with_context(|| format!("URL '{url}' has no bucket, container or account"))
+        };
+        // The path without the leading slash, which is empty if there is no prefix
+        let path = urlencoding::decode(url.path().trim_start_matches('/'))
+            .context("URL path is not valid UTF-8")?
+            .into_owned();
+        let non_empty = |s: &str| (!s.is_empty()).then(|| s.to_owned());
+
+        let storage = match url.scheme() {
+            "s3" => RemoteStorageKind::AwsS3(S3Config {
+                bucket_name: host()?.to_owned(),
+                bucket_region: param("region")
+                    .context("'region' query parameter is mandatory for s3 URLs")?,
+                prefix_in_bucket: non_empty(&path),
+                endpoint: param("endpoint"),
+                concurrency_limit: NonZeroUsize::new(DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT)
+                    .unwrap(),
+                max_keys_per_list_response: DEFAULT_MAX_KEYS_PER_LIST_RESPONSE,
+                upload_storage_class: None,
+                max_idle_connections: None,
+                connection_idle_timeout: None,
+                connect_timeout: None,
+                operation_attempt_timeout: None,
+                sdk_max_attempts: None,
+                profile_name: param("profile"),
+                rps_limits: RpsLimits::default(),
+                expected_bucket_owner: None,
+                requester_pays: false,
+                slow_request_threshold: None,
+                user_agent_suffix: None,
+                app_name: None,
+            }),
+            "azure" => {
+                let (container_name, prefix) = path.split_once('/').unwrap_or((&path, ""));
+                if container_name.is_empty() {
+                    bail!("azure URLs must be of the form azure://account/container/prefix");
+                }
+                RemoteStorageKind::AzureContainer(AzureConfig {
+                    container_name: container_name.to_owned(),
+                    container_region: param("region").unwrap_or_default(),
+                    prefix_in_container: non_empty(prefix),
+                    concurrency_limit: NonZeroUsize::new(
+                        DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT,
+                    )
+                    .unwrap(),
+                    max_keys_per_list_response: DEFAULT_MAX_KEYS_PER_LIST_RESPONSE,
+                    auth_method: AzureAuthMethod::DefaultChain,
+                    max_block_size: NonZeroUsize::new(DEFAULT_AZURE_MAX_BLOCK_SIZE).unwrap(),
+                    max_concurrency_per_upload: NonZeroUsize::new(
+                        DEFAULT_AZURE_MAX_CONCURRENCY_PER_UPLOAD,
+                    )
+                    .unwrap(),
+                    rps_limits: RpsLimits::default(),
+                    storage_account: Some(host()?.to_owned()),
+                    user_agent_suffix: None,
+                    app_name: None,
+                })
+            }
+            "file" => {
+                let local_path = url
+                    .to_file_path()
+                    .map_err(|()| anyhow::anyhow!("URL '{url}' is not a valid local path"))?;
+                RemoteStorageKind::LocalFs {
+                    local_path: Utf8PathBuf::try_from(local_path)
+                        .context("local path is not valid UTF-8")?,
+                    sync_on_upload: false,
+                }
+            }
+            other => {
+                bail!("unknown remote storage URL scheme '{other}', expected s3, azure or file")
+            }
+        };
+
+        if let Some(name) = params.keys().next() {
+            bail!("unknown query parameter '{name}' for {} URLs", url.scheme());
+        }
+
+        Ok(RemoteStorageConfig {
+            storage,
+            timeouts: timeout.into(),
+        })
+    }
+
+    /// The config as JSON, keyed like the TOML it is parsed from, for support bundles and debug
+    /// endpoints. Azure storage keys and SAS tokens are replaced by `"***"`. The S3 config holds
+    /// no credentials, those come from the environment or the named profile.
+    ///
+    /// Defaults filled in while parsing show up with their values, unset options as `null`.
+    pub fn redacted_config(&self) -> serde_json::Value {
+        const REDACTED: &str = "***";
+        let duration = |d: Option<Duration>| d.map(|d| humantime::format_duration(d).to_string());
+        let rps_limits = |limits: &RpsLimits| {
+            serde_json::json!({
+                "get": limits.get,
+                "put": limits.put,
+                "delete": limits.delete,
+                "list": limits.list,
+                "copy": limits.copy,
+                "time_travel": limits.time_travel,
+            })
+        };
+
+        let mut config = match &self.storage {
+            RemoteStorageKind::LocalFs {
+                local_path,
+                sync_on_upload,
+            } => serde_json::json!({
+                "local_path": local_path.as_str(),
+                "sync_on_upload": sync_on_upload,
+            }),
+            RemoteStorageKind::AwsS3(s3) => serde_json::json!({
+                "bucket_name": s3.bucket_name,
+                "bucket_region": s3.bucket_region,
+                "prefix_in_bucket": s3.prefix_in_bucket,
+                "endpoint": s3.endpoint,
+                "concurrency_limit": s3.concurrency_limit,
+                "max_keys_per_list_response": s3.max_keys_per_list_response,
+                "upload_storage_class": s3.upload_storage_class.as_ref().map(StorageClass::as_str),
+                "max_idle_connections": s3.max_idle_connections,
+                "connection_idle_timeout": duration(s3.connection_idle_timeout),
+                "connect_timeout": duration(s3.connect_timeout),
+                "operation_attempt_timeout": duration(s3.operation_attempt_timeout),
+                "sdk_max_attempts": s3.sdk_max_attempts,
+                "profile_name": s3.profile_name,
+                "rps_limits": rps_limits(&s3.rps_limits),
+                "expected_bucket_owner": s3.expected_bucket_owner,
+                "requester_pays": s3.requester_pays,
+                "slow_request_threshold": duration(s3.slow_request_threshold),
+                "user_agent_suffix": s3.user_agent_suffix,
+                "app_name": s3.app_name,
+            }),
+            RemoteStorageKind::AzureContainer(azure) => {
+                let mut config = serde_json::json!({
+                    "container_name": azure.container_name,
+                    "container_region": azure.container_region,
+                    "prefix_in_container": azure.prefix_in_container,
+                    "concurrency_limit": azure.concurrency_limit,
+                    "max_keys_per_list_response": azure.max_keys_per_list_response,
+                    "max_block_size": azure.max_block_size,
+                    "max_concurrency_per_upload": azure.max_concurrency_per_upload,
+                    "rps_limits": rps_limits(&azure.rps_limits),
+                    "storage_account": azure.storage_account,
+                    "user_agent_suffix": azure.user_agent_suffix,
+                    "app_name": azure.app_name,
+                });
+                let (auth_method, credential) = match &azure.auth_method {
+                    AzureAuthMethod::DefaultChain => ("default", None),
+                    AzureAuthMethod::ManagedIdentity { client_id } => (
+                        "managed_identity",
+                        Some(("managed_identity_client_id", client_id.as_deref())),
+                    ),
+                    AzureAuthMethod::StorageKey(_) => {
+                        ("storage_key", Some(("storage_access_key", Some(REDACTED))))
+                    }
+                    AzureAuthMethod::Sas(_) => ("sas", Some(("sas_token", Some(REDACTED)))),
+                };
+                config["auth_method"] = auth_method.into();
+                if let Some((name, value)) = credential {
+                    config[name] = value.into();
+                }
+                config
+            }
+            RemoteStorageKind::Mirror(mirror) => {
+                // The timeouts are the same for both, and shown once below
+                let mirrored = |storage: &RemoteStorageKind| {
+                    let mut config = RemoteStorageConfig {
+                        storage: storage.clone(),
+                        timeouts: self.timeouts,
+                    }
+                    .redacted_config();
+                    if let Some(config) = config.as_object_mut() {
+                        config.remove("timeout");
+                        config.remove("timeouts");
+                    }
+                    config
+                };
+                serde_json::json!({
+                    "mirror": {
+                        "primary": mirrored(&mirror.primary),
+                        "secondary": mirrored(&mirror.secondary),
+                        "mode": match mirror.mode {
+                            MirrorMode::Strict => "strict",
+                            MirrorMode::BestEffort => "best_effort",
+                        },
+                    },
+                })
+            }
+        };
+
+        let timeouts = &self.timeouts;
+        config["timeout"] = duration(Some(timeouts.default)).into();
+        config["timeouts"] = serde_json::json!({
+            "get": duration(Some(timeouts.get)),
+            "put": duration(Some(timeouts.put)),
+            "list": duration(Some(timeouts.list)),
+            "delete": duration(Some(timeouts.delete)),
+            "copy": duration(Some(timeouts.copy)),
+        });
+        config
+    }
+}
+
+// Helper functions to parse a toml Item
+fn parse_optional_integer<I, E>(name: &str, item: &toml_edit::Item) -> anyhow::Result<Option<I>>
+where
+    I: TryFrom<i64, Error = E>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let toml_integer = match item.get(name) {
+        Some(item) => item
+            .as_integer()
+            .with_context(|| format!("configure option {name} is not an integer"))?,
+        None => return Ok(None),
+    };
+
+    I::try_from(toml_integer)
+        .map(Some)
+        .with_context(|| format!("configure option {name} is too large"))
+}
+
+fn parse_azure_auth_method(toml: &toml_edit::Item) -> anyhow::Result<AzureAuthMethod> {
+    let Some(auth_method) = toml.get("auth_method") else {
+        return Ok(AzureAuthMethod::DefaultChain);
+    };
+    let required = |name: &str| -> anyhow::Result<String> {
+        let item = toml
+            .get(name)
+            .with_context(|| format!("'{name}' option is mandatory for the chosen auth_method"))?;
+        parse_toml_string(name, item)
+    };
+    Ok(match parse_toml_string("auth_method", auth_method)?.as_str() {
+        "default" => AzureAuthMethod::DefaultChain,
+        "managed_identity" => AzureAuthMethod::ManagedIdentity {
+            client_id: toml
+                .get("managed_identity_client_id")
+                .map(|item| parse_toml_string("managed_identity_client_id", item))
+                .transpose()?,
+        },
+        "storage_key" => AzureAuthMethod::StorageKey(required("storage_access_key")?),
+        "sas" => AzureAuthMethod::Sas(required("sas_token")?),
+        other => bail!(
+            "unknown auth_method '{other}', expected one of: default, managed_identity, storage_key, sas"
+        ),
+    })
+}
+
+/// Parses `prefix_in_container`. A leading separator is deprecated: Azure keeps it in the blob
+/// names for such a prefix, unlike S3 does for `prefix_in_bucket`.
+fn parse_prefix_in_container(toml: &toml_edit::Item) -> anyhow::Result<Option<String>> {
+    let Some(prefix) = toml.get("prefix_in_container") else {
+        return Ok(None);
+    };
+    let prefix = parse_toml_string("prefix_in_container", prefix)?;
+    if prefix.starts_with(REMOTE_STORAGE_PREFIX_SEPARATOR) {
+        tracing::warn!(
+            "'prefix_in_container' starting with '{REMOTE_STORAGE_PREFIX_SEPARATOR}' is deprecated, got '{prefix}': \
+             move the blobs below '{}' and drop the leading separator",
+            prefix.trim_matches(REMOTE_STORAGE_PREFIX_SEPARATOR)
+        );
+    }
+    Ok(Some(prefix))
+}
+
+fn parse_user_agent_suffix(toml: &toml_edit::Item) -> anyhow::Result<Option<String>> {
+    let Some(suffix) = toml.get("user_agent_suffix") else {
+        return Ok(None);
+    };
+    let suffix = parse_toml_string("user_agent_suffix", suffix)?;
+    if suffix.is_empty() || !suffix.chars().all(is_user_agent_char) {
+        bail!("'user_agent_suffix' must be a non-empty HTTP token, without spaces, '/' or ':', got '{suffix}'");
+    }
+    Ok(Some(suffix))
+}
+
+/// Parses the `mirror` table, whose `primary` and `secondary` are storage configs of their own.
+/// Their timeouts are ignored, the ones next to `mirror` apply to both.
+fn parse_mirror_config(mirror: &toml_edit::Item) -> anyhow::Result<MirrorConfig> {
+    if !mirror.is_table_like() {
+        bail!("configure option mirror is not a table");
+    }
+    let storage = |name: &str| -> anyhow::Result<Box<RemoteStorageKind>> {
+        let item = mirror
+            .get(name)
+            .with_context(|| format!("'mirror.{name}' is missing"))?;
+        let config = RemoteStorageConfig::from_toml(item)
+            .with_context(|| format!("parse 'mirror.{name}'"))?
+            .with_context(|| format!("'mirror.{name}' configures no storage"))?;
+        if matches!(config.storage, RemoteStorageKind::Mirror(_)) {
+            bail!("'mirror.{name}' can't be a mirror itself");
+        }
+        Ok(Box::new(config.storage))
+    };
+    let mode = match mirror
+        .get("mode")
+        .map(|mode| parse_toml_string("mode", mode))
+        .transpose()?
+        .as_deref()
+    {
+        None | Some("strict") => MirrorMode::Strict,
+        Some("best_effort") => MirrorMode::BestEffort,
+        Some(other) => {
+            bail!("unknown 'mirror.mode' '{other}', expected one of: strict, best_effort")
+        }
+    };
+    Ok(MirrorConfig {
+        primary: storage("primary")?,
+        secondary: storage("secondary")?,
+        mode,
+    })
+}
+
+fn parse_rps_limits(toml: &toml_edit::Item) -> anyhow::Result<RpsLimits> {
+    let Some(limits) = toml.get("rps_limits") else {
+        return Ok(RpsLimits::default());
+    };
+    if !limits.is_table_like() {
+        bail!("configure option rps_limits is not a table");
+    }
+    let limit = |name: &str| -> anyhow::Result<Option<NonZeroU32>> {
+        parse_optional_integer::<u32, _>(name, limits)?
+            .map(|rps| {
+                NonZeroU32::new(rps)
+                    .with_context(|| format!("'rps_limits.{name}' must be a positive integer"))
+            })
+            .transpose()
+    };
+    Ok(RpsLimits {
+        get: limit("get")?,
+        put: limit("put")?,
+        delete: limit("delete")?,
+        list: limit("list")?,
+        copy: limit("copy")?,
+        time_travel: limit("time_travel")?,
+    })
+}
+
+/// Parses the `timeouts` table, with `default` for the kinds it doesn't mention.
+fn parse_per_kind_timeouts(
+    toml: &toml_edit::Item,
+    default: Duration,
+) -> anyhow::Result<PerKindTimeouts> {
+    let Some(timeouts) = toml.get("timeouts") else {
+        return Ok(default.into());
+    };
+    if !timeouts.is_table_like() {
+        bail!("configure option timeouts is not a table");
+    }
+    let kind_timeout = |name: &str| -> anyhow::Result<Duration> {
+        let timeout = parse_optional_duration(name, timeouts)
+            .with_context(|| format!("parse 'timeouts.{name}'"))?
+            .unwrap_or(default);
+        if timeout < Duration::from_secs(1) {
+            bail!("'timeouts.{name}' was specified as {timeout:?} which is too low");
+        }
+        Ok(timeout)
+    };
+    if let Some((name, _)) = timeouts
+        .as_table_like()
+        .expect("checked above")
+        .iter()
+        .find(|(name, _)| !["get", "put", "list", "delete", "copy"].contains(name))
+    {
+        bail!("unknown request kind '{name}' in timeouts, expected one of: get, put, list, delete, copy");
+    }
+    Ok(PerKindTimeouts {
+        default,
+        get: kind_timeout("get")?,
+        put: kind_timeout("put")?,
+        list: kind_timeout("list")?,
+        delete: kind_timeout("delete")?,
+        copy: kind_timeout("copy")?,
+    })
+}
+
+fn parse_optional_duration(name: &str, item: &toml_edit::Item) -> anyhow::Result<Option<Duration>> {
+    let Some(item) = item.get(name) else {
+        return Ok(None);
+    };
+    let s = item
+        .as_str()
+        .with_context(|| format!("configure option {name} is not a string"))?;
+    humantime::parse_duration(s)
+        .map(Some)
+        .with_context(|| format!("configure option {name} is not a valid duration"))
+}
+
+fn parse_toml_string(name: &str, item: &Item) -> anyhow::Result<String> {
+    let s = item
+        .as_str()
+        .with_context(|| format!("configure option {name} is not a string"))?;
+    Ok(s.to_string())
+}
+
+struct ConcurrencyLimiter {
+    // Every request to S3 can be throttled or cancelled, if a certain number of requests per second is exceeded.
+    // Same goes to IAM, which is queried before every S3 request, if enabled. IAM has even lower RPS threshold.
+    // The helps to ensure we don't exceed the thresholds.
+    write: Arc<Semaphore>,
+    read: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimiter {
+    fn for_kind(&self, kind: RequestKind) -> &Arc<Semaphore> {
+        match kind {
+            RequestKind::Get => &self.read,
+            RequestKind::Put => &self.write,
+            RequestKind::List => &self.read,
+            RequestKind::Delete => &self.write,
+            RequestKind::Copy => &self.write,
+            RequestKind::TimeTravel => &self.write,
+        }
+    }
+
+    async fn acquire(
+        &self,
+        kind: RequestKind,
+    ) -> Result<tokio::sync::SemaphorePermit<'_>, tokio::sync::AcquireError> {
+        self.for_kind(kind).acquire().await
+    }
+
+    async fn acquire_owned(
+        &self,
+        kind: RequestKind,
+    ) -> Result<tokio::sync::OwnedSemaphorePermit, tokio::sync::AcquireError> {
+        Arc::clone(self.for_kind(kind)).acquire_owned().await
+    }
+
+    fn new(limit: usize) -> ConcurrencyLimiter {
+        Self {
+            read: Arc::new(Semaphore::new(limit)),
+            write: Arc::new(Semaphore::new(limit)),
+        }
+    }
+}
+
+/// Token buckets limiting the requests per second of each kind, see [`RpsLimits`].
+///
+/// Unlike the [`ConcurrencyLimiter`], nothing is held for the duration of a request: every request
+/// takes a token before it is sent, and the tokens are refilled evenly over each second.
+struct RateLimiter {
+    get: Option<leaky_bucket::RateLimiter>,
+    put: Option<leaky_bucket::RateLimiter>,
+    delete: Option<leaky_bucket::RateLimiter>,
+    list: Option<leaky_bucket::RateLimiter>,
+    copy: Option<leaky_bucket::RateLimiter>,
+    time_travel: Option<leaky_bucket::RateLimiter>,
+}
+
+impl RateLimiter {
+    fn for_kind(&self, kind: RequestKind) -> Option<&leaky_bucket::RateLimiter> {
+        match kind {
+            RequestKind::Get => self.get.as_ref(),
+            RequestKind::Put => self.put.as_ref(),
+            RequestKind::List => self.list.as_ref(),
+            RequestKind::Delete => self.delete.as_ref(),
+            RequestKind::Copy => self.copy.as_ref(),
+            RequestKind::TimeTravel => self.time_travel.as_ref(),
+        }
+    }
+
+    /// Waits until a request of `kind` may be sent. Returns immediately for unlimited kinds.
+    async fn acquire(&self, kind: RequestKind) {
+        if let Some(bucket) = self.for_kind(kind) {
+            bucket.acquire_one().await;
+        }
+    }
+
+    fn new(limits: &RpsLimits) -> RateLimiter {
+        let bucket = |rps: Option<NonZeroU32>| {
+            rps.map(|rps| {
+                // Refill one token at a time rather than a second's worth at once, so that a full
+                // bucket is the largest burst allowed. Timers are no finer than a millisecond, and
+                // the interval must not round down to zero, so higher limits refill in batches.
+                let interval = (Duration::from_secs(1) / rps.get()).max(Duration::from_millis(1));
+                let refill = (rps.get() as u128 * interval.as_nanos()).div_ceil(1_000_000_000);
+                leaky_bucket::RateLimiter::builder()
+                    .initial(rps.get() as usize)
+                    .max(rps.get() as usize)
+                    .refill(refill as usize)
+                    .interval(interval)
+                    .fair(true)
+                    .build()
+            })
+        };
+        Self {
+            get: bucket(limits.get),
+            put: bucket(limits.put),
+            delete: bucket(limits.delete),
+            list: bucket(limits.list),
+            copy: bucket(limits.copy),
+            time_travel: bucket(limits.time_travel),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_name() {
+        let k = RemotePath::new(Utf8Path::new("a/b/c")).unwrap();
+        assert_eq!(k.object_name(), Some("c"));
+
+        let k = RemotePath::new(Utf8Path::new("a/b/c/")).unwrap();
+        assert_eq!(k.object_name(), Some("c"));
+
+        let k = RemotePath::new(Utf8Path::new("a/")).unwrap();
+        assert_eq!(k.object_name(), Some("a"));
+
+        // XXX is it impossible to have an empty key?
+        let k = RemotePath::new(Utf8Path::new("")).unwrap();
+        assert_eq!(k.object_name(), None);
+    }
+
+    #[test]
+    fn as_local_fs() -> anyhow::Result<()> {
+        let root = camino_tempfile::tempdir()?;
+        let storage: GenericRemoteStorage = GenericRemoteStorage::LocalFs(LocalFs::new(
+            root.path().to_path_buf(),
+            Duration::from_secs(120),
+            false,
+        )?);
+        let local_fs = storage.as_local_fs().expect("storage is LocalFs");
+        assert_eq!(local_fs.storage_root(), root.path());
+
+        let unreliable = GenericRemoteStorage::unreliable_wrapper(storage, 1);
+        assert!(unreliable.as_local_fs().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn s3_and_azure_map_paths_identically() {
+        let paths = ["", "some/path", "some/path/"].map(|p| RemotePath::from_string(p).unwrap());
+        let prefixes = [
+            None,
+            Some(""),
+            Some("test/prefix"),
+            Some("test/prefix/"),
+            Some("/"),
+            Some("/test/prefix/"),
+        ];
+
+        for prefix in prefixes {
+            let s3 = S3Bucket::new(
+                &S3Config {
+                    bucket_name: "bucket".to_owned(),
+                    bucket_region: "region".to_owned(),
+                    prefix_in_bucket: prefix.map(str::to_string),
+                    endpoint: None,
+                    concurrency_limit: NonZeroUsize::new(100).unwrap(),
+                    max_keys_per_list_response: None,
+                    upload_storage_class: None,
+                    max_idle_connections: None,
+                    connection_idle_timeout: None,
+                    connect_timeout: None,
+                    operation_attempt_timeout: None,
+                    sdk_max_attempts: None,
+                    profile_name: None,
+                    rps_limits: Default::default(),
+                    expected_bucket_owner: None,
+                    requester_pays: false,
+                    slow_request_threshold: None,
+                    user_agent_suffix: None,
+                    app_name: None,
+                },
+                Duration::ZERO,
+            )
+            .expect("s3 storage init");
+            let azure = AzureBlobStorage::new(
+                &AzureConfig {
+                    container_name: "container".to_owned(),
+                    container_region: "region".to_owned(),
+                    prefix_in_container: prefix.map(str::to_string),
+                    concurrency_limit: NonZeroUsize::new(100).unwrap(),
+                    max_keys_per_list_response: None,
+                    auth_method: AzureAuthMethod::StorageKey("a2V5".to_owned()),
+                    max_block_size: NonZeroUsize::new(DEFAULT_AZURE_MAX_BLOCK_SIZE).unwrap(),
+                    max_concurrency_per_upload: NonZeroUsize::new(
+                        DEFAULT_AZURE_MAX_CONCURRENCY_PER_UPLOAD,
+                    )
+                    .unwrap(),
+                    rps_limits: Default::default(),
+                    storage_account: Some("account".to_owned()),
+                    user_agent_suffix: None,
+                    app_name: None,
+                },
+                Duration::ZERO,
+            )
+            .expect("azure storage init");
+
+            for path in &paths {
+                let key = s3.relative_path_to_s3_object(path);
+                let name = azure.relative_path_to_name(path);
+                if prefix == Some("/test/prefix/") {
+                    // Deprecated, the blob names keep the leading separator like they used to
+                    assert_eq!(name, format!("/{key}"), "{path}");
+                } else {
+                    assert_eq!(name, key, "{prefix:?} {path}");
+                }
+                // What is listed maps back to the uploaded path
+                assert_eq!(&s3.s3_object_to_relative_path(&key), path);
+                assert_eq!(&azure.name_to_relative_path(&name), path);
+            }
+        }
+    }
+
+    #[test]
+    fn etag_normalization() {
+        let quoted = Etag::from("\"abc\"");
+        let unquoted = Etag::from("abc");
+        let weak = Etag::from("W/\"abc\"");
+        assert_eq!(quoted, unquoted);
+        assert_eq!(quoted.to_string(), "\"abc\"");
+        assert_eq!(quoted.strip_quotes(), "abc");
+        assert!(!quoted.is_weak());
+
+        assert_eq!(weak, Etag::from("W/abc"));
+        assert_eq!(weak.to_string(), "W/\"abc\"");
+        assert_eq!(weak.strip_quotes(), "abc");
+        assert!(weak.is_weak());
+        assert_ne!(weak, quoted);
+        assert!(weak.eq_ignoring_weak(&quoted));
+
+        assert_eq!(Etag::from("\"\"").strip_quotes(), "");
+    }
+
+    #[test]
+    fn etag_multipart() {
+        assert!(Etag::from("\"d41d8cd98f00b204e9800998ecf8427e-12\"").is_multipart());
+        assert!(!Etag::from("\"d41d8cd98f00b204e9800998ecf8427e\"").is_multipart());
+        assert!(!Etag::from("\"abc-\"").is_multipart());
+        assert!(!Etag::from("0x8DC3A1B2C3D4E5F").is_multipart());
+    }
+
+    #[test]
+    fn rempte_path_cannot_be_created_from_absolute_ones() {
+        let err = RemotePath::new(Utf8Path::new("/")).expect_err("Should fail on absolute paths");
+        assert_eq!(err.to_string(), "Path \"/\" is not relative");
+    }
+
+    #[tokio::test]
+    async fn download_resumable_reads_whole_object() {
+        use futures::StreamExt;
+
+        let dir = camino_tempfile::tempdir().unwrap();
+        let storage = GenericRemoteStorage::LocalFs(
+            LocalFs::new(
+                dir.path().to_owned(),
+                RemoteStorageConfig::DEFAULT_TIMEOUT,
+                false,
+            )
+            .unwrap(),
+        );
+        let cancel = CancellationToken::new();
+
+        let path = RemotePath::from_string("some/object").unwrap();
+        let body = Bytes::from_static(b"some object contents");
+        let len = body.len();
+        let stream = futures::stream::once(futures::future::ready(Ok(body.clone())));
+        storage
+            .upload(stream, len, &path, UploadOptions::default(), &cancel)
+            .await
+            .unwrap();
+
+        let download = storage.download_resumable(&path, 3, &cancel).await.unwrap();
+        let mut stream = download.download_stream;
+        let mut contents = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            contents.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(contents, body);
+    }
+
+    /// A download of `chunks`, as [`resumable_download_stream`] gets it from the backend.
+    fn download_of(etag: &str, chunks: Vec<std::io::Result<&'static [u8]>>) -> Download {
+        let chunks = chunks
+            .into_iter()
+            .map(|chunk| chunk.map(Bytes::from_static));
+        Download {
+            download_stream: Box::pin(futures::stream::iter(chunks)),
+            last_modified: SystemTime::UNIX_EPOCH,
+            etag: Etag::from(etag),
+            content_length: 0,
+            object_size: 0,
+            metadata: None,
+            content_encoding: None,
+        }
+    }
+
+    /// Runs [`resumable_download_stream`] over `first`, resuming from `resumes` in order, and
+    /// returns what it yielded before it ended or failed, the error if any, and the offsets it
+    /// resumed at.
+    async fn resume_download(
+        first: Download,
+        resumes: Vec<Download>,
+        max_resumptions: u32,
+    ) -> (Vec<u8>, Option<std::io::Error>, Vec<u64>) {
+        use futures::StreamExt;
+
+        let offsets = std::sync::Mutex::new(Vec::new());
+        let mut resumes = resumes.into_iter();
+        let stream = resumable_download_stream(
+            RemotePath::from_string("some/object").unwrap(),
+            first.etag,
+            first.download_stream,
+            max_resumptions,
+            |offset| {
+                offsets.lock().unwrap().push(offset);
+                let resumed = resumes.next().expect("no more resumes");
+                futures::future::ready(Ok(resumed))
+            },
+        );
+        let mut stream = std::pin::pin!(stream);
+
+        let mut contents = Vec::new();
+        let mut error = None;
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => contents.extend_from_slice(&chunk),
+                Err(e) => error = Some(e),
+            }
+        }
+        let offsets = offsets.lock().unwrap().clone();
+        (contents, error, offsets)
+    }
+
+    fn io_error() -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset")
+    }
+
+    #[tokio::test]
+    async fn download_resumable_resumes_after_error() {
+        let first = download_of("etag", vec![Ok(b"some "), Err(io_error())]);
+        let resumes = vec![
+            download_of("etag", vec![Ok(b"object "), Err(io_error())]),
+            download_of("etag", vec![Ok(b"contents")]),
+        ];
+
+        let (contents, error, offsets) = resume_download(first, resumes, 2).await;
+        assert!(error.is_none(), "{error:?}");
+        assert_eq!(contents, b"some object contents");
+        assert_eq!(offsets, vec![5, 12]);
+    }
+
+    #[tokio::test]
+    async fn download_resumable_fails_when_object_changes() {
+        let first = download_of("etag", vec![Ok(b"some "), Err(io_error())]);
+        let resumes = vec![download_of("other etag", vec![Ok(b"other contents")])];
+
+        let (contents, error, offsets) = resume_download(first, resumes, 2).await;
+        assert_eq!(contents, b"some ");
+        let error = error.expect("the stream fails");
+        assert!(
+            error.to_string().contains("changed while downloading"),
+            "{error}"
+        );
+        assert_eq!(offsets, vec![5]);
+    }
+
+    #[tokio::test]
+    async fn download_resumable_runs_out_of_resumptions() {
+        let first = download_of("etag", vec![Ok(b"some "), Err(io_error())]);
+        let resumes = vec![download_of("etag", vec![Ok(b"object "), Err(io_error())])];
+
+        let (contents, error, offsets) = resume_download(first, resumes, 1).await;
+        assert_eq!(contents, b"some object ");
+        let error = error.expect("the stream fails");
+        assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+        assert_eq!(offsets, vec![5]);
+    }
+
+    #[tokio::test]
+    async fn download_resumable_does_not_resume_cancellation() {
+        let cancelled = std::io::Error::other(DownloadError::Cancelled(None));
+        let first = download_of("etag", vec![Ok(b"some "), Err(cancelled)]);
+
+        let (contents, error, offsets) = resume_download(first, Vec::new(), 3).await;
+        assert_eq!(contents, b"some ");
+        assert!(error.is_some());
+        assert!(offsets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn download_buffered_reads_whole_object() -> anyhow::Result<()> {
+        use futures::TryStreamExt;
+
+        let dir = camino_tempfile::tempdir()?;
+        let storage = GenericRemoteStorage::LocalFs(LocalFs::new(
+            dir.path().to_owned(),
+            RemoteStorageConfig::DEFAULT_TIMEOUT,
+            false,
+        )?);
+        let cancel = CancellationToken::new();
+
+        let body = (0..=255u8).cycle().take(1001).collect::<Vec<_>>();
+        let path = RemotePath::from_string("some/object")?;
+        let stream = futures::stream::once(futures::future::ready(Ok(Bytes::from(body.clone()))));
+        storage
+            .upload(stream, body.len(), &path, UploadOptions::default(), &cancel)
+            .await?;
+
+        // Ranges smaller than, a multiple of, and larger than the object, including a last
+        // range of a single byte, with one and with several of them in flight
+        for range_size in [10, 100, 1000, 2000] {
+            for readahead_bytes in [0, range_size as usize, 4000] {
+                let download = storage
+                    .download_in_ranges(&path, range_size, readahead_bytes, &cancel)
+                    .await?;
+                assert_eq!(download.content_length, body.len() as u64);
+                let chunks = download.download_stream.try_collect::<Vec<_>>().await?;
+                assert_eq!(
+                    chunks.concat(),
+                    body,
+                    "ranges of {range_size} bytes, readahead of {readahead_bytes} bytes"
+                );
+            }
+        }
+        let download = storage.download_buffered(&path, 1000, &cancel).await?;
+        let chunks = download.download_stream.try_collect::<Vec<_>>().await?;
+        assert_eq!(chunks.concat(), body);
+
+        // Ranges are of the stored bytes, the stitched together stream is decoded
+        let compressed = RemotePath::from_string("some/compressed")?;
+        storage
+            .upload_compressed(
+                Bytes::from(body.clone()),
+                &compressed,
+                None,
+                Compression::Gzip,
+                &cancel,
+            )
+            .await?;
+        let download = storage.download_buffered(&compressed, 10, &cancel).await?;
+        assert_eq!(download.content_encoding, None);
+        let chunks = download.download_stream.try_collect::<Vec<_>>().await?;
+        assert_eq!(chunks.concat(), body);
+
+        let empty = RemotePath::from_string("some/empty")?;
+        storage
+            .upload(
+                futures::stream::empty::<std::io::Result<Bytes>>(),
+                0,
+                &empty,
+                UploadOptions::default(),
+                &cancel,
+            )
+            .await?;
+        let download = storage.download_buffered(&empty, 10, &cancel).await?;
+        let chunks = download.download_stream.try_collect::<Vec<_>>().await?;
+        assert!(chunks.concat().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn download_buffered_small_readahead_keeps_range_size() -> anyhow::Result<()> {
+        use futures::TryStreamExt;
+
+        let dir = camino_tempfile::tempdir()?;
+        let storage = GenericRemoteStorage::LocalFs(LocalFs::new(
+            dir.path().to_owned(),
+            RemoteStorageConfig::DEFAULT_TIMEOUT,
+            false,
+        )?);
+        let cancel = CancellationToken::new();
+
+        let body = (0..=255u8).cycle().take(100_000).collect::<Vec<_>>();
+        let path = RemotePath::from_string("some/object")?;
+        storage
+            .upload_bytes(
+                Bytes::from(body.clone()),
+                &path,
+                UploadOptions::default(),
+                &cancel,
+            )
+            .await?;
+
+        // The object fits in a single range, so a tiny readahead still takes a single request
+        for readahead_bytes in [0, 1, 4096] {
+            let requests_before = storage.traffic_stats().requests;
+            let download = storage
+                .download_buffered(&path, readahead_bytes, &cancel)
+                .await?;
+            let chunks = download.download_stream.try_collect::<Vec<_>>().await?;
+            assert_eq!(chunks.concat(), body);
+            assert_eq!(
+                storage.traffic_stats().requests - requests_before,
+                1,
+                "readahead of {readahead_bytes} bytes"
+            );
+        }
+
+        // Larger objects take one request per range, not per readahead
+        let requests_before = storage.traffic_stats().requests;
+        let download = storage
+            .download_in_ranges(&path, 30_000, 1, &cancel)
+            .await?;
+        let chunks = download.download_stream.try_collect::<Vec<_>>().await?;
+        assert_eq!(chunks.concat(), body);
+        assert_eq!(storage.traffic_stats().requests - requests_before, 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn download_buffered_returns_other_errors() -> anyhow::Result<()> {
+        let dir = camino_tempfile::tempdir()?;
+        let local = GenericRemoteStorage::LocalFs(LocalFs::new(
+            dir.path().to_owned(),
+            RemoteStorageConfig::DEFAULT_TIMEOUT,
+            false,
+        )?);
+        let cancel = CancellationToken::new();
+        let path = RemotePath::from_string("some/object")?;
+        local
+            .upload_bytes(
+                Bytes::from_static(b"data"),
+                &path,
+                UploadOptions::default(),
+                &cancel,
+            )
+            .await?;
+
+        // Fails the first download attempt, which a fallback to a plain download would retry
+        let storage = GenericRemoteStorage::unreliable_wrapper(local, 1);
+        let e = storage
+            .download_buffered(&path, 10, &cancel)
+            .await
+            .map(|_| ())
+            .expect_err("the failed range download is not retried");
+        assert!(matches!(e, DownloadError::Other(_)), "{e:?}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn listing_prefixes_at_depth() {
+        let object = |key: &str| ListingObject {
+            key: RemotePath::from_string(key).unwrap(),
+            last_modified: SystemTime::UNIX_EPOCH,
+            size: 0,
+            metadata: None,
+            version_id: None,
+        };
+        let path = |p: &str| RemotePath::from_string(p).unwrap();
+
+        let mut listing = Listing {
+            prefixes: Vec::new(),
+            skipped: Vec::new(),
+            objects: vec![
+                object("a/b/c/d"),
+                object("a/b/e"),
+                object("a/f/g"),
+                object("a/h"),
+                object("i"),
+            ],
+        };
+
+        listing.compute_prefixes_at_depth(None);
+        assert!(listing.prefixes.is_empty());
 
-/// AWS S3 bucket coordinates and access credentials to manage the bucket contents (read and write).
-#[derive(Clone, PartialEq, Eq)]
-pub struct S3Config {
-    /// Name of the bucket to connect to.
-    pub bucket_name: String,
-    /// The region where the bucket is located at.
-    pub bucket_region: String,
-    /// A "subfolder" in the bucket, to use the same bucket separately by multiple remote storage users at once.
-    pub prefix_in_bucket: Option<String>,
-    /// A base URL to send S3 requests to.
-    /// By default, the endpoint is derived from a region name, assuming it's
-    /// an AWS S3 region name, erroring on wrong region name.
-    /// Endpoint provides a way to support other S3 flavors and their regions.
-    ///
-    /// Example: `http://127.0.0.1:5000`
-    pub endpoint: Option<String>,
-    /// AWS S3 has various limits on its API calls, we need not to exceed those.
-    /// See [`DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT`] for more details.
-    pub concurrency_limit: NonZeroUsize,
-    pub max_keys_per_list_response: Option<i32>,
-    pub upload_storage_class: Option<StorageClass>,
-}
+        listing.compute_prefixes_at_depth(Some(1));
+        assert_eq!(listing.prefixes, vec![path("a")]);
 
-impl Debug for S3Config {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("S3Config")
-            .field("bucket_name", &self.bucket_name)
-            .field("bucket_region", &self.bucket_region)
-            .field("prefix_in_bucket", &self.prefix_in_bucket)
-            .field("concurrency_limit", &self.concurrency_limit)
-            .field(
-                "max_keys_per_list_response",
-                &self.max_keys_per_list_response,
+        listing.compute_prefixes_at_depth(Some(2));
+        assert_eq!(listing.prefixes, vec![path("a/b"), path("a/f")]);
+
+        listing.compute_prefixes_at_depth(Some(3));
+        assert_eq!(listing.prefixes, vec![path("a/b/c")]);
+    }
+
+    #[test]
+    fn listing_sorted_by_bytes() {
+        let object = |key: &str| ListingObject {
+            key: RemotePath::from_string(key).unwrap(),
+            last_modified: SystemTime::UNIX_EPOCH,
+            size: 0,
+            metadata: None,
+            version_id: None,
+        };
+        let path = |p: &str| RemotePath::from_string(p).unwrap();
+
+        // In path order, `a/b` would come before `a-b`
+        let mut listing = Listing {
+            prefixes: vec![path("a/c"), path("a-c")],
+            skipped: Vec::new(),
+            objects: vec![object("a/b/c"), object("a/b"), object("a-b/c")],
+        };
+        assert!(!listing.is_sorted());
+
+        listing.sort();
+        assert!(listing.is_sorted());
+        assert_eq!(listing.prefixes, vec![path("a-c"), path("a/c")]);
+        let keys = listing
+            .objects
+            .iter()
+            .map(|o| o.key.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![path("a-b/c"), path("a/b"), path("a/b/c")]);
+
+        listing.compute_prefixes_at_depth(Some(1));
+        assert_eq!(listing.prefixes, vec![path("a"), path("a-b")]);
+        listing.compute_prefixes_at_depth(Some(2));
+        assert_eq!(listing.prefixes, vec![path("a-b/c"), path("a/b")]);
+        assert!(listing.is_sorted());
+    }
+
+    /// Written once against the trait, usable with concrete backends and the generic enum.
+    async fn upload_and_list(
+        storage: &impl RemoteStorage,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Vec<RemotePath>> {
+        let body = Bytes::from_static(b"contents");
+        let len = body.len();
+        let from = futures::stream::once(futures::future::ready(Ok(body)));
+        let path = RemotePath::from_string("a/b")?;
+        storage
+            .upload(from, len, &path, UploadOptions::default(), cancel)
+            .await?;
+        let listing = storage
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions::default(),
+                cancel,
             )
-            .finish()
+            .await?;
+        Ok(listing.objects.into_iter().map(|o| o.key).collect())
     }
-}
 
-/// Azure  bucket coordinates and access credentials to manage the bucket contents (read and write).
-#[derive(Clone, PartialEq, Eq)]
-pub struct AzureConfig {
-    /// Name of the container to connect to.
-    pub container_name: String,
-    /// The region where the bucket is located at.
-    pub container_region: String,
-    /// A "subfolder" in the container, to use the same container separately by multiple remote storage users at once.
-    pub prefix_in_container: Option<String>,
-    /// Azure has various limits on its API calls, we need not to exceed those.
-    /// See [`DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT`] for more details.
-    pub concurrency_limit: NonZeroUsize,
-    pub max_keys_per_list_response: Option<i32>,
-}
+    #[tokio::test]
+    async fn generic_storage_implements_trait() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let local = |root: &camino::Utf8Path| {
+            LocalFs::new(
+                root.to_path_buf(),
+                std::time::Duration::from_secs(120),
+                false,
+            )
+        };
 
-impl Debug for AzureConfig {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("AzureConfig")
-            .field("bucket_name", &self.container_name)
-            .field("bucket_region", &self.container_region)
-            .field("prefix_in_bucket", &self.prefix_in_container)
-            .field("concurrency_limit", &self.concurrency_limit)
-            .field(
-                "max_keys_per_list_response",
-                &self.max_keys_per_list_response,
+        let root = camino_tempfile::tempdir()?;
+        let keys = upload_and_list(&local(root.path())?, &cancel).await?;
+        assert_eq!(keys, vec![RemotePath::from_string("a/b")?]);
+
+        let root = camino_tempfile::tempdir()?;
+        let generic: GenericRemoteStorage = GenericRemoteStorage::LocalFs(local(root.path())?);
+        assert_eq!(upload_and_list(&generic, &cancel).await?, keys);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify_permissions_cleans_up() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let root = camino_tempfile::tempdir()?;
+        let storage: GenericRemoteStorage = GenericRemoteStorage::LocalFs(LocalFs::new(
+            root.path().to_path_buf(),
+            std::time::Duration::from_secs(120),
+            false,
+        )?);
+
+        let report = storage.verify_permissions(&cancel).await;
+        assert!(report.all_granted(), "{report:?}");
+        assert!(report.errors.is_empty());
+
+        let listing = storage
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
             )
-            .finish()
+            .await?;
+        assert!(listing.objects.is_empty(), "{:?}", listing.objects);
+
+        Ok(())
     }
-}
 
-impl RemoteStorageConfig {
-    pub const DEFAULT_TIMEOUT: Duration = std::time::Duration::from_secs(120);
+    #[tokio::test]
+    async fn healthcheck() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let root = camino_tempfile::tempdir()?;
+        let storage: GenericRemoteStorage = GenericRemoteStorage::LocalFs(LocalFs::new(
+            root.path().to_path_buf(),
+            std::time::Duration::from_secs(120),
+            false,
+        )?);
 
-    pub fn from_toml(toml: &toml_edit::Item) -> anyhow::Result<Option<RemoteStorageConfig>> {
-        let local_path = toml.get("local_path");
-        let bucket_name = toml.get("bucket_name");
-        let bucket_region = toml.get("bucket_region");
-        let container_name = toml.get("container_name");
-        let container_region = toml.get("container_region");
+        // An empty storage is just as healthy as one with contents
+        let probe = storage.healthcheck_handle()?;
+        probe.healthcheck(&cancel).await?;
+        let path = RemotePath::from_string("tenants/a")?;
+        storage
+            .upload_storage_object(
+                futures::stream::once(futures::future::ready(Ok(Bytes::from_static(b"a")))),
+                1,
+                &path,
+                &cancel,
+            )
+            .await?;
+        probe.healthcheck(&cancel).await?;
+
+        // Probes don't see the injected failures
+        let unreliable = GenericRemoteStorage::unreliable_wrapper(storage, 1);
+        unreliable
+            .healthcheck_handle()?
+            .healthcheck(&cancel)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_many_reports_each_item() -> anyhow::Result<()> {
+        use futures::StreamExt;
+
+        let root = camino_tempfile::tempdir()?;
+        let storage: GenericRemoteStorage = GenericRemoteStorage::LocalFs(LocalFs::new(
+            root.path().to_path_buf(),
+            std::time::Duration::from_secs(120),
+            false,
+        )?);
+        let cancel = CancellationToken::new();
+
+        let mut items = (0..20)
+            .map(|i| {
+                let path = RemotePath::from_string(&format!("aux/file_{i}"))?;
+                Ok((path, Bytes::from(format!("contents {i}")), None))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // `aux/file_0` is a file, so this can't be created below it
+        items.push((
+            RemotePath::from_string("aux/file_0/nested")?,
+            Bytes::from_static(b"fails"),
+            None,
+        ));
+
+        // Upload the parent first so the failing item deterministically fails
+        let (first, rest) = items.split_at(1);
+        let results = storage
+            .upload_many(futures::stream::iter(first.to_vec()), 1, &cancel)
+            .chain(storage.upload_many(futures::stream::iter(rest.to_vec()), 8, &cancel))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(results.len(), 21);
+        let failed = results
+            .iter()
+            .filter(|(_, res)| res.is_err())
+            .map(|(path, _)| path.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(failed, vec!["aux/file_0/nested"]);
+
+        let download = storage
+            .download(&RemotePath::from_string("aux/file_7")?, &cancel)
+            .await?;
+        let mut contents = Vec::new();
+        tokio::io::copy_buf(
+            &mut tokio_util::io::StreamReader::new(download.download_stream),
+            &mut contents,
+        )
+        .await?;
+        assert_eq!(contents, b"contents 7");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn download_errors_name_the_object() -> anyhow::Result<()> {
+        let root = camino_tempfile::tempdir()?;
+        let storage: GenericRemoteStorage = GenericRemoteStorage::LocalFs(LocalFs::new(
+            root.path().to_path_buf(),
+            std::time::Duration::from_secs(120),
+            false,
+        )?);
+        let cancel = CancellationToken::new();
+        let path = RemotePath::from_string("tenants/x/timelines/y/layer")?;
+
+        let err = storage
+            .download_byte_range(&path, 5, Some(3), &cancel)
+            .await
+            .unwrap_err();
+        let DownloadError::InvalidRange(e) = err else {
+            panic!("unexpected error: {err:?}");
+        };
+        assert_eq!(
+            e.to_string(),
+            "download tenants/x/timelines/y/layer bytes 5..3"
+        );
+
+        // Errors without an underlying error of their own name the object too, and stay matchable
+        let err = storage.download(&path, &cancel).await.unwrap_err();
+        let DownloadError::NotFound(Some(e)) = err else {
+            panic!("unexpected error: {err:?}");
+        };
+        assert_eq!(e.to_string(), "download tenants/x/timelines/y/layer");
+
+        let err = DownloadError::Timeout(None)
+            .add_context("download tenants/a/layer")
+            .add_context("download layer for compaction");
+        assert!(!err.is_permanent());
+        assert_eq!(
+            err.to_string(),
+            "timeout: download layer for compaction: download tenants/a/layer"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn forbidden_is_distinct_from_not_found() {
+        let err = DownloadError::Forbidden(anyhow::anyhow!("403 AccessDenied"))
+            .add_context("download tenants/a/index_part.json");
+        assert!(err.is_permanent());
+        let DownloadError::Forbidden(e) = &err else {
+            panic!("expected Forbidden, got {err:?}");
+        };
+        assert!(format!("{e:#}").contains("tenants/a/index_part.json"));
+        assert!(err.to_string().contains("Access denied"), "{err}");
+    }
+
+    #[test]
+    fn throttled_survives_context() {
+        let err = anyhow::anyhow!("503 SlowDown").context(Throttled);
+        assert!(Throttled::caused_by_throttling(&err));
+
+        let err = err.context("request deletion");
+        assert!(Throttled::caused_by_throttling(&err));
+        assert!(!TimeoutOrCancel::caused_by_cancel(&err));
+
+        let err = anyhow::anyhow!("500 InternalError").context("request deletion");
+        assert!(!Throttled::caused_by_throttling(&err));
+    }
+
+    #[test]
+    fn parse_azure_config_auth_method() {
+        let parse = |extra: &str| {
+            let input = format!(
+                "container_name = 'foo-bar'
+container_region = 'westeurope'
+{extra}"
+            );
+            let toml = input.parse::<toml_edit::Document>().unwrap();
+            let config = RemoteStorageConfig::from_toml(toml.as_item())?.expect("it exists");
+            match config.storage {
+                RemoteStorageKind::AzureContainer(azure_config) => Ok(azure_config.auth_method),
+                other => panic!("expected Azure config, got {other:?}"),
+            }
+        };
+
+        assert_eq!(parse("").unwrap(), AzureAuthMethod::DefaultChain);
+        assert_eq!(
+            parse("auth_method = 'managed_identity'").unwrap(),
+            AzureAuthMethod::ManagedIdentity { client_id: None }
+        );
+        assert_eq!(
+            parse("auth_method = 'managed_identity'\nmanaged_identity_client_id = 'abc'").unwrap(),
+            AzureAuthMethod::ManagedIdentity {
+                client_id: Some("abc".to_string())
+            }
+        );
+        assert_eq!(
+            parse("auth_method = 'sas'\nsas_token = 'sv=2022'").unwrap(),
+            AzureAuthMethod::Sas("sv=2022".to_string())
+        );
+        parse("auth_method = 'storage_key'").expect_err("missing storage_access_key");
+        parse("auth_method = 'password'").expect_err("unknown auth method");
+
+        let secret = AzureAuthMethod::StorageKey("hunter2".to_string());
+        assert!(!format!("{secret:?}").contains("hunter2"));
+    }
+
+    #[test]
+    fn redacted_config() {
+        let input = "container_name = 'foo-bar'
+container_region = 'westeurope'
+auth_method = 'storage_key'
+storage_access_key = 'hunter2'
+timeouts = { list = '10m' }";
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
+
+        let redacted = config.redacted_config();
+        assert!(!redacted.to_string().contains("hunter2"));
+        assert_eq!(redacted["auth_method"], "storage_key");
+        assert_eq!(redacted["storage_access_key"], "***");
+        assert_eq!(redacted["container_name"], "foo-bar");
+        assert_eq!(redacted["timeout"], "2m");
+        assert_eq!(redacted["timeouts"]["list"], "10m");
+
+        let config = RemoteStorageConfig::from_url("s3://bucket/prefix?region=us-east-1").unwrap();
+        let redacted = config.redacted_config();
+        assert_eq!(redacted["bucket_name"], "bucket");
+        assert_eq!(redacted["prefix_in_bucket"], "prefix");
+        assert!(redacted["endpoint"].is_null());
+    }
+
+    #[test]
+    fn parse_azure_config_block_size() {
+        let parse = |extra: &str| {
+            let input = format!(
+                "container_name = 'foo-bar'
+container_region = 'westeurope'
+{extra}"
+            );
+            let toml = input.parse::<toml_edit::Document>().unwrap();
+            let config = RemoteStorageConfig::from_toml(toml.as_item())?.expect("it exists");
+            match config.storage {
+                RemoteStorageKind::AzureContainer(azure_config) => Ok((
+                    azure_config.max_block_size.get(),
+                    azure_config.max_concurrency_per_upload.get(),
+                )),
+                other => panic!("expected Azure config, got {other:?}"),
+            }
+        };
+
+        assert_eq!(
+            parse("").unwrap(),
+            (
+                DEFAULT_AZURE_MAX_BLOCK_SIZE,
+                DEFAULT_AZURE_MAX_CONCURRENCY_PER_UPLOAD
+            )
+        );
+        assert_eq!(
+            parse("max_block_size = 16777216\nmax_concurrency_per_upload = 8").unwrap(),
+            (16 * 1024 * 1024, 8)
+        );
+        parse("max_block_size = 0").expect_err("zero block size");
+        parse("max_concurrency_per_upload = 0").expect_err("zero concurrency");
+    }
+
+    #[test]
+    fn parse_azure_config_prefix_in_container() {
+        let parse = |extra: &str| {
+            let input = format!(
+                "container_name = 'foo-bar'
+container_region = 'westeurope'
+{extra}"
+            );
+            let toml = input.parse::<toml_edit::Document>().unwrap();
+            let config = RemoteStorageConfig::from_toml(toml.as_item())?.expect("it exists");
+            match config.storage {
+                RemoteStorageKind::AzureContainer(azure_config) => {
+                    Ok(azure_config.prefix_in_container)
+                }
+                other => panic!("expected Azure config, got {other:?}"),
+            }
+        };
+
+        assert_eq!(parse("").unwrap(), None);
+        assert_eq!(
+            parse("prefix_in_container = 'some/prefix/'").unwrap(),
+            Some("some/prefix/".to_string())
+        );
+        // Deprecated, but still accepted
+        assert_eq!(
+            parse("prefix_in_container = '/some/prefix/'").unwrap(),
+            Some("/some/prefix/".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_s3_config_with_connection_pool() {
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+max_idle_connections = 50
+connection_idle_timeout = '30s'";
+
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
+
+        let RemoteStorageKind::AwsS3(s3_config) = config.storage else {
+            panic!("expected S3 config, got {:?}", config.storage);
+        };
+        assert_eq!(s3_config.max_idle_connections, NonZeroUsize::new(50));
+        assert_eq!(
+            s3_config.connection_idle_timeout,
+            Some(Duration::from_secs(30))
+        );
+
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+max_idle_connections = 0";
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+        RemoteStorageConfig::from_toml(toml.as_item()).expect_err("zero max_idle_connections");
+    }
+
+    #[test]
+    fn parse_s3_config_with_sdk_timeouts() {
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+connect_timeout = '1s'
+operation_attempt_timeout = '20s'
+sdk_max_attempts = 3";
+
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
+
+        let RemoteStorageKind::AwsS3(s3_config) = config.storage else {
+            panic!("expected S3 config, got {:?}", config.storage);
+        };
+        assert_eq!(s3_config.connect_timeout, Some(Duration::from_secs(1)));
+        assert_eq!(
+            s3_config.operation_attempt_timeout,
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(s3_config.sdk_max_attempts, NonZeroU32::new(3));
+
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+sdk_max_attempts = 0";
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+        RemoteStorageConfig::from_toml(toml.as_item()).expect_err("zero sdk_max_attempts");
+    }
+
+    #[test]
+    fn parse_s3_config_with_profile_name() {
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+profile_name = 'tenant-account'";
+
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
+
+        let RemoteStorageKind::AwsS3(s3_config) = config.storage else {
+            panic!("expected S3 config, got {:?}", config.storage);
+        };
+        assert_eq!(s3_config.profile_name.as_deref(), Some("tenant-account"));
+    }
+
+    #[test]
+    fn parse_s3_config_with_expected_bucket_owner() {
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+expected_bucket_owner = '123456789012'";
+
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
+
+        let RemoteStorageKind::AwsS3(s3_config) = config.storage else {
+            panic!("expected S3 config, got {:?}", config.storage);
+        };
+        assert_eq!(
+            s3_config.expected_bucket_owner.as_deref(),
+            Some("123456789012")
+        );
+    }
+
+    #[test]
+    fn parse_s3_config_with_requester_pays() {
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'";
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
+        let RemoteStorageKind::AwsS3(s3_config) = config.storage else {
+            panic!("expected S3 config, got {:?}", config.storage);
+        };
+        assert!(!s3_config.requester_pays);
+
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+requester_pays = true";
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
+        let RemoteStorageKind::AwsS3(s3_config) = config.storage else {
+            panic!("expected S3 config, got {:?}", config.storage);
+        };
+        assert!(s3_config.requester_pays);
+
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+requester_pays = 'yes'";
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+        RemoteStorageConfig::from_toml(toml.as_item()).expect_err("not a boolean");
+    }
+
+    #[test]
+    fn parse_user_agent_suffix() {
+        let parse = |extra: &str| {
+            let input = format!(
+                "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+{extra}"
+            );
+            let toml = input.parse::<toml_edit::Document>().unwrap();
+            let config = RemoteStorageConfig::from_toml(toml.as_item())?.expect("it exists");
+            match config.storage {
+                RemoteStorageKind::AwsS3(s3_config) => Ok(s3_config.user_agent_suffix),
+                other => panic!("expected S3 config, got {other:?}"),
+            }
+        };
 
-        let use_azure = container_name.is_some() && container_region.is_some();
+        assert_eq!(parse("").unwrap(), None);
+        let suffix = parse("user_agent_suffix = 'neon-prod.eu_1'").unwrap();
+        assert_eq!(suffix.as_deref(), Some("neon-prod.eu_1"));
+        parse("user_agent_suffix = 'neon/prod'").expect_err("not a token");
+        parse("user_agent_suffix = ''").expect_err("empty");
 
-        let default_concurrency_limit = if use_azure {
-            DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT
-        } else {
-            DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT
+        assert_eq!(
+            user_agent(None, suffix.as_deref()),
+            "remote_storage-neon-prod.eu_1"
+        );
+        let mut config = RemoteStorageConfig::from_url("s3://bucket?region=eu-central-1").unwrap();
+        config.set_app_name("pageserver", "git-env:0123456789abcdef");
+        let RemoteStorageKind::AwsS3(s3_config) = &config.storage else {
+            panic!("expected S3 config, got {:?}", config.storage);
         };
-        let concurrency_limit = NonZeroUsize::new(
-            parse_optional_integer("concurrency_limit", toml)?.unwrap_or(default_concurrency_limit),
+        assert_eq!(
+            s3_config.app_name.as_deref(),
+            Some("pageserver-0123456789ab")
+        );
+        let user_agent = user_agent(s3_config.app_name.as_deref(), suffix.as_deref());
+        assert_eq!(user_agent, "pageserver-0123456789ab-neon-prod.eu_1");
+        assert!(user_agent.chars().all(is_user_agent_char), "{user_agent}");
+    }
+
+    #[test]
+    fn parse_mirror_config() {
+        let parse = |input: &str| {
+            let toml = input.parse::<toml_edit::Document>().unwrap();
+            RemoteStorageConfig::from_toml(toml.as_item()).map(|config| config.expect("it exists"))
+        };
+
+        let config = parse(
+            "timeout = '10s'
+mirror = { mode = 'best_effort', primary = { local_path = '/primary' }, secondary = { bucket_name = 'foo-bar', bucket_region = 'eu-central-1', timeout = '1m' } }",
         )
-        .context("Failed to parse 'concurrency_limit' as a positive integer")?;
+        .unwrap();
+        assert_eq!(config.timeouts, Duration::from_secs(10).into());
+        let RemoteStorageKind::Mirror(mirror) = &config.storage else {
+            panic!("expected a mirror config, got {:?}", config.storage);
+        };
+        assert_eq!(mirror.mode, MirrorMode::BestEffort);
+        assert!(matches!(*mirror.primary, RemoteStorageKind::LocalFs { .. }));
+        assert!(matches!(*mirror.secondary, RemoteStorageKind::AwsS3(_)));
+        let redacted = config.redacted_config();
+        assert_eq!(redacted["mirror"]["primary"]["local_path"], "/primary");
+        assert_eq!(redacted["mirror"]["secondary"]["bucket_name"], "foo-bar");
+        assert_eq!(redacted["timeout"], "10s");
 
-        let max_keys_per_list_response =
-            parse_optional_integer::<i32, _>("max_keys_per_list_response", toml)
-                .context("Failed to parse 'max_keys_per_list_response' as a positive integer")?
-                .or(DEFAULT_MAX_KEYS_PER_LIST_RESPONSE);
+        let config = parse(
+            "mirror = { primary = { local_path = '/primary' }, secondary = { local_path = '/secondary' } }",
+        )
+        .unwrap();
+        let RemoteStorageKind::Mirror(mirror) = config.storage else {
+            panic!("expected a mirror config, got {:?}", config.storage);
+        };
+        assert_eq!(mirror.mode, MirrorMode::Strict);
 
-        let endpoint = toml
-            .get("endpoint")
-            .map(|endpoint| parse_toml_string("endpoint", endpoint))
-            .transpose()?;
+        parse("mirror = { primary = { local_path = '/primary' } }").expect_err("no secondary");
+        parse(
+            "mirror = { primary = { local_path = '/primary' }, secondary = { local_path = '/secondary' }, mode = 'lazy' }",
+        )
+        .expect_err("unknown mode");
+        parse(
+            "mirror = { primary = { local_path = '/primary' }, secondary = { mirror = { primary = { local_path = '/a' }, secondary = { local_path = '/b' } } } }",
+        )
+        .expect_err("nested mirror");
+        parse(
+            "local_path = '/local'
+mirror = { primary = { local_path = '/primary' }, secondary = { local_path = '/secondary' } }",
+        )
+        .expect_err("mirror and another storage");
+    }
 
-        let timeout = toml
-            .get("timeout")
-            .map(|timeout| {
-                timeout
-                    .as_str()
-                    .ok_or_else(|| anyhow::Error::msg("timeout was not a string"))
-            })
-            .transpose()
-            .and_then(|timeout| {
-                timeout
-                    .map(humantime::parse_duration)
-                    .transpose()
-                    .map_err(anyhow::Error::new)
-            })
-            .context("parse timeout")?
-            .unwrap_or(Self::DEFAULT_TIMEOUT);
+    #[test]
+    fn parse_s3_config_with_rps_limits() {
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+rps_limits = { get = 5500, put = 3500 }";
 
-        if timeout < Duration::from_secs(1) {
-            bail!("timeout was specified as {timeout:?} which is too low");
-        }
+        let toml = input.parse::<toml_edit::Document>().unwrap();
 
-        let storage = match (
-            local_path,
-            bucket_name,
-            bucket_region,
-            container_name,
-            container_region,
-        ) {
-            // no 'local_path' nor 'bucket_name' options are provided, consider this remote storage disabled
-            (None, None, None, None, None) => return Ok(None),
-            (_, Some(_), None, ..) => {
-                bail!("'bucket_region' option is mandatory if 'bucket_name' is given ")
-            }
-            (_, None, Some(_), ..) => {
-                bail!("'bucket_name' option is mandatory if 'bucket_region' is given ")
-            }
-            (None, Some(bucket_name), Some(bucket_region), ..) => {
-                RemoteStorageKind::AwsS3(S3Config {
-                    bucket_name: parse_toml_string("bucket_name", bucket_name)?,
-                    bucket_region: parse_toml_string("bucket_region", bucket_region)?,
-                    prefix_in_bucket: toml
-                        .get("prefix_in_bucket")
-                        .map(|prefix_in_bucket| {
-                            parse_toml_string("prefix_in_bucket", prefix_in_bucket)
-                        })
-                        .transpose()?,
-                    endpoint,
-                    concurrency_limit,
-                    max_keys_per_list_response,
-                    upload_storage_class: toml
-                        .get("upload_storage_class")
-                        .map(|prefix_in_bucket| -> anyhow::Result<_> {
-                            let s = parse_toml_string("upload_storage_class", prefix_in_bucket)?;
-                            let storage_class = StorageClass::from_str(&s).expect("infallible");
-                            #[allow(deprecated)]
-                            if matches!(storage_class, StorageClass::Unknown(_)) {
-                                bail!("Specified storage class unknown to SDK: '{s}'. Allowed values: {:?}", StorageClass::values());
-                            }
-                            Ok(storage_class)
-                        })
-                        .transpose()?,
-                })
-            }
-            (_, _, _, Some(_), None) => {
-                bail!("'container_name' option is mandatory if 'container_region' is given ")
-            }
-            (_, _, _, None, Some(_)) => {
-                bail!("'container_name' option is mandatory if 'container_region' is given ")
-            }
-            (None, None, None, Some(container_name), Some(container_region)) => {
-                RemoteStorageKind::AzureContainer(AzureConfig {
-                    container_name: parse_toml_string("container_name", container_name)?,
-                    container_region: parse_toml_string("container_region", container_region)?,
-                    prefix_in_container: toml
-                        .get("prefix_in_container")
-                        .map(|prefix_in_container| {
-                            parse_toml_string("prefix_in_container", prefix_in_container)
-                        })
-                        .transpose()?,
-                    concurrency_limit,
-                    max_keys_per_list_response,
-                })
-            }
-            (Some(local_path), None, None, None, None) => RemoteStorageKind::LocalFs(
-                Utf8PathBuf::from(parse_toml_string("local_path", local_path)?),
-            ),
-            (Some(_), Some(_), ..) => {
-                bail!("'local_path' and 'bucket_name' are mutually exclusive")
-            }
-            (Some(_), _, _, Some(_), Some(_)) => {
-                bail!("local_path and 'container_name' are mutually exclusive")
-            }
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
+
+        let RemoteStorageKind::AwsS3(s3_config) = config.storage else {
+            panic!("expected S3 config, got {:?}", config.storage);
         };
+        assert_eq!(
+            s3_config.rps_limits,
+            RpsLimits {
+                get: NonZeroU32::new(5500),
+                put: NonZeroU32::new(3500),
+                ..Default::default()
+            }
+        );
 
-        Ok(Some(RemoteStorageConfig { storage, timeout }))
+        let input = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+rps_limits = { get = 0 }";
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+        assert!(RemoteStorageConfig::from_toml(toml.as_item()).is_err());
     }
-}
 
-// Helper functions to parse a toml Item
-fn parse_optional_integer<I, E>(name: &str, item: &toml_edit::Item) -> anyhow::Result<Option<I>>
-where
-    I: TryFrom<i64, Error = E>,
-    E: std::error::Error + Send + Sync + 'static,
-{
-    let toml_integer = match item.get(name) {
-        Some(item) => item
-            .as_integer()
-            .with_context(|| format!("configure option {name} is not an integer"))?,
-        None => return Ok(None),
-    };
+    #[test]
+    fn from_url_s3() {
+        let config = RemoteStorageConfig::from_url(
+            "s3://foo-bar/some/prefix/?region=eu-central-1&endpoint=http://127.0.0.1:5000&timeout=7s",
+        )
+        .unwrap();
+        let toml = "bucket_name = 'foo-bar'
+bucket_region = 'eu-central-1'
+prefix_in_bucket = 'some/prefix/'
+endpoint = 'http://127.0.0.1:5000'
+timeout = '7s'"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        assert_eq!(
+            config,
+            RemoteStorageConfig::from_toml(toml.as_item())
+                .unwrap()
+                .expect("it exists")
+        );
 
-    I::try_from(toml_integer)
-        .map(Some)
-        .with_context(|| format!("configure option {name} is too large"))
-}
+        let config = RemoteStorageConfig::from_url("s3://foo-bar?region=eu-central-1").unwrap();
+        assert_eq!(config.timeouts, RemoteStorageConfig::DEFAULT_TIMEOUT.into());
+        let RemoteStorageKind::AwsS3(s3_config) = config.storage else {
+            panic!("expected S3 config, got {:?}", config.storage);
+        };
+        assert_eq!(s3_config.prefix_in_bucket, None);
 
-fn parse_toml_string(name: &str, item: &Item) -> anyhow::Result<String> {
-    let s = item
-        .as_str()
-        .with_context(|| format!("configure option {name} is not a string"))?;
-    Ok(s.to_string())
-}
+        // The region is mandatory, unknown parameters are rejected
+        assert!(RemoteStorageConfig::from_url("s3://foo-bar/prefix").is_err());
+        assert!(RemoteStorageConfig::from_url("s3://foo-bar?region=eu-central-1&foo=bar").is_err());
+    }
 
-struct ConcurrencyLimiter {
-    // Every request to S3 can be throttled or cancelled, if a certain number of requests per second is exceeded.
-    // Same goes to IAM, which is queried before every S3 request, if enabled. IAM has even lower RPS threshold.
-    // The helps to ensure we don't exceed the thresholds.
-    write: Arc<Semaphore>,
-    read: Arc<Semaphore>,
-}
+    #[test]
+    fn from_url_azure() {
+        let config =
+            RemoteStorageConfig::from_url("azure://account/container/some/prefix").unwrap();
+        let RemoteStorageKind::AzureContainer(azure_config) = config.storage else {
+            panic!("expected Azure config, got {:?}", config.storage);
+        };
+        assert_eq!(azure_config.storage_account.as_deref(), Some("account"));
+        assert_eq!(azure_config.container_name, "container");
+        assert_eq!(
+            azure_config.prefix_in_container.as_deref(),
+            Some("some/prefix")
+        );
+        assert_eq!(
+            azure_config.concurrency_limit.get(),
+            DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT
+        );
 
-impl ConcurrencyLimiter {
-    fn for_kind(&self, kind: RequestKind) -> &Arc<Semaphore> {
-        match kind {
-            RequestKind::Get => &self.read,
-            RequestKind::Put => &self.write,
-            RequestKind::List => &self.read,
-            RequestKind::Delete => &self.write,
-            RequestKind::Copy => &self.write,
-            RequestKind::TimeTravel => &self.write,
-        }
+        assert!(RemoteStorageConfig::from_url("azure://account").is_err());
     }
 
-    async fn acquire(
-        &self,
-        kind: RequestKind,
-    ) -> Result<tokio::sync::SemaphorePermit<'_>, tokio::sync::AcquireError> {
-        self.for_kind(kind).acquire().await
-    }
+    #[test]
+    fn from_url_local_fs() {
+        let config = RemoteStorageConfig::from_url("file:///some/local/path?timeout=5s").unwrap();
+        assert_eq!(
+            config,
+            RemoteStorageConfig {
+                storage: RemoteStorageKind::LocalFs {
+                    local_path: Utf8PathBuf::from("/some/local/path"),
+                    sync_on_upload: false,
+                },
+                timeouts: Duration::from_secs(5).into(),
+            }
+        );
 
-    async fn acquire_owned(
-        &self,
-        kind: RequestKind,
-    ) -> Result<tokio::sync::OwnedSemaphorePermit, tokio::sync::AcquireError> {
-        Arc::clone(self.for_kind(kind)).acquire_owned().await
+        assert!(RemoteStorageConfig::from_url("gs://bucket/prefix").is_err());
+        assert!(RemoteStorageConfig::from_url("/some/local/path").is_err());
     }
 
-    fn new(limit: usize) -> ConcurrencyLimiter {
-        Self {
-            read: Arc::new(Semaphore::new(limit)),
-            write: Arc::new(Semaphore::new(limit)),
+    #[tokio::test(start_paused = true)]
+    async fn rate_limiter_limits_only_configured_kinds() {
+        let limiter = RateLimiter::new(&RpsLimits {
+            put: NonZeroU32::new(10),
+            ..Default::default()
+        });
+
+        let started_at = tokio::time::Instant::now();
+        // The initial burst of a full bucket is not delayed
+        for _ in 0..10 {
+            limiter.acquire(RequestKind::Put).await;
+        }
+        assert!(started_at.elapsed() < Duration::from_millis(100));
+        for _ in 0..1000 {
+            limiter.acquire(RequestKind::Get).await;
+        }
+        assert!(started_at.elapsed() < Duration::from_millis(100));
+
+        // Then requests are spread out at the configured rate
+        for _ in 0..10 {
+            limiter.acquire(RequestKind::Put).await;
         }
+        assert!(started_at.elapsed() >= Duration::from_millis(900));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test(start_paused = true)]
+    async fn rate_limiter_allows_limits_above_timer_resolution() {
+        // One token per nanosecond or less would be a zero refill interval
+        let limiter = RateLimiter::new(&RpsLimits {
+            get: Some(NonZeroU32::MAX),
+            put: NonZeroU32::new(5000),
+            ..Default::default()
+        });
+        limiter.acquire(RequestKind::Get).await;
+
+        let started_at = tokio::time::Instant::now();
+        for _ in 0..10_000 {
+            limiter.acquire(RequestKind::Put).await;
+        }
+        let elapsed = started_at.elapsed();
+        assert!(elapsed >= Duration::from_millis(900), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(1100), "{elapsed:?}");
+    }
 
     #[test]
-    fn test_object_name() {
-        let k = RemotePath::new(Utf8Path::new("a/b/c")).unwrap();
-        assert_eq!(k.object_name(), Some("c"));
+    fn parse_localfs_config_with_timeout() {
+        let input = "local_path = '.'
+timeout = '5s'";
 
-        let k = RemotePath::new(Utf8Path::new("a/b/c/")).unwrap();
-        assert_eq!(k.object_name(), Some("c"));
+        let toml = input.parse::<toml_edit::Document>().unwrap();
 
-        let k = RemotePath::new(Utf8Path::new("a/")).unwrap();
-        assert_eq!(k.object_name(), Some("a"));
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
 
-        // XXX is it impossible to have an empty key?
-        let k = RemotePath::new(Utf8Path::new("")).unwrap();
-        assert_eq!(k.object_name(), None);
+        assert_eq!(
+            config,
+            RemoteStorageConfig {
+                storage: RemoteStorageKind::LocalFs {
+                    local_path: Utf8PathBuf::from("."),
+                    sync_on_upload: false,
+                },
+                timeouts: Duration::from_secs(5).into()
+            }
+        );
     }
 
     #[test]
-    fn rempte_path_cannot_be_created_from_absolute_ones() {
-        let err = RemotePath::new(Utf8Path::new("/")).expect_err("Should fail on absolute paths");
-        assert_eq!(err.to_string(), "Path \"/\" is not relative");
+    fn parse_per_kind_timeouts() {
+        let input = "local_path = '.'
+timeout = '30s'
+timeouts = { list = '10m', put = '5m' }";
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
+        let timeouts = config.timeouts;
+        assert_eq!(
+            timeouts,
+            PerKindTimeouts {
+                default: Duration::from_secs(30),
+                get: Duration::from_secs(30),
+                put: Duration::from_secs(300),
+                list: Duration::from_secs(600),
+                delete: Duration::from_secs(30),
+                copy: Duration::from_secs(30),
+            }
+        );
+        assert_eq!(timeouts.for_kind(RequestKind::TimeTravel), timeouts.default);
+        assert_eq!(timeouts.max(), Duration::from_secs(600));
+
+        // Without `timeout`, the table overrides the default timeout
+        let input = "local_path = '.'
+timeouts = { get = '10s' }";
+        let toml = input.parse::<toml_edit::Document>().unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .expect("it exists");
+        assert_eq!(config.timeouts.get, Duration::from_secs(10));
+        assert_eq!(config.timeouts.list, RemoteStorageConfig::DEFAULT_TIMEOUT);
+
+        for invalid in [
+            "timeouts = { head = '10s' }",
+            "timeouts = { get = '0s' }",
+            "timeouts = { get = 10 }",
+            "timeouts = '10s'",
+        ] {
+            let input = format!("local_path = '.'\n{invalid}");
+            let toml = input.parse::<toml_edit::Document>().unwrap();
+            assert!(
+                RemoteStorageConfig::from_toml(toml.as_item()).is_err(),
+                "{invalid}"
+            );
+        }
     }
 
     #[test]
-    fn parse_localfs_config_with_timeout() {
+    fn parse_localfs_config_with_sync_on_upload() {
         let input = "local_path = '.'
-timeout = '5s'";
+sync_on_upload = true";
 
         let toml = input.parse::<toml_edit::Document>().unwrap();
 
@@ -849,10 +4842,10 @@ timeout = '5s'";
             .expect("it exists");
 
         assert_eq!(
-            config,
-            RemoteStorageConfig {
-                storage: RemoteStorageKind::LocalFs(Utf8PathBuf::from(".")),
-                timeout: Duration::from_secs(5)
+            config.storage,
+            RemoteStorageKind::LocalFs {
+                local_path: Utf8PathBuf::from("."),
+                sync_on_upload: true,
             }
         );
     }
@@ -5,9 +5,10 @@
 //! volume is mounted to the local FS.
 
 use std::{
-    collections::HashSet,
+    collections::{BTreeSet, VecDeque},
     io::ErrorKind,
     num::NonZeroU32,
+    sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
@@ -15,16 +16,21 @@ use anyhow::{bail, ensure, Context};
 use bytes::Bytes;
 use camino::{Utf8Path, Utf8PathBuf};
 use futures::stream::Stream;
+use rand::Rng;
 use tokio::{
     fs,
     io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
 };
 use tokio_util::{io::ReaderStream, sync::CancellationToken};
-use utils::crashsafe::path_with_suffix_extension;
+use utils::crashsafe::{durable_rename, path_with_suffix_extension};
 
 use crate::{
-    Download, DownloadError, Listing, ListingMode, RemotePath, TimeTravelError, TimeoutOrCancel,
-    REMOTE_STORAGE_PREFIX_SEPARATOR,
+    metrics::RequestKind,
+    traffic::{CountingDownload, TrafficCounters},
+    Compression, CopyMetadata, Download, DownloadError, ListOptions, Listing, ListingMode,
+    ListingObject, PerKindTimeouts, PreconditionFailed, RemotePath, RemoteStorageConfig,
+    RemoteStorageKind, StorageDescription, TimeTravelError, TimeTravelSummary, TimeoutOrCancel,
+    TrafficStats, UploadOptions, REMOTE_STORAGE_PREFIX_SEPARATOR,
 };
 
 use super::{RemoteStorage, StorageMetadata};
@@ -35,13 +41,25 @@ const LOCAL_FS_TEMP_FILE_SUFFIX: &str = "___temp";
 #[derive(Debug, Clone)]
 pub struct LocalFs {
     storage_root: Utf8PathBuf,
-    timeout: Duration,
+    timeouts: PerKindTimeouts,
+    sync_on_upload: bool,
+    // Shared by the clones, like the files themselves
+    traffic: Arc<TrafficCounters>,
 }
 
 impl LocalFs {
     /// Attempts to create local FS storage, along with its root directory.
     /// Storage root will be created (if does not exist) and transformed into an absolute path (if passed as relative).
-    pub fn new(mut storage_root: Utf8PathBuf, timeout: Duration) -> anyhow::Result<Self> {
+    ///
+    /// With `sync_on_upload`, uploaded files, their metadata and any directories created for them
+    /// are fsynced before the upload returns, so that they survive an unclean shutdown. This is
+    /// off in tests to keep them fast, but should be enabled when `LocalFs` is the actual storage
+    /// of a single node deployment.
+    pub fn new(
+        mut storage_root: Utf8PathBuf,
+        timeouts: impl Into<PerKindTimeouts>,
+        sync_on_upload: bool,
+    ) -> anyhow::Result<Self> {
         if !storage_root.exists() {
             std::fs::create_dir_all(&storage_root).with_context(|| {
                 format!("Failed to create all directories in the given root path {storage_root:?}")
@@ -55,10 +73,17 @@ impl LocalFs {
 
         Ok(Self {
             storage_root,
-            timeout,
+            timeouts: timeouts.into(),
+            sync_on_upload,
+            traffic: Arc::default(),
         })
     }
 
+    /// The absolute directory all objects are stored under.
+    pub fn storage_root(&self) -> &Utf8Path {
+        &self.storage_root
+    }
+
     // mirrors S3Bucket::s3_object_to_relative_path
     fn local_file_to_relative_path(&self, key: Utf8PathBuf) -> RemotePath {
         let relative_path = key
@@ -83,7 +108,8 @@ impl LocalFs {
                         "Failed to deserialize metadata from the local storage at '{metadata_path}'",
                     )
                 })
-                .map(|metadata| Some(StorageMetadata(metadata)))
+                // Sidecars written before keys were normalized may still carry uppercase ones
+                .map(|metadata| Some(StorageMetadata(metadata).normalized()))
         } else {
             Ok(None)
         }
@@ -216,46 +242,47 @@ impl LocalFs {
         Ok(files)
     }
 
+    /// Writes `data` to a temp file and renames it into place. With `data_size_bytes`, the
+    /// stream must have exactly that size, otherwise it is read to its end.
     async fn upload0(
         &self,
         data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync,
-        data_size_bytes: usize,
+        data_size_bytes: Option<usize>,
         to: &RemotePath,
         metadata: Option<StorageMetadata>,
+        content_encoding: Option<Compression>,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
         let target_file_path = to.with_base(&self.storage_root);
-        create_target_directory(&target_file_path).await?;
-        // We need this dance with sort of durable rename (without fsyncs)
+        create_target_directory(&target_file_path, self.sync_on_upload).await?;
+        // We need this dance with sort of durable rename (fsyncs only with `sync_on_upload`)
         // to prevent partial uploads. This was really hit when pageserver shutdown
-        // cancelled the upload and partial file was left on the fs
-        // NOTE: Because temp file suffix always the same this operation is racy.
-        // Two concurrent operations can lead to the following sequence:
-        // T1: write(temp)
-        // T2: write(temp) -> overwrites the content
-        // T1: rename(temp, dst) -> succeeds
-        // T2: rename(temp, dst) -> fails, temp no longet exists
-        // This can be solved by supplying unique temp suffix every time, but this situation
-        // is not normal in the first place, the error can help (and helped at least once)
-        // to discover bugs in upper level synchronization.
-        let temp_file_path =
-            path_with_suffix_extension(&target_file_path, LOCAL_FS_TEMP_FILE_SUFFIX);
+        // cancelled the upload and partial file was left on the fs.
+        // Every upload gets its own temp file, so that an upload which was cancelled or timed
+        // out, but whose writes are still in flight, can't interfere with the next attempt.
+        let temp_file_path = unique_temp_path(&target_file_path);
         let mut destination = io::BufWriter::new(
             fs::OpenOptions::new()
                 .write(true)
-                .create(true)
-                .truncate(true)
+                .create_new(true)
                 .open(&temp_file_path)
                 .await
                 .with_context(|| {
                     format!("Failed to open target fs destination at '{target_file_path}'")
                 })?,
         );
+        // Removes the temp file on any error, and also if this future is dropped midway
+        let temp_file_guard = scopeguard::guard(temp_file_path.clone(), |temp_file_path| {
+            if let Err(e) = std::fs::remove_file(&temp_file_path) {
+                if e.kind() != ErrorKind::NotFound {
+                    tracing::warn!("Failed to remove temp file '{temp_file_path}': {e}");
+                }
+            }
+        });
 
-        let from_size_bytes = data_size_bytes as u64;
         let data = tokio_util::io::StreamReader::new(data);
         let data = std::pin::pin!(data);
-        let mut buffer_to_read = data.take(from_size_bytes);
+        let mut buffer_to_read = data.take(data_size_bytes.map_or(u64::MAX, |size| size as u64));
 
         // alternatively we could just write the bytes to a file, but local_fs is a testing utility
         let copy = io::copy_buf(&mut buffer_to_read, &mut destination);
@@ -264,13 +291,8 @@ impl LocalFs {
             biased;
             _ = cancel.cancelled() => {
                 let file = destination.into_inner();
-                // wait for the inflight operation(s) to complete so that there could be a next
-                // attempt right away and our writes are not directed to their file.
+                // wait for the inflight operation(s) to complete before the temp file is removed
                 file.into_std().await;
-
-                // TODO: leave the temp or not? leaving is probably less racy. enabled truncate at
-                // least.
-                fs::remove_file(temp_file_path).await.context("remove temp_file_path after cancellation or timeout")?;
                 return Err(TimeoutOrCancel::Cancel.into());
             }
             read = copy => read,
@@ -283,16 +305,18 @@ impl LocalFs {
                 )
             })?;
 
-        if bytes_read < from_size_bytes {
-            bail!("Provided stream was shorter than expected: {bytes_read} vs {from_size_bytes} bytes");
+        if let Some(from_size_bytes) = data_size_bytes.map(|size| size as u64) {
+            if bytes_read < from_size_bytes {
+                bail!("Provided stream was shorter than expected: {bytes_read} vs {from_size_bytes} bytes");
+            }
+            // Check if there is any extra data after the given size.
+            let mut from = buffer_to_read.into_inner();
+            let extra_read = from.read(&mut [1]).await?;
+            ensure!(
+                extra_read == 0,
+                "Provided stream was larger than expected: expected {from_size_bytes} bytes",
+            );
         }
-        // Check if there is any extra data after the given size.
-        let mut from = buffer_to_read.into_inner();
-        let extra_read = from.read(&mut [1]).await?;
-        ensure!(
-            extra_read == 0,
-            "Provided stream was larger than expected: expected {from_size_bytes} bytes",
-        );
 
         destination.flush().await.with_context(|| {
             format!(
@@ -300,34 +324,102 @@ impl LocalFs {
             )
         })?;
 
-        fs::rename(temp_file_path, &target_file_path)
+        // Close the file before the rename, `durable_rename` reopens it for the fsync
+        drop(destination);
+
+        durable_rename(&temp_file_path, &target_file_path, self.sync_on_upload)
             .await
             .with_context(|| {
                 format!(
                     "Failed to upload (rename) file to the local storage at '{target_file_path}'",
                 )
             })?;
+        // Renamed into place, nothing left to clean up
+        scopeguard::ScopeGuard::into_inner(temp_file_guard);
 
         if let Some(storage_metadata) = metadata {
             // FIXME: we must not be using metadata much, since this would forget the old metadata
             // for new writes? or perhaps metadata is sticky; could consider removing if it's never
             // used.
-            let storage_metadata_path = storage_metadata_path(&target_file_path);
-            fs::write(
-                &storage_metadata_path,
-                serde_json::to_string(&storage_metadata.0)
-                    .context("Failed to serialize storage metadata as json")?,
-            )
-            .await
-            .with_context(|| {
-                format!(
-                    "Failed to write metadata to the local storage at '{storage_metadata_path}'",
-                )
-            })?;
+            write_storage_metadata(&target_file_path, storage_metadata, self.sync_on_upload)
+                .await?;
         }
 
+        // Unlike the metadata, the encoding describes the bytes just written, so a stale one
+        // must not survive an overwrite.
+        write_content_encoding(&target_file_path, content_encoding, self.sync_on_upload).await?;
+
+        self.traffic.record_upload(bytes_read as usize);
         Ok(())
     }
+
+    async fn upload_with_timeout(
+        &self,
+        data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync,
+        data_size_bytes: Option<usize>,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let UploadOptions {
+            metadata,
+            content_encoding,
+            content_md5,
+            // There is no access control on the local file system
+            acl: _,
+            object_lock,
+        } = options;
+        if let Some(object_lock) = object_lock {
+            bail!("cannot upload {to} with {object_lock:?}: the local file system can't enforce retention");
+        }
+        let data = crate::support::Md5Verifying::new(data, data_size_bytes, content_md5);
+
+        self.traffic.record_request();
+        let cancel = cancel.child_token();
+
+        let op = self.upload0(
+            data,
+            data_size_bytes,
+            to,
+            metadata,
+            content_encoding,
+            &cancel,
+        );
+        let mut op = std::pin::pin!(op);
+
+        // race the upload0 to the timeout; if it goes over, do a graceful shutdown
+        let (res, timeout) = tokio::select! {
+            res = &mut op => (res, false),
+            _ = tokio::time::sleep(self.timeouts.for_kind(RequestKind::Put)) => {
+                cancel.cancel();
+                (op.await, true)
+            }
+        };
+
+        match res {
+            Err(e) if timeout && TimeoutOrCancel::caused_by_cancel(&e) => {
+                // we caused this cancel (or they happened simultaneously) -- swap it out to
+                // Timeout
+                Err(TimeoutOrCancel::Timeout.into())
+            }
+            res => res,
+        }
+    }
+
+    /// Like an object's `Content-Encoding`, the encoding is kept in a sidecar file next to it.
+    async fn read_content_encoding(
+        &self,
+        file_path: &Utf8Path,
+    ) -> anyhow::Result<Option<Compression>> {
+        let encoding_path = content_encoding_path(file_path);
+        match fs::read_to_string(&encoding_path).await {
+            Ok(encoding) => Ok(Compression::from_content_encoding(&encoding)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow::Error::new(e).context(format!(
+                "Failed to read content encoding from the local storage at '{encoding_path}'"
+            ))),
+        }
+    }
 }
 
 impl RemoteStorage for LocalFs {
@@ -336,8 +428,10 @@ impl RemoteStorage for LocalFs {
         prefix: Option<&RemotePath>,
         mode: ListingMode,
         max_keys: Option<NonZeroU32>,
+        options: ListOptions,
         cancel: &CancellationToken,
     ) -> Result<Listing, DownloadError> {
+        self.traffic.record_request();
         let op = async {
             let mut result = Listing::default();
 
@@ -346,50 +440,68 @@ impl RemoteStorage for LocalFs {
                 .list_recursive(prefix)
                 .await
                 .map_err(DownloadError::Other)?;
-            let keys = keys
-                .into_iter()
-                .filter(|k| {
-                    let path = k.with_base(&self.storage_root);
-                    !path.is_dir()
-                })
-                .collect();
+            let mut objects = Vec::with_capacity(keys.len());
+            for key in keys {
+                let path = key.with_base(&self.storage_root);
+                let metadata = match file_metadata(&path).await {
+                    Ok(metadata) => metadata,
+                    // The file was removed since we listed its directory
+                    Err(DownloadError::NotFound(_)) => continue,
+                    Err(e) => return Err(e),
+                };
+                if metadata.is_dir() {
+                    continue;
+                }
+                // Content encodings are part of their objects, not objects of their own
+                if path.extension() == Some(CONTENT_ENCODING_EXTENSION) {
+                    continue;
+                }
+                let last_modified = metadata.modified().map_err(|e| {
+                    DownloadError::Other(anyhow::anyhow!(e).context("Reading mtime"))
+                })?;
+                let storage_metadata = if options.with_metadata {
+                    self.read_storage_metadata(&path)
+                        .await
+                        .map_err(DownloadError::Other)?
+                } else {
+                    None
+                };
+                objects.push(ListingObject {
+                    key,
+                    last_modified,
+                    size: metadata.len(),
+                    metadata: storage_metadata,
+                    version_id: None,
+                });
+            }
+            let is_modified_since = |o: &ListingObject| match options.modified_since {
+                Some(since) => o.last_modified >= since,
+                None => true,
+            };
+
+            // S3 lists keys in the byte order of their UTF-8 encoding, which is not the order of
+            // paths, e.g. `a-b` comes before `a/b`. Sort the same way before applying `max_keys`.
+            objects.sort_by(|a, b| a.key.get_path().as_str().cmp(b.key.get_path().as_str()));
 
             if let ListingMode::NoDelimiter = mode {
-                result.keys = keys;
+                result.objects = objects.into_iter().filter(is_modified_since).collect();
             } else {
-                let mut prefixes = HashSet::new();
-                for key in keys {
-                    // If the part after the prefix includes a "/", take only the first part and put it in `prefixes`.
-                    let relative_key = if let Some(prefix) = prefix {
-                        let mut prefix = prefix.clone();
-                        // We only strip the dirname of the prefix, so that when we strip it from the start of keys we
-                        // end up with full file/dir names.
-                        let prefix_full_local_path = prefix.with_base(&self.storage_root);
-                        let has_slash = prefix.0.to_string().ends_with('/');
-                        let strip_prefix = if prefix_full_local_path.is_dir() && has_slash {
-                            prefix
-                        } else {
-                            prefix.0.pop();
-                            prefix
-                        };
-
-                        RemotePath::new(key.strip_prefix(&strip_prefix).unwrap()).unwrap()
-                    } else {
-                        key
+                // Like on S3, keys and prefixes are full paths rather than relative to the listed
+                // prefix, and the prefixes span up to the first delimiter after the listed prefix.
+                let list_prefix = prefix.map(|p| p.get_path().as_str()).unwrap_or_default();
+                let mut prefixes = BTreeSet::new();
+                for object in objects {
+                    let key = object.key.get_path().as_str();
+                    // Only paths starting with the listed prefix were listed
+                    let Some(relative_key) = key.strip_prefix(list_prefix) else {
+                        continue;
                     };
-
-                    let relative_key = format!("{}", relative_key);
-                    if relative_key.contains(REMOTE_STORAGE_PREFIX_SEPARATOR) {
-                        let first_part = relative_key
-                            .split(REMOTE_STORAGE_PREFIX_SEPARATOR)
-                            .next()
-                            .unwrap()
-                            .to_owned();
-                        prefixes.insert(first_part);
-                    } else {
-                        result
-                            .keys
-                            .push(RemotePath::from_string(&relative_key).unwrap());
+                    match relative_key.find(REMOTE_STORAGE_PREFIX_SEPARATOR) {
+                        Some(delimiter) => {
+                            prefixes.insert(key[..list_prefix.len() + delimiter].to_owned());
+                        }
+                        None if is_modified_since(&object) => result.objects.push(object),
+                        None => {}
                     }
                 }
                 result.prefixes = prefixes
@@ -399,19 +511,19 @@ impl RemoteStorage for LocalFs {
             }
 
             if let Some(max_keys) = max_keys {
-                result.keys.truncate(max_keys.get() as usize);
+                result.objects.truncate(max_keys.get() as usize);
             }
             Ok(result)
         };
 
         let timeout = async {
-            tokio::time::sleep(self.timeout).await;
-            Err(DownloadError::Timeout)
+            tokio::time::sleep(self.timeouts.for_kind(RequestKind::List)).await;
+            Err(DownloadError::Timeout(None))
         };
 
         let cancelled = async {
             cancel.cancelled().await;
-            Err(DownloadError::Cancelled)
+            Err(DownloadError::Cancelled(None))
         };
 
         tokio::select! {
@@ -421,43 +533,91 @@ impl RemoteStorage for LocalFs {
         }
     }
 
+    fn list_prefixes_recursive<'a>(
+        &'a self,
+        prefix: Option<&'a RemotePath>,
+        _cancel: &'a CancellationToken,
+    ) -> impl Stream<Item = Result<RemotePath, DownloadError>> + 'a {
+        let root = match prefix {
+            Some(prefix) => prefix.with_base(&self.storage_root),
+            None => self.storage_root.clone(),
+        };
+        // Breadth first, like the walk over object store prefixes
+        futures::stream::unfold(
+            (VecDeque::from([root]), VecDeque::new()),
+            move |(mut to_list, mut found)| async move {
+                loop {
+                    if let Some(dir) = found.pop_front() {
+                        let prefix = self.local_file_to_relative_path(dir);
+                        return Some((Ok(prefix), (to_list, found)));
+                    }
+                    let dir = to_list.pop_front()?;
+                    match read_subdirectories(&dir).await {
+                        Ok(subdirs) => {
+                            to_list.extend(subdirs.iter().cloned());
+                            found.extend(subdirs);
+                        }
+                        Err(e) => {
+                            let e = DownloadError::Other(
+                                e.context(format!("Failed to list directory '{dir}'")),
+                            );
+                            return Some((Err(e), (VecDeque::new(), VecDeque::new())));
+                        }
+                    }
+                }
+            },
+        )
+    }
+
     async fn upload(
         &self,
         data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync,
         data_size_bytes: usize,
         to: &RemotePath,
-        metadata: Option<StorageMetadata>,
+        options: UploadOptions,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
-        let cancel = cancel.child_token();
+        self.upload_with_timeout(data, Some(data_size_bytes), to, options, cancel)
+            .await
+    }
 
-        let op = self.upload0(data, data_size_bytes, to, metadata, &cancel);
-        let mut op = std::pin::pin!(op);
+    async fn upload_unsized(
+        &self,
+        data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.upload_with_timeout(data, None, to, options, cancel)
+            .await
+    }
 
-        // race the upload0 to the timeout; if it goes over, do a graceful shutdown
-        let (res, timeout) = tokio::select! {
-            res = &mut op => (res, false),
-            _ = tokio::time::sleep(self.timeout) => {
-                cancel.cancel();
-                (op.await, true)
-            }
-        };
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_with_deadline(from, None, cancel).await
+    }
 
-        match res {
-            Err(e) if timeout && TimeoutOrCancel::caused_by_cancel(&e) => {
-                // we caused this cancel (or they happened simultaneously) -- swap it out to
-                // Timeout
-                Err(TimeoutOrCancel::Timeout.into())
-            }
-            res => res,
-        }
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_byte_range_with_deadline(from, start_inclusive, end_exclusive, None, cancel)
+            .await
     }
 
-    async fn download(
+    async fn download_with_deadline(
         &self,
         from: &RemotePath,
+        deadline: Option<tokio::time::Instant>,
         cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
+        self.traffic.record_request();
         let target_path = from.with_base(&self.storage_root);
 
         let file_metadata = file_metadata(&target_path).await?;
@@ -477,9 +637,17 @@ impl RemoteStorage for LocalFs {
             .read_storage_metadata(&target_path)
             .await
             .map_err(DownloadError::Other)?;
+        let content_encoding = self
+            .read_content_encoding(&target_path)
+            .await
+            .map_err(DownloadError::Other)?;
 
-        let cancel_or_timeout = crate::support::cancel_or_timeout(self.timeout, cancel.clone());
+        let cancel_or_timeout = crate::support::cancel_or_timeout(
+            crate::support::timeout_until(self.timeouts.for_kind(RequestKind::Get), deadline),
+            cancel.clone(),
+        );
         let source = crate::support::DownloadStream::new(cancel_or_timeout, source);
+        let source = CountingDownload::new(self.traffic.clone(), source);
 
         let etag = mock_etag(&file_metadata);
         Ok(Download {
@@ -488,25 +656,23 @@ impl RemoteStorage for LocalFs {
                 .modified()
                 .map_err(|e| DownloadError::Other(anyhow::anyhow!(e).context("Reading mtime")))?,
             etag,
+            content_length: file_metadata.len(),
+            object_size: file_metadata.len(),
             download_stream: Box::pin(source),
+            content_encoding,
         })
     }
 
-    async fn download_byte_range(
+    async fn download_byte_range_with_deadline(
         &self,
         from: &RemotePath,
         start_inclusive: u64,
         end_exclusive: Option<u64>,
+        deadline: Option<tokio::time::Instant>,
         cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
-        if let Some(end_exclusive) = end_exclusive {
-            if end_exclusive <= start_inclusive {
-                return Err(DownloadError::Other(anyhow::anyhow!("Invalid range, start ({start_inclusive}) is not less than end_exclusive ({end_exclusive:?})")));
-            };
-            if start_inclusive == end_exclusive.saturating_sub(1) {
-                return Err(DownloadError::Other(anyhow::anyhow!("Invalid range, start ({start_inclusive}) and end_exclusive ({end_exclusive:?}) difference is zero bytes")));
-            }
-        }
+        self.traffic.record_request();
+        crate::support::check_byte_range(start_inclusive, end_exclusive)?;
 
         let target_path = from.with_base(&self.storage_root);
         let file_metadata = file_metadata(&target_path).await?;
@@ -525,6 +691,12 @@ impl RemoteStorage for LocalFs {
             .context("query file length")
             .map_err(DownloadError::Other)?
             .len();
+        // Like S3, which answers 416 for these, while an open range from 0 is the whole object
+        if start_inclusive > 0 && start_inclusive >= len {
+            return Err(DownloadError::InvalidRange(anyhow::anyhow!(
+                "range starts at {start_inclusive}, past the end of the {len} bytes object"
+            )));
+        }
 
         source
             .seek(io::SeekFrom::Start(start_inclusive))
@@ -536,12 +708,23 @@ impl RemoteStorage for LocalFs {
             .read_storage_metadata(&target_path)
             .await
             .map_err(DownloadError::Other)?;
+        let content_encoding = self
+            .read_content_encoding(&target_path)
+            .await
+            .map_err(DownloadError::Other)?;
 
-        let source = source.take(end_exclusive.unwrap_or(len) - start_inclusive);
+        // Without an end, read up to EOF. An end past EOF is clamped to it, like S3 does.
+        let end_exclusive = end_exclusive.map_or(len, |end| end.min(len));
+        let content_length = end_exclusive.saturating_sub(start_inclusive);
+        let source = source.take(content_length);
         let source = ReaderStream::new(source);
 
-        let cancel_or_timeout = crate::support::cancel_or_timeout(self.timeout, cancel.clone());
+        let cancel_or_timeout = crate::support::cancel_or_timeout(
+            crate::support::timeout_until(self.timeouts.for_kind(RequestKind::Get), deadline),
+            cancel.clone(),
+        );
         let source = crate::support::DownloadStream::new(cancel_or_timeout, source);
+        let source = CountingDownload::new(self.traffic.clone(), source);
 
         let etag = mock_etag(&file_metadata);
         Ok(Download {
@@ -550,20 +733,87 @@ impl RemoteStorage for LocalFs {
                 .modified()
                 .map_err(|e| DownloadError::Other(anyhow::anyhow!(e).context("Reading mtime")))?,
             etag,
+            content_length,
+            object_size: len,
             download_stream: Box::pin(source),
+            content_encoding,
         })
     }
 
-    async fn delete(&self, path: &RemotePath, _cancel: &CancellationToken) -> anyhow::Result<()> {
+    async fn head_object(
+        &self,
+        key: &RemotePath,
+        _cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError> {
+        self.traffic.record_request();
+        let target_path = key.with_base(&self.storage_root);
+        let file_metadata = file_metadata(&target_path).await?;
+        // Like in listings, directories are not objects
+        if file_metadata.is_dir() {
+            return Err(DownloadError::NotFound(None));
+        }
+        Ok(ListingObject {
+            key: key.clone(),
+            last_modified: file_metadata
+                .modified()
+                .map_err(|e| DownloadError::Other(anyhow::anyhow!(e).context("Reading mtime")))?,
+            size: file_metadata.len(),
+            metadata: self
+                .read_storage_metadata(&target_path)
+                .await
+                .map_err(DownloadError::Other)?,
+            version_id: None,
+        })
+    }
+
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.delete_if_exists(path, cancel).await.map(|_| ())
+    }
+
+    async fn delete_if_exists(
+        &self,
+        path: &RemotePath,
+        _cancel: &CancellationToken,
+    ) -> anyhow::Result<bool> {
+        self.traffic.record_request();
         let file_path = path.with_base(&self.storage_root);
-        match fs::remove_file(&file_path).await {
-            Ok(()) => Ok(()),
+        let existed = match fs::remove_file(&file_path).await {
+            Ok(()) => true,
             // The file doesn't exist. This shouldn't yield an error to mirror S3's behaviour.
             // See https://docs.aws.amazon.com/AmazonS3/latest/API/API_DeleteObject.html
             // > If there isn't a null version, Amazon S3 does not remove any objects but will still respond that the command was successful.
-            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
-            Err(e) => Err(anyhow::anyhow!(e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => false,
+            Err(e) => return Err(anyhow::anyhow!(e)),
+        };
+        // The content encoding is part of the object, so it goes with it
+        match fs::remove_file(content_encoding_path(&file_path)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(anyhow::anyhow!(e)),
+        }
+        Ok(existed)
+    }
+
+    async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let file_path = path.with_base(&self.storage_root);
+        let current = match fs::metadata(&file_path).await {
+            Ok(metadata) => mock_etag(&metadata),
+            // Already deleted, which is fine, like for `delete`
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(anyhow::anyhow!(e)),
+        };
+        if !current.eq_ignoring_weak(etag) {
+            return Err(
+                anyhow::anyhow!("{path} has ETag {current}, expected {etag}")
+                    .context(PreconditionFailed),
+            );
         }
+        self.delete(path, cancel).await
     }
 
     async fn delete_objects<'a>(
@@ -581,11 +831,13 @@ impl RemoteStorage for LocalFs {
         &self,
         from: &RemotePath,
         to: &RemotePath,
+        metadata: CopyMetadata,
         _cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
+        self.traffic.record_request();
         let from_path = from.with_base(&self.storage_root);
         let to_path = to.with_base(&self.storage_root);
-        create_target_directory(&to_path).await?;
+        create_target_directory(&to_path, self.sync_on_upload).await?;
         fs::copy(&from_path, &to_path).await.with_context(|| {
             format!(
                 "Failed to copy file from '{from_path}' to '{to_path}'",
@@ -593,31 +845,202 @@ impl RemoteStorage for LocalFs {
                 to_path = to_path
             )
         })?;
+
+        // The metadata sidecar must follow the object, and must not be left over from a
+        // previous object at the target path either.
+        let metadata = match metadata {
+            CopyMetadata::Preserve => self.read_storage_metadata(&from_path).await?,
+            CopyMetadata::Replace(metadata) => Some(metadata),
+        };
+        match metadata {
+            Some(metadata) => {
+                write_storage_metadata(&to_path, metadata, self.sync_on_upload).await?
+            }
+            None => {
+                let to_metadata_path = storage_metadata_path(&to_path);
+                match fs::remove_file(&to_metadata_path).await {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => {
+                        return Err(anyhow::Error::new(e).context(format!(
+                            "Failed to remove stale metadata at '{to_metadata_path}'"
+                        )))
+                    }
+                }
+            }
+        }
+        let content_encoding = self.read_content_encoding(&from_path).await?;
+        write_content_encoding(&to_path, content_encoding, self.sync_on_upload).await?;
         Ok(())
     }
 
+    async fn touch(&self, path: &RemotePath, _cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.traffic.record_request();
+        let target_path = path.with_base(&self.storage_root);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .open(&target_path)
+            .await
+            .with_context(|| format!("Failed to open file '{target_path}' to touch"))?;
+        file.into_std()
+            .await
+            .set_modified(SystemTime::now())
+            .with_context(|| format!("Failed to set mtime of file '{target_path}'"))
+    }
+
     async fn time_travel_recover(
         &self,
         _prefix: Option<&RemotePath>,
         _timestamp: SystemTime,
         _done_if_after: SystemTime,
         _cancel: &CancellationToken,
-    ) -> Result<(), TimeTravelError> {
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
         Err(TimeTravelError::Unimplemented)
     }
+
+    async fn abort_incomplete_uploads(
+        &self,
+        _prefix: Option<&RemotePath>,
+        _older_than: Duration,
+        _cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        // Uploads are written to a temporary file, there are no parts to clean up
+        Ok(0)
+    }
+
+    fn traffic_stats(&self) -> TrafficStats {
+        self.traffic.stats()
+    }
+
+    fn describe(&self) -> StorageDescription {
+        StorageDescription {
+            backend: "localfs",
+            bucket_or_container: self.storage_root.to_string(),
+            prefix: None,
+            endpoint: None,
+            region: None,
+        }
+    }
+
+    fn redacted_config(&self) -> serde_json::Value {
+        RemoteStorageConfig {
+            storage: RemoteStorageKind::LocalFs {
+                local_path: self.storage_root.clone(),
+                sync_on_upload: self.sync_on_upload,
+            },
+            timeouts: self.timeouts,
+        }
+        .redacted_config()
+    }
 }
 
 fn storage_metadata_path(original_path: &Utf8Path) -> Utf8PathBuf {
     path_with_suffix_extension(original_path, "metadata")
 }
 
-async fn create_target_directory(target_file_path: &Utf8Path) -> anyhow::Result<()> {
+/// Sorted subdirectories of `dir`, skipping symlinks. A missing `dir`, or a file, has none, like
+/// a prefix without objects.
+async fn read_subdirectories(dir: &Utf8Path) -> anyhow::Result<Vec<Utf8PathBuf>> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound || !dir.is_dir() => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut subdirs = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            let path = Utf8PathBuf::from_path_buf(entry.path())
+                .map_err(|pb| anyhow::anyhow!("non-Unicode path: {}", pb.to_string_lossy()))?;
+            subdirs.push(path);
+        }
+    }
+    subdirs.sort();
+    Ok(subdirs)
+}
+
+/// Extension of the sidecar file which holds the content encoding of an object.
+const CONTENT_ENCODING_EXTENSION: &str = "content_encoding";
+
+fn content_encoding_path(original_path: &Utf8Path) -> Utf8PathBuf {
+    path_with_suffix_extension(original_path, CONTENT_ENCODING_EXTENSION)
+}
+
+/// Writes the content encoding sidecar of `target_file_path`, or removes it for `None`.
+async fn write_content_encoding(
+    target_file_path: &Utf8Path,
+    content_encoding: Option<Compression>,
+    sync: bool,
+) -> anyhow::Result<()> {
+    let encoding_path = content_encoding_path(target_file_path);
+    let Some(content_encoding) = content_encoding else {
+        return match fs::remove_file(&encoding_path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(anyhow::Error::new(e).context(format!(
+                "Failed to remove stale content encoding at '{encoding_path}'"
+            ))),
+        };
+    };
+    let temp_path = unique_temp_path(&encoding_path);
+    fs::write(&temp_path, content_encoding.as_content_encoding())
+        .await
+        .with_context(|| {
+            format!("Failed to write content encoding to the local storage at '{temp_path}'")
+        })?;
+    durable_rename(&temp_path, &encoding_path, sync)
+        .await
+        .with_context(|| {
+            format!("Failed to write content encoding to the local storage at '{encoding_path}'")
+        })
+}
+
+async fn write_storage_metadata(
+    target_file_path: &Utf8Path,
+    storage_metadata: StorageMetadata,
+    sync: bool,
+) -> anyhow::Result<()> {
+    let storage_metadata_path = storage_metadata_path(target_file_path);
+    let temp_path = unique_temp_path(&storage_metadata_path);
+    fs::write(
+        &temp_path,
+        serde_json::to_string(&storage_metadata.normalized().0)
+            .context("Failed to serialize storage metadata as json")?,
+    )
+    .await
+    .with_context(|| format!("Failed to write metadata to the local storage at '{temp_path}'",))?;
+    durable_rename(&temp_path, &storage_metadata_path, sync)
+        .await
+        .with_context(|| {
+            format!("Failed to write metadata to the local storage at '{storage_metadata_path}'",)
+        })
+}
+
+/// Picks a fresh temp file name next to `path`, so that concurrent writers of the same
+/// file never share a temp file.
+fn unique_temp_path(path: &Utf8Path) -> Utf8PathBuf {
+    path_with_suffix_extension(
+        path,
+        &format!(
+            "{:08x}.{LOCAL_FS_TEMP_FILE_SUFFIX}",
+            rand::thread_rng().gen::<u32>()
+        ),
+    )
+}
+
+async fn create_target_directory(target_file_path: &Utf8Path, sync: bool) -> anyhow::Result<()> {
     let target_dir = match target_file_path.parent() {
         Some(parent_dir) => parent_dir,
         None => bail!("File path '{target_file_path}' has no parent directory"),
     };
     if !target_dir.exists() {
-        fs::create_dir_all(target_dir).await?;
+        if sync {
+            let target_dir = target_dir.to_owned();
+            tokio::task::spawn_blocking(move || utils::crashsafe::create_dir_all(target_dir))
+                .await
+                .context("create_dir_all task panicked")??;
+        } else {
+            fs::create_dir_all(target_dir).await?;
+        }
     }
     Ok(())
 }
@@ -625,7 +1048,7 @@ async fn create_target_directory(target_file_path: &Utf8Path) -> anyhow::Result<
 async fn file_metadata(file_path: &Utf8Path) -> Result<std::fs::Metadata, DownloadError> {
     tokio::fs::metadata(&file_path).await.map_err(|e| {
         if e.kind() == ErrorKind::NotFound {
-            DownloadError::NotFound
+            DownloadError::NotFound(None)
         } else {
             DownloadError::BadInput(e.into())
         }
@@ -643,9 +1066,13 @@ fn mock_etag(meta: &std::fs::Metadata) -> Etag {
 #[cfg(test)]
 mod fs_tests {
     use super::*;
+    use crate::{ContinuationToken, ObjectLockConfig, ObjectLockMode, PrefixSize};
 
     use camino_tempfile::tempdir;
-    use std::{collections::HashMap, io::Write};
+    use std::{
+        collections::{HashMap, HashSet},
+        io::Write,
+    };
 
     async fn read_and_check_metadata(
         storage: &LocalFs,
@@ -699,68 +1126,207 @@ mod fs_tests {
         // Check that you get an error if the size parameter doesn't match the actual
         // size of the stream.
         storage
-            .upload(content(), 0, &id, None, &cancel)
+            .upload(content(), 0, &id, UploadOptions::default(), &cancel)
             .await
             .expect_err("upload with zero size succeeded");
         storage
-            .upload(content(), 4, &id, None, &cancel)
+            .upload(content(), 4, &id, UploadOptions::default(), &cancel)
             .await
             .expect_err("upload with too short size succeeded");
         storage
-            .upload(content(), 6, &id, None, &cancel)
+            .upload(content(), 6, &id, UploadOptions::default(), &cancel)
             .await
             .expect_err("upload with too large size succeeded");
 
         // Correct size is 5, this should succeed.
-        storage.upload(content(), 5, &id, None, &cancel).await?;
+        storage
+            .upload(content(), 5, &id, UploadOptions::default(), &cancel)
+            .await?;
+        assert_eq!(
+            storage.list_all().await?,
+            vec![id],
+            "Failed uploads should not leave temporary files behind"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancelled_upload_leaves_nothing_behind() -> anyhow::Result<()> {
+        use futures::StreamExt;
+
+        let (storage, cancel) = create_storage()?;
+        let id = RemotePath::new(Utf8Path::new("timelines/some_timeline/cancelled"))?;
+        // Half of the promised bytes arrive, then the stream stalls
+        let content = || {
+            futures::stream::once(futures::future::ready(Ok(Bytes::from_static(b"12345"))))
+                .chain(futures::stream::pending())
+        };
+
+        let upload_cancel = cancel.child_token();
+        let (res, ()) = tokio::join!(
+            storage.upload(content(), 10, &id, UploadOptions::default(), &upload_cancel),
+            async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                upload_cancel.cancel();
+            }
+        );
+        assert!(TimeoutOrCancel::caused_by_cancel(
+            &res.expect_err("cancelled upload succeeded")
+        ));
+        assert!(!id.with_base(&storage.storage_root).exists());
+        assert_eq!(storage.list_all().await?, Vec::<RemotePath>::new());
+
+        // Dropping the upload midway cleans up as well
+        let dropped = tokio::time::timeout(
+            Duration::from_millis(100),
+            storage.upload(content(), 10, &id, UploadOptions::default(), &cancel),
+        )
+        .await;
+        assert!(dropped.is_err(), "stalled upload completed");
+        assert_eq!(storage.list_all().await?, Vec::<RemotePath>::new());
 
         Ok(())
     }
 
     fn create_storage() -> anyhow::Result<(LocalFs, CancellationToken)> {
         let storage_root = tempdir()?.path().to_path_buf();
-        LocalFs::new(storage_root, Duration::from_secs(120)).map(|s| (s, CancellationToken::new()))
+        LocalFs::new(storage_root, Duration::from_secs(120), false)
+            .map(|s| (s, CancellationToken::new()))
     }
 
     #[tokio::test]
-    async fn download_file() -> anyhow::Result<()> {
-        let (storage, cancel) = create_storage()?;
+    async fn upload_file_with_sync() -> anyhow::Result<()> {
+        let storage_root = tempdir()?.path().to_path_buf();
+        let storage = LocalFs::new(storage_root, Duration::from_secs(120), true)?;
+        let cancel = CancellationToken::new();
+        let metadata = StorageMetadata::from([("one", "1")]);
+
         let upload_name = "upload_1";
-        let upload_target = upload_dummy_file(&storage, upload_name, None, &cancel).await?;
+        let upload_target =
+            upload_dummy_file(&storage, upload_name, Some(metadata.clone()), &cancel).await?;
 
-        let contents = read_and_check_metadata(&storage, &upload_target, None).await?;
+        let contents = read_and_check_metadata(&storage, &upload_target, Some(&metadata)).await?;
+        assert_eq!(dummy_contents(upload_name), contents);
         assert_eq!(
-            dummy_contents(upload_name),
-            contents,
-            "We should upload and download the same contents"
+            list_files_sorted(&storage).await?,
+            vec![
+                upload_target.clone(),
+                RemotePath(storage_metadata_path(upload_target.get_path())),
+            ],
+            "No temporary files should be left behind"
         );
 
-        let non_existing_path = "somewhere/else";
-        match storage.download(&RemotePath::new(Utf8Path::new(non_existing_path))?, &cancel).await {
-            Err(DownloadError::NotFound) => {} // Should get NotFound for non existing keys
-            other => panic!("Should get a NotFound error when downloading non-existing storage files, but got: {other:?}"),
-        }
         Ok(())
     }
 
     #[tokio::test]
-    async fn download_file_range_positive() -> anyhow::Result<()> {
+    async fn concurrent_uploads_with_metadata() -> anyhow::Result<()> {
         let (storage, cancel) = create_storage()?;
-        let upload_name = "upload_1";
-        let upload_target = upload_dummy_file(&storage, upload_name, None, &cancel).await?;
+        let metadata = StorageMetadata::from([("one", "1")]);
+
+        let path = RemotePath::from_string("timelines/some_timeline/upload_1")?;
+        let body = Bytes::from_static(b"upload_1");
+        let upload = || {
+            let from = futures::stream::once(futures::future::ready(Ok(body.clone())));
+            let options = UploadOptions {
+                metadata: Some(metadata.clone()),
+                content_encoding: Some(Compression::Gzip),
+                ..Default::default()
+            };
+            storage.upload(from, body.len(), &path, options, &cancel)
+        };
 
-        let full_range_download_contents =
-            read_and_check_metadata(&storage, &upload_target, None).await?;
+        // Both uploads write the metadata and content encoding sidecars of the same key
+        let (first, second) = tokio::join!(upload(), upload());
+        first?;
+        second?;
+
+        read_and_check_metadata(&storage, &path, Some(&metadata)).await?;
         assert_eq!(
-            dummy_contents(upload_name),
-            full_range_download_contents,
-            "Download full range should return the whole upload"
+            list_files_sorted(&storage).await?,
+            vec![
+                path.clone(),
+                RemotePath(content_encoding_path(path.get_path())),
+                RemotePath(storage_metadata_path(path.get_path())),
+            ],
+            "No temporary files should be left behind"
         );
 
-        let uploaded_bytes = dummy_contents(upload_name).into_bytes();
-        let (first_part_local, second_part_local) = uploaded_bytes.split_at(3);
+        Ok(())
+    }
 
-        let first_part_download = storage
+    #[tokio::test]
+    async fn download_stream_fails_at_deadline() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let upload_name = "upload_1";
+        let upload_target = upload_dummy_file(&storage, upload_name, None, &cancel).await?;
+        let storage = crate::GenericRemoteStorage::LocalFs(storage);
+
+        // A deadline which doesn't pass changes nothing
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(120);
+        let download = storage
+            .download_with_deadline(&upload_target, Some(deadline), &cancel)
+            .await?;
+        assert_eq!(
+            aggregate(download.download_stream).await?,
+            dummy_contents(upload_name).into_bytes()
+        );
+
+        // The deadline ends the stream long before the 120s request timeout
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(100);
+        let download = storage
+            .download_byte_range_with_deadline(&upload_target, 1, None, Some(deadline), &cancel)
+            .await?;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let e = aggregate(download.download_stream)
+            .await
+            .expect_err("the deadline has passed");
+        let e = DownloadError::from(e.downcast::<std::io::Error>()?);
+        assert!(matches!(e, DownloadError::Timeout(_)), "{e:?}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn download_file() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let upload_name = "upload_1";
+        let upload_target = upload_dummy_file(&storage, upload_name, None, &cancel).await?;
+
+        let contents = read_and_check_metadata(&storage, &upload_target, None).await?;
+        assert_eq!(
+            dummy_contents(upload_name),
+            contents,
+            "We should upload and download the same contents"
+        );
+
+        let non_existing_path = "somewhere/else";
+        match storage.download(&RemotePath::new(Utf8Path::new(non_existing_path))?, &cancel).await {
+            Err(DownloadError::NotFound(_)) => {} // Should get NotFound for non existing keys
+            other => panic!("Should get a NotFound error when downloading non-existing storage files, but got: {other:?}"),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn download_file_range_positive() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let upload_name = "upload_1";
+        let upload_target = upload_dummy_file(&storage, upload_name, None, &cancel).await?;
+
+        let full_range_download_contents =
+            read_and_check_metadata(&storage, &upload_target, None).await?;
+        assert_eq!(
+            dummy_contents(upload_name),
+            full_range_download_contents,
+            "Download full range should return the whole upload"
+        );
+
+        let uploaded_bytes = dummy_contents(upload_name).into_bytes();
+        let (first_part_local, second_part_local) = uploaded_bytes.split_at(3);
+
+        let first_part_download = storage
             .download_byte_range(
                 &upload_target,
                 0,
@@ -817,6 +1383,88 @@ mod fs_tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn download_file_range_to_eof() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let upload_name = "upload_1";
+        let upload_target = upload_dummy_file(&storage, upload_name, None, &cancel).await?;
+        let uploaded_bytes = dummy_contents(upload_name).into_bytes();
+
+        for start in [0, 1, uploaded_bytes.len() / 2, uploaded_bytes.len() - 1] {
+            let tail = storage
+                .download_byte_range(&upload_target, start as u64, None, &cancel)
+                .await?
+                .download_stream;
+            assert_eq!(
+                aggregate(tail).await?,
+                &uploaded_bytes[start..],
+                "Open ended range from {start} should stream up to the end of the file"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn download_content_length() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let upload_name = "upload_1";
+        let upload_target = upload_dummy_file(&storage, upload_name, None, &cancel).await?;
+        let len = dummy_contents(upload_name).len() as u64;
+
+        let download = storage.download(&upload_target, &cancel).await?;
+        assert_eq!(download.content_length, len);
+
+        for (start, end, expected) in [
+            (0, Some(len), len),
+            (4, Some(10), 6),
+            (8, Some(len * 100), len - 8),
+            (4, None, len - 4),
+            (len + 10, None, 0),
+        ] {
+            let download = storage
+                .download_byte_range(&upload_target, start, end, &cancel)
+                .await?;
+            assert_eq!(download.content_length, expected, "range {start}..{end:?}");
+            let streamed = aggregate(download.download_stream).await?;
+            assert_eq!(streamed.len() as u64, expected, "range {start}..{end:?}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn download_to_file() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let upload_name = "upload_1";
+        let upload_target = upload_dummy_file(&storage, upload_name, None, &cancel).await?;
+        let contents = dummy_contents(upload_name);
+
+        let local_dir = tempdir()?;
+        let local = local_dir.path().join("downloaded");
+        // An existing file is replaced
+        std::fs::write(&local, "stale contents, longer than the object")?;
+
+        let downloaded = storage
+            .download_to_file(&upload_target, &local, &cancel)
+            .await?;
+        assert_eq!(downloaded.size, contents.len() as u64);
+        assert_eq!(std::fs::read_to_string(&local)?, contents);
+
+        // A failed download leaves the file, and no temp file, behind
+        let missing = RemotePath::from_string("missing")?;
+        let e = storage
+            .download_to_file(&missing, &local, &cancel)
+            .await
+            .unwrap_err();
+        assert!(matches!(e, DownloadError::NotFound(_)), "{e:?}");
+        assert_eq!(std::fs::read_to_string(&local)?, contents);
+        let files = std::fs::read_dir(local_dir.path())?.count();
+        assert_eq!(files, 1);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn download_file_range_negative() -> anyhow::Result<()> {
         let (storage, cancel) = create_storage()?;
@@ -836,13 +1484,22 @@ mod fs_tests {
         {
             Ok(_) => panic!("Should not allow downloading wrong ranges"),
             Err(e) => {
+                assert!(matches!(e, DownloadError::InvalidRange(_)), "{e:?}");
                 let error_string = e.to_string();
-                assert!(error_string.contains("zero bytes"));
+                assert!(error_string.contains("past the end"));
                 assert!(error_string.contains(&start.to_string()));
-                assert!(error_string.contains(&end.to_string()));
             }
         }
 
+        let start = 10;
+        match storage
+            .download_byte_range(&upload_target, start, Some(start), &cancel)
+            .await
+        {
+            Ok(_) => panic!("Should not allow downloading empty ranges"),
+            Err(e) => assert!(matches!(e, DownloadError::InvalidRange(_)), "{e:?}"),
+        }
+
         let start = 10000;
         let end = 234;
         assert!(start > end, "Should test an incorrect range");
@@ -852,8 +1509,9 @@ mod fs_tests {
         {
             Ok(_) => panic!("Should not allow downloading wrong ranges"),
             Err(e) => {
+                assert!(matches!(e, DownloadError::InvalidRange(_)), "{e:?}");
                 let error_string = e.to_string();
-                assert!(error_string.contains("Invalid range"));
+                assert!(error_string.contains("Invalid byte range"));
                 assert!(error_string.contains(&start.to_string()));
                 assert!(error_string.contains(&end.to_string()));
             }
@@ -876,6 +1534,239 @@ mod fs_tests {
             .await
             .expect("Should allow deleting non-existing storage files");
 
+        let upload_target = upload_dummy_file(&storage, upload_name, None, &cancel).await?;
+        assert!(storage.delete_if_exists(&upload_target, &cancel).await?);
+        assert!(!storage.delete_if_exists(&upload_target, &cancel).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_if_match() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let upload_target = upload_dummy_file(&storage, "upload_1", None, &cancel).await?;
+        let etag = storage.download(&upload_target, &cancel).await?.etag;
+
+        let err = storage
+            .delete_if_match(&upload_target, &Etag::from("other"), &cancel)
+            .await
+            .expect_err("should not delete with a mismatching ETag");
+        assert!(PreconditionFailed::caused_by_precondition_failure(&err));
+        assert_eq!(storage.list_all().await?.len(), 1);
+
+        storage
+            .delete_if_match(&upload_target, &etag, &cancel)
+            .await?;
+        assert!(storage.list_all().await?.is_empty());
+        // Like plain deletes, deleting a missing object succeeds
+        storage
+            .delete_if_match(&upload_target, &etag, &cancel)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_prefix() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        upload_dummy_file(&storage, "upload_1", None, &cancel).await?;
+        upload_dummy_file(&storage, "upload_2", None, &cancel).await?;
+        let timeline = RemotePath::from_string("timelines/some_timeline")?;
+
+        let neighbour = RemotePath::from_string("timelines/some_timeline_neighbour")?;
+        let body = Bytes::from_static(b"neighbour");
+        let len = body.len();
+        let from = futures::stream::once(futures::future::ready(Ok(body)));
+        storage
+            .upload(from, len, &neighbour, UploadOptions::default(), &cancel)
+            .await?;
+
+        // The neighbour only shares a string prefix with the timeline, and must survive
+        storage.delete_prefix(&timeline, &cancel).await?;
+        assert_eq!(storage.list_all().await?, vec![neighbour]);
+
+        // Nothing left to delete is not an error
+        storage.delete_prefix(&timeline, &cancel).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefix_size() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        upload_dummy_file(&storage, "upload_1", None, &cancel).await?;
+        upload_dummy_file(&storage, "upload_2", None, &cancel).await?;
+        let timeline = RemotePath::from_string("timelines/some_timeline")?;
+
+        let neighbour = RemotePath::from_string("timelines/some_timeline_neighbour")?;
+        let body = Bytes::from_static(b"neighbour");
+        let len = body.len();
+        let from = futures::stream::once(futures::future::ready(Ok(body)));
+        storage
+            .upload(from, len, &neighbour, UploadOptions::default(), &cancel)
+            .await?;
+
+        assert_eq!(
+            storage.prefix_size(&timeline, &cancel).await?,
+            PrefixSize {
+                total_bytes: (dummy_contents("upload_1").len() + dummy_contents("upload_2").len())
+                    as u64,
+                object_count: 2,
+            }
+        );
+        assert_eq!(
+            storage
+                .prefix_size(&RemotePath::from_string("timelines/missing")?, &cancel)
+                .await?,
+            PrefixSize::default()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn object_lock_is_unsupported() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let path = RemotePath::from_string("wal/000000010000000000000001")?;
+        let body = Bytes::from_static(b"wal");
+        let len = body.len();
+        let from = futures::stream::once(futures::future::ready(Ok(body)));
+        let options = UploadOptions {
+            object_lock: Some(ObjectLockConfig {
+                mode: ObjectLockMode::Compliance,
+                retain_until: SystemTime::now() + Duration::from_secs(3600),
+            }),
+            ..Default::default()
+        };
+
+        storage
+            .upload(from, len, &path, options, &cancel)
+            .await
+            .expect_err("LocalFs can't enforce retention");
+        assert!(storage.list_all().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_by_tag_is_unsupported() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let path = upload_dummy_file(&storage, "upload_1", None, &cancel).await?;
+
+        storage
+            .delete_by_tag(
+                &RemotePath::from_string("timelines")?,
+                "orphan",
+                "true",
+                &cancel,
+            )
+            .await
+            .expect_err("LocalFs has no object tags");
+        assert_eq!(storage.list_all().await?, vec![path]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_streaming_resumes_from_token() -> anyhow::Result<()> {
+        use futures::TryStreamExt;
+
+        let (storage, cancel) = create_storage()?;
+        let uploaded = [
+            upload_dummy_file(&storage, "upload_1", None, &cancel).await?,
+            upload_dummy_file(&storage, "upload_2", None, &cancel).await?,
+            upload_dummy_file(&storage, "upload_3", None, &cancel).await?,
+        ];
+        let page_keys = |listing: &Listing| {
+            listing
+                .objects
+                .iter()
+                .map(|o| o.key.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let pages: Vec<_> = storage
+            .list_streaming(
+                None,
+                ListingMode::NoDelimiter,
+                NonZeroU32::new(2),
+                None,
+                &cancel,
+            )
+            .try_collect()
+            .await?;
+        assert_eq!(pages.len(), 2);
+        assert_eq!(page_keys(&pages[0].0), uploaded[..2]);
+        assert_eq!(page_keys(&pages[1].0), uploaded[2..]);
+        assert_eq!(pages[1].1, None, "the last page has no token");
+
+        // The token survives a restart, e.g. when persisted as JSON
+        let token = pages[0].1.clone().expect("a page follows");
+        let token: ContinuationToken = serde_json::from_str(&serde_json::to_string(&token)?)?;
+        let (page, next) = storage
+            .list_page(
+                None,
+                ListingMode::NoDelimiter,
+                NonZeroU32::new(2),
+                Some(token),
+                &cancel,
+            )
+            .await?;
+        assert_eq!(page_keys(&page), uploaded[2..]);
+        assert_eq!(next, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_with_common_prefix_counts() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        upload_dummy_file(&storage, "upload_1", None, &cancel).await?;
+        upload_dummy_file(&storage, "upload_2", None, &cancel).await?;
+
+        // Objects in nested prefixes count towards the top-level one, direct children of the
+        // listed prefix don't count at all
+        for (path, body) in [
+            ("timelines/other_timeline/nested/layer", "layer"),
+            ("timelines/some_timeline_neighbour", "neighbour"),
+        ] {
+            let body = Bytes::from_static(body.as_bytes());
+            let len = body.len();
+            let from = futures::stream::once(futures::future::ready(Ok(body)));
+            storage
+                .upload(
+                    from,
+                    len,
+                    &RemotePath::from_string(path)?,
+                    UploadOptions::default(),
+                    &cancel,
+                )
+                .await?;
+        }
+
+        let timelines = RemotePath::from_string("timelines/")?;
+        assert_eq!(
+            storage
+                .list_with_common_prefix_counts(Some(&timelines), &cancel)
+                .await?,
+            vec![
+                (
+                    RemotePath::from_string("timelines/other_timeline")?,
+                    PrefixSize {
+                        total_bytes: "layer".len() as u64,
+                        object_count: 1,
+                    }
+                ),
+                (
+                    RemotePath::from_string("timelines/some_timeline")?,
+                    PrefixSize {
+                        total_bytes: (dummy_contents("upload_1").len()
+                            + dummy_contents("upload_2").len())
+                            as u64,
+                        object_count: 2,
+                    }
+                ),
+            ]
+        );
+
         Ok(())
     }
 
@@ -925,6 +1816,406 @@ mod fs_tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn metadata_keys_are_lowercased() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let metadata = StorageMetadata::from([("Foo", "Bar")]);
+        let upload_target =
+            upload_dummy_file(&storage, "upload_1", Some(metadata), &cancel).await?;
+
+        let expected = StorageMetadata::from([("foo", "Bar")]);
+        read_and_check_metadata(&storage, &upload_target, Some(&expected)).await?;
+
+        // Sidecars written with uppercase keys are normalized on read as well
+        let target_path = upload_target.with_base(&storage.storage_root);
+        fs::write(storage_metadata_path(&target_path), r#"{"Foo":"Bar"}"#).await?;
+        read_and_check_metadata(&storage, &upload_target, Some(&expected)).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn copy_file_metadata() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let upload_name = "upload_1";
+        let metadata = StorageMetadata::from([("one", "1")]);
+        let with_metadata =
+            upload_dummy_file(&storage, upload_name, Some(metadata.clone()), &cancel).await?;
+        let without_metadata = upload_dummy_file(&storage, "upload_2", None, &cancel).await?;
+        let copy_target = RemotePath::from_string("timelines/some_timeline/copy")?;
+
+        storage
+            .copy(
+                &with_metadata,
+                &copy_target,
+                CopyMetadata::Preserve,
+                &cancel,
+            )
+            .await?;
+        let contents = read_and_check_metadata(&storage, &copy_target, Some(&metadata)).await?;
+        assert_eq!(dummy_contents(upload_name), contents);
+
+        let replaced = StorageMetadata::from([("two", "2")]);
+        storage
+            .copy(
+                &with_metadata,
+                &copy_target,
+                CopyMetadata::Replace(replaced.clone()),
+                &cancel,
+            )
+            .await?;
+        read_and_check_metadata(&storage, &copy_target, Some(&replaced)).await?;
+
+        // Copying an object without metadata over one with metadata must not leave the old
+        // metadata behind.
+        storage
+            .copy(
+                &without_metadata,
+                &copy_target,
+                CopyMetadata::Preserve,
+                &cancel,
+            )
+            .await?;
+        read_and_check_metadata(&storage, &copy_target, None).await?;
+
+        // The source keeps its metadata throughout
+        read_and_check_metadata(&storage, &with_metadata, Some(&metadata)).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn touch_updates_last_modified() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let upload_name = "upload_1";
+        let metadata = StorageMetadata::from([("one", "1")]);
+        let path =
+            upload_dummy_file(&storage, upload_name, Some(metadata.clone()), &cancel).await?;
+
+        let long_ago = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(path.with_base(&storage.storage_root))?
+            .set_modified(long_ago)?;
+        let download = storage.download(&path, &cancel).await?;
+        assert_eq!(download.last_modified, long_ago);
+
+        storage.touch(&path, &cancel).await?;
+        let download = storage.download(&path, &cancel).await?;
+        assert!(download.last_modified > long_ago);
+        let contents = read_and_check_metadata(&storage, &path, Some(&metadata)).await?;
+        assert_eq!(dummy_contents(upload_name), contents);
+
+        assert!(storage
+            .touch(&RemotePath::from_string("missing")?, &cancel)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compressed_upload_round_trip() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let storage: crate::GenericRemoteStorage = crate::GenericRemoteStorage::LocalFs(storage);
+        let path = RemotePath::from_string("timelines/some_timeline/index_part.json")?;
+        let contents = Bytes::from(r#"{"layers":{}}"#.repeat(100));
+
+        storage
+            .upload_compressed(contents.clone(), &path, None, Compression::Gzip, &cancel)
+            .await?;
+        let download = storage.download(&path, &cancel).await?;
+        assert_eq!(download.content_encoding, None);
+        assert_eq!(aggregate(download.download_stream).await?, contents);
+
+        // Copies keep the encoding, so they decompress the same
+        let copy_target = RemotePath::from_string("timelines/some_timeline/copy")?;
+        storage
+            .copy_object(&path, &copy_target, CopyMetadata::Preserve, &cancel)
+            .await?;
+        let download = storage.download(&copy_target, &cancel).await?;
+        assert_eq!(aggregate(download.download_stream).await?, contents);
+
+        // A plain upload over the compressed object drops the encoding again
+        let plain = Bytes::from_static(b"plain");
+        storage
+            .upload(
+                futures::stream::once(futures::future::ready(Ok(plain.clone()))),
+                plain.len(),
+                &path,
+                UploadOptions::default(),
+                &cancel,
+            )
+            .await?;
+        let download = storage.download_byte_range(&path, 0, None, &cancel).await?;
+        assert_eq!(download.content_encoding, None);
+        assert_eq!(aggregate(download.download_stream).await?, plain);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn content_encoding_sidecar_is_not_an_object() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let path = RemotePath::from_string("timelines/some_timeline/index_part.json")?;
+        let contents = Bytes::from(r#"{"layers":{}}"#.repeat(100));
+        crate::GenericRemoteStorage::LocalFs(storage.clone())
+            .upload_compressed(contents, &path, None, Compression::Gzip, &cancel)
+            .await?;
+
+        let listing = storage
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
+            .await?;
+        let keys = listing
+            .objects
+            .into_iter()
+            .map(|o| o.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![path.clone()]);
+
+        storage.delete(&path, &cancel).await?;
+        assert!(storage.list_all().await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_verifies_content_md5() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let path = RemotePath::from_string("timelines/some_timeline/index_part.json")?;
+        // Several chunks, so the digest covers more than the last one
+        let chunks = [Bytes::from_static(b"first "), Bytes::from_static(b"second")];
+        let len = chunks.iter().map(Bytes::len).sum();
+        let md5 = md5::compute(b"first second").0;
+
+        let mut wrong_md5 = md5;
+        wrong_md5[0] ^= 1;
+        let from = futures::stream::iter(chunks.clone().map(Ok));
+        storage
+            .upload(
+                from,
+                len,
+                &path,
+                UploadOptions {
+                    content_md5: Some(wrong_md5),
+                    ..Default::default()
+                },
+                &cancel,
+            )
+            .await
+            .expect_err("should fail on a mismatching digest");
+        assert!(storage.list_all().await?.is_empty());
+
+        let from = futures::stream::iter(chunks.map(Ok));
+        storage
+            .upload(
+                from,
+                len,
+                &path,
+                UploadOptions {
+                    content_md5: Some(md5),
+                    ..Default::default()
+                },
+                &cancel,
+            )
+            .await?;
+        let download = storage.download(&path, &cancel).await?;
+        assert_eq!(
+            aggregate(download.download_stream).await?,
+            b"first second".to_vec()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_reports_progress() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let path = RemotePath::from_string("timelines/some_timeline/layer")?;
+        let chunks = [Bytes::from_static(b"first "), Bytes::from_static(b"second")];
+        let len = chunks.iter().map(Bytes::len).sum();
+
+        let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let progress = {
+            let reported = Arc::clone(&reported);
+            move |sent| reported.lock().unwrap().push(sent)
+        };
+        let from = crate::ProgressReporting::new(futures::stream::iter(chunks.map(Ok)), progress);
+        storage
+            .upload(from, len, &path, UploadOptions::default(), &cancel)
+            .await?;
+
+        assert_eq!(*reported.lock().unwrap(), vec![6, 12]);
+        let download = storage.download(&path, &cancel).await?;
+        assert_eq!(
+            aggregate(download.download_stream).await?,
+            b"first second".to_vec()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_with_depth() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        for key in [
+            "timelines/a/layer",
+            "timelines/a/deeper/layer",
+            "timelines/b/layer",
+            "timelines/index_part.json",
+            "other/file",
+        ] {
+            let body = Bytes::from(dummy_contents(key));
+            let size = body.len();
+            let body = futures::stream::once(futures::future::ready(Ok(body)));
+            storage
+                .upload(
+                    body,
+                    size,
+                    &RemotePath::from_string(key)?,
+                    UploadOptions::default(),
+                    &cancel,
+                )
+                .await?;
+        }
+
+        let list = |prefix: Option<RemotePath>, depth: u32| {
+            let storage = &storage;
+            let cancel = &cancel;
+            async move {
+                let listing = storage
+                    .list_with_depth(prefix.as_ref(), NonZeroU32::new(depth).unwrap(), cancel)
+                    .await?;
+                assert!(listing.is_sorted());
+                anyhow::Ok((
+                    listing
+                        .prefixes
+                        .iter()
+                        .map(|p| p.to_string())
+                        .collect::<Vec<_>>(),
+                    listing
+                        .objects
+                        .iter()
+                        .map(|o| o.key.to_string())
+                        .collect::<Vec<_>>(),
+                ))
+            }
+        };
+
+        let single = storage
+            .list(
+                None,
+                ListingMode::WithDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
+            .await?;
+        let (prefixes, keys) = list(None, 1).await?;
+        assert_eq!(prefixes, vec!["other", "timelines"]);
+        assert_eq!(
+            prefixes,
+            single
+                .prefixes
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+        );
+        assert!(keys.is_empty());
+
+        let (prefixes, keys) = list(None, 2).await?;
+        assert_eq!(
+            prefixes,
+            vec!["other", "timelines", "timelines/a", "timelines/b"]
+        );
+        assert_eq!(keys, vec!["other/file", "timelines/index_part.json"]);
+
+        let (prefixes, keys) = list(Some(RemotePath::from_string("timelines/")?), 3).await?;
+        assert_eq!(
+            prefixes,
+            vec!["timelines/a", "timelines/a/deeper", "timelines/b"]
+        );
+        assert_eq!(
+            keys,
+            vec![
+                "timelines/a/deeper/layer",
+                "timelines/a/layer",
+                "timelines/b/layer",
+                "timelines/index_part.json"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_prefixes_recursive() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        for key in [
+            "timelines/a/layer",
+            "timelines/a/deeper/layer",
+            "timelines/b/layer",
+            "timelines/index_part.json",
+            "other/file",
+        ] {
+            let body = Bytes::from(dummy_contents(key));
+            let size = body.len();
+            let body = futures::stream::once(futures::future::ready(Ok(body)));
+            storage
+                .upload(
+                    body,
+                    size,
+                    &RemotePath::from_string(key)?,
+                    UploadOptions::default(),
+                    &cancel,
+                )
+                .await?;
+        }
+
+        use futures::TryStreamExt;
+
+        let list = |prefix: Option<RemotePath>| {
+            let storage = &storage;
+            let cancel = &cancel;
+            async move {
+                storage
+                    .list_prefixes_recursive(prefix.as_ref(), cancel)
+                    .map_ok(|p| p.to_string())
+                    .try_collect::<Vec<_>>()
+                    .await
+            }
+        };
+
+        assert_eq!(
+            list(None).await?,
+            vec![
+                "other",
+                "timelines",
+                "timelines/a",
+                "timelines/b",
+                "timelines/a/deeper"
+            ]
+        );
+        assert_eq!(
+            list(Some(RemotePath::from_string("timelines")?)).await?,
+            vec!["timelines/a", "timelines/b", "timelines/a/deeper"]
+        );
+        assert!(list(Some(RemotePath::from_string("missing")?))
+            .await?
+            .is_empty());
+        assert!(list(Some(RemotePath::from_string("other/file")?))
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn list() -> anyhow::Result<()> {
         // No delimiter: should recursively list everything
@@ -935,24 +2226,47 @@ mod fs_tests {
         let uncle = upload_dummy_file(&storage, "grandparent/uncle", None, &cancel).await?;
 
         let listing = storage
-            .list(None, ListingMode::NoDelimiter, None, &cancel)
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
             .await?;
         assert!(listing.prefixes.is_empty());
+        for object in &listing.objects {
+            let name = object
+                .key
+                .get_path()
+                .strip_prefix("timelines/some_timeline")?;
+            assert_eq!(object.size, dummy_contents(name.as_str()).len() as u64);
+        }
         assert_eq!(
-            listing.keys.into_iter().collect::<HashSet<_>>(),
+            listing
+                .objects
+                .into_iter()
+                .map(|o| o.key)
+                .collect::<HashSet<_>>(),
             HashSet::from([uncle.clone(), child.clone(), child_sibling.clone()])
         );
 
         // Delimiter: should only go one deep
         let listing = storage
-            .list(None, ListingMode::WithDelimiter, None, &cancel)
+            .list(
+                None,
+                ListingMode::WithDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
             .await?;
 
         assert_eq!(
             listing.prefixes,
             [RemotePath::from_string("timelines").unwrap()].to_vec()
         );
-        assert!(listing.keys.is_empty());
+        assert!(listing.objects.is_empty());
 
         // Delimiter & prefix with a trailing slash
         let listing = storage
@@ -960,16 +2274,22 @@ mod fs_tests {
                 Some(&RemotePath::from_string("timelines/some_timeline/grandparent/").unwrap()),
                 ListingMode::WithDelimiter,
                 None,
+                ListOptions::default(),
                 &cancel,
             )
             .await?;
         assert_eq!(
-            listing.keys,
-            [RemotePath::from_string("uncle").unwrap()].to_vec()
+            listing
+                .objects
+                .into_iter()
+                .map(|o| o.key)
+                .collect::<Vec<_>>(),
+            [uncle.clone()].to_vec()
         );
         assert_eq!(
             listing.prefixes,
-            [RemotePath::from_string("parent").unwrap()].to_vec()
+            [RemotePath::from_string("timelines/some_timeline/grandparent/parent").unwrap()]
+                .to_vec()
         );
 
         // Delimiter and prefix without a trailing slash
@@ -978,13 +2298,14 @@ mod fs_tests {
                 Some(&RemotePath::from_string("timelines/some_timeline/grandparent").unwrap()),
                 ListingMode::WithDelimiter,
                 None,
+                ListOptions::default(),
                 &cancel,
             )
             .await?;
-        assert_eq!(listing.keys, [].to_vec());
+        assert_eq!(listing.objects, [].to_vec());
         assert_eq!(
             listing.prefixes,
-            [RemotePath::from_string("grandparent").unwrap()].to_vec()
+            [RemotePath::from_string("timelines/some_timeline/grandparent").unwrap()].to_vec()
         );
 
         // Delimiter and prefix that's partway through a path component
@@ -993,13 +2314,79 @@ mod fs_tests {
                 Some(&RemotePath::from_string("timelines/some_timeline/grandp").unwrap()),
                 ListingMode::WithDelimiter,
                 None,
+                ListOptions::default(),
                 &cancel,
             )
             .await?;
-        assert_eq!(listing.keys, [].to_vec());
+        assert_eq!(listing.objects, [].to_vec());
         assert_eq!(
             listing.prefixes,
-            [RemotePath::from_string("grandparent").unwrap()].to_vec()
+            [RemotePath::from_string("timelines/some_timeline/grandparent").unwrap()].to_vec()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_sorted_like_s3() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let nested_b = upload_dummy_file(&storage, "a/b", None, &cancel).await?;
+        let nested_c = upload_dummy_file(&storage, "a/c", None, &cancel).await?;
+        let dashed = upload_dummy_file(&storage, "a-b", None, &cancel).await?;
+
+        // `-` sorts before `/`
+        let listing = storage
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
+            .await?;
+        assert!(listing.is_sorted());
+        let keys = listing
+            .objects
+            .into_iter()
+            .map(|o| o.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![dashed.clone(), nested_b.clone(), nested_c]);
+
+        // Truncated after sorting
+        let listing = storage
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                NonZeroU32::new(2),
+                ListOptions::default(),
+                &cancel,
+            )
+            .await?;
+        let keys = listing
+            .objects
+            .into_iter()
+            .map(|o| o.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![dashed.clone(), nested_b]);
+
+        let listing = storage
+            .list(
+                Some(&RemotePath::from_string("timelines/some_timeline/")?),
+                ListingMode::WithDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
+            .await?;
+        let keys = listing
+            .objects
+            .into_iter()
+            .map(|o| o.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![dashed]);
+        assert_eq!(
+            listing.prefixes,
+            vec![RemotePath::from_string("timelines/some_timeline/a")?]
         );
 
         Ok(())
@@ -1026,18 +2413,17 @@ mod fs_tests {
                 ),
                 ListingMode::WithDelimiter,
                 None,
+                ListOptions::default(),
                 &cancel,
             )
             .await?;
-        assert_eq!(listing.keys, [].to_vec());
+        assert_eq!(listing.objects, [].to_vec());
 
-        let mut found_prefixes = listing.prefixes.clone();
-        found_prefixes.sort();
         assert_eq!(
-            found_prefixes,
+            listing.prefixes,
             [
-                RemotePath::from_string("tenant").unwrap(),
-                RemotePath::from_string("tenant-01").unwrap(),
+                RemotePath::from_string("timelines/some_timeline/grandparent/tenant").unwrap(),
+                RemotePath::from_string("timelines/some_timeline/grandparent/tenant-01").unwrap(),
             ]
             .to_vec()
         );
@@ -1045,6 +2431,154 @@ mod fs_tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn list_modified_since() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let child = upload_dummy_file(&storage, "grandparent/parent/child", None, &cancel).await?;
+        let uncle = upload_dummy_file(&storage, "grandparent/uncle", None, &cancel).await?;
+
+        let listing = storage
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions {
+                    modified_since: Some(UNIX_EPOCH),
+                    ..Default::default()
+                },
+                &cancel,
+            )
+            .await?;
+        assert_eq!(
+            listing
+                .objects
+                .into_iter()
+                .map(|o| o.key)
+                .collect::<HashSet<_>>(),
+            HashSet::from([child, uncle])
+        );
+
+        // Everything was written before this point in time, so all keys get filtered out, but
+        // prefixes are still listed.
+        let in_the_future = SystemTime::now() + Duration::from_secs(3600);
+        let listing = storage
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions {
+                    modified_since: Some(in_the_future),
+                    ..Default::default()
+                },
+                &cancel,
+            )
+            .await?;
+        assert!(listing.objects.is_empty());
+
+        let listing = storage
+            .list(
+                Some(&RemotePath::from_string("timelines/some_timeline/grandparent/").unwrap()),
+                ListingMode::WithDelimiter,
+                None,
+                ListOptions {
+                    modified_since: Some(in_the_future),
+                    ..Default::default()
+                },
+                &cancel,
+            )
+            .await?;
+        assert!(listing.objects.is_empty());
+        assert_eq!(
+            listing.prefixes,
+            [RemotePath::from_string("parent").unwrap()].to_vec()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_with_metadata() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        let metadata = StorageMetadata::from([("one", "1")]);
+        let with = upload_dummy_file(&storage, "with", Some(metadata.clone()), &cancel).await?;
+        let without = upload_dummy_file(&storage, "without", None, &cancel).await?;
+
+        let listing = storage
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions {
+                    with_metadata: true,
+                    ..Default::default()
+                },
+                &cancel,
+            )
+            .await?;
+        let listed = listing
+            .objects
+            .into_iter()
+            .map(|o| (o.key, o.metadata))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            listed,
+            HashMap::from([(with, Some(metadata)), (without, None)])
+        );
+
+        // Metadata is only read when asked for
+        let listing = storage
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
+            .await?;
+        assert_eq!(listing.objects.len(), 2);
+        assert!(listing.objects.iter().all(|o| o.metadata.is_none()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn traffic_stats() -> anyhow::Result<()> {
+        let (storage, cancel) = create_storage()?;
+        assert_eq!(storage.traffic_stats(), TrafficStats::default());
+
+        let path = upload_dummy_file(&storage, "upload", None, &cancel).await?;
+        let len = dummy_contents("upload").len() as u64;
+
+        // Clones share the counters
+        let clone = storage.clone();
+        let download = clone.download(&path, &cancel).await?;
+        assert_eq!(clone.traffic_stats().bytes_downloaded, 0);
+        aggregate(download.download_stream).await?;
+
+        assert_eq!(
+            storage.traffic_stats(),
+            TrafficStats {
+                bytes_uploaded: len,
+                bytes_downloaded: len,
+                requests: 2,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn describe() -> anyhow::Result<()> {
+        let (storage, _cancel) = create_storage()?;
+        let description = storage.describe();
+        assert_eq!(description.backend, "localfs");
+        assert_eq!(
+            description.bucket_or_container,
+            storage.storage_root.as_str()
+        );
+        assert_eq!(description.prefix, None);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn overwrite_shorter_file() -> anyhow::Result<()> {
         let (storage, cancel) = create_storage()?;
@@ -1056,7 +2590,9 @@ mod fs_tests {
             let len = body.len();
             let body =
                 futures::stream::once(futures::future::ready(std::io::Result::Ok(body.clone())));
-            storage.upload(body, len, &path, None, &cancel).await?;
+            storage
+                .upload(body, len, &path, UploadOptions::default(), &cancel)
+                .await?;
         }
 
         let read = aggregate(storage.download(&path, &cancel).await?.download_stream).await?;
@@ -1067,7 +2603,9 @@ mod fs_tests {
             let len = shorter.len();
             let body =
                 futures::stream::once(futures::future::ready(std::io::Result::Ok(shorter.clone())));
-            storage.upload(body, len, &path, None, &cancel).await?;
+            storage
+                .upload(body, len, &path, UploadOptions::default(), &cancel)
+                .await?;
         }
 
         let read = aggregate(storage.download(&path, &cancel).await?.download_stream).await?;
@@ -1075,6 +2613,25 @@ mod fs_tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn stalled_upload_times_out() -> anyhow::Result<()> {
+        let storage_root = tempdir()?.path().to_path_buf();
+        let storage = LocalFs::new(storage_root, Duration::from_millis(10), false)?;
+        let cancel = CancellationToken::new();
+
+        let path = RemotePath::new("does/not/matter/file".into())?;
+        let body = futures::stream::pending::<std::io::Result<Bytes>>();
+        let e = storage
+            .upload(body, 10, &path, UploadOptions::default(), &cancel)
+            .await
+            .unwrap_err();
+
+        assert!(TimeoutOrCancel::caused_by_timeout(&e), "{e:?}");
+        assert!(!TimeoutOrCancel::caused_by_cancel(&e));
+        assert!(TimeoutOrCancel::from_error(&e).is_some_and(TimeoutOrCancel::is_timeout));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn cancelled_upload_can_later_be_retried() -> anyhow::Result<()> {
         let (storage, cancel) = create_storage()?;
@@ -1089,7 +2646,7 @@ mod fs_tests {
             let cancel = cancel.child_token();
             cancel.cancel();
             let e = storage
-                .upload(body, len, &path, None, &cancel)
+                .upload(body, len, &path, UploadOptions::default(), &cancel)
                 .await
                 .unwrap_err();
 
@@ -1100,7 +2657,9 @@ mod fs_tests {
             let len = body.len();
             let body =
                 futures::stream::once(futures::future::ready(std::io::Result::Ok(body.clone())));
-            storage.upload(body, len, &path, None, &cancel).await?;
+            storage
+                .upload(body, len, &path, UploadOptions::default(), &cancel)
+                .await?;
         }
 
         let read = aggregate(storage.download(&path, &cancel).await?.download_stream).await?;
@@ -1136,7 +2695,16 @@ mod fs_tests {
         let file = tokio_util::io::ReaderStream::new(file);
 
         storage
-            .upload(file, size, &relative_path, metadata, cancel)
+            .upload(
+                file,
+                size,
+                &relative_path,
+                UploadOptions {
+                    metadata,
+                    ..Default::default()
+                },
+                cancel,
+            )
             .await?;
         Ok(relative_path)
     }
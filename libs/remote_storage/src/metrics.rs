@@ -1,5 +1,6 @@
 use metrics::{
-    register_histogram_vec, register_int_counter, register_int_counter_vec, Histogram, IntCounter,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
 };
 use once_cell::sync::Lazy;
 
@@ -19,7 +20,7 @@ use scopeguard::ScopeGuard;
 use RequestKind::*;
 
 impl RequestKind {
-    const fn as_str(&self) -> &'static str {
+    pub(crate) const fn as_str(&self) -> &'static str {
         match self {
             Get => "get_object",
             Put => "put_object",
@@ -64,12 +65,6 @@ impl RequestTyped<Histogram> {
     }
 }
 
-pub(crate) struct PassFailCancelledRequestTyped<C> {
-    success: RequestTyped<C>,
-    fail: RequestTyped<C>,
-    cancelled: RequestTyped<C>,
-}
-
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum AttemptOutcome {
     Ok,
@@ -96,37 +91,24 @@ impl AttemptOutcome {
     }
 }
 
-impl<C> PassFailCancelledRequestTyped<C> {
-    pub(crate) fn get(&self, kind: RequestKind, outcome: AttemptOutcome) -> &C {
-        let target = match outcome {
-            AttemptOutcome::Ok => &self.success,
-            AttemptOutcome::Err => &self.fail,
-            AttemptOutcome::Cancelled => &self.cancelled,
-        };
-        target.get(kind)
-    }
-
-    fn build_with(mut f: impl FnMut(RequestKind, AttemptOutcome) -> C) -> Self {
-        let success = RequestTyped::build_with(|kind| f(kind, AttemptOutcome::Ok));
-        let fail = RequestTyped::build_with(|kind| f(kind, AttemptOutcome::Err));
-        let cancelled = RequestTyped::build_with(|kind| f(kind, AttemptOutcome::Cancelled));
-
-        PassFailCancelledRequestTyped {
-            success,
-            fail,
-            cancelled,
-        }
-    }
-}
+/// [`BucketMetrics::req_seconds`], also labelled with the operation which issued the request, see
+/// [`crate::with_op_label`]. Operations are not known up front, so unlike for [`RequestTyped`], the
+/// histogram is looked up on every observation.
+pub(crate) struct RequestSeconds(HistogramVec);
 
-impl PassFailCancelledRequestTyped<Histogram> {
+impl RequestSeconds {
     pub(crate) fn observe_elapsed(
         &self,
         kind: RequestKind,
         outcome: impl Into<AttemptOutcome>,
         started_at: std::time::Instant,
     ) {
-        self.get(kind, outcome.into())
+        self.0
+            .with_label_values(&[
+                kind.as_str(),
+                outcome.into().as_str(),
+                crate::op_label::current_op_label(),
+            ])
             .observe(started_at.elapsed().as_secs_f64())
     }
 }
@@ -158,7 +140,7 @@ pub(crate) fn start_measuring_requests(
 
 pub(crate) struct BucketMetrics {
     /// Full request duration until successful completion, error or cancellation.
-    pub(crate) req_seconds: PassFailCancelledRequestTyped<Histogram>,
+    pub(crate) req_seconds: RequestSeconds,
     /// Total amount of seconds waited on queue.
     pub(crate) wait_seconds: RequestTyped<Histogram>,
 
@@ -169,6 +151,22 @@ pub(crate) struct BucketMetrics {
 
     /// Total amount of deleted objects in batches or single requests.
     pub(crate) deleted_objects_total: IntCounter,
+
+    /// Requests issued through [`crate::GenericRemoteStorage`], by backend, request type and the
+    /// operation label set with [`crate::with_op_label`].
+    pub(crate) requests_by_backend: IntCounterVec,
+
+    /// Requests rejected by the remote storage with a throttling response, by backend.
+    pub(crate) throttled_total: IntCounterVec,
+
+    /// Circuit breakers which currently reject requests, see [`crate::circuit_breaker`].
+    pub(crate) circuit_breakers_open: IntGauge,
+    /// Times a circuit breaker opened after consecutive failed requests.
+    pub(crate) circuit_breaker_trips_total: IntCounter,
+
+    /// Writes which succeeded on the primary but failed on the secondary storage of a
+    /// [`crate::MirrorStorage`].
+    pub(crate) mirror_secondary_failures_total: IntCounter,
 }
 
 impl Default for BucketMetrics {
@@ -178,13 +176,11 @@ impl Default for BucketMetrics {
         let req_seconds = register_histogram_vec!(
             "remote_storage_s3_request_seconds",
             "Seconds to complete a request",
-            &["request_type", "result"],
+            &["request_type", "result", "op"],
             buckets.to_vec(),
         )
         .unwrap();
-        let req_seconds = PassFailCancelledRequestTyped::build_with(|kind, outcome| {
-            req_seconds.with_label_values(&[kind.as_str(), outcome.as_str()])
-        });
+        let req_seconds = RequestSeconds(req_seconds);
 
         let wait_seconds = register_histogram_vec!(
             "remote_storage_s3_wait_seconds",
@@ -211,11 +207,48 @@ impl Default for BucketMetrics {
         )
         .unwrap();
 
+        let requests_by_backend = register_int_counter_vec!(
+            "remote_storage_requests_total",
+            "Requests issued to remote storage per backend, request type and internal operation",
+            &["backend", "request_type", "op"],
+        )
+        .unwrap();
+
+        let throttled_total = register_int_counter_vec!(
+            "remote_storage_throttled_total",
+            "Requests throttled by the remote storage, e.g. S3 SlowDown or Azure ServerBusy",
+            &["backend"],
+        )
+        .unwrap();
+
+        let circuit_breakers_open = register_int_gauge!(
+            "remote_storage_s3_circuit_breakers_open",
+            "Number of S3 circuit breakers currently rejecting requests after consecutive failures",
+        )
+        .unwrap();
+
+        let circuit_breaker_trips_total = register_int_counter!(
+            "remote_storage_s3_circuit_breaker_trips_total",
+            "Times an S3 circuit breaker opened after consecutive failed requests",
+        )
+        .unwrap();
+
+        let mirror_secondary_failures_total = register_int_counter!(
+            "remote_storage_mirror_secondary_failures_total",
+            "Writes mirrored to a secondary remote storage which failed there",
+        )
+        .unwrap();
+
         Self {
             req_seconds,
             wait_seconds,
             cancelled_waits,
             deleted_objects_total,
+            requests_by_backend,
+            throttled_total,
+            circuit_breakers_open,
+            circuit_breaker_trips_total,
+            mirror_secondary_failures_total,
         }
     }
 }
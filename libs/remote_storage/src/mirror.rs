@@ -0,0 +1,658 @@
+//! A wrapper which writes to two [`RemoteStorage`]s at once, to migrate from one storage to
+//! another without downtime: while both are written, the new one fills up with everything the
+//! old one gets, and once the existing data was copied over, readers can be switched to it.
+use std::io::SeekFrom;
+use std::num::NonZeroU32;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use bytes::Bytes;
+use futures::stream::{Stream, StreamExt};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    ContinuationToken, CopyMetadata, Download, DownloadError, Etag, ListOptions, Listing,
+    ListingMode, ListingObject, RemotePath, RemoteStorage, StorageDescription, TimeTravelError,
+    TimeTravelSummary, TrafficStats, UploadOptions,
+};
+
+/// How [`MirrorStorage`] treats writes which fail on the secondary storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorMode {
+    /// The write fails, like it would if the primary failed.
+    Strict,
+    /// The failure is logged and counted in `remote_storage_mirror_secondary_failures_total`, but
+    /// the write succeeds. The secondary then misses the change until it is made again.
+    BestEffort,
+}
+
+/// Writes go to both `primary` and `secondary`, reads only come from `primary`.
+///
+/// Every write is done on the primary first, and only on the secondary once it succeeded there,
+/// so the secondary never has a deletion or an object that the primary rejected. Uploads other
+/// than [`RemoteStorage::upload_bytes`] are spilled to an anonymous temporary file to send them
+/// twice, so they take disk space rather than memory while in progress.
+///
+/// Everything describing the storage, like [`RemoteStorage::describe`], is about the primary,
+/// except for [`RemoteStorage::traffic_stats`], which adds up the traffic of both.
+pub struct MirrorStorage<P, S> {
+    primary: P,
+    secondary: S,
+    mode: MirrorMode,
+}
+
+impl<P: RemoteStorage, S: RemoteStorage> MirrorStorage<P, S> {
+    pub fn new(primary: P, secondary: S, mode: MirrorMode) -> Self {
+        MirrorStorage {
+            primary,
+            secondary,
+            mode,
+        }
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+
+    pub fn mode(&self) -> MirrorMode {
+        self.mode
+    }
+
+    /// Applies the [`MirrorMode`] to the result of a write to the secondary.
+    fn secondary_result<T>(
+        &self,
+        operation: &str,
+        path: Option<&RemotePath>,
+        res: anyhow::Result<T>,
+    ) -> anyhow::Result<()> {
+        let Err(e) = res else {
+            return Ok(());
+        };
+        crate::metrics::BUCKET_METRICS
+            .mirror_secondary_failures_total
+            .inc();
+        match self.mode {
+            MirrorMode::Strict => Err(e.context(format!(
+                "{operation} {} on the secondary storage",
+                DisplayPath(path)
+            ))),
+            MirrorMode::BestEffort => {
+                tracing::warn!(
+                    "{operation} {} failed on the secondary storage, ignoring: {e:#}",
+                    DisplayPath(path)
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Uploads the data of [`spill`] to the primary, and then to the secondary.
+    async fn upload_spilled(
+        &self,
+        mut file: tokio::fs::File,
+        len: usize,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.primary
+            .upload(replay(&mut file).await?, len, to, options.clone(), cancel)
+            .await?;
+        let res = match replay(&mut file).await {
+            Ok(from) => self.secondary.upload(from, len, to, options, cancel).await,
+            Err(e) => Err(e),
+        };
+        self.secondary_result("upload", Some(to), res)
+    }
+}
+
+struct DisplayPath<'a>(Option<&'a RemotePath>);
+
+impl std::fmt::Display for DisplayPath<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(path) => write!(f, "{path}"),
+            None => write!(f, "everything"),
+        }
+    }
+}
+
+/// Writes the whole upload to an anonymous temporary file, which is gone once closed, to be able
+/// to send it to both storages. Returns the file and the number of bytes in it.
+async fn spill(
+    from: impl Stream<Item = std::io::Result<Bytes>>,
+) -> anyhow::Result<(tokio::fs::File, usize)> {
+    let file = tokio::task::spawn_blocking(camino_tempfile::tempfile)
+        .await
+        .context("spawn the creation of a temporary file")?
+        .context("create a temporary file for the upload")?;
+    let mut file = tokio::fs::File::from_std(file);
+    let mut from = std::pin::pin!(from);
+    let mut len = 0;
+    while let Some(chunk) = from.next().await {
+        let chunk = chunk.context("reading the data to upload")?;
+        file.write_all(&chunk)
+            .await
+            .context("write the upload to a temporary file")?;
+        len += chunk.len();
+    }
+    file.flush()
+        .await
+        .context("write the upload to a temporary file")?;
+    Ok((file, len))
+}
+
+/// Streams the spilled upload from its start, see [`spill`].
+async fn replay(
+    file: &mut tokio::fs::File,
+) -> anyhow::Result<impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static> {
+    file.seek(SeekFrom::Start(0))
+        .await
+        .context("rewind the temporary file of the upload")?;
+    let file = file
+        .try_clone()
+        .await
+        .context("reopen the temporary file of the upload")?;
+    Ok(ReaderStream::new(file))
+}
+
+impl<P: RemoteStorage, S: RemoteStorage> RemoteStorage for MirrorStorage<P, S> {
+    async fn list(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        options: ListOptions,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        self.primary
+            .list(prefix, mode, max_keys, options, cancel)
+            .await
+    }
+
+    fn list_prefixes_recursive<'a>(
+        &'a self,
+        prefix: Option<&'a RemotePath>,
+        cancel: &'a CancellationToken,
+    ) -> impl Stream<Item = Result<RemotePath, DownloadError>> + 'a {
+        self.primary.list_prefixes_recursive(prefix, cancel)
+    }
+
+    async fn list_page(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
+        cancel: &CancellationToken,
+    ) -> Result<(Listing, Option<ContinuationToken>), DownloadError> {
+        self.primary
+            .list_page(prefix, mode, max_keys, resume_from, cancel)
+            .await
+    }
+
+    async fn upload(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let (file, len) = spill(from).await?;
+        anyhow::ensure!(
+            len == data_size_bytes,
+            "upload stream has {len} bytes instead of {data_size_bytes}"
+        );
+        self.upload_spilled(file, len, to, options, cancel).await
+    }
+
+    async fn upload_unsized(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        // Once spilled, the size is known
+        let (file, len) = spill(from).await?;
+        self.upload_spilled(file, len, to, options, cancel).await
+    }
+
+    async fn upload_bytes(
+        &self,
+        data: Bytes,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.primary
+            .upload_bytes(data.clone(), to, options.clone(), cancel)
+            .await?;
+        let res = self.secondary.upload_bytes(data, to, options, cancel).await;
+        self.secondary_result("upload", Some(to), res)
+    }
+
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.primary.download(from, cancel).await
+    }
+
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.primary
+            .download_byte_range(from, start_inclusive, end_exclusive, cancel)
+            .await
+    }
+
+    async fn download_with_deadline(
+        &self,
+        from: &RemotePath,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.primary
+            .download_with_deadline(from, deadline, cancel)
+            .await
+    }
+
+    async fn download_byte_range_with_deadline(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.primary
+            .download_byte_range_with_deadline(
+                from,
+                start_inclusive,
+                end_exclusive,
+                deadline,
+                cancel,
+            )
+            .await
+    }
+
+    async fn head_object(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError> {
+        self.primary.head_object(key, cancel).await
+    }
+
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.primary.delete(path, cancel).await?;
+        let res = self.secondary.delete(path, cancel).await;
+        self.secondary_result("delete", Some(path), res)
+    }
+
+    async fn delete_if_exists(
+        &self,
+        path: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<bool> {
+        let existed = self.primary.delete_if_exists(path, cancel).await?;
+        let res = self.secondary.delete(path, cancel).await;
+        self.secondary_result("delete", Some(path), res)?;
+        Ok(existed)
+    }
+
+    /// The ETag is the one of the primary's object, the secondary has its own, so the copy there
+    /// is deleted once the primary's was.
+    async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.primary.delete_if_match(path, etag, cancel).await?;
+        let res = self.secondary.delete(path, cancel).await;
+        self.secondary_result("delete", Some(path), res)
+    }
+
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.primary.delete_objects(paths, cancel).await?;
+        let res = self.secondary.delete_objects(paths, cancel).await;
+        self.secondary_result("delete", paths.first(), res)
+    }
+
+    async fn delete_prefix(
+        &self,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        // Each storage lists what it has itself, the secondary may have less or more
+        self.primary.delete_prefix(prefix, cancel).await?;
+        let res = self.secondary.delete_prefix(prefix, cancel).await;
+        self.secondary_result("delete prefix", Some(prefix), res)
+    }
+
+    async fn delete_by_tag(
+        &self,
+        prefix: &RemotePath,
+        tag_key: &str,
+        tag_value: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        // The tags are those of each storage's own copies
+        let deleted = self
+            .primary
+            .delete_by_tag(prefix, tag_key, tag_value, cancel)
+            .await?;
+        let res = self
+            .secondary
+            .delete_by_tag(prefix, tag_key, tag_value, cancel)
+            .await;
+        self.secondary_result("delete by tag", Some(prefix), res)?;
+        Ok(deleted)
+    }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        metadata: CopyMetadata,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.primary
+            .copy(from, to, metadata.clone(), cancel)
+            .await?;
+        let res = self.secondary.copy(from, to, metadata, cancel).await;
+        self.secondary_result("copy to", Some(to), res)
+    }
+
+    async fn touch(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.primary.touch(path, cancel).await?;
+        let res = self.secondary.touch(path, cancel).await;
+        self.secondary_result("touch", Some(path), res)
+    }
+
+    async fn time_travel_recover(
+        &self,
+        prefix: Option<&RemotePath>,
+        timestamp: SystemTime,
+        done_if_after: SystemTime,
+        cancel: &CancellationToken,
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
+        let summary = self
+            .primary
+            .time_travel_recover(prefix, timestamp, done_if_after, cancel)
+            .await?;
+        let res = self
+            .secondary
+            .time_travel_recover(prefix, timestamp, done_if_after, cancel)
+            .await;
+        match res {
+            Ok(_) => {}
+            Err(TimeTravelError::Cancelled) => return Err(TimeTravelError::Cancelled),
+            Err(e) => self
+                .secondary_result(
+                    "time travel recovery of",
+                    prefix,
+                    Err(anyhow::Error::from(e)),
+                )
+                .map_err(TimeTravelError::Other)?,
+        }
+        // What the primary did, as that is where reads come from
+        Ok(summary)
+    }
+
+    async fn abort_incomplete_uploads(
+        &self,
+        prefix: Option<&RemotePath>,
+        older_than: Duration,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        let primary_aborted = self
+            .primary
+            .abort_incomplete_uploads(prefix, older_than, cancel)
+            .await?;
+        let secondary_aborted = match self
+            .secondary
+            .abort_incomplete_uploads(prefix, older_than, cancel)
+            .await
+        {
+            Ok(aborted) => aborted,
+            Err(e) => {
+                self.secondary_result::<()>("abort incomplete uploads below", prefix, Err(e))?;
+                0
+            }
+        };
+        Ok(primary_aborted + secondary_aborted)
+    }
+
+    fn traffic_stats(&self) -> TrafficStats {
+        let primary = self.primary.traffic_stats();
+        let secondary = self.secondary.traffic_stats();
+        TrafficStats {
+            bytes_uploaded: primary.bytes_uploaded + secondary.bytes_uploaded,
+            bytes_downloaded: primary.bytes_downloaded + secondary.bytes_downloaded,
+            requests: primary.requests + secondary.requests,
+        }
+    }
+
+    fn describe(&self) -> StorageDescription {
+        self.primary.describe()
+    }
+
+    fn redacted_config(&self) -> serde_json::Value {
+        serde_json::json!({
+            "primary": self.primary.redacted_config(),
+            "secondary": self.secondary.redacted_config(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino_tempfile::{tempdir, Utf8TempDir};
+
+    use super::*;
+    use crate::{GenericRemoteStorage, LocalFs, UnreliableWrapper};
+
+    fn path(p: &str) -> RemotePath {
+        RemotePath::from_string(p).unwrap()
+    }
+
+    /// The directory is removed once the returned [`Utf8TempDir`] is dropped, so keep it around
+    /// for as long as the storage is used.
+    fn local_fs() -> (LocalFs, Utf8TempDir) {
+        let dir = tempdir().unwrap();
+        let storage = LocalFs::new(dir.path().to_owned(), Duration::from_secs(120), false).unwrap();
+        (storage, dir)
+    }
+
+    fn once(data: Bytes) -> impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static {
+        futures::stream::once(futures::future::ready(Ok(data)))
+    }
+
+    async fn exists(storage: &impl RemoteStorage, key: &RemotePath) -> bool {
+        match storage.head_object(key, &CancellationToken::new()).await {
+            Ok(_) => true,
+            Err(DownloadError::NotFound(_)) => false,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    async fn read(storage: &impl RemoteStorage, key: &RemotePath) -> Vec<u8> {
+        let download = storage
+            .download(key, &CancellationToken::new())
+            .await
+            .unwrap();
+        let mut data = Vec::new();
+        let mut stream = std::pin::pin!(download.download_stream);
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn writes_go_to_both() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let ((primary, _primary_dir), (secondary, _secondary_dir)) = (local_fs(), local_fs());
+        let storage = MirrorStorage::new(primary, secondary, MirrorMode::Strict);
+        let key = path("tenants/a/index_part.json");
+
+        let data = Bytes::from_static(b"mirrored");
+        storage
+            .upload(
+                once(data.clone()),
+                data.len(),
+                &key,
+                UploadOptions::default(),
+                &cancel,
+            )
+            .await?;
+        assert_eq!(read(storage.primary(), &key).await, data);
+        assert_eq!(read(storage.secondary(), &key).await, data);
+
+        let copy = path("tenants/a/copy");
+        storage
+            .copy(&key, &copy, CopyMetadata::Preserve, &cancel)
+            .await?;
+        assert!(exists(storage.secondary(), &copy).await);
+
+        assert!(storage.delete_if_exists(&key, &cancel).await?);
+        assert!(!exists(storage.primary(), &key).await);
+        assert!(!exists(storage.secondary(), &key).await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unsized_uploads_go_to_both() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let ((primary, _primary_dir), (secondary, _secondary_dir)) = (local_fs(), local_fs());
+        let storage = MirrorStorage::new(primary, secondary, MirrorMode::Strict);
+        let key = path("tenants/a/layer");
+
+        let chunks =
+            [b"spilled ".as_slice(), b"in ", b"pieces"].map(|chunk| Ok(Bytes::from_static(chunk)));
+        storage
+            .upload_unsized(
+                futures::stream::iter(chunks),
+                &key,
+                UploadOptions::default(),
+                &cancel,
+            )
+            .await?;
+        assert_eq!(read(storage.primary(), &key).await, b"spilled in pieces");
+        assert_eq!(read(storage.secondary(), &key).await, b"spilled in pieces");
+
+        // A stream of another size than announced is written to neither
+        let other = path("tenants/a/other");
+        storage
+            .upload(
+                once(Bytes::from_static(b"short")),
+                6,
+                &other,
+                UploadOptions::default(),
+                &cancel,
+            )
+            .await
+            .expect_err("size mismatch should fail the upload");
+        assert!(!exists(storage.primary(), &other).await);
+        assert!(!exists(storage.secondary(), &other).await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mirror_from_config() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let (primary_dir, secondary_dir) = (tempdir()?, tempdir()?);
+        let config = crate::RemoteStorageConfig {
+            storage: crate::RemoteStorageKind::Mirror(crate::MirrorConfig {
+                primary: Box::new(crate::RemoteStorageKind::LocalFs {
+                    local_path: primary_dir.path().to_owned(),
+                    sync_on_upload: false,
+                }),
+                secondary: Box::new(crate::RemoteStorageKind::LocalFs {
+                    local_path: secondary_dir.path().to_owned(),
+                    sync_on_upload: false,
+                }),
+                mode: MirrorMode::Strict,
+            }),
+            timeouts: Duration::from_secs(120).into(),
+        };
+        let storage = GenericRemoteStorage::from_config(&config)?;
+        assert_eq!(storage.backend_name(), "mirror");
+
+        let key = path("tenants/a/index_part.json");
+        storage
+            .upload_bytes(
+                Bytes::from_static(b"mirrored"),
+                &key,
+                UploadOptions::default(),
+                &cancel,
+            )
+            .await?;
+        let GenericRemoteStorage::Mirror(mirror) = &storage else {
+            panic!("expected a mirror");
+        };
+        assert_eq!(read(mirror.secondary(), &key).await, b"mirrored");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn secondary_failures_depend_on_mode() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let key = path("tenants/a/index_part.json");
+        let data = Bytes::from_static(b"mirrored");
+        // Every operation fails once on the secondary
+        let failing = |local| UnreliableWrapper::new(GenericRemoteStorage::LocalFs(local), 1);
+
+        let ((primary, _primary_dir), (secondary, _secondary_dir)) = (local_fs(), local_fs());
+        let strict = MirrorStorage::new(primary, failing(secondary), MirrorMode::Strict);
+        strict
+            .upload(
+                once(data.clone()),
+                data.len(),
+                &key,
+                UploadOptions::default(),
+                &cancel,
+            )
+            .await
+            .expect_err("secondary failure should fail a strict upload");
+        // The primary was written before
+        assert!(exists(strict.primary(), &key).await);
+
+        let ((primary, _primary_dir), (secondary, _secondary_dir)) = (local_fs(), local_fs());
+        let best_effort = MirrorStorage::new(primary, failing(secondary), MirrorMode::BestEffort);
+        best_effort
+            .upload(
+                once(data.clone()),
+                data.len(),
+                &key,
+                UploadOptions::default(),
+                &cancel,
+            )
+            .await?;
+        assert!(exists(best_effort.primary(), &key).await);
+
+        Ok(())
+    }
+}
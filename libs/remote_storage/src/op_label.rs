@@ -0,0 +1,51 @@
+//! Attribution of remote storage requests to the internal workload which issues them, e.g.
+//! layer uploads from compaction versus heatmap uploads.
+//!
+//! The label is carried in a task local rather than as an argument of every [`crate::RemoteStorage`]
+//! method, so that only the callers which care need to opt in, by wrapping their operation in
+//! [`with_op_label`].
+
+use std::future::Future;
+
+use tracing::Instrument;
+
+/// The label of requests issued outside of [`with_op_label`].
+pub const UNLABELED_OP: &str = "unlabeled";
+
+tokio::task_local! {
+    static OP_LABEL: &'static str;
+}
+
+/// Runs `fut` with all remote storage requests it issues labelled `op_label`, both in the
+/// `op` label of the request metrics and as the `op` field of a span around it.
+///
+/// Only requests issued from within the task polling `fut` are labelled, not those of tasks it
+/// spawns.
+pub async fn with_op_label<F: Future>(op_label: &'static str, fut: F) -> F::Output {
+    let span = tracing::info_span!("remote_storage_op", op = op_label);
+    OP_LABEL.scope(op_label, fut.instrument(span)).await
+}
+
+/// The label set by the innermost [`with_op_label`], if any.
+pub(crate) fn current_op_label() -> &'static str {
+    OP_LABEL.try_with(|label| *label).unwrap_or(UNLABELED_OP)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn innermost_label_wins() {
+        assert_eq!(current_op_label(), UNLABELED_OP);
+        with_op_label("outer", async {
+            assert_eq!(current_op_label(), "outer");
+            with_op_label("inner", async {
+                assert_eq!(current_op_label(), "inner");
+            })
+            .await;
+            assert_eq!(current_op_label(), "outer");
+        })
+        .await;
+    }
+}
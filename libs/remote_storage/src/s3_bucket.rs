@@ -6,15 +6,15 @@
 
 use std::{
     borrow::Cow,
-    collections::HashMap,
-    num::NonZeroU32,
+    collections::BTreeMap,
+    num::{NonZeroU32, NonZeroUsize},
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{anyhow, Context as _};
+use anyhow::Context as _;
 use aws_config::{
     environment::credentials::EnvironmentVariableCredentialsProvider,
     imds::credentials::ImdsCredentialsProvider,
@@ -22,51 +22,126 @@ use aws_config::{
     profile::ProfileFileCredentialsProvider,
     provider_config::ProviderConfig,
     retry::{RetryConfigBuilder, RetryMode},
+    timeout::TimeoutConfig,
     web_identity_token::WebIdentityTokenCredentialsProvider,
     BehaviorVersion,
 };
 use aws_credential_types::provider::SharedCredentialsProvider;
 use aws_sdk_s3::{
-    config::{AsyncSleep, IdentityCache, Region, SharedAsyncSleep},
-    error::SdkError,
-    operation::get_object::GetObjectError,
-    types::{Delete, DeleteMarkerEntry, ObjectIdentifier, ObjectVersion, StorageClass},
+    config::{
+        http::HttpResponse,
+        interceptors::{BeforeTransmitInterceptorContextRef, FinalizerInterceptorContextRef},
+        AsyncSleep, ConfigBag, IdentityCache, Intercept, Region, RuntimeComponents,
+        SharedAsyncSleep,
+    },
+    error::{BoxError, DisplayErrorContext, ProvideErrorMetadata, SdkError},
+    operation::{
+        abort_multipart_upload::builders::AbortMultipartUploadFluentBuilder,
+        get_object::GetObjectError,
+        head_object::{HeadObjectError, HeadObjectOutput},
+        list_objects_v2::ListObjectsV2Output,
+    },
+    types::{
+        ChecksumAlgorithm, CommonPrefix, CompletedMultipartUpload, CompletedPart, Delete,
+        DeleteMarkerEntry, EncodingType, MetadataDirective, ObjectCannedAcl, ObjectIdentifier,
+        ObjectVersion, RequestPayer, StorageClass,
+    },
     Client,
 };
 use aws_smithy_async::rt::sleep::TokioSleep;
+use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
 
-use aws_smithy_types::{body::SdkBody, DateTime};
+use aws_smithy_types::{
+    body::SdkBody,
+    config_bag::{Storable, StoreReplace},
+    DateTime,
+};
 use aws_smithy_types::{byte_stream::ByteStream, date_time::ConversionError};
 use bytes::Bytes;
-use futures::stream::Stream;
+use futures::stream::{Stream, StreamExt, TryStreamExt};
 use hyper::Body;
+use itertools::Itertools;
 use scopeguard::ScopeGuard;
 use tokio_util::sync::CancellationToken;
 use utils::backoff;
 
 use super::StorageMetadata;
 use crate::{
-    error::Cancelled,
+    circuit_breaker::CircuitBreaker,
+    error::PermitError,
     metrics::{start_counting_cancelled_wait, start_measuring_requests},
-    support::PermitCarrying,
-    ConcurrencyLimiter, Download, DownloadError, Listing, ListingMode, RemotePath, RemoteStorage,
-    S3Config, TimeTravelError, TimeoutOrCancel, MAX_KEYS_PER_DELETE,
+    support::{self, PermitCarrying},
+    traffic::{CountingDownload, TrafficCounters},
+    Compression, ConcurrencyLimiter, ContinuationToken, CopyMetadata, Download, DownloadError,
+    Etag, ListOptions, Listing, ListingMode, ListingObject, ObjectAcl, ObjectLockMode,
+    PerKindTimeouts, PreconditionFailed, RateLimiter, RemotePath, RemoteStorage,
+    RemoteStorageConfig, RemoteStorageKind, RpsLimits, S3Config, Throttled, TimeTravelError,
+    TimeTravelSummary, TimeoutOrCancel, TrafficStats, UploadOptions,
+    DEFAULT_REMOTE_STORAGE_S3_CONNECTION_IDLE_TIMEOUT, DEFAULT_REMOTE_STORAGE_S3_CONNECT_TIMEOUT,
+    DEFAULT_REMOTE_STORAGE_S3_SLOW_REQUEST_THRESHOLD, MAX_KEYS_PER_DELETE,
     REMOTE_STORAGE_PREFIX_SEPARATOR,
 };
 
 use crate::metrics::AttemptOutcome;
 pub(super) use crate::metrics::RequestKind;
 
+/// How many `HeadObject` requests a listing [`ListOptions::with_metadata`] issues at once, and how many
+/// `GetObjectTagging` requests `delete_by_tag` does. The concurrency limiter still applies on top
+/// of this.
+const MAX_CONCURRENT_METADATA_REQUESTS: usize = 16;
+
+/// How many keys `time_travel_recover` restores at once. The concurrency limiter still applies on
+/// top of this.
+const MAX_CONCURRENT_TIME_TRAVEL_REQUESTS: usize = 16;
+
+/// How many versions and delete markers `time_travel_recover` recovers from at most, mostly so
+/// that we don't keep requesting forever if the listing is too long. Listing 100k entries takes
+/// roughly 40 seconds, and roughly corresponds to tenants of 2 TiB physical size.
+const TIME_TRAVEL_COMPLEXITY_LIMIT: usize = 100_000;
+
+/// The largest object a single `PutObject` request can upload, larger ones need a multipart
+/// upload.
+const MAX_PUT_OBJECT_SIZE: usize = 5 * 1024 * 1024 * 1024;
+
+/// The part size of `upload_unsized`, which holds one part in memory at a time. S3 wants at least
+/// 5 MiB per part and at most 10000 parts, so this allows for uploads of up to 156 GiB.
+const MULTIPART_UPLOAD_PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// Consecutive failed requests after which the circuit breaker rejects requests, see
+/// [`CircuitBreakerInterceptor`].
+const CIRCUIT_BREAKER_FAILURE_THRESHOLD: u32 = 20;
+const CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(10);
+
+const TIME_TRAVEL_WARN_THRESHOLD: u32 = 3;
+const TIME_TRAVEL_MAX_RETRIES: u32 = 10;
+
+fn is_time_travel_error_permanent(e: &TimeTravelError) -> bool {
+    matches!(e, TimeTravelError::Cancelled)
+}
+
 /// AWS S3 storage.
 pub struct S3Bucket {
     client: Client,
     bucket_name: String,
+    bucket_region: String,
+    endpoint: Option<String>,
     prefix_in_bucket: Option<String>,
     max_keys_per_list_response: Option<i32>,
     upload_storage_class: Option<StorageClass>,
+    expected_bucket_owner: Option<String>,
+    // `Some(RequestPayer::Requester)` for requester-pays buckets.
+    request_payer: Option<RequestPayer>,
     concurrency_limiter: ConcurrencyLimiter,
-    // Per-request timeout. Accessible for tests.
-    pub timeout: Duration,
+    // Shared with the handles created by `with_concurrency_limit`.
+    rate_limiter: Arc<RateLimiter>,
+    // Per-request timeouts. Accessible for tests.
+    pub timeouts: PerKindTimeouts,
+    traffic: Arc<TrafficCounters>,
+    // Shared with the client's interceptor, and with the handles created by
+    // `with_concurrency_limit`, which share the client.
+    circuit_breaker: Arc<CircuitBreaker>,
+    // The config this storage was created from, for `redacted_config`.
+    config: Arc<S3Config>,
 }
 
 struct GetObjectRequest {
@@ -74,9 +149,14 @@ struct GetObjectRequest {
     key: String,
     range: Option<String>,
 }
+
 impl S3Bucket {
     /// Creates the S3 storage, errors if incorrect AWS S3 configuration provided.
-    pub fn new(remote_storage_config: &S3Config, timeout: Duration) -> anyhow::Result<Self> {
+    pub fn new(
+        remote_storage_config: &S3Config,
+        timeouts: impl Into<PerKindTimeouts>,
+    ) -> anyhow::Result<Self> {
+        let timeouts = timeouts.into();
         tracing::debug!(
             "Creating s3 remote storage for S3 bucket {}",
             remote_storage_config.bucket_name
@@ -86,9 +166,17 @@ impl S3Bucket {
 
         let provider_conf = ProviderConfig::without_region().with_region(region.clone());
 
-        let credentials_provider = {
+        let credentials_provider = if let Some(profile_name) = &remote_storage_config.profile_name {
+            // An explicitly configured profile must not be overridden by ambient credentials
+            SharedCredentialsProvider::new(
+                ProfileFileCredentialsProvider::builder()
+                    .configure(&provider_conf)
+                    .profile_name(profile_name)
+                    .build(),
+            )
+        } else {
             // uses "AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY"
-            CredentialsProviderChain::first_try(
+            let chain = CredentialsProviderChain::first_try(
                 "env",
                 EnvironmentVariableCredentialsProvider::new(),
             )
@@ -108,7 +196,8 @@ impl S3Bucket {
                     .build(),
             )
             // uses imds v2
-            .or_else("imds", ImdsCredentialsProvider::builder().build())
+            .or_else("imds", ImdsCredentialsProvider::builder().build());
+            SharedCredentialsProvider::new(chain)
         };
 
         // AWS SDK requires us to specify how the RetryConfig should sleep when it wants to back off
@@ -120,7 +209,7 @@ impl S3Bucket {
         )
         .region(region)
         .identity_cache(IdentityCache::lazy().build())
-        .credentials_provider(SharedCredentialsProvider::new(credentials_provider))
+        .credentials_provider(credentials_provider)
         .sleep_impl(SharedAsyncSleep::from(sleep_impl));
 
         let sdk_config: aws_config::SdkConfig = std::thread::scope(|s| {
@@ -138,6 +227,35 @@ impl S3Bucket {
 
         let mut s3_config_builder = aws_sdk_s3::config::Builder::from(&sdk_config);
 
+        let app_name = crate::user_agent(
+            remote_storage_config.app_name.as_deref(),
+            remote_storage_config.user_agent_suffix.as_deref(),
+        );
+        s3_config_builder = s3_config_builder.app_name(
+            aws_sdk_s3::config::AppName::new(app_name)
+                .context("invalid application name for the User-Agent header")?,
+        );
+
+        // The concurrency limiter bounds the number of in-flight requests, separately for reads
+        // and writes. Keep enough idle connections around so that a full burst of requests can
+        // reuse them instead of paying for a new TLS handshake each time.
+        let max_idle_connections = remote_storage_config
+            .max_idle_connections
+            .map(NonZeroUsize::get)
+            .unwrap_or(remote_storage_config.concurrency_limit.get() * 2);
+        let mut hyper_builder = hyper::Client::builder();
+        hyper_builder
+            .pool_max_idle_per_host(max_idle_connections)
+            .pool_idle_timeout(
+                remote_storage_config
+                    .connection_idle_timeout
+                    .unwrap_or(DEFAULT_REMOTE_STORAGE_S3_CONNECTION_IDLE_TIMEOUT),
+            );
+        let http_client = HyperClientBuilder::new()
+            .hyper_builder(hyper_builder)
+            .build_https();
+        s3_config_builder = s3_config_builder.http_client(http_client);
+
         // Technically, the `remote_storage_config.endpoint` field only applies to S3 interactions.
         // (In case we ever re-use the `sdk_config` for more than just the S3 client in the future)
         if let Some(custom_endpoint) = remote_storage_config.endpoint.clone() {
@@ -147,90 +265,162 @@ impl S3Bucket {
         }
 
         // We do our own retries (see [`backoff::retry`]).  However, for the AWS SDK to enable rate limiting in response to throttling
-        // responses (e.g. 429 on too many ListObjectsv2 requests), we must provide a retry config.  We set it to use at most one
-        // attempt, and enable 'Adaptive' mode, which causes rate limiting to be enabled.
+        // responses (e.g. 429 on too many ListObjectsv2 requests), we must provide a retry config.  By default we set it to use at
+        // most one attempt, and enable 'Adaptive' mode, which causes rate limiting to be enabled.
+        let sdk_max_attempts = remote_storage_config
+            .sdk_max_attempts
+            .map_or(1, |attempts| attempts.get());
         let mut retry_config = RetryConfigBuilder::new();
         retry_config
-            .set_max_attempts(Some(1))
+            .set_max_attempts(Some(sdk_max_attempts))
             .set_mode(Some(RetryMode::Adaptive));
         s3_config_builder = s3_config_builder.retry_config(retry_config.build());
 
+        // Our own per-request timeout covers all SDK attempts of a request, so a single attempt
+        // must not be allowed to take longer than that, or the SDK retries are never reached. The
+        // SDK has one attempt timeout for all kinds of requests, so it defaults to the longest
+        // one: a shorter default would cut off every attempt of the slowest kind.
+        let max_timeout = timeouts.max();
+        let operation_attempt_timeout = remote_storage_config
+            .operation_attempt_timeout
+            .unwrap_or(max_timeout);
+        if operation_attempt_timeout > max_timeout {
+            tracing::warn!(
+                "S3 operation_attempt_timeout {operation_attempt_timeout:?} exceeds the remote storage timeouts {timeouts:?}, requests will time out before the attempt does"
+            );
+        }
+        s3_config_builder = s3_config_builder.timeout_config(
+            TimeoutConfig::builder()
+                .connect_timeout(
+                    remote_storage_config
+                        .connect_timeout
+                        .unwrap_or(DEFAULT_REMOTE_STORAGE_S3_CONNECT_TIMEOUT),
+                )
+                .operation_attempt_timeout(operation_attempt_timeout)
+                .build(),
+        );
+
+        let circuit_breaker = Arc::new(CircuitBreaker::new(
+            CIRCUIT_BREAKER_FAILURE_THRESHOLD,
+            CIRCUIT_BREAKER_COOLDOWN,
+        ));
+        s3_config_builder = s3_config_builder
+            .interceptor(CircuitBreakerInterceptor(Arc::clone(&circuit_breaker)))
+            .interceptor(SlowRequestInterceptor {
+                threshold: remote_storage_config
+                    .slow_request_threshold
+                    .unwrap_or(DEFAULT_REMOTE_STORAGE_S3_SLOW_REQUEST_THRESHOLD),
+            });
+
         let s3_config = s3_config_builder.build();
         let client = aws_sdk_s3::Client::from_conf(s3_config);
 
-        let prefix_in_bucket = remote_storage_config
-            .prefix_in_bucket
-            .as_deref()
-            .map(|prefix| {
-                let mut prefix = prefix;
-                while prefix.starts_with(REMOTE_STORAGE_PREFIX_SEPARATOR) {
-                    prefix = &prefix[1..]
-                }
-
-                let mut prefix = prefix.to_string();
-                while prefix.ends_with(REMOTE_STORAGE_PREFIX_SEPARATOR) {
-                    prefix.pop();
-                }
-                prefix
-            });
+        let prefix_in_bucket =
+            support::normalize_storage_prefix(remote_storage_config.prefix_in_bucket.as_deref());
 
         Ok(Self {
             client,
             bucket_name: remote_storage_config.bucket_name.clone(),
+            bucket_region: remote_storage_config.bucket_region.clone(),
+            endpoint: remote_storage_config.endpoint.clone(),
             max_keys_per_list_response: remote_storage_config.max_keys_per_list_response,
             prefix_in_bucket,
             concurrency_limiter: ConcurrencyLimiter::new(
                 remote_storage_config.concurrency_limit.get(),
             ),
+            rate_limiter: Arc::new(RateLimiter::new(&remote_storage_config.rps_limits)),
             upload_storage_class: remote_storage_config.upload_storage_class.clone(),
-            timeout,
+            expected_bucket_owner: remote_storage_config.expected_bucket_owner.clone(),
+            request_payer: remote_storage_config
+                .requester_pays
+                .then_some(RequestPayer::Requester),
+            timeouts,
+            traffic: Arc::default(),
+            circuit_breaker,
+            config: Arc::new(remote_storage_config.clone()),
         })
     }
 
-    fn s3_object_to_relative_path(&self, key: &str) -> RemotePath {
-        let relative_path =
-            match key.strip_prefix(self.prefix_in_bucket.as_deref().unwrap_or_default()) {
-                Some(stripped) => stripped,
-                // we rely on AWS to return properly prefixed paths
-                // for requests with a certain prefix
-                None => panic!(
-                    "Key {} does not start with bucket prefix {:?}",
-                    key, self.prefix_in_bucket
-                ),
-            };
-        RemotePath(
-            relative_path
-                .split(REMOTE_STORAGE_PREFIX_SEPARATOR)
-                .collect(),
-        )
+    /// A handle on the same bucket which shares the HTTP connection pool and the rate limits with
+    /// this one, but has its own concurrency limit and traffic counters.
+    pub(crate) fn with_concurrency_limit(&self, limit: NonZeroUsize) -> Self {
+        Self {
+            client: self.client.clone(),
+            bucket_name: self.bucket_name.clone(),
+            bucket_region: self.bucket_region.clone(),
+            endpoint: self.endpoint.clone(),
+            prefix_in_bucket: self.prefix_in_bucket.clone(),
+            max_keys_per_list_response: self.max_keys_per_list_response,
+            upload_storage_class: self.upload_storage_class.clone(),
+            expected_bucket_owner: self.expected_bucket_owner.clone(),
+            request_payer: self.request_payer.clone(),
+            concurrency_limiter: ConcurrencyLimiter::new(limit.get()),
+            rate_limiter: Arc::clone(&self.rate_limiter),
+            timeouts: self.timeouts,
+            traffic: Arc::default(),
+            circuit_breaker: Arc::clone(&self.circuit_breaker),
+            config: Arc::new(S3Config {
+                concurrency_limit: limit,
+                ..S3Config::clone(&self.config)
+            }),
+        }
+    }
+
+    /// A handle on the same bucket for [`crate::GenericRemoteStorage::healthcheck`], with a single
+    /// permit and a client, rate limiter and circuit breaker of its own.
+    pub(crate) fn healthcheck_handle(&self) -> anyhow::Result<Self> {
+        let config = S3Config {
+            concurrency_limit: NonZeroUsize::MIN,
+            rps_limits: RpsLimits::default(),
+            ..S3Config::clone(&self.config)
+        };
+        Self::new(&config, self.timeouts)
+    }
+
+    pub(crate) fn s3_object_to_relative_path(&self, key: &str) -> RemotePath {
+        match support::strip_storage_prefix(self.prefix_in_bucket.as_deref(), key) {
+            Some(path) => path,
+            // we rely on AWS to return properly prefixed paths
+            // for requests with a certain prefix
+            None => panic!(
+                "Key {} does not start with bucket prefix {:?}",
+                key, self.prefix_in_bucket
+            ),
+        }
     }
 
     pub fn relative_path_to_s3_object(&self, path: &RemotePath) -> String {
         assert_eq!(std::path::MAIN_SEPARATOR, REMOTE_STORAGE_PREFIX_SEPARATOR);
-        let path_string = path.get_path().as_str();
-        match &self.prefix_in_bucket {
-            Some(prefix) => prefix.clone() + "/" + path_string,
-            None => path_string.to_string(),
-        }
+        support::join_storage_prefix(self.prefix_in_bucket.as_deref(), path)
     }
 
     async fn permit(
         &self,
         kind: RequestKind,
         cancel: &CancellationToken,
-    ) -> Result<tokio::sync::SemaphorePermit<'_>, Cancelled> {
+    ) -> Result<tokio::sync::SemaphorePermit<'_>, PermitError> {
+        if let Err(remaining) = self.circuit_breaker.check() {
+            return Err(PermitError::Unavailable(remaining));
+        }
+
         let started_at = start_counting_cancelled_wait(kind);
-        let acquire = self.concurrency_limiter.acquire(kind);
+        // Take the rate limit token first, so that waiting for it doesn't occupy a concurrency slot
+        let acquire = async {
+            self.rate_limiter.acquire(kind).await;
+            self.concurrency_limiter.acquire(kind).await
+        };
 
         let permit = tokio::select! {
             permit = acquire => permit.expect("semaphore is never closed"),
-            _ = cancel.cancelled() => return Err(Cancelled),
+            _ = cancel.cancelled() => return Err(PermitError::Cancelled),
         };
 
         let started_at = ScopeGuard::into_inner(started_at);
         crate::metrics::BUCKET_METRICS
             .wait_seconds
             .observe_elapsed(kind, started_at);
+        // Every request is sent with a permit
+        self.traffic.record_request();
 
         Ok(permit)
     }
@@ -239,25 +429,44 @@ impl S3Bucket {
         &self,
         kind: RequestKind,
         cancel: &CancellationToken,
-    ) -> Result<tokio::sync::OwnedSemaphorePermit, Cancelled> {
+    ) -> Result<tokio::sync::OwnedSemaphorePermit, PermitError> {
+        if let Err(remaining) = self.circuit_breaker.check() {
+            return Err(PermitError::Unavailable(remaining));
+        }
+
         let started_at = start_counting_cancelled_wait(kind);
-        let acquire = self.concurrency_limiter.acquire_owned(kind);
+        let acquire = async {
+            self.rate_limiter.acquire(kind).await;
+            self.concurrency_limiter.acquire_owned(kind).await
+        };
 
         let permit = tokio::select! {
             permit = acquire => permit.expect("semaphore is never closed"),
-            _ = cancel.cancelled() => return Err(Cancelled),
+            _ = cancel.cancelled() => return Err(PermitError::Cancelled),
         };
 
         let started_at = ScopeGuard::into_inner(started_at);
         crate::metrics::BUCKET_METRICS
             .wait_seconds
             .observe_elapsed(kind, started_at);
+        // Every request is sent with a permit
+        self.traffic.record_request();
         Ok(permit)
     }
 
+    /// Completes after the per-request timeout. A request running into it counts as failed for
+    /// the circuit breaker, just like one which got an error response.
+    async fn request_timeout(&self, kind: RequestKind) {
+        tokio::time::sleep(self.timeouts.for_kind(kind)).await;
+        self.circuit_breaker.record_failure();
+    }
+
+    /// Downloads the object, failing with [`DownloadError::Timeout`] at the caller's `deadline`
+    /// like at the request timeout. Either drops the request, and its connection with it.
     async fn download_object(
         &self,
         request: GetObjectRequest,
+        deadline: Option<tokio::time::Instant>,
         cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
         let kind = RequestKind::Get;
@@ -270,14 +479,25 @@ impl S3Bucket {
             .client
             .get_object()
             .bucket(request.bucket)
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
             .key(request.key)
             .set_range(request.range)
             .send();
 
+        // Unlike the request timeout, the caller's deadline says nothing about the health of S3
+        let deadline_passed = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+
         let get_object = tokio::select! {
             res = get_object => res,
-            _ = tokio::time::sleep(self.timeout) => return Err(DownloadError::Timeout),
-            _ = cancel.cancelled() => return Err(DownloadError::Cancelled),
+            _ = self.request_timeout(kind) => return Err(DownloadError::Timeout(None)),
+            _ = deadline_passed => return Err(DownloadError::Timeout(None)),
+            _ = cancel.cancelled() => return Err(DownloadError::Cancelled(None)),
         };
 
         let started_at = ScopeGuard::into_inner(started_at);
@@ -293,7 +513,7 @@ impl S3Bucket {
                     AttemptOutcome::Ok,
                     started_at,
                 );
-                return Err(DownloadError::NotFound);
+                return Err(DownloadError::NotFound(None));
             }
             Err(e) => {
                 crate::metrics::BUCKET_METRICS.req_seconds.observe_elapsed(
@@ -302,21 +522,41 @@ impl S3Bucket {
                     started_at,
                 );
 
-                return Err(DownloadError::Other(
-                    anyhow::Error::new(e).context("download s3 object"),
-                ));
+                return Err(to_download_error(e, "download s3 object"));
             }
         };
 
         // even if we would have no timeout left, continue anyways. the caller can decide to ignore
         // the errors considering timeouts and cancellation.
-        let remaining = self.timeout.saturating_sub(started_at.elapsed());
+        let remaining = self
+            .timeouts
+            .for_kind(kind)
+            .saturating_sub(started_at.elapsed());
+        let remaining = crate::support::timeout_until(remaining, deadline);
 
         let metadata = object_output.metadata().cloned().map(StorageMetadata);
+        let content_encoding = object_output
+            .content_encoding()
+            .and_then(Compression::from_content_encoding);
         let etag = object_output
             .e_tag
             .ok_or(DownloadError::Other(anyhow::anyhow!("Missing ETag header")))?
             .into();
+        // For range requests, this is the length of the range rather than of the object
+        let content_length = object_output
+            .content_length
+            .ok_or(DownloadError::Other(anyhow::anyhow!(
+                "Missing Content-Length header"
+            )))?
+            .try_into()
+            .map_err(|e: std::num::TryFromIntError| DownloadError::Other(e.into()))?;
+        // `bytes <start>-<end>/<size>` for range requests
+        let object_size = object_output
+            .content_range
+            .as_deref()
+            .and_then(|range| range.rsplit_once('/'))
+            .and_then(|(_, size)| size.parse().ok())
+            .unwrap_or(content_length);
         let last_modified = object_output
             .last_modified
             .ok_or(DownloadError::Other(anyhow::anyhow!(
@@ -332,161 +572,152 @@ impl S3Bucket {
 
         let cancel_or_timeout = crate::support::cancel_or_timeout(remaining, cancel.clone());
         let body = crate::support::DownloadStream::new(cancel_or_timeout, body);
+        let body = CountingDownload::new(self.traffic.clone(), body);
 
         Ok(Download {
             metadata,
             etag,
+            content_length,
+            object_size,
             last_modified,
             download_stream: Box::pin(body),
+            content_encoding,
         })
     }
 
-    async fn delete_oids(
+    async fn list0(
         &self,
-        _permit: &tokio::sync::SemaphorePermit<'_>,
-        delete_objects: &[ObjectIdentifier],
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        modified_since: Option<SystemTime>,
         cancel: &CancellationToken,
-    ) -> anyhow::Result<()> {
-        let kind = RequestKind::Delete;
-        let mut cancel = std::pin::pin!(cancel.cancelled());
+    ) -> Result<Listing, DownloadError> {
+        let kind = RequestKind::List;
+        // s3 sdk wants i32
+        let mut max_keys = max_keys.map(|mk| mk.get() as i32);
+        let mut result = Listing::default();
 
-        for chunk in delete_objects.chunks(MAX_KEYS_PER_DELETE) {
-            let started_at = start_measuring_requests(kind);
+        let list_prefix = self.list_prefix(prefix);
 
-            let req = self
-                .client
-                .delete_objects()
-                .bucket(self.bucket_name.clone())
-                .delete(
-                    Delete::builder()
-                        .set_objects(Some(chunk.to_vec()))
-                        .build()
-                        .context("build request")?,
-                )
-                .send();
+        let _permit = self.permit(kind, cancel).await?;
 
-            let resp = tokio::select! {
-                resp = req => resp,
-                _ = tokio::time::sleep(self.timeout) => return Err(TimeoutOrCancel::Timeout.into()),
-                _ = &mut cancel => return Err(TimeoutOrCancel::Cancel.into()),
-            };
+        let mut continuation_token = None;
 
-            let started_at = ScopeGuard::into_inner(started_at);
-            crate::metrics::BUCKET_METRICS
-                .req_seconds
-                .observe_elapsed(kind, &resp, started_at);
+        loop {
+            let response = self
+                .list_objects_page(
+                    list_prefix.clone(),
+                    mode,
+                    max_keys,
+                    continuation_token,
+                    cancel,
+                )
+                .await?;
 
-            let resp = resp.context("request deletion")?;
-            crate::metrics::BUCKET_METRICS
-                .deleted_objects_total
-                .inc_by(chunk.len() as u64);
+            let keys = response.contents();
+            let empty = Vec::new();
+            let prefixes = response.common_prefixes.as_ref().unwrap_or(&empty);
 
-            if let Some(errors) = resp.errors {
-                // Log a bounded number of the errors within the response:
-                // these requests can carry 1000 keys so logging each one
-                // would be too verbose, especially as errors may lead us
-                // to retry repeatedly.
-                const LOG_UP_TO_N_ERRORS: usize = 10;
-                for e in errors.iter().take(LOG_UP_TO_N_ERRORS) {
-                    tracing::warn!(
-                        "DeleteObjects key {} failed: {}: {}",
-                        e.key.as_ref().map(Cow::from).unwrap_or("".into()),
-                        e.code.as_ref().map(Cow::from).unwrap_or("".into()),
-                        e.message.as_ref().map(Cow::from).unwrap_or("".into())
-                    );
-                }
+            tracing::debug!("list: {} prefixes, {} keys", prefixes.len(), keys.len());
 
-                return Err(anyhow::anyhow!(
-                    "Failed to delete {}/{} objects",
-                    errors.len(),
-                    chunk.len(),
-                ));
+            for object in keys {
+                let key = object.key().expect("response does not contain a key");
+                let object = match self.listing_object(key, object.last_modified, object.size) {
+                    Ok(object) => object,
+                    Err(e) => {
+                        skip_listed_key(&mut result, key, e);
+                        continue;
+                    }
+                };
+                if matches!(modified_since, Some(since) if object.last_modified < since) {
+                    continue;
+                }
+                result.objects.push(object);
+                if let Some(mut mk) = max_keys {
+                    assert!(mk > 0);
+                    mk -= 1;
+                    if mk == 0 {
+                        return Ok(result); // limit reached
+                    }
+                    max_keys = Some(mk);
+                }
             }
-        }
-        Ok(())
-    }
-}
-
-pin_project_lite::pin_project! {
-    struct ByteStreamAsStream {
-        #[pin]
-        inner: aws_smithy_types::byte_stream::ByteStream
-    }
-}
-
-impl From<aws_smithy_types::byte_stream::ByteStream> for ByteStreamAsStream {
-    fn from(inner: aws_smithy_types::byte_stream::ByteStream) -> Self {
-        ByteStreamAsStream { inner }
-    }
-}
-
-impl Stream for ByteStreamAsStream {
-    type Item = std::io::Result<Bytes>;
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // this does the std::io::ErrorKind::Other conversion
-        self.project().inner.poll_next(cx).map_err(|x| x.into())
-    }
+            self.push_listed_prefixes(&mut result, prefixes);
 
-    // cannot implement size_hint because inner.size_hint is remaining size in bytes, which makes
-    // sense and Stream::size_hint does not really
-}
+            continuation_token = match response.next_continuation_token {
+                Some(new_token) => Some(new_token),
+                None => break,
+            };
+        }
 
-pin_project_lite::pin_project! {
-    /// Times and tracks the outcome of the request.
-    struct TimedDownload<S> {
-        started_at: std::time::Instant,
-        outcome: AttemptOutcome,
-        #[pin]
-        inner: S
+        Ok(result)
     }
 
-    impl<S> PinnedDrop for TimedDownload<S> {
-        fn drop(mut this: Pin<&mut Self>) {
-            crate::metrics::BUCKET_METRICS.req_seconds.observe_elapsed(RequestKind::Get, this.outcome, this.started_at);
-        }
-    }
-}
+    /// A single `ListObjectsV2` request, for at most `max_keys` keys. The caller holds the permit.
+    async fn list_objects_page(
+        &self,
+        list_prefix: Option<String>,
+        mode: ListingMode,
+        max_keys: Option<i32>,
+        continuation_token: Option<String>,
+        cancel: &CancellationToken,
+    ) -> Result<ListObjectsV2Output, DownloadError> {
+        let kind = RequestKind::List;
+        let started_at = start_measuring_requests(kind);
 
-impl<S> TimedDownload<S> {
-    fn new(started_at: std::time::Instant, inner: S) -> Self {
-        TimedDownload {
-            started_at,
-            outcome: AttemptOutcome::Cancelled,
-            inner,
+        // min of two Options, returning Some if one is value and another is
+        // None (None is smaller than anything, so plain min doesn't work).
+        let request_max_keys = self
+            .max_keys_per_list_response
+            .into_iter()
+            .chain(max_keys.into_iter())
+            .min();
+        let mut request = self
+            .client
+            .list_objects_v2()
+            .bucket(self.bucket_name.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .set_prefix(list_prefix)
+            .set_continuation_token(continuation_token)
+            .set_max_keys(request_max_keys)
+            // Keys can contain characters that XML 1.0 can't represent, so have S3
+            // url-encode them, the listing decodes them.
+            .encoding_type(EncodingType::Url);
+
+        if let ListingMode::WithDelimiter = mode {
+            request = request.delimiter(REMOTE_STORAGE_PREFIX_SEPARATOR.to_string());
         }
-    }
-}
 
-impl<S: Stream<Item = std::io::Result<Bytes>>> Stream for TimedDownload<S> {
-    type Item = <S as Stream>::Item;
+        let request = request.send();
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        use std::task::ready;
+        let response = tokio::select! {
+            res = request => res,
+            _ = self.request_timeout(kind) => return Err(DownloadError::Timeout(None)),
+            _ = cancel.cancelled() => return Err(DownloadError::Cancelled(None)),
+        };
 
-        let this = self.project();
+        let response = response.map_err(|e| to_download_error(e, "Failed to list S3 prefixes"));
 
-        let res = ready!(this.inner.poll_next(cx));
-        match &res {
-            Some(Ok(_)) => {}
-            Some(Err(_)) => *this.outcome = AttemptOutcome::Err,
-            None => *this.outcome = AttemptOutcome::Ok,
-        }
+        let started_at = ScopeGuard::into_inner(started_at);
 
-        Poll::Ready(res)
-    }
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &response, started_at);
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
+        response
     }
-}
 
-impl RemoteStorage for S3Bucket {
-    async fn list(
+    /// Like [`Self::list0`], but lists every version of the objects with `ListObjectVersions`,
+    /// filling in [`ListingObject::version_id`]. Delete markers are not listed.
+    async fn list_versions0(
         &self,
         prefix: Option<&RemotePath>,
         mode: ListingMode,
         max_keys: Option<NonZeroU32>,
+        modified_since: Option<SystemTime>,
         cancel: &CancellationToken,
     ) -> Result<Listing, DownloadError> {
         let kind = RequestKind::List;
@@ -494,25 +725,16 @@ impl RemoteStorage for S3Bucket {
         let mut max_keys = max_keys.map(|mk| mk.get() as i32);
         let mut result = Listing::default();
 
-        // get the passed prefix or if it is not set use prefix_in_bucket value
-        let list_prefix = prefix
-            .map(|p| self.relative_path_to_s3_object(p))
-            .or_else(|| {
-                self.prefix_in_bucket.clone().map(|mut s| {
-                    s.push(REMOTE_STORAGE_PREFIX_SEPARATOR);
-                    s
-                })
-            });
+        let list_prefix = self.list_prefix(prefix);
 
         let _permit = self.permit(kind, cancel).await?;
 
-        let mut continuation_token = None;
+        let mut key_marker = None;
+        let mut version_id_marker = None;
 
         loop {
             let started_at = start_measuring_requests(kind);
 
-            // min of two Options, returning Some if one is value and another is
-            // None (None is smaller than anything, so plain min doesn't work).
             let request_max_keys = self
                 .max_keys_per_list_response
                 .into_iter()
@@ -520,11 +742,15 @@ impl RemoteStorage for S3Bucket {
                 .min();
             let mut request = self
                 .client
-                .list_objects_v2()
+                .list_object_versions()
                 .bucket(self.bucket_name.clone())
+                .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+                .set_request_payer(self.request_payer.clone())
                 .set_prefix(list_prefix.clone())
-                .set_continuation_token(continuation_token)
-                .set_max_keys(request_max_keys);
+                .set_key_marker(key_marker)
+                .set_version_id_marker(version_id_marker)
+                .set_max_keys(request_max_keys)
+                .encoding_type(EncodingType::Url);
 
             if let ListingMode::WithDelimiter = mode {
                 request = request.delimiter(REMOTE_STORAGE_PREFIX_SEPARATOR.to_string());
@@ -534,13 +760,12 @@ impl RemoteStorage for S3Bucket {
 
             let response = tokio::select! {
                 res = request => res,
-                _ = tokio::time::sleep(self.timeout) => return Err(DownloadError::Timeout),
-                _ = cancel.cancelled() => return Err(DownloadError::Cancelled),
+                _ = self.request_timeout(kind) => return Err(DownloadError::Timeout(None)),
+                _ = cancel.cancelled() => return Err(DownloadError::Cancelled(None)),
             };
 
-            let response = response
-                .context("Failed to list S3 prefixes")
-                .map_err(DownloadError::Other);
+            let response =
+                response.map_err(|e| to_download_error(e, "Failed to list S3 object versions"));
 
             let started_at = ScopeGuard::into_inner(started_at);
 
@@ -550,16 +775,31 @@ impl RemoteStorage for S3Bucket {
 
             let response = response?;
 
-            let keys = response.contents();
+            let versions = response.versions();
             let empty = Vec::new();
             let prefixes = response.common_prefixes.as_ref().unwrap_or(&empty);
 
-            tracing::debug!("list: {} prefixes, {} keys", prefixes.len(), keys.len());
+            tracing::debug!(
+                "list versions: {} prefixes, {} versions",
+                prefixes.len(),
+                versions.len()
+            );
 
-            for object in keys {
-                let object_path = object.key().expect("response does not contain a key");
-                let remote_path = self.s3_object_to_relative_path(object_path);
-                result.keys.push(remote_path);
+            for version in versions {
+                let key = version.key().expect("response does not contain a key");
+                let mut object = match self.listing_object(key, version.last_modified, version.size)
+                {
+                    Ok(object) => object,
+                    Err(e) => {
+                        skip_listed_key(&mut result, key, e);
+                        continue;
+                    }
+                };
+                if matches!(modified_since, Some(since) if object.last_modified < since) {
+                    continue;
+                }
+                object.version_id = version.version_id.clone();
+                result.objects.push(object);
                 if let Some(mut mk) = max_keys {
                     assert!(mk > 0);
                     mk -= 1;
@@ -570,106 +810,1421 @@ impl RemoteStorage for S3Bucket {
                 }
             }
 
-            // S3 gives us prefixes like "foo/", we return them like "foo"
-            result.prefixes.extend(prefixes.iter().filter_map(|o| {
-                Some(
-                    self.s3_object_to_relative_path(
-                        o.prefix()?
-                            .trim_end_matches(REMOTE_STORAGE_PREFIX_SEPARATOR),
-                    ),
-                )
-            }));
+            self.push_listed_prefixes(&mut result, prefixes);
 
-            continuation_token = match response.next_continuation_token {
-                Some(new_token) => Some(new_token),
-                None => break,
-            };
+            if !response.is_truncated.unwrap_or_default() {
+                break;
+            }
+            key_marker = response.next_key_marker;
+            version_id_marker = response.next_version_id_marker;
         }
 
         Ok(result)
     }
 
-    async fn upload(
-        &self,
-        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
-        from_size_bytes: usize,
-        to: &RemotePath,
-        metadata: Option<StorageMetadata>,
-        cancel: &CancellationToken,
-    ) -> anyhow::Result<()> {
-        let kind = RequestKind::Put;
-        let _permit = self.permit(kind, cancel).await?;
-
-        let started_at = start_measuring_requests(kind);
+    /// The passed prefix, or if it is not set the `prefix_in_bucket`, as a key prefix to list.
+    fn list_prefix(&self, prefix: Option<&RemotePath>) -> Option<String> {
+        support::storage_list_prefix(self.prefix_in_bucket.as_deref(), prefix)
+    }
 
-        let body = Body::wrap_stream(from);
-        let bytes_stream = ByteStream::new(SdkBody::from_body_0_4(body));
+    /// Converts an object or object version of a listing response, failing for keys which don't
+    /// decode to a valid path, and for objects without a valid size.
+    fn listing_object(
+        &self,
+        key: &str,
+        last_modified: Option<DateTime>,
+        size: Option<i64>,
+    ) -> anyhow::Result<ListingObject> {
+        let object_path = decode_listed_key(key)?;
+        // Counting these as empty would skew anything summing up sizes, e.g. `prefix_size`
+        let size = size
+            .and_then(|size| u64::try_from(size).ok())
+            .with_context(|| format!("Listed object {key:?} has no valid size: {size:?}"))?;
+        let key = self.s3_object_to_relative_path(&object_path);
+        // Objects with a missing or unrepresentable timestamp are treated as fresh, so
+        // that incremental scans err on the side of looking at them.
+        let last_modified = match last_modified.map(SystemTime::try_from) {
+            Some(Ok(t)) => t,
+            _ => {
+                tracing::warn!(
+                    "Remote storage last_modified {:?} for {} is missing or out of bounds",
+                    last_modified,
+                    key
+                );
+                SystemTime::now()
+            }
+        };
+        Ok(ListingObject {
+            key,
+            last_modified,
+            size,
+            metadata: None,
+            version_id: None,
+        })
+    }
+
+    fn push_listed_prefixes(&self, result: &mut Listing, prefixes: &[CommonPrefix]) {
+        // S3 gives us prefixes like "foo/", we return them like "foo"
+        for listed in prefixes.iter().filter_map(|o| o.prefix()) {
+            let prefix = match decode_listed_key(listed) {
+                Ok(prefix) => prefix,
+                Err(e) => {
+                    skip_listed_key(result, listed, e);
+                    continue;
+                }
+            };
+            result.prefixes.push(self.s3_object_to_relative_path(
+                prefix.trim_end_matches(REMOTE_STORAGE_PREFIX_SEPARATOR),
+            ));
+        }
+    }
+
+    /// Fills in the metadata of listed objects, or of the listed versions of them, with a
+    /// `HeadObject` request per object, at most
+    /// [`MAX_CONCURRENT_METADATA_REQUESTS`] at a time.
+    async fn fetch_metadata(
+        &self,
+        objects: &mut [ListingObject],
+        cancel: &CancellationToken,
+    ) -> Result<(), DownloadError> {
+        futures::stream::iter(objects.iter_mut())
+            .map(|object| async move {
+                object.metadata = self
+                    .head_object_metadata(&object.key, object.version_id.clone(), cancel)
+                    .await?;
+                Ok::<_, DownloadError>(())
+            })
+            .buffer_unordered(MAX_CONCURRENT_METADATA_REQUESTS)
+            .try_collect()
+            .await
+    }
+
+    async fn head_object_metadata(
+        &self,
+        key: &RemotePath,
+        version_id: Option<String>,
+        cancel: &CancellationToken,
+    ) -> Result<Option<StorageMetadata>, DownloadError> {
+        let output = self.head_object0(key, version_id, cancel).await?;
+        // Deleted since it was listed
+        Ok(output.and_then(|output| output.metadata().cloned().map(StorageMetadata)))
+    }
+
+    /// Sends a `HeadObject` request, returning `None` if there is no such object.
+    async fn head_object0(
+        &self,
+        key: &RemotePath,
+        version_id: Option<String>,
+        cancel: &CancellationToken,
+    ) -> Result<Option<HeadObjectOutput>, DownloadError> {
+        let kind = RequestKind::Get;
+        let _permit = self.permit(kind, cancel).await?;
+
+        let started_at = start_measuring_requests(kind);
+
+        let head_object = self
+            .client
+            .head_object()
+            .bucket(self.bucket_name.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .key(self.relative_path_to_s3_object(key))
+            .set_version_id(version_id)
+            .send();
+
+        let head_object = tokio::select! {
+            res = head_object => res,
+            _ = self.request_timeout(kind) => return Err(DownloadError::Timeout(None)),
+            _ = cancel.cancelled() => return Err(DownloadError::Cancelled(None)),
+        };
+
+        let started_at = ScopeGuard::into_inner(started_at);
+
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &head_object, started_at);
+
+        match head_object {
+            Ok(output) => Ok(Some(output)),
+            Err(SdkError::ServiceError(e)) if matches!(e.err(), HeadObjectError::NotFound(_)) => {
+                Ok(None)
+            }
+            Err(e) => Err(to_download_error(e, "head s3 object").add_context(key.to_string())),
+        }
+    }
+
+    /// Sends a `GetObjectTagging` request, returning `None` if there is no such object.
+    async fn get_object_tags(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Option<Vec<(String, String)>>, DownloadError> {
+        let kind = RequestKind::Get;
+        let _permit = self.permit(kind, cancel).await?;
+
+        let started_at = start_measuring_requests(kind);
+
+        let get_tags = self
+            .client
+            .get_object_tagging()
+            .bucket(self.bucket_name.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .key(self.relative_path_to_s3_object(key))
+            .send();
+
+        let get_tags = tokio::select! {
+            res = get_tags => res,
+            _ = self.request_timeout(kind) => return Err(DownloadError::Timeout(None)),
+            _ = cancel.cancelled() => return Err(DownloadError::Cancelled(None)),
+        };
+
+        let started_at = ScopeGuard::into_inner(started_at);
+
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &get_tags, started_at);
+
+        match get_tags {
+            Ok(output) => Ok(Some(
+                output
+                    .tag_set
+                    .into_iter()
+                    .map(|tag| (tag.key, tag.value))
+                    .collect(),
+            )),
+            // `GetObjectTagging` has no modeled error for a missing key
+            Err(e) if e.code() == Some("NoSuchKey") => Ok(None),
+            Err(e) => Err(to_download_error(e, "get s3 object tags").add_context(key.to_string())),
+        }
+    }
+
+    /// Lists one page of the versions and delete markers below `prefix` for time travel recovery,
+    /// starting at `markers` and advancing them to the next page. Also returns whether this was
+    /// the last page.
+    async fn list_object_versions_page(
+        &self,
+        prefix: Option<String>,
+        markers: &mut VersionMarkers,
+        cancel: &CancellationToken,
+    ) -> Result<(Vec<VerOrDelete>, bool), TimeTravelError> {
+        let VersionMarkers {
+            key_marker,
+            version_id_marker,
+        } = std::mem::take(markers);
+        let _permit = self.permit(RequestKind::TimeTravel, cancel).await?;
+
+        let response = backoff::retry(
+            || async {
+                let op = self
+                    .client
+                    .list_object_versions()
+                    .bucket(self.bucket_name.clone())
+                    .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+                    .set_request_payer(self.request_payer.clone())
+                    .set_prefix(prefix.clone())
+                    .set_key_marker(key_marker.clone())
+                    .set_version_id_marker(version_id_marker.clone())
+                    .send();
+
+                tokio::select! {
+                    res = op => res.map_err(|e| TimeTravelError::Other(e.into())),
+                    _ = cancel.cancelled() => Err(TimeTravelError::Cancelled),
+                }
+            },
+            is_time_travel_error_permanent,
+            TIME_TRAVEL_WARN_THRESHOLD,
+            TIME_TRAVEL_MAX_RETRIES,
+            "listing object versions for time_travel_recover",
+            cancel,
+        )
+        .await
+        .ok_or_else(|| TimeTravelError::Cancelled)
+        .and_then(|x| x)?;
+
+        tracing::trace!(
+            "  Got List response version_id_marker={:?}, key_marker={:?}",
+            response.version_id_marker,
+            response.key_marker
+        );
+
+        let versions = response
+            .versions
+            .unwrap_or_default()
+            .into_iter()
+            .map(VerOrDelete::from_version);
+        let deletes = response
+            .delete_markers
+            .unwrap_or_default()
+            .into_iter()
+            .map(VerOrDelete::from_delete_marker);
+        let page =
+            itertools::process_results(versions.chain(deletes), |n_vds| n_vds.collect::<Vec<_>>())
+                .map_err(TimeTravelError::Other)?;
+
+        fn none_if_empty(v: Option<String>) -> Option<String> {
+            v.filter(|v| !v.is_empty())
+        }
+        *markers = VersionMarkers {
+            key_marker: none_if_empty(response.next_key_marker),
+            version_id_marker: none_if_empty(response.next_version_id_marker),
+        };
+        let is_last_page = markers.version_id_marker.is_none();
+        // The final response is not supposed to be truncated
+        if is_last_page && response.is_truncated.unwrap_or_default() {
+            return Err(TimeTravelError::Other(anyhow::anyhow!(
+                "Received truncated ListObjectVersions response for prefix={prefix:?}"
+            )));
+        }
+        Ok((page, is_last_page))
+    }
+
+    /// Lists everything [`RemoteStorage::time_travel_recover`] would recover from below `prefix`,
+    /// one page at a time, and fails if it can't be recovered, see [`check_time_travel_page`].
+    /// Returns the number of versions and delete markers.
+    async fn check_time_travel_listing(
+        &self,
+        prefix: Option<String>,
+        cancel: &CancellationToken,
+    ) -> Result<usize, TimeTravelError> {
+        let mut markers = VersionMarkers::default();
+        let mut count = 0;
+        loop {
+            let (page, is_last_page) = self
+                .list_object_versions_page(prefix.clone(), &mut markers, cancel)
+                .await?;
+            check_time_travel_page(&page, &mut count)?;
+            if is_last_page {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Restores `key` to its state at `timestamp`, given all of its versions and delete markers
+    /// sorted by modification time. The returned summary counts just this key.
+    async fn time_travel_recover_key(
+        &self,
+        key: &str,
+        versions: Vec<VerOrDelete>,
+        timestamp: DateTime,
+        done_if_after: DateTime,
+        cancel: &CancellationToken,
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
+        let mut summary = TimeTravelSummary::default();
+        let last_vd = versions.last().unwrap();
+        if last_vd.last_modified > done_if_after {
+            tracing::trace!("Key {key} has version later than done_if_after, skipping");
+            summary.skipped += 1;
+            return Ok(summary);
+        }
+        // the version we want to restore to.
+        let version_to_restore_to =
+            match versions.binary_search_by_key(&timestamp, |tpl| tpl.last_modified) {
+                Ok(v) => v,
+                Err(e) => e,
+            };
+        if version_to_restore_to == versions.len() {
+            tracing::trace!("Key {key} has no changes since timestamp, skipping");
+            summary.skipped += 1;
+            return Ok(summary);
+        }
+        let mut do_delete = false;
+        if version_to_restore_to == 0 {
+            // All versions more recent, so the key didn't exist at the specified time point.
+            tracing::trace!(
+                "All {} versions more recent for {key}, deleting",
+                versions.len()
+            );
+            do_delete = true;
+        } else {
+            match &versions[version_to_restore_to - 1] {
+                VerOrDelete {
+                    kind: VerOrDeleteKind::Version,
+                    version_id,
+                    ..
+                } => {
+                    tracing::trace!("Copying old version {version_id} for {key}...");
+                    let _permit = self.permit(RequestKind::TimeTravel, cancel).await?;
+                    // Restore the state to the last version by copying
+                    let source_id = format!(
+                        "{}?versionId={version_id}",
+                        copy_source(&self.bucket_name, key)
+                    );
+
+                    backoff::retry(
+                        || async {
+                            let op = self
+                                .client
+                                .copy_object()
+                                .bucket(self.bucket_name.clone())
+                                .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+                                .set_request_payer(self.request_payer.clone())
+                                .set_expected_source_bucket_owner(
+                                    self.expected_bucket_owner.clone(),
+                                )
+                                .key(key)
+                                .set_storage_class(self.upload_storage_class.clone())
+                                .copy_source(&source_id)
+                                .send();
+
+                            tokio::select! {
+                                res = op => res.map_err(|e| TimeTravelError::Other(e.into())),
+                                _ = cancel.cancelled() => Err(TimeTravelError::Cancelled),
+                            }
+                        },
+                        is_time_travel_error_permanent,
+                        TIME_TRAVEL_WARN_THRESHOLD,
+                        TIME_TRAVEL_MAX_RETRIES,
+                        "copying object version for time_travel_recover",
+                        cancel,
+                    )
+                    .await
+                    .ok_or_else(|| TimeTravelError::Cancelled)
+                    .and_then(|x| x)
+                    .map_err(|e| e.restoring(key, Some(version_id)))?;
+                    tracing::info!(%version_id, %key, "Copied old version in S3");
+                    summary.restored += 1;
+                }
+                VerOrDelete {
+                    kind: VerOrDeleteKind::DeleteMarker,
+                    ..
+                } => {
+                    do_delete = true;
+                }
+            }
+        };
+        if do_delete {
+            if matches!(last_vd.kind, VerOrDeleteKind::DeleteMarker) {
+                // Key has since been deleted (but there was some history), no need to do anything
+                tracing::trace!("Key {key} already deleted, skipping.");
+                summary.skipped += 1;
+            } else {
+                tracing::trace!("Deleting {key}...");
+                let permit = self.permit(RequestKind::TimeTravel, cancel).await?;
+
+                let oid = ObjectIdentifier::builder()
+                    .key(key.to_owned())
+                    .build()
+                    .map_err(|e| TimeTravelError::Other(e.into()))?;
+
+                self.delete_oids(&permit, &[oid], cancel)
+                    .await
+                    .map_err(|e| {
+                        // delete_oid0 will use TimeoutOrCancel
+                        if TimeoutOrCancel::caused_by_cancel(&e) {
+                            TimeTravelError::Cancelled
+                        } else {
+                            TimeTravelError::Other(e).restoring(key, None)
+                        }
+                    })?;
+                summary.deleted += 1;
+            }
+        }
+        Ok(summary)
+    }
+
+    async fn upload0(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        from_size_bytes: usize,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let UploadOptions {
+            metadata,
+            content_encoding,
+            content_md5,
+            acl,
+            object_lock,
+        } = options;
+        let kind = RequestKind::Put;
+        let _permit = self.permit(kind, cancel).await?;
+
+        let started_at = start_measuring_requests(kind);
+
+        let body = Body::wrap_stream(from);
+        let bytes_stream = ByteStream::new(SdkBody::from_body_0_4(body));
+
+        let upload = self
+            .client
+            .put_object()
+            .bucket(self.bucket_name.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .key(self.relative_path_to_s3_object(to))
+            .set_metadata(metadata.map(|m| m.normalized().0))
+            .set_storage_class(self.upload_storage_class.clone())
+            .set_content_encoding(content_encoding.map(|c| c.as_content_encoding().to_owned()))
+            // A mismatching body is rejected with a `BadDigest` error
+            .set_content_md5(content_md5.map(aws_smithy_types::base64::encode))
+            .set_acl(acl.map(canned_acl))
+            .set_object_lock_mode(object_lock.map(|lock| object_lock_mode(lock.mode)))
+            .set_object_lock_retain_until_date(
+                object_lock.map(|lock| DateTime::from(lock.retain_until)),
+            )
+            // Object Lock uploads must carry an integrity checksum, which the SDK only sends
+            // when asked to
+            .set_checksum_algorithm(object_lock.map(|_| ChecksumAlgorithm::Crc32))
+            .content_length(from_size_bytes.try_into()?)
+            .body(bytes_stream)
+            .send();
+
+        let upload = tokio::time::timeout(self.timeouts.for_kind(kind), upload);
+
+        // Dropping the `PutObject` request on cancellation aborts it without storing anything.
+        // Multipart uploads, see `upload_unsized0`, must be aborted instead, or their parts are
+        // kept (and billed) until `abort_incomplete_uploads` runs.
+        let res = tokio::select! {
+            res = upload => res,
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        };
+
+        if let Ok(inner) = &res {
+            // do not incl. timeouts as errors in metrics but cancellations
+            let started_at = ScopeGuard::into_inner(started_at);
+            crate::metrics::BUCKET_METRICS
+                .req_seconds
+                .observe_elapsed(kind, inner, started_at);
+        }
+
+        match res {
+            Ok(Ok(_put)) => {
+                self.traffic.record_upload(from_size_bytes);
+                Ok(())
+            }
+            Ok(Err(sdk)) => Err(to_anyhow_error(sdk)),
+            Err(_timeout) => {
+                self.circuit_breaker.record_failure();
+                Err(TimeoutOrCancel::Timeout.into())
+            }
+        }
+    }
+
+    /// Uploads a stream of unknown size in parts of [`MULTIPART_UPLOAD_PART_SIZE`], or with a
+    /// single `PutObject` request if it ends within the first part. With `data_size_bytes`, for
+    /// streams too large for a single `PutObject`, fails if `from` is shorter or longer than that.
+    ///
+    /// A failed multipart upload is aborted, so that its parts aren't kept (and billed). Only
+    /// dropping the future midway leaves them behind for [`Self::abort_incomplete_uploads`].
+    async fn upload_unsized0(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        data_size_bytes: Option<usize>,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        // Multipart uploads have no digest of the whole object to send, so check it while
+        // streaming, and let S3 verify each part against its own digest
+        let from = support::Md5Verifying::new(from, None, options.content_md5);
+        let mut parts = std::pin::pin!(support::chunks(from, MULTIPART_UPLOAD_PART_SIZE));
+        let first = parts
+            .next()
+            .await
+            .transpose()
+            .context("read the upload stream")?
+            .unwrap_or_default();
+        if first.len() < MULTIPART_UPLOAD_PART_SIZE {
+            let len = first.len();
+            if let Some(data_size_bytes) = data_size_bytes {
+                anyhow::ensure!(
+                    len == data_size_bytes,
+                    "upload stream ended after {len} of {data_size_bytes} bytes"
+                );
+            }
+            let from = futures::stream::once(futures::future::ready(Ok(first)));
+            return self.upload0(from, len, to, options, cancel).await;
+        }
+
+        let key = self.relative_path_to_s3_object(to);
+        let upload_id = self.create_multipart_upload(&key, options, cancel).await?;
+        let abort_on_drop = AbortOnDrop::new(self, &key, &upload_id);
+
+        let res = async {
+            let mut completed = Vec::new();
+            let mut uploaded = 0;
+            let mut part = Some(first);
+            while let Some(data) = part {
+                // Part numbers start at 1
+                let part_number = completed.len() as i32 + 1;
+                uploaded += data.len();
+                if let Some(data_size_bytes) = data_size_bytes {
+                    anyhow::ensure!(
+                        uploaded <= data_size_bytes,
+                        "upload stream is longer than {data_size_bytes} bytes"
+                    );
+                }
+                let completed_part = self
+                    .upload_part(&key, &upload_id, part_number, data, cancel)
+                    .await
+                    .with_context(|| format!("upload part {part_number}"))?;
+                completed.push(completed_part);
+                part = parts
+                    .next()
+                    .await
+                    .transpose()
+                    .context("read the upload stream")?;
+            }
+            if let Some(data_size_bytes) = data_size_bytes {
+                anyhow::ensure!(
+                    uploaded == data_size_bytes,
+                    "upload stream ended after {uploaded} of {data_size_bytes} bytes"
+                );
+            }
+            self.complete_multipart_upload(&key, &upload_id, completed, cancel)
+                .await?;
+            anyhow::Ok(uploaded)
+        }
+        .await;
+        // Completed, or aborted below with the usual permits and metrics
+        abort_on_drop.disarm();
+
+        match res {
+            Ok(uploaded) => {
+                self.traffic.record_upload(uploaded);
+                Ok(())
+            }
+            Err(e) => {
+                // Not with `cancel`, which may well be why the upload failed
+                let abort = self
+                    .abort_multipart_upload(&key, &upload_id, &CancellationToken::new())
+                    .await;
+                if let Err(abort) = abort {
+                    tracing::warn!(
+                        "Failed to abort multipart upload {upload_id} of {key}, leaving it to abort_incomplete_uploads: {abort:#}"
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        key: &str,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<String> {
+        let UploadOptions {
+            metadata,
+            content_encoding,
+            // Checked while streaming the parts
+            content_md5: _,
+            acl,
+            object_lock,
+        } = options;
+        let kind = RequestKind::Put;
+        let _permit = self.permit(kind, cancel).await?;
+        let started_at = start_measuring_requests(kind);
+
+        let request = self
+            .client
+            .create_multipart_upload()
+            .bucket(self.bucket_name.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .key(key)
+            .set_metadata(metadata.map(|m| m.normalized().0))
+            .set_storage_class(self.upload_storage_class.clone())
+            .set_content_encoding(content_encoding.map(|c| c.as_content_encoding().to_owned()))
+            .set_acl(acl.map(canned_acl))
+            // Object Lock needs the parts to carry an integrity checksum, which their
+            // `Content-MD5` is
+            .set_object_lock_mode(object_lock.map(|lock| object_lock_mode(lock.mode)))
+            .set_object_lock_retain_until_date(
+                object_lock.map(|lock| DateTime::from(lock.retain_until)),
+            )
+            .send();
+
+        let res = tokio::select! {
+            res = request => res,
+            _ = self.request_timeout(kind) => return Err(TimeoutOrCancel::Timeout.into()),
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        };
+
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &res, started_at);
+
+        let res = res.map_err(to_anyhow_error)?;
+        res.upload_id
+            .context("CreateMultipartUpload response has no upload id")
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        data: Bytes,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<CompletedPart> {
+        let kind = RequestKind::Put;
+        let _permit = self.permit(kind, cancel).await?;
+        let started_at = start_measuring_requests(kind);
+
+        let request = self
+            .client
+            .upload_part()
+            .bucket(self.bucket_name.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            // The part is in memory anyway, so let S3 verify it arrived intact, like a whole
+            // object with `UploadOptions::content_md5`
+            .content_md5(aws_smithy_types::base64::encode(md5::compute(&data).0))
+            .content_length(data.len().try_into()?)
+            .body(ByteStream::from(data))
+            .send();
+
+        let res = tokio::select! {
+            res = request => res,
+            _ = self.request_timeout(kind) => return Err(TimeoutOrCancel::Timeout.into()),
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        };
+
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &res, started_at);
+
+        let res = res.map_err(to_anyhow_error)?;
+        Ok(CompletedPart::builder()
+            .set_e_tag(res.e_tag)
+            .part_number(part_number)
+            .build())
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<CompletedPart>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let kind = RequestKind::Put;
+        let _permit = self.permit(kind, cancel).await?;
+        let started_at = start_measuring_requests(kind);
+
+        let request = self
+            .client
+            .complete_multipart_upload()
+            .bucket(self.bucket_name.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send();
+
+        let res = tokio::select! {
+            res = request => res,
+            _ = self.request_timeout(kind) => return Err(TimeoutOrCancel::Timeout.into()),
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        };
+
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &res, started_at);
+
+        res.map_err(to_anyhow_error)?;
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let kind = RequestKind::Delete;
+        let _permit = self.permit(kind, cancel).await?;
+        let started_at = start_measuring_requests(kind);
+
+        let request = self
+            .client
+            .abort_multipart_upload()
+            .bucket(self.bucket_name.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .key(key)
+            .upload_id(upload_id)
+            .send();
+
+        let res = tokio::select! {
+            res = request => res,
+            _ = self.request_timeout(kind) => return Err(TimeoutOrCancel::Timeout.into()),
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        };
+
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &res, started_at);
+
+        res.map_err(to_anyhow_error)?;
+        Ok(())
+    }
+
+    async fn delete_oids(
+        &self,
+        _permit: &tokio::sync::SemaphorePermit<'_>,
+        delete_objects: &[ObjectIdentifier],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let kind = RequestKind::Delete;
+        let mut cancel = std::pin::pin!(cancel.cancelled());
+
+        for chunk in delete_objects.chunks(MAX_KEYS_PER_DELETE) {
+            let started_at = start_measuring_requests(kind);
+
+            let req = self
+                .client
+                .delete_objects()
+                .bucket(self.bucket_name.clone())
+                .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+                .set_request_payer(self.request_payer.clone())
+                .delete(
+                    Delete::builder()
+                        .set_objects(Some(chunk.to_vec()))
+                        .build()
+                        .context("build request")?,
+                )
+                .send();
+
+            let resp = tokio::select! {
+                resp = req => resp,
+                _ = self.request_timeout(kind) => return Err(TimeoutOrCancel::Timeout.into()),
+                _ = &mut cancel => return Err(TimeoutOrCancel::Cancel.into()),
+            };
+
+            let started_at = ScopeGuard::into_inner(started_at);
+            crate::metrics::BUCKET_METRICS
+                .req_seconds
+                .observe_elapsed(kind, &resp, started_at);
+
+            let resp = resp.map_err(to_anyhow_error).context("request deletion")?;
+            crate::metrics::BUCKET_METRICS
+                .deleted_objects_total
+                .inc_by(chunk.len() as u64);
+
+            if let Some(errors) = resp.errors {
+                // Log a bounded number of the errors within the response:
+                // these requests can carry 1000 keys so logging each one
+                // would be too verbose, especially as errors may lead us
+                // to retry repeatedly.
+                const LOG_UP_TO_N_ERRORS: usize = 10;
+                for e in errors.iter().take(LOG_UP_TO_N_ERRORS) {
+                    tracing::warn!(
+                        "DeleteObjects key {} failed: {}: {}",
+                        e.key.as_ref().map(Cow::from).unwrap_or("".into()),
+                        e.code.as_ref().map(Cow::from).unwrap_or("".into()),
+                        e.message.as_ref().map(Cow::from).unwrap_or("".into())
+                    );
+                }
+
+                return Err(anyhow::anyhow!(
+                    "Failed to delete {}/{} objects",
+                    errors.len(),
+                    chunk.len(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Aborts a multipart upload in the background if dropped before [`Self::disarm`], which happens
+/// when the future of [`S3Bucket::upload_unsized0`] is dropped, e.g. by a `select!` or `try_join!`
+/// that completed otherwise. Without it, the parts stay around until
+/// [`RemoteStorage::abort_incomplete_uploads`].
+struct AbortOnDrop {
+    request: Option<AbortMultipartUploadFluentBuilder>,
+    timeout: Duration,
+}
+
+impl AbortOnDrop {
+    fn new(bucket: &S3Bucket, key: &str, upload_id: &str) -> Self {
+        let request = bucket
+            .client
+            .abort_multipart_upload()
+            .bucket(bucket.bucket_name.clone())
+            .set_expected_bucket_owner(bucket.expected_bucket_owner.clone())
+            .set_request_payer(bucket.request_payer.clone())
+            .key(key)
+            .upload_id(upload_id);
+        AbortOnDrop {
+            request: Some(request),
+            timeout: bucket.timeouts.for_kind(RequestKind::Delete),
+        }
+    }
+
+    fn disarm(mut self) {
+        self.request = None;
+    }
+}
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        let Some(request) = self.request.take() else {
+            return;
+        };
+        let upload_id = request.get_upload_id().clone().unwrap_or_default();
+        let key = request.get_key().clone().unwrap_or_default();
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!("Can't abort the dropped multipart upload {upload_id} of {key} outside of a runtime, leaving it to abort_incomplete_uploads");
+            return;
+        };
+        let timeout = self.timeout;
+        runtime.spawn(async move {
+            match tokio::time::timeout(timeout, request.send()).await {
+                Ok(Ok(_)) => tracing::info!("Aborted the dropped multipart upload {upload_id} of {key}"),
+                Ok(Err(e)) => tracing::warn!(
+                    "Failed to abort the dropped multipart upload {upload_id} of {key}, leaving it to abort_incomplete_uploads: {}",
+                    DisplayErrorContext(e)
+                ),
+                Err(_) => tracing::warn!(
+                    "Timed out aborting the dropped multipart upload {upload_id} of {key}, leaving it to abort_incomplete_uploads"
+                ),
+            }
+        });
+    }
+}
+
+/// Buckets which enforce bucket owner object ownership reject requests with ACLs other than
+/// `bucket-owner-full-control`, which surfaces as an upload error.
+fn canned_acl(acl: ObjectAcl) -> ObjectCannedAcl {
+    match acl {
+        ObjectAcl::Private => ObjectCannedAcl::Private,
+        ObjectAcl::PublicRead => ObjectCannedAcl::PublicRead,
+        ObjectAcl::BucketOwnerFullControl => ObjectCannedAcl::BucketOwnerFullControl,
+    }
+}
+
+fn object_lock_mode(mode: ObjectLockMode) -> aws_sdk_s3::types::ObjectLockMode {
+    match mode {
+        ObjectLockMode::Governance => aws_sdk_s3::types::ObjectLockMode::Governance,
+        ObjectLockMode::Compliance => aws_sdk_s3::types::ObjectLockMode::Compliance,
+    }
+}
+
+/// The `x-amz-copy-source` value for an object: unlike the key of other requests, the SDK sends it
+/// verbatim, so it has to be url-encoded by us. The bucket name needs to be specified as a prefix.
+fn copy_source(bucket_name: &str, key: &str) -> String {
+    let key = key
+        .split(REMOTE_STORAGE_PREFIX_SEPARATOR)
+        .map(urlencoding::encode)
+        .join("/");
+    format!("{bucket_name}/{key}")
+}
+
+/// Records a listed key or prefix which can't be represented as a [`RemotePath`] or
+/// [`ListingObject`] in [`Listing::skipped`], instead of failing the listing of everything else.
+fn skip_listed_key(result: &mut Listing, key: &str, error: anyhow::Error) {
+    tracing::warn!("Skipping listed key {key:?}: {error:#}");
+    result.skipped.push((key.to_owned(), format!("{error:#}")));
+}
+
+/// Decodes a key or prefix of a listing requested with [`EncodingType::Url`]. S3 encodes spaces
+/// as `+`, and literal `+` as `%2B`.
+fn decode_listed_key(key: &str) -> anyhow::Result<String> {
+    let key = key.replace('+', " ");
+    urlencoding::decode(&key)
+        .map(|k| k.into_owned())
+        .with_context(|| format!("Listed key {key:?} is not valid url-encoded UTF-8"))
+}
+
+/// Returns true if S3 (or an S3 compatible store) rejected the request because we are sending too
+/// many of them.
+fn is_throttled<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> bool {
+    if matches!(
+        err.code(),
+        Some("SlowDown" | "Throttling" | "ThrottlingException" | "RequestLimitExceeded")
+    ) {
+        return true;
+    }
+    err.raw_response()
+        .is_some_and(|response| response.status().as_u16() == 429)
+}
+
+/// Returns true if S3 rejected the request because the credentials lack permission for it.
+fn is_forbidden<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> bool {
+    if err.code() == Some("AccessDenied") {
+        return true;
+    }
+    err.raw_response()
+        .is_some_and(|response| response.status().as_u16() == 403)
+}
+
+/// Returns true if S3 refused to copy the object server-side, while downloading and uploading it
+/// again would work: `CopyObject` only accepts sources of up to 5 GiB, and some S3-compatible
+/// stores don't implement it at all.
+fn is_copy_ineligible<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> bool {
+    match err.code() {
+        Some("NotImplemented") => true,
+        Some("InvalidRequest") => err
+            .message()
+            .is_some_and(|message| message.contains("copy source is larger than the maximum")),
+        _ => false,
+    }
+}
+
+/// Feeds the outcome of every request attempt of the SDK client into the [`CircuitBreaker`].
+///
+/// Attempts which got no response at all, e.g. because connecting failed, or a server error
+/// response count as failures. Any other response, including a 404 or 403, shows that S3 is
+/// reachable and answering.
+#[derive(Debug)]
+struct CircuitBreakerInterceptor(Arc<CircuitBreaker>);
+
+impl Intercept for CircuitBreakerInterceptor {
+    fn name(&self) -> &'static str {
+        "CircuitBreakerInterceptor"
+    }
+
+    fn read_after_attempt(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        match context.response() {
+            Some(response) if response.status().as_u16() < 500 => self.0.record_success(),
+            _ => self.0.record_failure(),
+        }
+        Ok(())
+    }
+}
+
+/// When the current request attempt was sent, see [`SlowRequestInterceptor`].
+#[derive(Debug, Clone, Copy)]
+struct AttemptStartedAt(Instant);
+
+impl Storable for AttemptStartedAt {
+    type Storer = StoreReplace<Self>;
+}
+
+/// Logs request attempts which took longer than the threshold, with the S3 request ID, so that
+/// a stall can be traced to a single request and object. See [`S3Config::slow_request_threshold`].
+#[derive(Debug)]
+struct SlowRequestInterceptor {
+    threshold: Duration,
+}
+
+impl Intercept for SlowRequestInterceptor {
+    fn name(&self) -> &'static str {
+        "SlowRequestInterceptor"
+    }
+
+    fn read_before_attempt(
+        &self,
+        _context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        cfg.interceptor_state()
+            .store_put(AttemptStartedAt(Instant::now()));
+        Ok(())
+    }
+
+    fn read_after_attempt(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let Some(AttemptStartedAt(started_at)) = cfg.load::<AttemptStartedAt>().copied() else {
+            return Ok(());
+        };
+        let elapsed = started_at.elapsed();
+        if elapsed < self.threshold {
+            return Ok(());
+        }
+
+        let request = context.request();
+        let response = context.response();
+        tracing::warn!(
+            method = request.map(|r| r.method()),
+            uri = request.map(|r| r.uri()),
+            status = response.map(|r| r.status().as_u16()),
+            request_id = response.and_then(|r| r.headers().get("x-amz-request-id")),
+            elapsed_ms = elapsed.as_millis() as u64,
+            "slow S3 request",
+        );
+        Ok(())
+    }
+}
+
+fn count_throttled() {
+    crate::metrics::BUCKET_METRICS
+        .throttled_total
+        .with_label_values(&["s3"])
+        .inc();
+}
+
+fn to_download_error<E>(err: SdkError<E, HttpResponse>, context: &'static str) -> DownloadError
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    if is_throttled(&err) {
+        count_throttled();
+        DownloadError::Throttled(anyhow::Error::new(err).context(context))
+    } else if is_forbidden(&err) {
+        DownloadError::Forbidden(anyhow::Error::new(err).context(context))
+    } else if err.code() == Some("InvalidRange") {
+        // A range starting past the end of the object
+        DownloadError::InvalidRange(anyhow::Error::new(err).context(context))
+    } else {
+        DownloadError::Other(anyhow::Error::new(err).context(context))
+    }
+}
+
+/// Converts errors of `anyhow::Error` returning operations, marking throttling with [`Throttled`].
+fn to_anyhow_error<E>(err: SdkError<E, HttpResponse>) -> anyhow::Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    if is_throttled(&err) {
+        count_throttled();
+        anyhow::Error::new(err).context(Throttled)
+    } else {
+        anyhow::Error::new(err)
+    }
+}
+
+pin_project_lite::pin_project! {
+    struct ByteStreamAsStream {
+        #[pin]
+        inner: aws_smithy_types::byte_stream::ByteStream
+    }
+}
+
+impl From<aws_smithy_types::byte_stream::ByteStream> for ByteStreamAsStream {
+    fn from(inner: aws_smithy_types::byte_stream::ByteStream) -> Self {
+        ByteStreamAsStream { inner }
+    }
+}
+
+impl Stream for ByteStreamAsStream {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // this does the std::io::ErrorKind::Other conversion
+        self.project().inner.poll_next(cx).map_err(|x| x.into())
+    }
+
+    // cannot implement size_hint because inner.size_hint is remaining size in bytes, which makes
+    // sense and Stream::size_hint does not really
+}
+
+pin_project_lite::pin_project! {
+    /// Times and tracks the outcome of the request.
+    struct TimedDownload<S> {
+        started_at: std::time::Instant,
+        outcome: AttemptOutcome,
+        #[pin]
+        inner: S
+    }
+
+    impl<S> PinnedDrop for TimedDownload<S> {
+        fn drop(mut this: Pin<&mut Self>) {
+            crate::metrics::BUCKET_METRICS.req_seconds.observe_elapsed(RequestKind::Get, this.outcome, this.started_at);
+        }
+    }
+}
+
+impl<S> TimedDownload<S> {
+    fn new(started_at: std::time::Instant, inner: S) -> Self {
+        TimedDownload {
+            started_at,
+            outcome: AttemptOutcome::Cancelled,
+            inner,
+        }
+    }
+}
+
+impl<S: Stream<Item = std::io::Result<Bytes>>> Stream for TimedDownload<S> {
+    type Item = <S as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        use std::task::ready;
+
+        let this = self.project();
+
+        let res = ready!(this.inner.poll_next(cx));
+        match &res {
+            Some(Ok(_)) => {}
+            Some(Err(_)) => *this.outcome = AttemptOutcome::Err,
+            None => *this.outcome = AttemptOutcome::Ok,
+        }
+
+        Poll::Ready(res)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl RemoteStorage for S3Bucket {
+    async fn list(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        options: ListOptions,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        let ListOptions {
+            modified_since,
+            with_metadata,
+            include_version_ids,
+        } = options;
+        let mut listing = if include_version_ids {
+            self.list_versions0(prefix, mode, max_keys, modified_since, cancel)
+                .await?
+        } else {
+            self.list0(prefix, mode, max_keys, modified_since, cancel)
+                .await?
+        };
+        if with_metadata {
+            // Only after the listing is done: lists and reads share the same concurrency limit
+            self.fetch_metadata(&mut listing.objects, cancel).await?;
+        }
+        // S3 lists in this order already, but callers merging listings rely on it
+        listing.sort();
+        Ok(listing)
+    }
+
+    async fn list_page(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
+        cancel: &CancellationToken,
+    ) -> Result<(Listing, Option<ContinuationToken>), DownloadError> {
+        let list_prefix = self.list_prefix(prefix);
+
+        let _permit = self.permit(RequestKind::List, cancel).await?;
+
+        let response = self
+            .list_objects_page(
+                list_prefix,
+                mode,
+                max_keys.map(|mk| mk.get() as i32),
+                resume_from.map(ContinuationToken::into_inner),
+                cancel,
+            )
+            .await?;
+
+        let mut listing = Listing::default();
+        for object in response.contents() {
+            let key = object.key().expect("response does not contain a key");
+            match self.listing_object(key, object.last_modified, object.size) {
+                Ok(object) => listing.objects.push(object),
+                Err(e) => skip_listed_key(&mut listing, key, e),
+            }
+        }
+        if let Some(prefixes) = response.common_prefixes.as_ref() {
+            self.push_listed_prefixes(&mut listing, prefixes);
+        }
+        listing.sort();
+
+        let next = response.next_continuation_token.map(ContinuationToken);
+        Ok((listing, next))
+    }
+
+    async fn upload(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        from_size_bytes: usize,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        if from_size_bytes > MAX_PUT_OBJECT_SIZE {
+            // Too large for a single `PutObject`, which S3 would reject
+            return self
+                .upload_unsized0(from, Some(from_size_bytes), to, options, cancel)
+                .await;
+        }
+        self.upload0(from, from_size_bytes, to, options, cancel)
+            .await
+    }
+
+    async fn upload_unsized(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.upload_unsized0(from, None, to, options, cancel).await
+    }
+
+    async fn upload_bytes(
+        &self,
+        data: Bytes,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        // The payload is at hand anyway, so let S3 verify it arrived intact
+        let options = UploadOptions {
+            content_md5: options.content_md5.or_else(|| Some(md5::compute(&data).0)),
+            ..options
+        };
+        let data_size_bytes = data.len();
+        let from = futures::stream::once(futures::future::ready(Ok(data)));
+        self.upload(from, data_size_bytes, to, options, cancel)
+            .await
+    }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        metadata: CopyMetadata,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let kind = RequestKind::Copy;
+        let permit = self.permit(kind, cancel).await?;
 
-        let upload = self
+        let timeout = self.request_timeout(kind);
+
+        let started_at = start_measuring_requests(kind);
+
+        let copy_source = copy_source(&self.bucket_name, &self.relative_path_to_s3_object(from));
+
+        let (metadata_directive, new_metadata) = match metadata.clone() {
+            CopyMetadata::Preserve => (MetadataDirective::Copy, None),
+            CopyMetadata::Replace(metadata) => {
+                (MetadataDirective::Replace, Some(metadata.normalized().0))
+            }
+        };
+
+        let op = self
             .client
-            .put_object()
+            .copy_object()
             .bucket(self.bucket_name.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .set_expected_source_bucket_owner(self.expected_bucket_owner.clone())
             .key(self.relative_path_to_s3_object(to))
-            .set_metadata(metadata.map(|m| m.0))
             .set_storage_class(self.upload_storage_class.clone())
-            .content_length(from_size_bytes.try_into()?)
-            .body(bytes_stream)
+            .copy_source(copy_source)
+            .metadata_directive(metadata_directive)
+            .set_metadata(new_metadata)
             .send();
 
-        let upload = tokio::time::timeout(self.timeout, upload);
-
         let res = tokio::select! {
-            res = upload => res,
+            res = op => res,
+            _ = timeout => return Err(TimeoutOrCancel::Timeout.into()),
             _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
         };
 
-        if let Ok(inner) = &res {
-            // do not incl. timeouts as errors in metrics but cancellations
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &res, started_at);
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) if is_copy_ineligible(&e) => {
+                // The download and upload take permits of their own
+                drop(permit);
+                tracing::info!(
+                    "{from} can't be copied server-side ({}), copying it to {to} by downloading and re-uploading it",
+                    DisplayErrorContext(&e)
+                );
+                crate::support::copy_by_streaming(self, from, to, metadata, cancel).await
+            }
+            Err(e) => Err(to_anyhow_error(e)),
+        }
+    }
+
+    async fn touch(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let key = self.relative_path_to_s3_object(path);
+
+        // S3 only accepts copying an object onto itself if something changes, so the metadata, the
+        // headers and the storage class get replaced with their current values: a replacing copy
+        // drops whatever isn't passed explicitly. The ETag condition keeps an object overwritten
+        // in the meantime from getting the metadata of the one it replaced.
+        let head = {
+            let kind = RequestKind::Get;
+            let _permit = self.permit(kind, cancel).await?;
+            let started_at = start_measuring_requests(kind);
+
+            let op = self
+                .client
+                .head_object()
+                .bucket(self.bucket_name.clone())
+                .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+                .set_request_payer(self.request_payer.clone())
+                .key(key.clone())
+                .send();
+
+            let res = tokio::select! {
+                res = op => res,
+                _ = self.request_timeout(kind) => return Err(TimeoutOrCancel::Timeout.into()),
+                _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+            };
+
             let started_at = ScopeGuard::into_inner(started_at);
             crate::metrics::BUCKET_METRICS
                 .req_seconds
-                .observe_elapsed(kind, inner, started_at);
-        }
+                .observe_elapsed(kind, &res, started_at);
 
-        match res {
-            Ok(Ok(_put)) => Ok(()),
-            Ok(Err(sdk)) => Err(sdk.into()),
-            Err(_timeout) => Err(TimeoutOrCancel::Timeout.into()),
+            res.map_err(to_anyhow_error)
+                .with_context(|| format!("head {path} to touch it"))?
+        };
+
+        // CopyObject is a single request, limited like PutObject
+        let size = head.content_length.unwrap_or(0);
+        if size > MAX_PUT_OBJECT_SIZE as i64 {
+            anyhow::bail!(
+                "cannot touch {path}: its {size} bytes are more than the {MAX_PUT_OBJECT_SIZE} bytes S3 copies in one request"
+            );
         }
-    }
 
-    async fn copy(
-        &self,
-        from: &RemotePath,
-        to: &RemotePath,
-        cancel: &CancellationToken,
-    ) -> anyhow::Result<()> {
         let kind = RequestKind::Copy;
         let _permit = self.permit(kind, cancel).await?;
-
-        let timeout = tokio::time::sleep(self.timeout);
-
         let started_at = start_measuring_requests(kind);
 
-        // we need to specify bucket_name as a prefix
-        let copy_source = format!(
-            "{}/{}",
-            self.bucket_name,
-            self.relative_path_to_s3_object(from)
-        );
-
         let op = self
             .client
             .copy_object()
             .bucket(self.bucket_name.clone())
-            .key(self.relative_path_to_s3_object(to))
-            .set_storage_class(self.upload_storage_class.clone())
-            .copy_source(copy_source)
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .set_expected_source_bucket_owner(self.expected_bucket_owner.clone())
+            .key(key.clone())
+            .copy_source(copy_source(&self.bucket_name, &key))
+            .set_copy_source_if_match(head.e_tag)
+            .set_storage_class(head.storage_class)
+            .metadata_directive(MetadataDirective::Replace)
+            .set_metadata(head.metadata)
+            .set_cache_control(head.cache_control)
+            .set_content_disposition(head.content_disposition)
+            .set_content_encoding(head.content_encoding)
+            .set_content_language(head.content_language)
+            .set_content_type(head.content_type)
+            .set_expires(head.expires)
             .send();
 
         let res = tokio::select! {
             res = op => res,
-            _ = timeout => return Err(TimeoutOrCancel::Timeout.into()),
+            _ = self.request_timeout(kind) => return Err(TimeoutOrCancel::Timeout.into()),
             _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
         };
 
@@ -678,7 +2233,7 @@ impl RemoteStorage for S3Bucket {
             .req_seconds
             .observe_elapsed(kind, &res, started_at);
 
-        res?;
+        res.map_err(to_anyhow_error)?;
 
         Ok(())
     }
@@ -687,6 +2242,26 @@ impl RemoteStorage for S3Bucket {
         &self,
         from: &RemotePath,
         cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_with_deadline(from, None, cancel).await
+    }
+
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_byte_range_with_deadline(from, start_inclusive, end_exclusive, None, cancel)
+            .await
+    }
+
+    async fn download_with_deadline(
+        &self,
+        from: &RemotePath,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
         // if prefix is not none then download file `prefix/from`
         // if prefix is none then download file `from`
@@ -696,21 +2271,25 @@ impl RemoteStorage for S3Bucket {
                 key: self.relative_path_to_s3_object(from),
                 range: None,
             },
+            deadline,
             cancel,
         )
         .await
     }
 
-    async fn download_byte_range(
+    async fn download_byte_range_with_deadline(
         &self,
         from: &RemotePath,
         start_inclusive: u64,
         end_exclusive: Option<u64>,
+        deadline: Option<tokio::time::Instant>,
         cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
+        support::check_byte_range(start_inclusive, end_exclusive)?;
+
         // S3 accepts ranges as https://www.w3.org/Protocols/rfc2616/rfc2616-sec14.html#sec14.35
         // and needs both ends to be exclusive
-        let end_inclusive = end_exclusive.map(|end| end.saturating_sub(1));
+        let end_inclusive = end_exclusive.map(|end| end - 1);
         let range = Some(match end_inclusive {
             Some(end_inclusive) => format!("bytes={start_inclusive}-{end_inclusive}"),
             None => format!("bytes={start_inclusive}-"),
@@ -722,6 +2301,7 @@ impl RemoteStorage for S3Bucket {
                 key: self.relative_path_to_s3_object(from),
                 range,
             },
+            deadline,
             cancel,
         )
         .await
@@ -746,21 +2326,145 @@ impl RemoteStorage for S3Bucket {
         self.delete_oids(&permit, &delete_objects, cancel).await
     }
 
+    async fn delete_by_tag(
+        &self,
+        prefix: &RemotePath,
+        tag_key: &str,
+        tag_value: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        // Deleting below `a/b` must not touch `a/bc`
+        let list_prefix = prefix.add_trailing_slash();
+        let pages = self.list_streaming(
+            Some(&list_prefix),
+            ListingMode::NoDelimiter,
+            None,
+            None,
+            cancel,
+        );
+        let mut pages = std::pin::pin!(pages);
+
+        // One page at a time, so that a huge prefix is never held in memory as a whole. The
+        // listing picks up after the last listed key, which deleting that key doesn't affect.
+        let mut deleted = 0;
+        while let Some(page) = pages.next().await {
+            let (page, _) =
+                page.with_context(|| format!("listing objects to delete below {prefix}"))?;
+
+            let tagged: Vec<RemotePath> = futures::stream::iter(page.objects)
+                .map(|object| async move {
+                    // Deleted since it was listed
+                    let Some(tags) = self.get_object_tags(&object.key, cancel).await? else {
+                        return Ok(None);
+                    };
+                    let is_tagged = tags.iter().any(|(k, v)| k == tag_key && v == tag_value);
+                    Ok::<_, DownloadError>(is_tagged.then_some(object.key))
+                })
+                .buffer_unordered(MAX_CONCURRENT_METADATA_REQUESTS)
+                .try_filter_map(|key| futures::future::ready(Ok(key)))
+                .try_collect()
+                .await
+                .with_context(|| format!("fetching the tags of the objects below {prefix}"))?;
+
+            self.delete_objects(&tagged, cancel).await?;
+            deleted += tagged.len();
+        }
+        Ok(deleted)
+    }
+
+    async fn head_object(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError> {
+        let output = self
+            .head_object0(key, None, cancel)
+            .await?
+            .ok_or(DownloadError::NotFound(None))?;
+        let last_modified = output
+            .last_modified
+            .context("HeadObject response has no last modified time")
+            .and_then(|t| SystemTime::try_from(t).context("last modified time is out of bounds"))
+            .map_err(DownloadError::Other)?;
+        let size = output
+            .content_length
+            .and_then(|size| u64::try_from(size).ok())
+            .context("HeadObject response has no valid content length")
+            .map_err(DownloadError::Other)?;
+        Ok(ListingObject {
+            key: key.clone(),
+            last_modified,
+            size,
+            metadata: output.metadata().cloned().map(StorageMetadata),
+            version_id: output.version_id,
+        })
+    }
+
     async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
         let paths = std::array::from_ref(path);
         self.delete_objects(paths, cancel).await
     }
 
+    async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let kind = RequestKind::Delete;
+        let _permit = self.permit(kind, cancel).await?;
+        let started_at = start_measuring_requests(kind);
+
+        // Our SDK version predates the `if_match` setter of `DeleteObject`, so the header is
+        // added to the request directly.
+        let if_match = etag.to_string();
+        let op = self
+            .client
+            .delete_object()
+            .bucket(self.bucket_name.clone())
+            .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+            .set_request_payer(self.request_payer.clone())
+            .key(self.relative_path_to_s3_object(path))
+            .customize()
+            .mutate_request(move |request| {
+                request.headers_mut().insert("If-Match", if_match.clone());
+            })
+            .send();
+
+        let res = tokio::select! {
+            res = op => res,
+            _ = self.request_timeout(kind) => return Err(TimeoutOrCancel::Timeout.into()),
+            _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+        };
+
+        let started_at = ScopeGuard::into_inner(started_at);
+        crate::metrics::BUCKET_METRICS
+            .req_seconds
+            .observe_elapsed(kind, &res, started_at);
+
+        match res {
+            Ok(_) => {
+                crate::metrics::BUCKET_METRICS.deleted_objects_total.inc();
+                Ok(())
+            }
+            Err(e) if e.raw_response().is_some_and(|r| r.status().as_u16() == 412) => {
+                Err(anyhow::Error::new(e)
+                    .context(PreconditionFailed)
+                    .context(format!("delete {path} if it matches {etag}")))
+            }
+            // Deleted already, e.g. by an earlier attempt of this deletion
+            Err(e) if e.raw_response().is_some_and(|r| r.status().as_u16() == 404) => Ok(()),
+            Err(e) => Err(to_anyhow_error(e)).with_context(|| format!("delete {path}")),
+        }
+    }
+
     async fn time_travel_recover(
         &self,
         prefix: Option<&RemotePath>,
         timestamp: SystemTime,
         done_if_after: SystemTime,
         cancel: &CancellationToken,
-    ) -> Result<(), TimeTravelError> {
-        let kind = RequestKind::TimeTravel;
-        let permit = self.permit(kind, cancel).await?;
-
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
         let timestamp = DateTime::from(timestamp);
         let done_if_after = DateTime::from(done_if_after);
 
@@ -771,211 +2475,212 @@ impl RemoteStorage for S3Bucket {
             .map(|p| self.relative_path_to_s3_object(p))
             .or_else(|| self.prefix_in_bucket.clone());
 
-        let warn_threshold = 3;
-        let max_retries = 10;
-        let is_permanent = |e: &_| matches!(e, TimeTravelError::Cancelled);
-
-        let mut key_marker = None;
-        let mut version_id_marker = None;
-        let mut versions_and_deletes = Vec::new();
-
+        // Check the whole listing before changing anything, so that an unversioned object or a
+        // listing too long to recover fails the recovery before it restored anything. Objects
+        // written after the check are only seen while restoring, so an unversioned one can still
+        // fail the recovery halfway, which then needs to be rerun once versioning is enabled.
+        let checked_count = self
+            .check_time_travel_listing(prefix.clone(), cancel)
+            .await?;
+        tracing::debug!("Recovering from {checked_count} versions and deletions");
+
+        let mut markers = VersionMarkers::default();
+        // The versions of the last key of the previous page, which may continue on the next one.
+        let mut carried_over = Vec::new();
+        let mut versions_and_deletes_count = 0;
+        let mut summary = TimeTravelSummary::default();
+
+        // Only hold one page of versions in memory at a time: versions are listed ordered by key,
+        // so all keys of a page but the last one are complete, and get restored before the next
+        // page is requested.
         loop {
-            let response = backoff::retry(
-                || async {
-                    let op = self
-                        .client
-                        .list_object_versions()
-                        .bucket(self.bucket_name.clone())
-                        .set_prefix(prefix.clone())
-                        .set_key_marker(key_marker.clone())
-                        .set_version_id_marker(version_id_marker.clone())
-                        .send();
-
-                    tokio::select! {
-                        res = op => res.map_err(|e| TimeTravelError::Other(e.into())),
-                        _ = cancel.cancelled() => Err(TimeTravelError::Cancelled),
-                    }
-                },
-                is_permanent,
-                warn_threshold,
-                max_retries,
-                "listing object versions for time_travel_recover",
-                cancel,
-            )
-            .await
-            .ok_or_else(|| TimeTravelError::Cancelled)
-            .and_then(|x| x)?;
+            let (page, is_last_page) = self
+                .list_object_versions_page(prefix.clone(), &mut markers, cancel)
+                .await?;
+            versions_and_deletes_count += page.len();
+
+            let (complete, incomplete) =
+                group_versions_by_key(std::mem::take(&mut carried_over), page, is_last_page)?;
+            carried_over = incomplete;
+
+            futures::stream::iter(complete)
+                .map(|(key, versions)| async move {
+                    self.time_travel_recover_key(&key, versions, timestamp, done_if_after, cancel)
+                        .await
+                })
+                .buffer_unordered(MAX_CONCURRENT_TIME_TRAVEL_REQUESTS)
+                .try_for_each(|key_summary| {
+                    summary += key_summary;
+                    futures::future::ready(Ok(()))
+                })
+                .await?;
 
-            tracing::trace!(
-                "  Got List response version_id_marker={:?}, key_marker={:?}",
-                response.version_id_marker,
-                response.key_marker
-            );
-            let versions = response
-                .versions
-                .unwrap_or_default()
-                .into_iter()
-                .map(VerOrDelete::from_version);
-            let deletes = response
-                .delete_markers
-                .unwrap_or_default()
-                .into_iter()
-                .map(VerOrDelete::from_delete_marker);
-            itertools::process_results(versions.chain(deletes), |n_vds| {
-                versions_and_deletes.extend(n_vds)
-            })
-            .map_err(TimeTravelError::Other)?;
-            fn none_if_empty(v: Option<String>) -> Option<String> {
-                v.filter(|v| !v.is_empty())
-            }
-            version_id_marker = none_if_empty(response.next_version_id_marker);
-            key_marker = none_if_empty(response.next_key_marker);
-            if version_id_marker.is_none() {
-                // The final response is not supposed to be truncated
-                if response.is_truncated.unwrap_or_default() {
-                    return Err(TimeTravelError::Other(anyhow::anyhow!(
-                        "Received truncated ListObjectVersions response for prefix={prefix:?}"
-                    )));
-                }
+            if is_last_page {
                 break;
             }
-            // Limit the number of versions deletions, mostly so that we don't
-            // keep requesting forever if the list is too long, as we'd put the
-            // list in RAM.
-            // Building a list of 100k entries that reaches the limit roughly takes
-            // 40 seconds, and roughly corresponds to tenants of 2 TiB physical size.
-            const COMPLEXITY_LIMIT: usize = 100_000;
-            if versions_and_deletes.len() >= COMPLEXITY_LIMIT {
-                return Err(TimeTravelError::TooManyVersions);
-            }
         }
 
         tracing::info!(
-            "Built list for time travel with {} versions and deletions",
-            versions_and_deletes.len()
+            restored = summary.restored,
+            deleted = summary.deleted,
+            skipped = summary.skipped,
+            "Finished time travel recovery over {versions_and_deletes_count} versions and deletions"
         );
+        Ok(summary)
+    }
+
+    async fn abort_incomplete_uploads(
+        &self,
+        prefix: Option<&RemotePath>,
+        older_than: Duration,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        let kind = RequestKind::List;
+        // Like in `list`, don't match the uploads of a neighbouring `prefix_in_bucket`
+        let list_prefix = self.list_prefix(prefix);
+        let cutoff = SystemTime::now()
+            .checked_sub(older_than)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
 
-        // Work on the list of references instead of the objects directly,
-        // otherwise we get lifetime errors in the sort_by_key call below.
-        let mut versions_and_deletes = versions_and_deletes.iter().collect::<Vec<_>>();
+        let mut key_marker = None;
+        let mut upload_id_marker = None;
+        let mut aborted = 0;
+        loop {
+            let permit = self.permit(kind, cancel).await?;
+            let started_at = start_measuring_requests(kind);
 
-        versions_and_deletes.sort_by_key(|vd| (&vd.key, &vd.last_modified));
+            let request = self
+                .client
+                .list_multipart_uploads()
+                .bucket(self.bucket_name.clone())
+                .set_expected_bucket_owner(self.expected_bucket_owner.clone())
+                .set_request_payer(self.request_payer.clone())
+                .set_prefix(list_prefix.clone())
+                .set_key_marker(key_marker.take())
+                .set_upload_id_marker(upload_id_marker.take())
+                .send();
 
-        let mut vds_for_key = HashMap::<_, Vec<_>>::new();
+            let response = tokio::select! {
+                res = request => res,
+                _ = self.request_timeout(kind) => return Err(TimeoutOrCancel::Timeout.into()),
+                _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+            };
 
-        for vd in &versions_and_deletes {
-            let VerOrDelete {
-                version_id, key, ..
-            } = &vd;
-            if version_id == "null" {
-                return Err(TimeTravelError::Other(anyhow!("Received ListVersions response for key={key} with version_id='null', \
-                    indicating either disabled versioning, or legacy objects with null version id values")));
-            }
-            tracing::trace!(
-                "Parsing version key={key} version_id={version_id} kind={:?}",
-                vd.kind
-            );
+            let started_at = ScopeGuard::into_inner(started_at);
+            crate::metrics::BUCKET_METRICS
+                .req_seconds
+                .observe_elapsed(kind, &response, started_at);
+            drop(permit);
 
-            vds_for_key.entry(key).or_default().push(vd);
-        }
-        for (key, versions) in vds_for_key {
-            let last_vd = versions.last().unwrap();
-            if last_vd.last_modified > done_if_after {
-                tracing::trace!("Key {key} has version later than done_if_after, skipping");
-                continue;
-            }
-            // the version we want to restore to.
-            let version_to_restore_to =
-                match versions.binary_search_by_key(&timestamp, |tpl| tpl.last_modified) {
-                    Ok(v) => v,
-                    Err(e) => e,
+            let response = response.map_err(to_anyhow_error)?;
+
+            for upload in response.uploads() {
+                let (Some(key), Some(upload_id)) = (upload.key(), upload.upload_id()) else {
+                    continue;
                 };
-            if version_to_restore_to == versions.len() {
-                tracing::trace!("Key {key} has no changes since timestamp, skipping");
-                continue;
-            }
-            let mut do_delete = false;
-            if version_to_restore_to == 0 {
-                // All versions more recent, so the key didn't exist at the specified time point.
-                tracing::trace!(
-                    "All {} versions more recent for {key}, deleting",
-                    versions.len()
-                );
-                do_delete = true;
-            } else {
-                match &versions[version_to_restore_to - 1] {
-                    VerOrDelete {
-                        kind: VerOrDeleteKind::Version,
-                        version_id,
-                        ..
-                    } => {
-                        tracing::trace!("Copying old version {version_id} for {key}...");
-                        // Restore the state to the last version by copying
-                        let source_id =
-                            format!("{}/{key}?versionId={version_id}", self.bucket_name);
-
-                        backoff::retry(
-                            || async {
-                                let op = self
-                                    .client
-                                    .copy_object()
-                                    .bucket(self.bucket_name.clone())
-                                    .key(key)
-                                    .set_storage_class(self.upload_storage_class.clone())
-                                    .copy_source(&source_id)
-                                    .send();
-
-                                tokio::select! {
-                                    res = op => res.map_err(|e| TimeTravelError::Other(e.into())),
-                                    _ = cancel.cancelled() => Err(TimeTravelError::Cancelled),
-                                }
-                            },
-                            is_permanent,
-                            warn_threshold,
-                            max_retries,
-                            "copying object version for time_travel_recover",
-                            cancel,
-                        )
-                        .await
-                        .ok_or_else(|| TimeTravelError::Cancelled)
-                        .and_then(|x| x)?;
-                        tracing::info!(%version_id, %key, "Copied old version in S3");
-                    }
-                    VerOrDelete {
-                        kind: VerOrDeleteKind::DeleteMarker,
-                        ..
-                    } => {
-                        do_delete = true;
-                    }
+                // Leave uploads of unknown age alone, they might still be in progress
+                let initiated = upload.initiated.map(SystemTime::try_from);
+                if !matches!(initiated, Some(Ok(initiated)) if initiated < cutoff) {
+                    continue;
                 }
-            };
-            if do_delete {
-                if matches!(last_vd.kind, VerOrDeleteKind::DeleteMarker) {
-                    // Key has since been deleted (but there was some history), no need to do anything
-                    tracing::trace!("Key {key} already deleted, skipping.");
-                } else {
-                    tracing::trace!("Deleting {key}...");
-
-                    let oid = ObjectIdentifier::builder()
-                        .key(key.to_owned())
-                        .build()
-                        .map_err(|e| TimeTravelError::Other(e.into()))?;
+                tracing::info!("Aborting incomplete multipart upload {upload_id} of {key}");
+                self.abort_multipart_upload(key, upload_id, cancel).await?;
+                aborted += 1;
+            }
 
-                    self.delete_oids(&permit, &[oid], cancel)
-                        .await
-                        .map_err(|e| {
-                            // delete_oid0 will use TimeoutOrCancel
-                            if TimeoutOrCancel::caused_by_cancel(&e) {
-                                TimeTravelError::Cancelled
-                            } else {
-                                TimeTravelError::Other(e)
-                            }
-                        })?;
-                }
+            if !response.is_truncated().unwrap_or(false) {
+                break;
             }
+            key_marker = response.next_key_marker;
+            upload_id_marker = response.next_upload_id_marker;
         }
-        Ok(())
+
+        Ok(aborted)
+    }
+
+    fn traffic_stats(&self) -> TrafficStats {
+        self.traffic.stats()
+    }
+
+    fn describe(&self) -> StorageDescription {
+        StorageDescription {
+            backend: "s3",
+            bucket_or_container: self.bucket_name.clone(),
+            prefix: self.prefix_in_bucket.clone(),
+            endpoint: self.endpoint.clone(),
+            region: Some(self.bucket_region.clone()),
+        }
+    }
+
+    fn redacted_config(&self) -> serde_json::Value {
+        RemoteStorageConfig {
+            storage: RemoteStorageKind::AwsS3(S3Config::clone(&self.config)),
+            timeouts: self.timeouts,
+        }
+        .redacted_config()
+    }
+}
+
+/// Where the next `ListObjectVersions` page starts, `None` for the first page.
+#[derive(Default)]
+struct VersionMarkers {
+    key_marker: Option<String>,
+    version_id_marker: Option<String>,
+}
+
+/// Fails if time travel recovery can't use the versions of `page`, because one has no version id,
+/// or because `count` of the previous pages and this one exceeds [`TIME_TRAVEL_COMPLEXITY_LIMIT`].
+fn check_time_travel_page(page: &[VerOrDelete], count: &mut usize) -> Result<(), TimeTravelError> {
+    if let Some(vd) = page.iter().find(|vd| vd.version_id == "null") {
+        return Err(TimeTravelError::VersioningDisabled {
+            key: vd.key.clone(),
+        });
+    }
+    *count += page.len();
+    if *count >= TIME_TRAVEL_COMPLEXITY_LIMIT {
+        return Err(TimeTravelError::TooManyVersions);
+    }
+    Ok(())
+}
+
+/// Groups the versions and delete markers of a `ListObjectVersions` page by key, each sorted by
+/// modification time, together with the versions `carried_over` from the previous page.
+///
+/// Unless this is the last page, the versions of the last key may continue on the next page, so
+/// they are returned separately, to be carried over to the next call.
+fn group_versions_by_key(
+    carried_over: Vec<VerOrDelete>,
+    page: Vec<VerOrDelete>,
+    is_last_page: bool,
+) -> Result<(BTreeMap<String, Vec<VerOrDelete>>, Vec<VerOrDelete>), TimeTravelError> {
+    let mut vds_for_key = BTreeMap::<_, Vec<_>>::new();
+    for vd in carried_over.into_iter().chain(page) {
+        let VerOrDelete {
+            version_id, key, ..
+        } = &vd;
+        if version_id == "null" {
+            return Err(TimeTravelError::VersioningDisabled { key: key.clone() });
+        }
+        tracing::trace!(
+            "Parsing version key={key} version_id={version_id} kind={:?}",
+            vd.kind
+        );
+
+        vds_for_key.entry(key.clone()).or_default().push(vd);
+    }
+    for versions in vds_for_key.values_mut() {
+        versions.sort_by_key(|vd| vd.last_modified);
     }
+
+    let incomplete = if is_last_page {
+        Vec::new()
+    } else {
+        vds_for_key
+            .pop_last()
+            .map(|(_, versions)| versions)
+            .unwrap_or_default()
+    };
+    Ok((vds_for_key, incomplete))
 }
 
 // Save RAM and only store the needed data instead of the entire ObjectVersion/DeleteMarkerEntry
@@ -1037,9 +2742,17 @@ impl VerOrDelete {
 #[cfg(test)]
 mod tests {
     use camino::Utf8Path;
-    use std::num::NonZeroUsize;
+    use std::{num::NonZeroUsize, sync::Arc};
+
+    use aws_sdk_s3::types::CommonPrefix;
+    use aws_smithy_types::DateTime;
 
-    use crate::{RemotePath, S3Bucket, S3Config};
+    use super::{
+        check_time_travel_page, copy_source, decode_listed_key, group_versions_by_key,
+        is_copy_ineligible, RequestKind, VerOrDelete, VerOrDeleteKind,
+        TIME_TRAVEL_COMPLEXITY_LIMIT,
+    };
+    use crate::{Listing, RemotePath, RemoteStorage, S3Bucket, S3Config, TimeTravelError};
 
     #[test]
     fn relative_path() {
@@ -1084,6 +2797,18 @@ mod tests {
                 concurrency_limit: NonZeroUsize::new(100).unwrap(),
                 max_keys_per_list_response: Some(5),
                 upload_storage_class: None,
+                max_idle_connections: None,
+                connection_idle_timeout: None,
+                connect_timeout: None,
+                operation_attempt_timeout: None,
+                sdk_max_attempts: None,
+                profile_name: None,
+                rps_limits: Default::default(),
+                expected_bucket_owner: None,
+                requester_pays: false,
+                slow_request_threshold: None,
+                user_agent_suffix: None,
+                app_name: None,
             };
             let storage =
                 S3Bucket::new(&config, std::time::Duration::ZERO).expect("remote storage init");
@@ -1094,4 +2819,214 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn special_char_keys() {
+        let key = "prefix/weird key +%/日本.bin";
+        assert_eq!(
+            copy_source("bucket", key),
+            "bucket/prefix/weird%20key%20%2B%25/%E6%97%A5%E6%9C%AC.bin"
+        );
+
+        // This is how S3 returns the key in a listing with `encoding-type=url`
+        let listed = "prefix/weird+key+%2B%25/%E6%97%A5%E6%9C%AC.bin";
+        assert_eq!(decode_listed_key(listed).unwrap(), key);
+        assert_eq!(decode_listed_key("plain/key").unwrap(), "plain/key");
+    }
+
+    #[test]
+    fn copy_ineligible_errors() {
+        use aws_sdk_s3::{
+            config::http::HttpResponse,
+            error::{ErrorMetadata, SdkError},
+            operation::copy_object::CopyObjectError,
+        };
+        use aws_smithy_types::body::SdkBody;
+
+        let error = |code: &str, message: &str| {
+            let metadata = ErrorMetadata::builder().code(code).message(message).build();
+            let raw = HttpResponse::new(400u16.try_into().unwrap(), SdkBody::empty());
+            SdkError::service_error(CopyObjectError::generic(metadata), raw)
+        };
+
+        // What S3 answers for sources above the 5 GiB limit of `CopyObject`
+        assert!(is_copy_ineligible(&error(
+            "InvalidRequest",
+            "The specified copy source is larger than the maximum allowable size for a copy source: 5368709120",
+        )));
+        // S3-compatible storages without server-side copies
+        assert!(is_copy_ineligible(&error(
+            "NotImplemented",
+            "A header you provided implies functionality that is not implemented",
+        )));
+
+        assert!(!is_copy_ineligible(&error(
+            "InvalidRequest",
+            "This copy request is illegal because it is trying to copy an object to itself without changing the object's metadata",
+        )));
+        assert!(!is_copy_ineligible(&error("AccessDenied", "Access Denied")));
+    }
+
+    #[test]
+    fn malformed_listed_keys_are_skipped() {
+        let config = S3Config {
+            bucket_name: "bucket".to_owned(),
+            bucket_region: "region".to_owned(),
+            prefix_in_bucket: None,
+            endpoint: None,
+            concurrency_limit: NonZeroUsize::new(100).unwrap(),
+            max_keys_per_list_response: None,
+            upload_storage_class: None,
+            max_idle_connections: None,
+            connection_idle_timeout: None,
+            connect_timeout: None,
+            operation_attempt_timeout: None,
+            sdk_max_attempts: None,
+            profile_name: None,
+            rps_limits: Default::default(),
+            expected_bucket_owner: None,
+            requester_pays: false,
+            slow_request_threshold: None,
+            user_agent_suffix: None,
+            app_name: None,
+        };
+        let storage =
+            S3Bucket::new(&config, std::time::Duration::ZERO).expect("remote storage init");
+
+        // `%FF` decodes to a byte which is not valid UTF-8
+        assert!(storage.listing_object("bad/%FF", None, Some(1)).is_err());
+        // Objects without a valid size fail too, rather than counting as empty
+        assert!(storage.listing_object("no/size", None, None).is_err());
+        assert!(storage
+            .listing_object("negative/size", None, Some(-1))
+            .is_err());
+        assert_eq!(
+            storage.listing_object("good", None, Some(1)).unwrap().size,
+            1
+        );
+
+        let mut listing = Listing::default();
+        let prefixes = ["good/", "bad%FF/"].map(|p| CommonPrefix::builder().prefix(p).build());
+        storage.push_listed_prefixes(&mut listing, &prefixes);
+        assert_eq!(
+            listing.prefixes,
+            vec![RemotePath::from_string("good").unwrap()]
+        );
+        assert_eq!(listing.skipped.len(), 1);
+        assert_eq!(listing.skipped[0].0, "bad%FF/");
+    }
+
+    #[test]
+    fn concurrency_limited_handles_are_independent() {
+        let config = S3Config {
+            bucket_name: "bucket".to_owned(),
+            bucket_region: "region".to_owned(),
+            prefix_in_bucket: Some("prefix/".to_owned()),
+            endpoint: None,
+            concurrency_limit: NonZeroUsize::new(100).unwrap(),
+            max_keys_per_list_response: None,
+            upload_storage_class: None,
+            max_idle_connections: None,
+            connection_idle_timeout: None,
+            connect_timeout: None,
+            operation_attempt_timeout: None,
+            sdk_max_attempts: None,
+            profile_name: None,
+            rps_limits: Default::default(),
+            expected_bucket_owner: None,
+            requester_pays: false,
+            slow_request_threshold: None,
+            user_agent_suffix: None,
+            app_name: None,
+        };
+        let storage =
+            S3Bucket::new(&config, std::time::Duration::ZERO).expect("remote storage init");
+        let limited = storage.with_concurrency_limit(NonZeroUsize::new(2).unwrap());
+
+        let available = |s: &S3Bucket| {
+            s.concurrency_limiter
+                .for_kind(RequestKind::Get)
+                .available_permits()
+        };
+        let _permit = limited
+            .concurrency_limiter
+            .for_kind(RequestKind::Get)
+            .try_acquire()
+            .unwrap();
+        assert_eq!(available(&limited), 1);
+        assert_eq!(available(&storage), 100);
+
+        assert!(Arc::ptr_eq(&storage.rate_limiter, &limited.rate_limiter));
+        assert_eq!(limited.describe(), storage.describe());
+    }
+
+    fn version(key: &str, version_id: &str, secs: i64) -> VerOrDelete {
+        VerOrDelete {
+            kind: VerOrDeleteKind::Version,
+            last_modified: DateTime::from_secs(secs),
+            version_id: version_id.to_owned(),
+            key: key.to_owned(),
+        }
+    }
+
+    #[test]
+    fn versions_are_grouped_across_pages() {
+        let ids = |versions: &[VerOrDelete]| {
+            versions
+                .iter()
+                .map(|vd| vd.version_id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // S3 lists the versions of a key newest first, and the last key may continue on the
+        // next page
+        let page = vec![
+            version("a", "a2", 2),
+            version("a", "a1", 1),
+            version("b", "b3", 3),
+        ];
+        let (complete, carried_over) = group_versions_by_key(Vec::new(), page, false).unwrap();
+        assert_eq!(complete.keys().collect::<Vec<_>>(), ["a"]);
+        assert_eq!(ids(&complete["a"]), ["a1", "a2"]);
+        assert_eq!(ids(&carried_over), ["b3"]);
+
+        let page = vec![version("b", "b2", 2), version("c", "c1", 1)];
+        let (complete, carried_over) = group_versions_by_key(carried_over, page, true).unwrap();
+        assert!(carried_over.is_empty());
+        assert_eq!(complete.keys().collect::<Vec<_>>(), ["b", "c"]);
+        assert_eq!(ids(&complete["b"]), ["b2", "b3"]);
+        assert_eq!(ids(&complete["c"]), ["c1"]);
+
+        // Versioning must be enabled
+        let page = vec![version("d", "null", 1)];
+        assert!(matches!(
+            group_versions_by_key(Vec::new(), page, true),
+            Err(TimeTravelError::VersioningDisabled { key }) if key == "d"
+        ));
+    }
+
+    #[test]
+    fn time_travel_pages_are_checked_across_pages() {
+        let mut count = 0;
+        let page = vec![version("a", "a1", 1), version("b", "b1", 1)];
+        check_time_travel_page(&page, &mut count).unwrap();
+        assert_eq!(count, 2);
+
+        // Versioning must be enabled for every object, not only the ones of the first page
+        let page = vec![version("c", "c1", 1), version("d", "null", 1)];
+        assert!(matches!(
+            check_time_travel_page(&page, &mut count),
+            Err(TimeTravelError::VersioningDisabled { key }) if key == "d"
+        ));
+
+        // The limit is on all versions below the prefix, not per key
+        let mut count = TIME_TRAVEL_COMPLEXITY_LIMIT - 2;
+        let page = vec![version("e", "e1", 1)];
+        check_time_travel_page(&page, &mut count).unwrap();
+        let page = vec![version("f", "f1", 1)];
+        assert!(matches!(
+            check_time_travel_page(&page, &mut count),
+            Err(TimeTravelError::TooManyVersions)
+        ));
+    }
 }
@@ -0,0 +1,424 @@
+//! A [`GenericRemoteStorage`] handle that is confined to a prefix of the storage.
+
+use std::{num::NonZeroU32, time::SystemTime};
+
+use anyhow::Context;
+use bytes::Bytes;
+use camino::{Utf8Component, Utf8Path};
+use futures::stream::Stream;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    Compression, CopyMetadata, Download, DownloadError, Etag, GenericRemoteStorage, ListOptions,
+    Listing, ListingMode, ListingObject, ObjectMetadata, PrefixSize, RemotePath, StorageMetadata,
+    TimeTravelError, TimeTravelSummary, UploadOptions,
+};
+
+/// A [`GenericRemoteStorage`] scoped to a prefix, e.g. `tenants/<id>`, created with
+/// [`GenericRemoteStorage::scoped`].
+///
+/// All paths passed in are relative to the prefix, and the prefix is stripped from the keys and
+/// prefixes of listings. Paths which would escape the prefix, i.e. contain `..`, are rejected, so
+/// the holder of this handle cannot read or write anything outside of its namespace.
+#[derive(Clone)]
+pub struct ScopedRemoteStorage {
+    inner: GenericRemoteStorage,
+    prefix: RemotePath,
+}
+
+impl ScopedRemoteStorage {
+    pub(crate) fn new(inner: GenericRemoteStorage, prefix: RemotePath) -> Self {
+        Self { inner, prefix }
+    }
+
+    /// The prefix all paths are relative to, relative to the root of the unscoped storage.
+    pub fn prefix(&self) -> &RemotePath {
+        &self.prefix
+    }
+
+    /// Narrows the scope further, to `prefix` relative to the current one.
+    pub fn scoped(&self, prefix: &RemotePath) -> anyhow::Result<Self> {
+        Ok(Self::new(self.inner.clone(), self.to_inner(prefix)?))
+    }
+
+    /// Maps a path relative to the scope to a path relative to the root of the storage.
+    fn to_inner(&self, path: &RemotePath) -> anyhow::Result<RemotePath> {
+        let escapes = path
+            .get_path()
+            .components()
+            .any(|c| !matches!(c, Utf8Component::Normal(_)));
+        anyhow::ensure!(
+            !escapes,
+            "Path {path} is not confined to the storage scope {}",
+            self.prefix
+        );
+        Ok(self.prefix.join(path.get_path()))
+    }
+
+    /// Prefix to list or recover when the caller passed a prefix of `prefix`.
+    ///
+    /// Listing prefixes are plain string prefixes on S3 and Azure, so without a trailing slash,
+    /// scope `tenants/a` would also match the keys of `tenants/ab`.
+    fn to_inner_prefix(&self, prefix: Option<&RemotePath>) -> anyhow::Result<RemotePath> {
+        match prefix {
+            Some(prefix) => self.to_inner(prefix),
+            None => Ok(self.prefix.add_trailing_slash()),
+        }
+    }
+
+    fn from_inner(&self, path: RemotePath) -> anyhow::Result<RemotePath> {
+        let relative = path
+            .strip_prefix(&self.prefix)
+            .with_context(|| format!("Listed path {path} is outside of scope {}", self.prefix))?;
+        RemotePath::new(relative)
+    }
+
+    /// See [`GenericRemoteStorage::list`]
+    pub async fn list(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        options: ListOptions,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        let prefix = self
+            .to_inner_prefix(prefix)
+            .map_err(DownloadError::BadInput)?;
+        let listing = self
+            .inner
+            .list(Some(&prefix), mode, max_keys, options, cancel)
+            .await?;
+
+        let prefixes = listing
+            .prefixes
+            .into_iter()
+            .map(|p| self.from_inner(p))
+            .collect::<anyhow::Result<_>>()
+            .map_err(DownloadError::Other)?;
+        let keys = listing
+            .objects
+            .into_iter()
+            .map(|object| {
+                Ok(ListingObject {
+                    key: self.from_inner(object.key)?,
+                    ..object
+                })
+            })
+            .collect::<anyhow::Result<_>>()
+            .map_err(DownloadError::Other)?;
+        Ok(Listing {
+            prefixes,
+            objects: keys,
+            skipped: listing.skipped,
+        })
+    }
+
+    /// See [`GenericRemoteStorage::upload`]
+    pub async fn upload(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let to = self.to_inner(to)?;
+        self.inner
+            .upload(from, data_size_bytes, &to, options, cancel)
+            .await
+    }
+
+    /// See [`GenericRemoteStorage::upload_bytes`]
+    pub async fn upload_bytes(
+        &self,
+        data: Bytes,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let to = self.to_inner(to)?;
+        self.inner.upload_bytes(data, &to, options, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::upload_unsized`]
+    pub async fn upload_unsized(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let to = self.to_inner(to)?;
+        self.inner.upload_unsized(from, &to, options, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::upload_compressed`]
+    pub async fn upload_compressed(
+        &self,
+        data: Bytes,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+        compression: Compression,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let to = self.to_inner(to)?;
+        self.inner
+            .upload_compressed(data, &to, metadata, compression, cancel)
+            .await
+    }
+
+    /// See [`GenericRemoteStorage::download`]
+    pub async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        let from = self.to_inner(from).map_err(DownloadError::BadInput)?;
+        self.inner.download(&from, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::download_to_file`]
+    pub async fn download_to_file(
+        &self,
+        from: &RemotePath,
+        local: &Utf8Path,
+        cancel: &CancellationToken,
+    ) -> Result<ObjectMetadata, DownloadError> {
+        let from = self.to_inner(from).map_err(DownloadError::BadInput)?;
+        self.inner.download_to_file(&from, local, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::download_byte_range`]
+    pub async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        let from = self.to_inner(from).map_err(DownloadError::BadInput)?;
+        self.inner
+            .download_byte_range(&from, start_inclusive, end_exclusive, cancel)
+            .await
+    }
+
+    /// See [`GenericRemoteStorage::head_object`]. The key of the returned object is relative to
+    /// the scope.
+    pub async fn head_object(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError> {
+        let inner_key = self.to_inner(key).map_err(DownloadError::BadInput)?;
+        let object = self.inner.head_object(&inner_key, cancel).await?;
+        Ok(ListingObject {
+            key: key.clone(),
+            ..object
+        })
+    }
+
+    /// See [`GenericRemoteStorage::delete`]
+    pub async fn delete(
+        &self,
+        path: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let path = self.to_inner(path)?;
+        self.inner.delete(&path, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::delete_if_exists`]
+    pub async fn delete_if_exists(
+        &self,
+        path: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<bool> {
+        let path = self.to_inner(path)?;
+        self.inner.delete_if_exists(&path, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::delete_if_match`]
+    pub async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let path = self.to_inner(path)?;
+        self.inner.delete_if_match(&path, etag, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::delete_objects`]
+    pub async fn delete_objects(
+        &self,
+        paths: &[RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let paths = paths
+            .iter()
+            .map(|p| self.to_inner(p))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        self.inner.delete_objects(&paths, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::delete_prefix`]
+    pub async fn delete_prefix(
+        &self,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let prefix = self.to_inner(prefix)?;
+        self.inner.delete_prefix(&prefix, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::delete_by_tag`]
+    pub async fn delete_by_tag(
+        &self,
+        prefix: &RemotePath,
+        tag_key: &str,
+        tag_value: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        let prefix = self.to_inner(prefix)?;
+        self.inner
+            .delete_by_tag(&prefix, tag_key, tag_value, cancel)
+            .await
+    }
+
+    /// See [`GenericRemoteStorage::prefix_size`]
+    pub async fn prefix_size(
+        &self,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<PrefixSize, DownloadError> {
+        let prefix = self.to_inner(prefix).map_err(DownloadError::BadInput)?;
+        self.inner.prefix_size(&prefix, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::copy_object`]. Both paths are within the scope.
+    pub async fn copy_object(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        metadata: CopyMetadata,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let from = self.to_inner(from)?;
+        let to = self.to_inner(to)?;
+        self.inner.copy_object(&from, &to, metadata, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::touch`]
+    pub async fn touch(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let path = self.to_inner(path)?;
+        self.inner.touch(&path, cancel).await
+    }
+
+    /// See [`GenericRemoteStorage::time_travel_recover`]
+    pub async fn time_travel_recover(
+        &self,
+        prefix: Option<&RemotePath>,
+        timestamp: SystemTime,
+        done_if_after: SystemTime,
+        cancel: &CancellationToken,
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
+        let prefix = self
+            .to_inner_prefix(prefix)
+            .map_err(TimeTravelError::BadInput)?;
+        self.inner
+            .time_travel_recover(Some(&prefix), timestamp, done_if_after, cancel)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use camino_tempfile::tempdir;
+
+    use super::*;
+    use crate::LocalFs;
+
+    fn path(p: &str) -> RemotePath {
+        RemotePath::from_string(p).unwrap()
+    }
+
+    async fn upload(storage: &ScopedRemoteStorage, to: &RemotePath) -> anyhow::Result<()> {
+        let body = Bytes::from_static(b"scoped contents");
+        storage
+            .upload_bytes(
+                body,
+                to,
+                UploadOptions::default(),
+                &CancellationToken::new(),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn paths_are_confined_to_scope() -> anyhow::Result<()> {
+        let root = tempdir()?;
+        let storage: GenericRemoteStorage = GenericRemoteStorage::LocalFs(LocalFs::new(
+            root.path().to_path_buf(),
+            Duration::from_secs(120),
+            false,
+        )?);
+        let cancel = CancellationToken::new();
+
+        let tenant = storage.scoped(path("tenants/a"));
+        let neighbour = storage.scoped(path("tenants/ab"));
+        upload(&tenant, &path("timelines/t1/index_part.json")).await?;
+        upload(&neighbour, &path("timelines/t2/index_part.json")).await?;
+
+        // The unscoped storage sees the full path
+        storage
+            .download(&path("tenants/a/timelines/t1/index_part.json"), &cancel)
+            .await?;
+
+        // Listings are relative to the scope, and don't leak into the neighbouring prefix
+        let listing = tenant
+            .list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
+            .await?;
+        let keys = listing
+            .objects
+            .into_iter()
+            .map(|o| o.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![path("timelines/t1/index_part.json")]);
+
+        let listing = tenant
+            .list(
+                Some(&path("timelines/")),
+                ListingMode::WithDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
+            .await?;
+        assert_eq!(listing.prefixes, vec![path("timelines/t1")]);
+
+        // Paths escaping the scope are rejected
+        assert!(upload(&tenant, &path("../ab/timelines/t3")).await.is_err());
+        assert!(matches!(
+            tenant
+                .download(&path("../ab/timelines/t2/index_part.json"), &cancel)
+                .await,
+            Err(DownloadError::BadInput(_))
+        ));
+
+        // Nested scopes compose
+        let timeline = tenant.scoped(&path("timelines/t1"))?;
+        assert_eq!(timeline.prefix(), &path("tenants/a/timelines/t1"));
+        timeline.download(&path("index_part.json"), &cancel).await?;
+
+        Ok(())
+    }
+}
@@ -6,13 +6,14 @@ use futures::stream::Stream;
 use std::collections::HashMap;
 use std::num::NonZeroU32;
 use std::sync::Mutex;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use std::{collections::hash_map::Entry, sync::Arc};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    Download, DownloadError, GenericRemoteStorage, Listing, ListingMode, RemotePath, RemoteStorage,
-    StorageMetadata, TimeTravelError,
+    ContinuationToken, CopyMetadata, Download, DownloadError, Etag, GenericRemoteStorage,
+    ListOptions, Listing, ListingMode, ListingObject, RemotePath, RemoteStorage,
+    StorageDescription, TimeTravelError, TimeTravelSummary, TrafficStats, UploadOptions,
 };
 
 pub struct UnreliableWrapper {
@@ -31,6 +32,7 @@ enum RemoteOp {
     ListPrefixes(Option<RemotePath>),
     Upload(RemotePath),
     Download(RemotePath),
+    Head(RemotePath),
     Delete(RemotePath),
     DeleteObjects(Vec<RemotePath>),
     TimeTravelRecover(Option<RemotePath>),
@@ -43,6 +45,7 @@ impl UnreliableWrapper {
             GenericRemoteStorage::AwsS3(s) => GenericRemoteStorage::AwsS3(s),
             GenericRemoteStorage::AzureBlob(s) => GenericRemoteStorage::AzureBlob(s),
             GenericRemoteStorage::LocalFs(s) => GenericRemoteStorage::LocalFs(s),
+            GenericRemoteStorage::Mirror(s) => GenericRemoteStorage::Mirror(s),
             // We could also make this a no-op, as in, extract the inner of the passed generic remote storage
             GenericRemoteStorage::Unreliable(_s) => {
                 panic!("Can't wrap unreliable wrapper unreliably")
@@ -55,6 +58,17 @@ impl UnreliableWrapper {
         }
     }
 
+    /// The wrapped storage, without the injected failures.
+    pub(crate) fn inner(&self) -> GenericRemoteStorage {
+        match &self.inner {
+            GenericRemoteStorage::AwsS3(s) => GenericRemoteStorage::AwsS3(Arc::clone(s)),
+            GenericRemoteStorage::AzureBlob(s) => GenericRemoteStorage::AzureBlob(Arc::clone(s)),
+            GenericRemoteStorage::LocalFs(s) => GenericRemoteStorage::LocalFs(s.clone()),
+            GenericRemoteStorage::Mirror(s) => GenericRemoteStorage::Mirror(Arc::clone(s)),
+            GenericRemoteStorage::Unreliable(_) => unreachable!("never wrapped, see Self::new"),
+        }
+    }
+
     ///
     /// Common functionality for all operations.
     ///
@@ -112,11 +126,29 @@ impl RemoteStorage for UnreliableWrapper {
         prefix: Option<&RemotePath>,
         mode: ListingMode,
         max_keys: Option<NonZeroU32>,
+        options: ListOptions,
         cancel: &CancellationToken,
     ) -> Result<Listing, DownloadError> {
         self.attempt(RemoteOp::ListPrefixes(prefix.cloned()))
             .map_err(DownloadError::Other)?;
-        self.inner.list(prefix, mode, max_keys, cancel).await
+        self.inner
+            .list(prefix, mode, max_keys, options, cancel)
+            .await
+    }
+
+    async fn list_page(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        max_keys: Option<NonZeroU32>,
+        resume_from: Option<ContinuationToken>,
+        cancel: &CancellationToken,
+    ) -> Result<(Listing, Option<ContinuationToken>), DownloadError> {
+        self.attempt(RemoteOp::ListPrefixes(prefix.cloned()))
+            .map_err(DownloadError::Other)?;
+        self.inner
+            .list_page(prefix, mode, max_keys, resume_from, cancel)
+            .await
     }
 
     async fn upload(
@@ -126,15 +158,26 @@ impl RemoteStorage for UnreliableWrapper {
         // otherwise it starts to fail with the concurrent connection count increasing.
         data_size_bytes: usize,
         to: &RemotePath,
-        metadata: Option<StorageMetadata>,
+        options: UploadOptions,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
         self.attempt(RemoteOp::Upload(to.clone()))?;
         self.inner
-            .upload(data, data_size_bytes, to, metadata, cancel)
+            .upload(data, data_size_bytes, to, options, cancel)
             .await
     }
 
+    async fn upload_unsized(
+        &self,
+        data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        to: &RemotePath,
+        options: UploadOptions,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.attempt(RemoteOp::Upload(to.clone()))?;
+        self.inner.upload_unsized(data, to, options, cancel).await
+    }
+
     async fn download(
         &self,
         from: &RemotePath,
@@ -142,7 +185,19 @@ impl RemoteStorage for UnreliableWrapper {
     ) -> Result<Download, DownloadError> {
         self.attempt(RemoteOp::Download(from.clone()))
             .map_err(DownloadError::Other)?;
-        self.inner.download(from, cancel).await
+        // The outer GenericRemoteStorage decodes, so pass the stored bytes on as they are.
+        self.inner.download_raw(from, None, cancel).await
+    }
+
+    async fn download_with_deadline(
+        &self,
+        from: &RemotePath,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.attempt(RemoteOp::Download(from.clone()))
+            .map_err(DownloadError::Other)?;
+        self.inner.download_raw(from, deadline, cancel).await
     }
 
     async fn download_byte_range(
@@ -162,10 +217,64 @@ impl RemoteStorage for UnreliableWrapper {
             .await
     }
 
+    async fn download_byte_range_with_deadline(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        deadline: Option<tokio::time::Instant>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.attempt(RemoteOp::Download(from.clone()))
+            .map_err(DownloadError::Other)?;
+        self.inner
+            .download_byte_range_with_deadline(
+                from,
+                start_inclusive,
+                end_exclusive,
+                deadline,
+                cancel,
+            )
+            .await
+    }
+
+    async fn head_object(
+        &self,
+        key: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<ListingObject, DownloadError> {
+        self.attempt(RemoteOp::Head(key.clone()))
+            .map_err(DownloadError::Other)?;
+        self.inner.head_object(key, cancel).await
+    }
+
     async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
         self.delete_inner(path, true, cancel).await
     }
 
+    async fn delete_if_match(
+        &self,
+        path: &RemotePath,
+        etag: &Etag,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.attempt(RemoteOp::Delete(path.clone()))?;
+        self.inner.delete_if_match(path, etag, cancel).await
+    }
+
+    async fn delete_by_tag(
+        &self,
+        prefix: &RemotePath,
+        tag_key: &str,
+        tag_value: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        self.attempt(RemoteOp::Delete(prefix.clone()))?;
+        self.inner
+            .delete_by_tag(prefix, tag_key, tag_value, cancel)
+            .await
+    }
+
     async fn delete_objects<'a>(
         &self,
         paths: &'a [RemotePath],
@@ -192,12 +301,19 @@ impl RemoteStorage for UnreliableWrapper {
         &self,
         from: &RemotePath,
         to: &RemotePath,
+        metadata: CopyMetadata,
         cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
         // copy is equivalent to download + upload
         self.attempt(RemoteOp::Download(from.clone()))?;
         self.attempt(RemoteOp::Upload(to.clone()))?;
-        self.inner.copy_object(from, to, cancel).await
+        self.inner.copy_object(from, to, metadata, cancel).await
+    }
+
+    async fn touch(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        // touch rewrites the object in place
+        self.attempt(RemoteOp::Upload(path.clone()))?;
+        self.inner.touch(path, cancel).await
     }
 
     async fn time_travel_recover(
@@ -206,11 +322,34 @@ impl RemoteStorage for UnreliableWrapper {
         timestamp: SystemTime,
         done_if_after: SystemTime,
         cancel: &CancellationToken,
-    ) -> Result<(), TimeTravelError> {
+    ) -> Result<TimeTravelSummary, TimeTravelError> {
         self.attempt(RemoteOp::TimeTravelRecover(prefix.map(|p| p.to_owned())))
             .map_err(TimeTravelError::Other)?;
         self.inner
             .time_travel_recover(prefix, timestamp, done_if_after, cancel)
             .await
     }
+
+    async fn abort_incomplete_uploads(
+        &self,
+        prefix: Option<&RemotePath>,
+        older_than: Duration,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        self.inner
+            .abort_incomplete_uploads(prefix, older_than, cancel)
+            .await
+    }
+
+    fn traffic_stats(&self) -> TrafficStats {
+        self.inner.traffic_stats()
+    }
+
+    fn describe(&self) -> StorageDescription {
+        self.inner.describe()
+    }
+
+    fn redacted_config(&self) -> serde_json::Value {
+        self.inner.redacted_config()
+    }
 }
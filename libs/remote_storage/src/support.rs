@@ -1,15 +1,35 @@
 use std::{
+    collections::{HashSet, VecDeque},
     future::Future,
+    num::NonZeroU32,
     pin::Pin,
     task::{Context, Poll},
     time::Duration,
 };
 
-use bytes::Bytes;
-use futures_util::Stream;
+use bytes::{Bytes, BytesMut};
+use camino::Utf8Path;
+use futures_util::{Stream, StreamExt, TryStreamExt};
 use tokio_util::sync::CancellationToken;
 
-use crate::TimeoutOrCancel;
+use anyhow::Context as _;
+
+use crate::{
+    ContinuationToken, CopyMetadata, Download, DownloadError, ListOptions, Listing, ListingMode,
+    ObjectMetadata, PermissionReport, PrefixSize, RemotePath, RemoteStorage, TimeoutOrCancel,
+    UploadOptions, MAX_KEYS_PER_DELETE, REMOTE_STORAGE_PREFIX_SEPARATOR,
+};
+
+/// Suffix of the temporary files which [`download_to_file`] downloads into.
+const DOWNLOAD_TEMP_FILE_SUFFIX: &str = "___download";
+
+/// How many listings [`list_with_depth`] runs at once. The concurrency limiter of the storage
+/// still applies on top of this.
+const MAX_CONCURRENT_DEPTH_LISTINGS: usize = 16;
+
+/// How many keys and prefixes [`list_page_after`] returns without a `max_keys`, like a single
+/// S3 listing response.
+const DEFAULT_LIST_PAGE_SIZE: usize = 1000;
 
 pin_project_lite::pin_project! {
     /// An `AsyncRead` adapter which carries a permit for the lifetime of the value.
@@ -38,6 +58,131 @@ impl<S: Stream> Stream for PermitCarrying<S> {
     }
 }
 
+pin_project_lite::pin_project! {
+    /// Passes the stream through, calling `progress` with the total number of bytes passed on
+    /// so far after every chunk, e.g. to log how far along a long upload is.
+    ///
+    /// Wrapping the stream of an upload, the callback runs whenever the backend takes another
+    /// chunk. For multipart uploads, that is as each part is assembled, so a value may be
+    /// reported before the part containing it has been acknowledged by the storage. Retries
+    /// within the backend don't restart the count.
+    pub struct ProgressReporting<S, F> {
+        #[pin]
+        inner: S,
+        progress: F,
+        sent: u64,
+    }
+}
+
+impl<S, F> ProgressReporting<S, F> {
+    pub fn new(inner: S, progress: F) -> Self {
+        Self {
+            inner,
+            progress,
+            sent: 0,
+        }
+    }
+}
+
+impl<S, F> Stream for ProgressReporting<S, F>
+where
+    S: Stream<Item = std::io::Result<Bytes>>,
+    F: FnMut(u64),
+{
+    type Item = <S as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let next = std::task::ready!(this.inner.poll_next(cx));
+        if let Some(Ok(chunk)) = &next {
+            *this.sent += chunk.len() as u64;
+            (this.progress)(*this.sent);
+        }
+        Poll::Ready(next)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// Passes the stream through, but replaces the chunk which completes `data_size_bytes` with
+    /// an error if the MD5 digest of the data isn't the expected one. Consumers which stop
+    /// reading after `data_size_bytes` still see the error. Streams of unknown size are verified
+    /// once they end, and without an expected digest, nothing is verified. See
+    /// [`crate::UploadOptions::content_md5`].
+    pub(crate) struct Md5Verifying<S> {
+        #[pin]
+        inner: S,
+        remaining: Option<usize>,
+        // The digest of the data so far and the expected one, until verified
+        verifying: Option<(md5::Context, [u8; 16])>,
+    }
+}
+
+impl<S> Md5Verifying<S> {
+    pub(crate) fn new(
+        inner: S,
+        data_size_bytes: Option<usize>,
+        expected: Option<[u8; 16]>,
+    ) -> Self {
+        Self {
+            inner,
+            remaining: data_size_bytes,
+            verifying: expected.map(|expected| (md5::Context::new(), expected)),
+        }
+    }
+}
+
+impl<S> Stream for Md5Verifying<S>
+where
+    S: Stream<Item = std::io::Result<Bytes>>,
+{
+    type Item = <S as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+
+        let item = std::task::ready!(this.inner.poll_next(cx));
+        let verify = match (&item, this.verifying.as_mut()) {
+            (Some(Ok(bytes)), Some((context, _))) => {
+                context.consume(bytes);
+                // Fail with the last chunk already, before the upload can complete
+                match this.remaining {
+                    Some(remaining) => {
+                        *remaining = remaining.saturating_sub(bytes.len());
+                        *remaining == 0
+                    }
+                    None => false,
+                }
+            }
+            // Also the only check of empty streams, and of those shorter than announced
+            (None, Some(_)) => true,
+            // Errors are left to the upload to report
+            _ => false,
+        };
+        if verify {
+            let (context, expected) = this.verifying.take().expect("checked above");
+            let actual = context.compute();
+            if actual.0 != expected {
+                return Poll::Ready(Some(Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "MD5 digest of the uploaded data {actual:x} doesn't match the expected {:x}",
+                        md5::Digest(expected)
+                    ),
+                ))));
+            }
+        }
+        Poll::Ready(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
 pin_project_lite::pin_project! {
     pub(crate) struct DownloadStream<F, S> {
         hit: bool,
@@ -112,6 +257,559 @@ pub(crate) fn cancel_or_timeout(
     }
 }
 
+/// The per-request `timeout` of a storage, shortened to end at the caller's `deadline` if that
+/// comes first. Backends use it for both the request and the stream of a download, so that the
+/// request is dropped, closing its connection, when the deadline passes.
+pub(crate) fn timeout_until(timeout: Duration, deadline: Option<tokio::time::Instant>) -> Duration {
+    match deadline {
+        Some(deadline) => {
+            timeout.min(deadline.saturating_duration_since(tokio::time::Instant::now()))
+        }
+        None => timeout,
+    }
+}
+
+/// Bounds `download`, and the reading of the stream it returns, by `deadline`. The default of
+/// [`RemoteStorage::download_with_deadline`], for storages without request timeouts of their own.
+pub(crate) async fn download_with_deadline(
+    deadline: Option<tokio::time::Instant>,
+    download: impl Future<Output = Result<Download, DownloadError>>,
+) -> Result<Download, DownloadError> {
+    let Some(deadline) = deadline else {
+        return download.await;
+    };
+    // Dropping the request future aborts the request, and its connection with it
+    let mut download = tokio::select! {
+        res = download => res?,
+        _ = tokio::time::sleep_until(deadline) => return Err(DownloadError::Timeout(None)),
+    };
+    let expired = async move {
+        tokio::time::sleep_until(deadline).await;
+        TimeoutOrCancel::Timeout
+    };
+    download.download_stream = Box::pin(DownloadStream::new(expired, download.download_stream));
+    Ok(download)
+}
+
+/// Splits `from` into chunks of `chunk_size` bytes, the last one shorter, so that streams of
+/// unknown size can be uploaded in parts. Only one chunk is buffered at a time.
+pub(crate) fn chunks(
+    from: impl Stream<Item = std::io::Result<Bytes>>,
+    chunk_size: usize,
+) -> impl Stream<Item = std::io::Result<Bytes>> {
+    let state = (Box::pin(from), BytesMut::new(), false);
+    futures::stream::try_unfold(state, move |(mut from, mut buffer, mut ended)| async move {
+        while !ended && buffer.len() < chunk_size {
+            match from.next().await.transpose()? {
+                Some(data) => buffer.extend_from_slice(&data),
+                None => ended = true,
+            }
+        }
+        if buffer.is_empty() {
+            return Ok(None);
+        }
+        let chunk = buffer.split_to(chunk_size.min(buffer.len())).freeze();
+        Ok(Some((chunk, (from, buffer, ended))))
+    })
+}
+
+/// The fallback of [`RemoteStorage::upload_unsized`]: buffers all of `from` in memory to learn
+/// its size, and uploads it in one go.
+pub(crate) async fn upload_buffered<S: RemoteStorage + ?Sized>(
+    storage: &S,
+    from: impl Stream<Item = std::io::Result<Bytes>>,
+    to: &RemotePath,
+    options: UploadOptions,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let mut from = std::pin::pin!(from);
+    let mut buffer = BytesMut::new();
+    while let Some(data) = from.next().await {
+        buffer.extend_from_slice(&data.context("read the upload stream")?);
+    }
+    let data = buffer.freeze();
+    let len = data.len();
+    let from = futures::stream::once(futures::future::ready(Ok(data)));
+    storage.upload(from, len, to, options, cancel).await
+}
+
+/// Rejects byte ranges without a single byte in them, before any request is made for them.
+///
+/// The start of a range is only checked against the object size by the storage itself, so that
+/// a range download stays a single request.
+pub(crate) fn check_byte_range(
+    start_inclusive: u64,
+    end_exclusive: Option<u64>,
+) -> Result<(), DownloadError> {
+    match end_exclusive {
+        Some(end_exclusive) if end_exclusive <= start_inclusive => {
+            Err(DownloadError::InvalidRange(anyhow::anyhow!(
+                "range {start_inclusive}..{end_exclusive} is empty or inverted"
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Walks the delimiter hierarchy below `prefix` breadth first, yielding every common prefix.
+///
+/// This is the [`RemoteStorage::list_prefixes_recursive`] implementation for object stores, where
+/// each level takes one (paginated) listing. The stream ends after the first error.
+pub(crate) fn list_prefixes_breadth_first<'a, S: RemoteStorage + ?Sized>(
+    storage: &'a S,
+    prefix: Option<&'a RemotePath>,
+    cancel: &'a CancellationToken,
+) -> impl Stream<Item = Result<RemotePath, DownloadError>> + 'a {
+    // Listing `a/b` would also match `a/bc`, so always list "inside" a prefix.
+    let to_list = VecDeque::from([prefix.map(RemotePath::add_trailing_slash)]);
+    futures::stream::unfold(
+        (to_list, VecDeque::new()),
+        move |(mut to_list, mut found)| async move {
+            loop {
+                if let Some(prefix) = found.pop_front() {
+                    return Some((Ok(prefix), (to_list, found)));
+                }
+                let prefix = to_list.pop_front()?;
+                let listing = storage
+                    .list(
+                        prefix.as_ref(),
+                        ListingMode::WithDelimiter,
+                        None,
+                        ListOptions::default(),
+                        cancel,
+                    )
+                    .await;
+                match listing {
+                    Ok(listing) => {
+                        for prefix in listing.prefixes {
+                            to_list.push_back(Some(prefix.add_trailing_slash()));
+                            found.push_back(prefix);
+                        }
+                    }
+                    Err(e) => return Some((Err(e), (VecDeque::new(), VecDeque::new()))),
+                }
+            }
+        },
+    )
+}
+
+/// The [`RemoteStorage::list_page`] implementation for storages without native paging: lists
+/// `prefix` in full, and returns the keys and prefixes after `resume_from` in listing order. The
+/// token is the last key or prefix of the page.
+pub(crate) async fn list_page_after<S: RemoteStorage + ?Sized>(
+    storage: &S,
+    prefix: Option<&RemotePath>,
+    mode: ListingMode,
+    max_keys: Option<NonZeroU32>,
+    resume_from: Option<ContinuationToken>,
+    cancel: &CancellationToken,
+) -> Result<(Listing, Option<ContinuationToken>), DownloadError> {
+    let listing = storage
+        .list(prefix, mode, None, ListOptions::default(), cancel)
+        .await?;
+    let page_size = max_keys.map_or(DEFAULT_LIST_PAGE_SIZE, |mk| mk.get() as usize);
+    let resume_from = resume_from.map(ContinuationToken::into_inner);
+
+    // Prefixes and keys are interleaved by path, so that the token orders both of them.
+    let prefixes = listing.prefixes.into_iter().map(|prefix| (prefix, None));
+    let keys = listing
+        .objects
+        .into_iter()
+        .map(|object| (object.key.clone(), Some(object)));
+    let mut entries: Vec<_> = prefixes
+        .chain(keys)
+        .filter(|(path, _)| {
+            resume_from
+                .as_deref()
+                .map_or(true, |after| path.get_path().as_str() > after)
+        })
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.get_path().as_str().cmp(b.get_path().as_str()));
+
+    let next = (entries.len() > page_size).then(|| {
+        entries.truncate(page_size);
+        let (last, _) = entries.last().expect("page size is not zero");
+        ContinuationToken(last.get_path().to_string())
+    });
+
+    let mut page = Listing::default();
+    if resume_from.is_none() {
+        page.skipped = listing.skipped;
+    }
+    for (path, object) in entries {
+        match object {
+            Some(object) => page.objects.push(object),
+            None => page.prefixes.push(path),
+        }
+    }
+    Ok((page, next))
+}
+
+/// The [`RemoteStorage::list_streaming`] implementation, listing one page after another.
+pub(crate) fn list_streaming<'a, S: RemoteStorage + ?Sized>(
+    storage: &'a S,
+    prefix: Option<&'a RemotePath>,
+    mode: ListingMode,
+    max_keys_per_page: Option<NonZeroU32>,
+    resume_from: Option<ContinuationToken>,
+    cancel: &'a CancellationToken,
+) -> impl Stream<Item = Result<(Listing, Option<ContinuationToken>), DownloadError>> + 'a {
+    // `None` once the last page or an error was yielded
+    futures::stream::unfold(Some(resume_from), move |resume_from| async move {
+        let resume_from = resume_from?;
+        match storage
+            .list_page(prefix, mode, max_keys_per_page, resume_from, cancel)
+            .await
+        {
+            Ok((page, next)) => Some((Ok((page, next.clone())), next.map(Some))),
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+/// Normalizes a configured `prefix_in_bucket` or `prefix_in_container`, the "subfolder" all
+/// keys of a storage live in, by dropping leading and trailing separators from it.
+///
+/// The cloud backends store and list keys through [`join_storage_prefix`],
+/// [`storage_list_prefix`] and [`strip_storage_prefix`] with the normalized prefix, so the same
+/// configured prefix maps a [`RemotePath`] to the same key on all of them.
+pub(crate) fn normalize_storage_prefix(prefix: Option<&str>) -> Option<String> {
+    prefix.map(|prefix| {
+        prefix
+            .trim_matches(REMOTE_STORAGE_PREFIX_SEPARATOR)
+            .to_string()
+    })
+}
+
+/// The key of `path` below the normalized `storage_prefix`. A trailing separator of `path` is
+/// kept, so that listing such a prefix only matches keys inside it.
+pub(crate) fn join_storage_prefix(storage_prefix: Option<&str>, path: &RemotePath) -> String {
+    let path = path.get_path().as_str();
+    match storage_prefix {
+        Some(prefix) => format!("{prefix}{REMOTE_STORAGE_PREFIX_SEPARATOR}{path}"),
+        None => path.to_string(),
+    }
+}
+
+/// The key prefix to list for `prefix`, or without one everything inside `storage_prefix`, but
+/// not in a neighbouring prefix which merely starts with the same string.
+pub(crate) fn storage_list_prefix(
+    storage_prefix: Option<&str>,
+    prefix: Option<&RemotePath>,
+) -> Option<String> {
+    match prefix {
+        Some(prefix) => Some(join_storage_prefix(storage_prefix, prefix)),
+        None => storage_prefix.map(|s| format!("{s}{REMOTE_STORAGE_PREFIX_SEPARATOR}")),
+    }
+}
+
+/// The reverse of [`join_storage_prefix`], for keys returned by the storage. `None` if `key` is
+/// not inside `storage_prefix`.
+pub(crate) fn strip_storage_prefix(storage_prefix: Option<&str>, key: &str) -> Option<RemotePath> {
+    let relative = match storage_prefix {
+        Some(prefix) => key
+            .strip_prefix(prefix)?
+            .strip_prefix(REMOTE_STORAGE_PREFIX_SEPARATOR)?,
+        None => key,
+    };
+    Some(RemotePath(
+        relative.split(REMOTE_STORAGE_PREFIX_SEPARATOR).collect(),
+    ))
+}
+
+/// See [`RemoteStorage::list_with_depth`].
+///
+/// Lists one level at a time, with up to [`MAX_CONCURRENT_DEPTH_LISTINGS`] listings of the
+/// prefixes found on the previous level in flight.
+pub(crate) async fn list_with_depth<S: RemoteStorage + ?Sized>(
+    storage: &S,
+    prefix: Option<&RemotePath>,
+    depth: NonZeroU32,
+    cancel: &CancellationToken,
+) -> Result<Listing, DownloadError> {
+    let mut result = storage
+        .list(
+            prefix,
+            ListingMode::WithDelimiter,
+            None,
+            ListOptions::default(),
+            cancel,
+        )
+        .await?;
+
+    let mut to_list = result.prefixes.clone();
+    for _ in 1..depth.get() {
+        if to_list.is_empty() {
+            break;
+        }
+        let listings: Vec<Listing> = futures::stream::iter(std::mem::take(&mut to_list))
+            .map(|prefix| async move {
+                // Listing `a/b` would also match `a/bc`, so always list "inside" a prefix.
+                storage
+                    .list(
+                        Some(&prefix.add_trailing_slash()),
+                        ListingMode::WithDelimiter,
+                        None,
+                        ListOptions::default(),
+                        cancel,
+                    )
+                    .await
+            })
+            .buffer_unordered(MAX_CONCURRENT_DEPTH_LISTINGS)
+            .try_collect()
+            .await?;
+        for listing in listings {
+            to_list.extend(listing.prefixes.iter().cloned());
+            result.prefixes.extend(listing.prefixes);
+            result.objects.extend(listing.objects);
+            result.skipped.extend(listing.skipped);
+        }
+    }
+
+    result.sort();
+    Ok(result)
+}
+
+/// See [`RemoteStorage::download_to_file`].
+pub(crate) async fn download_to_file<S: RemoteStorage + ?Sized>(
+    storage: &S,
+    from: &RemotePath,
+    local: &Utf8Path,
+    cancel: &CancellationToken,
+) -> Result<ObjectMetadata, DownloadError> {
+    use tokio::io::AsyncWriteExt;
+
+    let download = storage.download(from, cancel).await?;
+
+    // Concurrent downloads to the same path must not share the temp file
+    let temp_path = utils::crashsafe::path_with_suffix_extension(
+        local,
+        &format!("{:08x}.{DOWNLOAD_TEMP_FILE_SUFFIX}", rand::random::<u32>()),
+    );
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .await
+        .with_context(|| format!("Failed to create temp file '{temp_path}' for the download"))
+        .map_err(DownloadError::Other)?;
+    // Removes the temp file on any error, and also if this future is dropped midway
+    let temp_file_guard = scopeguard::guard(temp_path.clone(), |temp_path| {
+        if let Err(e) = std::fs::remove_file(&temp_path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Failed to remove temp file '{temp_path}': {e}");
+            }
+        }
+    });
+
+    let mut reader = tokio_util::io::StreamReader::new(download.download_stream);
+    let mut writer = tokio::io::BufWriter::new(file);
+    // Errors of the stream itself, like timeouts, come back as they are
+    let size = tokio::io::copy_buf(&mut reader, &mut writer).await?;
+    writer.flush().await?;
+    drop(writer);
+
+    utils::crashsafe::durable_rename(&temp_path, local, true)
+        .await
+        .with_context(|| format!("Failed to move the download '{temp_path}' to '{local}'"))
+        .map_err(DownloadError::Other)?;
+    scopeguard::ScopeGuard::into_inner(temp_file_guard);
+
+    Ok(ObjectMetadata {
+        last_modified: download.last_modified,
+        etag: download.etag,
+        size,
+        metadata: download.metadata,
+    })
+}
+
+/// Copies `from` to `to` by downloading the object and uploading it again, for objects which the
+/// storage can't copy server-side. The content encoding of the object is kept, as by a
+/// server-side copy.
+///
+/// This is the fallback of the [`RemoteStorage::copy`] implementations, and transfers the whole
+/// object twice. The objects that can't be copied server-side are mostly the ones too large for
+/// it, which are also too large for a single S3 `PutObject`, so the object is uploaded again with
+/// [`RemoteStorage::upload_unsized`], i.e. in parts.
+pub(crate) async fn copy_by_streaming<S: RemoteStorage + ?Sized>(
+    storage: &S,
+    from: &RemotePath,
+    to: &RemotePath,
+    metadata: CopyMetadata,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let download = storage.download(from, cancel).await.map_err(|e| match e {
+        DownloadError::Cancelled(_) => anyhow::Error::new(TimeoutOrCancel::Cancel),
+        DownloadError::Timeout(_) => anyhow::Error::new(TimeoutOrCancel::Timeout),
+        e => anyhow::Error::new(e).context(format!("downloading {from} to copy it to {to}")),
+    })?;
+    let metadata = match metadata {
+        CopyMetadata::Preserve => download.metadata,
+        CopyMetadata::Replace(metadata) => Some(metadata),
+    };
+    let options = UploadOptions {
+        metadata,
+        content_encoding: download.content_encoding,
+        ..Default::default()
+    };
+    storage
+        .upload_unsized(download.download_stream, to, options, cancel)
+        .await
+}
+
+/// Deletes every object below `prefix`, listing and deleting up to [`MAX_KEYS_PER_DELETE`] keys
+/// at a time. Returns how many objects were deleted.
+///
+/// This is the [`RemoteStorage::delete_prefix`] implementation for all backends. Deleted keys drop
+/// out of the listing, so every batch is simply the first page of a fresh listing.
+pub(crate) async fn delete_prefix<S: RemoteStorage + ?Sized>(
+    storage: &S,
+    prefix: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<usize> {
+    // Deleting `a/b` must not touch `a/bc`
+    let prefix_to_list = prefix.add_trailing_slash();
+    let batch_size = NonZeroU32::new(MAX_KEYS_PER_DELETE as u32).expect("non-zero");
+
+    let mut deleted = 0;
+    let mut previous_batch = HashSet::new();
+    loop {
+        let listing = storage
+            .list(
+                Some(&prefix_to_list),
+                ListingMode::NoDelimiter,
+                Some(batch_size),
+                ListOptions::default(),
+                cancel,
+            )
+            .await
+            .with_context(|| format!("listing objects to delete below {prefix}"))?;
+        let batch = listing
+            .objects
+            .into_iter()
+            .map(|object| object.key)
+            .collect::<Vec<_>>();
+        if batch.is_empty() {
+            return Ok(deleted);
+        }
+        // Don't spin forever on a backend which acknowledges deletions it didn't do
+        if let Some(key) = batch.iter().find(|key| previous_batch.contains(*key)) {
+            anyhow::bail!("{key} is still listed after being deleted");
+        }
+
+        storage.delete_objects(&batch, cancel).await?;
+        deleted += batch.len();
+        previous_batch = batch.into_iter().collect();
+    }
+}
+
+/// Sums up the sizes of the objects below `prefix`, from a listing of it.
+///
+/// This is the [`RemoteStorage::prefix_size`] implementation for all backends. The listing is
+/// folded one [`RemoteStorage::list_streaming`] page at a time, so only a single page of keys is
+/// held in memory however many objects are below `prefix`.
+pub(crate) async fn prefix_size<S: RemoteStorage + ?Sized>(
+    storage: &S,
+    prefix: &RemotePath,
+    cancel: &CancellationToken,
+) -> Result<PrefixSize, DownloadError> {
+    // The size of `a/b` must not include `a/bc`
+    let prefix = prefix.add_trailing_slash();
+    storage
+        .list_streaming(Some(&prefix), ListingMode::NoDelimiter, None, None, cancel)
+        .try_fold(PrefixSize::default(), |size, (page, _)| async move {
+            Ok(page.objects.iter().fold(size, |size, object| PrefixSize {
+                total_bytes: size.total_bytes + object.size,
+                object_count: size.object_count + 1,
+            }))
+        })
+        .await
+}
+
+/// See [`RemoteStorage::list_with_common_prefix_counts`].
+///
+/// The common prefixes are sized as their listing pages come in, up to
+/// [`MAX_CONCURRENT_DEPTH_LISTINGS`] at a time, each with its own [`prefix_size`]. Neither the
+/// top level listing nor the listings below the prefixes are collected, only the results are.
+pub(crate) async fn list_with_common_prefix_counts<S: RemoteStorage + ?Sized>(
+    storage: &S,
+    prefix: Option<&RemotePath>,
+    cancel: &CancellationToken,
+) -> Result<Vec<(RemotePath, PrefixSize)>, DownloadError> {
+    let mut sizes: Vec<(RemotePath, PrefixSize)> = storage
+        .list_streaming(prefix, ListingMode::WithDelimiter, None, None, cancel)
+        .map_ok(|(page, _)| futures::stream::iter(page.prefixes.into_iter().map(Ok)))
+        .try_flatten()
+        .map_ok(|prefix| async move {
+            let size = prefix_size(storage, &prefix, cancel).await?;
+            Ok::<_, DownloadError>((prefix, size))
+        })
+        .try_buffer_unordered(MAX_CONCURRENT_DEPTH_LISTINGS)
+        .try_collect()
+        .await?;
+    sizes.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(sizes)
+}
+
+/// See [`crate::GenericRemoteStorage::verify_permissions`].
+pub(crate) async fn verify_permissions<S: RemoteStorage + ?Sized>(
+    storage: &S,
+    cancel: &CancellationToken,
+) -> PermissionReport {
+    let probe_dir =
+        RemotePath::from_string(&format!(".permission_probe/{:016x}", rand::random::<u64>()))
+            .expect("probe path is relative");
+    let object = probe_dir.join("object");
+    let copy = probe_dir.join("copy");
+    let mut report = PermissionReport::default();
+
+    let put = storage
+        .upload_bytes(
+            Bytes::from_static(b"permission probe"),
+            &object,
+            UploadOptions::default(),
+            cancel,
+        )
+        .await;
+    report.can_put = report.record("put", put);
+
+    // Without the upload there is nothing to read, but the storage only tells a missing object
+    // apart from access denied to callers which may read, so that still counts as allowed.
+    let get = match storage.download(&object, cancel).await {
+        Err(DownloadError::NotFound(_)) if !report.can_put => Ok(()),
+        res => res.map(|_| ()).map_err(anyhow::Error::from),
+    };
+    report.can_get = report.record("get", get);
+
+    let list = storage
+        .list(
+            Some(&probe_dir.add_trailing_slash()),
+            ListingMode::NoDelimiter,
+            None,
+            ListOptions::default(),
+            cancel,
+        )
+        .await;
+    report.can_list = report.record("list", list.map_err(anyhow::Error::from));
+
+    let copied = if report.can_put {
+        storage
+            .copy(&object, &copy, CopyMetadata::Preserve, cancel)
+            .await
+    } else {
+        Err(anyhow::anyhow!(
+            "not probed, the source object could not be uploaded"
+        ))
+    };
+    report.can_copy = report.record("copy", copied);
+
+    // Deleting a missing object succeeds, so this is probed even if nothing was uploaded
+    let deleted = storage.delete_objects(&[object, copy], cancel).await;
+    report.can_delete = report.record("delete", deleted);
+
+    report
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -142,11 +840,11 @@ mod tests {
         assert!(
             inner
                 .downcast_ref::<DownloadError>()
-                .is_some_and(|e| matches!(e, DownloadError::Cancelled)),
+                .is_some_and(|e| matches!(e, DownloadError::Cancelled(_))),
             "{inner:?}"
         );
         let e = DownloadError::from(e);
-        assert!(matches!(e, DownloadError::Cancelled), "{e:?}");
+        assert!(matches!(e, DownloadError::Cancelled(_)), "{e:?}");
 
         tokio::select! {
             _ = stream.next() => unreachable!("no timeout ever happens as we were already cancelled"),
@@ -172,11 +870,11 @@ mod tests {
         assert!(
             inner
                 .downcast_ref::<DownloadError>()
-                .is_some_and(|e| matches!(e, DownloadError::Timeout)),
+                .is_some_and(|e| matches!(e, DownloadError::Timeout(_))),
             "{inner:?}"
         );
         let e = DownloadError::from(e);
-        assert!(matches!(e, DownloadError::Timeout), "{e:?}");
+        assert!(matches!(e, DownloadError::Timeout(_)), "{e:?}");
 
         cancel.cancel();
 
@@ -186,6 +884,215 @@ mod tests {
         }
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn download_past_deadline() {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let response = |headers_after: Duration| async move {
+            tokio::time::sleep(headers_after).await;
+            Ok(Download {
+                download_stream: Box::pin(futures::stream::pending()),
+                last_modified: std::time::SystemTime::UNIX_EPOCH,
+                etag: "etag".into(),
+                content_length: 10,
+                object_size: 10,
+                metadata: None,
+                content_encoding: None,
+            })
+        };
+
+        // The response doesn't arrive in time
+        let res = download_with_deadline(Some(deadline), response(Duration::from_secs(11))).await;
+        assert!(matches!(res, Err(DownloadError::Timeout(_))));
+
+        // The response arrives, but the body stalls
+        let download = download_with_deadline(Some(deadline), response(Duration::from_secs(1)))
+            .await
+            .expect("headers arrived before the deadline");
+        let mut stream = download.download_stream;
+        let e = stream
+            .next()
+            .await
+            .expect("there must be some")
+            .unwrap_err();
+        assert!(tokio::time::Instant::now() >= deadline);
+        let e = DownloadError::from(e);
+        assert!(matches!(e, DownloadError::Timeout(_)), "{e:?}");
+
+        // Without a deadline, nothing changes
+        let download = download_with_deadline(None, response(Duration::from_secs(100)))
+            .await
+            .expect("no deadline");
+        let mut stream = download.download_stream;
+        tokio::select! {
+            _ = stream.next() => unreachable!("the stream never yields"),
+            _ = tokio::time::sleep(Duration::from_secs(1000)) => {},
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeout_until_deadline() {
+        let timeout = Duration::from_secs(120);
+        assert_eq!(timeout_until(timeout, None), timeout);
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        assert_eq!(
+            timeout_until(timeout, Some(deadline)),
+            Duration::from_secs(10)
+        );
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(1000);
+        assert_eq!(timeout_until(timeout, Some(deadline)), timeout);
+
+        // A deadline in the past leaves no time at all
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        let deadline = tokio::time::Instant::now() - Duration::from_secs(10);
+        assert_eq!(timeout_until(timeout, Some(deadline)), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn chunks_of_unsized_stream() {
+        let from = futures::stream::iter(["abc", "de", "", "fghij", "k"])
+            .map(|s| Ok(Bytes::from_static(s.as_bytes())));
+        let chunks: Vec<_> = chunks(from, 4).map(|chunk| chunk.unwrap()).collect().await;
+        assert_eq!(chunks, ["abcd", "efgh", "ijk"]);
+
+        let empty = futures::stream::empty::<std::io::Result<Bytes>>();
+        assert_eq!(chunks(empty, 4).count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn md5_verifying_empty_stream() {
+        let empty = || futures::stream::empty::<std::io::Result<Bytes>>();
+
+        let verified: Vec<_> = Md5Verifying::new(empty(), Some(0), Some(md5::compute(b"").0))
+            .collect()
+            .await;
+        assert!(verified.is_empty());
+
+        let mut verified = std::pin::pin!(Md5Verifying::new(
+            empty(),
+            Some(0),
+            Some(md5::compute(b"a").0)
+        ));
+        let e = verified.next().await.expect("an error").unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData, "{e:?}");
+        assert!(verified.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn md5_verifying_unsized_stream() {
+        let from =
+            || futures::stream::iter(["ab", "c"]).map(|s| Ok(Bytes::from_static(s.as_bytes())));
+
+        let verified: Vec<_> = Md5Verifying::new(from(), None, Some(md5::compute(b"abc").0))
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        assert_eq!(verified, ["ab", "c"]);
+
+        // Without a size, the mismatch is only found once the stream ends
+        let mut verified = std::pin::pin!(Md5Verifying::new(from(), None, Some([0; 16])));
+        assert_eq!(verified.next().await.unwrap().unwrap(), "ab");
+        assert_eq!(verified.next().await.unwrap().unwrap(), "c");
+        let e = verified.next().await.expect("an error").unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData, "{e:?}");
+
+        let unverified: Vec<_> = Md5Verifying::new(from(), Some(3), None)
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        assert_eq!(unverified, ["ab", "c"]);
+    }
+
+    #[test]
+    fn storage_prefix_join() {
+        let path = RemotePath::from_string("tenants/t1/index_part.json").unwrap();
+        let dir = RemotePath::from_string("tenants/").unwrap();
+
+        for configured in ["prefix", "prefix/", "/prefix", "//prefix//"] {
+            let prefix = normalize_storage_prefix(Some(configured));
+            let prefix = prefix.as_deref();
+            assert_eq!(prefix, Some("prefix"));
+
+            let key = join_storage_prefix(prefix, &path);
+            assert_eq!(key, "prefix/tenants/t1/index_part.json");
+            assert_eq!(strip_storage_prefix(prefix, &key), Some(path.clone()));
+
+            assert_eq!(
+                storage_list_prefix(prefix, Some(&dir)).as_deref(),
+                Some("prefix/tenants/")
+            );
+            assert_eq!(
+                storage_list_prefix(prefix, None).as_deref(),
+                Some("prefix/")
+            );
+        }
+
+        // Keys of a neighbouring prefix are not ours
+        assert_eq!(
+            strip_storage_prefix(Some("prefix"), "prefix2/tenants/t1"),
+            None
+        );
+
+        assert_eq!(normalize_storage_prefix(None), None);
+        assert_eq!(
+            join_storage_prefix(None, &path),
+            "tenants/t1/index_part.json"
+        );
+        assert_eq!(storage_list_prefix(None, None), None);
+        assert_eq!(
+            strip_storage_prefix(None, "tenants/t1/index_part.json"),
+            Some(path)
+        );
+    }
+
+    #[tokio::test]
+    async fn copy_by_streaming_keeps_encoding_and_metadata() -> anyhow::Result<()> {
+        use crate::{Compression, LocalFs, StorageMetadata, UploadOptions};
+
+        let root = camino_tempfile::tempdir()?;
+        let storage = LocalFs::new(root.path().to_owned(), Duration::from_secs(120), false)?;
+        let cancel = CancellationToken::new();
+
+        let from = RemotePath::from_string("a/from")?;
+        let to = RemotePath::from_string("a/to")?;
+        let body = Bytes::from_static(b"not actually gzip, but never decoded here");
+        let len = body.len();
+        let metadata = StorageMetadata::from([("one", "1")]);
+        let options = UploadOptions {
+            metadata: Some(metadata.clone()),
+            content_encoding: Some(Compression::Gzip),
+            ..Default::default()
+        };
+        storage
+            .upload(
+                futures::stream::once(futures::future::ready(Ok(body.clone()))),
+                len,
+                &from,
+                options,
+                &cancel,
+            )
+            .await?;
+
+        copy_by_streaming(&storage, &from, &to, CopyMetadata::Preserve, &cancel).await?;
+        let download = storage.download(&to, &cancel).await?;
+        assert_eq!(download.metadata, Some(metadata));
+        assert_eq!(download.content_encoding, Some(Compression::Gzip));
+        let copied = download
+            .download_stream
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+        assert_eq!(copied, &body[..]);
+
+        let replaced = StorageMetadata::from([("two", "2")]);
+        let replace = CopyMetadata::Replace(replaced.clone());
+        copy_by_streaming(&storage, &from, &to, replace, &cancel).await?;
+        let download = storage.download(&to, &cancel).await?;
+        assert_eq!(download.metadata, Some(replaced));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn notified_but_pollable_after() {
         let inner = futures::stream::once(futures::future::ready(Ok(bytes::Bytes::from_static(
@@ -203,7 +1110,7 @@ mod tests {
         assert!(
             matches!(
                 ioe.get_ref().unwrap().downcast_ref::<DownloadError>(),
-                Some(&DownloadError::Cancelled)
+                Some(&DownloadError::Cancelled(_))
             ),
             "{ioe:?}"
         );
@@ -0,0 +1,89 @@
+//! Per-backend traffic counters, which unlike the prometheus metrics can be read in-process.
+
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::Stream;
+
+/// Cumulative traffic of a storage backend since it was created, see
+/// [`crate::GenericRemoteStorage::traffic_stats`].
+///
+/// The counters are kept per backend instance rather than per process, so code owning a storage,
+/// e.g. for a single tenant, can attribute the traffic to it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    /// Bytes of successfully uploaded objects.
+    pub bytes_uploaded: u64,
+    /// Bytes read from download streams, which may be less than the size of the downloaded
+    /// objects if the streams were dropped early.
+    pub bytes_downloaded: u64,
+    /// Requests issued to the storage, including failed ones.
+    pub requests: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct TrafficCounters {
+    bytes_uploaded: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    requests: AtomicU64,
+}
+
+impl TrafficCounters {
+    pub(crate) fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_upload(&self, bytes: usize) {
+        self.bytes_uploaded
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> TrafficStats {
+        TrafficStats {
+            bytes_uploaded: self.bytes_uploaded.load(Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// Counts the bytes read from a download stream as downloaded.
+    pub(crate) struct CountingDownload<S> {
+        counters: Arc<TrafficCounters>,
+        #[pin]
+        inner: S,
+    }
+}
+
+impl<S> CountingDownload<S> {
+    pub(crate) fn new(counters: Arc<TrafficCounters>, inner: S) -> Self {
+        Self { counters, inner }
+    }
+}
+
+impl<S: Stream<Item = std::io::Result<Bytes>>> Stream for CountingDownload<S> {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let res = this.inner.poll_next(cx);
+        if let Poll::Ready(Some(Ok(bytes))) = &res {
+            this.counters
+                .bytes_downloaded
+                .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
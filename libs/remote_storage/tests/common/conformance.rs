@@ -0,0 +1,454 @@
+//! The contract every [`RemoteStorage`] backend must satisfy, as a single suite which the test
+//! binaries run against each backend they set up.
+//!
+//! This module only depends on the public API of the crate, so that it can be included on its own,
+//! without the rest of `common`.
+
+use std::time::Duration;
+
+use anyhow::{ensure, Context};
+use bytes::Bytes;
+use futures::StreamExt;
+use remote_storage::{
+    CopyMetadata, Download, DownloadError, ListOptions, ListingMode, RemotePath, RemoteStorage,
+    StorageMetadata, TimeoutOrCancel, UploadOptions,
+};
+use std::num::NonZeroU32;
+use tokio_util::sync::CancellationToken;
+
+/// Runs all conformance checks against `storage`, using only keys below `base`, which should be
+/// empty. The objects created are deleted again when the checks pass.
+pub(crate) async fn run_conformance<S: RemoteStorage>(
+    storage: &S,
+    base: &RemotePath,
+) -> anyhow::Result<()> {
+    let cancel = CancellationToken::new();
+
+    upload_download_round_trip(storage, base, &cancel)
+        .await
+        .context("upload/download round trip")?;
+    unsized_upload(storage, base, &cancel)
+        .await
+        .context("upload of unknown size")?;
+    byte_range_reads(storage, base, &cancel)
+        .await
+        .context("byte range reads")?;
+    metadata_is_preserved(storage, base, &cancel)
+        .await
+        .context("metadata preservation")?;
+    listing_order_delimiters_and_max_keys(storage, base, &cancel)
+        .await
+        .context("listing")?;
+    delete_is_idempotent(storage, base, &cancel)
+        .await
+        .context("idempotent delete")?;
+    copy_with_metadata(storage, base, &cancel)
+        .await
+        .context("copy with metadata")?;
+    cancelled_upload_stores_nothing(storage, base, &cancel)
+        .await
+        .context("cancellation")?;
+
+    storage.delete_prefix(base, &cancel).await?;
+    Ok(())
+}
+
+fn key(base: &RemotePath, name: &str) -> RemotePath {
+    RemotePath::from_string(&format!("{base}/{name}")).expect("valid key")
+}
+
+async fn download_bytes(download: Download) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    tokio::io::copy_buf(
+        &mut tokio_util::io::StreamReader::new(download.download_stream),
+        &mut buf,
+    )
+    .await?;
+    Ok(buf)
+}
+
+async fn upload_download_round_trip<S: RemoteStorage>(
+    storage: &S,
+    base: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let path = key(base, "round_trip");
+    let body = Bytes::from_static(b"remote blob data here");
+    storage
+        .upload_bytes(body.clone(), &path, UploadOptions::default(), cancel)
+        .await?;
+
+    let download = storage.download(&path, cancel).await?;
+    ensure!(download.content_length == body.len() as u64);
+    ensure!(download.object_size == body.len() as u64);
+    ensure!(download_bytes(download).await? == body);
+
+    let head = storage.head_object(&path, cancel).await?;
+    ensure!(head.key == path, "head of {path} returned key {}", head.key);
+    ensure!(head.size == body.len() as u64);
+
+    // Overwriting replaces the contents
+    let body = Bytes::from_static(b"replaced");
+    storage
+        .upload_bytes(body.clone(), &path, UploadOptions::default(), cancel)
+        .await?;
+    ensure!(download_bytes(storage.download(&path, cancel).await?).await? == body);
+
+    match storage.download(&key(base, "missing"), cancel).await {
+        Err(DownloadError::NotFound(_)) => Ok(()),
+        Err(e) => Err(e).context("download of a missing object"),
+        Ok(_) => anyhow::bail!("download of a missing object succeeded"),
+    }
+}
+
+async fn unsized_upload<S: RemoteStorage>(
+    storage: &S,
+    base: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let path = key(base, "unsized");
+    let parts = ["streamed ", "without ", "", "a size"];
+    let from = futures::stream::iter(parts).map(|part| Ok(Bytes::from_static(part.as_bytes())));
+    let metadata = StorageMetadata::from([("foo", "bar")]);
+    storage
+        .upload_unsized(
+            from,
+            &path,
+            UploadOptions {
+                metadata: Some(metadata.clone()),
+                ..Default::default()
+            },
+            cancel,
+        )
+        .await?;
+
+    let download = storage.download(&path, cancel).await?;
+    ensure!(download.metadata == Some(metadata));
+    ensure!(download_bytes(download).await? == parts.concat().as_bytes());
+    Ok(())
+}
+
+async fn byte_range_reads<S: RemoteStorage>(
+    storage: &S,
+    base: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let path = key(base, "byte_ranges");
+    let body = Bytes::from_static(b"0123456789abcdef");
+    let len = body.len() as u64;
+    storage
+        .upload_bytes(body.clone(), &path, UploadOptions::default(), cancel)
+        .await?;
+
+    let ranges = [
+        (0, Some(len), 0..body.len()),
+        (4, Some(10), 4..10),
+        (4, Some(5), 4..5),
+        // An end past the end of the object is clamped to it
+        (8, Some(len * 100), 8..body.len()),
+        (0, Some(u64::MAX), 0..body.len()),
+        (4, None, 4..body.len()),
+        (0, None, 0..body.len()),
+    ];
+    for (start, end, expected) in ranges {
+        let download = storage
+            .download_byte_range(&path, start, end, cancel)
+            .await
+            .with_context(|| format!("range {start}..{end:?}"))?;
+        ensure!(download.content_length == expected.len() as u64);
+        ensure!(download.object_size == len, "range {start}..{end:?}");
+        ensure!(
+            download_bytes(download).await? == body[expected],
+            "range {start}..{end:?}"
+        );
+    }
+
+    // Inverted and empty ranges are rejected before any request, ranges starting past the end
+    // of the object by the storage
+    let invalid = [
+        (10, Some(4)),
+        (4, Some(4)),
+        (len, None),
+        (len * 100, Some(len * 200)),
+        (u64::MAX, None),
+    ];
+    for (start, end) in invalid {
+        match storage.download_byte_range(&path, start, end, cancel).await {
+            Err(DownloadError::InvalidRange(_)) => {}
+            Err(e) => return Err(e).with_context(|| format!("invalid range {start}..{end:?}")),
+            Ok(_) => anyhow::bail!("invalid range {start}..{end:?} was downloaded"),
+        }
+    }
+    Ok(())
+}
+
+async fn metadata_is_preserved<S: RemoteStorage>(
+    storage: &S,
+    base: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let path = key(base, "with_metadata");
+    storage
+        .upload_bytes(
+            Bytes::from_static(b"data"),
+            &path,
+            UploadOptions {
+                metadata: Some(StorageMetadata::from([("Foo", "Bar"), ("baz", "Qux")])),
+                ..Default::default()
+            },
+            cancel,
+        )
+        .await?;
+
+    // Every backend returns the same map, whatever case it stores keys in
+    let expected = Some(StorageMetadata::from([("foo", "Bar"), ("baz", "Qux")]));
+    let download = storage.download(&path, cancel).await?;
+    ensure!(download.metadata == expected, "{:?}", download.metadata);
+    let head = storage.head_object(&path, cancel).await?;
+    ensure!(head.metadata == expected, "{:?}", head.metadata);
+
+    let path = key(base, "without_metadata");
+    storage
+        .upload_bytes(
+            Bytes::from_static(b"data"),
+            &path,
+            UploadOptions::default(),
+            cancel,
+        )
+        .await?;
+    let metadata = storage.download(&path, cancel).await?.metadata;
+    ensure!(
+        metadata.map_or(true, |m| m == StorageMetadata::default()),
+        "object uploaded without metadata has some"
+    );
+    Ok(())
+}
+
+async fn listing_order_delimiters_and_max_keys<S: RemoteStorage>(
+    storage: &S,
+    base: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let names = ["a-b", "a/b", "a/c/d", "a/c/e", "b", "b0/f"];
+    for name in names {
+        let path = key(base, &format!("tree/{name}"));
+        storage
+            .upload_bytes(
+                Bytes::from(name.as_bytes().to_vec()),
+                &path,
+                UploadOptions::default(),
+                cancel,
+            )
+            .await?;
+    }
+    let tree = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| key(base, &format!("tree/{name}")))
+            .collect::<Vec<_>>()
+    };
+    let prefix = key(base, "tree").add_trailing_slash();
+
+    let list = |mode, max_keys: Option<u32>| {
+        let prefix = &prefix;
+        async move {
+            let listing = storage
+                .list(
+                    Some(prefix),
+                    mode,
+                    max_keys.and_then(NonZeroU32::new),
+                    ListOptions::default(),
+                    cancel,
+                )
+                .await?;
+            ensure!(listing.is_sorted(), "{mode:?} listing is not sorted");
+            anyhow::Ok(listing)
+        }
+    };
+
+    // Keys are listed in the byte order of their paths, not component-wise
+    let listing = list(ListingMode::NoDelimiter, None).await?;
+    let keys: Vec<_> = listing.objects.iter().map(|o| o.key.clone()).collect();
+    ensure!(keys == tree(&names), "{keys:?}");
+    ensure!(listing.prefixes.is_empty());
+    for (object, name) in listing.objects.iter().zip(names) {
+        ensure!(object.size == name.len() as u64, "size of {}", object.key);
+    }
+
+    let listing = list(ListingMode::WithDelimiter, None).await?;
+    let keys: Vec<_> = listing.objects.iter().map(|o| o.key.clone()).collect();
+    ensure!(keys == tree(&["a-b", "b"]), "{keys:?}");
+    ensure!(
+        listing.prefixes == tree(&["a", "b0"]),
+        "{:?}",
+        listing.prefixes
+    );
+
+    let listing = list(ListingMode::NoDelimiter, Some(2)).await?;
+    let keys: Vec<_> = listing.objects.iter().map(|o| o.key.clone()).collect();
+    ensure!(keys == tree(&["a-b", "a/b"]), "{keys:?}");
+
+    // A prefix is a plain string prefix, also matching keys which continue it
+    let listing = storage
+        .list(
+            Some(&key(base, "tree/b")),
+            ListingMode::NoDelimiter,
+            None,
+            ListOptions::default(),
+            cancel,
+        )
+        .await?;
+    let keys: Vec<_> = listing.objects.iter().map(|o| o.key.clone()).collect();
+    ensure!(keys == tree(&["b", "b0/f"]), "{keys:?}");
+
+    storage.delete_objects(&tree(&names), cancel).await?;
+    let listing = list(ListingMode::NoDelimiter, None).await?;
+    ensure!(listing.objects.is_empty() && listing.prefixes.is_empty());
+    Ok(())
+}
+
+async fn delete_is_idempotent<S: RemoteStorage>(
+    storage: &S,
+    base: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let path = key(base, "to_delete");
+    let missing = key(base, "never_existed");
+    storage
+        .upload_bytes(
+            Bytes::from_static(b"data"),
+            &path,
+            UploadOptions::default(),
+            cancel,
+        )
+        .await?;
+
+    storage
+        .delete(&missing, cancel)
+        .await
+        .context("delete of a missing object")?;
+    storage
+        .delete_objects(&[missing.clone(), path.clone()], cancel)
+        .await
+        .context("delete_objects including a missing object")?;
+    match storage.head_object(&path, cancel).await {
+        Err(DownloadError::NotFound(_)) => {}
+        res => anyhow::bail!("deleted object is still there: {res:?}"),
+    }
+    storage
+        .delete(&path, cancel)
+        .await
+        .context("repeated delete")?;
+    storage
+        .delete_objects(&[path.clone()], cancel)
+        .await
+        .context("repeated delete_objects")?;
+    Ok(())
+}
+
+async fn copy_with_metadata<S: RemoteStorage>(
+    storage: &S,
+    base: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let source = key(base, "copy_source");
+    let body = Bytes::from_static(b"copied contents");
+    let source_metadata = StorageMetadata::from([("source", "original")]);
+    storage
+        .upload_bytes(
+            body.clone(),
+            &source,
+            UploadOptions {
+                metadata: Some(source_metadata.clone()),
+                ..Default::default()
+            },
+            cancel,
+        )
+        .await?;
+
+    let preserved = key(base, "copy_preserved");
+    storage
+        .copy(&source, &preserved, CopyMetadata::Preserve, cancel)
+        .await?;
+    let download = storage.download(&preserved, cancel).await?;
+    ensure!(download.metadata.as_ref() == Some(&source_metadata));
+    ensure!(download_bytes(download).await? == body);
+
+    let replaced_metadata = StorageMetadata::from([("source", "replaced")]);
+    let replaced = key(base, "copy_replaced");
+    storage
+        .copy(
+            &source,
+            &replaced,
+            CopyMetadata::Replace(replaced_metadata.clone()),
+            cancel,
+        )
+        .await?;
+    let download = storage.download(&replaced, cancel).await?;
+    ensure!(download.metadata.as_ref() == Some(&replaced_metadata));
+    ensure!(download_bytes(download).await? == body);
+
+    // Replacing with no metadata drops the source's, rather than keeping it
+    let cleared = key(base, "copy_cleared");
+    storage
+        .copy(
+            &source,
+            &cleared,
+            CopyMetadata::Replace(StorageMetadata::from([] as [(&str, &str); 0])),
+            cancel,
+        )
+        .await?;
+    let download = storage.download(&cleared, cancel).await?;
+    ensure!(download.metadata.as_ref() != Some(&source_metadata));
+    ensure!(download_bytes(download).await? == body);
+
+    // The source is left as it was
+    let download = storage.download(&source, cancel).await?;
+    ensure!(download.metadata.as_ref() == Some(&source_metadata));
+
+    match storage
+        .copy(
+            &key(base, "missing"),
+            &key(base, "copy_of_missing"),
+            CopyMetadata::Preserve,
+            cancel,
+        )
+        .await
+    {
+        Err(_) => Ok(()),
+        Ok(()) => anyhow::bail!("copy of a missing object succeeded"),
+    }
+}
+
+async fn cancelled_upload_stores_nothing<S: RemoteStorage>(
+    storage: &S,
+    base: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let path = key(base, "cancelled");
+    // Half of the promised bytes arrive, then the stream stalls
+    let body = futures::stream::once(futures::future::ready(Ok(Bytes::from_static(b"12345"))))
+        .chain(futures::stream::pending());
+
+    let upload_cancel = cancel.child_token();
+    let (res, ()) = tokio::join!(
+        storage.upload(body, 10, &path, UploadOptions::default(), &upload_cancel),
+        async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            upload_cancel.cancel();
+        }
+    );
+    let e = match res {
+        Ok(()) => anyhow::bail!("cancelled upload succeeded"),
+        Err(e) => e,
+    };
+    ensure!(
+        TimeoutOrCancel::caused_by_cancel(&e),
+        "upload failed with something else than a cancel: {e:#}"
+    );
+    match storage.head_object(&path, cancel).await {
+        Err(DownloadError::NotFound(_)) => {}
+        res => anyhow::bail!("cancelled upload stored an object: {res:?}"),
+    }
+    Ok(())
+}
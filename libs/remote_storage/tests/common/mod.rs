@@ -8,11 +8,13 @@ use bytes::Bytes;
 use camino::Utf8Path;
 use futures::stream::Stream;
 use once_cell::sync::OnceCell;
-use remote_storage::{Download, GenericRemoteStorage, RemotePath};
+use remote_storage::{Download, GenericRemoteStorage, RemotePath, UploadOptions};
 use tokio::task::JoinSet;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info};
 
+pub(crate) mod conformance;
+
 static LOGGING_DONE: OnceCell<()> = OnceCell::new();
 
 pub(crate) fn upload_stream(
@@ -75,7 +77,7 @@ pub(crate) async fn upload_simple_remote_data(
 
             let (data, len) = upload_stream(format!("remote blob data {i}").into_bytes().into());
             task_client
-                .upload(data, len, &blob_path, None, &cancel)
+                .upload(data, len, &blob_path, UploadOptions::default(), &cancel)
                 .await?;
 
             Ok::<_, anyhow::Error>(blob_path)
@@ -106,6 +108,24 @@ pub(crate) async fn upload_simple_remote_data(
     }
 }
 
+/// A `prefix_in_bucket` or `prefix_in_container` which keeps the objects of concurrent tests and
+/// test runs sharing a bucket or container apart.
+pub(crate) fn random_test_prefix() -> anyhow::Result<String> {
+    use rand::Rng;
+
+    // due to how time works, we've had test runners use the same nanos as bucket prefixes.
+    // millis is just a debugging aid for easier finding the prefix later.
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("random test prefix part calculation")?
+        .as_millis();
+
+    // because nanos can be the same for two threads so can millis, add randomness
+    let random = rand::thread_rng().gen::<u32>();
+
+    Ok(format!("test_{millis}_{random:08x}/"))
+}
+
 pub(crate) async fn cleanup(
     client: &Arc<GenericRemoteStorage>,
     objects_to_delete: HashSet<RemotePath>,
@@ -166,7 +186,13 @@ pub(crate) async fn upload_remote_data(
             let (data, data_len) =
                 upload_stream(format!("remote blob data {i}").into_bytes().into());
             task_client
-                .upload(data, data_len, &blob_path, None, &cancel)
+                .upload(
+                    data,
+                    data_len,
+                    &blob_path,
+                    UploadOptions::default(),
+                    &cancel,
+                )
                 .await?;
 
             Ok::<_, anyhow::Error>((blob_prefix, blob_path))
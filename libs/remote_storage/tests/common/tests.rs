@@ -1,13 +1,22 @@
 use anyhow::Context;
 use camino::Utf8Path;
+use remote_storage::Compression;
+use remote_storage::CopyMetadata;
+use remote_storage::GenericRemoteStorage;
+use remote_storage::ListOptions;
 use remote_storage::ListingMode;
+use remote_storage::LocalFs;
 use remote_storage::RemotePath;
+use remote_storage::StorageMetadata;
+use remote_storage::UploadOptions;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{collections::HashSet, num::NonZeroU32};
 use test_context::test_context;
 use tokio_util::sync::CancellationToken;
 use tracing::debug;
 
+use crate::common::conformance::run_conformance;
 use crate::common::{download_to_vec, upload_stream, wrap_stream};
 
 use super::{
@@ -55,7 +64,13 @@ async fn pagination_should_work(ctx: &mut MaybeEnabledStorageWithTestBlobs) -> a
     let base_prefix = RemotePath::new(Utf8Path::new(ctx.enabled.base_prefix))
         .context("common_prefix construction")?;
     let root_remote_prefixes = test_client
-        .list(None, ListingMode::WithDelimiter, None, &cancel)
+        .list(
+            None,
+            ListingMode::WithDelimiter,
+            None,
+            ListOptions::default(),
+            &cancel,
+        )
         .await?
         .prefixes
         .into_iter()
@@ -70,6 +85,7 @@ async fn pagination_should_work(ctx: &mut MaybeEnabledStorageWithTestBlobs) -> a
             Some(&base_prefix.add_trailing_slash()),
             ListingMode::WithDelimiter,
             None,
+            ListOptions::default(),
             &cancel,
         )
         .await?
@@ -115,11 +131,18 @@ async fn list_no_delimiter_works(
     let base_prefix =
         RemotePath::new(Utf8Path::new("folder1")).context("common_prefix construction")?;
     let root_files = test_client
-        .list(None, ListingMode::NoDelimiter, None, &cancel)
+        .list(
+            None,
+            ListingMode::NoDelimiter,
+            None,
+            ListOptions::default(),
+            &cancel,
+        )
         .await
         .context("client list root files failure")?
-        .keys
+        .objects
         .into_iter()
+        .map(|o| o.key)
         .collect::<HashSet<_>>();
     assert_eq!(
         root_files,
@@ -134,18 +157,26 @@ async fn list_no_delimiter_works(
             None,
             ListingMode::NoDelimiter,
             Some(NonZeroU32::new(2).unwrap()),
+            ListOptions::default(),
             &cancel,
         )
         .await
         .context("client list root files failure")?;
-    assert_eq!(limited_root_files.keys.len(), 2);
+    assert_eq!(limited_root_files.objects.len(), 2);
 
     let nested_remote_files = test_client
-        .list(Some(&base_prefix), ListingMode::NoDelimiter, None, &cancel)
+        .list(
+            Some(&base_prefix),
+            ListingMode::NoDelimiter,
+            None,
+            ListOptions::default(),
+            &cancel,
+        )
         .await
         .context("client list nested files failure")?
-        .keys
+        .objects
         .into_iter()
+        .map(|o| o.key)
         .collect::<HashSet<_>>();
     let trim_remote_blobs: HashSet<_> = ctx
         .remote_blobs
@@ -184,6 +215,38 @@ async fn delete_non_exising_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Resu
     Ok(())
 }
 
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn delete_if_exists_reports_missing_keys(
+    ctx: &mut MaybeEnabledStorage,
+) -> anyhow::Result<()> {
+    let ctx = match ctx {
+        MaybeEnabledStorage::Enabled(ctx) => ctx,
+        MaybeEnabledStorage::Disabled => return Ok(()),
+    };
+
+    let cancel = CancellationToken::new();
+
+    let path = RemotePath::new(Utf8Path::new(
+        format!("{}/delete_if_exists", ctx.base_prefix).as_str(),
+    ))
+    .with_context(|| "RemotePath conversion")?;
+
+    assert!(!ctx.client.delete_if_exists(&path, &cancel).await?);
+
+    let (data, len) = upload_stream("remote blob data".as_bytes().into());
+    ctx.client
+        .upload(data, len, &path, UploadOptions::default(), &cancel)
+        .await?;
+
+    assert!(ctx.client.delete_if_exists(&path, &cancel).await?);
+    // Deleting is idempotent, the object is gone now
+    assert!(!ctx.client.delete_if_exists(&path, &cancel).await?);
+    ctx.client.delete(&path, &cancel).await?;
+
+    Ok(())
+}
+
 #[test_context(MaybeEnabledStorage)]
 #[tokio::test]
 async fn delete_objects_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
@@ -204,19 +267,31 @@ async fn delete_objects_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<(
         .with_context(|| "RemotePath conversion")?;
 
     let (data, len) = upload_stream("remote blob data1".as_bytes().into());
-    ctx.client.upload(data, len, &path1, None, &cancel).await?;
+    ctx.client
+        .upload(data, len, &path1, UploadOptions::default(), &cancel)
+        .await?;
 
     let (data, len) = upload_stream("remote blob data2".as_bytes().into());
-    ctx.client.upload(data, len, &path2, None, &cancel).await?;
+    ctx.client
+        .upload(data, len, &path2, UploadOptions::default(), &cancel)
+        .await?;
 
     let (data, len) = upload_stream("remote blob data3".as_bytes().into());
-    ctx.client.upload(data, len, &path3, None, &cancel).await?;
+    ctx.client
+        .upload(data, len, &path3, UploadOptions::default(), &cancel)
+        .await?;
 
     ctx.client.delete_objects(&[path1, path2], &cancel).await?;
 
     let prefixes = ctx
         .client
-        .list(None, ListingMode::WithDelimiter, None, &cancel)
+        .list(
+            None,
+            ListingMode::WithDelimiter,
+            None,
+            ListOptions::default(),
+            &cancel,
+        )
         .await?
         .prefixes;
 
@@ -227,6 +302,53 @@ async fn delete_objects_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<(
     Ok(())
 }
 
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn delete_prefix_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let cancel = CancellationToken::new();
+
+    let path = |p: &str| {
+        RemotePath::new(Utf8Path::new(format!("{}/{p}", ctx.base_prefix).as_str()))
+            .with_context(|| "RemotePath conversion")
+    };
+    let doomed = [path("dir/a")?, path("dir/b")?, path("dir/sub/c")?];
+    let neighbour = path("dir_neighbour/d")?;
+
+    for p in doomed.iter().chain([&neighbour]) {
+        let (data, len) = upload_stream("remote blob data".as_bytes().into());
+        ctx.client
+            .upload(data, len, p, UploadOptions::default(), &cancel)
+            .await?;
+    }
+
+    ctx.client.delete_prefix(&path("dir")?, &cancel).await?;
+
+    // Only the neighbouring prefix, which merely shares the string prefix, is left
+    let keys = ctx
+        .client
+        .list(
+            Some(&path("")?),
+            ListingMode::NoDelimiter,
+            None,
+            ListOptions::default(),
+            &cancel,
+        )
+        .await?
+        .objects
+        .into_iter()
+        .map(|o| o.key)
+        .collect::<Vec<_>>();
+    assert_eq!(keys, vec![neighbour.clone()]);
+
+    ctx.client.delete_objects(&[neighbour], &cancel).await?;
+
+    Ok(())
+}
+
 #[test_context(MaybeEnabledStorage)]
 #[tokio::test]
 async fn upload_download_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
@@ -243,11 +365,15 @@ async fn upload_download_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<
 
     let (data, len) = wrap_stream(orig.clone());
 
-    ctx.client.upload(data, len, &path, None, &cancel).await?;
+    ctx.client
+        .upload(data, len, &path, UploadOptions::default(), &cancel)
+        .await?;
 
     // Normal download request
     let dl = ctx.client.download(&path, &cancel).await?;
+    let content_length = dl.content_length;
     let buf = download_to_vec(dl).await?;
+    assert_eq!(content_length, buf.len() as u64);
     assert_eq!(&buf, &orig);
 
     // Full range (end specified)
@@ -255,7 +381,9 @@ async fn upload_download_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<
         .client
         .download_byte_range(&path, 0, Some(len as u64), &cancel)
         .await?;
+    let content_length = dl.content_length;
     let buf = download_to_vec(dl).await?;
+    assert_eq!(content_length, buf.len() as u64);
     assert_eq!(&buf, &orig);
 
     // partial range (end specified)
@@ -263,7 +391,9 @@ async fn upload_download_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<
         .client
         .download_byte_range(&path, 4, Some(10), &cancel)
         .await?;
+    let content_length = dl.content_length;
     let buf = download_to_vec(dl).await?;
+    assert_eq!(content_length, buf.len() as u64);
     assert_eq!(&buf, &orig[4..10]);
 
     // partial range (end beyond real end)
@@ -271,7 +401,9 @@ async fn upload_download_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<
         .client
         .download_byte_range(&path, 8, Some(len as u64 * 100), &cancel)
         .await?;
+    let content_length = dl.content_length;
     let buf = download_to_vec(dl).await?;
+    assert_eq!(content_length, buf.len() as u64);
     assert_eq!(&buf, &orig[8..]);
 
     // Partial range (end unspecified)
@@ -279,7 +411,9 @@ async fn upload_download_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<
         .client
         .download_byte_range(&path, 4, None, &cancel)
         .await?;
+    let content_length = dl.content_length;
     let buf = download_to_vec(dl).await?;
+    assert_eq!(content_length, buf.len() as u64);
     assert_eq!(&buf, &orig[4..]);
 
     // Full range (end unspecified)
@@ -287,7 +421,9 @@ async fn upload_download_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<
         .client
         .download_byte_range(&path, 0, None, &cancel)
         .await?;
+    let content_length = dl.content_length;
     let buf = download_to_vec(dl).await?;
+    assert_eq!(content_length, buf.len() as u64);
     assert_eq!(&buf, &orig);
 
     debug!("Cleanup: deleting file at path {path:?}");
@@ -299,6 +435,314 @@ async fn upload_download_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<
     Ok(())
 }
 
+/// A key with characters that need escaping must be listable, and the listed path must download
+/// and copy the same object.
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn special_char_key_round_trip(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let cancel = CancellationToken::new();
+
+    let prefix = RemotePath::new(Utf8Path::new(
+        format!("{}/special", ctx.base_prefix).as_str(),
+    ))
+    .with_context(|| "RemotePath conversion")?;
+    let path = prefix.join("weird key +%/日本.bin");
+
+    let orig = bytes::Bytes::from_static("special key contents".as_bytes());
+    let (data, len) = wrap_stream(orig.clone());
+    ctx.client
+        .upload(data, len, &path, UploadOptions::default(), &cancel)
+        .await?;
+
+    let listing = ctx
+        .client
+        .list(
+            Some(&prefix.add_trailing_slash()),
+            ListingMode::NoDelimiter,
+            None,
+            ListOptions::default(),
+            &cancel,
+        )
+        .await?;
+    let listed = listing
+        .objects
+        .into_iter()
+        .map(|o| o.key)
+        .collect::<Vec<_>>();
+    assert_eq!(listed, vec![path.clone()]);
+
+    let listing = ctx
+        .client
+        .list(
+            Some(&prefix.add_trailing_slash()),
+            ListingMode::WithDelimiter,
+            None,
+            ListOptions::default(),
+            &cancel,
+        )
+        .await?;
+    assert_eq!(listing.prefixes, vec![prefix.join("weird key +%")]);
+
+    let dl = ctx.client.download(&listed[0], &cancel).await?;
+    let buf = download_to_vec(dl).await?;
+    assert_eq!(&buf, &orig);
+
+    let path_dest = prefix.join("copy of weird key +%");
+    ctx.client
+        .copy_object(&listed[0], &path_dest, CopyMetadata::default(), &cancel)
+        .await?;
+    let dl = ctx.client.download(&path_dest, &cancel).await?;
+    let buf = download_to_vec(dl).await?;
+    assert_eq!(&buf, &orig);
+
+    debug!("Cleanup: deleting files at {prefix:?}");
+    ctx.client
+        .delete_objects(&[path, path_dest], &cancel)
+        .await
+        .with_context(|| format!("{prefix:?} removal"))?;
+
+    Ok(())
+}
+
+/// Every backend stores a path below its prefix as `{prefix}/{path}`, so the same path names the
+/// same key in a bucket and in a container. The keys are observed through a client without a
+/// prefix.
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn prefixed_key_round_trip(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let cancel = CancellationToken::new();
+
+    let storage_prefix = ctx
+        .client
+        .describe()
+        .prefix
+        .context("the tests use a prefix")?;
+    assert!(!storage_prefix.is_empty());
+    let prefix = RemotePath::from_string(&format!("{}/prefixed", ctx.base_prefix))?;
+    let path = prefix.join("some/blob");
+    let key = RemotePath::from_string(&format!("{storage_prefix}/{path}"))?;
+
+    let orig = bytes::Bytes::from_static("prefixed key contents".as_bytes());
+    ctx.client
+        .upload_bytes(orig.clone(), &path, UploadOptions::default(), &cancel)
+        .await?;
+
+    async fn list_keys(
+        client: &GenericRemoteStorage,
+        prefix: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Vec<RemotePath>> {
+        let listing = client
+            .list(
+                Some(&prefix.add_trailing_slash()),
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions::default(),
+                cancel,
+            )
+            .await?;
+        Ok(listing.objects.into_iter().map(|o| o.key).collect())
+    }
+    assert_eq!(
+        list_keys(&ctx.client, &prefix, &cancel).await?,
+        vec![path.clone()]
+    );
+    let key_prefix = RemotePath::from_string(&format!("{storage_prefix}/{prefix}"))?;
+    assert_eq!(
+        list_keys(&ctx.unprefixed_client, &key_prefix, &cancel).await?,
+        vec![key.clone()]
+    );
+
+    let buf = download_to_vec(ctx.client.download(&path, &cancel).await?).await?;
+    assert_eq!(&buf, &orig);
+    let buf = download_to_vec(ctx.unprefixed_client.download(&key, &cancel).await?).await?;
+    assert_eq!(&buf, &orig);
+
+    ctx.client.delete(&path, &cancel).await?;
+
+    Ok(())
+}
+
+/// Open ended byte ranges must stream to the end of the object on every backend, including objects
+/// large enough for the SDK to fetch them in several chunks.
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn download_byte_range_to_eof_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let cancel = CancellationToken::new();
+
+    let path = RemotePath::new(Utf8Path::new(
+        format!("{}/file_to_eof", ctx.base_prefix).as_str(),
+    ))
+    .with_context(|| "RemotePath conversion")?;
+
+    // A bit over 2.5MiB, so that the Azure SDK's 1MiB chunking needs several requests
+    let orig = (0..(5 * 1024 * 1024 / 2 + 13))
+        .map(|i: usize| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    let orig = bytes::Bytes::from(orig);
+
+    let (data, len) = wrap_stream(orig.clone());
+
+    ctx.client
+        .upload(data, len, &path, UploadOptions::default(), &cancel)
+        .await?;
+
+    for start in [0, 1, 1024 * 1024 + 7, len - 1] {
+        let dl = ctx
+            .client
+            .download_byte_range(&path, start as u64, None, &cancel)
+            .await?;
+        let buf = download_to_vec(dl).await?;
+        assert_eq!(
+            buf.len(),
+            len - start,
+            "open ended range from {start} has wrong length"
+        );
+        assert!(
+            buf == orig[start..],
+            "open ended range from {start} returned wrong bytes"
+        );
+    }
+
+    debug!("Cleanup: deleting file at path {path:?}");
+    ctx.client
+        .delete(&path, &cancel)
+        .await
+        .with_context(|| format!("{path:?} removal"))?;
+
+    Ok(())
+}
+
+/// S3 and Azure answer any range of an empty object with an error, so the buffered download
+/// has to fall back to a plain one for these.
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn download_buffered_of_empty_object_works(
+    ctx: &mut MaybeEnabledStorage,
+) -> anyhow::Result<()> {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let cancel = CancellationToken::new();
+
+    let path = RemotePath::new(Utf8Path::new(
+        format!("{}/empty_file", ctx.base_prefix).as_str(),
+    ))
+    .with_context(|| "RemotePath conversion")?;
+
+    let (data, len) = wrap_stream(bytes::Bytes::new());
+    ctx.client
+        .upload(data, len, &path, UploadOptions::default(), &cancel)
+        .await?;
+
+    let dl = ctx.client.download_buffered(&path, 1024, &cancel).await?;
+    let buf = download_to_vec(dl).await?;
+    assert!(buf.is_empty());
+
+    debug!("Cleanup: deleting file at path {path:?}");
+    ctx.client
+        .delete(&path, &cancel)
+        .await
+        .with_context(|| format!("{path:?} removal"))?;
+
+    Ok(())
+}
+
+/// Objects uploaded compressed are decompressed on download, while byte ranges return the
+/// stored, compressed bytes.
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn compressed_upload_round_trip(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let cancel = CancellationToken::new();
+
+    let path = RemotePath::new(Utf8Path::new(
+        format!("{}/index_part.json", ctx.base_prefix).as_str(),
+    ))
+    .with_context(|| "RemotePath conversion")?;
+
+    let orig = bytes::Bytes::from(r#"{"layers":{}}"#.repeat(1000));
+
+    ctx.client
+        .upload_compressed(orig.clone(), &path, None, Compression::Gzip, &cancel)
+        .await?;
+
+    let dl = ctx.client.download(&path, &cancel).await?;
+    assert_eq!(dl.content_encoding, None);
+    let buf = download_to_vec(dl).await?;
+    assert_eq!(&buf, &orig);
+
+    let dl = ctx
+        .client
+        .download_byte_range(&path, 0, None, &cancel)
+        .await?;
+    assert_eq!(dl.content_encoding, Some(Compression::Gzip));
+    let buf = download_to_vec(dl).await?;
+    assert!(buf.len() < orig.len());
+    assert_eq!(&buf[..2], &[0x1f, 0x8b], "gzip magic");
+
+    debug!("Cleanup: deleting file at path {path:?}");
+    ctx.client
+        .delete(&path, &cancel)
+        .await
+        .with_context(|| format!("{path:?} removal"))?;
+
+    Ok(())
+}
+
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn metadata_keys_are_lowercased(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let cancel = CancellationToken::new();
+
+    let path = RemotePath::new(Utf8Path::new(
+        format!("{}/file_with_metadata", ctx.base_prefix).as_str(),
+    ))
+    .with_context(|| "RemotePath conversion")?;
+
+    let (data, len) = wrap_stream(bytes::Bytes::from_static(b"remote blob data content"));
+    ctx.client
+        .upload(
+            data,
+            len,
+            &path,
+            UploadOptions {
+                metadata: Some(StorageMetadata::from([("Foo", "Bar")])),
+                ..Default::default()
+            },
+            &cancel,
+        )
+        .await?;
+
+    // Every backend returns the same map, whatever case it stores keys in
+    let dl = ctx.client.download(&path, &cancel).await?;
+    assert_eq!(dl.metadata, Some(StorageMetadata::from([("foo", "Bar")])));
+
+    ctx.client.delete(&path, &cancel).await?;
+
+    Ok(())
+}
+
 #[test_context(MaybeEnabledStorage)]
 #[tokio::test]
 async fn copy_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
@@ -318,15 +762,46 @@ async fn copy_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
     .with_context(|| "RemotePath conversion")?;
 
     let orig = bytes::Bytes::from_static("remote blob data content".as_bytes());
+    let orig_metadata = StorageMetadata::from([("source", "original")]);
 
     let (data, len) = wrap_stream(orig.clone());
 
-    ctx.client.upload(data, len, &path, None, &cancel).await?;
+    ctx.client
+        .upload(
+            data,
+            len,
+            &path,
+            UploadOptions {
+                metadata: Some(orig_metadata.clone()),
+                ..Default::default()
+            },
+            &cancel,
+        )
+        .await?;
 
-    // Normal download request
-    ctx.client.copy_object(&path, &path_dest, &cancel).await?;
+    // By default, the copy keeps the source metadata
+    ctx.client
+        .copy_object(&path, &path_dest, CopyMetadata::default(), &cancel)
+        .await?;
 
     let dl = ctx.client.download(&path_dest, &cancel).await?;
+    assert_eq!(dl.metadata.as_ref(), Some(&orig_metadata));
+    let buf = download_to_vec(dl).await?;
+    assert_eq!(&buf, &orig);
+
+    // Replacing the metadata leaves the contents alone
+    let new_metadata = StorageMetadata::from([("source", "replaced")]);
+    ctx.client
+        .copy_object(
+            &path,
+            &path_dest,
+            CopyMetadata::Replace(new_metadata.clone()),
+            &cancel,
+        )
+        .await?;
+
+    let dl = ctx.client.download(&path_dest, &cancel).await?;
+    assert_eq!(dl.metadata.as_ref(), Some(&new_metadata));
     let buf = download_to_vec(dl).await?;
     assert_eq!(&buf, &orig);
 
@@ -338,3 +813,89 @@ async fn copy_works(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Lists the same tree from the storage under test and from a [`LocalFs`], which is used in place
+/// of the real storages in tests, so they must agree on the listing order and on what the keys and
+/// prefixes of a listing are.
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn listing_matches_local_fs(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let cancel = CancellationToken::new();
+    let local_root = camino_tempfile::tempdir()?;
+    let local = GenericRemoteStorage::LocalFs(LocalFs::new(
+        local_root.path().to_path_buf(),
+        Duration::from_secs(120),
+        false,
+    )?);
+
+    let path = |p: &str| RemotePath::from_string(&format!("{}/tree/{p}", ctx.base_prefix));
+    let paths = ["a-b", "a/b", "a/c/d", "a/c/e", "b", "b0/f"]
+        .into_iter()
+        .map(path)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for path in &paths {
+        for storage in [ctx.client.as_ref(), &local] {
+            let (data, len) = upload_stream(path.to_string().into_bytes().into());
+            storage
+                .upload(data, len, path, UploadOptions::default(), &cancel)
+                .await?;
+        }
+    }
+
+    let listings = [
+        (path("")?, ListingMode::NoDelimiter),
+        (path("")?, ListingMode::WithDelimiter),
+        (path("a/")?, ListingMode::WithDelimiter),
+        (path("a/c")?, ListingMode::NoDelimiter),
+        (path("b")?, ListingMode::NoDelimiter),
+    ];
+    for (prefix, mode) in listings {
+        for max_keys in [None, NonZeroU32::new(2)] {
+            let mut results = Vec::new();
+            for storage in [ctx.client.as_ref(), &local] {
+                let listing = storage
+                    .list(
+                        Some(&prefix),
+                        mode,
+                        max_keys,
+                        ListOptions::default(),
+                        &cancel,
+                    )
+                    .await?;
+                assert!(
+                    listing.is_sorted(),
+                    "listing {prefix:?} in mode {mode:?} is not sorted"
+                );
+                let keys = listing
+                    .objects
+                    .into_iter()
+                    .map(|o| (o.key, o.size))
+                    .collect::<Vec<_>>();
+                results.push((keys, listing.prefixes));
+            }
+            assert_eq!(
+                results[0], results[1],
+                "listing {prefix:?} in mode {mode:?} with max_keys {max_keys:?}"
+            );
+        }
+    }
+
+    ctx.client.delete_objects(&paths, &cancel).await?;
+
+    Ok(())
+}
+
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn conformance_suite_passes(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let base = RemotePath::from_string(&format!("{}/conformance", ctx.base_prefix))?;
+    run_conformance(ctx.client.as_ref(), &base).await
+}
@@ -0,0 +1,40 @@
+//! Runs the conformance suite against the backends which need no external service. The object
+//! stores run it along with the rest of the shared tests, when enabled.
+
+use std::time::Duration;
+
+use remote_storage::{GenericRemoteStorage, InMemory, LocalFs, RemotePath};
+
+#[path = "common/conformance.rs"]
+mod conformance;
+
+use conformance::run_conformance;
+
+fn local_fs() -> anyhow::Result<(LocalFs, camino_tempfile::Utf8TempDir)> {
+    let storage_root = camino_tempfile::tempdir()?;
+    let storage = LocalFs::new(
+        storage_root.path().to_path_buf(),
+        Duration::from_secs(120),
+        false,
+    )?;
+    Ok((storage, storage_root))
+}
+
+#[tokio::test]
+async fn local_fs_conformance() -> anyhow::Result<()> {
+    let (storage, _storage_root) = local_fs()?;
+    run_conformance(&storage, &RemotePath::from_string("conformance")?).await
+}
+
+#[tokio::test]
+async fn generic_local_fs_conformance() -> anyhow::Result<()> {
+    let (storage, _storage_root) = local_fs()?;
+    let storage: GenericRemoteStorage = GenericRemoteStorage::LocalFs(storage);
+    run_conformance(&storage, &RemotePath::from_string("conformance")?).await
+}
+
+#[tokio::test]
+async fn in_memory_conformance() -> anyhow::Result<()> {
+    let storage = InMemory::new();
+    run_conformance(&storage, &RemotePath::from_string("conformance")?).await
+}
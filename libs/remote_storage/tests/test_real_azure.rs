@@ -2,14 +2,17 @@ use std::env;
 use std::num::NonZeroUsize;
 use std::ops::ControlFlow;
 use std::sync::Arc;
-use std::time::UNIX_EPOCH;
 use std::{collections::HashSet, time::Duration};
 
 use anyhow::Context;
+use bytes::Bytes;
 use remote_storage::{
-    AzureConfig, GenericRemoteStorage, RemotePath, RemoteStorageConfig, RemoteStorageKind,
+    AzureAuthMethod, AzureConfig, GenericRemoteStorage, RemotePath, RemoteStorageConfig,
+    RemoteStorageKind, UploadOptions, DEFAULT_AZURE_MAX_BLOCK_SIZE,
+    DEFAULT_AZURE_MAX_CONCURRENCY_PER_UPLOAD,
 };
 use test_context::AsyncTestContext;
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 
 mod common;
@@ -17,7 +20,10 @@ mod common;
 #[path = "common/tests.rs"]
 mod tests_azure;
 
-use common::{cleanup, ensure_logging_ready, upload_remote_data, upload_simple_remote_data};
+use common::{
+    cleanup, download_to_vec, ensure_logging_ready, random_test_prefix, upload_remote_data,
+    upload_simple_remote_data,
+};
 
 const ENABLE_REAL_AZURE_REMOTE_STORAGE_ENV_VAR_NAME: &str = "ENABLE_REAL_AZURE_REMOTE_STORAGE";
 
@@ -25,17 +31,28 @@ const BASE_PREFIX: &str = "test";
 
 struct EnabledAzure {
     client: Arc<GenericRemoteStorage>,
+    /// The same container without a `prefix_in_container`, to see the blobs `client` stores.
+    unprefixed_client: Arc<GenericRemoteStorage>,
     base_prefix: &'static str,
 }
 
 impl EnabledAzure {
     async fn setup(max_keys_in_list_response: Option<i32>) -> Self {
-        let client = create_azure_client(max_keys_in_list_response)
+        let create = |prefix_in_container| {
+            create_azure_client(
+                max_keys_in_list_response,
+                DEFAULT_AZURE_MAX_BLOCK_SIZE,
+                DEFAULT_AZURE_MAX_CONCURRENCY_PER_UPLOAD,
+                prefix_in_container,
+            )
             .context("Azure client creation")
-            .expect("Azure client creation failed");
+            .expect("Azure client creation failed")
+        };
+        let prefix_in_container = random_test_prefix().expect("random test prefix");
 
         EnabledAzure {
-            client,
+            client: create(Some(prefix_in_container)),
+            unprefixed_client: create(None),
             base_prefix: BASE_PREFIX,
         }
     }
@@ -45,7 +62,7 @@ impl EnabledAzure {
         match Arc::get_mut(&mut self.client).expect("outer Arc::get_mut") {
             GenericRemoteStorage::AzureBlob(azure) => {
                 let azure = Arc::get_mut(azure).expect("inner Arc::get_mut");
-                azure.timeout = timeout;
+                azure.timeouts = timeout.into();
             }
             _ => unreachable!(),
         }
@@ -189,9 +206,10 @@ impl AsyncTestContext for MaybeEnabledStorageWithSimpleTestBlobs {
 
 fn create_azure_client(
     max_keys_per_list_response: Option<i32>,
+    max_block_size: usize,
+    max_concurrency_per_upload: usize,
+    prefix_in_container: Option<String>,
 ) -> anyhow::Result<Arc<GenericRemoteStorage>> {
-    use rand::Rng;
-
     let remote_storage_azure_container = env::var("REMOTE_STORAGE_AZURE_CONTAINER").context(
         "`REMOTE_STORAGE_AZURE_CONTAINER` env var is not set, but real Azure tests are enabled",
     )?;
@@ -199,27 +217,141 @@ fn create_azure_client(
         "`REMOTE_STORAGE_AZURE_REGION` env var is not set, but real Azure tests are enabled",
     )?;
 
-    // due to how time works, we've had test runners use the same nanos as bucket prefixes.
-    // millis is just a debugging aid for easier finding the prefix later.
-    let millis = std::time::SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .context("random Azure test prefix part calculation")?
-        .as_millis();
-
-    // because nanos can be the same for two threads so can millis, add randomness
-    let random = rand::thread_rng().gen::<u32>();
-
     let remote_storage_config = RemoteStorageConfig {
         storage: RemoteStorageKind::AzureContainer(AzureConfig {
             container_name: remote_storage_azure_container,
             container_region: remote_storage_azure_region,
-            prefix_in_container: Some(format!("test_{millis}_{random:08x}/")),
+            prefix_in_container,
             concurrency_limit: NonZeroUsize::new(100).unwrap(),
             max_keys_per_list_response,
+            auth_method: AzureAuthMethod::DefaultChain,
+            max_block_size: NonZeroUsize::new(max_block_size).unwrap(),
+            max_concurrency_per_upload: NonZeroUsize::new(max_concurrency_per_upload).unwrap(),
+            rps_limits: Default::default(),
+            storage_account: None,
+            user_agent_suffix: None,
+            app_name: None,
         }),
-        timeout: Duration::from_secs(120),
+        timeouts: Duration::from_secs(120).into(),
     };
     Ok(Arc::new(
         GenericRemoteStorage::from_config(&remote_storage_config).context("remote storage init")?,
     ))
 }
+
+/// Uploads larger than a block are staged as several blocks, and concurrent uploads of the same
+/// blob don't mix up each other's blocks.
+#[tokio::test]
+async fn block_uploads_work() -> anyhow::Result<()> {
+    ensure_logging_ready();
+    if env::var(ENABLE_REAL_AZURE_REMOTE_STORAGE_ENV_VAR_NAME).is_err() {
+        info!("`{ENABLE_REAL_AZURE_REMOTE_STORAGE_ENV_VAR_NAME}` env variable is not set, skipping the test");
+        return Ok(());
+    }
+    let client = create_azure_client(None, 1024, 4, Some(random_test_prefix()?))?;
+    let cancel = CancellationToken::new();
+    let path = RemotePath::from_string(&format!("{BASE_PREFIX}/blocks"))?;
+
+    let contents = (0..4u8)
+        .map(|n| Bytes::from(vec![n; 10 * 1024 + 17]))
+        .collect::<Vec<_>>();
+    let uploads = contents.iter().enumerate().map(|(n, content)| {
+        let (client, cancel, path) = (&client, &cancel, &path);
+        async move {
+            let chunks = content
+                .chunks(300)
+                .map(|chunk| Ok(content.slice_ref(chunk)))
+                .collect::<Vec<_>>();
+            let from = futures::stream::iter(chunks);
+            if n % 2 == 0 {
+                client
+                    .upload(from, content.len(), path, UploadOptions::default(), cancel)
+                    .await
+            } else {
+                client
+                    .upload_unsized(from, path, UploadOptions::default(), cancel)
+                    .await
+            }
+        }
+    });
+    futures::future::try_join_all(uploads).await?;
+
+    let downloaded = download_to_vec(client.download(&path, &cancel).await?).await?;
+    assert!(
+        contents.iter().any(|content| downloaded == content[..]),
+        "the blob must be one of the uploads, not a mix of their blocks"
+    );
+
+    let too_large = Bytes::from(vec![0; 50_001 * 1024]);
+    let len = too_large.len();
+    let err = client
+        .upload(
+            futures::stream::once(futures::future::ready(Ok(too_large))),
+            len,
+            &path,
+            UploadOptions::default(),
+            &cancel,
+        )
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("50000"), "{err:#}");
+
+    client.delete(&path, &cancel).await?;
+    Ok(())
+}
+
+/// Range downloads report the size of the whole blob, also when the SDK fetches the blob in
+/// several chunks.
+#[tokio::test]
+async fn range_downloads_report_blob_size() -> anyhow::Result<()> {
+    ensure_logging_ready();
+    if env::var(ENABLE_REAL_AZURE_REMOTE_STORAGE_ENV_VAR_NAME).is_err() {
+        info!("`{ENABLE_REAL_AZURE_REMOTE_STORAGE_ENV_VAR_NAME}` env variable is not set, skipping the test");
+        return Ok(());
+    }
+    let client = create_azure_client(
+        None,
+        DEFAULT_AZURE_MAX_BLOCK_SIZE,
+        DEFAULT_AZURE_MAX_CONCURRENCY_PER_UPLOAD,
+        Some(random_test_prefix()?),
+    )?;
+    let cancel = CancellationToken::new();
+    let path = RemotePath::from_string(&format!("{BASE_PREFIX}/ranges"))?;
+
+    // Larger than the chunks the SDK downloads blobs in
+    let contents = Bytes::from(
+        (0..3 * 1024 * 1024 + 17)
+            .map(|n| n as u8)
+            .collect::<Vec<_>>(),
+    );
+    let size = contents.len() as u64;
+    client
+        .upload_bytes(contents.clone(), &path, UploadOptions::default(), &cancel)
+        .await?;
+
+    for (start, end) in [
+        (0, None),
+        (2, Some(5)),
+        (1024 * 1024 + 1, None),
+        (size - 3, Some(size + 10)),
+    ] {
+        let download = client
+            .download_byte_range(&path, start, end, &cancel)
+            .await?;
+        let expected = &contents[start as usize..end.unwrap_or(size).min(size) as usize];
+        assert_eq!(download.object_size, size, "range {start}..{end:?}");
+        assert_eq!(
+            download.content_length,
+            expected.len() as u64,
+            "range {start}..{end:?}"
+        );
+        assert_eq!(download_to_vec(download).await?, expected);
+    }
+
+    let download = client.download(&path, &cancel).await?;
+    assert_eq!(download.object_size, size);
+    assert_eq!(download.content_length, size);
+
+    client.delete(&path, &cancel).await?;
+    Ok(())
+}
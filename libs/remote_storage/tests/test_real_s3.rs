@@ -4,7 +4,7 @@ use std::future::Future;
 use std::num::NonZeroUsize;
 use std::ops::ControlFlow;
 use std::sync::Arc;
-use std::time::{Duration, UNIX_EPOCH};
+use std::time::Duration;
 use std::{collections::HashSet, time::SystemTime};
 
 use crate::common::{download_to_vec, upload_stream};
@@ -12,8 +12,9 @@ use anyhow::Context;
 use camino::Utf8Path;
 use futures_util::StreamExt;
 use remote_storage::{
-    DownloadError, GenericRemoteStorage, ListingMode, RemotePath, RemoteStorageConfig,
-    RemoteStorageKind, S3Config,
+    DownloadError, GenericRemoteStorage, ListOptions, ListingMode, RemotePath, RemoteStorageConfig,
+    RemoteStorageKind, S3Config, StorageMetadata, TimeTravelSummary, TimeoutOrCancel,
+    UploadOptions,
 };
 use test_context::test_context;
 use test_context::AsyncTestContext;
@@ -26,7 +27,10 @@ mod common;
 #[path = "common/tests.rs"]
 mod tests_s3;
 
-use common::{cleanup, ensure_logging_ready, upload_remote_data, upload_simple_remote_data};
+use common::{
+    cleanup, ensure_logging_ready, random_test_prefix, upload_remote_data,
+    upload_simple_remote_data,
+};
 use utils::backoff;
 
 const ENABLE_REAL_S3_REMOTE_STORAGE_ENV_VAR_NAME: &str = "ENABLE_REAL_S3_REMOTE_STORAGE";
@@ -75,14 +79,21 @@ async fn s3_time_travel_recovery_works(ctx: &mut MaybeEnabledStorage) -> anyhow:
         client: &Arc<GenericRemoteStorage>,
         cancel: &CancellationToken,
     ) -> anyhow::Result<HashSet<RemotePath>> {
-        Ok(
-            retry(|| client.list(None, ListingMode::NoDelimiter, None, cancel))
-                .await
-                .context("list root files failure")?
-                .keys
-                .into_iter()
-                .collect::<HashSet<_>>(),
-        )
+        Ok(retry(|| {
+            client.list(
+                None,
+                ListingMode::NoDelimiter,
+                None,
+                ListOptions::default(),
+                cancel,
+            )
+        })
+        .await
+        .context("list root files failure")?
+        .objects
+        .into_iter()
+        .map(|o| o.key)
+        .collect::<HashSet<_>>())
     }
 
     let cancel = CancellationToken::new();
@@ -98,7 +109,8 @@ async fn s3_time_travel_recovery_works(ctx: &mut MaybeEnabledStorage) -> anyhow:
 
     retry(|| {
         let (data, len) = upload_stream("remote blob data1".as_bytes().into());
-        ctx.client.upload(data, len, &path1, None, &cancel)
+        ctx.client
+            .upload(data, len, &path1, UploadOptions::default(), &cancel)
     })
     .await?;
 
@@ -110,7 +122,8 @@ async fn s3_time_travel_recovery_works(ctx: &mut MaybeEnabledStorage) -> anyhow:
 
     retry(|| {
         let (data, len) = upload_stream(old_data.as_bytes().into());
-        ctx.client.upload(data, len, &path2, None, &cancel)
+        ctx.client
+            .upload(data, len, &path2, UploadOptions::default(), &cancel)
     })
     .await?;
 
@@ -133,7 +146,8 @@ async fn s3_time_travel_recovery_works(ctx: &mut MaybeEnabledStorage) -> anyhow:
 
     retry(|| {
         let (data, len) = upload_stream("remote blob data3".as_bytes().into());
-        ctx.client.upload(data, len, &path3, None, &cancel)
+        ctx.client
+            .upload(data, len, &path3, UploadOptions::default(), &cancel)
     })
     .await?;
 
@@ -141,7 +155,8 @@ async fn s3_time_travel_recovery_works(ctx: &mut MaybeEnabledStorage) -> anyhow:
 
     retry(|| {
         let (data, len) = upload_stream(new_data.as_bytes().into());
-        ctx.client.upload(data, len, &path2, None, &cancel)
+        ctx.client
+            .upload(data, len, &path2, UploadOptions::default(), &cancel)
     })
     .await?;
 
@@ -150,11 +165,41 @@ async fn s3_time_travel_recovery_works(ctx: &mut MaybeEnabledStorage) -> anyhow:
     let t2 = time_point().await;
     println!("at t2: {t2_files:?}");
 
+    // Every version is listed, but not the delete marker of path1
+    let versions = retry(|| {
+        ctx.client.list(
+            None,
+            ListingMode::NoDelimiter,
+            None,
+            ListOptions {
+                include_version_ids: true,
+                ..Default::default()
+            },
+            &cancel,
+        )
+    })
+    .await?
+    .objects;
+    assert!(versions.iter().all(|o| o.version_id.is_some()));
+    let version_count = |path: &RemotePath| versions.iter().filter(|o| &o.key == path).count();
+    assert_eq!(version_count(&path1), 1);
+    assert_eq!(version_count(&path2), 2);
+    assert_eq!(version_count(&path3), 1);
+
     // No changes after recovery to t2 (no-op)
     let t_final = time_point().await;
-    ctx.client
+    let summary = ctx
+        .client
         .time_travel_recover(None, t2, t_final, &cancel)
         .await?;
+    assert_eq!(
+        summary,
+        TimeTravelSummary {
+            restored: 0,
+            deleted: 0,
+            skipped: 3,
+        }
+    );
     let t2_files_recovered = list_files(&ctx.client, &cancel).await?;
     println!("after recovery to t2: {t2_files_recovered:?}");
     assert_eq!(t2_files, t2_files_recovered);
@@ -163,9 +208,18 @@ async fn s3_time_travel_recovery_works(ctx: &mut MaybeEnabledStorage) -> anyhow:
 
     // after recovery to t1: path1 is back, path2 has the old content
     let t_final = time_point().await;
-    ctx.client
+    let summary = ctx
+        .client
         .time_travel_recover(None, t1, t_final, &cancel)
         .await?;
+    assert_eq!(
+        summary,
+        TimeTravelSummary {
+            restored: 2,
+            deleted: 1,
+            skipped: 0,
+        }
+    );
     let t1_files_recovered = list_files(&ctx.client, &cancel).await?;
     println!("after recovery to t1: {t1_files_recovered:?}");
     assert_eq!(t1_files, t1_files_recovered);
@@ -174,9 +228,18 @@ async fn s3_time_travel_recovery_works(ctx: &mut MaybeEnabledStorage) -> anyhow:
 
     // after recovery to t0: everything is gone except for path1
     let t_final = time_point().await;
-    ctx.client
+    let summary = ctx
+        .client
         .time_travel_recover(None, t0, t_final, &cancel)
         .await?;
+    assert_eq!(
+        summary,
+        TimeTravelSummary {
+            restored: 1,
+            deleted: 1,
+            skipped: 1,
+        }
+    );
     let t0_files_recovered = list_files(&ctx.client, &cancel).await?;
     println!("after recovery to t0: {t0_files_recovered:?}");
     assert_eq!(t0_files, t0_files_recovered);
@@ -191,17 +254,24 @@ async fn s3_time_travel_recovery_works(ctx: &mut MaybeEnabledStorage) -> anyhow:
 
 struct EnabledS3 {
     client: Arc<GenericRemoteStorage>,
+    /// The same bucket without a `prefix_in_bucket`, to see the keys `client` stores.
+    unprefixed_client: Arc<GenericRemoteStorage>,
     base_prefix: &'static str,
 }
 
 impl EnabledS3 {
     async fn setup(max_keys_in_list_response: Option<i32>) -> Self {
-        let client = create_s3_client(max_keys_in_list_response)
+        let client = random_test_prefix()
+            .and_then(|prefix| create_s3_client(max_keys_in_list_response, Some(prefix)))
+            .context("S3 client creation")
+            .expect("S3 client creation failed");
+        let unprefixed_client = create_s3_client(max_keys_in_list_response, None)
             .context("S3 client creation")
             .expect("S3 client creation failed");
 
         EnabledS3 {
             client,
+            unprefixed_client,
             base_prefix: BASE_PREFIX,
         }
     }
@@ -210,7 +280,7 @@ impl EnabledS3 {
         match Arc::get_mut(&mut self.client).expect("outer Arc::get_mut") {
             GenericRemoteStorage::AwsS3(s3) => {
                 let s3 = Arc::get_mut(s3).expect("inner Arc::get_mut");
-                s3.timeout = timeout;
+                s3.timeouts = timeout.into();
             }
             _ => unreachable!(),
         }
@@ -354,35 +424,36 @@ impl AsyncTestContext for MaybeEnabledStorageWithSimpleTestBlobs {
 
 fn create_s3_client(
     max_keys_per_list_response: Option<i32>,
+    prefix_in_bucket: Option<String>,
 ) -> anyhow::Result<Arc<GenericRemoteStorage>> {
-    use rand::Rng;
-
     let remote_storage_s3_bucket = env::var("REMOTE_STORAGE_S3_BUCKET")
         .context("`REMOTE_STORAGE_S3_BUCKET` env var is not set, but real S3 tests are enabled")?;
     let remote_storage_s3_region = env::var("REMOTE_STORAGE_S3_REGION")
         .context("`REMOTE_STORAGE_S3_REGION` env var is not set, but real S3 tests are enabled")?;
 
-    // due to how time works, we've had test runners use the same nanos as bucket prefixes.
-    // millis is just a debugging aid for easier finding the prefix later.
-    let millis = std::time::SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .context("random s3 test prefix part calculation")?
-        .as_millis();
-
-    // because nanos can be the same for two threads so can millis, add randomness
-    let random = rand::thread_rng().gen::<u32>();
-
     let remote_storage_config = RemoteStorageConfig {
         storage: RemoteStorageKind::AwsS3(S3Config {
             bucket_name: remote_storage_s3_bucket,
             bucket_region: remote_storage_s3_region,
-            prefix_in_bucket: Some(format!("test_{millis}_{random:08x}/")),
+            prefix_in_bucket,
             endpoint: None,
             concurrency_limit: NonZeroUsize::new(100).unwrap(),
             max_keys_per_list_response,
             upload_storage_class: None,
+            max_idle_connections: None,
+            connection_idle_timeout: None,
+            connect_timeout: None,
+            operation_attempt_timeout: None,
+            sdk_max_attempts: None,
+            profile_name: None,
+            rps_limits: Default::default(),
+            expected_bucket_owner: None,
+            requester_pays: false,
+            slow_request_threshold: None,
+            user_agent_suffix: None,
+            app_name: None,
         }),
-        timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+        timeouts: RemoteStorageConfig::DEFAULT_TIMEOUT.into(),
     };
     Ok(Arc::new(
         GenericRemoteStorage::from_config(&remote_storage_config).context("remote storage init")?,
@@ -458,7 +529,7 @@ async fn download_is_timeouted(ctx: &mut MaybeEnabledStorage) {
         assert!(
             inner
                 .downcast_ref::<DownloadError>()
-                .is_some_and(|e| matches!(e, DownloadError::Timeout)),
+                .is_some_and(|e| matches!(e, DownloadError::Timeout(_))),
             "{inner:?}"
         );
     }
@@ -516,13 +587,13 @@ async fn download_is_cancelled(ctx: &mut MaybeEnabledStorage) {
         assert!(
             inner
                 .downcast_ref::<DownloadError>()
-                .is_some_and(|e| matches!(e, DownloadError::Cancelled)),
+                .is_some_and(|e| matches!(e, DownloadError::Cancelled(_))),
             "{inner:?}"
         );
 
         let e = DownloadError::from(e);
 
-        assert!(matches!(e, DownloadError::Cancelled), "{e:?}");
+        assert!(matches!(e, DownloadError::Cancelled(_)), "{e:?}");
     }
 
     let cancel = CancellationToken::new();
@@ -530,6 +601,202 @@ async fn download_is_cancelled(ctx: &mut MaybeEnabledStorage) {
     ctx.client.delete_objects(&[path], &cancel).await.unwrap();
 }
 
+/// Cancels an upload halfway through its body, after a simulated failure of the first attempt
+/// like the retries in the pageserver see them, and checks that neither the object nor an
+/// incomplete multipart upload is left behind.
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn cancelled_upload_leaves_no_parts(ctx: &mut MaybeEnabledStorage) {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return;
+    };
+
+    let client = GenericRemoteStorage::unreliable_wrapper((*ctx.client).clone(), 1);
+    let prefix = RemotePath::from_string(&format!("{}/cancelled_upload", ctx.base_prefix)).unwrap();
+    let path = prefix.join("object");
+
+    let chunk = bytes::Bytes::from(vec![0u8; 1024]);
+    let len = chunk.len() * 1024;
+    let contents = || {
+        // Only the first chunk of the announced length ever arrives
+        futures::stream::iter([Ok::<_, std::io::Error>(chunk.clone())])
+            .chain(futures::stream::pending())
+    };
+
+    let cancel = CancellationToken::new();
+    let err = client
+        .upload(contents(), len, &path, UploadOptions::default(), &cancel)
+        .await
+        .expect_err("first attempt fails");
+    assert!(!TimeoutOrCancel::caused_by_cancel(&err), "{err:?}");
+
+    let upload = client.upload(contents(), len, &path, UploadOptions::default(), &cancel);
+    let cancel_later = async {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        cancel.cancel();
+    };
+    let (res, ()) = tokio::join!(upload, cancel_later);
+    let err = res.expect_err("upload is cancelled");
+    assert!(TimeoutOrCancel::caused_by_cancel(&err), "{err:?}");
+
+    let cancel = CancellationToken::new();
+    let aborted = ctx
+        .client
+        .abort_incomplete_uploads(Some(&prefix), Duration::ZERO, &cancel)
+        .await
+        .unwrap();
+    assert_eq!(
+        aborted, 0,
+        "cancelled upload left an incomplete multipart upload"
+    );
+
+    let res = ctx.client.download(&path, &cancel).await;
+    assert!(matches!(res, Err(DownloadError::NotFound(_))), "{res:?}");
+}
+
+/// Drops a multipart upload halfway through, like `try_join!` does once another branch fails, and
+/// checks that the upload is aborted in the background rather than left for
+/// `abort_incomplete_uploads`.
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn dropped_multipart_upload_is_aborted(ctx: &mut MaybeEnabledStorage) {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return;
+    };
+
+    let prefix = RemotePath::from_string(&format!("{}/dropped_upload", ctx.base_prefix)).unwrap();
+    let path = prefix.join("object");
+    let cancel = CancellationToken::new();
+
+    // A whole part of 16 MiB, and then nothing, so that the upload is multipart and never ends
+    let part = bytes::Bytes::from(vec![0u8; 16 * 1024 * 1024]);
+    let contents = futures::stream::iter([Ok::<_, std::io::Error>(part.clone())])
+        .chain(futures::stream::pending());
+    let upload = ctx
+        .client
+        .upload_unsized(contents, &path, UploadOptions::default(), &cancel);
+    tokio::time::timeout(Duration::from_secs(30), upload)
+        .await
+        .expect_err("the upload never completes");
+
+    // The abort is spawned, give it time to complete
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let aborted = ctx
+        .client
+        .abort_incomplete_uploads(Some(&prefix), Duration::ZERO, &cancel)
+        .await
+        .unwrap();
+    assert_eq!(
+        aborted, 0,
+        "dropped upload left an incomplete multipart upload"
+    );
+}
+
+/// Touches an object, which S3 does by copying it onto itself, and checks that it is modified
+/// again without losing its metadata.
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn touch_keeps_metadata(ctx: &mut MaybeEnabledStorage) {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return;
+    };
+
+    let path = RemotePath::from_string(&format!("{}/touch/object", ctx.base_prefix)).unwrap();
+    let cancel = CancellationToken::new();
+    let options = UploadOptions {
+        metadata: Some(StorageMetadata::from([("foo", "bar")])),
+        ..Default::default()
+    };
+    ctx.client
+        .upload_bytes(
+            bytes::Bytes::from_static(b"touched"),
+            &path,
+            options,
+            &cancel,
+        )
+        .await
+        .unwrap();
+    let before = ctx.client.head_object(&path, &cancel).await.unwrap();
+
+    // Last modified times have a resolution of a second
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    ctx.client.touch(&path, &cancel).await.unwrap();
+
+    let after = ctx.client.head_object(&path, &cancel).await.unwrap();
+    assert!(after.last_modified > before.last_modified);
+    assert_eq!(after.metadata, before.metadata);
+    assert!(after.metadata.is_some());
+    assert_eq!(after.size, before.size);
+
+    let contents = download_to_vec(ctx.client.download(&path, &cancel).await.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(contents, b"touched");
+
+    ctx.client.delete(&path, &cancel).await.unwrap();
+}
+
+/// Starts a multipart upload without completing it, like a pageserver which crashed midway, and
+/// checks that it is only aborted once it is old enough.
+#[test_context(MaybeEnabledStorage)]
+#[tokio::test]
+async fn abort_incomplete_uploads(ctx: &mut MaybeEnabledStorage) -> anyhow::Result<()> {
+    let MaybeEnabledStorage::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+    let cancel = CancellationToken::new();
+
+    let GenericRemoteStorage::AwsS3(s3) = &*ctx.client else {
+        unreachable!()
+    };
+    let path = RemotePath::from_string(&format!("{}/abort/incomplete", ctx.base_prefix))?;
+    let key = s3.relative_path_to_s3_object(&path);
+    let description = ctx.client.describe();
+    let bucket = description.bucket_or_container;
+    let sdk_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .region(aws_sdk_s3::config::Region::new(
+            description.region.context("S3 has a region")?,
+        ))
+        .load()
+        .await;
+    let client = aws_sdk_s3::Client::new(&sdk_config);
+
+    let upload = client
+        .create_multipart_upload()
+        .bucket(&bucket)
+        .key(&key)
+        .send()
+        .await?;
+    let upload_id = upload.upload_id().context("upload id")?.to_owned();
+
+    let aborted = ctx
+        .client
+        .abort_incomplete_uploads(None, Duration::from_secs(3600), &cancel)
+        .await?;
+    assert_eq!(aborted, 0, "the upload was just started");
+
+    // Initiation times have a resolution of a second
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let aborted = ctx
+        .client
+        .abort_incomplete_uploads(None, Duration::ZERO, &cancel)
+        .await?;
+    assert_eq!(aborted, 1);
+
+    let uploads = client
+        .list_multipart_uploads()
+        .bucket(&bucket)
+        .prefix(&key)
+        .send()
+        .await?;
+    assert!(uploads
+        .uploads()
+        .iter()
+        .all(|upload| upload.upload_id() != Some(upload_id.as_str())));
+
+    Ok(())
+}
+
 /// Upload a long enough file so that we cannot download it in single chunk
 ///
 /// For s3 the first chunk seems to be less than 10kB, so this has a bit of a safety margin
@@ -547,7 +814,7 @@ async fn upload_large_enough_file(
     let contents = futures::stream::iter(contents.map(std::io::Result::Ok));
 
     client
-        .upload(contents, len, path, None, cancel)
+        .upload(contents, len, path, UploadOptions::default(), cancel)
         .await
         .expect("upload succeeds");
 
@@ -0,0 +1,278 @@
+//! Runs the shared remote storage tests against an S3-compatible store, e.g. a MinIO or
+//! LocalStack container, through the regular S3 client with a custom endpoint.
+//!
+//! The tests are skipped unless `S3_TEST_ENDPOINT` is set. The bucket, `S3_TEST_BUCKET`, is
+//! created if it doesn't exist yet. Credentials come from the usual AWS environment variables.
+
+use std::env;
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::{collections::HashSet, time::Duration};
+
+use anyhow::Context;
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::config::Region;
+use remote_storage::{
+    GenericRemoteStorage, RemotePath, RemoteStorageConfig, RemoteStorageKind, S3Config,
+};
+use test_context::AsyncTestContext;
+use tokio::sync::OnceCell;
+use tracing::info;
+
+mod common;
+
+#[path = "common/tests.rs"]
+mod tests_s3_compatible;
+
+use common::{
+    cleanup, ensure_logging_ready, random_test_prefix, upload_remote_data,
+    upload_simple_remote_data,
+};
+
+const S3_TEST_ENDPOINT_ENV_VAR_NAME: &str = "S3_TEST_ENDPOINT";
+const DEFAULT_S3_TEST_BUCKET: &str = "neon-remote-storage-tests";
+const DEFAULT_S3_TEST_REGION: &str = "us-east-1";
+
+const BASE_PREFIX: &str = "test";
+
+struct S3TestEnv {
+    endpoint: String,
+    bucket: String,
+    region: String,
+}
+
+impl S3TestEnv {
+    fn from_env() -> Option<Self> {
+        let endpoint = env::var(S3_TEST_ENDPOINT_ENV_VAR_NAME).ok()?;
+        Some(Self {
+            endpoint,
+            bucket: env::var("S3_TEST_BUCKET").unwrap_or_else(|_| DEFAULT_S3_TEST_BUCKET.into()),
+            region: env::var("S3_TEST_REGION").unwrap_or_else(|_| DEFAULT_S3_TEST_REGION.into()),
+        })
+    }
+}
+
+/// Fresh MinIO and LocalStack containers have no buckets, so create ours once per test binary.
+async fn ensure_bucket_exists(test_env: &S3TestEnv) {
+    static BUCKET_CREATED: OnceCell<()> = OnceCell::const_new();
+    BUCKET_CREATED
+        .get_or_init(|| async {
+            let sdk_config = aws_config::defaults(BehaviorVersion::v2023_11_09())
+                .region(Region::new(test_env.region.clone()))
+                .endpoint_url(&test_env.endpoint)
+                .load()
+                .await;
+            let client = aws_sdk_s3::Client::from_conf(
+                aws_sdk_s3::config::Builder::from(&sdk_config)
+                    .force_path_style(true)
+                    .build(),
+            );
+            match client.create_bucket().bucket(&test_env.bucket).send().await {
+                Ok(_) => info!("Created bucket {}", test_env.bucket),
+                Err(e) => {
+                    let e = e.into_service_error();
+                    if e.is_bucket_already_owned_by_you() || e.is_bucket_already_exists() {
+                        info!("Bucket {} already exists", test_env.bucket);
+                    } else {
+                        panic!("Failed to create bucket {}: {e}", test_env.bucket);
+                    }
+                }
+            }
+        })
+        .await;
+}
+
+struct EnabledS3Compatible {
+    client: Arc<GenericRemoteStorage>,
+    /// The same bucket without a `prefix_in_bucket`, to see the keys `client` stores.
+    unprefixed_client: Arc<GenericRemoteStorage>,
+    base_prefix: &'static str,
+}
+
+impl EnabledS3Compatible {
+    async fn setup(test_env: S3TestEnv, max_keys_in_list_response: Option<i32>) -> Self {
+        ensure_bucket_exists(&test_env).await;
+        let create = |prefix_in_bucket| {
+            create_s3_compatible_client(&test_env, max_keys_in_list_response, prefix_in_bucket)
+                .context("S3 client creation")
+                .expect("S3 client creation failed")
+        };
+        let prefix_in_bucket = random_test_prefix().expect("random test prefix");
+
+        EnabledS3Compatible {
+            client: create(Some(prefix_in_bucket)),
+            unprefixed_client: create(None),
+            base_prefix: BASE_PREFIX,
+        }
+    }
+}
+
+enum MaybeEnabledStorage {
+    Enabled(EnabledS3Compatible),
+    Disabled,
+}
+
+impl AsyncTestContext for MaybeEnabledStorage {
+    async fn setup() -> Self {
+        ensure_logging_ready();
+
+        let Some(test_env) = S3TestEnv::from_env() else {
+            info!(
+                "`{}` env variable is not set, skipping the test",
+                S3_TEST_ENDPOINT_ENV_VAR_NAME
+            );
+            return Self::Disabled;
+        };
+
+        Self::Enabled(EnabledS3Compatible::setup(test_env, None).await)
+    }
+}
+
+enum MaybeEnabledStorageWithTestBlobs {
+    Enabled(S3CompatibleWithTestBlobs),
+    Disabled,
+    UploadsFailed(anyhow::Error, S3CompatibleWithTestBlobs),
+}
+
+struct S3CompatibleWithTestBlobs {
+    enabled: EnabledS3Compatible,
+    remote_prefixes: HashSet<RemotePath>,
+    remote_blobs: HashSet<RemotePath>,
+}
+
+impl AsyncTestContext for MaybeEnabledStorageWithTestBlobs {
+    async fn setup() -> Self {
+        ensure_logging_ready();
+        let Some(test_env) = S3TestEnv::from_env() else {
+            info!(
+                "`{}` env variable is not set, skipping the test",
+                S3_TEST_ENDPOINT_ENV_VAR_NAME
+            );
+            return Self::Disabled;
+        };
+
+        let max_keys_in_list_response = 10;
+        let upload_tasks_count = 1 + (2 * usize::try_from(max_keys_in_list_response).unwrap());
+
+        let enabled = EnabledS3Compatible::setup(test_env, Some(max_keys_in_list_response)).await;
+
+        match upload_remote_data(&enabled.client, enabled.base_prefix, upload_tasks_count).await {
+            ControlFlow::Continue(uploads) => {
+                info!("Remote objects created successfully");
+
+                Self::Enabled(S3CompatibleWithTestBlobs {
+                    enabled,
+                    remote_prefixes: uploads.prefixes,
+                    remote_blobs: uploads.blobs,
+                })
+            }
+            ControlFlow::Break(uploads) => Self::UploadsFailed(
+                anyhow::anyhow!("One or multiple blobs failed to upload to S3"),
+                S3CompatibleWithTestBlobs {
+                    enabled,
+                    remote_prefixes: uploads.prefixes,
+                    remote_blobs: uploads.blobs,
+                },
+            ),
+        }
+    }
+
+    async fn teardown(self) {
+        match self {
+            Self::Disabled => {}
+            Self::Enabled(ctx) | Self::UploadsFailed(_, ctx) => {
+                cleanup(&ctx.enabled.client, ctx.remote_blobs).await;
+            }
+        }
+    }
+}
+
+enum MaybeEnabledStorageWithSimpleTestBlobs {
+    Enabled(S3CompatibleWithSimpleTestBlobs),
+    Disabled,
+    UploadsFailed(anyhow::Error, S3CompatibleWithSimpleTestBlobs),
+}
+struct S3CompatibleWithSimpleTestBlobs {
+    enabled: EnabledS3Compatible,
+    remote_blobs: HashSet<RemotePath>,
+}
+
+impl AsyncTestContext for MaybeEnabledStorageWithSimpleTestBlobs {
+    async fn setup() -> Self {
+        ensure_logging_ready();
+        let Some(test_env) = S3TestEnv::from_env() else {
+            info!(
+                "`{}` env variable is not set, skipping the test",
+                S3_TEST_ENDPOINT_ENV_VAR_NAME
+            );
+            return Self::Disabled;
+        };
+
+        let max_keys_in_list_response = 10;
+        let upload_tasks_count = 1 + (2 * usize::try_from(max_keys_in_list_response).unwrap());
+
+        let enabled = EnabledS3Compatible::setup(test_env, Some(max_keys_in_list_response)).await;
+
+        match upload_simple_remote_data(&enabled.client, upload_tasks_count).await {
+            ControlFlow::Continue(uploads) => {
+                info!("Remote objects created successfully");
+
+                Self::Enabled(S3CompatibleWithSimpleTestBlobs {
+                    enabled,
+                    remote_blobs: uploads,
+                })
+            }
+            ControlFlow::Break(uploads) => Self::UploadsFailed(
+                anyhow::anyhow!("One or multiple blobs failed to upload to S3"),
+                S3CompatibleWithSimpleTestBlobs {
+                    enabled,
+                    remote_blobs: uploads,
+                },
+            ),
+        }
+    }
+
+    async fn teardown(self) {
+        match self {
+            Self::Disabled => {}
+            Self::Enabled(ctx) | Self::UploadsFailed(_, ctx) => {
+                cleanup(&ctx.enabled.client, ctx.remote_blobs).await;
+            }
+        }
+    }
+}
+
+fn create_s3_compatible_client(
+    test_env: &S3TestEnv,
+    max_keys_per_list_response: Option<i32>,
+    prefix_in_bucket: Option<String>,
+) -> anyhow::Result<Arc<GenericRemoteStorage>> {
+    let remote_storage_config = RemoteStorageConfig {
+        storage: RemoteStorageKind::AwsS3(S3Config {
+            bucket_name: test_env.bucket.clone(),
+            bucket_region: test_env.region.clone(),
+            prefix_in_bucket,
+            endpoint: Some(test_env.endpoint.clone()),
+            concurrency_limit: NonZeroUsize::new(100).unwrap(),
+            max_keys_per_list_response,
+            upload_storage_class: None,
+            max_idle_connections: None,
+            connection_idle_timeout: None,
+            connect_timeout: None,
+            operation_attempt_timeout: None,
+            sdk_max_attempts: None,
+            profile_name: None,
+            rps_limits: Default::default(),
+            expected_bucket_owner: None,
+            requester_pays: false,
+            slow_request_threshold: None,
+            user_agent_suffix: None,
+            app_name: None,
+        }),
+        timeouts: Duration::from_secs(120).into(),
+    };
+    Ok(Arc::new(
+        GenericRemoteStorage::from_config(&remote_storage_config).context("remote storage init")?,
+    ))
+}
@@ -12,6 +12,7 @@ use tokio::{
     fs::{File, OpenOptions},
     io::AsyncBufRead,
     io::AsyncSeekExt,
+    io::AsyncWrite,
     io::AsyncWriteExt,
 };
 use tokio_tar::{Archive, Builder, HeaderMode};
@@ -28,6 +29,18 @@ pub async fn create_zst_tarball(path: &Utf8Path, tarball: &Utf8Path) -> Result<(
         .await
         .with_context(|| format!("tempfile creation {tarball}"))?;
 
+    let mut compressed = write_zst_tarball(path, file).await?;
+    let compressed_len = compressed.metadata().await?.len();
+    compressed.seek(SeekFrom::Start(0)).await?;
+    Ok((compressed, compressed_len))
+}
+
+/// Writes a Zstandard tarball of `path` into `writer`, e.g. one end of a pipe to stream it
+/// somewhere without a temporary file. The writer is shut down when all of it was written.
+pub async fn write_zst_tarball<W: AsyncWrite + Unpin + Send + 'static>(
+    path: &Utf8Path,
+    writer: W,
+) -> Result<W> {
     let mut paths = Vec::new();
     for entry in WalkDir::new(path) {
         let entry = entry?;
@@ -42,7 +55,7 @@ pub async fn create_zst_tarball(path: &Utf8Path, tarball: &Utf8Path) -> Result<(
     // Do a sort to get a more consistent listing
     paths.sort_unstable();
     let zstd = ZstdEncoder::with_quality_and_params(
-        file,
+        writer,
         Level::Default,
         &[CParameter::enable_long_distance_matching(true)],
     );
@@ -60,10 +73,7 @@ pub async fn create_zst_tarball(path: &Utf8Path, tarball: &Utf8Path) -> Result<(
     }
     let mut zstd = builder.into_inner().await?;
     zstd.shutdown().await?;
-    let mut compressed = zstd.into_inner();
-    let compressed_len = compressed.metadata().await?.len();
-    compressed.seek(SeekFrom::Start(0)).await?;
-    Ok((compressed, compressed_len))
+    Ok(zstd.into_inner())
 }
 
 /// Creates a Zstandard tarball.
@@ -181,10 +181,17 @@ async fn main() -> anyhow::Result<()> {
             let config = RemoteStorageConfig::from_toml(toml_item)?.expect("incomplete config");
             let storage = remote_storage::GenericRemoteStorage::from_config(&config);
             let cancel = CancellationToken::new();
-            storage
+            let summary = storage
                 .unwrap()
                 .time_travel_recover(Some(&prefix), timestamp, done_if_after, &cancel)
                 .await?;
+            println!(
+                "restored {} objects, deleted {}, left {} as they were",
+                summary.restored, summary.deleted, summary.skipped
+            );
+            if summary.restored == 0 && summary.deleted == 0 {
+                println!("nothing changed, check the timestamp and prefix");
+            }
         }
         Commands::Key(dkc) => dkc.execute(),
     };
@@ -18,7 +18,7 @@ use pageserver::disk_usage_eviction_task::{self, launch_disk_usage_global_evicti
 use pageserver::metrics::{STARTUP_DURATION, STARTUP_IS_LOADING};
 use pageserver::task_mgr::WALRECEIVER_RUNTIME;
 use pageserver::tenant::{secondary, TenantSharedResources};
-use remote_storage::GenericRemoteStorage;
+use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
 use tokio::signal::unix::SignalKind;
 use tokio::time::Instant;
 use tracing::*;
@@ -49,6 +49,12 @@ project_build_tag!(BUILD_TAG);
 
 const PID_FILE_NAME: &str = "pageserver.pid";
 
+/// Key that [`probe_remote_storage`] sends a HEAD request for. It is not expected to exist.
+const REMOTE_STORAGE_PROBE_KEY: &str = "pageserver_startup_probe";
+
+/// Upper bound on how long [`probe_remote_storage`] may hold up startup.
+const REMOTE_STORAGE_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
 const FEATURES: &[&str] = &[
     #[cfg(feature = "testing")]
     "testing",
@@ -705,7 +711,13 @@ fn create_remote_storage_client(
     };
 
     // Create the client
-    let mut remote_storage = GenericRemoteStorage::from_config(config)?;
+    let mut config = config.clone();
+    config.set_app_name("pageserver", GIT_VERSION);
+    let mut remote_storage = GenericRemoteStorage::from_config(&config)?;
+
+    // Probe before wrapping the client with simulated failures, so that those don't show up as
+    // a storage misconfiguration.
+    BACKGROUND_RUNTIME.block_on(probe_remote_storage(&remote_storage));
 
     // If `test_remote_failures` is non-zero, wrap the client with a
     // wrapper that simulates failures.
@@ -724,6 +736,39 @@ fn create_remote_storage_client(
     Ok(remote_storage)
 }
 
+/// Check that the remote storage is reachable with the configured credentials, by sending a HEAD
+/// request for a key that does not exist. Storage misconfiguration would otherwise only show up
+/// once the first tenant attaches. Failures are logged, but don't prevent startup: the storage
+/// may just be temporarily unavailable.
+async fn probe_remote_storage(remote_storage: &GenericRemoteStorage) {
+    let key = RemotePath::from_string(REMOTE_STORAGE_PROBE_KEY).expect("valid key");
+    let cancel = tokio_util::sync::CancellationToken::new();
+    let started_at = Instant::now();
+    let res = tokio::time::timeout(
+        REMOTE_STORAGE_PROBE_TIMEOUT,
+        remote_storage.head_object(&key, &cancel),
+    )
+    .await;
+    match res {
+        // Any response about the object, even that it is missing, means that we can reach and
+        // authenticate to the storage.
+        Ok(Ok(_)) | Ok(Err(DownloadError::NotFound(_))) => {
+            info!(
+                "Remote storage is reachable, probe took {:?}",
+                started_at.elapsed()
+            );
+        }
+        Ok(Err(e)) => {
+            error!("Remote storage probe failed, check the remote_storage configuration: {e:#}");
+        }
+        Err(_) => {
+            error!(
+                "Remote storage probe did not complete in {REMOTE_STORAGE_PROBE_TIMEOUT:?}, check the remote_storage configuration"
+            );
+        }
+    }
+}
+
 fn cli() -> Command {
     Command::new("Neon page server")
         .about("Materializes WAL stream to pages and serves them to the postgres")
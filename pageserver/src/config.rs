@@ -80,6 +80,7 @@ pub mod defaults {
 
     pub const DEFAULT_HEATMAP_UPLOAD_CONCURRENCY: usize = 8;
     pub const DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY: usize = 1;
+    pub const DEFAULT_PRELOAD_INDEX_DOWNLOAD_CONCURRENCY: usize = 16;
 
     pub const DEFAULT_INGEST_BATCH_SIZE: u64 = 100;
 
@@ -161,6 +162,7 @@ pub mod defaults {
 
 #heatmap_upload_concurrency = {DEFAULT_HEATMAP_UPLOAD_CONCURRENCY}
 #secondary_download_concurrency = {DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY}
+#preload_index_download_concurrency = {DEFAULT_PRELOAD_INDEX_DOWNLOAD_CONCURRENCY}
 
 #ephemeral_bytes_per_memory_kb = {DEFAULT_EPHEMERAL_BYTES_PER_MEMORY_KB}
 
@@ -277,6 +279,10 @@ pub struct PageServerConf {
     /// deprioritises secondary downloads vs. remote storage operations for attached tenants.
     pub secondary_download_concurrency: usize,
 
+    /// How many index parts a tenant downloads concurrently when it loads its timelines, so that
+    /// tenants with many timelines don't take over the remote storage concurrency limit on attach.
+    pub preload_index_download_concurrency: usize,
+
     /// Maximum number of WAL records to be ingested and committed at the same time
     pub ingest_batch_size: u64,
 
@@ -387,6 +393,7 @@ struct PageServerConfigBuilder {
 
     heatmap_upload_concurrency: BuilderValue<usize>,
     secondary_download_concurrency: BuilderValue<usize>,
+    preload_index_download_concurrency: BuilderValue<usize>,
 
     ingest_batch_size: BuilderValue<u64>,
 
@@ -477,6 +484,7 @@ impl PageServerConfigBuilder {
 
             heatmap_upload_concurrency: Set(DEFAULT_HEATMAP_UPLOAD_CONCURRENCY),
             secondary_download_concurrency: Set(DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY),
+            preload_index_download_concurrency: Set(DEFAULT_PRELOAD_INDEX_DOWNLOAD_CONCURRENCY),
 
             ingest_batch_size: Set(DEFAULT_INGEST_BATCH_SIZE),
 
@@ -648,6 +656,10 @@ impl PageServerConfigBuilder {
         self.secondary_download_concurrency = BuilderValue::Set(value)
     }
 
+    pub fn preload_index_download_concurrency(&mut self, value: usize) {
+        self.preload_index_download_concurrency = BuilderValue::Set(value)
+    }
+
     pub fn ingest_batch_size(&mut self, ingest_batch_size: u64) {
         self.ingest_batch_size = BuilderValue::Set(ingest_batch_size)
     }
@@ -727,6 +739,7 @@ impl PageServerConfigBuilder {
                 control_plane_emergency_mode,
                 heatmap_upload_concurrency,
                 secondary_download_concurrency,
+                preload_index_download_concurrency,
                 ingest_batch_size,
                 get_vectored_impl,
                 get_impl,
@@ -1007,6 +1020,11 @@ impl PageServerConf {
                 "secondary_download_concurrency" => {
                     builder.secondary_download_concurrency(parse_toml_u64(key, item)? as usize)
                 },
+                "preload_index_download_concurrency" => {
+                    let value = parse_toml_u64(key, item)? as usize;
+                    ensure!(value > 0, "{key} must be greater than 0");
+                    builder.preload_index_download_concurrency(value)
+                },
                 "ingest_batch_size" => builder.ingest_batch_size(parse_toml_u64(key, item)?),
                 "virtual_file_io_engine" => {
                     builder.virtual_file_io_engine(parse_toml_from_str("virtual_file_io_engine", item)?)
@@ -1102,6 +1120,8 @@ impl PageServerConf {
             control_plane_emergency_mode: false,
             heatmap_upload_concurrency: defaults::DEFAULT_HEATMAP_UPLOAD_CONCURRENCY,
             secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
+            preload_index_download_concurrency:
+                defaults::DEFAULT_PRELOAD_INDEX_DOWNLOAD_CONCURRENCY,
             ingest_batch_size: defaults::DEFAULT_INGEST_BATCH_SIZE,
             virtual_file_io_engine: DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap(),
             get_vectored_impl: defaults::DEFAULT_GET_VECTORED_IMPL.parse().unwrap(),
@@ -1341,6 +1361,8 @@ background_task_maximum_delay = '334 s'
                 control_plane_emergency_mode: false,
                 heatmap_upload_concurrency: defaults::DEFAULT_HEATMAP_UPLOAD_CONCURRENCY,
                 secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
+                preload_index_download_concurrency:
+                    defaults::DEFAULT_PRELOAD_INDEX_DOWNLOAD_CONCURRENCY,
                 ingest_batch_size: defaults::DEFAULT_INGEST_BATCH_SIZE,
                 virtual_file_io_engine: DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap(),
                 get_vectored_impl: defaults::DEFAULT_GET_VECTORED_IMPL.parse().unwrap(),
@@ -1414,6 +1436,8 @@ background_task_maximum_delay = '334 s'
                 control_plane_emergency_mode: false,
                 heatmap_upload_concurrency: defaults::DEFAULT_HEATMAP_UPLOAD_CONCURRENCY,
                 secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
+                preload_index_download_concurrency:
+                    defaults::DEFAULT_PRELOAD_INDEX_DOWNLOAD_CONCURRENCY,
                 ingest_batch_size: 100,
                 virtual_file_io_engine: DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap(),
                 get_vectored_impl: defaults::DEFAULT_GET_VECTORED_IMPL.parse().unwrap(),
@@ -1468,8 +1492,11 @@ broker_endpoint = '{broker_endpoint}'
             assert_eq!(
                 parsed_remote_storage_config,
                 RemoteStorageConfig {
-                    storage: RemoteStorageKind::LocalFs(local_storage_path.clone()),
-                    timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+                    storage: RemoteStorageKind::LocalFs {
+                        local_path: local_storage_path.clone(),
+                        sync_on_upload: false,
+                    },
+                    timeouts: RemoteStorageConfig::DEFAULT_TIMEOUT.into(),
                 },
                 "Remote storage config should correctly parse the local FS config and fill other storage defaults"
             );
@@ -1537,8 +1564,20 @@ broker_endpoint = '{broker_endpoint}'
                         concurrency_limit: s3_concurrency_limit,
                         max_keys_per_list_response: None,
                         upload_storage_class: None,
+                        max_idle_connections: None,
+                        connection_idle_timeout: None,
+                        connect_timeout: None,
+                        operation_attempt_timeout: None,
+                        sdk_max_attempts: None,
+                        profile_name: None,
+                        rps_limits: Default::default(),
+                        expected_bucket_owner: None,
+                        requester_pays: false,
+                        slow_request_threshold: None,
+                        user_agent_suffix: None,
+                        app_name: None,
                     }),
-                    timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+                    timeouts: RemoteStorageConfig::DEFAULT_TIMEOUT.into(),
                 },
                 "Remote storage config should correctly parse the S3 config"
             );
@@ -2,7 +2,7 @@ use std::time::SystemTime;
 
 use chrono::{DateTime, Utc};
 use consumption_metrics::{Event, EventChunk, IdempotencyKey, CHUNK_SIZE};
-use remote_storage::{GenericRemoteStorage, RemotePath};
+use remote_storage::{GenericRemoteStorage, RemotePath, UploadOptions};
 use tokio::io::AsyncWriteExt;
 use tokio_util::sync::CancellationToken;
 use tracing::Instrument;
@@ -116,10 +116,10 @@ pub(super) async fn upload_metrics_bucket(
 
     // Write to remote storage
     client
-        .upload_storage_object(
-            futures::stream::once(futures::future::ready(Ok(compressed_bytes.into()))),
-            compressed_length,
+        .upload_bytes(
+            compressed_bytes.into(),
             &path,
+            UploadOptions::default(),
             cancel,
         )
         .await?;
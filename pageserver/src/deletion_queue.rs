@@ -850,8 +850,11 @@ mod test {
         std::fs::create_dir_all(remote_fs_dir)?;
         let remote_fs_dir = harness.conf.workdir.join("remote_fs").canonicalize_utf8()?;
         let storage_config = RemoteStorageConfig {
-            storage: RemoteStorageKind::LocalFs(remote_fs_dir.clone()),
-            timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+            storage: RemoteStorageKind::LocalFs {
+                local_path: remote_fs_dir.clone(),
+                sync_on_upload: false,
+            },
+            timeouts: RemoteStorageConfig::DEFAULT_TIMEOUT.into(),
         };
         let storage = GenericRemoteStorage::from_config(&storage_config).unwrap();
 
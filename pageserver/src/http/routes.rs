@@ -116,6 +116,8 @@ pub struct State {
     auth: Option<Arc<SwappableJwtAuth>>,
     allowlist_routes: Vec<Uri>,
     remote_storage: GenericRemoteStorage,
+    // Kept for the lifetime of the process, see `GenericRemoteStorage::healthcheck_handle`.
+    remote_storage_healthcheck: GenericRemoteStorage,
     broker_client: storage_broker::BrokerClientChannel,
     disk_usage_eviction_state: Arc<disk_usage_eviction_task::State>,
     deletion_queue_client: DeletionQueueClient,
@@ -139,12 +141,16 @@ impl State {
             .iter()
             .map(|v| v.parse().unwrap())
             .collect::<Vec<_>>();
+        let remote_storage_healthcheck = remote_storage
+            .healthcheck_handle()
+            .context("create the remote storage handle for health checks")?;
         Ok(Self {
             conf,
             tenant_manager,
             auth,
             allowlist_routes,
             remote_storage,
+            remote_storage_healthcheck,
             broker_client,
             disk_usage_eviction_state,
             deletion_queue_client,
@@ -462,6 +468,21 @@ async fn status_handler(
     json_response(StatusCode::OK, StatusResponse { id: config.id })
 }
 
+// Unlike the plain healthcheck above, this goes out to the remote storage, so it is not in the
+// list of routes which skip authentication.
+async fn remote_storage_status_handler(
+    request: Request<Body>,
+    cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    let state = get_state(&request);
+    match state.remote_storage_healthcheck.healthcheck(&cancel).await {
+        Ok(()) => json_response(StatusCode::OK, ()),
+        Err(_) if cancel.is_cancelled() => Err(ApiError::Cancelled),
+        Err(e) => Err(ApiError::ResourceUnavailable(format!("{e:#}").into())),
+    }
+}
+
 async fn reload_auth_validation_keys_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -1663,7 +1684,7 @@ async fn tenant_time_travel_remote_storage_handler(
 
     tracing::info!("Issuing time travel request internally. timestamp={timestamp_raw}, done_if_after={done_if_after_raw}");
 
-    remote_timeline_client::upload::time_travel_recover_tenant(
+    let summary = remote_timeline_client::upload::time_travel_recover_tenant(
         &state.remote_storage,
         &tenant_shard_id,
         timestamp,
@@ -1683,13 +1704,28 @@ async fn tenant_time_travel_remote_storage_handler(
         TimeTravelError::TooManyVersions => {
             ApiError::InternalServerError(anyhow!("too many versions in remote storage"))
         }
+        TimeTravelError::VersioningDisabled { key } => ApiError::PreconditionFailed(
+            format!("remote storage bucket versioning is disabled, found unversioned {key}")
+                .into_boxed_str(),
+        ),
+        e @ TimeTravelError::Restore { .. } => {
+            warn!("internal error: {e}");
+            ApiError::InternalServerError(anyhow!("internal error"))
+        }
         TimeTravelError::Other(e) => {
             warn!("internal error: {e}");
             ApiError::InternalServerError(anyhow!("internal error"))
         }
     })?;
 
-    json_response(StatusCode::OK, ())
+    if summary.restored == 0 && summary.deleted == 0 {
+        // Most likely the timestamp or the tenant is wrong
+        warn!(?summary, "Time travel recovery didn't change any objects");
+    } else {
+        tracing::info!(?summary, "Time travel recovery done");
+    }
+
+    json_response(StatusCode::OK, summary)
 }
 
 /// Testing helper to transition a tenant to [`crate::tenant::TenantState::Broken`].
@@ -2190,7 +2226,7 @@ async fn tenant_scan_remote_handler(
                         index_part.layer_metadata.len(), index_part.metadata.disk_consistent_lsn());
                     generation = std::cmp::max(generation, index_generation);
                 }
-                Err(DownloadError::NotFound) => {
+                Err(DownloadError::NotFound(_)) => {
                     // This is normal for tenants that were created with multiple shards: they have an unsharded path
                     // containing the timeline's initdb tarball but no index.  Otherwise it is a bit strange.
                     tracing::info!("Timeline path {tenant_shard_id}/{timeline_id} exists in remote storage but has no index, skipping");
@@ -2709,6 +2745,9 @@ pub fn make_router(
         .data(state)
         .get("/metrics", |r| request_span(r, prometheus_metrics_handler))
         .get("/v1/status", |r| api_handler(r, status_handler))
+        .get("/v1/status/remote_storage", |r| {
+            api_handler(r, remote_storage_status_handler)
+        })
         .put("/v1/failpoints", |r| {
             testing_api_handler("manage failpoints", r, failpoints_handler)
         })
@@ -19,6 +19,7 @@ use enumset::EnumSet;
 use futures::stream::FuturesUnordered;
 use futures::FutureExt;
 use futures::StreamExt;
+use futures::TryStreamExt;
 use pageserver_api::models;
 use pageserver_api::models::AuxFilePolicy;
 use pageserver_api::models::TimelineState;
@@ -27,6 +28,7 @@ use pageserver_api::models::WalRedoManagerStatus;
 use pageserver_api::shard::ShardIdentity;
 use pageserver_api::shard::ShardStripeSize;
 use pageserver_api::shard::TenantShardId;
+use remote_storage::CopyMetadata;
 use remote_storage::DownloadError;
 use remote_storage::GenericRemoteStorage;
 use remote_storage::TimeoutOrCancel;
@@ -47,8 +49,8 @@ use utils::sync::gate::Gate;
 use utils::sync::gate::GateGuard;
 use utils::timeout::timeout_cancellable;
 use utils::timeout::TimeoutCancellableError;
-use utils::zstd::create_zst_tarball;
 use utils::zstd::extract_zst_tarball;
+use utils::zstd::write_zst_tarball;
 
 use self::config::AttachedLocationConfig;
 use self::config::AttachmentMode;
@@ -86,7 +88,6 @@ use crate::tenant::config::TenantConfOpt;
 pub use crate::tenant::remote_timeline_client::index::IndexPart;
 use crate::tenant::remote_timeline_client::remote_initdb_archive_path;
 use crate::tenant::remote_timeline_client::MaybeDeletedIndexPart;
-use crate::tenant::remote_timeline_client::INITDB_PATH;
 use crate::tenant::storage_layer::DeltaLayer;
 use crate::tenant::storage_layer::ImageLayer;
 use crate::InitializationOrder;
@@ -655,6 +656,17 @@ impl Tenant {
             deletion_queue_client,
         } = resources;
 
+        // A tenant with its own concurrency limit gets its own handle on the remote storage, which
+        // still shares the connection pool with all other tenants.
+        let remote_storage = match attached_conf
+            .tenant_conf
+            .remote_storage_concurrency_limit
+            .or(conf.default_tenant_conf.remote_storage_concurrency_limit)
+        {
+            Some(limit) => remote_storage.with_concurrency_limit(limit),
+            None => remote_storage,
+        };
+
         let attach_mode = attached_conf.location.attach_mode;
         let generation = attached_conf.location.generation;
 
@@ -981,7 +993,7 @@ impl Tenant {
                     existent_timelines.insert(timeline_id);
                     i
                 }
-                Err(DownloadError::NotFound) => {
+                Err(DownloadError::NotFound(_)) => {
                     // There is no index_part on the remote. We only get here
                     // if there is some prefix for the timeline in the remote storage.
                     // This can e.g. be the initdb.tar.zst archive, maybe a
@@ -1229,6 +1241,11 @@ impl Tenant {
         remote_storage: &GenericRemoteStorage,
         cancel: CancellationToken,
     ) -> anyhow::Result<HashMap<TimelineId, TimelinePreload>> {
+        // Spawning all downloads at once would take over the concurrency limit of the remote
+        // storage for tenants with thousands of timelines, starving everyone else.
+        let concurrency = Arc::new(tokio::sync::Semaphore::new(
+            self.conf.preload_index_download_concurrency,
+        ));
         let mut part_downloads = JoinSet::new();
         for timeline_id in timeline_ids {
             let client = RemoteTimelineClient::new(
@@ -1240,8 +1257,14 @@ impl Tenant {
                 self.generation,
             );
             let cancel_clone = cancel.clone();
+            let concurrency = Arc::clone(&concurrency);
             part_downloads.spawn(
                 async move {
+                    let _permit = concurrency
+                        .acquire_owned()
+                        .await
+                        .expect("the semaphore is never closed");
+
                     debug!("starting index part download");
 
                     let index_part = client.download_index_file(&cancel_clone).await;
@@ -3236,41 +3259,41 @@ impl Tenant {
 
     async fn upload_initdb(
         &self,
-        timelines_path: &Utf8PathBuf,
         pgdata_path: &Utf8PathBuf,
         timeline_id: &TimelineId,
     ) -> anyhow::Result<()> {
-        let temp_path = timelines_path.join(format!(
-            "{INITDB_PATH}.upload-{timeline_id}.{TEMP_FILE_SUFFIX}"
-        ));
-
-        scopeguard::defer! {
-            if let Err(e) = fs::remove_file(&temp_path) {
-                error!("Failed to remove temporary initdb archive '{temp_path}': {e}");
-            }
-        }
-
-        let (pgdata_zstd, tar_zst_size) = create_zst_tarball(pgdata_path, &temp_path).await?;
-        const INITDB_TAR_ZST_WARN_LIMIT: u64 = 2 * 1024 * 1024;
-        if tar_zst_size > INITDB_TAR_ZST_WARN_LIMIT {
-            warn!(
-                "compressed {temp_path} size of {tar_zst_size} is above limit {INITDB_TAR_ZST_WARN_LIMIT}."
-            );
-        }
-
         pausable_failpoint!("before-initdb-upload");
 
-        backoff::retry(
+        let tar_zst_size = backoff::retry(
             || async {
-                self::remote_timeline_client::upload_initdb_dir(
+                // The archive is compressed right into the upload, without a temporary file, so
+                // that only a bounded part of it is ever buffered. Every attempt compresses it
+                // again, which gives the same bytes, as the tarball is deterministic.
+                let (writer, reader) = tokio::io::duplex(self::remote_timeline_client::BUFFER_SIZE);
+                let size = Arc::new(AtomicU64::new(0));
+                let uploaded = Arc::clone(&size);
+                let initdb_tar_zst = tokio_util::io::ReaderStream::with_capacity(
+                    reader,
+                    self::remote_timeline_client::BUFFER_SIZE,
+                )
+                .inspect_ok(move |chunk| {
+                    uploaded.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                });
+
+                let compress = async {
+                    write_zst_tarball(pgdata_path, writer)
+                        .await
+                        .context("compress initdb archive")
+                };
+                let upload = self::remote_timeline_client::upload_initdb_dir(
                     &self.remote_storage,
                     &self.tenant_shard_id.tenant_id,
                     timeline_id,
-                    pgdata_zstd.try_clone().await?,
-                    tar_zst_size,
+                    initdb_tar_zst,
                     &self.cancel,
-                )
-                .await
+                );
+                tokio::try_join!(compress, upload)?;
+                anyhow::Ok(size.load(Ordering::Relaxed))
             },
             |_| false,
             3,
@@ -3280,7 +3303,15 @@ impl Tenant {
         )
         .await
         .ok_or_else(|| anyhow::Error::new(TimeoutOrCancel::Cancel))
-        .and_then(|x| x)
+        .and_then(|x| x)?;
+
+        const INITDB_TAR_ZST_WARN_LIMIT: u64 = 2 * 1024 * 1024;
+        if tar_zst_size > INITDB_TAR_ZST_WARN_LIMIT {
+            warn!(
+                "compressed initdb archive of {timeline_id} size of {tar_zst_size} is above limit {INITDB_TAR_ZST_WARN_LIMIT}."
+            );
+        }
+        Ok(())
     }
 
     /// - run initdb to init temporary instance and get bootstrap data
@@ -3330,7 +3361,7 @@ impl Tenant {
 
                 // if this fails, it will get retried by retried control plane requests
                 self.remote_storage
-                    .copy_object(source_path, dest_path, &self.cancel)
+                    .copy_object(source_path, dest_path, CopyMetadata::Preserve, &self.cancel)
                     .await
                     .context("copy initdb tar")?;
             }
@@ -3362,8 +3393,7 @@ impl Tenant {
 
             // Upload the created data dir to S3
             if self.tenant_shard_id().is_shard_zero() {
-                self.upload_initdb(&timelines_path, &pgdata_path, &timeline_id)
-                    .await?;
+                self.upload_initdb(&pgdata_path, &timeline_id).await?;
             }
         }
         let pgdata_lsn = import_datadir::get_lsn_from_controlfile(&pgdata_path)?.align();
@@ -3831,6 +3861,7 @@ pub(crate) mod harness {
                     tenant_conf.image_layer_creation_check_threshold,
                 ),
                 switch_aux_file_policy: Some(tenant_conf.switch_aux_file_policy),
+                remote_storage_concurrency_limit: tenant_conf.remote_storage_concurrency_limit,
             }
         }
     }
@@ -3893,8 +3924,11 @@ pub(crate) mod harness {
             let remote_fs_dir = conf.workdir.join("localfs");
             std::fs::create_dir_all(&remote_fs_dir).unwrap();
             let config = RemoteStorageConfig {
-                storage: RemoteStorageKind::LocalFs(remote_fs_dir.clone()),
-                timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+                storage: RemoteStorageKind::LocalFs {
+                    local_path: remote_fs_dir.clone(),
+                    sync_on_upload: false,
+                },
+                timeouts: RemoteStorageConfig::DEFAULT_TIMEOUT.into(),
             };
             let remote_storage = GenericRemoteStorage::from_config(&config).unwrap();
             let deletion_queue = MockDeletionQueue::new(Some(remote_storage.clone()));
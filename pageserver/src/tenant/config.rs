@@ -18,7 +18,7 @@ use pageserver_api::shard::{ShardCount, ShardIdentity, ShardNumber, ShardStripeS
 use serde::de::IntoDeserializer;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::num::NonZeroU64;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::time::Duration;
 use utils::generation::Generation;
 
@@ -377,6 +377,11 @@ pub struct TenantConf {
     /// There is a `last_aux_file_policy` flag which gets persisted in `index_part.json` once the first aux
     /// file is written.
     pub switch_aux_file_policy: AuxFilePolicy,
+
+    /// If set, the tenant gets its own limit of concurrent remote storage requests of each kind,
+    /// instead of competing with all other tenants for the permits of the pageserver-wide
+    /// `concurrency_limit`. Applied when the tenant is attached.
+    pub remote_storage_concurrency_limit: Option<NonZeroUsize>,
 }
 
 /// Same as TenantConf, but this struct preserves the information about
@@ -476,6 +481,10 @@ pub struct TenantConfOpt {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub switch_aux_file_policy: Option<AuxFilePolicy>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub remote_storage_concurrency_limit: Option<NonZeroUsize>,
 }
 
 impl TenantConfOpt {
@@ -538,6 +547,9 @@ impl TenantConfOpt {
             switch_aux_file_policy: self
                 .switch_aux_file_policy
                 .unwrap_or(global_conf.switch_aux_file_policy),
+            remote_storage_concurrency_limit: self
+                .remote_storage_concurrency_limit
+                .or(global_conf.remote_storage_concurrency_limit),
         }
     }
 }
@@ -582,6 +594,7 @@ impl Default for TenantConf {
             timeline_get_throttle: crate::tenant::throttle::Config::disabled(),
             image_layer_creation_check_threshold: DEFAULT_IMAGE_LAYER_CREATION_CHECK_THRESHOLD,
             switch_aux_file_policy: AuxFilePolicy::default_tenant_config(),
+            remote_storage_concurrency_limit: None,
         }
     }
 }
@@ -657,6 +670,7 @@ impl From<TenantConfOpt> for models::TenantConfig {
             timeline_get_throttle: value.timeline_get_throttle.map(ThrottleConfig::from),
             image_layer_creation_check_threshold: value.image_layer_creation_check_threshold,
             switch_aux_file_policy: value.switch_aux_file_policy,
+            remote_storage_concurrency_limit: value.remote_storage_concurrency_limit,
         }
     }
 }
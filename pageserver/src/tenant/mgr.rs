@@ -1425,15 +1425,20 @@ impl TenantManager {
                 Some(&remote_path),
                 remote_storage::ListingMode::NoDelimiter,
                 None,
+                remote_storage::ListOptions::default(),
                 &self.cancel,
             )
             .await
         {
-            Ok(listing) => listing.keys,
-            Err(remote_storage::DownloadError::Cancelled) => {
+            Ok(listing) => listing
+                .objects
+                .into_iter()
+                .map(|o| o.key)
+                .collect::<Vec<_>>(),
+            Err(remote_storage::DownloadError::Cancelled(_)) => {
                 return Err(DeleteTenantError::Cancelled)
             }
-            Err(remote_storage::DownloadError::NotFound) => return Ok(StatusCode::NOT_FOUND),
+            Err(remote_storage::DownloadError::NotFound(_)) => return Ok(StatusCode::NOT_FOUND),
             Err(other) => return Err(DeleteTenantError::Other(anyhow::anyhow!(other))),
         };
 
@@ -203,7 +203,7 @@ use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use remote_storage::{
-    DownloadError, GenericRemoteStorage, ListingMode, RemotePath, TimeoutOrCancel,
+    DownloadError, GenericRemoteStorage, ListOptions, ListingMode, RemotePath, TimeoutOrCancel,
 };
 use std::ops::DerefMut;
 use tracing::{debug, error, info, instrument, warn};
@@ -1351,6 +1351,7 @@ impl RemoteTimelineClient {
                         Some(&timeline_storage_path),
                         ListingMode::NoDelimiter,
                         None,
+                        ListOptions::default(),
                         &cancel,
                     )
                     .await
@@ -1360,7 +1361,10 @@ impl RemoteTimelineClient {
         )
         .await
         .context("list files remaining files")?
-        .keys;
+        .objects
+        .into_iter()
+        .map(|o| o.key)
+        .collect::<Vec<_>>();
 
         // We will delete the current index_part object last, since it acts as a deletion
         // marker via its deleted_at attribute
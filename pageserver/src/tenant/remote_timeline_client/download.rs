@@ -10,8 +10,7 @@ use std::str::FromStr;
 use anyhow::{anyhow, Context};
 use camino::{Utf8Path, Utf8PathBuf};
 use pageserver_api::shard::TenantShardId;
-use tokio::fs::{self, File, OpenOptions};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::fs::{self, File};
 use tokio_util::io::StreamReader;
 use tokio_util::sync::CancellationToken;
 use tracing::warn;
@@ -25,7 +24,7 @@ use crate::tenant::storage_layer::LayerName;
 use crate::tenant::Generation;
 use crate::virtual_file::{on_fatal_io_error, MaybeFatalIo, VirtualFile};
 use crate::TEMP_FILE_SUFFIX;
-use remote_storage::{DownloadError, GenericRemoteStorage, ListingMode, RemotePath};
+use remote_storage::{DownloadError, GenericRemoteStorage, ListOptions, ListingMode, RemotePath};
 use utils::crashsafe::path_with_suffix_extension;
 use utils::id::{TenantId, TimelineId};
 use utils::pausable_failpoint;
@@ -145,88 +144,25 @@ async fn download_object<'a>(
     let res = match crate::virtual_file::io_engine::get() {
         crate::virtual_file::io_engine::IoEngine::NotSet => panic!("unset"),
         crate::virtual_file::io_engine::IoEngine::StdFs => {
-            async {
-                let destination_file = tokio::fs::File::create(dst_path)
-                    .await
-                    .with_context(|| format!("create a destination file for layer '{dst_path}'"))
-                    .map_err(DownloadError::Other)?;
-
-                let download = storage.download(src_path, cancel).await?;
-
-                pausable_failpoint!("before-downloading-layer-stream-pausable");
-
-                let mut buf_writer =
-                    tokio::io::BufWriter::with_capacity(super::BUFFER_SIZE, destination_file);
-
-                let mut reader = tokio_util::io::StreamReader::new(download.download_stream);
-
-                let bytes_amount = tokio::io::copy_buf(&mut reader, &mut buf_writer).await?;
-                buf_writer.flush().await?;
-
-                let mut destination_file = buf_writer.into_inner();
-
-                // Tokio doc here: https://docs.rs/tokio/1.17.0/tokio/fs/struct.File.html states that:
-                // A file will not be closed immediately when it goes out of scope if there are any IO operations
-                // that have not yet completed. To ensure that a file is closed immediately when it is dropped,
-                // you should call flush before dropping it.
-                //
-                // From the tokio code I see that it waits for pending operations to complete. There shouldt be any because
-                // we assume that `destination_file` file is fully written. I e there is no pending .write(...).await operations.
-                // But for additional safety lets check/wait for any pending operations.
-                destination_file
-                    .flush()
-                    .await
-                    .with_context(|| format!("flush source file at {dst_path}"))
-                    .map_err(DownloadError::Other)?;
+            pausable_failpoint!("before-downloading-layer-stream-pausable");
 
-                // not using sync_data because it can lose file size update
-                destination_file
-                    .sync_all()
-                    .await
-                    .with_context(|| format!("failed to fsync source file at {dst_path}"))
-                    .map_err(DownloadError::Other)?;
-
-                Ok(bytes_amount)
-            }
-            .await
+            // Lands the object in `dst_path` only once it is complete and fsynced
+            storage
+                .download_to_file(src_path, dst_path, cancel)
+                .await
+                .map(|metadata| metadata.size)
         }
         #[cfg(target_os = "linux")]
         crate::virtual_file::io_engine::IoEngine::TokioEpollUring => {
-            use crate::virtual_file::owned_buffers_io::{self, util::size_tracking_writer};
-            use bytes::BytesMut;
             async {
                 let destination_file = VirtualFile::create(dst_path, ctx)
                     .await
                     .with_context(|| format!("create a destination file for layer '{dst_path}'"))
                     .map_err(DownloadError::Other)?;
 
-                let mut download = storage.download(src_path, cancel).await?;
-
-                pausable_failpoint!("before-downloading-layer-stream-pausable");
-
-                // TODO: use vectored write (writev) once supported by tokio-epoll-uring.
-                // There's chunks_vectored() on the stream.
-                let (bytes_amount, destination_file) = async {
-                    let size_tracking = size_tracking_writer::Writer::new(destination_file);
-                    let mut buffered = owned_buffers_io::write::BufferedWriter::<BytesMut, _>::new(
-                        size_tracking,
-                        BytesMut::with_capacity(super::BUFFER_SIZE),
-                    );
-                    while let Some(res) =
-                        futures::StreamExt::next(&mut download.download_stream).await
-                    {
-                        let chunk = match res {
-                            Ok(chunk) => chunk,
-                            Err(e) => return Err(e),
-                        };
-                        buffered
-                            .write_buffered(tokio_epoll_uring::BoundedBuf::slice_full(chunk), ctx)
-                            .await?;
-                    }
-                    let size_tracking = buffered.flush_and_into_inner(ctx).await?;
-                    Ok(size_tracking.into_inner())
-                }
-                .await?;
+                let bytes_amount =
+                    download_into_virtual_file(storage, src_path, &destination_file, cancel, ctx)
+                        .await?;
 
                 // not using sync_data because it can lose file size update
                 destination_file
@@ -255,6 +191,74 @@ async fn download_object<'a>(
     }
 }
 
+/// Download the object `src_path` in the remote `storage` into `dst_file`, writing from offset
+/// zero. Chunks of the download stream are written straight into the file, without first
+/// copying them into a buffer unless they are smaller than [`super::BUFFER_SIZE`], so this is
+/// the cheapest way to land an object in a file that is already open.
+///
+/// The caller owns the file: it is neither fsynced nor removed on errors, and it is not
+/// truncated, so it should be new or at most as long as the object.
+///
+/// The number of bytes written is returned.
+#[cfg_attr(target_os = "macos", allow(dead_code))]
+pub(crate) async fn download_into_virtual_file(
+    storage: &GenericRemoteStorage,
+    src_path: &RemotePath,
+    dst_file: &VirtualFile,
+    cancel: &CancellationToken,
+    ctx: &RequestContext,
+) -> Result<u64, DownloadError> {
+    use crate::virtual_file::owned_buffers_io::{self, util::size_tracking_writer};
+    use bytes::BytesMut;
+
+    let mut download = storage.download(src_path, cancel).await?;
+
+    pausable_failpoint!("before-downloading-layer-stream-pausable");
+
+    // TODO: use vectored write (writev) once supported by tokio-epoll-uring.
+    // There's chunks_vectored() on the stream.
+    let size_tracking = size_tracking_writer::Writer::new(WriteAtOffset {
+        file: dst_file,
+        offset: 0,
+    });
+    let mut buffered = owned_buffers_io::write::BufferedWriter::<BytesMut, _>::new(
+        size_tracking,
+        BytesMut::with_capacity(super::BUFFER_SIZE),
+    );
+    while let Some(chunk) = futures::StreamExt::next(&mut download.download_stream).await {
+        buffered
+            .write_buffered(tokio_epoll_uring::BoundedBuf::slice_full(chunk?), ctx)
+            .await?;
+    }
+    let size_tracking = buffered.flush_and_into_inner(ctx).await?;
+    let (bytes_amount, _) = size_tracking.into_inner();
+    Ok(bytes_amount)
+}
+
+/// Writes to a borrowed [`VirtualFile`] at increasing offsets, so that
+/// [`download_into_virtual_file`] doesn't need to own the file.
+struct WriteAtOffset<'a> {
+    file: &'a VirtualFile,
+    offset: u64,
+}
+
+impl crate::virtual_file::owned_buffers_io::write::OwnedAsyncWriter for WriteAtOffset<'_> {
+    async fn write_all<
+        B: tokio_epoll_uring::BoundedBuf<Buf = Buf>,
+        Buf: tokio_epoll_uring::IoBuf + Send,
+    >(
+        &mut self,
+        buf: B,
+        ctx: &RequestContext,
+    ) -> std::io::Result<(usize, B::Buf)> {
+        let nbytes = buf.bytes_init();
+        let (buf, res) = self.file.write_all_at(buf, self.offset, ctx).await;
+        res?;
+        self.offset += u64::try_from(nbytes).unwrap();
+        Ok((nbytes, buf))
+    }
+}
+
 const TEMP_DOWNLOAD_EXTENSION: &str = "temp_download";
 
 pub(crate) fn is_temp_download_file(path: &Utf8Path) -> bool {
@@ -275,7 +279,15 @@ where
     T: FromStr + Eq + std::hash::Hash,
 {
     let listing = download_retry_forever(
-        || storage.list(Some(&prefix), ListingMode::WithDelimiter, None, &cancel),
+        || {
+            storage.list(
+                Some(&prefix),
+                ListingMode::WithDelimiter,
+                None,
+                ListOptions::default(),
+                &cancel,
+            )
+        },
         &format!("list identifiers in prefix {prefix}"),
         &cancel,
     )
@@ -295,7 +307,7 @@ where
         };
     }
 
-    for key in listing.keys {
+    for key in listing.objects {
         let object_name = key
             .object_name()
             .ok_or_else(|| anyhow::anyhow!("object name for key {key}"))?;
@@ -403,7 +415,7 @@ pub(crate) async fn download_index_part(
             );
             return Ok(index_part);
         }
-        Err(DownloadError::NotFound) => {}
+        Err(DownloadError::NotFound(_)) => {}
         Err(e) => return Err(e),
     };
 
@@ -428,7 +440,7 @@ pub(crate) async fn download_index_part(
             tracing::debug!("Found index_part from previous generation");
             return Ok(index_part);
         }
-        Err(DownloadError::NotFound) => {
+        Err(DownloadError::NotFound(_)) => {
             tracing::debug!(
                 "No index_part found from previous generation, falling back to listing"
             );
@@ -446,19 +458,26 @@ pub(crate) async fn download_index_part(
     let indices = download_retry(
         || async {
             storage
-                .list(Some(&index_prefix), ListingMode::NoDelimiter, None, cancel)
+                .list(
+                    Some(&index_prefix),
+                    ListingMode::NoDelimiter,
+                    None,
+                    ListOptions::default(),
+                    cancel,
+                )
                 .await
         },
         "list index_part files",
         cancel,
     )
     .await?
-    .keys;
+    .objects;
 
     // General case logic for which index to use: the latest index whose generation
     // is <= our own.  See "Finding the remote indices for timelines" in docs/rfcs/025-generation-numbers.md
     let max_previous_generation = indices
         .into_iter()
+        .map(|o| o.key)
         .filter_map(parse_remote_index_path)
         .filter(|g| g <= &my_generation)
         .max();
@@ -512,36 +531,23 @@ pub(crate) async fn download_initdb_tar_zst(
 
     let file = download_retry(
         || async {
-            let file = OpenOptions::new()
-                .create(true)
-                .truncate(true)
-                .read(true)
-                .write(true)
-                .open(&temp_path)
+            match storage
+                .download_to_file(&remote_path, &temp_path, cancel)
                 .await
-                .with_context(|| format!("tempfile creation {temp_path}"))
-                .map_err(DownloadError::Other)?;
-
-            let download = match storage.download(&remote_path, cancel).await {
-                Ok(dl) => dl,
-                Err(DownloadError::NotFound) => {
-                    storage.download(&remote_preserved_path, cancel).await?
+            {
+                Ok(_) => {}
+                Err(DownloadError::NotFound(_)) => {
+                    storage
+                        .download_to_file(&remote_preserved_path, &temp_path, cancel)
+                        .await?;
                 }
                 Err(other) => Err(other)?,
-            };
-            let mut download = tokio_util::io::StreamReader::new(download.download_stream);
-            let mut writer = tokio::io::BufWriter::with_capacity(super::BUFFER_SIZE, file);
-
-            tokio::io::copy_buf(&mut download, &mut writer).await?;
-
-            let mut file = writer.into_inner();
+            }
 
-            file.seek(std::io::SeekFrom::Start(0))
+            File::open(&temp_path)
                 .await
-                .with_context(|| format!("rewinding initdb.tar.zst at: {remote_path:?}"))
-                .map_err(DownloadError::Other)?;
-
-            Ok(file)
+                .with_context(|| format!("opening downloaded initdb.tar.zst at: {temp_path}"))
+                .map_err(DownloadError::Other)
         },
         &format!("download {remote_path}"),
         cancel,
@@ -586,7 +592,7 @@ where
         cancel,
     )
     .await
-    .ok_or_else(|| DownloadError::Cancelled)
+    .ok_or_else(|| DownloadError::Cancelled(None))
     .and_then(|x| x)
 }
 
@@ -608,6 +614,6 @@ where
         cancel,
     )
     .await
-    .ok_or_else(|| DownloadError::Cancelled)
+    .ok_or_else(|| DownloadError::Cancelled(None))
     .and_then(|x| x)
 }
@@ -4,11 +4,11 @@ use anyhow::{bail, Context};
 use bytes::Bytes;
 use camino::Utf8Path;
 use fail::fail_point;
+use futures::Stream;
 use pageserver_api::shard::TenantShardId;
-use std::io::{ErrorKind, SeekFrom};
+use std::io::ErrorKind;
 use std::time::SystemTime;
-use tokio::fs::{self, File};
-use tokio::io::AsyncSeekExt;
+use tokio::fs;
 use tokio_util::sync::CancellationToken;
 use utils::{backoff, pausable_failpoint};
 
@@ -17,7 +17,10 @@ use super::Generation;
 use crate::tenant::remote_timeline_client::{
     remote_index_path, remote_initdb_archive_path, remote_initdb_preserved_archive_path,
 };
-use remote_storage::{GenericRemoteStorage, RemotePath, TimeTravelError};
+use remote_storage::{
+    with_op_label, CopyMetadata, GenericRemoteStorage, RemotePath, TimeTravelError,
+    TimeTravelSummary, UploadOptions,
+};
 use utils::id::{TenantId, TimelineId};
 
 use tracing::info;
@@ -42,16 +45,9 @@ pub(crate) async fn upload_index_part<'a>(
     let serialized = index_part.to_s3_bytes()?;
     let serialized = Bytes::from(serialized);
 
-    let index_part_size = serialized.len();
-
     let remote_path = remote_index_path(tenant_shard_id, timeline_id, generation);
-    storage
-        .upload_storage_object(
-            futures::stream::once(futures::future::ready(Ok(serialized))),
-            index_part_size,
-            &remote_path,
-            cancel,
-        )
+    let upload = storage.upload_bytes(serialized, &remote_path, UploadOptions::default(), cancel);
+    with_op_label("index_upload", upload)
         .await
         .with_context(|| format!("upload index part for '{tenant_shard_id} / {timeline_id}'"))
 }
@@ -105,8 +101,14 @@ pub(super) async fn upload_timeline_layer<'a>(
 
     let reader = tokio_util::io::ReaderStream::with_capacity(source_file, super::BUFFER_SIZE);
 
-    storage
-        .upload(reader, fs_size, remote_path, None, cancel)
+    let upload = storage.upload(
+        reader,
+        fs_size,
+        remote_path,
+        UploadOptions::default(),
+        cancel,
+    );
+    with_op_label("layer_upload", upload)
         .await
         .with_context(|| format!("upload layer from local path '{local_path}'"))
 }
@@ -124,30 +126,30 @@ pub(super) async fn copy_timeline_layer(
     pausable_failpoint!("before-copy-layer-pausable");
 
     storage
-        .copy_object(source_path, target_path, cancel)
+        .copy_object(source_path, target_path, CopyMetadata::Preserve, cancel)
         .await
         .with_context(|| format!("copy layer {source_path} to {target_path}"))
 }
 
-/// Uploads the given `initdb` data to the remote storage.
+/// Uploads the given `initdb` archive to the remote storage while it is still being written, so
+/// its size isn't known up front.
 pub(crate) async fn upload_initdb_dir(
     storage: &GenericRemoteStorage,
     tenant_id: &TenantId,
     timeline_id: &TimelineId,
-    mut initdb_tar_zst: File,
-    size: u64,
+    initdb_tar_zst: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
     cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
     tracing::trace!("uploading initdb dir");
 
-    // We might have read somewhat into the file already in the prior retry attempt
-    initdb_tar_zst.seek(SeekFrom::Start(0)).await?;
-
-    let file = tokio_util::io::ReaderStream::with_capacity(initdb_tar_zst, super::BUFFER_SIZE);
-
     let remote_path = remote_initdb_archive_path(tenant_id, timeline_id);
-    storage
-        .upload_storage_object(file, size as usize, &remote_path, cancel)
+    let upload = storage.upload_unsized(
+        initdb_tar_zst,
+        &remote_path,
+        UploadOptions::default(),
+        cancel,
+    );
+    with_op_label("initdb_upload", upload)
         .await
         .with_context(|| format!("upload initdb dir for '{tenant_id} / {timeline_id}'"))
 }
@@ -161,7 +163,7 @@ pub(crate) async fn preserve_initdb_archive(
     let source_path = remote_initdb_archive_path(tenant_id, timeline_id);
     let dest_path = remote_initdb_preserved_archive_path(tenant_id, timeline_id);
     storage
-        .copy_object(&source_path, &dest_path, cancel)
+        .copy_object(&source_path, &dest_path, CopyMetadata::Preserve, cancel)
         .await
         .with_context(|| format!("backing up initdb archive for '{tenant_id} / {timeline_id}'"))
 }
@@ -172,7 +174,7 @@ pub(crate) async fn time_travel_recover_tenant(
     timestamp: SystemTime,
     done_if_after: SystemTime,
     cancel: &CancellationToken,
-) -> Result<(), TimeTravelError> {
+) -> Result<TimeTravelSummary, TimeTravelError> {
     let warn_after = 3;
     let max_attempts = 10;
     let mut prefixes = Vec::with_capacity(2);
@@ -190,14 +192,20 @@ pub(crate) async fn time_travel_recover_tenant(
         let timelines_path = super::remote_timelines_path(tenant_shard_id);
         prefixes.push(timelines_path);
     }
+    let mut summary = TimeTravelSummary::default();
     for prefix in &prefixes {
-        backoff::retry(
+        summary += backoff::retry(
             || async {
                 storage
                     .time_travel_recover(Some(prefix), timestamp, done_if_after, cancel)
                     .await
             },
-            |e| !matches!(e, TimeTravelError::Other(_)),
+            |e| {
+                !matches!(
+                    e,
+                    TimeTravelError::Other(_) | TimeTravelError::Restore { .. }
+                )
+            },
             warn_after,
             max_attempts,
             "time travel recovery of tenant prefix",
@@ -207,5 +215,5 @@ pub(crate) async fn time_travel_recover_tenant(
         .ok_or_else(|| TimeTravelError::Cancelled)
         .and_then(|x| x)?;
     }
-    Ok(())
+    Ok(summary)
 }
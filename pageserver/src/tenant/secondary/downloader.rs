@@ -469,8 +469,8 @@ enum UpdateError {
 impl From<DownloadError> for UpdateError {
     fn from(value: DownloadError) -> Self {
         match &value {
-            DownloadError::Cancelled => Self::Cancelled,
-            DownloadError::NotFound => Self::NoData,
+            DownloadError::Cancelled(_) => Self::Cancelled,
+            DownloadError::NotFound(_) => Self::NoData,
             _ => Self::DownloadError(value),
         }
     }
@@ -1015,7 +1015,7 @@ impl<'a> TenantDownloader<'a> {
 
         let downloaded_bytes = match downloaded_bytes {
             Ok(bytes) => bytes,
-            Err(DownloadError::NotFound) => {
+            Err(DownloadError::NotFound(_)) => {
                 // A heatmap might be out of date and refer to a layer that doesn't exist any more.
                 // This is harmless: continue to download the next layer. It is expected during compaction
                 // GC.
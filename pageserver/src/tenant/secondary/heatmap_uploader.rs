@@ -20,7 +20,7 @@ use crate::{
 
 use futures::Future;
 use pageserver_api::shard::TenantShardId;
-use remote_storage::{GenericRemoteStorage, TimeoutOrCancel};
+use remote_storage::{with_op_label, GenericRemoteStorage, TimeoutOrCancel, UploadOptions};
 
 use super::{
     heatmap::HeatMapTenant,
@@ -439,10 +439,9 @@ async fn upload_tenant_heatmap(
     tracing::debug!("Uploading {size} byte heatmap to {path}");
     if let Err(e) = backoff::retry(
         || async {
-            let bytes = futures::stream::once(futures::future::ready(Ok(bytes.clone())));
-            remote_storage
-                .upload_storage_object(bytes, size, &path, cancel)
-                .await
+            let upload =
+                remote_storage.upload_bytes(bytes.clone(), &path, UploadOptions::default(), cancel);
+            with_op_label("heatmap_upload", upload).await
         },
         TimeoutOrCancel::caused_by_cancel,
         3,
@@ -37,7 +37,9 @@ pub use delta_layer::{DeltaLayer, DeltaLayerWriter, ValueRef};
 pub use image_layer::{ImageLayer, ImageLayerWriter};
 pub use inmemory_layer::InMemoryLayer;
 pub use layer_desc::{PersistentLayerDesc, PersistentLayerKey};
-pub use layer_name::{DeltaLayerName, ImageLayerName, LayerName};
+pub use layer_name::{
+    parse_remote_layer_file_name, DeltaLayerName, ImageLayerName, LayerName, RemoteLayerPathExt,
+};
 
 pub(crate) use layer::{EvictionError, Layer, ResidentLayer};
 
@@ -1106,7 +1106,7 @@ impl LayerInner {
                 match e.downcast_ref::<remote_storage::DownloadError>() {
                     // If the download failed due to its cancellation token,
                     // propagate the cancellation error upstream.
-                    Some(remote_storage::DownloadError::Cancelled) => {
+                    Some(remote_storage::DownloadError::Cancelled(_)) => {
                         Err(DownloadError::DownloadCancelled)
                     }
                     // FIXME: this is not embedding the error because historically it would had
@@ -9,6 +9,8 @@ use std::ops::Range;
 use std::str::FromStr;
 
 use regex::Regex;
+use remote_storage::RemotePath;
+use utils::generation::Generation;
 use utils::lsn::Lsn;
 
 use super::PersistentLayerDesc;
@@ -248,6 +250,63 @@ impl LayerName {
             Image(_) => "image",
         }
     }
+
+    pub fn is_delta(&self) -> bool {
+        matches!(self, LayerName::Delta(_))
+    }
+
+    pub fn key_range(&self) -> &Range<Key> {
+        match self {
+            LayerName::Image(name) => &name.key_range,
+            LayerName::Delta(name) => &name.key_range,
+        }
+    }
+
+    /// The LSN range covered by the layer, which for image layers is the single LSN they are at.
+    pub fn lsn_range(&self) -> Range<Lsn> {
+        match self {
+            LayerName::Image(name) => name.lsn_as_range(),
+            LayerName::Delta(name) => name.lsn_range.clone(),
+        }
+    }
+
+    /// Parses the layer name of a layer's remote path, as built by
+    /// [`crate::tenant::remote_timeline_client::remote_layer_path`]. Returns `None` if the path
+    /// does not point to a layer file.
+    pub fn parse(path: &RemotePath) -> Option<Self> {
+        let (layer_name, _generation) = parse_remote_layer_file_name(path.object_name()?).ok()?;
+        Some(layer_name)
+    }
+}
+
+/// Parses the object name of a layer in remote storage: its [`LayerName`], followed by the
+/// generation suffix, unless the layer was written before generations were introduced.
+///
+/// This is the one canonical parser for remote layer names, shared by the pageserver and tools
+/// like the storage scrubber, which must agree on what is a layer and what is not.
+pub fn parse_remote_layer_file_name(name: &str) -> Result<(LayerName, Generation), String> {
+    match name.rsplit_once('-') {
+        // Delta layer names end with a 16 digit LSN, so only an 8 digit suffix is a generation
+        Some((layer_file_name, gen)) if gen.len() == 8 => {
+            let layer = layer_file_name.parse::<LayerName>()?;
+            let gen =
+                Generation::parse_suffix(gen).ok_or("Malformed generation suffix".to_string())?;
+            Ok((layer, gen))
+        }
+        _ => Ok((name.parse::<LayerName>()?, Generation::none())),
+    }
+}
+
+/// Layer helpers for [`RemotePath`], which itself knows nothing about layers.
+pub trait RemoteLayerPathExt {
+    /// Whether the path points to a layer file, see [`LayerName::parse`].
+    fn is_layer_file(&self) -> bool;
+}
+
+impl RemoteLayerPathExt for RemotePath {
+    fn is_layer_file(&self) -> bool {
+        LayerName::parse(self).is_some()
+    }
 }
 
 impl fmt::Display for LayerName {
@@ -376,4 +435,40 @@ mod test {
         let parsed = LayerName::from_str("000000000000000000000000000000000000-000000067F00000001000004DF0000000006__00000000014FED58-000000000154C481").unwrap();
         assert_eq!(parsed, expected);
     }
+
+    #[test]
+    fn remote_layer_parse() {
+        let image = "000000000000000000000000000000000000-000000067F00000001000004DF0000000006__00000000014FED58";
+        let delta = "000000000000000000000000000000000000-000000067F00000001000004DF0000000006__00000000014FED58-000000000154C481";
+
+        let (parsed, gen) = parse_remote_layer_file_name(&format!("{image}-00000001")).unwrap();
+        assert_eq!(parsed, LayerName::from_str(image).unwrap());
+        assert_eq!(gen, Generation::new(1));
+        assert!(!parsed.is_delta());
+        assert_eq!(
+            parsed.lsn_range(),
+            Lsn::from_hex("00000000014FED58").unwrap()..Lsn::from_hex("00000000014FED59").unwrap()
+        );
+
+        // Without a generation, the trailing LSN of a delta layer must not be taken for one
+        let (parsed, gen) = parse_remote_layer_file_name(delta).unwrap();
+        assert_eq!(parsed, LayerName::from_str(delta).unwrap());
+        assert_eq!(gen, Generation::none());
+        assert!(parsed.is_delta());
+        assert_eq!(
+            parsed.key_range(),
+            &(Key::from_i128(0)..Key::from_hex("000000067F00000001000004DF0000000006").unwrap())
+        );
+
+        let timeline =
+            "tenants/3aa8fcc61f6d357410b7de754b1d9001/timelines/ed4a8ebc81d63ca4ad9c1ce6f8ce4c3b";
+        let path = |name: &str| RemotePath::from_string(&format!("{timeline}/{name}")).unwrap();
+        assert_eq!(
+            LayerName::parse(&path(&format!("{delta}-0000000a"))),
+            Some(LayerName::from_str(delta).unwrap())
+        );
+        assert!(path(image).is_layer_file());
+        assert!(!path("index_part.json-00000001").is_layer_file());
+        assert!(!path("initdb.tar.zst").is_layer_file());
+    }
 }
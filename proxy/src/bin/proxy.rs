@@ -513,7 +513,11 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
         interval: args.metric_backup_collection_interval,
         remote_storage_config: remote_storage_from_toml(
             &args.metric_backup_collection_remote_storage,
-        )?,
+        )?
+        .map(|mut remote_storage| {
+            remote_storage.set_app_name("proxy", GIT_VERSION);
+            remote_storage
+        }),
         chunk_size: args.metric_backup_collection_chunk_size,
     };
 
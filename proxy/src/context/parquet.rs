@@ -14,7 +14,7 @@ use parquet::{
     record::RecordWriter,
 };
 use pq_proto::StartupMessageParams;
-use remote_storage::{GenericRemoteStorage, RemotePath, TimeoutOrCancel};
+use remote_storage::{GenericRemoteStorage, RemotePath, TimeoutOrCancel, UploadOptions};
 use serde::ser::SerializeMap;
 use tokio::{sync::mpsc, time};
 use tokio_util::sync::CancellationToken;
@@ -381,9 +381,8 @@ async fn upload_parquet(
     let cancel = CancellationToken::new();
     let maybe_err = backoff::retry(
         || async {
-            let stream = futures::stream::once(futures::future::ready(Ok(data.clone())));
             storage
-                .upload(stream, data.len(), &path, None, &cancel)
+                .upload_bytes(data.clone(), &path, UploadOptions::default(), &cancel)
                 .await
         },
         TimeoutOrCancel::caused_by_cancel,
@@ -486,8 +485,20 @@ mod tests {
                     .unwrap(),
                     max_keys_per_list_response: DEFAULT_MAX_KEYS_PER_LIST_RESPONSE,
                     upload_storage_class: None,
+                    max_idle_connections: None,
+                    connection_idle_timeout: None,
+                    connect_timeout: None,
+                    operation_attempt_timeout: None,
+                    sdk_max_attempts: None,
+                    profile_name: None,
+                    rps_limits: Default::default(),
+                    expected_bucket_owner: None,
+                    requester_pays: false,
+                    slow_request_threshold: None,
+                    user_agent_suffix: None,
+                    app_name: None,
                 }),
-                timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+                timeouts: RemoteStorageConfig::DEFAULT_TIMEOUT.into(),
             })
         );
         assert_eq!(parquet_upload.parquet_upload_row_group_size, 100);
@@ -543,8 +554,11 @@ mod tests {
         rx: impl Stream<Item = RequestData>,
     ) -> Vec<(u64, usize, i64)> {
         let remote_storage_config = RemoteStorageConfig {
-            storage: RemoteStorageKind::LocalFs(tmpdir.to_path_buf()),
-            timeout: std::time::Duration::from_secs(120),
+            storage: RemoteStorageKind::LocalFs {
+                local_path: tmpdir.to_path_buf(),
+                sync_on_upload: false,
+            },
+            timeouts: std::time::Duration::from_secs(120).into(),
         };
         let storage = GenericRemoteStorage::from_config(&remote_storage_config).unwrap();
 
@@ -14,7 +14,7 @@ use consumption_metrics::{idempotency_key, Event, EventChunk, EventType, CHUNK_S
 use dashmap::{mapref::entry::Entry, DashMap};
 use futures::future::select;
 use once_cell::sync::Lazy;
-use remote_storage::{GenericRemoteStorage, RemotePath, TimeoutOrCancel};
+use remote_storage::{GenericRemoteStorage, RemotePath, TimeoutOrCancel, UploadOptions};
 use serde::{Deserialize, Serialize};
 use std::{
     convert::Infallible,
@@ -460,9 +460,13 @@ async fn upload_events_chunk(
     let compressed_data: Bytes = encoder.get_ref().clone().into();
     backoff::retry(
         || async {
-            let stream = futures::stream::once(futures::future::ready(Ok(compressed_data.clone())));
             storage
-                .upload(stream, compressed_data.len(), remote_path, None, cancel)
+                .upload_bytes(
+                    compressed_data.clone(),
+                    remote_path,
+                    UploadOptions::default(),
+                    cancel,
+                )
                 .await
         },
         TimeoutOrCancel::caused_by_cancel,
@@ -300,7 +300,10 @@ async fn main() -> anyhow::Result<()> {
         broker_keepalive_interval: args.broker_keepalive_interval,
         heartbeat_timeout: args.heartbeat_timeout,
         peer_recovery_enabled: args.peer_recovery,
-        remote_storage: args.remote_storage,
+        remote_storage: args.remote_storage.map(|mut remote_storage| {
+            remote_storage.set_app_name("safekeeper", GIT_VERSION);
+            remote_storage
+        }),
         max_offloader_lag_bytes: args.max_offloader_lag,
         wal_backup_enabled: !args.disable_wal_backup,
         backup_parallel_jobs: args.wal_backup_parallel_jobs,
@@ -18,7 +18,10 @@ use std::time::Duration;
 use postgres_ffi::v14::xlog_utils::XLogSegNoOffsetToRecPtr;
 use postgres_ffi::XLogFileName;
 use postgres_ffi::{XLogSegNo, PG_TLI};
-use remote_storage::{GenericRemoteStorage, ListingMode, RemotePath, StorageMetadata};
+use remote_storage::{
+    CopyMetadata, GenericRemoteStorage, ListOptions, ListingMode, RemotePath, StorageMetadata,
+    UploadOptions,
+};
 use tokio::fs::File;
 
 use tokio::select;
@@ -477,7 +480,10 @@ pub(crate) async fn backup_partial_segment(
             file,
             size,
             target_file,
-            Some(StorageMetadata::from([("sk_type", "partial_segment")])),
+            UploadOptions {
+                metadata: Some(StorageMetadata::from([("sk_type", "partial_segment")])),
+                ..Default::default()
+            },
             &cancel,
         )
         .await
@@ -542,10 +548,14 @@ pub async fn delete_timeline(ttid: &TenantTimelineId) -> Result<()> {
                         Some(&remote_path),
                         ListingMode::NoDelimiter,
                         Some(batch_size),
+                        ListOptions::default(),
                         &cancel,
                     )
                     .await?
-                    .keys;
+                    .objects
+                    .into_iter()
+                    .map(|o| o.key)
+                    .collect::<Vec<_>>();
                 if files.is_empty() {
                     return Ok(()); // done
                 }
@@ -606,14 +616,15 @@ pub async fn copy_s3_segments(
             Some(&remote_dst_path),
             ListingMode::NoDelimiter,
             None,
+            ListOptions::default(),
             &cancel,
         )
         .await?
-        .keys;
+        .objects;
 
     let uploaded_segments = &files
         .iter()
-        .filter_map(|file| file.object_name().map(ToOwned::to_owned))
+        .filter_map(|file| file.key.object_name().map(ToOwned::to_owned))
         .collect::<HashSet<_>>();
 
     debug!(
@@ -635,7 +646,9 @@ pub async fn copy_s3_segments(
         let from = remote_timeline_path(src_ttid)?.join(&segment_name);
         let to = remote_dst_path.join(&segment_name);
 
-        storage.copy_object(&from, &to, &cancel).await?;
+        storage
+            .copy_object(&from, &to, CopyMetadata::Preserve, &cancel)
+            .await?;
     }
 
     info!(
@@ -0,0 +1,52 @@
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+use remote_storage::{
+    GenericRemoteStorage, RemoteStorageConfig, RemoteStorageKind, S3Config,
+    DEFAULT_MAX_KEYS_PER_LIST_RESPONSE, DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::BucketConfig;
+
+/// Aborts the multipart uploads in the bucket, below its `prefix_in_bucket` if it has one, which
+/// were started more than `min_age` ago and never completed. Their parts are billed until then.
+///
+/// Returns how many uploads were aborted.
+pub async fn abort_incomplete_uploads(
+    bucket_config: BucketConfig,
+    min_age: Duration,
+    cancel: &CancellationToken,
+) -> anyhow::Result<usize> {
+    let storage = GenericRemoteStorage::from_config(&RemoteStorageConfig {
+        storage: RemoteStorageKind::AwsS3(S3Config {
+            bucket_name: bucket_config.bucket,
+            bucket_region: bucket_config.region,
+            prefix_in_bucket: bucket_config.prefix_in_bucket,
+            endpoint: bucket_config.endpoint,
+            concurrency_limit: NonZeroUsize::new(DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT)
+                .unwrap(),
+            max_keys_per_list_response: DEFAULT_MAX_KEYS_PER_LIST_RESPONSE,
+            upload_storage_class: None,
+            max_idle_connections: None,
+            connection_idle_timeout: None,
+            connect_timeout: None,
+            operation_attempt_timeout: None,
+            sdk_max_attempts: None,
+            profile_name: None,
+            rps_limits: Default::default(),
+            expected_bucket_owner: None,
+            requester_pays: false,
+            slow_request_threshold: None,
+            user_agent_suffix: None,
+            app_name: Some("storage_scrubber".to_string()),
+        }),
+        timeouts: RemoteStorageConfig::DEFAULT_TIMEOUT.into(),
+    })?;
+
+    let aborted = storage
+        .abort_incomplete_uploads(None, min_age, cancel)
+        .await?;
+    tracing::info!("Aborted {aborted} incomplete multipart uploads older than {min_age:?}");
+    Ok(aborted)
+}
@@ -1,21 +1,27 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use anyhow::Context;
 use aws_sdk_s3::Client;
 use pageserver::tenant::remote_timeline_client::index::LayerFileMetadata;
 use pageserver_api::shard::ShardIndex;
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 use utils::generation::Generation;
 use utils::id::TimelineId;
 
 use crate::cloud_admin_api::BranchData;
-use crate::metadata_stream::stream_listing;
-use crate::{download_object_with_retries, RootTarget, TenantShardTimelineId};
-use futures_util::StreamExt;
+use crate::metadata_stream::{stream_objects, stream_tenant_timelines, stream_tenants};
+use crate::{
+    download_object_with_retries, init_remote, BucketConfig, NodeKind, RootTarget,
+    TenantShardTimelineId,
+};
+use futures_util::{StreamExt, TryStreamExt};
 use pageserver::tenant::remote_timeline_client::parse_remote_index_path;
-use pageserver::tenant::storage_layer::LayerName;
+use pageserver::tenant::storage_layer::{parse_remote_layer_file_name, LayerName};
 use pageserver::tenant::IndexPart;
+use pageserver_api::shard::TenantShardId;
 use remote_storage::RemotePath;
+use serde::Serialize;
 
 pub(crate) struct TimelineAnalysis {
     /// Anomalies detected
@@ -29,6 +35,10 @@ pub(crate) struct TimelineAnalysis {
     /// Keys not referenced in metadata: candidates for removal, but NOT NECESSARILY: beware
     /// of races between reading the metadata and reading the objects.
     pub(crate) garbage_keys: Vec<String>,
+
+    /// Layers whose size in remote storage differs from the size recorded in the index. Layer
+    /// objects are never rewritten in place, so this points at corruption rather than a race.
+    pub(crate) size_mismatches: Vec<String>,
 }
 
 impl TimelineAnalysis {
@@ -37,6 +47,7 @@ impl TimelineAnalysis {
             errors: Vec::new(),
             warnings: Vec::new(),
             garbage_keys: Vec::new(),
+            size_mismatches: Vec::new(),
         }
     }
 }
@@ -113,17 +124,30 @@ pub(crate) fn branch_cleanup_and_check_errors(
                             ))
                         }
 
-                        if !tenant_objects.check_ref(id.timeline_id, &layer, &metadata) {
-                            // FIXME: this will emit false positives if an index was
-                            // uploaded concurrently with our scan.  To make this check
-                            // correct, we need to try sending a HEAD request for the
-                            // layer we think is missing.
-                            result.errors.push(format!(
-                                "index_part.json contains a layer {}{} (shard {}) that is not present in remote storage",
-                                layer,
-                                metadata.generation.get_suffix(),
-                                metadata.shard
-                            ))
+                        match tenant_objects.check_ref(id.timeline_id, &layer, &metadata) {
+                            None => {
+                                // FIXME: this will emit false positives if an index was
+                                // uploaded concurrently with our scan.  To make this check
+                                // correct, we need to try sending a HEAD request for the
+                                // layer we think is missing.
+                                result.errors.push(format!(
+                                    "index_part.json contains a layer {}{} (shard {}) that is not present in remote storage",
+                                    layer,
+                                    metadata.generation.get_suffix(),
+                                    metadata.shard
+                                ))
+                            }
+                            Some(s3_size) if s3_size != metadata.file_size => {
+                                result.size_mismatches.push(format!(
+                                    "index_part.json contains a layer {}{} (shard {}) of {} bytes, but remote storage has {} bytes",
+                                    layer,
+                                    metadata.generation.get_suffix(),
+                                    metadata.shard,
+                                    metadata.file_size,
+                                    s3_size
+                                ))
+                            }
+                            Some(_) => {}
                         }
                     }
                 }
@@ -146,6 +170,13 @@ pub(crate) fn branch_cleanup_and_check_errors(
         warn!("Timeline metadata errors: {0:?}", result.errors);
     }
 
+    if !result.size_mismatches.is_empty() {
+        error!(
+            "Timeline layer size mismatches: {0:?}",
+            result.size_mismatches
+        );
+    }
+
     if !result.warnings.is_empty() {
         warn!("Timeline metadata warnings: {0:?}", result.warnings);
     }
@@ -163,6 +194,8 @@ pub(crate) fn branch_cleanup_and_check_errors(
 #[derive(Default)]
 pub(crate) struct LayerRef {
     ref_count: usize,
+    /// Object size from the listing
+    size: u64,
 }
 
 /// Top-level index of objects in a tenant.  This may be used by any shard-timeline within
@@ -178,7 +211,7 @@ impl TenantObjectListing {
     pub(crate) fn push(
         &mut self,
         ttid: TenantShardTimelineId,
-        layers: HashSet<(LayerName, Generation)>,
+        layers: HashMap<(LayerName, Generation), u64>,
     ) {
         let shard_index = ShardIndex::new(
             ttid.tenant_shard_id.shard_number,
@@ -188,7 +221,7 @@ impl TenantObjectListing {
             (shard_index, ttid.timeline_id),
             layers
                 .into_iter()
-                .map(|l| (l, LayerRef::default()))
+                .map(|(l, size)| (l, LayerRef { ref_count: 0, size }))
                 .collect(),
         );
 
@@ -202,24 +235,21 @@ impl TenantObjectListing {
     /// the layer's refcount will be incremented.  Later, after calling this for all references in all indices
     /// in a tenant, orphan layers may be detected by their zero refcounts.
     ///
-    /// Returns true if the layer exists
+    /// Returns the listed size of the layer if it exists
     pub(crate) fn check_ref(
         &mut self,
         timeline_id: TimelineId,
         layer_file: &LayerName,
         metadata: &LayerFileMetadata,
-    ) -> bool {
-        let Some(shard_tl) = self.shard_timelines.get_mut(&(metadata.shard, timeline_id)) else {
-            return false;
-        };
-
-        let Some(layer_ref) = shard_tl.get_mut(&(layer_file.clone(), metadata.generation)) else {
-            return false;
-        };
+    ) -> Option<u64> {
+        let shard_tl = self
+            .shard_timelines
+            .get_mut(&(metadata.shard, timeline_id))?;
+        let layer_ref = shard_tl.get_mut(&(layer_file.clone(), metadata.generation))?;
 
         layer_ref.ref_count += 1;
 
-        true
+        Some(layer_ref.size)
     }
 
     pub(crate) fn get_orphans(&self) -> Vec<(ShardIndex, TimelineId, LayerName, Generation)> {
@@ -252,32 +282,21 @@ pub(crate) enum BlobDataParseResult {
     Parsed {
         index_part: Box<IndexPart>,
         index_part_generation: Generation,
-        s3_layers: HashSet<(LayerName, Generation)>,
+        /// Listed layers, with their object sizes
+        s3_layers: HashMap<(LayerName, Generation), u64>,
     },
     /// The remains of a deleted Timeline (i.e. an initdb archive only)
     Relic,
     Incorrect(Vec<String>),
 }
 
-fn parse_layer_object_name(name: &str) -> Result<(LayerName, Generation), String> {
-    match name.rsplit_once('-') {
-        // FIXME: this is gross, just use a regex?
-        Some((layer_filename, gen)) if gen.len() == 8 => {
-            let layer = layer_filename.parse::<LayerName>()?;
-            let gen =
-                Generation::parse_suffix(gen).ok_or("Malformed generation suffix".to_string())?;
-            Ok((layer, gen))
-        }
-        _ => Ok((name.parse::<LayerName>()?, Generation::none())),
-    }
-}
-
 pub(crate) async fn list_timeline_blobs(
     s3_client: &Client,
     id: TenantShardTimelineId,
     s3_root: &RootTarget,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<S3TimelineBlobData> {
-    let mut s3_layers = HashSet::new();
+    let mut s3_layers = HashMap::new();
 
     let mut errors = Vec::new();
     let mut unknown_keys = Vec::new();
@@ -288,10 +307,12 @@ pub(crate) async fn list_timeline_blobs(
     let mut index_part_keys: Vec<String> = Vec::new();
     let mut initdb_archive: bool = false;
 
-    let mut stream = std::pin::pin!(stream_listing(s3_client, &timeline_dir_target));
+    let mut stream = std::pin::pin!(stream_objects(s3_client, &timeline_dir_target, cancel));
     while let Some(obj) = stream.next().await {
         let obj = obj?;
-        let key = obj.key();
+        let Some(key) = obj.key() else {
+            continue;
+        };
 
         let blob_name = key.strip_prefix(&timeline_dir_target.prefix_in_bucket);
         match blob_name {
@@ -303,10 +324,10 @@ pub(crate) async fn list_timeline_blobs(
                 tracing::debug!("initdb archive {key}");
                 initdb_archive = true;
             }
-            Some(maybe_layer_name) => match parse_layer_object_name(maybe_layer_name) {
+            Some(maybe_layer_name) => match parse_remote_layer_file_name(maybe_layer_name) {
                 Ok((new_layer, gen)) => {
                     tracing::debug!("Parsed layer key: {} {:?}", new_layer, gen);
-                    s3_layers.insert((new_layer, gen));
+                    s3_layers.insert((new_layer, gen), obj.size().unwrap_or(0) as u64);
                 }
                 Err(e) => {
                     tracing::info!("Error parsing key {maybe_layer_name}");
@@ -367,6 +388,7 @@ pub(crate) async fn list_timeline_blobs(
             s3_client,
             &timeline_dir_target.bucket_name,
             index_part_object_key,
+            cancel,
         )
         .await
         .context("index_part.json download")?;
@@ -401,3 +423,152 @@ pub(crate) async fn list_timeline_blobs(
         unknown_keys,
     })
 }
+
+/// Result of cross-referencing one timeline shard's `index_part.json` with a listing of its prefix.
+#[derive(Serialize)]
+pub struct LayerReferences {
+    pub ttid: TenantShardTimelineId,
+    /// Layers referenced by the index which are missing from the listing.
+    pub dangling: Vec<String>,
+    /// Listed layers which the index does not reference.
+    pub orphans: Vec<String>,
+    /// The timeline could not be checked, e.g. because its index is missing or unparseable.
+    pub errors: Vec<String>,
+}
+
+impl LayerReferences {
+    fn is_clean(&self) -> bool {
+        self.dangling.is_empty() && self.orphans.is_empty() && self.errors.is_empty()
+    }
+}
+
+/// Cross-reference the layers referenced by a timeline shard's index with the layers listed
+/// under its prefix.
+///
+/// Only layers owned by this shard are checked: references to ancestor shards' layers after a
+/// shard split are covered by the tenant-wide checks in `scan_metadata`. Listed layers with a
+/// generation at or above the index's are not reported as orphans, because they may have been
+/// uploaded ahead of the next index.
+pub(crate) async fn check_layer_references(
+    s3_client: &Client,
+    ttid: TenantShardTimelineId,
+    s3_root: &RootTarget,
+    cancel: &CancellationToken,
+) -> anyhow::Result<LayerReferences> {
+    let data = list_timeline_blobs(s3_client, ttid, s3_root, cancel).await?;
+
+    let mut result = LayerReferences {
+        ttid,
+        dangling: Vec::new(),
+        orphans: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    match data.blob_data {
+        BlobDataParseResult::Parsed {
+            index_part,
+            index_part_generation,
+            mut s3_layers,
+        } => {
+            let shard_index = ShardIndex::new(
+                ttid.tenant_shard_id.shard_number,
+                ttid.tenant_shard_id.shard_count,
+            );
+            for (layer, metadata) in &index_part.layer_metadata {
+                if metadata.shard != shard_index {
+                    continue;
+                }
+                if s3_layers
+                    .remove(&(layer.clone(), metadata.generation))
+                    .is_none()
+                {
+                    result
+                        .dangling
+                        .push(format!("{layer}{}", metadata.generation.get_suffix()));
+                }
+            }
+            result.orphans = s3_layers
+                .into_keys()
+                .filter(|(_, generation)| *generation < index_part_generation)
+                .map(|(layer, generation)| format!("{layer}{}", generation.get_suffix()))
+                .collect();
+            result.dangling.sort();
+            result.orphans.sort();
+        }
+        BlobDataParseResult::Relic => {}
+        BlobDataParseResult::Incorrect(errors) => result.errors = errors,
+    }
+
+    if !result.dangling.is_empty() {
+        error!(
+            "Timeline {ttid} index references layers missing from remote storage: {:?}",
+            result.dangling
+        );
+    }
+    if !result.orphans.is_empty() {
+        warn!(
+            "Timeline {ttid} has layers not referenced by its index: {:?}",
+            result.orphans
+        );
+    }
+
+    Ok(result)
+}
+
+#[derive(Serialize)]
+pub struct LayerReferencesSummary {
+    pub timeline_shard_count: usize,
+    /// Only timeline shards with findings are included.
+    pub timelines: Vec<LayerReferences>,
+}
+
+impl LayerReferencesSummary {
+    /// Dangling references mean an index points at data that is gone: that is corruption.
+    /// Orphans only waste space.
+    pub fn is_fatal(&self) -> bool {
+        self.timelines
+            .iter()
+            .any(|t| !t.dangling.is_empty() || !t.errors.is_empty())
+    }
+}
+
+/// Check every timeline shard in the bucket (or in the given tenants) for dangling and orphan
+/// layer references.
+pub async fn verify_layer_references(
+    bucket_config: BucketConfig,
+    tenant_ids: Vec<TenantShardId>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<LayerReferencesSummary> {
+    let (s3_client, target) = init_remote(bucket_config, NodeKind::Pageserver)?;
+
+    let tenants = if tenant_ids.is_empty() {
+        futures::future::Either::Left(stream_tenants(&s3_client, &target, cancel))
+    } else {
+        futures::future::Either::Right(futures::stream::iter(tenant_ids.into_iter().map(Ok)))
+    };
+
+    // Same as `scan_metadata`: be mindful of pageservers accessing the same prefixes.
+    const CONCURRENCY: usize = 32;
+
+    let timelines = tenants.map_ok(|t| stream_tenant_timelines(&s3_client, &target, t, cancel));
+    let timelines = timelines.try_buffered(CONCURRENCY);
+    let timelines = timelines.try_flatten();
+
+    let timelines =
+        timelines.map_ok(|ttid| check_layer_references(&s3_client, ttid, &target, cancel));
+    let mut timelines = std::pin::pin!(timelines.try_buffered(CONCURRENCY));
+
+    let mut summary = LayerReferencesSummary {
+        timeline_shard_count: 0,
+        timelines: Vec::new(),
+    };
+    while let Some(references) = timelines.next().await {
+        let references = references?;
+        summary.timeline_shard_count += 1;
+        if !references.is_clean() {
+            summary.timelines.push(references);
+        }
+    }
+
+    Ok(summary)
+}
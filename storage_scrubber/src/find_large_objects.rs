@@ -0,0 +1,215 @@
+use std::io::Write;
+
+use futures::{StreamExt, TryStreamExt};
+use pageserver::tenant::storage_layer::{parse_remote_layer_file_name, LayerName};
+use pageserver_api::shard::TenantShardId;
+use remote_storage::RemotePath;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use utils::id::TimelineId;
+
+use crate::{
+    init_remote, list_objects_with_retries, metadata_stream::stream_tenants, BucketConfig,
+    NodeKind, TenantShardTimelineId,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+enum LargeObjectKind {
+    DeltaLayer,
+    ImageLayer,
+    Other,
+}
+
+impl LargeObjectKind {
+    fn from_key(key: &str) -> Self {
+        let fname = key.split('/').last().unwrap();
+
+        let Ok((layer_name, _generation)) = parse_remote_layer_file_name(fname) else {
+            return LargeObjectKind::Other;
+        };
+
+        match layer_name {
+            LayerName::Image(_) => LargeObjectKind::ImageLayer,
+            LayerName::Delta(_) => LargeObjectKind::DeltaLayer,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            LargeObjectKind::DeltaLayer => "delta_layer",
+            LargeObjectKind::ImageLayer => "image_layer",
+            LargeObjectKind::Other => "other",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LargeObject {
+    pub tenant_shard_id: TenantShardId,
+    /// `None` for objects stored at the tenant level rather than in a timeline.
+    pub timeline_id: Option<TimelineId>,
+    pub key: String,
+    pub size: u64,
+    kind: LargeObjectKind,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct LargeObjectListing {
+    pub objects: Vec<LargeObject>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A single JSON document, printed once the scan is complete.
+    Json,
+    /// One CSV row per object, printed as soon as the object is found.
+    Csv,
+}
+
+/// Where found objects go: either buffered for a final JSON document, or streamed out as CSV.
+enum Output<W> {
+    Json(LargeObjectListing, W),
+    Csv(W),
+}
+
+impl<W: Write> Output<W> {
+    fn new(format: OutputFormat, mut writer: W) -> std::io::Result<Self> {
+        Ok(match format {
+            OutputFormat::Json => Output::Json(LargeObjectListing::default(), writer),
+            OutputFormat::Csv => {
+                writeln!(writer, "tenant_id,timeline_id,key,size_bytes,kind")?;
+                Output::Csv(writer)
+            }
+        })
+    }
+
+    fn push(&mut self, object: LargeObject) -> std::io::Result<()> {
+        match self {
+            Output::Json(listing, _) => listing.objects.push(object),
+            Output::Csv(writer) => writeln!(
+                writer,
+                "{},{},{},{},{}",
+                object.tenant_shard_id,
+                object
+                    .timeline_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+                csv_escape(&object.key),
+                object.size,
+                object.kind.as_str(),
+            )?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Output::Json(listing, mut writer) => {
+                writeln!(writer, "{}", serde_json::to_string(&listing)?)?;
+            }
+            Output::Csv(mut writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Quote a CSV field if it contains anything that would otherwise break the row apart.
+fn csv_escape(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+/// Find objects of at least `min_size` bytes in all tenants of the bucket, writing them to stdout
+/// in the requested `format`.
+pub async fn find_large_objects(
+    bucket_config: BucketConfig,
+    min_size: u64,
+    ignore_deltas: bool,
+    concurrency: usize,
+    format: OutputFormat,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let (s3_client, target) = init_remote(bucket_config.clone(), NodeKind::Pageserver)?;
+    let tenants = std::pin::pin!(stream_tenants(&s3_client, &target, cancel));
+    let objects_stream = tenants.map_ok(|tenant_shard_id| {
+        let mut tenant_root = target.tenant_root(&tenant_shard_id);
+        let s3_client = s3_client.clone();
+        async move {
+            let mut objects = Vec::new();
+            let mut total_objects_ctr = 0u64;
+            // We want the objects and not just common prefixes
+            tenant_root.delimiter.clear();
+            let mut continuation_token = None;
+            loop {
+                let fetch_response = list_objects_with_retries(
+                    &s3_client,
+                    &tenant_root,
+                    continuation_token.clone(),
+                    cancel,
+                )
+                .await?;
+                for obj in fetch_response.contents().iter().filter(|o| {
+                    if let Some(obj_size) = o.size {
+                        min_size as i64 <= obj_size
+                    } else {
+                        false
+                    }
+                }) {
+                    let key = obj.key().expect("couldn't get key").to_owned();
+                    let kind = LargeObjectKind::from_key(&key);
+                    if ignore_deltas && kind == LargeObjectKind::DeltaLayer {
+                        continue;
+                    }
+                    let timeline_id = RemotePath::from_string(key.trim_start_matches('/'))
+                        .ok()
+                        .and_then(|key| TenantShardTimelineId::from_key(&key))
+                        .map(|id| id.timeline_id);
+                    objects.push(LargeObject {
+                        tenant_shard_id,
+                        timeline_id,
+                        key,
+                        size: obj.size.unwrap() as u64,
+                        kind,
+                    })
+                }
+                total_objects_ctr += fetch_response.contents().len() as u64;
+                match fetch_response.next_continuation_token {
+                    Some(new_token) => continuation_token = Some(new_token),
+                    None => break,
+                }
+            }
+
+            Ok((tenant_shard_id, objects, total_objects_ctr))
+        }
+    });
+    let mut objects_stream = std::pin::pin!(objects_stream.try_buffer_unordered(concurrency));
+
+    // Streaming CSV rows as tenants complete means we never hold the full result in memory.
+    let mut output = Output::new(format, std::io::stdout())?;
+    let mut tenant_ctr = 0u64;
+    let mut object_ctr = 0u64;
+    let mut found_ctr = 0u64;
+    while let Some(res) = objects_stream.next().await {
+        let (tenant_shard_id, objects, total_objects_ctr) = res?;
+        found_ctr += objects.len() as u64;
+        for object in objects {
+            output.push(object)?;
+        }
+        object_ctr += total_objects_ctr;
+        tenant_ctr += 1;
+        if tenant_ctr % 100 == 0 {
+            tracing::info!(
+                "Scanned {tenant_ctr} shards. objects={object_ctr}, found={found_ctr}, current={tenant_shard_id}."
+            );
+        }
+    }
+
+    let bucket_name = target.bucket_name();
+    tracing::info!(
+        "Scan of {bucket_name} finished. Scanned {tenant_ctr} shards. objects={object_ctr}, found={found_ctr}."
+    );
+    output.finish()
+}
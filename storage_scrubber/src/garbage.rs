@@ -5,24 +5,29 @@
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use anyhow::Context;
 use aws_sdk_s3::{
+    primitives::ByteStream,
     types::{Delete, ObjectIdentifier},
     Client,
 };
 use futures_util::TryStreamExt;
 use pageserver_api::shard::TenantShardId;
 use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
 use utils::id::TenantId;
 
 use crate::{
     cloud_admin_api::{CloudAdminApiClient, MaybeDeleted, ProjectData},
-    init_remote,
-    metadata_stream::{stream_listing, stream_tenant_timelines, stream_tenants},
-    BucketConfig, ConsoleConfig, NodeKind, RootTarget, TenantShardTimelineId, TraversingDepth,
+    download_object_with_retries, init_remote, init_s3_client,
+    metadata_stream::{stream_objects, stream_tenant_timelines, stream_tenants},
+    BucketConfig, ConsoleConfig, NodeKind, RootTarget, S3Target, TenantShardTimelineId,
+    TraversingDepth,
 };
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -61,6 +66,11 @@ pub struct GarbageList {
     /// in the scrubber that might otherwise generate a "deleted all" result.
     active_tenant_count: usize,
     active_timeline_count: usize,
+
+    /// Objects modified more recently than this are never considered garbage: entities containing
+    /// them were left out of `items`, and objects written since the list was made are not purged.
+    #[serde(default)]
+    min_age: Option<Duration>,
 }
 
 impl GarbageList {
@@ -71,6 +81,7 @@ impl GarbageList {
             active_timeline_count: 0,
             node_kind,
             bucket_config,
+            min_age: None,
         }
     }
 
@@ -105,17 +116,63 @@ pub async fn find_garbage(
     depth: TraversingDepth,
     node_kind: NodeKind,
     output_path: String,
+    min_age: Option<Duration>,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
-    let garbage = find_garbage_inner(bucket_config, console_config, depth, node_kind).await?;
+    let mut garbage = find_garbage_inner(
+        bucket_config.clone(),
+        console_config,
+        depth,
+        node_kind,
+        cancel,
+    )
+    .await?;
+    if let Some(min_age) = min_age {
+        let (s3_client, target) = init_remote(bucket_config, node_kind)?;
+        exclude_recently_modified(&s3_client, &target, &mut garbage, min_age, cancel).await?;
+    }
     let serialized = serde_json::to_vec_pretty(&garbage)?;
 
-    tokio::fs::write(&output_path, &serialized).await?;
+    match GarbageListPath::parse(&output_path) {
+        GarbageListPath::Local(path) => tokio::fs::write(path, &serialized).await?,
+        GarbageListPath::S3 { bucket, key } => {
+            init_s3_client(&bucket_config)
+                .put_object()
+                .bucket(bucket)
+                .key(key)
+                .body(ByteStream::from(serialized))
+                .send()
+                .await
+                .with_context(|| format!("uploading garbage list to {output_path}"))?;
+        }
+    }
 
     tracing::info!("Wrote garbage report to {output_path}");
 
     Ok(())
 }
 
+/// Where a garbage list is stored: a local file, or an object given as `s3://bucket/key`, so that
+/// finding and purging garbage can happen on different machines.
+///
+/// S3 locations are accessed with the same region and credentials as the scanned bucket.
+enum GarbageListPath<'a> {
+    Local(&'a str),
+    S3 { bucket: &'a str, key: &'a str },
+}
+
+impl<'a> GarbageListPath<'a> {
+    fn parse(path: &'a str) -> Self {
+        match path
+            .strip_prefix("s3://")
+            .and_then(|rest| rest.split_once('/'))
+        {
+            Some((bucket, key)) => Self::S3 { bucket, key },
+            None => Self::Local(path),
+        }
+    }
+}
+
 // How many concurrent S3 operations to issue (approximately): this is the concurrency
 // for things like listing the timelines within tenant prefixes.
 const S3_CONCURRENCY: usize = 32;
@@ -138,6 +195,7 @@ async fn find_garbage_inner(
     console_config: ConsoleConfig,
     depth: TraversingDepth,
     node_kind: NodeKind,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<GarbageList> {
     // Construct clients for S3 and for Console API
     let (s3_client, target) = init_remote(bucket_config.clone(), node_kind)?;
@@ -166,7 +224,7 @@ async fn find_garbage_inner(
 
     // Enumerate Tenants in S3, and check if each one exists in Console
     tracing::info!("Finding all tenants in bucket {}...", bucket_config.bucket);
-    let tenants = stream_tenants(&s3_client, &target);
+    let tenants = stream_tenants(&s3_client, &target, cancel);
     let tenants_checked = tenants.map_ok(|t| {
         let api_client = cloud_admin_api_client.clone();
         let console_cache = console_cache.clone();
@@ -258,7 +316,8 @@ async fn find_garbage_inner(
 
     // Construct a stream of all timelines within active tenants
     let active_tenants = tokio_stream::iter(active_tenants.iter().map(Ok));
-    let timelines = active_tenants.map_ok(|t| stream_tenant_timelines(&s3_client, &target, *t));
+    let timelines =
+        active_tenants.map_ok(|t| stream_tenant_timelines(&s3_client, &target, *t, cancel));
     let timelines = timelines.try_buffer_unordered(S3_CONCURRENCY);
     let timelines = timelines.try_flatten();
 
@@ -303,6 +362,56 @@ async fn find_garbage_inner(
     Ok(garbage)
 }
 
+/// The cutoff for objects to count as modified less than `min_age` ago. A `min_age` reaching back
+/// before the epoch makes every object recent.
+fn recent_cutoff(min_age: Duration) -> SystemTime {
+    SystemTime::now()
+        .checked_sub(min_age)
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Remove entities from the garbage list if any of their objects were modified less than `min_age` ago:
+/// recent writes suggest that the tenant or timeline is still in use, e.g. by an in-flight upload.
+async fn exclude_recently_modified(
+    s3_client: &Arc<Client>,
+    target: &RootTarget,
+    garbage: &mut GarbageList,
+    min_age: Duration,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let cutoff = recent_cutoff(min_age);
+    garbage.min_age = Some(min_age);
+
+    let items = std::mem::take(&mut garbage.items);
+    let checked = tokio_stream::iter(items.into_iter().map(Ok)).map_ok(|item| async move {
+        let listing =
+            list_entity_objects(s3_client, target, &item.entity, Some(cutoff), cancel).await?;
+        anyhow::Ok((item, listing.too_recent))
+    });
+    let mut checked = std::pin::pin!(checked.try_buffer_unordered(S3_CONCURRENCY));
+
+    let mut excluded = 0;
+    while let Some(result) = checked.next().await {
+        let (item, too_recent) = result?;
+        if too_recent > 0 {
+            tracing::info!(
+                "Not treating {:?} as garbage: {too_recent} objects modified within {}",
+                item.entity,
+                humantime::format_duration(min_age)
+            );
+            excluded += 1;
+        } else {
+            garbage.items.push(item);
+        }
+    }
+
+    tracing::info!(
+        "Excluded {excluded} garbage items with recently modified objects, {} left",
+        garbage.items.len()
+    );
+    Ok(())
+}
+
 #[derive(clap::ValueEnum, Debug, Clone)]
 pub enum PurgeMode {
     /// The safest mode: only delete tenants that were explicitly reported as deleted
@@ -323,107 +432,260 @@ impl std::fmt::Display for PurgeMode {
     }
 }
 
+/// Objects in a garbage entity's prefix.
+struct EntityObjects {
+    /// Objects last modified before the cutoff, if any was given
+    objects: Vec<ObjectIdentifier>,
+    /// Number of objects skipped for being modified at or after the cutoff
+    too_recent: usize,
+}
+
+async fn list_objects_modified_before(
+    s3_client: &Client,
+    mut prefix: S3Target,
+    modified_before: Option<SystemTime>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<EntityObjects> {
+    // Remove delimiter, so that object listing lists all keys in the prefix and not just
+    // common prefixes.
+    prefix.delimiter = String::new();
+
+    let mut result = EntityObjects {
+        objects: Vec::new(),
+        too_recent: 0,
+    };
+    let mut stream = std::pin::pin!(stream_objects(s3_client, &prefix, cancel));
+    while let Some(object) = stream.next().await {
+        let object = object?;
+        if let Some(modified_before) = modified_before {
+            // An object without a usable timestamp is treated as recent, to be on the safe side.
+            let last_modified = object
+                .last_modified()
+                .and_then(|t| SystemTime::try_from(*t).ok());
+            if !matches!(last_modified, Some(t) if t < modified_before) {
+                result.too_recent += 1;
+                continue;
+            }
+        }
+        let Some(key) = object.key() else {
+            continue;
+        };
+        result
+            .objects
+            .push(ObjectIdentifier::builder().key(key).build()?);
+    }
+    Ok(result)
+}
+
+async fn list_entity_objects(
+    s3_client: &Client,
+    target: &RootTarget,
+    entity: &GarbageEntity,
+    modified_before: Option<SystemTime>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<EntityObjects> {
+    match entity {
+        GarbageEntity::Tenant(tenant_shard_id) => {
+            tracing::debug!("Listing objects in tenant {tenant_shard_id}");
+            let tenant_root = target.tenant_root(tenant_shard_id);
+            list_objects_modified_before(s3_client, tenant_root, modified_before, cancel).await
+        }
+        GarbageEntity::Timeline(ttid) => {
+            tracing::debug!("Listing objects in timeline {ttid}");
+            let timeline_root = target.timeline_root(ttid);
+            list_objects_modified_before(s3_client, timeline_root, modified_before, cancel).await
+        }
+    }
+}
+
 pub async fn get_tenant_objects(
     s3_client: &Arc<Client>,
     target: RootTarget,
     tenant_shard_id: TenantShardId,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<Vec<ObjectIdentifier>> {
-    tracing::debug!("Listing objects in tenant {tenant_shard_id}");
-    // TODO: apply extra validation based on object modification time.  Don't purge
-    // tenants where any timeline's index_part.json has been touched recently.
-
-    let mut tenant_root = target.tenant_root(&tenant_shard_id);
-
-    // Remove delimiter, so that object listing lists all keys in the prefix and not just
-    // common prefixes.
-    tenant_root.delimiter = String::new();
-
-    let key_stream = stream_listing(s3_client, &tenant_root);
-    key_stream.try_collect().await
+    let entity = GarbageEntity::Tenant(tenant_shard_id);
+    Ok(
+        list_entity_objects(s3_client, &target, &entity, None, cancel)
+            .await?
+            .objects,
+    )
 }
 
 pub async fn get_timeline_objects(
     s3_client: &Arc<Client>,
     target: RootTarget,
     ttid: TenantShardTimelineId,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<Vec<ObjectIdentifier>> {
-    tracing::debug!("Listing objects in timeline {ttid}");
-    let mut timeline_root = target.timeline_root(&ttid);
+    let entity = GarbageEntity::Timeline(ttid);
+    Ok(
+        list_entity_objects(s3_client, &target, &entity, None, cancel)
+            .await?
+            .objects,
+    )
+}
 
-    // TODO: apply extra validation based on object modification time.  Don't purge
-    // timelines whose index_part.json has been touched recently.
+const MAX_KEYS_PER_DELETE: usize = 1000;
 
-    // Remove delimiter, so that object listing lists all keys in the prefix and not just
-    // common prefixes.
-    timeline_root.delimiter = String::new();
-    let key_stream = stream_listing(s3_client, &timeline_root);
+/// How many DeleteObjects requests to issue concurrently by default when purging.
+pub const DEFAULT_PURGE_CONCURRENCY: usize = 8;
 
-    key_stream.try_collect().await
+/// Outcome of a single DeleteObjects request
+struct DeleteBatchResult {
+    deleted: usize,
+    failed: usize,
 }
 
-const MAX_KEYS_PER_DELETE: usize = 1000;
-
-/// Drain a buffer of keys into DeleteObjects requests
-///
-/// If `drain` is true, drains keys completely; otherwise stops when <
-/// MAX_KEYS_PER_DELETE keys are left.
-/// `num_deleted` returns number of deleted keys.
-async fn do_delete(
-    s3_client: &Arc<Client>,
-    bucket_name: &str,
-    keys: &mut Vec<ObjectIdentifier>,
+/// Delete up to [`MAX_KEYS_PER_DELETE`] keys in one DeleteObjects request.  Failures are
+/// logged and counted rather than returned, so that one bad batch doesn't stop the purge.
+async fn delete_batch(
+    s3_client: Arc<Client>,
+    bucket_name: String,
+    keys: Vec<ObjectIdentifier>,
     dry_run: bool,
-    drain: bool,
-    progress_tracker: &mut DeletionProgressTracker,
-) -> anyhow::Result<()> {
-    while (!keys.is_empty() && drain) || (keys.len() >= MAX_KEYS_PER_DELETE) {
-        let request_keys =
-            keys.split_off(keys.len() - (std::cmp::min(MAX_KEYS_PER_DELETE, keys.len())));
-        let num_deleted = request_keys.len();
-        if dry_run {
-            tracing::info!("Dry-run deletion of objects: ");
-            for k in request_keys {
-                tracing::info!("  {k:?}");
-            }
-        } else {
-            let delete_request = s3_client
-                .delete_objects()
-                .bucket(bucket_name)
-                .delete(Delete::builder().set_objects(Some(request_keys)).build()?);
-            delete_request
-                .send()
-                .await
-                .context("DeleteObjects request")?;
-            progress_tracker.register(num_deleted);
+) -> DeleteBatchResult {
+    let num_keys = keys.len();
+    if dry_run {
+        tracing::info!("Dry-run deletion of objects: ");
+        for k in keys {
+            tracing::info!("  {k:?}");
         }
+        return DeleteBatchResult {
+            deleted: 0,
+            failed: 0,
+        };
     }
 
-    Ok(())
+    let delete = match Delete::builder().set_objects(Some(keys)).build() {
+        Ok(delete) => delete,
+        Err(e) => {
+            tracing::error!("Failed to build DeleteObjects request: {e}");
+            return DeleteBatchResult {
+                deleted: 0,
+                failed: num_keys,
+            };
+        }
+    };
+    match s3_client
+        .delete_objects()
+        .bucket(bucket_name)
+        .delete(delete)
+        .send()
+        .await
+        .context("DeleteObjects request")
+    {
+        Ok(response) => {
+            for e in response.errors() {
+                tracing::warn!(
+                    "Failed to delete {}: {}",
+                    e.key().unwrap_or("<unknown key>"),
+                    e.message().unwrap_or("<no message>")
+                );
+            }
+            let failed = response.errors().len();
+            DeleteBatchResult {
+                deleted: num_keys - failed,
+                failed,
+            }
+        }
+        Err(e) => {
+            tracing::error!("{e:#}");
+            DeleteBatchResult {
+                deleted: 0,
+                failed: num_keys,
+            }
+        }
+    }
 }
 
 /// Simple tracker reporting each 10k deleted keys.
 #[derive(Default)]
 struct DeletionProgressTracker {
     num_deleted: usize,
+    num_failed: usize,
     last_reported_num_deleted: usize,
 }
 
 impl DeletionProgressTracker {
-    fn register(&mut self, n: usize) {
-        self.num_deleted += n;
+    fn register(&mut self, result: DeleteBatchResult) {
+        self.num_deleted += result.deleted;
+        self.num_failed += result.failed;
         if self.num_deleted - self.last_reported_num_deleted > 10000 {
-            tracing::info!("progress: deleted {} keys", self.num_deleted);
+            tracing::info!(
+                "progress: deleted {} keys, {} failed",
+                self.num_deleted,
+                self.num_failed
+            );
             self.last_reported_num_deleted = self.num_deleted;
         }
     }
 }
 
+/// Issues DeleteObjects requests in the background, with at most `concurrency` in flight.
+struct Deleter {
+    s3_client: Arc<Client>,
+    bucket_name: String,
+    dry_run: bool,
+    concurrency: usize,
+    in_flight: JoinSet<DeleteBatchResult>,
+    progress_tracker: DeletionProgressTracker,
+}
+
+impl Deleter {
+    /// Drain a buffer of keys into DeleteObjects requests
+    ///
+    /// If `drain` is true, drains keys completely; otherwise stops when <
+    /// MAX_KEYS_PER_DELETE keys are left.
+    async fn delete(
+        &mut self,
+        keys: &mut Vec<ObjectIdentifier>,
+        drain: bool,
+    ) -> anyhow::Result<()> {
+        while (!keys.is_empty() && drain) || (keys.len() >= MAX_KEYS_PER_DELETE) {
+            while self.in_flight.len() >= self.concurrency {
+                let result = self.in_flight.join_next().await.expect("non-empty")?;
+                self.progress_tracker.register(result);
+            }
+            let request_keys =
+                keys.split_off(keys.len() - (std::cmp::min(MAX_KEYS_PER_DELETE, keys.len())));
+            self.in_flight.spawn(delete_batch(
+                self.s3_client.clone(),
+                self.bucket_name.clone(),
+                request_keys,
+                self.dry_run,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Wait for all requests in flight
+    async fn finish(mut self) -> anyhow::Result<DeletionProgressTracker> {
+        while let Some(result) = self.in_flight.join_next().await {
+            self.progress_tracker.register(result?);
+        }
+        Ok(self.progress_tracker)
+    }
+}
+
+/// Purge the garbage listed in `input_path`.  `bucket_config` is only used to access the list itself
+/// if it is stored in S3: the bucket to purge is taken from the list.
 pub async fn purge_garbage(
+    bucket_config: BucketConfig,
     input_path: String,
     mode: PurgeMode,
+    concurrency: usize,
     dry_run: bool,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
-    let list_bytes = tokio::fs::read(&input_path).await?;
+    let list_bytes = match GarbageListPath::parse(&input_path) {
+        GarbageListPath::Local(path) => tokio::fs::read(path).await?,
+        GarbageListPath::S3 { bucket, key } => {
+            download_object_with_retries(&init_s3_client(&bucket_config), bucket, key, cancel)
+                .await
+                .with_context(|| format!("downloading garbage list from {input_path}"))?
+        }
+    };
     let garbage_list = serde_json::from_slice::<GarbageList>(&list_bytes)?;
     tracing::info!(
         "Loaded {} items in garbage list from {}",
@@ -462,53 +724,64 @@ pub async fn purge_garbage(
         mode
     );
 
+    // Re-apply the age filter of the garbage list, so that entities written to since it was made are
+    // left alone, like they would have been left out of the list.
+    let modified_before = garbage_list.min_age.map(recent_cutoff);
+
     let items = tokio_stream::iter(filtered_items.map(Ok));
     let get_objects_results = items.map_ok(|i| {
         let s3_client = s3_client.clone();
         let target = target.clone();
         async move {
-            match i.entity {
-                GarbageEntity::Tenant(tenant_id) => {
-                    get_tenant_objects(&s3_client, target, tenant_id).await
-                }
-                GarbageEntity::Timeline(ttid) => {
-                    get_timeline_objects(&s3_client, target, ttid).await
-                }
+            let listing =
+                list_entity_objects(&s3_client, &target, &i.entity, modified_before, cancel)
+                    .await?;
+            if listing.too_recent > 0 {
+                // Like in `exclude_recently_modified`: a recent write means the entity is in use,
+                // and deleting only its older objects would leave it half gone
+                tracing::warn!(
+                    "Skipping {:?}: {} objects were modified recently",
+                    i.entity,
+                    listing.too_recent,
+                );
+                return anyhow::Ok(Vec::new());
             }
+            anyhow::Ok(listing.objects)
         }
     });
     let mut get_objects_results =
         std::pin::pin!(get_objects_results.try_buffer_unordered(S3_CONCURRENCY));
 
+    let mut deleter = Deleter {
+        s3_client: s3_client.clone(),
+        bucket_name: garbage_list.bucket_config.bucket.clone(),
+        dry_run,
+        concurrency: concurrency.max(1),
+        in_flight: JoinSet::new(),
+        progress_tracker: DeletionProgressTracker::default(),
+    };
     let mut objects_to_delete = Vec::new();
-    let mut progress_tracker = DeletionProgressTracker::default();
     while let Some(result) = get_objects_results.next().await {
         let mut object_list = result?;
         objects_to_delete.append(&mut object_list);
-        if objects_to_delete.len() >= MAX_KEYS_PER_DELETE {
-            do_delete(
-                &s3_client,
-                &garbage_list.bucket_config.bucket,
-                &mut objects_to_delete,
-                dry_run,
-                false,
-                &mut progress_tracker,
-            )
-            .await?;
-        }
+        deleter.delete(&mut objects_to_delete, false).await?;
     }
+    deleter.delete(&mut objects_to_delete, true).await?;
+    let progress_tracker = deleter.finish().await?;
 
-    do_delete(
-        &s3_client,
-        &garbage_list.bucket_config.bucket,
-        &mut objects_to_delete,
-        dry_run,
-        true,
-        &mut progress_tracker,
-    )
-    .await?;
+    tracing::info!(
+        "{} keys deleted in total, {} failed",
+        progress_tracker.num_deleted,
+        progress_tracker.num_failed
+    );
 
-    tracing::info!("{} keys deleted in total", progress_tracker.num_deleted);
+    if progress_tracker.num_failed > 0 {
+        // Deleting is idempotent: a re-run against the same list only lists and deletes what is left.
+        anyhow::bail!(
+            "Failed to delete {} keys, re-run the purge to retry",
+            progress_tracker.num_failed
+        );
+    }
 
     Ok(())
 }
@@ -1,7 +1,9 @@
 #![deny(unsafe_code)]
 #![deny(clippy::undocumented_unsafe_blocks)]
+pub mod abort_incomplete_uploads;
 pub mod checks;
 pub mod cloud_admin_api;
+pub mod find_large_objects;
 pub mod garbage;
 pub mod metadata_stream;
 pub mod pageserver_physical_gc;
@@ -11,6 +13,7 @@ pub mod tenant_snapshot;
 
 use std::env;
 use std::fmt::Display;
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -28,18 +31,44 @@ use aws_smithy_async::rt::sleep::TokioSleep;
 
 use camino::{Utf8Path, Utf8PathBuf};
 use clap::ValueEnum;
-use pageserver::tenant::TENANTS_SEGMENT_NAME;
+use once_cell::sync::Lazy;
+use pageserver::tenant::{TENANTS_SEGMENT_NAME, TIMELINES_SEGMENT_NAME};
 use pageserver_api::shard::TenantShardId;
+use rand::Rng;
+use remote_storage::RemotePath;
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncReadExt;
+use tokio_util::sync::CancellationToken;
 use tracing::error;
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
-use utils::fs_ext;
 use utils::id::{TenantId, TimelineId};
-
-const MAX_RETRIES: usize = 20;
+use utils::{backoff, fs_ext};
+
+const DEFAULT_MAX_RETRIES: u32 = 20;
+const MAX_RETRIES_ENV_VAR: &str = "MAX_RETRIES";
+const RETRY_BACKOFF_BASE_INCREMENT: f64 = 0.5;
+const RETRY_BACKOFF_MAX_SECONDS: f64 = 30.0;
+
+/// How many times a request to remote storage is attempted before giving up, from the
+/// `MAX_RETRIES` environment variable.
+static MAX_RETRIES: Lazy<u32> = Lazy::new(|| match env::var(MAX_RETRIES_ENV_VAR) {
+    Ok(s) => match s.parse() {
+        Ok(max_retries) => max_retries,
+        Err(e) => {
+            error!("Ignoring invalid {MAX_RETRIES_ENV_VAR} '{s}': {e}");
+            DEFAULT_MAX_RETRIES
+        }
+    },
+    Err(_) => DEFAULT_MAX_RETRIES,
+});
+
+/// Remote storage could not be accessed, even after retries.  Distinguishes transient or
+/// configuration problems from problems found in the scanned data.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct RemoteAccessError(String);
 const CLOUD_ADMIN_API_TOKEN_ENV_VAR: &str = "CLOUD_ADMIN_API_TOKEN";
 
 #[derive(Debug, Clone)]
@@ -73,6 +102,60 @@ impl TenantShardTimelineId {
             timeline_id,
         }
     }
+
+    /// Parses the timeline a pageserver key belongs to, from its
+    /// `tenants/<tenant shard>/timelines/<timeline>` segments. The tenant shard directory may be
+    /// unsharded (`<tenant>`) or sharded (`<tenant>-<shard>`), and anything may come before and
+    /// after these segments, like the `prefix_in_bucket` or the name of a layer.
+    ///
+    /// Returns `None` for keys which are not within a timeline, e.g. tenant manifests.
+    pub fn from_key(key: &RemotePath) -> Option<Self> {
+        let segments = key
+            .get_path()
+            .components()
+            .map(|c| c.as_str())
+            .collect::<Vec<_>>();
+        segments.windows(4).find_map(|window| match window {
+            [TENANTS_SEGMENT_NAME, tenant_shard_id, TIMELINES_SEGMENT_NAME, timeline_id] => Some(
+                Self::new(tenant_shard_id.parse().ok()?, timeline_id.parse().ok()?),
+            ),
+            _ => None,
+        })
+    }
+}
+
+/// Parses the tenant shard from a prefix listed below [`RootTarget::tenants_root`], e.g.
+/// `<prefix_in_bucket>/tenants/<tenant>-<shard>/`, or from a key within that tenant shard.
+/// Both unsharded and sharded tenant directories are accepted.
+///
+/// Returns `None` if `prefix` is not below `tenants_target`, and an error if it is, but its first
+/// segment is not a tenant shard.
+pub(crate) fn parse_tenant_shard_prefix(
+    tenants_target: &S3Target,
+    prefix: &str,
+) -> Option<anyhow::Result<TenantShardId>> {
+    let segment = tenants_target.first_segment_of(prefix)?;
+    Some(
+        segment
+            .parse::<TenantShardId>()
+            .with_context(|| format!("Incorrect tenant shard id in prefix: {prefix}")),
+    )
+}
+
+/// Parses the timeline from a prefix listed below [`RootTarget::timelines_root`], e.g.
+/// `<prefix_in_bucket>/tenants/<tenant shard>/timelines/<timeline>/`, or from a key within that
+/// timeline. Like [`parse_tenant_shard_prefix`], returns `None` if `prefix` is not below
+/// `timelines_target`.
+pub(crate) fn parse_timeline_prefix(
+    timelines_target: &S3Target,
+    prefix: &str,
+) -> Option<anyhow::Result<TimelineId>> {
+    let segment = timelines_target.first_segment_of(prefix)?;
+    Some(
+        segment
+            .parse::<TimelineId>()
+            .with_context(|| format!("Incorrect timeline id in prefix: {prefix}")),
+    )
 }
 
 impl Display for TenantShardTimelineId {
@@ -131,6 +214,16 @@ impl S3Target {
         }
         new_self
     }
+
+    /// The first path segment of `key` below this target, e.g. the tenant shard directory name of a
+    /// key below [`RootTarget::tenants_root`]. `None` if `key` is not below this target.
+    fn first_segment_of<'a>(&self, key: &'a str) -> Option<&'a str> {
+        key.strip_prefix(&self.prefix_in_bucket)?
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .filter(|segment| !segment.is_empty())
+    }
 }
 
 #[derive(Clone)]
@@ -156,6 +249,18 @@ impl RootTarget {
         }
     }
 
+    /// Key of the cursor which incremental pageserver metadata scans resume from, next to the
+    /// tenants rather than among them. `None` for safekeepers, whose scans are not incremental.
+    pub(crate) fn scan_cursor_key(&self) -> Option<String> {
+        match self {
+            Self::Pageserver(root) => Some(format!(
+                "{}/scrubber/scan_metadata_cursor.json",
+                root.prefix_in_bucket.trim_end_matches('/')
+            )),
+            Self::Safekeeper(_) => None,
+        }
+    }
+
     pub(crate) fn tenant_shards_prefix(&self, tenant_id: &TenantId) -> S3Target {
         // Only pageserver remote storage contains tenant-shards
         assert!(matches!(self, Self::Pageserver(_)));
@@ -206,6 +311,13 @@ pub struct BucketConfig {
     pub region: String,
     pub bucket: String,
     pub prefix_in_bucket: Option<String>,
+    /// Custom endpoint for S3-compatible stores such as MinIO.  Defaults to the AWS endpoint for `region`.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Address buckets as `<endpoint>/<bucket>` rather than `<bucket>.<endpoint>`, as most
+    /// S3-compatible stores require.
+    #[serde(default)]
+    pub force_path_style: bool,
 }
 
 impl BucketConfig {
@@ -213,15 +325,40 @@ impl BucketConfig {
         let region = env::var("REGION").context("'REGION' param retrieval")?;
         let bucket = env::var("BUCKET").context("'BUCKET' param retrieval")?;
         let prefix_in_bucket = env::var("BUCKET_PREFIX").ok();
+        let endpoint = env::var("ENDPOINT").ok();
+        let force_path_style = match env::var("FORCE_PATH_STYLE") {
+            Ok(s) => s
+                .parse()
+                .with_context(|| format!("'FORCE_PATH_STYLE' param parsing: {s}"))?,
+            Err(_) => false,
+        };
 
         Ok(Self {
             region,
             bucket,
             prefix_in_bucket,
+            endpoint,
+            force_path_style,
         })
     }
 }
 
+/// Read newline-separated [`TenantShardId`]s from a file.  Empty lines and lines starting with `#`
+/// are ignored.
+pub fn read_tenant_ids_file(path: &Utf8Path) -> anyhow::Result<Vec<TenantShardId>> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("reading tenant IDs from {path}"))?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.parse::<TenantShardId>()
+                .with_context(|| format!("parsing tenant ID '{line}' in {path}"))
+        })
+        .collect()
+}
+
 pub struct ConsoleConfig {
     pub token: String,
     pub base_url: Url,
@@ -261,7 +398,8 @@ pub fn init_logging(file_name: &str) -> WorkerGuard {
     guard
 }
 
-pub fn init_s3_client(bucket_region: Region) -> Client {
+pub fn init_s3_client(bucket_config: &BucketConfig) -> Client {
+    let bucket_region = Region::new(bucket_config.region.clone());
     let credentials_provider = {
         // uses "AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY"
         let chain = CredentialsProviderChain::first_try(
@@ -306,9 +444,16 @@ pub fn init_s3_client(bucket_region: Region) -> Client {
         .sleep_impl(SharedAsyncSleep::from(sleep_impl))
         .credentials_provider(credentials_provider);
 
-    if let Ok(endpoint) = env::var("AWS_ENDPOINT_URL") {
+    if let Some(endpoint) = bucket_config
+        .endpoint
+        .clone()
+        .or_else(|| env::var("AWS_ENDPOINT_URL").ok())
+    {
         builder = builder.endpoint_url(endpoint)
     }
+    if bucket_config.force_path_style {
+        builder = builder.force_path_style(true)
+    }
 
     Client::from_conf(builder.build())
 }
@@ -317,9 +462,8 @@ fn init_remote(
     bucket_config: BucketConfig,
     node_kind: NodeKind,
 ) -> anyhow::Result<(Arc<Client>, RootTarget)> {
-    let bucket_region = Region::new(bucket_config.region);
     let delimiter = "/".to_string();
-    let s3_client = Arc::new(init_s3_client(bucket_region));
+    let s3_client = Arc::new(init_s3_client(&bucket_config));
 
     let s3_root = match node_kind {
         NodeKind::Pageserver => RootTarget::Pageserver(S3Target {
@@ -339,86 +483,114 @@ fn init_remote(
     Ok((s3_client, s3_root))
 }
 
+/// Sleeps before retrying a failed request for the `attempt`th time, counting from 1.
+///
+/// The delay grows exponentially, and half of it is random, so that the many tasks of a parallel
+/// scan which all got throttled at once don't retry in lockstep.
+async fn retry_backoff(attempt: u32) {
+    let max_seconds = backoff::exponential_backoff_duration_seconds(
+        attempt,
+        RETRY_BACKOFF_BASE_INCREMENT,
+        RETRY_BACKOFF_MAX_SECONDS,
+    );
+    let seconds = max_seconds / 2.0 + rand::thread_rng().gen_range(0.0..=max_seconds / 2.0);
+    tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+}
+
+/// Runs `fut` to completion, unless `cancel` fires first.
+async fn cancellable<T>(
+    fut: impl Future<Output = T>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<T> {
+    tokio::select! {
+        res = fut => Ok(res),
+        _ = cancel.cancelled() => Err(anyhow::anyhow!("Cancelled")),
+    }
+}
+
 async fn list_objects_with_retries(
     s3_client: &Client,
     s3_target: &S3Target,
     continuation_token: Option<String>,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output> {
-    for _ in 0..MAX_RETRIES {
-        match s3_client
+    let max_retries = *MAX_RETRIES;
+    for attempt in 1..=max_retries {
+        let request = s3_client
             .list_objects_v2()
             .bucket(&s3_target.bucket_name)
             .prefix(&s3_target.prefix_in_bucket)
             .delimiter(&s3_target.delimiter)
             .set_continuation_token(continuation_token.clone())
-            .send()
-            .await
-        {
+            .send();
+        match cancellable(request, cancel).await? {
             Ok(response) => return Ok(response),
             Err(e) => {
                 error!(
                     "list_objects_v2 query failed: {e}, bucket_name={}, prefix={}, delimiter={}",
                     s3_target.bucket_name, s3_target.prefix_in_bucket, s3_target.delimiter
                 );
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                cancellable(retry_backoff(attempt), cancel).await?;
             }
         }
     }
 
-    anyhow::bail!("Failed to list objects {MAX_RETRIES} times")
+    Err(RemoteAccessError(format!("Failed to list objects {max_retries} times")).into())
 }
 
 async fn download_object_with_retries(
     s3_client: &Client,
     bucket_name: &str,
     key: &str,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<Vec<u8>> {
-    for _ in 0..MAX_RETRIES {
+    let max_retries = *MAX_RETRIES;
+    for attempt in 1..=max_retries {
         let mut body_buf = Vec::new();
-        let response_stream = match s3_client
-            .get_object()
-            .bucket(bucket_name)
-            .key(key)
-            .send()
-            .await
-        {
+        let request = s3_client.get_object().bucket(bucket_name).key(key).send();
+        let response_stream = match cancellable(request, cancel).await? {
             Ok(response) => response,
             Err(e) => {
                 error!("Failed to download object for key {key}: {e}");
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                cancellable(retry_backoff(attempt), cancel).await?;
                 continue;
             }
         };
 
-        match response_stream
-            .body
-            .into_async_read()
-            .read_to_end(&mut body_buf)
-            .await
-        {
+        let mut read_stream = response_stream.body.into_async_read();
+        match cancellable(read_stream.read_to_end(&mut body_buf), cancel).await? {
             Ok(bytes_read) => {
                 tracing::debug!("Downloaded {bytes_read} bytes for object {key}");
                 return Ok(body_buf);
             }
             Err(e) => {
                 error!("Failed to stream object body for key {key}: {e}");
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                cancellable(retry_backoff(attempt), cancel).await?;
             }
         }
     }
 
-    anyhow::bail!("Failed to download objects with key {key} {MAX_RETRIES} times")
+    Err(RemoteAccessError(format!(
+        "Failed to download objects with key {key} {max_retries} times"
+    ))
+    .into())
 }
 
+/// Downloads `version_id` of `key`, or its current version, into `local_path`, retrying on errors.
+///
+/// This can't use `remote_storage`'s `download_to_file`: that only downloads the current version of
+/// an object, while snapshots also download layers which were deleted since their index was written.
 async fn download_object_to_file(
     s3_client: &Client,
     bucket_name: &str,
     key: &str,
     version_id: Option<&str>,
     local_path: &Utf8Path,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
     let tmp_path = Utf8PathBuf::from(format!("{local_path}.tmp"));
-    for _ in 0..MAX_RETRIES {
+    let max_retries = *MAX_RETRIES;
+    for attempt in 1..=max_retries {
         tokio::fs::remove_file(&tmp_path)
             .await
             .or_else(fs_ext::ignore_not_found)?;
@@ -434,25 +606,178 @@ async fn download_object_to_file(
             None => request,
         };
 
-        let response_stream = match request.send().await {
+        let response_stream = match cancellable(request.send(), cancel).await? {
             Ok(response) => response,
             Err(e) => {
                 error!(
                     "Failed to download object for key {key} version {}: {e:#}",
                     version_id.unwrap_or("")
                 );
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                cancellable(retry_backoff(attempt), cancel).await?;
                 continue;
             }
         };
 
         let mut read_stream = response_stream.body.into_async_read();
 
-        tokio::io::copy(&mut read_stream, &mut file).await?;
+        if let Err(e) = cancellable(tokio::io::copy(&mut read_stream, &mut file), cancel).await? {
+            error!("Failed to stream object body for key {key}: {e}");
+            cancellable(retry_backoff(attempt), cancel).await?;
+            continue;
+        }
 
         tokio::fs::rename(&tmp_path, local_path).await?;
         return Ok(());
     }
 
-    anyhow::bail!("Failed to download objects with key {key} {MAX_RETRIES} times")
+    Err(RemoteAccessError(format!(
+        "Failed to download objects with key {key} {max_retries} times"
+    ))
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use pageserver_api::shard::{ShardCount, ShardNumber};
+
+    use super::*;
+
+    const TENANT: &str = "0123456789abcdef0123456789abcdef";
+    const TIMELINE: &str = "fedcba9876543210fedcba9876543210";
+
+    fn unsharded() -> TenantShardId {
+        TenantShardId::unsharded(TENANT.parse().unwrap())
+    }
+
+    fn sharded() -> TenantShardId {
+        TenantShardId {
+            tenant_id: TENANT.parse::<TenantId>().unwrap(),
+            shard_number: ShardNumber(1),
+            shard_count: ShardCount::new(2),
+        }
+    }
+
+    fn root(prefix_in_bucket: &str) -> RootTarget {
+        RootTarget::Pageserver(S3Target {
+            bucket_name: "bucket".to_string(),
+            prefix_in_bucket: prefix_in_bucket.to_string(),
+            delimiter: "/".to_string(),
+        })
+    }
+
+    fn from_key(key: &str) -> Option<TenantShardTimelineId> {
+        TenantShardTimelineId::from_key(&RemotePath::from_string(key).unwrap())
+    }
+
+    #[test]
+    fn timeline_from_key() {
+        let timeline_id: TimelineId = TIMELINE.parse().unwrap();
+        for (tenant_shard_id, tenant_dir) in [
+            (unsharded(), TENANT.to_string()),
+            (sharded(), format!("{TENANT}-0102")),
+        ] {
+            let expected = Some(TenantShardTimelineId::new(tenant_shard_id, timeline_id));
+            let layer = format!("tenants/{tenant_dir}/timelines/{TIMELINE}/layer-00000001");
+            assert_eq!(from_key(&layer), expected);
+            assert_eq!(from_key(&format!("pageserver/v1/{layer}")), expected);
+            assert_eq!(
+                from_key(&format!("tenants/{tenant_dir}/timelines/{TIMELINE}")),
+                expected
+            );
+
+            // Keys of the tenant shard which are not within a timeline
+            assert_eq!(
+                from_key(&format!("pageserver/v1/tenants/{tenant_dir}/manifest.json")),
+                None
+            );
+            assert_eq!(
+                from_key(&format!("pageserver/v1/tenants/{tenant_dir}/timelines")),
+                None
+            );
+        }
+
+        // Malformed segments
+        assert_eq!(
+            from_key(&format!("tenants/not-a-tenant/timelines/{TIMELINE}/layer")),
+            None
+        );
+        assert_eq!(
+            from_key(&format!("tenants/{TENANT}/timelines/not-a-timeline/layer")),
+            None
+        );
+        assert_eq!(
+            from_key(&format!("tenants/{TENANT}/other/{TIMELINE}/layer")),
+            None
+        );
+    }
+
+    #[test]
+    fn tenant_shard_prefix() {
+        for prefix_in_bucket in ["", "pageserver/v1/"] {
+            let tenants = root(prefix_in_bucket).tenants_root();
+            let tenants_prefix = &tenants.prefix_in_bucket;
+
+            for (tenant_shard_id, tenant_dir) in [
+                (unsharded(), TENANT.to_string()),
+                (sharded(), format!("{TENANT}-0102")),
+            ] {
+                let prefix = format!("{tenants_prefix}{tenant_dir}/");
+                assert_eq!(
+                    parse_tenant_shard_prefix(&tenants, &prefix)
+                        .unwrap()
+                        .unwrap(),
+                    tenant_shard_id
+                );
+                let key = format!("{tenants_prefix}{tenant_dir}/timelines/{TIMELINE}/layer");
+                assert_eq!(
+                    parse_tenant_shard_prefix(&tenants, &key).unwrap().unwrap(),
+                    tenant_shard_id
+                );
+            }
+
+            // Below the tenants root, but not a tenant shard
+            let malformed = format!("{tenants_prefix}not-a-tenant/");
+            assert!(parse_tenant_shard_prefix(&tenants, &malformed)
+                .unwrap()
+                .is_err());
+            assert!(parse_tenant_shard_prefix(&tenants, tenants_prefix).is_none());
+        }
+
+        let tenants = root("pageserver/v1/").tenants_root();
+        assert!(parse_tenant_shard_prefix(&tenants, &format!("safekeeper/v1/{TENANT}/")).is_none());
+    }
+
+    #[test]
+    fn timeline_prefix() {
+        for prefix_in_bucket in ["", "pageserver/v1/"] {
+            for tenant_shard_id in [unsharded(), sharded()] {
+                let timelines = root(prefix_in_bucket).timelines_root(&tenant_shard_id);
+                let timelines_prefix = &timelines.prefix_in_bucket;
+                let timeline_id: TimelineId = TIMELINE.parse().unwrap();
+
+                let prefix = format!("{timelines_prefix}{TIMELINE}/");
+                assert_eq!(
+                    parse_timeline_prefix(&timelines, &prefix).unwrap().unwrap(),
+                    timeline_id
+                );
+                let key = format!("{timelines_prefix}{TIMELINE}/layer-00000001");
+                assert_eq!(
+                    parse_timeline_prefix(&timelines, &key).unwrap().unwrap(),
+                    timeline_id
+                );
+
+                let malformed = format!("{timelines_prefix}not-a-timeline/");
+                assert!(parse_timeline_prefix(&timelines, &malformed)
+                    .unwrap()
+                    .is_err());
+
+                // Keys of the tenant shard which are not below its timelines
+                let tenant_prefix = root(prefix_in_bucket)
+                    .tenant_root(&tenant_shard_id)
+                    .prefix_in_bucket;
+                let manifest = format!("{tenant_prefix}manifest.json");
+                assert!(parse_timeline_prefix(&timelines, &manifest).is_none());
+            }
+        }
+    }
 }
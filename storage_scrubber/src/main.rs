@@ -1,17 +1,28 @@
-use anyhow::bail;
+use std::process::ExitCode;
+use std::time::Duration;
+
 use camino::Utf8PathBuf;
 use pageserver_api::shard::TenantShardId;
-use storage_scrubber::garbage::{find_garbage, purge_garbage, PurgeMode};
+use storage_scrubber::abort_incomplete_uploads::abort_incomplete_uploads;
+use storage_scrubber::checks::verify_layer_references;
+use storage_scrubber::find_large_objects::{find_large_objects, OutputFormat};
+use storage_scrubber::garbage::{
+    find_garbage, purge_garbage, PurgeMode, DEFAULT_PURGE_CONCURRENCY,
+};
 use storage_scrubber::pageserver_physical_gc::GcMode;
-use storage_scrubber::scan_pageserver_metadata::scan_metadata;
+use storage_scrubber::scan_pageserver_metadata::{
+    scan_metadata, ScanScope, DEFAULT_SCAN_CONCURRENCY,
+};
 use storage_scrubber::tenant_snapshot::SnapshotDownloader;
 use storage_scrubber::{
-    init_logging, pageserver_physical_gc::pageserver_physical_gc,
+    init_logging, pageserver_physical_gc::pageserver_physical_gc, read_tenant_ids_file,
     scan_safekeeper_metadata::scan_safekeeper_metadata, BucketConfig, ConsoleConfig, NodeKind,
-    TraversingDepth,
+    RemoteAccessError, TraversingDepth,
 };
 
 use clap::{Parser, Subcommand};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
 use utils::id::TenantId;
 
 #[derive(Parser)]
@@ -34,12 +45,18 @@ enum Command {
         depth: TraversingDepth,
         #[arg(short, long, default_value_t = String::from("garbage.json"))]
         output_path: String,
+        /// Don't consider tenants or timelines garbage if any of their objects were modified more recently than this
+        #[arg(long = "min-age")]
+        min_age: Option<humantime::Duration>,
     },
     PurgeGarbage {
         #[arg(short, long)]
         input_path: String,
         #[arg(short, long, default_value_t = PurgeMode::DeletedOnly)]
         mode: PurgeMode,
+        /// How many DeleteObjects requests to issue in parallel
+        #[arg(long = "concurrency", short = 'j', default_value_t = DEFAULT_PURGE_CONCURRENCY)]
+        concurrency: usize,
     },
     #[command(verbatim_doc_comment)]
     ScanMetadata {
@@ -49,12 +66,24 @@ enum Command {
         json: bool,
         #[arg(long = "tenant-id", num_args = 0..)]
         tenant_ids: Vec<TenantShardId>,
+        /// For pageserver node_kind only, how many tenants to scan in parallel
+        #[arg(long = "concurrency", short = 'j', default_value_t = DEFAULT_SCAN_CONCURRENCY)]
+        concurrency: usize,
         #[arg(long, default_value = None)]
         /// For safekeeper node_kind only, points to db with debug dump
         dump_db_connstr: Option<String>,
         /// For safekeeper node_kind only, table in the db with debug dump
         #[arg(long, default_value = None)]
         dump_db_table: Option<String>,
+        /// For pageserver node_kind only, only scan tenants modified since the last complete scan
+        /// which found no errors, resuming from the cursor it left in the bucket. Scans all
+        /// tenants if there is no cursor yet.
+        #[arg(long, default_value_t = false, conflicts_with = "since")]
+        incremental: bool,
+        /// For pageserver node_kind only, only scan tenants with objects modified at or after
+        /// this time, e.g. `2024-06-01T00:00:00Z`
+        #[arg(long)]
+        since: Option<humantime::Timestamp>,
     },
     TenantSnapshot {
         #[arg(long = "tenant-id")]
@@ -63,22 +92,140 @@ enum Command {
         concurrency: usize,
         #[arg(short, long)]
         output_path: Utf8PathBuf,
+        /// Redownload all layers, instead of skipping those already present in the output path
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// Check that every layer referenced by an index_part.json exists, and report layers
+    /// which no index references.
+    VerifyLayerReferences {
+        #[arg(long = "tenant-id", num_args = 0..)]
+        tenant_ids: Vec<TenantShardId>,
+    },
+    FindLargeObjects {
+        #[arg(long = "min-size")]
+        min_size: u64,
+        #[arg(short, long, default_value_t = false)]
+        ignore_deltas: bool,
+        #[arg(long = "concurrency", short = 'j', default_value_t = 64)]
+        concurrency: usize,
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
     },
     PageserverPhysicalGc {
         #[arg(long = "tenant-id", num_args = 0..)]
         tenant_ids: Vec<TenantShardId>,
+        /// File with newline-separated tenant shard IDs to process, in addition to any `--tenant-id`
+        #[arg(long = "tenant-id-file")]
+        tenant_id_file: Option<Utf8PathBuf>,
+        /// Tenant shards to skip.  An unsharded tenant ID skips all of the tenant's shards.
+        #[arg(long = "exclude-tenant-id", num_args = 0..)]
+        exclude_tenant_ids: Vec<TenantShardId>,
         #[arg(long = "min-age")]
         min_age: humantime::Duration,
         #[arg(short, long, default_value_t = GcMode::IndicesOnly)]
         mode: GcMode,
     },
+    /// Abort multipart uploads which were started longer than `--min-age` ago and never
+    /// completed, e.g. by a pageserver which crashed mid-upload.
+    AbortIncompleteUploads {
+        #[arg(long = "min-age")]
+        min_age: humantime::Duration,
+    },
+}
+
+/// Errors describing the outcome of a scan, which get their own exit code.
+#[derive(Debug, thiserror::Error)]
+enum ScrubError {
+    #[error("Fatal scrub errors detected")]
+    Fatal,
+    #[error("Dangling layer references detected")]
+    DanglingReferences,
+    #[error("No timelines found in bucket {bucket} prefix {prefix}")]
+    Empty { bucket: String, prefix: String },
+}
+
+impl ScrubError {
+    fn empty(bucket_config: BucketConfig) -> Self {
+        Self::Empty {
+            bucket: bucket_config.bucket,
+            prefix: bucket_config
+                .prefix_in_bucket
+                .unwrap_or("<none>".to_string()),
+        }
+    }
+}
+
+/// Exit codes, so that automation can branch on the outcome without parsing logs.  `2` is
+/// left out, because clap uses it for invalid command line arguments.
+mod exit_code {
+    /// Any error not covered below, e.g. bad configuration.
+    pub const OTHER_ERROR: u8 = 1;
+    /// The scan completed and found corruption.
+    pub const FATAL_SCRUB_ERRORS: u8 = 3;
+    /// The scan completed but found nothing to scan, which likely means a misconfigured bucket or prefix.
+    pub const EMPTY_BUCKET: u8 = 4;
+    /// Remote storage could not be accessed, even after retries.
+    pub const REMOTE_ACCESS_ERROR: u8 = 5;
+}
+
+fn exit_code_for(e: &anyhow::Error) -> u8 {
+    for cause in e.chain() {
+        if let Some(e) = cause.downcast_ref::<ScrubError>() {
+            return match e {
+                ScrubError::Fatal | ScrubError::DanglingReferences => exit_code::FATAL_SCRUB_ERRORS,
+                ScrubError::Empty { .. } => exit_code::EMPTY_BUCKET,
+            };
+        }
+        if cause.is::<RemoteAccessError>() {
+            return exit_code::REMOTE_ACCESS_ERROR;
+        }
+    }
+    exit_code::OTHER_ERROR
+}
+
+/// After a shutdown signal, commands stop at their next cancellation check.  Work which doesn't check
+/// for cancellation, e.g. listings, is dropped once this much time has passed.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Cancel `cancel` on SIGINT or SIGTERM, so that in-flight downloads and retries stop promptly.
+fn cancel_on_shutdown_signal(cancel: CancellationToken) -> anyhow::Result<()> {
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        let signal = tokio::select! {
+            _ = sigint.recv() => "SIGINT",
+            _ = sigterm.recv() => "SIGTERM",
+        };
+        tracing::info!("Got signal {signal}, shutting down");
+        cancel.cancel();
+    });
+    Ok(())
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
+    let cancel = CancellationToken::new();
+    let result = tokio::select! {
+        res = run(cli, &cancel) => res,
+        _ = async {
+            cancel.cancelled().await;
+            tokio::time::sleep(SHUTDOWN_GRACE_PERIOD).await;
+        } => Err(anyhow::anyhow!("Shut down before the command completed")),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(exit_code_for(&e))
+        }
+    }
+}
 
+async fn run(cli: Cli, cancel: &CancellationToken) -> anyhow::Result<()> {
     let bucket_config = BucketConfig::from_env()?;
+    cancel_on_shutdown_signal(cancel.clone())?;
 
     let command_log_name = match &cli.command {
         Command::ScanMetadata { .. } => "scan",
@@ -86,6 +233,9 @@ async fn main() -> anyhow::Result<()> {
         Command::PurgeGarbage { .. } => "purge-garbage",
         Command::TenantSnapshot { .. } => "tenant-snapshot",
         Command::PageserverPhysicalGc { .. } => "pageserver-physical-gc",
+        Command::VerifyLayerReferences { .. } => "verify-layer-references",
+        Command::FindLargeObjects { .. } => "find-large-objects",
+        Command::AbortIncompleteUploads { .. } => "abort-incomplete-uploads",
     };
     let _guard = init_logging(&format!(
         "{}_{}_{}_{}.log",
@@ -100,10 +250,18 @@ async fn main() -> anyhow::Result<()> {
             json,
             tenant_ids,
             node_kind,
+            concurrency,
             dump_db_connstr,
             dump_db_table,
+            incremental,
+            since,
         } => {
             if let NodeKind::Safekeeper = node_kind {
+                if incremental || since.is_some() {
+                    anyhow::bail!(
+                        "--incremental and --since are only supported for pageserver scans"
+                    );
+                }
                 let dump_db_connstr =
                     dump_db_connstr.ok_or(anyhow::anyhow!("dump_db_connstr not specified"))?;
                 let dump_db_table =
@@ -114,6 +272,7 @@ async fn main() -> anyhow::Result<()> {
                     tenant_ids.iter().map(|tshid| tshid.tenant_id).collect(),
                     dump_db_connstr,
                     dump_db_table,
+                    cancel,
                 )
                 .await?;
                 if json {
@@ -122,23 +281,30 @@ async fn main() -> anyhow::Result<()> {
                     println!("{}", summary.summary_string());
                 }
                 if summary.is_fatal() {
-                    bail!("Fatal scrub errors detected");
+                    return Err(ScrubError::Fatal.into());
                 }
                 if summary.is_empty() {
                     // Strictly speaking an empty bucket is a valid bucket, but if someone ran the
                     // scrubber they were likely expecting to scan something, and if we see no timelines
                     // at all then it's likely due to some configuration issues like a bad prefix
-                    bail!(
-                        "No timelines found in bucket {} prefix {}",
-                        bucket_config.bucket,
-                        bucket_config
-                            .prefix_in_bucket
-                            .unwrap_or("<none>".to_string())
-                    );
+                    return Err(ScrubError::empty(bucket_config).into());
                 }
                 Ok(())
             } else {
-                match scan_metadata(bucket_config.clone(), tenant_ids).await {
+                let scope = match (incremental, since) {
+                    (true, _) => ScanScope::Incremental,
+                    (false, Some(since)) => ScanScope::ModifiedSince(since.into()),
+                    (false, None) => ScanScope::Full,
+                };
+                match scan_metadata(
+                    bucket_config.clone(),
+                    tenant_ids,
+                    concurrency,
+                    scope,
+                    cancel,
+                )
+                .await
+                {
                     Err(e) => {
                         tracing::error!("Failed: {e}");
                         Err(e)
@@ -150,18 +316,12 @@ async fn main() -> anyhow::Result<()> {
                             println!("{}", summary.summary_string());
                         }
                         if summary.is_fatal() {
-                            Err(anyhow::anyhow!("Fatal scrub errors detected"))
+                            Err(ScrubError::Fatal.into())
                         } else if summary.is_empty() {
                             // Strictly speaking an empty bucket is a valid bucket, but if someone ran the
                             // scrubber they were likely expecting to scan something, and if we see no timelines
                             // at all then it's likely due to some configuration issues like a bad prefix
-                            Err(anyhow::anyhow!(
-                                "No timelines found in bucket {} prefix {}",
-                                bucket_config.bucket,
-                                bucket_config
-                                    .prefix_in_bucket
-                                    .unwrap_or("<none>".to_string())
-                            ))
+                            Err(ScrubError::empty(bucket_config).into())
                         } else {
                             Ok(())
                         }
@@ -173,30 +333,105 @@ async fn main() -> anyhow::Result<()> {
             node_kind,
             depth,
             output_path,
+            min_age,
         } => {
             let console_config = ConsoleConfig::from_env()?;
-            find_garbage(bucket_config, console_config, depth, node_kind, output_path).await
+            find_garbage(
+                bucket_config,
+                console_config,
+                depth,
+                node_kind,
+                output_path,
+                min_age.map(Into::into),
+                cancel,
+            )
+            .await
         }
-        Command::PurgeGarbage { input_path, mode } => {
-            purge_garbage(input_path, mode, !cli.delete).await
+        Command::PurgeGarbage {
+            input_path,
+            mode,
+            concurrency,
+        } => {
+            purge_garbage(
+                bucket_config,
+                input_path,
+                mode,
+                concurrency,
+                !cli.delete,
+                cancel,
+            )
+            .await
         }
         Command::TenantSnapshot {
             tenant_id,
             output_path,
             concurrency,
+            force,
         } => {
-            let downloader =
-                SnapshotDownloader::new(bucket_config, tenant_id, output_path, concurrency)?;
+            let downloader = SnapshotDownloader::new(
+                bucket_config,
+                tenant_id,
+                output_path,
+                concurrency,
+                force,
+                cancel.clone(),
+            )?;
             downloader.download().await
         }
         Command::PageserverPhysicalGc {
-            tenant_ids,
+            mut tenant_ids,
+            tenant_id_file,
+            exclude_tenant_ids,
             min_age,
             mode,
         } => {
-            let summary =
-                pageserver_physical_gc(bucket_config, tenant_ids, min_age.into(), mode).await?;
+            if let Some(tenant_id_file) = tenant_id_file {
+                let from_file = read_tenant_ids_file(&tenant_id_file)?;
+                if from_file.is_empty() {
+                    // An empty list would mean "all tenants", which is unlikely to be what was meant
+                    anyhow::bail!("No tenant IDs found in {tenant_id_file}");
+                }
+                tenant_ids.extend(from_file);
+            }
+            let summary = pageserver_physical_gc(
+                bucket_config,
+                tenant_ids,
+                exclude_tenant_ids,
+                min_age.into(),
+                mode,
+                cancel,
+            )
+            .await?;
+            println!("{}", serde_json::to_string(&summary).unwrap());
+            Ok(())
+        }
+        Command::FindLargeObjects {
+            min_size,
+            ignore_deltas,
+            concurrency,
+            format,
+        } => {
+            find_large_objects(
+                bucket_config,
+                min_size,
+                ignore_deltas,
+                concurrency,
+                format,
+                cancel,
+            )
+            .await
+        }
+        Command::VerifyLayerReferences { tenant_ids } => {
+            let summary = verify_layer_references(bucket_config, tenant_ids, cancel).await?;
             println!("{}", serde_json::to_string(&summary).unwrap());
+            if summary.is_fatal() {
+                return Err(ScrubError::DanglingReferences.into());
+            }
+            Ok(())
+        }
+        Command::AbortIncompleteUploads { min_age } => {
+            let aborted = abort_incomplete_uploads(bucket_config, min_age.into(), cancel).await?;
+            println!("Aborted {aborted} incomplete uploads");
             Ok(())
         }
     }
@@ -1,9 +1,15 @@
-use anyhow::Context;
 use async_stream::{stream, try_stream};
-use aws_sdk_s3::{types::ObjectIdentifier, Client};
+use aws_sdk_s3::{
+    types::{Object, ObjectIdentifier},
+    Client,
+};
 use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
 
-use crate::{list_objects_with_retries, RootTarget, S3Target, TenantShardTimelineId};
+use crate::{
+    list_objects_with_retries, parse_tenant_shard_prefix, parse_timeline_prefix, RootTarget,
+    S3Target, TenantShardTimelineId,
+};
 use pageserver_api::shard::TenantShardId;
 use utils::id::{TenantId, TimelineId};
 
@@ -11,27 +17,20 @@ use utils::id::{TenantId, TimelineId};
 pub fn stream_tenants<'a>(
     s3_client: &'a Client,
     target: &'a RootTarget,
+    cancel: &'a CancellationToken,
 ) -> impl Stream<Item = anyhow::Result<TenantShardId>> + 'a {
     try_stream! {
         let mut continuation_token = None;
         let tenants_target = target.tenants_root();
         loop {
             let fetch_response =
-                list_objects_with_retries(s3_client, &tenants_target, continuation_token.clone()).await?;
+                list_objects_with_retries(s3_client, &tenants_target, continuation_token.clone(), cancel).await?;
 
             let new_entry_ids = fetch_response
                 .common_prefixes()
                 .iter()
                 .filter_map(|prefix| prefix.prefix())
-                .filter_map(|prefix| -> Option<&str> {
-                    prefix
-                        .strip_prefix(&tenants_target.prefix_in_bucket)?
-                        .strip_suffix('/')
-                }).map(|entry_id_str| {
-                entry_id_str
-                    .parse()
-                    .with_context(|| format!("Incorrect entry id str: {entry_id_str}"))
-            });
+                .filter_map(|prefix| parse_tenant_shard_prefix(&tenants_target, prefix));
 
             for i in new_entry_ids {
                 yield i?;
@@ -49,6 +48,7 @@ pub async fn stream_tenant_shards<'a>(
     s3_client: &'a Client,
     target: &'a RootTarget,
     tenant_id: TenantId,
+    cancel: &'a CancellationToken,
 ) -> anyhow::Result<impl Stream<Item = Result<TenantShardId, anyhow::Error>> + 'a> {
     let mut tenant_shard_ids: Vec<Result<TenantShardId, anyhow::Error>> = Vec::new();
     let mut continuation_token = None;
@@ -56,8 +56,13 @@ pub async fn stream_tenant_shards<'a>(
 
     loop {
         tracing::info!("Listing in {}", shards_target.prefix_in_bucket);
-        let fetch_response =
-            list_objects_with_retries(s3_client, &shards_target, continuation_token.clone()).await;
+        let fetch_response = list_objects_with_retries(
+            s3_client,
+            &shards_target,
+            continuation_token.clone(),
+            cancel,
+        )
+        .await;
         let fetch_response = match fetch_response {
             Err(e) => {
                 tenant_shard_ids.push(Err(e));
@@ -66,22 +71,12 @@ pub async fn stream_tenant_shards<'a>(
             Ok(r) => r,
         };
 
+        let tenants_target = target.tenants_root();
         let new_entry_ids = fetch_response
             .common_prefixes()
             .iter()
             .filter_map(|prefix| prefix.prefix())
-            .filter_map(|prefix| -> Option<&str> {
-                prefix
-                    .strip_prefix(&target.tenants_root().prefix_in_bucket)?
-                    .strip_suffix('/')
-            })
-            .map(|entry_id_str| {
-                let first_part = entry_id_str.split('/').next().unwrap();
-
-                first_part
-                    .parse::<TenantShardId>()
-                    .with_context(|| format!("Incorrect entry id str: {first_part}"))
-            });
+            .filter_map(|prefix| parse_tenant_shard_prefix(&tenants_target, prefix));
 
         for i in new_entry_ids {
             tenant_shard_ids.push(i);
@@ -108,6 +103,7 @@ pub async fn stream_tenant_timelines<'a>(
     s3_client: &'a Client,
     target: &'a RootTarget,
     tenant: TenantShardId,
+    cancel: &'a CancellationToken,
 ) -> anyhow::Result<impl Stream<Item = Result<TenantShardTimelineId, anyhow::Error>> + 'a> {
     let mut timeline_ids: Vec<Result<TimelineId, anyhow::Error>> = Vec::new();
     let mut continuation_token = None;
@@ -115,9 +111,13 @@ pub async fn stream_tenant_timelines<'a>(
 
     loop {
         tracing::debug!("Listing in {}", tenant);
-        let fetch_response =
-            list_objects_with_retries(s3_client, &timelines_target, continuation_token.clone())
-                .await;
+        let fetch_response = list_objects_with_retries(
+            s3_client,
+            &timelines_target,
+            continuation_token.clone(),
+            cancel,
+        )
+        .await;
         let fetch_response = match fetch_response {
             Err(e) => {
                 timeline_ids.push(Err(e));
@@ -130,16 +130,7 @@ pub async fn stream_tenant_timelines<'a>(
             .common_prefixes()
             .iter()
             .filter_map(|prefix| prefix.prefix())
-            .filter_map(|prefix| -> Option<&str> {
-                prefix
-                    .strip_prefix(&timelines_target.prefix_in_bucket)?
-                    .strip_suffix('/')
-            })
-            .map(|entry_id_str| {
-                entry_id_str
-                    .parse::<TimelineId>()
-                    .with_context(|| format!("Incorrect entry id str: {entry_id_str}"))
-            });
+            .filter_map(|prefix| parse_timeline_prefix(&timelines_target, prefix));
 
         for i in new_entry_ids {
             timeline_ids.push(i);
@@ -163,12 +154,13 @@ pub async fn stream_tenant_timelines<'a>(
 pub(crate) fn stream_listing<'a>(
     s3_client: &'a Client,
     target: &'a S3Target,
+    cancel: &'a CancellationToken,
 ) -> impl Stream<Item = anyhow::Result<ObjectIdentifier>> + 'a {
     try_stream! {
         let mut continuation_token = None;
         loop {
             let fetch_response =
-                list_objects_with_retries(s3_client, target, continuation_token.clone()).await?;
+                list_objects_with_retries(s3_client, target, continuation_token.clone(), cancel).await?;
 
             if target.delimiter.is_empty() {
                 for object_key in fetch_response.contents().iter().filter_map(|object| object.key())
@@ -190,3 +182,28 @@ pub(crate) fn stream_listing<'a>(
         }
     }
 }
+
+/// Like [`stream_listing`] without a delimiter, but yields the full listing entries, including
+/// e.g. their modification time.
+pub(crate) fn stream_objects<'a>(
+    s3_client: &'a Client,
+    target: &'a S3Target,
+    cancel: &'a CancellationToken,
+) -> impl Stream<Item = anyhow::Result<Object>> + 'a {
+    try_stream! {
+        let mut continuation_token = None;
+        loop {
+            let fetch_response =
+                list_objects_with_retries(s3_client, target, continuation_token.clone(), cancel).await?;
+
+            for object in fetch_response.contents() {
+                yield object.clone();
+            }
+
+            match fetch_response.next_continuation_token {
+                Some(new_token) => continuation_token = Some(new_token),
+                None => break,
+            }
+        }
+    }
+}
@@ -10,6 +10,7 @@ use pageserver::tenant::IndexPart;
 use pageserver_api::shard::TenantShardId;
 use remote_storage::RemotePath;
 use serde::Serialize;
+use tokio_util::sync::CancellationToken;
 use tracing::{info_span, Instrument};
 use utils::generation::Generation;
 
@@ -157,23 +158,39 @@ async fn maybe_delete_index(
 pub async fn pageserver_physical_gc(
     bucket_config: BucketConfig,
     tenant_ids: Vec<TenantShardId>,
+    exclude_tenant_ids: Vec<TenantShardId>,
     min_age: Duration,
     mode: GcMode,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<GcSummary> {
     let (s3_client, target) = init_remote(bucket_config.clone(), NodeKind::Pageserver)?;
 
     let tenants = if tenant_ids.is_empty() {
-        futures::future::Either::Left(stream_tenants(&s3_client, &target))
+        futures::future::Either::Left(stream_tenants(&s3_client, &target, cancel))
     } else {
         futures::future::Either::Right(futures::stream::iter(tenant_ids.into_iter().map(Ok)))
     };
 
+    // An unsharded ID in the exclusions skips all shards of that tenant.
+    let is_excluded = |t: &TenantShardId| {
+        exclude_tenant_ids
+            .iter()
+            .any(|e| e == t || (e.is_unsharded() && e.tenant_id == t.tenant_id))
+    };
+    let tenants = tenants.try_filter(|t| {
+        let excluded = is_excluded(t);
+        if excluded {
+            tracing::info!("Skipping excluded tenant shard {t}");
+        }
+        std::future::ready(!excluded)
+    });
+
     // How many tenants to process in parallel.  We need to be mindful of pageservers
     // accessing the same per tenant prefixes, so use a lower setting than pageservers.
     const CONCURRENCY: usize = 32;
 
     // Generate a stream of TenantTimelineId
-    let timelines = tenants.map_ok(|t| stream_tenant_timelines(&s3_client, &target, t));
+    let timelines = tenants.map_ok(|t| stream_tenant_timelines(&s3_client, &target, t, cancel));
     let timelines = timelines.try_buffered(CONCURRENCY);
     let timelines = timelines.try_flatten();
 
@@ -185,9 +202,10 @@ pub async fn pageserver_physical_gc(
         target: &RootTarget,
         mode: GcMode,
         ttid: TenantShardTimelineId,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<GcSummary> {
         let mut summary = GcSummary::default();
-        let data = list_timeline_blobs(s3_client, ttid, target).await?;
+        let data = list_timeline_blobs(s3_client, ttid, target, cancel).await?;
 
         let (latest_gen, candidates) = match &data.blob_data {
             BlobDataParseResult::Parsed {
@@ -222,8 +240,17 @@ pub async fn pageserver_physical_gc(
 
         Ok(summary)
     }
-    let timelines = timelines
-        .map_ok(|ttid| gc_timeline(&s3_client, &bucket_config, &min_age, &target, mode, ttid));
+    let timelines = timelines.map_ok(|ttid| {
+        gc_timeline(
+            &s3_client,
+            &bucket_config,
+            &min_age,
+            &target,
+            mode,
+            ttid,
+            cancel,
+        )
+    });
     let mut timelines = std::pin::pin!(timelines.try_buffered(CONCURRENCY));
 
     let mut summary = GcSummary::default();
@@ -1,18 +1,24 @@
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
 
 use crate::checks::{
     branch_cleanup_and_check_errors, list_timeline_blobs, BlobDataParseResult, S3TimelineBlobData,
     TenantObjectListing, TimelineAnalysis,
 };
-use crate::metadata_stream::{stream_tenant_timelines, stream_tenants};
+use crate::metadata_stream::{stream_objects, stream_tenant_timelines, stream_tenants};
 use crate::{init_remote, BucketConfig, NodeKind, RootTarget, TenantShardTimelineId};
+use anyhow::Context;
+use async_stream::try_stream;
+use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::Client;
-use futures_util::{StreamExt, TryStreamExt};
+use futures_util::{Stream, StreamExt, TryStreamExt};
 use histogram::Histogram;
 use pageserver::tenant::remote_timeline_client::remote_layer_path;
 use pageserver::tenant::IndexPart;
 use pageserver_api::shard::TenantShardId;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
 use utils::id::TenantId;
 
 #[derive(Serialize)]
@@ -23,7 +29,11 @@ pub struct MetadataSummary {
     with_errors: HashSet<TenantShardTimelineId>,
     with_warnings: HashSet<TenantShardTimelineId>,
     with_orphans: HashSet<TenantShardTimelineId>,
+    /// Timelines referencing layers whose size in remote storage differs from their index
+    with_size_mismatches: HashSet<TenantShardTimelineId>,
     indices_by_version: HashMap<usize, usize>,
+    /// Tenants an incremental scan skipped, because none of their objects were modified since
+    unmodified_tenant_count: usize,
 
     layer_count: MinMaxHisto,
     timeline_size_bytes: MinMaxHisto,
@@ -95,7 +105,9 @@ impl MetadataSummary {
             with_errors: HashSet::new(),
             with_warnings: HashSet::new(),
             with_orphans: HashSet::new(),
+            with_size_mismatches: HashSet::new(),
             indices_by_version: HashMap::new(),
+            unmodified_tenant_count: 0,
             layer_count: MinMaxHisto::new(),
             timeline_size_bytes: MinMaxHisto::new(),
             layer_size_bytes: MinMaxHisto::new(),
@@ -146,6 +158,10 @@ impl MetadataSummary {
         if !analysis.warnings.is_empty() {
             self.with_warnings.insert(*id);
         }
+
+        if !analysis.size_mismatches.is_empty() {
+            self.with_size_mismatches.insert(*id);
+        }
     }
 
     fn notify_timeline_orphan(&mut self, ttid: &TenantShardTimelineId) {
@@ -163,22 +179,26 @@ impl MetadataSummary {
 
         format!(
             "Tenants: {}
+Unmodified tenants skipped: {}
 Timelines: {}
 Timeline-shards: {}
 With errors: {}
 With warnings: {}
 With orphan layers: {}
+With layer size mismatches: {}
 Index versions: {version_summary}
 Timeline size bytes: {}
 Layer size bytes: {}
 Timeline layer count: {}
 ",
             self.tenant_count,
+            self.unmodified_tenant_count,
             self.timeline_count,
             self.timeline_shard_count,
             self.with_errors.len(),
             self.with_warnings.len(),
             self.with_orphans.len(),
+            self.with_size_mismatches.len(),
             self.timeline_size_bytes.oneline(),
             self.layer_size_bytes.oneline(),
             self.layer_count.oneline(),
@@ -186,34 +206,236 @@ Timeline layer count: {}
     }
 
     pub fn is_fatal(&self) -> bool {
-        !self.with_errors.is_empty()
+        !self.with_errors.is_empty() || !self.with_size_mismatches.is_empty()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.timeline_shard_count == 0
+        self.timeline_shard_count == 0 && self.unmodified_tenant_count == 0
     }
 }
 
+/// Which tenants [`scan_metadata`] scans.
+#[derive(Debug, Clone, Copy)]
+pub enum ScanScope {
+    /// Every tenant.
+    Full,
+    /// Only tenants with an object modified at or after the given time.
+    ModifiedSince(SystemTime),
+    /// Only tenants modified since the last complete scan which found no errors, as recorded in
+    /// the scan cursor in the bucket. Every tenant is scanned if there is no cursor yet.
+    Incremental,
+}
+
+/// Stored at [`RootTarget::scan_cursor_key`] by complete scans which found no errors, for the
+/// next [`ScanScope::Incremental`] scan to resume from.
+///
+/// Scans which found errors don't move the cursor, so that the next incremental scan looks at
+/// the same tenants again, and keeps reporting the errors until they are fixed.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ScanCursor {
+    /// When the scan which wrote the cursor started. Everything modified before was scanned.
+    started_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Incremental scans look at objects modified this long before the previous scan started, so
+/// that objects whose `LastModified` lags behind our clock are not missed.
+const SCAN_CURSOR_CLOCK_SKEW_MARGIN: Duration = Duration::from_secs(10 * 60);
+
+async fn read_scan_cursor(
+    s3_client: &Client,
+    target: &RootTarget,
+) -> anyhow::Result<Option<ScanCursor>> {
+    let key = target
+        .scan_cursor_key()
+        .context("only pageserver scans have a scan cursor")?;
+    let response = s3_client
+        .get_object()
+        .bucket(target.bucket_name())
+        .key(&key)
+        .send()
+        .await;
+    let response = match response {
+        Ok(response) => response,
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+        Err(e) => {
+            return Err(anyhow::Error::new(e).context(format!("downloading scan cursor {key}")))
+        }
+    };
+    let body = response
+        .body
+        .collect()
+        .await
+        .with_context(|| format!("downloading scan cursor {key}"))?;
+    let cursor = serde_json::from_slice(&body.into_bytes())
+        .with_context(|| format!("parsing scan cursor {key}"))?;
+    Ok(Some(cursor))
+}
+
+async fn write_scan_cursor(
+    s3_client: &Client,
+    target: &RootTarget,
+    cursor: &ScanCursor,
+) -> anyhow::Result<()> {
+    let key = target
+        .scan_cursor_key()
+        .context("only pageserver scans have a scan cursor")?;
+    s3_client
+        .put_object()
+        .bucket(target.bucket_name())
+        .key(&key)
+        .body(ByteStream::from(serde_json::to_vec_pretty(cursor)?))
+        .send()
+        .await
+        .with_context(|| format!("uploading scan cursor {key}"))?;
+    tracing::info!("Wrote scan cursor {key}: {cursor:?}");
+    Ok(())
+}
+
+/// Returns true if any object of any shard of the tenant was modified at or after `since`.
+async fn tenant_modified_since(
+    s3_client: &Client,
+    target: &RootTarget,
+    tenant_id: TenantId,
+    since: SystemTime,
+    cancel: &CancellationToken,
+) -> anyhow::Result<bool> {
+    let mut prefix = target.tenant_shards_prefix(&tenant_id);
+    // List the objects rather than the common prefixes
+    prefix.delimiter = String::new();
+
+    let mut objects = std::pin::pin!(stream_objects(s3_client, &prefix, cancel));
+    while let Some(object) = objects.next().await {
+        let object = object?;
+        // An object without a usable timestamp counts as modified, to be on the safe side.
+        let last_modified = object
+            .last_modified()
+            .and_then(|t| SystemTime::try_from(*t).ok());
+        if !matches!(last_modified, Some(t) if t < since) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Groups the shards of each tenant, which `tenants` must yield next to each other, as listings
+/// do.
+fn group_tenant_shards<'a>(
+    tenants: impl Stream<Item = anyhow::Result<TenantShardId>> + 'a,
+) -> impl Stream<Item = anyhow::Result<Vec<TenantShardId>>> + 'a {
+    try_stream! {
+        let mut tenants = std::pin::pin!(tenants);
+        let mut shards: Vec<TenantShardId> = Vec::new();
+        while let Some(shard) = tenants.next().await {
+            let shard = shard?;
+            if shards.first().is_some_and(|first| first.tenant_id != shard.tenant_id) {
+                yield std::mem::take(&mut shards);
+            }
+            shards.push(shard);
+        }
+        if !shards.is_empty() {
+            yield shards;
+        }
+    }
+}
+
+/// Narrows `tenants` down to the shards of tenants with an object modified at or after `since`,
+/// counting the skipped tenants in `unmodified`. All shards of a tenant are kept or skipped
+/// together, because the shards of a tenant are analyzed together.
+fn modified_tenant_shards<'a>(
+    s3_client: &'a Client,
+    target: &'a RootTarget,
+    tenants: impl Stream<Item = anyhow::Result<TenantShardId>> + 'a,
+    since: SystemTime,
+    concurrency: usize,
+    unmodified: &'a AtomicUsize,
+    cancel: &'a CancellationToken,
+) -> impl Stream<Item = anyhow::Result<TenantShardId>> + 'a {
+    group_tenant_shards(tenants)
+        .map_ok(move |shards| async move {
+            let tenant_id = shards[0].tenant_id;
+            if tenant_modified_since(s3_client, target, tenant_id, since, cancel).await? {
+                Ok(shards)
+            } else {
+                tracing::debug!("Skipping unmodified tenant {tenant_id}");
+                unmodified.fetch_add(1, Ordering::Relaxed);
+                Ok(Vec::new())
+            }
+        })
+        .try_buffered(concurrency)
+        .map_ok(|shards| futures::stream::iter(shards.into_iter().map(Ok)))
+        .try_flatten()
+}
+
+/// How many tenants to process in parallel by default.  We need to be mindful of pageservers
+/// accessing the same per tenant prefixes, so use a lower setting than pageservers.
+pub const DEFAULT_SCAN_CONCURRENCY: usize = 32;
+
 /// Scan the pageserver metadata in an S3 bucket, reporting errors and statistics.
+///
+/// Up to `concurrency` tenants and timeline shards are listed at once. Results are still
+/// consumed in key order, so that all shards of a tenant are analyzed together.
+///
+/// `scope` limits the scan to recently modified tenants. A complete scan of all tenants which
+/// finds no errors records where the next [`ScanScope::Incremental`] scan starts.
 pub async fn scan_metadata(
     bucket_config: BucketConfig,
     tenant_ids: Vec<TenantShardId>,
+    concurrency: usize,
+    scope: ScanScope,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<MetadataSummary> {
     let (s3_client, target) = init_remote(bucket_config, NodeKind::Pageserver)?;
 
+    let started_at = SystemTime::now();
+    // Only scans which look at every tenant modified since the cursor may move it forward
+    let write_cursor =
+        tenant_ids.is_empty() && matches!(scope, ScanScope::Full | ScanScope::Incremental);
+    let modified_since = match scope {
+        ScanScope::Full => None,
+        ScanScope::ModifiedSince(since) => Some(since),
+        ScanScope::Incremental => match read_scan_cursor(&s3_client, &target).await? {
+            Some(cursor) => {
+                tracing::info!(
+                    "Scanning tenants modified since the scan started at {}",
+                    cursor.started_at
+                );
+                let since = SystemTime::from(cursor.started_at);
+                Some(
+                    since
+                        .checked_sub(SCAN_CURSOR_CLOCK_SKEW_MARGIN)
+                        .unwrap_or(SystemTime::UNIX_EPOCH),
+                )
+            }
+            None => {
+                tracing::info!("No scan cursor found, scanning all tenants");
+                None
+            }
+        },
+    };
+
     let tenants = if tenant_ids.is_empty() {
-        futures::future::Either::Left(stream_tenants(&s3_client, &target))
+        futures::future::Either::Left(stream_tenants(&s3_client, &target, cancel))
     } else {
         futures::future::Either::Right(futures::stream::iter(tenant_ids.into_iter().map(Ok)))
     };
 
-    // How many tenants to process in parallel.  We need to be mindful of pageservers
-    // accessing the same per tenant prefixes, so use a lower setting than pageservers.
-    const CONCURRENCY: usize = 32;
+    let unmodified_tenants = AtomicUsize::new(0);
+    let tenants = match modified_since {
+        Some(since) => futures::future::Either::Left(modified_tenant_shards(
+            &s3_client,
+            &target,
+            tenants,
+            since,
+            concurrency,
+            &unmodified_tenants,
+            cancel,
+        )),
+        None => futures::future::Either::Right(tenants),
+    };
 
     // Generate a stream of TenantTimelineId
-    let timelines = tenants.map_ok(|t| stream_tenant_timelines(&s3_client, &target, t));
-    let timelines = timelines.try_buffered(CONCURRENCY);
+    let timelines = tenants.map_ok(|t| stream_tenant_timelines(&s3_client, &target, t, cancel));
+    let timelines = timelines.try_buffered(concurrency);
     let timelines = timelines.try_flatten();
 
     // Generate a stream of S3TimelineBlobData
@@ -221,12 +443,13 @@ pub async fn scan_metadata(
         s3_client: &Client,
         target: &RootTarget,
         ttid: TenantShardTimelineId,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<(TenantShardTimelineId, S3TimelineBlobData)> {
-        let data = list_timeline_blobs(s3_client, ttid, target).await?;
+        let data = list_timeline_blobs(s3_client, ttid, target, cancel).await?;
         Ok((ttid, data))
     }
-    let timelines = timelines.map_ok(|ttid| report_on_timeline(&s3_client, &target, ttid));
-    let mut timelines = std::pin::pin!(timelines.try_buffered(CONCURRENCY));
+    let timelines = timelines.map_ok(|ttid| report_on_timeline(&s3_client, &target, ttid, cancel));
+    let mut timelines = std::pin::pin!(timelines.try_buffered(concurrency));
 
     // We must gather all the TenantShardTimelineId->S3TimelineBlobData for each tenant, because different
     // shards in the same tenant might refer to one anothers' keys if a shard split has happened.
@@ -345,5 +568,109 @@ pub async fn scan_metadata(
         );
     }
 
+    summary.unmodified_tenant_count = unmodified_tenants.load(Ordering::Relaxed);
+
+    if write_cursor && !summary.is_fatal() {
+        let cursor = ScanCursor {
+            started_at: started_at.into(),
+        };
+        // The scan results are valid all the same, e.g. with read-only credentials
+        if let Err(e) = write_scan_cursor(&s3_client, &target, &cursor).await {
+            tracing::warn!("Failed to write the scan cursor: {e:#}");
+        }
+    }
+
     Ok(summary)
 }
+
+#[cfg(test)]
+mod tests {
+    use pageserver_api::shard::{ShardCount, ShardNumber};
+
+    use super::*;
+
+    fn shard(tenant_id: TenantId, number: u8, count: u8) -> TenantShardId {
+        TenantShardId {
+            tenant_id,
+            shard_number: ShardNumber(number),
+            shard_count: ShardCount::new(count),
+        }
+    }
+
+    #[tokio::test]
+    async fn group_tenant_shards_groups_adjacent_shards() {
+        let unsharded = TenantId::from_array([1; 16]);
+        let sharded = TenantId::from_array([2; 16]);
+        let tenants = vec![
+            TenantShardId::unsharded(unsharded),
+            shard(sharded, 0, 2),
+            shard(sharded, 1, 2),
+        ];
+
+        let groups: Vec<_> =
+            group_tenant_shards(futures::stream::iter(tenants.into_iter().map(Ok)))
+                .try_collect()
+                .await
+                .unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                vec![TenantShardId::unsharded(unsharded)],
+                vec![shard(sharded, 0, 2), shard(sharded, 1, 2)],
+            ]
+        );
+
+        let groups: Vec<_> = group_tenant_shards(futures::stream::empty())
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(groups.is_empty());
+    }
+
+    #[tokio::test]
+    async fn group_tenant_shards_propagates_errors() {
+        let tenant = TenantId::from_array([1; 16]);
+        let tenants = vec![
+            Ok(shard(tenant, 0, 2)),
+            Err(anyhow::anyhow!("listing failed")),
+            Ok(shard(tenant, 1, 2)),
+        ];
+
+        let result: anyhow::Result<Vec<_>> = group_tenant_shards(futures::stream::iter(tenants))
+            .try_collect()
+            .await;
+        assert_eq!(result.unwrap_err().to_string(), "listing failed");
+    }
+
+    #[test]
+    fn scan_cursor_round_trip() {
+        let cursor = ScanCursor {
+            started_at: "2024-06-01T12:00:00Z".parse().unwrap(),
+        };
+        let json = serde_json::to_vec_pretty(&cursor).unwrap();
+        assert_eq!(serde_json::from_slice::<ScanCursor>(&json).unwrap(), cursor);
+
+        // Cursors written before `last_tenant_id` was dropped still parse
+        let old = r#"{"started_at":"2024-06-01T12:00:00Z","last_tenant_id":"01010101010101010101010101010101"}"#;
+        assert_eq!(serde_json::from_str::<ScanCursor>(old).unwrap(), cursor);
+    }
+
+    #[test]
+    fn scan_cursor_key() {
+        let target = |prefix_in_bucket: &str| crate::S3Target {
+            bucket_name: "bucket".to_string(),
+            prefix_in_bucket: prefix_in_bucket.to_string(),
+            delimiter: "/".to_string(),
+        };
+        assert_eq!(
+            RootTarget::Pageserver(target("pageserver/v1/tenants/"))
+                .scan_cursor_key()
+                .as_deref(),
+            Some("pageserver/v1/tenants/scrubber/scan_metadata_cursor.json")
+        );
+        assert_eq!(
+            RootTarget::Safekeeper(target("safekeeper/v1/wal/")).scan_cursor_key(),
+            None
+        );
+    }
+}
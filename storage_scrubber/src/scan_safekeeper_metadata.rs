@@ -7,6 +7,7 @@ use pageserver_api::shard::TenantShardId;
 use postgres_ffi::{XLogFileName, PG_TLI};
 use serde::Serialize;
 use tokio_postgres::types::PgLsn;
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, trace};
 use utils::{
     id::{TenantId, TenantTimelineId, TimelineId},
@@ -66,6 +67,7 @@ pub async fn scan_safekeeper_metadata(
     tenant_ids: Vec<TenantId>,
     dump_db_connstr: String,
     dump_db_table: String,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<MetadataSummary> {
     info!(
         "checking bucket {}, region {}, dump_db_table {}",
@@ -125,6 +127,7 @@ pub async fn scan_safekeeper_metadata(
             ttid,
             timeline_start_lsn,
             backup_lsn,
+            cancel,
         )
     });
     // Run multiple check_timeline's concurrently.
@@ -162,6 +165,7 @@ async fn check_timeline(
     ttid: TenantTimelineId,
     timeline_start_lsn: Lsn,
     backup_lsn: Lsn,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<TimelineCheckResult> {
     trace!(
         "checking ttid {}, should contain WAL [{}-{}]",
@@ -187,7 +191,7 @@ async fn check_timeline(
     // we need files, so unset it.
     timeline_dir_target.delimiter = String::new();
 
-    let mut stream = std::pin::pin!(stream_listing(s3_client, &timeline_dir_target));
+    let mut stream = std::pin::pin!(stream_listing(s3_client, &timeline_dir_target, cancel));
     while let Some(obj) = stream.next().await {
         let obj = obj?;
         let key = obj.key();
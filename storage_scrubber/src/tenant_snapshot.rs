@@ -15,6 +15,7 @@ use pageserver::tenant::remote_timeline_client::index::LayerFileMetadata;
 use pageserver::tenant::storage_layer::LayerName;
 use pageserver::tenant::IndexPart;
 use pageserver_api::shard::TenantShardId;
+use tokio_util::sync::CancellationToken;
 use utils::generation::Generation;
 use utils::id::TenantId;
 
@@ -25,6 +26,9 @@ pub struct SnapshotDownloader {
     tenant_id: TenantId,
     output_path: Utf8PathBuf,
     concurrency: usize,
+    /// Redownload layers even if a complete copy already exists in `output_path`
+    force: bool,
+    cancel: CancellationToken,
 }
 
 impl SnapshotDownloader {
@@ -33,6 +37,8 @@ impl SnapshotDownloader {
         tenant_id: TenantId,
         output_path: Utf8PathBuf,
         concurrency: usize,
+        force: bool,
+        cancel: CancellationToken,
     ) -> anyhow::Result<Self> {
         let (s3_client, s3_root) = init_remote(bucket_config.clone(), NodeKind::Pageserver)?;
         Ok(Self {
@@ -42,6 +48,8 @@ impl SnapshotDownloader {
             tenant_id,
             output_path,
             concurrency,
+            force,
+            cancel,
         })
     }
 
@@ -65,11 +73,25 @@ impl SnapshotDownloader {
         assert_eq!(layer_metadata.shard, ttid.tenant_shard_id.to_index());
 
         // Assumption: we always write layer files atomically, and layer files are immutable.  Therefore if the file
-        // already exists on local disk, we assume it is fully correct and skip it.
-        if tokio::fs::try_exists(&local_path).await? {
+        // already exists on local disk with the size the index expects, e.g. from an earlier interrupted run, we
+        // assume it is fully correct and skip it.
+        let existing_size = match tokio::fs::metadata(&local_path).await {
+            Ok(metadata) => Some(metadata.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        if !self.force && existing_size == Some(layer_metadata.file_size) {
             tracing::debug!("{} already exists", local_path);
             return Ok((layer_name, layer_metadata));
         } else {
+            if let Some(existing_size) = existing_size {
+                if !self.force {
+                    tracing::warn!(
+                        "{local_path} has size {existing_size}, expected {}, downloading again",
+                        layer_metadata.file_size
+                    );
+                }
+            }
             tracing::debug!("{} requires download...", local_path);
 
             let timeline_root = self.s3_root.timeline_root(&ttid);
@@ -97,6 +119,7 @@ impl SnapshotDownloader {
                 &remote_layer_path,
                 version.version_id.as_deref(),
                 &local_path,
+                &self.cancel,
             )
             .await?;
 
@@ -218,7 +241,8 @@ impl SnapshotDownloader {
         let (s3_client, target) = init_remote(self.bucket_config.clone(), NodeKind::Pageserver)?;
 
         // Generate a stream of TenantShardId
-        let shards = stream_tenant_shards(&s3_client, &target, self.tenant_id).await?;
+        let shards =
+            stream_tenant_shards(&s3_client, &target, self.tenant_id, &self.cancel).await?;
         let shards: Vec<TenantShardId> = shards.try_collect().await?;
 
         // Only read from shards that have the highest count: avoids redundantly downloading
@@ -236,18 +260,21 @@ impl SnapshotDownloader {
 
         for shard in shards.into_iter().filter(|s| s.shard_count == shard_count) {
             // Generate a stream of TenantTimelineId
-            let timelines = stream_tenant_timelines(&s3_client, &self.s3_root, shard).await?;
+            let timelines =
+                stream_tenant_timelines(&s3_client, &self.s3_root, shard, &self.cancel).await?;
 
             // Generate a stream of S3TimelineBlobData
             async fn load_timeline_index(
                 s3_client: &Client,
                 target: &RootTarget,
                 ttid: TenantShardTimelineId,
+                cancel: &CancellationToken,
             ) -> anyhow::Result<(TenantShardTimelineId, S3TimelineBlobData)> {
-                let data = list_timeline_blobs(s3_client, ttid, target).await?;
+                let data = list_timeline_blobs(s3_client, ttid, target, cancel).await?;
                 Ok((ttid, data))
             }
-            let timelines = timelines.map_ok(|ttid| load_timeline_index(&s3_client, &target, ttid));
+            let timelines = timelines
+                .map_ok(|ttid| load_timeline_index(&s3_client, &target, ttid, &self.cancel));
             let mut timelines = std::pin::pin!(timelines.try_buffered(8));
 
             while let Some(i) = timelines.next().await {
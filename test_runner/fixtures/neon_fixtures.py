@@ -3992,11 +3992,11 @@ class StorageScrubber:
             log.error(stdout)
             raise
 
-    def tenant_snapshot(self, tenant_id: TenantId, output_path: Path):
-        stdout = self.scrubber_cli(
-            ["tenant-snapshot", "--tenant-id", str(tenant_id), "--output-path", str(output_path)],
-            timeout=30,
-        )
+    def tenant_snapshot(self, tenant_id: TenantId, output_path: Path, force: bool = False):
+        args = ["tenant-snapshot", "--tenant-id", str(tenant_id), "--output-path", str(output_path)]
+        if force:
+            args.append("--force")
+        stdout = self.scrubber_cli(args, timeout=30)
         log.info(f"tenant-snapshot output: {stdout}")
 
     def pageserver_physical_gc(
@@ -195,6 +195,7 @@ def test_fully_custom_config(positive_env: NeonEnv):
         "walreceiver_connect_timeout": "13m",
         "image_layer_creation_check_threshold": 1,
         "switch_aux_file_policy": "cross-validation",
+        "remote_storage_concurrency_limit": 4,
     }
 
     ps_http = env.pageserver.http_client()
@@ -65,6 +65,22 @@ def test_scrubber_tenant_snapshot(neon_env_builder: NeonEnvBuilder, shard_count:
 
     assert len(os.listdir(output_path)) > 0
 
+    # A repeated snapshot into the same directory resumes: layers already present are kept,
+    # and a truncated one is downloaded again.
+    layer_paths = [
+        os.path.join(root, file)
+        for root, _dirs, files in os.walk(output_path)
+        for file in files
+        if not file.startswith("index_part.json")
+    ]
+    assert len(layer_paths) > 0
+    truncated = layer_paths[0]
+    expect_size = os.path.getsize(truncated)
+    with open(truncated, "r+b") as f:
+        f.truncate(1)
+    scrubber.tenant_snapshot(tenant_id, output_path)
+    assert os.path.getsize(truncated) == expect_size
+
     workload.stop()
 
     # Stop pageservers